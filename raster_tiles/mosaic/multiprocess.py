@@ -24,6 +24,7 @@ from raster_tiles.mosaic.mosaic_options import MosaicOptions
 from raster_tiles.utils import DividedCache
 from server.tile_utils import is_warped_raster_asset
 from server.sqlite_db import sqlite_db_connect
+from server.mbtiles import insert_tiles
 
 import __main__
 
@@ -57,16 +58,9 @@ def save_mbtile(mosaic_options: MosaicOptions, q: multiprocessing.Queue):
                         for res in result:
                             # tuple[bytes, x, y, z]
                             if isinstance(res, tuple):
+                                tile = (res[3], res[1], res[2], sqlite3.Binary(res[0]))
                                 try:
-                                    cursor.execute(
-                                        """INSERT OR IGNORE INTO tiles (zoom_level, tile_column, tile_row, tile_data) values (?, ?, ?, ?);""",
-                                        (
-                                            res[3],
-                                            res[1],
-                                            res[2],
-                                            sqlite3.Binary(res[0]),
-                                        ),
-                                    )
+                                    insert_tiles(cursor, [tile])
                                     connection.commit()
                                 except sqlite3.OperationalError:
                                     # Exception `database is locked`
@@ -74,15 +68,7 @@ def save_mbtile(mosaic_options: MosaicOptions, q: multiprocessing.Queue):
                                     for i in range(0, attempts):
                                         time.sleep(0.2)
                                         try:
-                                            cursor.execute(
-                                                """INSERT OR IGNORE INTO tiles (zoom_level, tile_column, tile_row, tile_data) values (?, ?, ?, ?);""",
-                                                (
-                                                    res[3],
-                                                    res[1],
-                                                    res[2],
-                                                    sqlite3.Binary(res[0]),
-                                                ),
-                                            )
+                                            insert_tiles(cursor, [tile])
                                             connection.commit()
                                         except Exception as exc:
                                             if i == (attempts - 1):
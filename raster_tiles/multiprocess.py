@@ -16,6 +16,7 @@ from raster_tiles.tiles_processing import create_base_tile, tile_job_details
 from raster_tiles.models.tiles_options import TilesOptions
 from raster_tiles.models.tile_job import TileJob
 from server.sqlite_db import sqlite_db_connect
+from server.mbtiles import insert_tiles
 
 import __main__
 
@@ -48,16 +49,9 @@ def save_mbtile(options: TilesOptions, input_file: str, q: multiprocessing.Queue
                         for res in result:
                             # tuple[bytes, x, y, z]
                             if isinstance(res, tuple):
+                                tile = (res[3], res[1], res[2], sqlite3.Binary(res[0]))
                                 try:
-                                    cursor.execute(
-                                        """INSERT OR IGNORE INTO tiles (zoom_level, tile_column, tile_row, tile_data) values (?, ?, ?, ?);""",
-                                        (
-                                            res[3],
-                                            res[1],
-                                            res[2],
-                                            sqlite3.Binary(res[0]),
-                                        ),
-                                    )
+                                    insert_tiles(cursor, [tile])
                                     connection.commit()
                                 except sqlite3.OperationalError:
                                     # Exception `database is locked`
@@ -65,15 +59,7 @@ def save_mbtile(options: TilesOptions, input_file: str, q: multiprocessing.Queue
                                     for i in range(0, attempts):
                                         time.sleep(0.2)
                                         try:
-                                            cursor.execute(
-                                                """INSERT OR IGNORE INTO tiles (zoom_level, tile_column, tile_row, tile_data) values (?, ?, ?, ?);""",
-                                                (
-                                                    res[3],
-                                                    res[1],
-                                                    res[2],
-                                                    sqlite3.Binary(res[0]),
-                                                ),
-                                            )
+                                            insert_tiles(cursor, [tile])
                                             connection.commit()
                                         except Exception as exc:
                                             if i == (attempts - 1):
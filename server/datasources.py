@@ -32,6 +32,11 @@ class StoreType(StrEnum):
     internal = "internal"
     tiles = "tiles"
     tilejson = "tilejson"
+    postgis = "postgis"
+    file = "file"
+    cog = "cog"
+    remote = "remote"
+    composite = "composite"
 
 
 class LayerType(StrEnum):
@@ -146,6 +151,38 @@ class DataStoreRasterTileJson:
         self.encoding = EncodingType(encoding_type)
 
 
+class DataStoreRasterCog:
+    """Cloud-Optimized GeoTIFF by URL rendered to PNG/WebP tiles by the balancer"""
+
+    __slots__ = ("type", "store", "url", "format", "encoding")
+
+    def __init__(self, url: str, format: Optional[str] = None):
+        self.type = DataType.raster
+        self.store = StoreType.cog
+        self.url = url
+        self.format = format or "png"
+        self.encoding = EncodingType("f32")
+
+
+class DataStoreRasterRemote:
+    """Upstream tile service by the URL template, tiles are cached by the balancer"""
+
+    __slots__ = ("type", "store", "url", "rate_limit", "headers", "encoding")
+
+    def __init__(
+        self,
+        url: str,
+        rate_limit: Optional[float] = None,
+        headers: Optional[Dict[str, str]] = None,
+    ):
+        self.type = DataType.raster
+        self.store = StoreType.remote
+        self.url = url
+        self.rate_limit = rate_limit
+        self.headers = headers or {}
+        self.encoding = EncodingType("f32")
+
+
 # ============================== Vector ==============================
 
 
@@ -160,6 +197,60 @@ class DataStoreVectorInternal:
         self.store = StoreType.internal
 
 
+class DataStoreVectorPostgis:
+    """Tiles are generated by the balancer from the tables of the config database"""
+
+    __slots__ = (
+        "type",
+        "store",
+    )
+
+    def __init__(self):
+        self.type = DataType.vector
+        self.store = StoreType.postgis
+
+
+class DataStoreVectorFile:
+    """GeoJSON/FlatGeobuf file in 'datasources/vector' tiled by the balancer"""
+
+    __slots__ = ("type", "store", "file", "layer")
+
+    def __init__(self, file: str, layer: Optional[str] = None):
+        self.type = DataType.vector
+        self.store = StoreType.file
+        self.file = file
+        self.layer = layer
+
+
+class DataStoreVectorRemote:
+    """Upstream tile service by the URL template, tiles are cached by the balancer"""
+
+    __slots__ = ("type", "store", "url", "rate_limit", "headers")
+
+    def __init__(
+        self,
+        url: str,
+        rate_limit: Optional[float] = None,
+        headers: Optional[Dict[str, str]] = None,
+    ):
+        self.type = DataType.vector
+        self.store = StoreType.remote
+        self.url = url
+        self.rate_limit = rate_limit
+        self.headers = headers or {}
+
+
+class DataStoreVectorComposite:
+    """Layers of child vector DataSources merged into one tile by the balancer"""
+
+    __slots__ = ("type", "store", "datasources")
+
+    def __init__(self, datasources: List[Dict[str, Any]]):
+        self.type = DataType.vector
+        self.store = StoreType.composite
+        self.datasources = datasources
+
+
 class DataStoreVectorMBTiles:
     __slots__ = ("type", "store", "path")
 
@@ -196,10 +287,16 @@ DataStoreRaster = Union[
     DataStoreRasterMBTiles,
     DataStoreRasterTiles,
     DataStoreRasterTileJson,
+    DataStoreRasterCog,
+    DataStoreRasterRemote,
 ]
 
 DataStoreVector = Union[
     DataStoreVectorInternal,
+    DataStoreVectorPostgis,
+    DataStoreVectorFile,
+    DataStoreVectorRemote,
+    DataStoreVectorComposite,
     DataStoreVectorMBTiles,
     DataStoreVectorTiles,
     DataStoreVectorTileJson,
@@ -467,6 +564,34 @@ def setup_data_store(store: str, data_store_dict: Dict[str, Any]) -> DataStore:
             # Generating tiles offline from OSM data using PostGIS
             return DataStoreVectorInternal()
 
+        elif store == StoreType.postgis:
+            # Tiles are generated by the balancer, workers are not requested
+            return DataStoreVectorPostgis()
+
+        elif store == StoreType.file:
+            # Tiles are cut by the balancer from the file loaded into memory
+            file: Optional[str] = data_store_dict.get("file")
+            assert isinstance(file, str), f"'file' has wrong format: {file}"
+            return DataStoreVectorFile(file, data_store_dict.get("layer"))
+
+        elif store == StoreType.remote:
+            # Missing tiles are fetched by the balancer from the upstream and cached
+            url: Optional[str] = data_store_dict.get("url")
+            assert isinstance(url, str), f"'url' has wrong format: {url}"
+            return DataStoreVectorRemote(
+                url, data_store_dict.get("rate_limit"), data_store_dict.get("headers")
+            )
+
+        elif store == StoreType.composite:
+            # Tiles of child DataSources are merged by the balancer
+            datasources: Optional[List[Dict[str, Any]]] = data_store_dict.get(
+                "datasources"
+            )
+            assert isinstance(
+                datasources, list
+            ), f"'datasources' has wrong format: {datasources}, must be a List"
+            return DataStoreVectorComposite(datasources)
+
         elif store == StoreType.tiles:
             # Use external tiles from MapTiler
             # raise Exception(f"Vector Store type '{StoreType.tiles}' not implemented")
@@ -512,6 +637,20 @@ def setup_data_store(store: str, data_store_dict: Dict[str, Any]) -> DataStore:
             ), f"'tiles' has wrong format: {tiles}, must be a List"
             return DataStoreRasterTiles(tiles)
 
+        elif store == StoreType.cog:
+            # Tiles are rendered by the balancer from range requests to the GeoTIFF
+            url: Optional[str] = data_store_dict.get("url")
+            assert isinstance(url, str), f"'url' has wrong format: {url}"
+            return DataStoreRasterCog(url, data_store_dict.get("format"))
+
+        elif store == StoreType.remote:
+            # Missing tiles are fetched by the balancer from the upstream and cached
+            url = data_store_dict.get("url")
+            assert isinstance(url, str), f"'url' has wrong format: {url}"
+            return DataStoreRasterRemote(
+                url, data_store_dict.get("rate_limit"), data_store_dict.get("headers")
+            )
+
         elif store == StoreType.tilejson:
             raise Exception(f"Vector Store type '{StoreType.tilejson}' not implemented")
         else:
@@ -660,8 +799,10 @@ class DataSourceVector:
         # Layers remain undefined for external source of tiles - DataStoreVectorTiles
         self.layers: Optional[List[VectorDataLayer]] = None
 
-        # TODO: for now layers processing for internal store type
-        if isinstance(self.data_store, DataStoreVectorInternal):
+        # TODO: for now layers processing for internal and postgis store types
+        if isinstance(
+            self.data_store, (DataStoreVectorInternal, DataStoreVectorPostgis)
+        ):
             layers = ds.get("layers")
             assert (
                 isinstance(layers, list) and len(layers) > 0
@@ -125,6 +125,28 @@ async def health():
     )
 
 
+@app.post(
+    "/api/log_level",
+    responses={200: {"content": {"application/json": {}}}},
+    response_class=JSONResponse,
+)
+async def log_level(body: Dict[str, Any]):
+    level = str(body.get("level", "")).upper()
+    if level not in ("ERROR", "WARNING", "WARN", "INFO", "DEBUG", "TRACE"):
+        raise HTTPException(
+            status_code=status.HTTP_400_BAD_REQUEST,
+            detail=f"Log level '{level}' is unsupported",
+        )
+
+    # Python logging has no TRACE level
+    python_level = {"WARN": "WARNING", "TRACE": "DEBUG"}.get(level, level)
+    logging.getLogger().setLevel(python_level)
+    return JSONResponse(
+        status_code=status.HTTP_200_OK,
+        content={"worker_pid": os.getpid(), "log_level": level},
+    )
+
+
 @app.get("/favicon.ico", include_in_schema=False)
 async def favicon():
     return FileResponse("static/favicon.ico")
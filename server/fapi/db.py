@@ -8,6 +8,7 @@ from asyncpg.pool import Pool
 from asyncpg import Connection
 from fastapi import FastAPI
 from server.fapi.utils import load_environments_from_file
+from server.fapi.registry import SQLitePool, registry_config, REGISTRY_SQLITE
 
 logger = logging.getLogger(__name__)
 
@@ -23,12 +24,21 @@ async def set_connection_type_codec(connection: Connection):
 
 def dsn_postgresql() -> Tuple[str, int]:
     config: Dict[str, str] = load_environments_from_file()
-    dsn = f'postgresql://{config["DBUSER"]}:{config["DBPASS"]}@{config["DBHOST"]}:{config["DBPORT"]}/{config["DBNAME"]}'
     db_pool_size: int = int(config["DBPOOLSIZE"])
+    # single connection string replaces the variables 'DB*'
+    if config.get("DATABASE_URL"):
+        return config["DATABASE_URL"], db_pool_size
+    dsn = f'postgresql://{config["DBUSER"]}:{config["DBPASS"]}@{config["DBHOST"]}:{config["DBPORT"]}/{config["DBNAME"]}'
     return dsn, db_pool_size
 
 
 async def connect_to_db(app: FastAPI) -> None:
+    app.state.registry_type, registry_path = registry_config()
+    if app.state.registry_type == REGISTRY_SQLITE:
+        # DataSources are stored in SQLite, PostGIS is unavailable
+        app.state.db_pool = SQLitePool(registry_path)
+        return
+
     dsn, db_pool_size = dsn_postgresql()
     app.state.db_pool: Pool = await asyncpg.create_pool(
         dsn=dsn,
@@ -38,7 +48,8 @@ async def connect_to_db(app: FastAPI) -> None:
         max_queries=5000,
         max_inactive_connection_lifetime=300,
         timeout=180,  # 3 Minutes
-        ssl=False,
+        # 'sslmode' of the connection string is applied by asyncpg
+        ssl=None if "sslmode=" in dsn else False,
     )
     try:
         # check version PostGIS
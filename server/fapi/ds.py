@@ -12,6 +12,7 @@ from fastapi import APIRouter, Request, Body, HTTPException, status
 from fastapi.responses import JSONResponse, Response
 from asyncpg.pool import Pool
 from asyncpg import Connection, UniqueViolationError
+from sqlite3 import IntegrityError
 from pydantic_core import ErrorDetails
 from pydantic import (
     BaseModel,
@@ -26,6 +27,8 @@ from pydantic import (
 
 
 from raster_tiles.defaults import PIXEL_SELECTION_METHOD
+from server.fapi.registry import SQLiteConnection
+from server.paths import tiles_dir, data_dir
 from server.datasources import (
     load_datasources_from_db,
     BUFFER,
@@ -307,6 +310,21 @@ class Bounds(BaseModel):
         return self
 
 
+def validate_remote_store(
+    model: str, url: Optional[str], rate_limit: Optional[float]
+) -> None:
+    if url is None or not url.startswith(("http://", "https://")):
+        raise ValueError(
+            f"{model}.url must be HTTP(S) URL for store type 'remote'. Got '{url}'"
+        )
+    if not all(p in url for p in ("{z}", "{x}", "{y}")):
+        raise ValueError(
+            f"{model}.url must be a template with '{{z}}', '{{x}}' and '{{y}}'. Got '{url}'"
+        )
+    if rate_limit is not None and rate_limit <= 0:
+        raise ValueError(f"{model}.rate_limit must be positive. Got {rate_limit}")
+
+
 # ====================================================================
 # ============================== Raster ==============================
 # ====================================================================
@@ -320,6 +338,10 @@ class DataStoreRasterBase(BaseModel):
     dataset: Optional[str] = None
     file: Optional[str] = None
     folder: Optional[str] = None
+    url: Optional[str] = None
+    format: Optional[str] = None
+    rate_limit: Optional[float] = None
+    headers: Optional[Dict[str, str]] = None
 
     @validator("type")
     def validate_type(cls, value):
@@ -353,6 +375,17 @@ class DataStoreRasterBase(BaseModel):
             raise ValueError(
                 f"DataStoreRasterBase.[file, folder] the 'file' and 'folder' fields cannot be specified at the same time"
             )
+        if self.store == StoreType.cog:
+            if self.url is None or not self.url.startswith(("http://", "https://")):
+                raise ValueError(
+                    f"DataStoreRasterBase.url must be HTTP(S) URL for store type 'cog'. Got '{self.url}'"
+                )
+            if self.format not in (None, "png", "webp"):
+                raise ValueError(
+                    f"DataStoreRasterBase.format must be 'png' or 'webp'. Got '{self.format}'"
+                )
+        if self.store == StoreType.remote:
+            validate_remote_store("DataStoreRasterBase", self.url, self.rate_limit)
         return self
 
     def model_post_init(self, __context):
@@ -475,9 +508,12 @@ class DataSourceRasterBase(BaseModel):
     encoding: Optional[str] = EncodingType.f32
     use_cache_only: Optional[bool] = False
     compress_tiles: Optional[bool] = False
+    storage_compression: Optional[str] = None
 
     @validator("dataStore")
     def validate_type(cls, value: DataStoreRasterBase, values):
+        if value.store in (StoreType.cog, StoreType.remote):
+            return value
         if values["mosaics"]:
             if value.folder is None:
                 raise ValueError(
@@ -551,11 +587,23 @@ class DataSourceRasterBase(BaseModel):
 # ====================================================================
 
 
+class CompositeChild(BaseModel):
+    id: str
+    prefix: Optional[str] = None
+    layers: Optional[Dict[str, str]] = None
+
+
 class DataStoreVectorBase(BaseModel):
     type: str
     store: str
     tiles: Optional[List[Url]] = None
     keys: Optional[List[str]] = None
+    file: Optional[str] = None
+    layer: Optional[str] = None
+    url: Optional[str] = None
+    rate_limit: Optional[float] = None
+    headers: Optional[Dict[str, str]] = None
+    datasources: Optional[List[CompositeChild]] = None
 
     @validator("type")
     def validate_type(cls, value):
@@ -579,6 +627,31 @@ class DataStoreVectorBase(BaseModel):
             raise ValueError(
                 f"DataStoreVectorBase.tiles must be defined for store type 'tiles'"
             )
+        if self.store == StoreType.file:
+            if self.file is None:
+                raise ValueError(
+                    f"DataStoreVectorBase.file must be defined for store type 'file'"
+                )
+            if os.path.splitext(self.file)[1].lower() not in (
+                ".geojson",
+                ".json",
+                ".fgb",
+            ):
+                raise ValueError(
+                    f"DataStoreVectorBase.file must be GeoJSON or FlatGeobuf. Got '{self.file}'"
+                )
+        if self.store == StoreType.remote:
+            validate_remote_store("DataStoreVectorBase", self.url, self.rate_limit)
+        if self.store == StoreType.composite:
+            if not self.datasources:
+                raise ValueError(
+                    f"DataStoreVectorBase.datasources must be defined for store type 'composite'"
+                )
+            ids = [child.id for child in self.datasources]
+            if len(ids) != len(set(ids)):
+                raise ValueError(
+                    f"DataStoreVectorBase.datasources must have unique ids. Got {ids}"
+                )
         return self
 
 
@@ -695,6 +768,7 @@ class DataSourceVectorBase(BaseModel):
     layers: Optional[List[VectorLayer]] = None
     use_cache_only: Optional[bool] = False
     compress_tiles: Optional[bool] = False
+    storage_compression: Optional[str] = None
 
     @validator("type")
     def validate_type(cls, value):
@@ -723,9 +797,12 @@ class DataSourceVectorBase(BaseModel):
             raise ValueError(
                 f"DataSourceVectorBase.maxzoom must be in range [0-20] and more than minzoom. Got {self.maxzoom}"
             )
-        if self.dataStore.store == StoreType.internal and self.layers is None:
+        if (
+            self.dataStore.store in (StoreType.internal, StoreType.postgis)
+            and self.layers is None
+        ):
             raise ValueError(
-                f"DataSourceVectorBase.layers must be defined for store type 'internal'"
+                f"DataSourceVectorBase.layers must be defined for store type '{self.dataStore.store}'"
             )
         if self.bounds is not None and self.center is not None:
             center_lng: float = self.center.root[0]
@@ -858,7 +935,7 @@ async def save_datasource_to_db(
                         query,
                         *params,
                     )
-            except UniqueViolationError:
+            except (UniqueViolationError, IntegrityError):
                 new_id: str = str(uuid4())
                 ds["id"] = new_id
                 new_params = (new_id,) + params[1:]
@@ -895,6 +972,20 @@ async def validate_vector_layers(
     validation_errors: Optional[List[ErrorDetails]] = None
     layers: Optional[List[VectorLayer]] = datasource.layers
 
+    # without PostgreSQL the tables of the internal store are unavailable
+    if isinstance(connection, SQLiteConnection):
+        if datasource.dataStore.store in (StoreType.internal, StoreType.postgis):
+            return [
+                ErrorDetails(
+                    type="value_error",
+                    loc=("dataStore", "store"),
+                    msg=f"Store '{datasource.dataStore.store}' requires the PostgreSQL registry of DataSources",
+                    input=f"{datasource.dataStore.store}",
+                    ctx={"datasource_id": f"{datasource.id}"},
+                )
+            ]
+        return None
+
     if layers is not None:
         for layer in layers:
             # skip validation if SQL used
@@ -1305,10 +1396,12 @@ async def delete_datasource(
 
     # 2. Clear 'data' and 'tiles' directories from DataSource folders
     try:
-        tiles_dir: str = os.path.join(request.app.state.root_path, "tiles", ds_id)
-        shutil.rmtree(tiles_dir, ignore_errors=True)
-        data_dir: str = os.path.join(request.app.state.root_path, "data", ds_id)
-        shutil.rmtree(data_dir, ignore_errors=True)
+        ds_tiles_dir: str = os.path.join(
+            tiles_dir(request.app.state.root_path), ds_id
+        )
+        shutil.rmtree(ds_tiles_dir, ignore_errors=True)
+        ds_data_dir: str = os.path.join(data_dir(request.app.state.root_path), ds_id)
+        shutil.rmtree(ds_data_dir, ignore_errors=True)
     except Exception as e:
         message = (
             f"Worker {pid}, error remove DataSource '{ds_id}' directories: {str(e)}"
@@ -17,7 +17,7 @@ from server.pyramid_utils import (
     run_pyramid_in_threadpool,
     run_mosaics_pyramid_in_threadpool,
 )
-from server.datasources import DataSource, RasterTileSettings
+from server.datasources import DataSource, RasterTileSettings, StoreType
 
 
 logger = logging.getLogger(__name__)
@@ -27,6 +27,12 @@ async def single_pyramid(
     request: Request, p: Pyramid, background_tasks: BackgroundTasks
 ) -> JSONResponse:
     ds: DataSource = request.app.state.datasources.get(p.datasource_id)
+    if ds.data_store.store != StoreType.internal:
+        raise HTTPException(
+            status_code=status.HTTP_400_BAD_REQUEST,
+            detail=f"Creating a pyramid of raster tiles is only possible for the internal type store. Got '{ds.data_store.store}'",
+        )
+
     pyr_settings_to_pyramid(ds.pyr_settings, p)
     root_path: str = request.app.state.root_path
     is_exist_dataset = await exist_dataset(ds.data_store.file, root_path)
@@ -12,6 +12,7 @@ from starlette.exceptions import HTTPException
 from raster_tiles.single_tile.tile import mosaic_tile, tile
 from server.tile_utils import get_tile_job
 from server.datasources import DataSource, DataStoreRaster
+from server.paths import data_dir
 
 logger = logging.getLogger(__name__)
 
@@ -89,7 +90,7 @@ async def get_tile(
                     ]
         else:
             db_path: str = os.path.join(
-                root_path, "data", datasource_id, f"{dataset}.db"
+                data_dir(root_path), datasource_id, f"{dataset}.db"
             )
 
             if not await aios.path.isfile(db_path):
@@ -174,7 +175,7 @@ async def get_mosaics_tile(
                     ]
         else:
             db_path: str = os.path.join(
-                root_path, "data", datasource_id, f"{dataset}.db"
+                data_dir(root_path), datasource_id, f"{dataset}.db"
             )
             if not await aios.path.isfile(db_path):
                 return Response(
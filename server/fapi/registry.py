@@ -0,0 +1,104 @@
+import os
+import re
+import sqlite3
+import orjson
+import aiosqlite
+
+from pathlib import Path
+from contextlib import asynccontextmanager
+from typing import Any, AsyncIterator, Dict, List, Optional, Tuple
+
+REGISTRY_POSTGRES = "postgres"
+REGISTRY_SQLITE = "sqlite"
+REGISTRY_SQLITE_PATH = "data/datasources.db"
+
+# columns of the table 'datasource' stored as JSON text in SQLite
+JSON_COLUMNS = ("bounds", "center", "data")
+
+
+def registry_config() -> Tuple[str, str]:
+    """Type and path of the registry of DataSources from 'server.registry' in 'config_app.json'"""
+    root_path: str = str(Path(__file__).parents[2])
+    with open(os.path.join(root_path, "config_app.json"), "rb") as f:
+        config: Dict[str, Any] = orjson.loads(f.read())
+    registry: Dict[str, Any] = config.get("server", {}).get("registry", {})
+    registry_type: str = registry.get("type", REGISTRY_POSTGRES)
+    path: str = os.path.join(root_path, registry.get("path", REGISTRY_SQLITE_PATH))
+    return registry_type, path
+
+
+class SQLiteConnection:
+    """
+    Connection to the SQLite registry with the subset of 'asyncpg.Connection'
+    used by the queries of the table 'datasource'
+    """
+
+    def __init__(self, connection: aiosqlite.Connection):
+        self.connection = connection
+
+    @staticmethod
+    def _query(query: str) -> str:
+        # placeholders '$1' of PostgreSQL are '?1' in SQLite
+        return re.sub(r"\$(\d+)", r"?\1", query)
+
+    @staticmethod
+    def _params(args: Tuple[Any, ...]) -> Tuple[Any, ...]:
+        return tuple(
+            orjson.dumps(arg).decode() if isinstance(arg, (dict, list)) else arg
+            for arg in args
+        )
+
+    @staticmethod
+    def _record(row: sqlite3.Row) -> Dict[str, Any]:
+        record: Dict[str, Any] = dict(row)
+        for column in JSON_COLUMNS:
+            if isinstance(record.get(column), str):
+                record[column] = orjson.loads(record[column])
+        return record
+
+    async def execute(self, query: str, *args) -> None:
+        await self.connection.execute(self._query(query), self._params(args))
+        await self.connection.commit()
+
+    async def fetch(self, query: str, *args) -> List[Dict[str, Any]]:
+        async with self.connection.execute(
+            self._query(query), self._params(args)
+        ) as cursor:
+            rows = await cursor.fetchall()
+        return [self._record(row) for row in rows]
+
+    async def fetchrow(self, query: str, *args) -> Optional[Dict[str, Any]]:
+        async with self.connection.execute(
+            self._query(query), self._params(args)
+        ) as cursor:
+            row = await cursor.fetchone()
+        return self._record(row) if row is not None else None
+
+    async def fetchval(self, query: str, *args) -> Any:
+        async with self.connection.execute(
+            self._query(query), self._params(args)
+        ) as cursor:
+            row = await cursor.fetchone()
+        return row[0] if row is not None else None
+
+
+class SQLitePool:
+    """
+    Replaces 'asyncpg.Pool' when DataSources are stored in SQLite.
+    The table is created by the balancer at startup.
+    """
+
+    def __init__(self, path: str):
+        self.path = path
+
+    @asynccontextmanager
+    async def acquire(self) -> AsyncIterator[SQLiteConnection]:
+        connection = await aiosqlite.connect(self.path, timeout=180)
+        connection.row_factory = sqlite3.Row
+        try:
+            yield SQLiteConnection(connection)
+        finally:
+            await connection.close()
+
+    async def close(self) -> None:
+        pass
@@ -27,16 +27,22 @@ from fastapi.responses import JSONResponse
 from server.fapi.db import dsn_postgresql, set_connection_type_codec
 from server.fapi.utils import initialize_event_loop
 from server.fapi.vector.mvt_postgis import generate_mvt
+from server.paths import tiles_dir
 from server.datasources import DataSource, VECTOR, StoreType
 from server.pyramid_utils import (
     Pyramid,
     check_running_pyramid_for_dataset,
+    pyramid_mbtiles_db,
     set_state_pyramid,
     terminate_child_processes,
 )
 from server.sqlite_db import sqlite_db_connect_async
 from server.tile_utils import save_tile_on_disk
-from server.mbtiles import async_mbtiles_setup
+from server.mbtiles import (
+    TILE_COMPRESSION_ZSTD,
+    async_insert_tiles,
+    async_mbtiles_setup,
+)
 
 
 logger = logging.getLogger(__name__)
@@ -69,6 +75,7 @@ async def single_pyramid(
         p.datasource_id,
         root_path,
         id_pyramid,
+        bool(p.atomic_swap),
     )
 
     return JSONResponse(
@@ -103,6 +110,7 @@ async def run_vector_pyramid_in_threadpool(
     datasource_id: str,
     root_path: str,
     id_pyramid: str,
+    atomic_swap: bool = False,
 ) -> None:
     try:
         await run_in_threadpool(
@@ -111,6 +119,7 @@ async def run_vector_pyramid_in_threadpool(
             datasource_id,
             root_path,
             id_pyramid,
+            atomic_swap,
         )
     except Exception as e:
         await set_state_pyramid(0, 1, id_pyramid, root_path)
@@ -127,13 +136,14 @@ def vector_pyramid_in_separate_process(
     datasource_id: str,
     root_path: str,
     id_pyramid: str,
+    atomic_swap: bool = False,
 ) -> None:
     # strictly 'spawn' processes
     mp_context = multiprocessing.get_context("spawn")
     try:
         vp = mp_context.Process(
             target=vector_pyramid_with_loop,
-            args=(ds, datasource_id, root_path, id_pyramid),
+            args=(ds, datasource_id, root_path, id_pyramid, atomic_swap),
             kwargs={},
         )
         vp.start()
@@ -150,10 +160,13 @@ def vector_pyramid_with_loop(
     datasource_id: str,
     root_path: str,
     id_pyramid: str,
+    atomic_swap: bool = False,
 ) -> None:
     initialize_event_loop()
     loop = asyncio.get_event_loop()
-    loop.run_until_complete(vector_pyramid(ds, datasource_id, root_path, id_pyramid))
+    loop.run_until_complete(
+        vector_pyramid(ds, datasource_id, root_path, id_pyramid, atomic_swap)
+    )
     loop.close()
 
 
@@ -162,6 +175,7 @@ async def vector_pyramid(
     datasource_id: str,
     root_path: str,
     id_pyramid: str,
+    atomic_swap: bool = False,
 ):
     mbtiles_db: Optional[str] = None
     connection: Optional[aiosqlite.Connection] = None
@@ -169,9 +183,7 @@ async def vector_pyramid(
     if ds.mbtiles:
         await asyncio.sleep(2)
 
-        mbtiles_db = os.path.join(
-            root_path, "tiles", datasource_id, f"{datasource_id}.mbtiles"
-        )
+        mbtiles_db = pyramid_mbtiles_db(root_path, datasource_id, atomic_swap)
         if await aio_os.path.isfile(mbtiles_db):
             try:
                 await aio_os.remove(mbtiles_db)
@@ -192,7 +204,9 @@ async def vector_pyramid(
         if not await aio_os.path.isfile(mbtiles_db):
             connection = await sqlite_db_connect_async(mbtiles_db)
             cursor = await connection.cursor()
-            await async_mbtiles_setup(cursor)
+            await async_mbtiles_setup(
+                cursor, (ds.storage_compression or "").lower() == TILE_COMPRESSION_ZSTD
+            )
         else:
             connection = await sqlite_db_connect_async(mbtiles_db)
             cursor = await connection.cursor()
@@ -302,8 +316,7 @@ async def get_vector_tile(
     # Save only not empty tiles
     if mvt != b"":
         tile_file_name: str = os.path.join(
-            f"{root_path}",
-            "tiles",
+            tiles_dir(root_path),
             datasource_id,
             f"{z}",
             f"{x}",
@@ -338,10 +351,7 @@ async def save_batched_mvt_tiles(
             for t in tiles
         ]
         try:
-            await cursor.executemany(
-                """INSERT OR IGNORE INTO tiles (zoom_level, tile_column, tile_row, tile_data) values (?, ?, ?, ?);""",
-                ts,
-            )
+            await async_insert_tiles(cursor, ts)
             await connection.commit()
         except aiosqlite.OperationalError as e:
             # Exception `database is locked`
@@ -349,10 +359,7 @@ async def save_batched_mvt_tiles(
             for i in range(0, attempts):
                 await asyncio.sleep(0.2)
                 try:
-                    await cursor.executemany(
-                        """INSERT OR IGNORE INTO tiles (zoom_level, tile_column, tile_row, tile_data) values (?, ?, ?, ?);""",
-                        ts,
-                    )
+                    await async_insert_tiles(cursor, ts)
                     await connection.commit()
                 except Exception as exc:
                     if i == (attempts - 1):
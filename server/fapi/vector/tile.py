@@ -14,10 +14,17 @@ from fastapi import Response, Request, status, BackgroundTasks, FastAPI
 from starlette.exceptions import HTTPException
 
 from server.fapi.utils import MAPTILER_ERROR
-from server.datasources import DataSource, DataStoreVectorTiles, DataStoreVectorInternal
+from server.datasources import (
+    DataSource,
+    DataStoreVectorTiles,
+    DataStoreVectorInternal,
+    DataStoreVectorPostgis,
+)
 from server.fapi.vector.mvt_postgis import generate_mvt
+from server.paths import tiles_dir
 from server.tile_utils import save_tile_on_disk
 from server.sqlite_db import sqlite_db_connect_async
+from server.mbtiles import async_insert_tiles
 
 logger = logging.getLogger(__name__)
 
@@ -51,7 +58,7 @@ async def vector_tile(
         # In case of an error, we return it directly
         if isinstance(mvt, Response):
             return mvt
-    elif isinstance(data_store, DataStoreVectorInternal):
+    elif isinstance(data_store, (DataStoreVectorInternal, DataStoreVectorPostgis)):
         mvt = await generate_mvt(ds, request.app.state.db_pool, z, x, y)
 
     # Save only not empty tiles
@@ -216,8 +223,7 @@ async def save_tile(
     root_path: str,
 ):
     tile_file_name: str = os.path.join(
-        f"{root_path}",
-        "tiles",
+        tiles_dir(root_path),
         datasource_id,
         f"{z}",
         f"{x}",
@@ -226,16 +232,13 @@ async def save_tile(
 
     if mbtiles:
         mbtiles_db: str = os.path.join(
-            root_path, "tiles", datasource_id, f"{datasource_id}.mbtiles"
+            tiles_dir(root_path), datasource_id, f"{datasource_id}.mbtiles"
         )
         if await aio_os.path.isfile(mbtiles_db):
             connection: aiosqlite.Connection = await sqlite_db_connect_async(mbtiles_db)
             cursor: aiosqlite.Cursor = await connection.cursor()
             try:
-                await cursor.execute(
-                    """INSERT OR IGNORE INTO tiles (zoom_level, tile_column, tile_row, tile_data) values (?, ?, ?, ?);""",
-                    (z, x, y, buffer),
-                )
+                await async_insert_tiles(cursor, [(z, x, y, buffer)])
                 await connection.commit()
             except aiosqlite.OperationalError as e:
                 # Exception `database is locked`
@@ -243,10 +246,7 @@ async def save_tile(
                 for i in range(0, attempts):
                     await asyncio.sleep(0.2)
                     try:
-                        await cursor.execute(
-                            """INSERT OR IGNORE INTO tiles (zoom_level, tile_column, tile_row, tile_data) values (?, ?, ?, ?);""",
-                            (z, x, y, buffer),
-                        )
+                        await async_insert_tiles(cursor, [(z, x, y, buffer)])
                         await connection.commit()
                     except Exception as exc:
                         if i == (attempts - 1):
@@ -1,5 +1,13 @@
+import os
 import sqlite3
 import aiosqlite
+import gzip
+import xxhash
+import zstandard
+
+from typing import List, Tuple
+
+PRAGMA_AUTO_VACUUM: str = """PRAGMA auto_vacuum=INCREMENTAL;"""
 
 TABLE_TILES: str = """
     CREATE TABLE tiles (
@@ -32,20 +40,135 @@ TABLE_GRID_DATA: str = """
     );
 """
 
+TABLE_TILES_CHECKSUM: str = """
+    CREATE TABLE IF NOT EXISTS tiles_checksum (
+        zoom_level integer NOT NULL,
+        tile_column integer NOT NULL,
+        tile_row integer NOT NULL,
+        xxhash text NOT NULL,
+        PRIMARY KEY(zoom_level, tile_column, tile_row)
+    );
+"""
+
+SQL_INSERT_TILE: str = """INSERT OR IGNORE INTO tiles (zoom_level, tile_column, tile_row, tile_data) values (?, ?, ?, ?);"""
+
+SQL_INSERT_CHECKSUM: str = """INSERT OR REPLACE INTO tiles_checksum (zoom_level, tile_column, tile_row, xxhash) values (?, ?, ?, ?);"""
+
+# Integrity mode of the server: checksums of written tiles are verified on read
+INTEGRITY: bool = os.environ.get("TILER_INTEGRITY") == "1"
+
+
+def tile_checksum(buffer: bytes) -> str:
+    return xxhash.xxh3_64_hexdigest(buffer)
+
+
+# Compression of tiles at rest: MBTiles with 'tile_compression' = 'zstd' in 'metadata'
+SQL_SELECT_COMPRESSION: str = """SELECT value FROM metadata WHERE name = 'tile_compression' LIMIT 1;"""
+
+SQL_INSERT_COMPRESSION: str = """INSERT INTO metadata (name, value) values ('tile_compression', ?);"""
+
+TILE_COMPRESSION_ZSTD: str = "zstd"
+
+ZSTD_LEVEL: int = 3
+ZSTD_MAGIC: bytes = b"\x28\xb5\x2f\xfd"
+GZIP_MAGIC: bytes = b"\x1f\x8b"
+
+
+# gzipped tiles are stored ungzipped, the server gzips them again on serve
+def encode_stored_tile(buffer: bytes) -> bytes:
+    if buffer.startswith(ZSTD_MAGIC):
+        return buffer
+    if buffer.startswith(GZIP_MAGIC):
+        buffer = gzip.decompress(buffer)
+    return zstandard.ZstdCompressor(level=ZSTD_LEVEL).compress(buffer)
+
+
+# Blobs without zstd are returned as is
+def decode_stored_tile(buffer: bytes) -> bytes:
+    if buffer.startswith(ZSTD_MAGIC):
+        return zstandard.ZstdDecompressor().decompress(buffer)
+    return buffer
+
+
+def is_compression_value(row) -> bool:
+    return row is not None and str(row[0]).lower() == TILE_COMPRESSION_ZSTD
+
+
+def stored_tiles(
+    tiles: List[Tuple[int, int, int, bytes]], compressed: bool
+) -> List[Tuple[int, int, int, bytes]]:
+    if not compressed:
+        return tiles
+    return [(z, x, y, encode_stored_tile(buffer)) for z, x, y, buffer in tiles]
+
+
+def mbtiles_compressed(cursor: sqlite3.Cursor) -> bool:
+    try:
+        return is_compression_value(cursor.execute(SQL_SELECT_COMPRESSION).fetchone())
+    except sqlite3.OperationalError:
+        return False
+
+
+async def async_mbtiles_compressed(cursor: aiosqlite.Cursor) -> bool:
+    try:
+        await cursor.execute(SQL_SELECT_COMPRESSION)
+        return is_compression_value(await cursor.fetchone())
+    except sqlite3.OperationalError:
+        return False
+
+
+def insert_tiles(cursor: sqlite3.Cursor, tiles: List[Tuple[int, int, int, bytes]]):
+    tiles = stored_tiles(tiles, mbtiles_compressed(cursor))
+    if not INTEGRITY:
+        cursor.executemany(SQL_INSERT_TILE, tiles)
+        return
+
+    # MBTiles created before integrity mode gets the table of checksums
+    cursor.execute(TABLE_TILES_CHECKSUM)
+    for z, x, y, buffer in tiles:
+        cursor.execute(SQL_INSERT_TILE, (z, x, y, buffer))
+        # checksum is recorded only for the written tile, existing tile is kept
+        if cursor.rowcount == 1:
+            cursor.execute(SQL_INSERT_CHECKSUM, (z, x, y, tile_checksum(buffer)))
+
+
+async def async_insert_tiles(
+    cursor: aiosqlite.Cursor, tiles: List[Tuple[int, int, int, bytes]]
+):
+    tiles = stored_tiles(tiles, await async_mbtiles_compressed(cursor))
+    if not INTEGRITY:
+        await cursor.executemany(SQL_INSERT_TILE, tiles)
+        return
+
+    await cursor.execute(TABLE_TILES_CHECKSUM)
+    for z, x, y, buffer in tiles:
+        await cursor.execute(SQL_INSERT_TILE, (z, x, y, buffer))
+        if cursor.rowcount == 1:
+            await cursor.execute(SQL_INSERT_CHECKSUM, (z, x, y, tile_checksum(buffer)))
+
 
-def mbtiles_setup(cursor: sqlite3.Cursor):
+def mbtiles_setup(cursor: sqlite3.Cursor, compressed: bool = False):
+    # free pages are released by the maintenance of the server
+    cursor.execute(PRAGMA_AUTO_VACUUM)
     cursor.execute(TABLE_TILES)
     cursor.execute(TABLE_METADATA)
     cursor.execute(TABLE_GRIDS)
     cursor.execute(TABLE_GRID_DATA)
+    cursor.execute(TABLE_TILES_CHECKSUM)
+    if compressed:
+        cursor.execute(SQL_INSERT_COMPRESSION, (TILE_COMPRESSION_ZSTD,))
 
 
 def create_index(cursor: sqlite3.Cursor):
     cursor.execute("""create unique index name on metadata (name);""")
 
 
-async def async_mbtiles_setup(cursor: aiosqlite.Cursor):
+async def async_mbtiles_setup(cursor: aiosqlite.Cursor, compressed: bool = False):
+    await cursor.execute(PRAGMA_AUTO_VACUUM)
     await cursor.execute(TABLE_TILES)
     await cursor.execute(TABLE_METADATA)
     await cursor.execute(TABLE_GRIDS)
     await cursor.execute(TABLE_GRID_DATA)
+    await cursor.execute(TABLE_TILES_CHECKSUM)
+    if compressed:
+        await cursor.execute(SQL_INSERT_COMPRESSION, (TILE_COMPRESSION_ZSTD,))
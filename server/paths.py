@@ -0,0 +1,15 @@
+import os
+
+# Directories of 'server.paths' resolved by the balancer, by default 'tiles' and 'data' in the root
+CACHE_DIR_ENV = "TILER_CACHE_DIR"
+DATA_DIR_ENV = "TILER_DATA_DIR"
+
+
+# Cache of tiles and MBTiles of DataSources
+def tiles_dir(root_path: str) -> str:
+    return os.environ.get(CACHE_DIR_ENV) or os.path.join(root_path, "tiles")
+
+
+# Source rasters, mosaics and the database 'tiler.db'
+def data_dir(root_path: str) -> str:
+    return os.environ.get(DATA_DIR_ENV) or os.path.join(root_path, "data")
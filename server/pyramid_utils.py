@@ -25,6 +25,7 @@ from raster_tiles.mosaic.multiprocess import (
     generate_mosaics_tiles_in_separate_processes,
 )
 from server.sqlite_db import sqlite_db_connect_async
+from server.paths import tiles_dir, data_dir
 
 from server.robyn import (
     Request,
@@ -56,6 +57,8 @@ class Pyramid(BaseModel):
     nodata_default: Optional[float] = -999999
     pixel_selection_method: Optional[str] = "FirstMethod"
     merge: Optional[bool] = True
+    # MBTiles is built in '{datasource_id}.mbtiles.new' and swapped in by the balancer
+    atomic_swap: Optional[bool] = False
 
     @validator("resampling")
     def validate_resampling(cls, value):
@@ -152,7 +155,7 @@ async def set_state_pyramid(
     dataset: Optional[str] = None,
     datasource_id: Optional[str] = None,
 ) -> None:
-    tiler_db: str = os.path.join(root_path, "data", "tiler.db")
+    tiler_db: str = os.path.join(data_dir(root_path), "tiler.db")
 
     if await aos.path.isfile(tiler_db):
         try:
@@ -216,7 +219,7 @@ async def set_state_pyramid(
 async def check_running_pyramid_for_dataset(
     dataset: str, datasource_id: str, root_path: str
 ) -> Optional[str]:
-    tiler_db: str = os.path.join(root_path, "data", "tiler.db")
+    tiler_db: str = os.path.join(data_dir(root_path), "tiler.db")
 
     if await aos.path.isfile(tiler_db):
         try:
@@ -254,14 +257,14 @@ async def check_running_pyramid_for_dataset(
 
 
 async def exist_dataset(dataset: str, root_path: str) -> bool:
-    dataset_file: str = os.path.join(root_path, "data", dataset)
+    dataset_file: str = os.path.join(data_dir(root_path), dataset)
     if not await aos.path.isfile(dataset_file):
         return False
     return True
 
 
 async def exist_mosaic_dataset(dataset: str, root_path: str) -> bool:
-    dataset_dir: str = os.path.join(root_path, "data", "mosaics", dataset)
+    dataset_dir: str = os.path.join(data_dir(root_path), "mosaics", dataset)
 
     if not await aos.path.isdir(dataset_dir):
         return False
@@ -281,7 +284,7 @@ async def exist_mosaic_dataset(dataset: str, root_path: str) -> bool:
 
 async def exist_mosaic(mosaic: str) -> None:
     parent_dir: Path = Path(__file__).parents[2]
-    mosaic_dir: str = os.path.join(str(parent_dir), "data", "mosaics", mosaic)
+    mosaic_dir: str = os.path.join(data_dir(str(parent_dir)), "mosaics", mosaic)
 
     if not await aos.path.isdir(mosaic_dir):
         raise HTTPException(
@@ -465,7 +468,7 @@ def json_to_mosaic_options(assets_dir: str, json_body: Dict[str, Any]) -> Mosaic
 
 def create_datasource_tiles_directory(datasource_id: str) -> str:
     datasource_dir: str = os.path.join(
-        str(Path(__file__).parents[1]), "tiles", datasource_id
+        tiles_dir(str(Path(__file__).parents[1])), datasource_id
     )
     if not os.path.isdir(datasource_dir):
         os.makedirs(datasource_dir, exist_ok=True)
@@ -473,6 +476,13 @@ def create_datasource_tiles_directory(datasource_id: str) -> str:
     return datasource_dir
 
 
+def pyramid_mbtiles_db(root_path: str, datasource_id: str, atomic_swap: bool) -> str:
+    name: str = f"{datasource_id}.mbtiles"
+    if atomic_swap:
+        name = f"{name}.new"
+    return os.path.join(tiles_dir(root_path), datasource_id, name)
+
+
 def pyramid_to_tiles_options(p: Pyramid, dataset: str, root_path: str) -> TilesOptions:
     options = TilesOptions()
 
@@ -486,11 +496,8 @@ def pyramid_to_tiles_options(p: Pyramid, dataset: str, root_path: str) -> TilesO
     options.zoom = p.zoom
     options.mbtiles = p.mbtiles
 
-    options.mbtiles_db = os.path.join(
-        root_path,
-        "tiles",
-        p.datasource_id,
-        f"{p.datasource_id}.mbtiles",
+    options.mbtiles_db = pyramid_mbtiles_db(
+        root_path, p.datasource_id, bool(p.atomic_swap)
     )
     options.datasource_dir = create_datasource_tiles_directory(p.datasource_id)
 
@@ -520,11 +527,8 @@ def pyramid_to_mosaics_options(
     options.count_processes = p.count_processes
     options.mbtiles = p.mbtiles
 
-    options.mbtiles_db = os.path.join(
-        root_path,
-        "tiles",
-        p.datasource_id,
-        f"{p.datasource_id}.mbtiles",
+    options.mbtiles_db = pyramid_mbtiles_db(
+        root_path, p.datasource_id, bool(p.atomic_swap)
     )
     options.datasource_dir = create_datasource_tiles_directory(p.datasource_id)
 
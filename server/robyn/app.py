@@ -23,6 +23,7 @@ from concurrent_log_handler import ConcurrentTimedRotatingFileHandler as _
 from starlette.exceptions import HTTPException
 
 from server.tile_utils import get_tile_job
+from server.paths import data_dir
 from raster_tiles.single_tile.tile import mosaic_tile, tile
 from server.pyramid_utils import (
     check_running_pyramid_for_dataset,
@@ -52,6 +53,21 @@ async def health():
     return f"Robyn process with ID {pid} is running"
 
 
+@app.post("/api/log_level")  # type: ignore
+async def log_level(request: Request) -> Union[Response, Dict[str, Any]]:
+    json_body = json.loads(request.body)
+    level = str(json_body.get("level", "")).upper()
+    if level not in ("ERROR", "WARNING", "WARN", "INFO", "DEBUG", "TRACE"):
+        return bad_request(
+            request, jsonify({"message": f"Log level '{level}' is unsupported"})
+        )
+
+    # Python logging has no TRACE level
+    python_level = {"WARN": "WARNING", "TRACE": "DEBUG"}.get(level, level)
+    logging.getLogger().setLevel(python_level)
+    return {"worker_pid": os.getpid(), "log_level": level}
+
+
 @app.get("/api/tile/:dataset/:z/:x/:y_ext")  # type: ignore
 async def get_tile(
     request: Request,
@@ -113,7 +129,7 @@ async def get_tile(
         else:
             parent_dir: Path = Path(__file__).parents[2]
             db_path: str = os.path.join(
-                str(parent_dir), "data", dataset, f"{dataset}.db"
+                data_dir(str(parent_dir)), dataset, f"{dataset}.db"
             )
             tile_job: Optional[sqlite3.Row] = await get_tile_job(db_path)
             modified_time = os.path.getmtime(db_path)
@@ -303,7 +319,7 @@ async def get_mosaics_tile(
         else:
             parent_dir: Path = Path(__file__).parents[2]
             db_path: str = os.path.join(
-                str(parent_dir), "data", "mosaics", dataset, f"{dataset}.db"
+                data_dir(str(parent_dir)), "mosaics", dataset, f"{dataset}.db"
             )
             tile_job: Optional[sqlite3.Row] = await get_tile_job(db_path)
             modified_time = os.path.getmtime(db_path)
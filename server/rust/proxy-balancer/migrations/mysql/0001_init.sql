@@ -0,0 +1,13 @@
+-- Initial schema of the config database
+CREATE TABLE IF NOT EXISTS queue (
+    id INT AUTO_INCREMENT PRIMARY KEY,
+    job_id VARCHAR(36) NOT NULL UNIQUE,
+    created_at DATETIME(6) NOT NULL,
+    updated_at DATETIME(6) NOT NULL,
+    scheduled_for DATETIME(6) NOT NULL,
+    failed_attempts INT NOT NULL,
+    status INT NOT NULL,
+    job_detail JSON NOT NULL,
+    INDEX index_queue_on_scheduled_for (scheduled_for),
+    INDEX index_queue_on_status (status)
+);
@@ -1,4 +1,4 @@
--- name: create-table-datasource
+-- Initial schema of the config database
 CREATE TABLE IF NOT EXISTS datasource (
     id SERIAL PRIMARY KEY,
     identifier VARCHAR NOT NULL UNIQUE,
@@ -17,7 +17,6 @@ CREATE TABLE IF NOT EXISTS datasource (
     data JSONB NOT NULL
 );
 
--- name: create-table-queue
 CREATE TABLE IF NOT EXISTS queue (
     id SERIAL PRIMARY KEY,
     job_id VARCHAR NOT NULL UNIQUE,
@@ -28,7 +27,5 @@ CREATE TABLE IF NOT EXISTS queue (
     status INT NOT NULL,
     job_detail JSONB NOT NULL
 );
--- name: create-index_queue_on_scheduled_for
 CREATE INDEX IF NOT EXISTS index_queue_on_scheduled_for ON queue (scheduled_for);
--- name: create-index_queue_on_status
 CREATE INDEX IF NOT EXISTS index_queue_on_status ON queue (status);
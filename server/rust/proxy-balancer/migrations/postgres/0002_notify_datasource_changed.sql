@@ -0,0 +1,12 @@
+-- Changes of DataSources are announced to every node of the cluster
+CREATE OR REPLACE FUNCTION notify_datasource_changed() RETURNS trigger AS $$
+BEGIN
+    PERFORM pg_notify('datasource_changed', COALESCE(NEW.identifier, OLD.identifier));
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS datasource_changed ON datasource;
+CREATE TRIGGER datasource_changed
+    AFTER INSERT OR UPDATE OR DELETE ON datasource
+    FOR EACH ROW EXECUTE FUNCTION notify_datasource_changed();
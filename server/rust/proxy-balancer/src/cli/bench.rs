@@ -0,0 +1,428 @@
+use std::process::exit;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::anyhow;
+use clap::Args;
+use serde::Serialize;
+use tokio::task::JoinHandle;
+
+#[derive(Args, Debug, Clone)]
+pub struct BenchArgs {
+    // Base URL of the running server, e.g. 'http://localhost:8000'
+    #[arg(long)]
+    pub url: String,
+    #[arg(long, default_value_t = 8)]
+    pub concurrency: usize,
+    // Duration of the load: '30s', '5m' or seconds
+    #[arg(long, default_value = "60s")]
+    pub duration: String,
+    // Random tiles of DataSources, the option is repeated for several DataSources
+    #[arg(long)]
+    pub datasource: Vec<String>,
+    // Zoom levels of random tiles: '10' or '0-14'
+    #[arg(long, default_value = "0-14")]
+    pub zoom: String,
+    // Extension of random tiles
+    #[arg(long, default_value = "png")]
+    pub ext: String,
+    // Random tiles inside 'min_lon,min_lat,max_lon,max_lat' in degrees
+    #[arg(long)]
+    pub bbox: Option<String>,
+    // File with recorded paths or URLs of requests, one per line, replayed instead of random tiles
+    #[arg(long)]
+    pub record: Option<String>,
+    // Recorded requests are replayed in random order, by default in the order of the file
+    #[arg(long)]
+    pub shuffle: bool,
+    // Report is printed as JSON to compare releases
+    #[arg(long)]
+    pub json: bool,
+}
+
+// Requests of the load: recorded paths or random tiles
+enum Pattern {
+    Recorded {
+        paths: Vec<String>,
+        shuffle: bool,
+    },
+    Random {
+        datasources: Vec<String>,
+        zooms: (u32, u32),
+        ext: String,
+        bbox: Option<[f64; 4]>,
+    },
+}
+
+// xorshift generator of random tiles, the load does not need cryptographic randomness
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Rng {
+        Rng(seed.max(1))
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let mut x = self.0;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.0 = x;
+        x
+    }
+
+    // Value in the range 'from..=to'
+    fn range(&mut self, from: u64, to: u64) -> u64 {
+        from + self.next_u64() % (to - from + 1)
+    }
+}
+
+impl Pattern {
+    fn path(&self, rng: &mut Rng, index: u64) -> String {
+        match self {
+            Pattern::Recorded { paths, shuffle } => {
+                let i = if *shuffle { rng.next_u64() } else { index };
+                paths[(i % paths.len() as u64) as usize].clone()
+            }
+            Pattern::Random {
+                datasources,
+                zooms,
+                ext,
+                bbox,
+            } => {
+                let datasource_id =
+                    &datasources[rng.range(0, datasources.len() as u64 - 1) as usize];
+                let z = rng.range(zooms.0 as u64, zooms.1 as u64) as u32;
+                let max = (1u64 << z) - 1;
+                let (x_min, y_min, x_max, y_max) = match bbox {
+                    Some([min_lon, min_lat, max_lon, max_lat]) => {
+                        let (x_min, y_min) = lonlat_to_tile(*min_lon, *max_lat, z);
+                        let (x_max, y_max) = lonlat_to_tile(*max_lon, *min_lat, z);
+                        (x_min, y_min, x_max, y_max)
+                    }
+                    None => (0, 0, max, max),
+                };
+                let x = rng.range(x_min, x_max);
+                let y = rng.range(y_min, y_max);
+                format!("/api/tile/{datasource_id}/{z}/{x}/{y}.{ext}")
+            }
+        }
+    }
+}
+
+// Tile of WebMercator with the point, Y of the XYZ scheme
+fn lonlat_to_tile(lon: f64, lat: f64, z: u32) -> (u64, u64) {
+    let n = (1u64 << z) as f64;
+    let lat = lat.clamp(-85.0511, 85.0511).to_radians();
+    let x = ((lon.clamp(-180.0, 180.0) + 180.0) / 360.0 * n).floor();
+    let y = ((1.0 - lat.tan().asinh() / std::f64::consts::PI) / 2.0 * n).floor();
+    (x.clamp(0.0, n - 1.0) as u64, y.clamp(0.0, n - 1.0) as u64)
+}
+
+// '30s', '5m', '1h' or seconds
+fn parse_duration(value: &str) -> Result<Duration, anyhow::Error> {
+    let value = value.trim();
+    let (number, unit) = match value.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => value.split_at(i),
+        None => (value, "s"),
+    };
+    let number: u64 = number
+        .parse()
+        .map_err(|_| anyhow!("Duration '{value}' is invalid"))?;
+    let secs = match unit {
+        "s" => number,
+        "m" => number * 60,
+        "h" => number * 60 * 60,
+        _ => {
+            return Err(anyhow!(
+                "Unit of duration '{value}' must be 's', 'm' or 'h'"
+            ))
+        }
+    };
+    Ok(Duration::from_secs(secs))
+}
+
+fn parse_zooms(value: &str) -> Result<(u32, u32), anyhow::Error> {
+    let (from, to) = value.split_once('-').unwrap_or((value, value));
+    let from: u32 = from.trim().parse()?;
+    let to: u32 = to.trim().parse()?;
+    if from > to || to > 30 {
+        return Err(anyhow!("Zoom levels '{value}' are invalid"));
+    }
+    Ok((from, to))
+}
+
+fn parse_bbox(value: &str) -> Result<[f64; 4], anyhow::Error> {
+    let values = value
+        .split(',')
+        .map(|v| v.trim().parse::<f64>())
+        .collect::<Result<Vec<f64>, _>>()?;
+    match values[..] {
+        [min_lon, min_lat, max_lon, max_lat] if min_lon < max_lon && min_lat < max_lat => {
+            Ok([min_lon, min_lat, max_lon, max_lat])
+        }
+        _ => Err(anyhow!(
+            "Bbox '{value}' must be 'min_lon,min_lat,max_lon,max_lat'"
+        )),
+    }
+}
+
+async fn load_pattern(args: &BenchArgs) -> Result<Pattern, anyhow::Error> {
+    if let Some(record) = args.record.as_ref() {
+        let data = tokio::fs::read_to_string(record).await?;
+        let paths: Vec<String> = data
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(String::from)
+            .collect();
+        if paths.is_empty() {
+            return Err(anyhow!("File '{record}' has no requests"));
+        }
+        return Ok(Pattern::Recorded {
+            paths,
+            shuffle: args.shuffle,
+        });
+    }
+    if args.datasource.is_empty() {
+        return Err(anyhow!("Option '--datasource' or '--record' is required"));
+    }
+    Ok(Pattern::Random {
+        datasources: args.datasource.clone(),
+        zooms: parse_zooms(&args.zoom)?,
+        ext: args.ext.clone(),
+        bbox: args.bbox.as_deref().map(parse_bbox).transpose()?,
+    })
+}
+
+// Results of requests of one task of the load
+#[derive(Debug, Default)]
+struct BenchResult {
+    latencies: Vec<Duration>,
+    // responses by classes of status: 2xx, 3xx, 4xx, 5xx
+    statuses: [u64; 4],
+    errors: u64,
+    bytes: u64,
+}
+
+impl BenchResult {
+    fn add(&mut self, other: BenchResult) {
+        self.latencies.extend(other.latencies);
+        for (i, n) in other.statuses.iter().enumerate() {
+            self.statuses[i] += n;
+        }
+        self.errors += other.errors;
+        self.bytes += other.bytes;
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct BenchReport {
+    url: String,
+    concurrency: usize,
+    duration_secs: f64,
+    requests: u64,
+    errors: u64,
+    status_2xx: u64,
+    status_3xx: u64,
+    status_4xx: u64,
+    status_5xx: u64,
+    requests_per_sec: f64,
+    bytes_per_sec: f64,
+    latency_ms: LatencyReport,
+}
+
+#[derive(Debug, Serialize)]
+struct LatencyReport {
+    min: f64,
+    mean: f64,
+    p50: f64,
+    p90: f64,
+    p95: f64,
+    p99: f64,
+    max: f64,
+}
+
+// Nearest-rank percentile of sorted latencies in milliseconds
+fn percentile(sorted: &[Duration], q: f64) -> f64 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+    let rank = ((q * sorted.len() as f64).ceil() as usize).clamp(1, sorted.len());
+    sorted[rank - 1].as_secs_f64() * 1000.0
+}
+
+fn bench_task(
+    client: reqwest::Client,
+    base_url: Arc<String>,
+    pattern: Arc<Pattern>,
+    deadline: Instant,
+    seed: u64,
+    offset: u64,
+    step: u64,
+) -> JoinHandle<BenchResult> {
+    tokio::spawn(async move {
+        let mut rng = Rng::new(seed);
+        let mut result = BenchResult::default();
+        let mut index = offset;
+        while Instant::now() < deadline {
+            let path = pattern.path(&mut rng, index);
+            index += step;
+            let url = if path.starts_with("http://") || path.starts_with("https://") {
+                path
+            } else {
+                format!("{base_url}{path}")
+            };
+
+            let started = Instant::now();
+            match client.get(&url).send().await {
+                Ok(response) => {
+                    let class = (response.status().as_u16() / 100).clamp(2, 5) - 2;
+                    match response.bytes().await {
+                        Ok(body) => {
+                            result.statuses[class as usize] += 1;
+                            result.bytes += body.len() as u64;
+                        }
+                        Err(_) => result.errors += 1,
+                    }
+                }
+                Err(_) => result.errors += 1,
+            }
+            result.latencies.push(started.elapsed());
+        }
+        result
+    })
+}
+
+/*
+    Load of the running server by concurrent tile requests for the duration: random tiles of DataSources
+    or replay of recorded requests. Throughput and percentiles of latency are reported to measure
+    performance of the proxy from release to release
+*/
+pub async fn command_bench(args: BenchArgs) {
+    let duration = match parse_duration(&args.duration) {
+        Ok(d) => d,
+        Err(err) => {
+            eprintln!("{err}");
+            exit(1);
+        }
+    };
+    let pattern = match load_pattern(&args).await {
+        Ok(p) => Arc::new(p),
+        Err(err) => {
+            eprintln!("Error load requests of the load: {err}");
+            exit(1);
+        }
+    };
+    let concurrency = args.concurrency.max(1);
+    let client = match reqwest::Client::builder()
+        .pool_max_idle_per_host(concurrency)
+        .build()
+    {
+        Ok(c) => c,
+        Err(err) => {
+            eprintln!("Error create HTTP client: {err}");
+            exit(1);
+        }
+    };
+
+    let base_url = Arc::new(args.url.trim_end_matches('/').to_string());
+    let seed = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(1);
+    if !args.json {
+        println!(
+            "Load of '{base_url}' by {concurrency} connections for {:?}",
+            duration
+        );
+    }
+
+    let started = Instant::now();
+    let deadline = started + duration;
+    let tasks: Vec<JoinHandle<BenchResult>> = (0..concurrency)
+        .map(|i| {
+            bench_task(
+                client.clone(),
+                base_url.clone(),
+                pattern.clone(),
+                deadline,
+                seed.wrapping_add(i as u64 * 0x9E37_79B9),
+                i as u64,
+                concurrency as u64,
+            )
+        })
+        .collect();
+
+    let mut result = BenchResult::default();
+    for task in tasks {
+        match task.await {
+            Ok(r) => result.add(r),
+            Err(err) => eprintln!("Error of the load task: {err}"),
+        }
+    }
+    let elapsed = started.elapsed().as_secs_f64();
+
+    result.latencies.sort();
+    let requests = result.latencies.len() as u64;
+    let mean = if requests > 0 {
+        result
+            .latencies
+            .iter()
+            .map(|d| d.as_secs_f64() * 1000.0)
+            .sum::<f64>()
+            / requests as f64
+    } else {
+        0.0
+    };
+    let report = BenchReport {
+        url: base_url.to_string(),
+        concurrency,
+        duration_secs: elapsed,
+        requests,
+        errors: result.errors,
+        status_2xx: result.statuses[0],
+        status_3xx: result.statuses[1],
+        status_4xx: result.statuses[2],
+        status_5xx: result.statuses[3],
+        requests_per_sec: requests as f64 / elapsed,
+        bytes_per_sec: result.bytes as f64 / elapsed,
+        latency_ms: LatencyReport {
+            min: percentile(&result.latencies, 0.0),
+            mean,
+            p50: percentile(&result.latencies, 0.5),
+            p90: percentile(&result.latencies, 0.9),
+            p95: percentile(&result.latencies, 0.95),
+            p99: percentile(&result.latencies, 0.99),
+            max: percentile(&result.latencies, 1.0),
+        },
+    };
+
+    if args.json {
+        match serde_json::to_string_pretty(&report) {
+            Ok(json) => println!("{json}"),
+            Err(err) => {
+                eprintln!("Error serialize report: {err}");
+                exit(1);
+            }
+        }
+        return;
+    }
+
+    let l = &report.latency_ms;
+    println!(
+        "Requests: {} in {:.1}s, {:.1} req/s, {:.1} KB/s",
+        report.requests,
+        report.duration_secs,
+        report.requests_per_sec,
+        report.bytes_per_sec / 1024.0
+    );
+    println!(
+        "Responses: 2xx {}, 3xx {}, 4xx {}, 5xx {}, errors {}",
+        report.status_2xx, report.status_3xx, report.status_4xx, report.status_5xx, report.errors
+    );
+    println!(
+        "Latency (ms): min {:.2}, mean {:.2}, p50 {:.2}, p90 {:.2}, p95 {:.2}, p99 {:.2}, max {:.2}",
+        l.min, l.mean, l.p50, l.p90, l.p95, l.p99, l.max
+    );
+}
@@ -0,0 +1,218 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::process::exit;
+
+use anyhow::anyhow;
+
+use super::confirm;
+use crate::config::{load_config, Config};
+use crate::db::db_pool;
+use crate::db::registry::open_registry;
+use crate::defaults::REGISTRY_POSTGRES;
+use crate::environment::setup_envs;
+use crate::paths::Paths;
+
+// Directory or file of the cache without DataSource in the registry
+#[derive(Debug)]
+struct Orphan {
+    path: PathBuf,
+    reason: &'static str,
+    size: u64,
+}
+
+async fn path_size(path: &Path) -> u64 {
+    let mut size = 0;
+    let mut stack = vec![path.to_path_buf()];
+    while let Some(p) = stack.pop() {
+        let Ok(metadata) = tokio::fs::symlink_metadata(&p).await else {
+            continue;
+        };
+        if !metadata.is_dir() {
+            size += metadata.len();
+            continue;
+        }
+        if let Ok(mut entries) = tokio::fs::read_dir(&p).await {
+            while let Ok(Some(entry)) = entries.next_entry().await {
+                stack.push(entry.path());
+            }
+        }
+    }
+    size
+}
+
+async fn read_entries(dir: &Path) -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+    if let Ok(mut entries) = tokio::fs::read_dir(dir).await {
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            paths.push(entry.path());
+        }
+    }
+    paths.sort();
+    paths
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+/*
+    Caches '{cache}/{datasource_id}' of deleted DataSources and fragments '-wal'/'-shm'
+    of MBTiles without the file of the DataSource left after crashes
+*/
+async fn tiles_orphans(paths: &Paths, identifiers: &HashSet<String>) -> Vec<Orphan> {
+    let mut orphans = Vec::new();
+    for path in read_entries(&paths.cache).await {
+        if !path.is_dir() {
+            continue;
+        }
+        if !identifiers.contains(&file_name(&path)) {
+            orphans.push(Orphan {
+                size: path_size(&path).await,
+                path,
+                reason: "cache of deleted DataSource",
+            });
+            continue;
+        }
+        for fragment in read_entries(&path).await {
+            let name = file_name(&fragment);
+            let Some(db_name) = name
+                .strip_suffix("-wal")
+                .or_else(|| name.strip_suffix("-shm"))
+            else {
+                continue;
+            };
+            if !tokio::fs::try_exists(path.join(db_name))
+                .await
+                .unwrap_or(true)
+            {
+                orphans.push(Orphan {
+                    size: path_size(&fragment).await,
+                    path: fragment,
+                    reason: "fragment of missing MBTiles",
+                });
+            }
+        }
+    }
+    orphans
+}
+
+/*
+    JSON files of DataSources in 'datasources/{vector,raster}' with identifiers missing in the registry,
+    DataSources in the trash keep their files until they are purged
+*/
+async fn definitions_orphans(paths: &Paths, identifiers: &HashSet<String>) -> Vec<Orphan> {
+    let mut orphans = Vec::new();
+    for ds_type in ["vector", "raster"] {
+        for path in read_entries(&paths.datasources_dir(ds_type)).await {
+            if path.extension().and_then(|ext| ext.to_str()) != Some("json") {
+                continue;
+            }
+            let Ok(data) = tokio::fs::read(&path).await else {
+                continue;
+            };
+            let Ok(definition) = serde_json::from_slice::<serde_json::Value>(&data) else {
+                continue;
+            };
+            match definition.get("id").and_then(|id| id.as_str()) {
+                Some(id) if !identifiers.contains(id) => {
+                    let trashed = paths.trash.join(format!("{id}.json"));
+                    if tokio::fs::try_exists(&trashed).await.unwrap_or(true) {
+                        continue;
+                    }
+                    orphans.push(Orphan {
+                        size: data.len() as u64,
+                        path,
+                        reason: "definition of deleted DataSource",
+                    });
+                }
+                _ => {}
+            }
+        }
+    }
+    orphans
+}
+
+async fn registry_identifiers(
+    paths: &Paths,
+    config: &Config,
+) -> Result<HashSet<String>, anyhow::Error> {
+    let pool = if config.registry_type == REGISTRY_POSTGRES {
+        db_pool(&config.db_pool).await?.postgres()
+    } else {
+        // the registry created empty by opening would make every cache an orphan
+        let path = paths.resolve(&config.registry_path);
+        if !tokio::fs::try_exists(&path).await? {
+            return Err(anyhow!("Registry {path:?} not found"));
+        }
+        None
+    };
+    let registry = open_registry(paths, config, pool).await?;
+    let datasources = registry.datasources().await?;
+    Ok(datasources.into_iter().map(|ds| ds.identifier).collect())
+}
+
+/*
+    Cross-check of the registry of DataSources against the cache and the directory 'datasources':
+    orphans are reported, with 'dry_run' nothing is removed, with 'yes' they are removed
+    without confirmation
+*/
+pub async fn command_cleanup(cwd: String, dry_run: bool, yes: bool) {
+    let _ = setup_envs();
+    let config = load_config()
+        .await
+        .expect("Error load configuration from 'config_app.json'");
+
+    let paths = Paths::new(&cwd, &config.paths);
+
+    let identifiers = match registry_identifiers(&paths, &config).await {
+        Ok(ids) => ids,
+        Err(err) => {
+            eprintln!("Error load DataSources from the registry: {err}");
+            exit(1);
+        }
+    };
+
+    let mut orphans = tiles_orphans(&paths, &identifiers).await;
+    orphans.extend(definitions_orphans(&paths, &identifiers).await);
+    if orphans.is_empty() {
+        println!(
+            "No orphans, {} DataSources in the registry",
+            identifiers.len()
+        );
+        return;
+    }
+
+    for orphan in orphans.iter() {
+        println!(
+            "{:?}: {}, {} bytes",
+            orphan.path, orphan.reason, orphan.size
+        );
+    }
+    let total: u64 = orphans.iter().map(|orphan| orphan.size).sum();
+    println!("Orphans: {}, {} bytes", orphans.len(), total);
+
+    if dry_run || !(yes || confirm(&format!("Remove {} orphans?", orphans.len()))) {
+        return;
+    }
+
+    let mut failed = false;
+    for orphan in orphans {
+        let result = if orphan.path.is_dir() {
+            tokio::fs::remove_dir_all(&orphan.path).await
+        } else {
+            tokio::fs::remove_file(&orphan.path).await
+        };
+        match result {
+            Ok(_) => println!("Removed {:?}", orphan.path),
+            Err(err) => {
+                eprintln!("Error remove {:?}: {err}", orphan.path);
+                failed = true;
+            }
+        }
+    }
+    if failed {
+        exit(1);
+    }
+}
@@ -1,30 +1,162 @@
+use std::path::Path;
 use std::process::exit;
 
-use crate::config::load_db_config;
-use crate::db::{connection_pg_config_db, create_pg_config_db, init_db, init_pg_db};
+use clap::Args;
+
+use super::confirm;
+use crate::config::{load_config, load_db_config, DBBackend, DBConfig};
+use crate::db::registry::sqlite::SqliteRegistry;
+use crate::db::{
+    connection_mysql_config_db, connection_pg_config_db, create_mysql_config_db,
+    create_pg_config_db, db_tiler, drop_mysql_schema, drop_pg_schema, init_db, migrate_mysql_db,
+    migrate_pg_db, pending_mysql_migrations, pending_pg_migrations,
+};
+use crate::defaults::{REGISTRY_POSTGRES, REGISTRY_SQLITE};
 use crate::environment::{init_dirs, setup_envs};
+use crate::paths::Paths;
 
 use sqlx::Connection;
 
-pub async fn command_init(cwd: String) {
+#[derive(Args, Debug, Clone)]
+pub struct InitArgs {
+    // Drop the schema of the config database and SQLite databases and create them again
+    #[arg(long)]
+    pub force: bool,
+    // Re-initialization is not confirmed in the console
+    #[arg(long)]
+    pub yes: bool,
+    // Databases are not initialized, only directories
+    #[arg(long)]
+    pub skip_db: bool,
+    // Directories are not initialized, only databases
+    #[arg(long)]
+    pub skip_dirs: bool,
+}
+
+/*
+    Initialization is idempotent: existing directories and databases are kept and reported,
+    only missing ones are created and new migrations are applied.
+    With 'force' DataSources of the registry, the job queue and history of pyramids are lost
+*/
+pub async fn command_init(cwd: String, args: InitArgs) {
     let _ = setup_envs();
-    let db_config = load_db_config();
+    let config = load_config()
+        .await
+        .expect("Error load configuration from 'config_app.json'");
 
-    // initialize application directories
-    if let Err(err) = init_dirs(&cwd).await {
-        eprintln!(
-            "Error initialize application directories: {}",
-            err.to_string()
-        );
+    let paths = Paths::new(&cwd, &config.paths);
+
+    if args.force
+        && !args.skip_db
+        && !args.yes
+        && !confirm("Drop the registry of DataSources, the job queue and 'tiler.db'?")
+    {
+        println!("Init is cancelled");
         exit(1);
     }
 
-    if let Err(err) = create_pg_config_db(&db_config).await {
-        eprintln!("Error create PostgreSQL DataBase: {}", err.to_string());
+    // initialize application directories
+    if !args.skip_dirs {
+        match init_dirs(&paths).await {
+            Ok(created) if created.is_empty() => println!("Directories exist"),
+            Ok(created) => {
+                for dir in created {
+                    println!("Directory {dir:?} created");
+                }
+            }
+            Err(err) => {
+                eprintln!("Error initialize application directories: {err}");
+                exit(1);
+            }
+        }
+    }
+
+    if args.skip_db {
+        return;
+    }
+
+    // registry 'sqlite' is created in its file
+    if config.registry_type == REGISTRY_SQLITE {
+        let path = paths.resolve(&config.registry_path);
+        let exists = prepare_sqlite_db(&path, args.force).await;
+        if let Err(err) = SqliteRegistry::open(path.clone()).await {
+            eprintln!("Error initialize registry of DataSources: {err}");
+            exit(1);
+        }
+        report_sqlite_db("Registry of DataSources", &path, exists);
+    }
+
+    // config database keeps the registry 'postgres' and the job queue of the master
+    if config.registry_type == REGISTRY_POSTGRES || config.master {
+        let db_config = load_db_config();
+        match db_config.backend {
+            DBBackend::Postgres => init_postgres(&db_config, args.force).await,
+            DBBackend::MySql => {
+                if config.registry_type == REGISTRY_POSTGRES {
+                    eprintln!("Registry 'postgres' of DataSources requires 'DBSCHEME=postgres'");
+                    exit(1);
+                }
+                init_mysql(&db_config, args.force).await
+            }
+        }
+    }
+
+    // check exist DB tiler.db
+    let path = db_tiler(&paths).expect("Error path of DataBase 'tiler.db'");
+    let exists = prepare_sqlite_db(&path, args.force).await;
+    if let Err(err) = init_db(&paths).await {
+        eprintln!("Error initialize DataBase 'tiler.db': {err}");
         exit(1);
     }
+    report_sqlite_db("DataBase 'tiler.db'", &path, exists);
+}
+
+// With 'force' the database and its '-wal'/'-shm' files are removed, the result - the database exists
+async fn prepare_sqlite_db(path: &Path, force: bool) -> bool {
+    let exists = tokio::fs::try_exists(path).await.unwrap_or(false);
+    if !exists || !force {
+        return exists;
+    }
+    for suffix in ["", "-wal", "-shm"] {
+        let mut file = path.as_os_str().to_owned();
+        file.push(suffix);
+        if let Err(err) = tokio::fs::remove_file(&file).await {
+            if err.kind() != std::io::ErrorKind::NotFound {
+                eprintln!("Error remove {file:?}: {err}");
+                exit(1);
+            }
+        }
+    }
+    false
+}
+
+fn report_sqlite_db(name: &str, path: &Path, existed: bool) {
+    if existed {
+        println!("{name} exists: {path:?}");
+    } else {
+        println!("{name} created: {path:?}");
+    }
+}
+
+fn report_migrations(backend: &str, pending: &[i64]) {
+    if pending.is_empty() {
+        println!("Schema of {backend} DataBase is up to date");
+    } else {
+        println!("Migrations {pending:?} of {backend} DataBase applied");
+    }
+}
+
+async fn init_postgres(db_config: &DBConfig, force: bool) {
+    match create_pg_config_db(db_config).await {
+        Err(err) => {
+            eprintln!("Error create PostgreSQL DataBase: {err}");
+            exit(1);
+        }
+        Ok(true) => println!("PostgreSQL DataBase '{}' created", db_config.name),
+        Ok(false) => println!("PostgreSQL DataBase '{}' exists", db_config.name),
+    }
 
-    let conn = match connection_pg_config_db(&db_config).await {
+    let mut conn = match connection_pg_config_db(db_config).await {
         Err(err) => {
             eprintln!("{err}");
             exit(1);
@@ -32,20 +164,74 @@ pub async fn command_init(cwd: String) {
         Ok(c) => c,
     };
 
-    let conn = match init_pg_db(&cwd, conn).await {
+    if force {
+        if let Err(err) = drop_pg_schema(&mut conn).await {
+            eprintln!("Error drop schema of PostgreSQL DataBase: {err}");
+            exit(1);
+        }
+        println!("Schema of PostgreSQL DataBase dropped");
+    }
+    let pending = match pending_pg_migrations(&mut conn).await {
+        Err(err) => {
+            eprintln!("Error check migrations of PostgreSQL DataBase: {err}");
+            exit(1);
+        }
+        Ok(p) => p,
+    };
+
+    let conn = match migrate_pg_db(conn).await {
         Err(err) => {
-            eprintln!("Error initialize PostgreSQL DataBase: {}", err.to_string());
+            eprintln!("Error migrate PostgreSQL DataBase: {err}");
             exit(1);
         }
         Ok(conn) => conn,
     };
+    report_migrations("PostgreSQL", &pending);
     conn.close()
         .await
         .expect("Error close PostgreSQL connection");
+}
 
-    // check exist DB tiler.db
-    if let Err(err) = init_db(&cwd).await {
-        eprintln!("Error initialize DataBase 'tiler.db': {}", err.to_string());
-        exit(1);
+async fn init_mysql(db_config: &DBConfig, force: bool) {
+    match create_mysql_config_db(db_config).await {
+        Err(err) => {
+            eprintln!("Error create MySQL DataBase: {err}");
+            exit(1);
+        }
+        Ok(true) => println!("MySQL DataBase '{}' created", db_config.name),
+        Ok(false) => println!("MySQL DataBase '{}' exists", db_config.name),
+    }
+
+    let mut conn = match connection_mysql_config_db(db_config).await {
+        Err(err) => {
+            eprintln!("{err}");
+            exit(1);
+        }
+        Ok(c) => c,
+    };
+
+    if force {
+        if let Err(err) = drop_mysql_schema(&mut conn).await {
+            eprintln!("Error drop schema of MySQL DataBase: {err}");
+            exit(1);
+        }
+        println!("Schema of MySQL DataBase dropped");
     }
+    let pending = match pending_mysql_migrations(&mut conn).await {
+        Err(err) => {
+            eprintln!("Error check migrations of MySQL DataBase: {err}");
+            exit(1);
+        }
+        Ok(p) => p,
+    };
+
+    let conn = match migrate_mysql_db(conn).await {
+        Err(err) => {
+            eprintln!("Error migrate MySQL DataBase: {err}");
+            exit(1);
+        }
+        Ok(conn) => conn,
+    };
+    report_migrations("MySQL", &pending);
+    conn.close().await.expect("Error close MySQL connection");
 }
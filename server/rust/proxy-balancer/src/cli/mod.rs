@@ -1,3 +1,20 @@
+pub mod bench;
+pub mod cleanup;
 pub mod init;
 pub mod serve;
 pub mod serve_cache;
+pub mod status;
+pub mod verify;
+
+use std::io::Write;
+
+// Answer 'y' or 'yes' in the console confirms the destructive command
+pub fn confirm(question: &str) -> bool {
+    print!("{question} [y/N] ");
+    let _ = std::io::stdout().flush();
+    let mut answer = String::new();
+    if std::io::stdin().read_line(&mut answer).is_err() {
+        return false;
+    }
+    matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+}
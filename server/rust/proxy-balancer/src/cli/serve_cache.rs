@@ -1,50 +1,75 @@
 use std::convert::Infallible;
 use std::net::SocketAddr;
-use std::path::PathBuf;
 use std::process::exit;
+use std::sync::Arc;
 
 use ctrlc;
-use hyper::server::conn::AddrStream;
 use hyper::service::{make_service_fn, service_fn};
 use hyper::Server;
+use tiler_core::sqlite_clients::{sqlite_clients_maintenance, MessageSQLiteClient};
 use tracing::{event, Level};
 
 use crate::config::load_config;
+use crate::forwarded::{incoming, ClientStream};
 use crate::handles::cache::handle_cache;
-use crate::log::init_tracing;
-use crate::tasks::sqlite_clients::{sqlite_clients_maintenance, MessageSQLiteClient};
+use crate::log::{init_tracing, otel::shutdown_tracer};
+use crate::paths::Paths;
+use crate::remote::OriginSource;
+use crate::utils::static_root;
 
 pub async fn command_serve_cache(cwd: String) {
     let config = load_config()
         .await
         .expect("Error load configuration from 'config_app.json'");
 
-    if let Err(err) = init_tracing(&config.log_level_server) {
+    if let Err(err) = init_tracing(&config.log_level_server, config.otlp.as_ref()) {
         eprintln!("Error init tracing: {:?}", err);
         exit(1);
     }
 
     let (tx_sqlite_client, rx_sqlite_client) = flume::unbounded::<MessageSQLiteClient>();
-    let sqlite_clients_handle = sqlite_clients_maintenance(rx_sqlite_client);
+    // cache is served read-only, MBTiles are not maintained
+    let sqlite_clients_handle = sqlite_clients_maintenance(
+        rx_sqlite_client,
+        0,
+        config.mbtiles_thresholds,
+        config.sqlite_pool_max_clients,
+        config.sqlite_pool_idle_secs,
+        config.serve_cache.immutable,
+    );
 
-    let base_path = PathBuf::from(cwd.clone());
+    let paths = Paths::new(&cwd, &config.paths);
+    let static_root = static_root(&paths, &config);
+    // tiles missing in the roots are requested from the origin tiler
+    let origin = match config.serve_cache.origin.as_ref().map(OriginSource::new) {
+        Some(Ok(origin)) => Some(Arc::new(origin)),
+        Some(Err(err)) => {
+            eprintln!("Error create client of the origin: {:?}", err);
+            exit(1);
+        }
+        None => None,
+    };
 
-    let make_svc = make_service_fn(|conn: &AddrStream| {
+    let make_svc = make_service_fn(|conn: &ClientStream| {
         let remote_addr = conn.remote_addr().ip();
         let tx_sqlite_client = tx_sqlite_client.clone();
-        let cwd = cwd.clone();
-        let base_path = base_path.clone();
+        let paths = paths.clone();
+        let static_root = static_root.clone();
+        let config = config.clone();
+        let origin = origin.clone();
 
         async move {
             // Request handler
             Ok::<_, Infallible>(service_fn(move |req| {
-                let cwd = cwd.clone();
+                let paths = paths.clone();
                 handle_cache(
-                    cwd,
+                    paths,
                     remote_addr,
                     req,
                     tx_sqlite_client.clone(),
-                    base_path.clone(),
+                    static_root.clone(),
+                    config.clone(),
+                    origin.clone(),
                 )
             }))
         }
@@ -61,6 +86,7 @@ pub async fn command_serve_cache(cwd: String) {
             event!(Level::INFO, "Terminating...");
             std::thread::sleep(std::time::Duration::from_secs(3));
             sqlite_clients_handle.abort();
+            shutdown_tracer();
             exit(0);
         }
     })
@@ -69,7 +95,10 @@ pub async fn command_serve_cache(cwd: String) {
     let bind_addr = format!("{}:{}", config.host, config.port);
     let addr: SocketAddr = bind_addr.parse().expect("Could not parse ip:port");
 
-    let server = Server::bind(&addr).serve(make_svc);
+    let listener = incoming(addr, config.proxy.protocol)
+        .await
+        .expect("Could not bind ip:port");
+    let server = Server::builder(listener).serve(make_svc);
     event!(Level::INFO, "Isone Tiler Server running on {:?}", addr);
     if let Err(e) = server.await {
         event!(Level::ERROR, "Server error: {}", e);
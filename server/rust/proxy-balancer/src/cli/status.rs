@@ -0,0 +1,235 @@
+use std::process::exit;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use serde_json::Value;
+
+use crate::config::load_config;
+use crate::defaults::LOCALHOST;
+use crate::structs::EndPoints;
+
+const STATUS_TIMEOUT_SECS: u64 = 10;
+
+async fn get_json(
+    client: &reqwest::Client,
+    base_url: &str,
+    path: &str,
+) -> Result<Value, anyhow::Error> {
+    let response = client.get(format!("{base_url}{path}")).send().await?;
+    let status = response.status();
+    let body = response.text().await?;
+    if !status.is_success() {
+        return Err(anyhow!("'{path}' answered {status}: {body}"));
+    }
+    Ok(serde_json::from_str(&body)?)
+}
+
+// Address of the server from 'config_app.json', the wildcard host is queried on the localhost
+async fn default_address() -> Result<String, anyhow::Error> {
+    let config = load_config().await?;
+    let host = match config.host.as_str() {
+        "0.0.0.0" | "::" | "" => LOCALHOST,
+        host => host,
+    };
+    Ok(format!("{host}:{}", config.port))
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{value:.1} {}", UNITS[unit])
+    }
+}
+
+fn print_health(health: &Value) {
+    let ports = |key: &str| -> Vec<String> {
+        health[key]
+            .as_array()
+            .map(|ports| ports.iter().map(|p| p.to_string()).collect())
+            .unwrap_or_default()
+    };
+    let success = ports("success_ports");
+    let error = ports("error_ports");
+    println!(
+        "Workers: {} healthy, {} failed ({})",
+        success.len(),
+        error.len(),
+        health["health"].as_str().unwrap_or("unknown")
+    );
+    if !success.is_empty() {
+        println!("  healthy ports: {}", success.join(", "));
+    }
+    if !error.is_empty() {
+        println!("  failed ports: {}", error.join(", "));
+    }
+    for worker in health["workers"].as_array().into_iter().flatten() {
+        if let Some(err) = worker["error"].as_str() {
+            println!("  port {}: {err}", worker["port"]);
+        }
+    }
+}
+
+fn print_info_workers(info: &Value) {
+    if let Some(memory) = info["worker_memory"].as_object() {
+        let mut total = 0;
+        for (pid, childs) in memory {
+            let bytes: u64 = childs
+                .as_array()
+                .map(|childs| childs.iter().filter_map(Value::as_u64).sum())
+                .unwrap_or_default();
+            let count = info["worker_childs"][pid]
+                .as_array()
+                .map(Vec::len)
+                .unwrap_or_default();
+            println!(
+                "  process {pid}: {count} childs, memory {}",
+                format_bytes(bytes)
+            );
+            total += bytes;
+        }
+        println!("Memory of workers: {}", format_bytes(total));
+    }
+    if let Some(breakers) = info["circuit_breakers"].as_object() {
+        for (port, breaker) in breakers {
+            if let Some(state) = breaker["state"].as_str() {
+                if state != "closed" {
+                    println!("  circuit breaker of port {port}: {state}");
+                }
+            }
+        }
+    }
+    if let Some(restarts) = info["worker_restarts"].as_object() {
+        for (port, restart) in restarts {
+            if let Some(count) = restart["crashes"].as_u64() {
+                if count > 0 {
+                    println!("  port {port} crashed {count} times");
+                }
+            }
+        }
+    }
+    let storage = &info["storage"];
+    if let (Some(total), Some(available)) = (
+        storage["total_bytes"].as_u64(),
+        storage["available_bytes"].as_u64(),
+    ) {
+        println!(
+            "Storage: {} available of {}",
+            format_bytes(available),
+            format_bytes(total)
+        );
+    }
+}
+
+fn print_queue(limits: &Value) {
+    let Some(ports) = limits["ports"].as_object() else {
+        return;
+    };
+    let sum = |key: &str| -> u64 { ports.values().filter_map(|p| p[key].as_u64()).sum() };
+    println!(
+        "Queue: {} waiting ({} batch), {} of {} permits in use, {} recent rejections",
+        sum("queued"),
+        sum("batch_queued"),
+        sum("configured").saturating_sub(sum("available")),
+        sum("configured"),
+        sum("recent_rejections")
+    );
+}
+
+async fn print_datasources(client: &reqwest::Client, base_url: &str) {
+    let datasources = match get_json(client, base_url, EndPoints::DataSources.as_ref()).await {
+        Ok(d) => d,
+        Err(err) => {
+            println!("DataSources: unavailable ({err})");
+            return;
+        }
+    };
+    let identifiers: Vec<&str> = datasources
+        .as_array()
+        .map(|ds| ds.iter().filter_map(|d| d["identifier"].as_str()).collect())
+        .unwrap_or_default();
+    println!("DataSources: {}", identifiers.len());
+
+    let mut total = 0;
+    for datasource_id in identifiers {
+        let path = format!("{}/{datasource_id}/stats", EndPoints::DataSources);
+        match get_json(client, base_url, &path).await {
+            Ok(stats) => {
+                let size = stats["mbtiles"]["size"].as_u64().unwrap_or_default();
+                let tiles = stats["mbtiles"]["tiles"].as_u64().unwrap_or_default();
+                let disk_tiles = stats["disk"]["tiles"].as_u64().unwrap_or_default();
+                println!(
+                    "  {datasource_id}: cache {}, {tiles} tiles in MBTiles, {disk_tiles} tiles on disk",
+                    format_bytes(size)
+                );
+                total += size;
+            }
+            Err(err) => println!("  {datasource_id}: stats unavailable ({err})"),
+        }
+    }
+    println!("Cache of DataSources: {}", format_bytes(total));
+}
+
+/*
+    Summary of a running server: health of workers, their memory, queue of tile requests,
+    DataSources and sizes of their cache. By default the address of 'config_app.json' is queried,
+    unavailable endpoints are reported without stopping the summary
+*/
+pub async fn command_status(address: Option<String>) {
+    let address = match address {
+        Some(a) => a,
+        None => match default_address().await {
+            Ok(a) => a,
+            Err(err) => {
+                eprintln!("Error load address from config_app.json: {err}");
+                exit(1);
+            }
+        },
+    };
+    let base_url = if address.starts_with("http://") || address.starts_with("https://") {
+        address.trim_end_matches('/').to_string()
+    } else {
+        format!("http://{}", address.trim_end_matches('/'))
+    };
+    let client = match reqwest::Client::builder()
+        .timeout(Duration::from_secs(STATUS_TIMEOUT_SECS))
+        .build()
+    {
+        Ok(c) => c,
+        Err(err) => {
+            eprintln!("Error create HTTP client: {err}");
+            exit(1);
+        }
+    };
+
+    println!("Server: {base_url}");
+    match get_json(&client, &base_url, EndPoints::Health.as_ref()).await {
+        Ok(health) => print_health(&health),
+        Err(err) => {
+            eprintln!("Server {base_url} is unavailable: {err}");
+            exit(1);
+        }
+    }
+    match get_json(&client, &base_url, EndPoints::InfoWorkers.as_ref()).await {
+        Ok(info) => print_info_workers(&info),
+        Err(err) => println!("Info of workers: unavailable ({err})"),
+    }
+    match get_json(
+        &client,
+        &base_url,
+        EndPoints::LimitConcurrentRequests.as_ref(),
+    )
+    .await
+    {
+        Ok(limits) => print_queue(&limits),
+        Err(err) => println!("Queue: unavailable ({err})"),
+    }
+    print_datasources(&client, &base_url).await;
+}
@@ -0,0 +1,222 @@
+use std::path::{Path, PathBuf};
+use std::process::exit;
+
+use anyhow::anyhow;
+use async_sqlite::{ClientBuilder, JournalMode};
+use tiler_core::integrity::{
+    disk_tile_checksum, is_tile_intact, remove_disk_tile, remove_mbtile, tile_checksum,
+    write_disk_tile_checksum, TABLE_TILES_CHECKSUM,
+};
+use tiler_core::mbtiles::get_mbtiles_paths;
+
+use crate::config::load_config;
+use crate::paths::Paths;
+
+#[derive(Debug, Default)]
+struct VerifyReport {
+    checked: u64,
+    unrecorded: u64,
+    corrupt: u64,
+    repaired: u64,
+    recorded: u64,
+}
+
+impl VerifyReport {
+    fn add(&mut self, other: VerifyReport) {
+        self.checked += other.checked;
+        self.unrecorded += other.unrecorded;
+        self.corrupt += other.corrupt;
+        self.repaired += other.repaired;
+        self.recorded += other.recorded;
+    }
+}
+
+/*
+    Scan of the cache of the DataSource (MBTiles and tiles on disk) for corrupt tiles.
+    With 'repair' corrupt tiles are removed and created again by workers on request,
+    with 'record' checksums of tiles without them are recorded (cache created before integrity mode)
+*/
+pub async fn command_verify(cwd: String, datasource_id: String, repair: bool, record: bool) {
+    let config = load_config()
+        .await
+        .expect("Error load configuration from 'config_app.json'");
+    let tiles_dir = Paths::new(&cwd, &config.paths).tiles_dir(&datasource_id);
+    if !tokio::fs::try_exists(&tiles_dir).await.unwrap_or(false) {
+        eprintln!("Cache of DataSource '{datasource_id}' not found: {tiles_dir:?}");
+        exit(1);
+    }
+
+    let mut report = VerifyReport::default();
+    let mbtiles_paths = get_mbtiles_paths(tiles_dir.clone())
+        .await
+        .unwrap_or_default();
+    for p in mbtiles_paths {
+        match verify_mbtiles(&p, repair, record).await {
+            Ok(r) => {
+                println!(
+                    "{p:?}: checked {}, corrupt {}, without checksum {}",
+                    r.checked, r.corrupt, r.unrecorded
+                );
+                report.add(r);
+            }
+            Err(err) => {
+                eprintln!("Error verify MBTiles {p:?}: {err}");
+                exit(1);
+            }
+        }
+    }
+
+    match verify_disk_tiles(&tiles_dir, repair, record).await {
+        Ok(r) => {
+            println!(
+                "Tiles on disk: checked {}, corrupt {}, without checksum {}",
+                r.checked, r.corrupt, r.unrecorded
+            );
+            report.add(r);
+        }
+        Err(err) => {
+            eprintln!("Error verify tiles on disk {tiles_dir:?}: {err}");
+            exit(1);
+        }
+    }
+
+    println!(
+        "DataSource '{datasource_id}': checked {} tiles, corrupt {}, repaired {}, without checksum {}, recorded {}",
+        report.checked, report.corrupt, report.repaired, report.unrecorded, report.recorded
+    );
+    if report.corrupt > report.repaired {
+        exit(1);
+    }
+}
+
+async fn verify_mbtiles(
+    mbtiles_db: &Path,
+    repair: bool,
+    record: bool,
+) -> Result<VerifyReport, anyhow::Error> {
+    let client = ClientBuilder::new()
+        .path(mbtiles_db)
+        .journal_mode(JournalMode::Wal)
+        .open()
+        .await
+        .map_err(|err| anyhow!(err))?;
+
+    let result = client
+        .conn(move |connection| {
+            connection.execute(TABLE_TILES_CHECKSUM, ())?;
+
+            let mut report = VerifyReport::default();
+            let mut corrupt: Vec<(u64, u64, u64)> = Vec::new();
+            let mut unrecorded: Vec<(u64, u64, u64, String)> = Vec::new();
+            let mut stmt = connection.prepare(
+                "SELECT t.zoom_level, t.tile_column, t.tile_row, t.tile_data, c.xxhash
+                 FROM tiles t LEFT JOIN tiles_checksum c
+                 ON c.zoom_level = t.zoom_level AND c.tile_column = t.tile_column AND c.tile_row = t.tile_row;",
+            )?;
+            let mut rows = stmt.query([])?;
+            while let Some(row) = rows.next()? {
+                let key: (u64, u64, u64) = (row.get(0)?, row.get(1)?, row.get(2)?);
+                let data: Option<Vec<u8>> = row.get(3)?;
+                let checksum: Option<String> = row.get(4)?;
+                report.checked += 1;
+                match data {
+                    Some(data) if is_tile_intact(&data, checksum.as_deref()) => {
+                        if checksum.is_none() {
+                            report.unrecorded += 1;
+                            if record {
+                                unrecorded.push((key.0, key.1, key.2, tile_checksum(&data)));
+                            }
+                        }
+                    }
+                    _ => corrupt.push(key),
+                }
+            }
+            drop(rows);
+            drop(stmt);
+
+            report.corrupt = corrupt.len() as u64;
+            if repair {
+                let tx = connection.unchecked_transaction()?;
+                for (z, x, y) in corrupt {
+                    remove_mbtile(&tx, z, x, y)?;
+                    report.repaired += 1;
+                }
+                tx.commit()?;
+            }
+            if record {
+                let tx = connection.unchecked_transaction()?;
+                for (z, x, y, checksum) in unrecorded {
+                    tx.execute(
+                        "INSERT OR REPLACE INTO tiles_checksum (zoom_level, tile_column, tile_row, xxhash) VALUES (?1, ?2, ?3, ?4);",
+                        rusqlite::params![z, x, y, checksum],
+                    )?;
+                    report.recorded += 1;
+                }
+                tx.commit()?;
+            }
+            Ok(report)
+        })
+        .await
+        .map_err(|err| anyhow!(err));
+
+    if let Err(err) = client.close().await {
+        eprintln!("Error close connection: {err}");
+    }
+    result
+}
+
+// Tiles on disk: '{z}/{x}/{y}.{ext}' with the sidecar file of the checksum '{y}.{ext}.xxh'
+async fn verify_disk_tiles(
+    tiles_dir: &Path,
+    repair: bool,
+    record: bool,
+) -> Result<VerifyReport, anyhow::Error> {
+    let mut report = VerifyReport::default();
+    let mut dirs: Vec<PathBuf> = Vec::new();
+    let mut entries = tokio::fs::read_dir(tiles_dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        if entry.file_type().await?.is_dir() {
+            dirs.push(entry.path());
+        }
+    }
+
+    while let Some(dir) = dirs.pop() {
+        let mut entries = tokio::fs::read_dir(&dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if entry.file_type().await?.is_dir() {
+                dirs.push(path);
+                continue;
+            }
+            if path.extension().is_some_and(|ext| ext == "xxh") {
+                continue;
+            }
+
+            // empty file marks the tile without data
+            let data = tokio::fs::read(&path).await?;
+            if data.is_empty() {
+                continue;
+            }
+            let checksum = disk_tile_checksum(&path).await;
+            report.checked += 1;
+            if is_tile_intact(&data, checksum.as_deref()) {
+                if checksum.is_none() {
+                    report.unrecorded += 1;
+                    if record {
+                        write_disk_tile_checksum(&path, &data).await;
+                        report.recorded += 1;
+                    }
+                }
+                continue;
+            }
+
+            report.corrupt += 1;
+            println!("Corrupt tile {path:?}");
+            if repair {
+                remove_disk_tile(&path).await;
+                report.repaired += 1;
+            }
+        }
+    }
+    Ok(report)
+}
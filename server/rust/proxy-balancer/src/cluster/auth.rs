@@ -0,0 +1,77 @@
+use anyhow::anyhow;
+use hmac::{Hmac, Mac};
+use hyper::http::header::{HeaderMap, HeaderValue};
+use hyper::Method;
+use sha2::{Digest, Sha256};
+
+use crate::defaults::{
+    CLUSTER_SIGNATURE_HEADER, CLUSTER_SIGNATURE_MAX_AGE_SECS, CLUSTER_TIMESTAMP_HEADER,
+};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/*
+    Requests between distributed instances (master -> node) are signed with a shared secret:
+    HMAC-SHA256 over "timestamp\nMETHOD\npath?query\nsha256(body)"
+*/
+fn canonical_request(
+    timestamp: &str,
+    method: &Method,
+    path_and_query: &str,
+    body: &[u8],
+) -> String {
+    let body_digest = hex::encode(Sha256::digest(body));
+    format!("{timestamp}\n{method}\n{path_and_query}\n{body_digest}")
+}
+
+pub fn sign_request(
+    headers: &mut HeaderMap<HeaderValue>,
+    secret: &str,
+    method: &Method,
+    path_and_query: &str,
+    body: &[u8],
+) -> Result<(), anyhow::Error> {
+    let timestamp = chrono::Utc::now().timestamp().to_string();
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes())?;
+    mac.update(canonical_request(&timestamp, method, path_and_query, body).as_bytes());
+    let signature = hex::encode(mac.finalize().into_bytes());
+
+    headers.insert(CLUSTER_TIMESTAMP_HEADER, HeaderValue::from_str(&timestamp)?);
+    headers.insert(CLUSTER_SIGNATURE_HEADER, HeaderValue::from_str(&signature)?);
+    Ok(())
+}
+
+pub fn verify_request(
+    headers: &HeaderMap<HeaderValue>,
+    secret: &str,
+    method: &Method,
+    path_and_query: &str,
+    body: &[u8],
+) -> Result<(), anyhow::Error> {
+    let timestamp = headers
+        .get(CLUSTER_TIMESTAMP_HEADER)
+        .ok_or(anyhow!("Header '{CLUSTER_TIMESTAMP_HEADER}' is missing"))?
+        .to_str()?;
+    let signature = headers
+        .get(CLUSTER_SIGNATURE_HEADER)
+        .ok_or(anyhow!("Header '{CLUSTER_SIGNATURE_HEADER}' is missing"))?
+        .to_str()?;
+
+    // protection against replay of intercepted requests
+    let ts = timestamp.parse::<i64>()?;
+    let age = (chrono::Utc::now().timestamp() - ts).abs();
+    if age > CLUSTER_SIGNATURE_MAX_AGE_SECS {
+        return Err(anyhow!("Signature of request is expired"));
+    }
+
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes())?;
+    mac.update(canonical_request(timestamp, method, path_and_query, body).as_bytes());
+    mac.verify_slice(&hex::decode(signature)?)
+        .map_err(|_| anyhow!("Signature of request is invalid"))
+}
+
+// Request claims to come from another instance of the cluster
+pub fn is_cluster_request(headers: &HeaderMap<HeaderValue>) -> bool {
+    headers.contains_key(crate::defaults::MASTER_HEADER)
+        || headers.contains_key(CLUSTER_SIGNATURE_HEADER)
+}
@@ -0,0 +1,94 @@
+use hyper::http::header::{HeaderMap, HeaderValue};
+use hyper::StatusCode;
+
+use crate::config::Config;
+use crate::defaults::{HOPS_HEADER, LOCALHOST, ROUTE_DEBUG_HEADER, ROUTE_HEADER};
+use crate::handles::error::{ApiError, ErrorCode};
+
+/*
+    Nodes passed by the request in the cluster: masters forwarding tiles and pyramids to nodes
+    of the DataSource append their address to 'x-tiler-route' and increment 'x-tiler-hops'.
+    The request is refused when it returns to a node of its route or exceeds 'max_hops'
+*/
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RouteTrace {
+    pub hops: u64,
+    pub nodes: Vec<String>,
+    // the client asked for the route in the header of the response
+    pub debug: bool,
+}
+
+impl RouteTrace {
+    pub fn from_headers(headers: &HeaderMap<HeaderValue>) -> Self {
+        let nodes: Vec<String> = headers
+            .get(ROUTE_HEADER)
+            .and_then(|route| route.to_str().ok())
+            .map(|route| {
+                route
+                    .split(',')
+                    .map(|node| node.trim().to_string())
+                    .filter(|node| !node.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default();
+        // removed route does not reset the count of hops
+        let hops = headers
+            .get(HOPS_HEADER)
+            .and_then(|hops| hops.to_str().ok())
+            .and_then(|hops| hops.trim().parse::<u64>().ok())
+            .unwrap_or(0)
+            .max(nodes.len() as u64);
+        RouteTrace {
+            hops,
+            nodes,
+            debug: headers.contains_key(ROUTE_DEBUG_HEADER),
+        }
+    }
+
+    // Error 508 of the request which came back to the node or went through too many nodes
+    pub fn refuse(&self, node: &str, max_hops: u64) -> Option<ApiError> {
+        let message = if self.nodes.iter().any(|n| n == node) {
+            format!(
+                "Routing loop: the request returned to the node '{node}', route {}",
+                self.nodes.join(", ")
+            )
+        } else if self.hops > max_hops {
+            format!(
+                "Request exceeded {max_hops} hops of the cluster, route {}",
+                self.nodes.join(", ")
+            )
+        } else {
+            return None;
+        };
+        Some(ApiError::new(StatusCode::LOOP_DETECTED, message).with_code(ErrorCode::RoutingLoop))
+    }
+
+    // Headers of the request forwarded by the node to the next node
+    pub fn forward(&self, headers: &mut HeaderMap<HeaderValue>, node: &str) {
+        let mut nodes = self.nodes.clone();
+        nodes.push(node.to_string());
+        if let Ok(route) = HeaderValue::from_str(&nodes.join(", ")) {
+            headers.insert(ROUTE_HEADER, route);
+        }
+        headers.insert(HOPS_HEADER, HeaderValue::from(self.hops + 1));
+    }
+
+    // Final route of the response: nodes, the node which served it and its worker
+    pub fn route(&self, node: &str, worker_port: Option<u16>) -> String {
+        let mut nodes = self.nodes.clone();
+        nodes.push(node.to_string());
+        if let Some(port) = worker_port {
+            nodes.push(format!("{LOCALHOST}:{port} (worker)"));
+        }
+        nodes.join(", ")
+    }
+}
+
+// Address of the node in routes: 'address' of the master, otherwise 'advertise_address' of the node
+pub fn node_address(config: &Config) -> String {
+    config
+        .address
+        .clone()
+        .or_else(|| config.cluster_advertise_address.clone())
+        .unwrap_or_else(|| format!("{}:{}", config.host, config.port))
+}
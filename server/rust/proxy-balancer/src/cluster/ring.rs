@@ -0,0 +1,58 @@
+use sha2::{Digest, Sha256};
+
+use crate::defaults::CLUSTER_RING_VIRTUAL_NODES;
+
+fn ring_hash(value: &str) -> u64 {
+    let digest = Sha256::digest(value.as_bytes());
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&digest[..8]);
+    u64::from_be_bytes(bytes)
+}
+
+/*
+    Consistent hashing ring of cluster nodes: every node is placed on the ring
+    with several virtual points, a key belongs to the first point clockwise.
+    Removing a node moves only its own keys to the neighbours
+*/
+#[derive(Debug, Clone)]
+pub struct HashRing {
+    points: Vec<(u64, usize)>,
+    nodes: Vec<String>,
+}
+
+impl HashRing {
+    pub fn new(nodes: &[String]) -> Self {
+        let mut points = Vec::with_capacity(nodes.len() * CLUSTER_RING_VIRTUAL_NODES);
+        for (i, node) in nodes.iter().enumerate() {
+            for v in 0..CLUSTER_RING_VIRTUAL_NODES {
+                points.push((ring_hash(&format!("{node}#{v}")), i));
+            }
+        }
+        points.sort_unstable();
+        HashRing {
+            points,
+            nodes: nodes.to_vec(),
+        }
+    }
+
+    // Nodes in the order of preference for the key: owner first, then failover
+    pub fn nodes_for(&self, key: &str) -> Vec<String> {
+        if self.points.is_empty() {
+            return Vec::new();
+        }
+        let hash = ring_hash(key);
+        let start = self.points.partition_point(|(point, _)| *point < hash);
+
+        let mut ordered: Vec<String> = Vec::with_capacity(self.nodes.len());
+        for k in 0..self.points.len() {
+            let (_, i) = self.points[(start + k) % self.points.len()];
+            if !ordered.contains(&self.nodes[i]) {
+                ordered.push(self.nodes[i].clone());
+                if ordered.len() == self.nodes.len() {
+                    break;
+                }
+            }
+        }
+        ordered
+    }
+}
@@ -0,0 +1,351 @@
+mod tiff;
+
+use std::collections::{BTreeSet, HashMap};
+use std::io::{Cursor, Read, SeekFrom};
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use flate2::read::ZlibDecoder;
+use image::{DynamicImage, ImageFormat, RgbaImage};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio::sync::OnceCell;
+
+use crate::defaults::{COG_HEADER_BYTES, COG_HEADER_MAX_BYTES, COG_REQUEST_TIMEOUT_SECS};
+use crate::grid::TileGrid;
+use crate::structs::{ContentType, Extension};
+use crate::tasks::datasources::DataSource;
+use tiff::{
+    parse_header, Crs, Ifd, TiffError, TiffHeader, COMPRESSION_ADOBE_DEFLATE, COMPRESSION_DEFLATE,
+    COMPRESSION_LZW, COMPRESSION_NONE, PREDICTOR_HORIZONTAL,
+};
+
+pub const STORE_COG: &str = "cog";
+
+/*
+    Raster DataSource with the store 'cog': Cloud-Optimized GeoTIFF (8-bit bands, EPSG:3857
+    or EPSG:4326) on HTTP(S) server or object storage. The header is read once, tiles are rendered
+    by the proxy from internal tiles of the overview closest to the zoom fetched by range requests
+*/
+#[derive(Debug)]
+pub struct Cog {
+    url: String,
+    format: Extension,
+    client: reqwest::Client,
+    header: OnceCell<TiffHeader>,
+}
+
+impl Cog {
+    // URL and format of tiles ('png' or 'webp') from the field 'dataStore' of the DataSource
+    pub fn from_datasource(ds: &DataSource) -> Result<Self, anyhow::Error> {
+        let data_store = ds.data.0.get("dataStore");
+        let url = data_store
+            .and_then(|s| s.get("url"))
+            .and_then(|u| u.as_str())
+            .ok_or(anyhow!("DataSource has no 'dataStore.url'"))?;
+        if !url.starts_with("http://") && !url.starts_with("https://") {
+            return Err(anyhow!("URL of COG must be HTTP(S), got '{url}'"));
+        }
+        let format = match data_store
+            .and_then(|s| s.get("format"))
+            .and_then(|f| f.as_str())
+        {
+            None | Some("png") => Extension::Png,
+            Some("webp") => Extension::Webp,
+            Some(format) => return Err(anyhow!("Format of COG tiles '{format}' is unsupported")),
+        };
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(COG_REQUEST_TIMEOUT_SECS))
+            .build()?;
+        Ok(Cog {
+            url: url.to_string(),
+            format,
+            client,
+            header: OnceCell::new(),
+        })
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self.format {
+            Extension::Webp => ContentType::Webp.into(),
+            _ => ContentType::Png.into(),
+        }
+    }
+
+    // Pixels of tiles are sampled in EPSG:3857 or EPSG:4326 of the grid
+    pub fn supports_grid(&self, grid: &TileGrid) -> bool {
+        grid.unproject(0.0, 0.0).is_some()
+    }
+
+    // Server without support of ranges returns the whole file
+    async fn range(&self, offset: u64, len: u64) -> Result<Vec<u8>, anyhow::Error> {
+        let response = self
+            .client
+            .get(&self.url)
+            .header(
+                reqwest::header::RANGE,
+                format!("bytes={}-{}", offset, offset + len - 1),
+            )
+            .send()
+            .await?;
+        let status = response.status();
+        if !status.is_success() {
+            return Err(anyhow!("Error request range of '{}': {status}", self.url));
+        }
+        let bytes = response.bytes().await?;
+        if status == reqwest::StatusCode::PARTIAL_CONTENT {
+            return Ok(bytes.to_vec());
+        }
+        let start = (offset as usize).min(bytes.len());
+        let end = (offset + len).min(bytes.len() as u64) as usize;
+        Ok(bytes[start..end].to_vec())
+    }
+
+    // Prefix of the file is extended until all image file directories are parsed
+    async fn header(&self) -> Result<&TiffHeader, anyhow::Error> {
+        self.header
+            .get_or_try_init(|| async {
+                let mut len = COG_HEADER_BYTES;
+                loop {
+                    let prefix = self.range(0, len).await?;
+                    match parse_header(&prefix) {
+                        Ok(header) => return Ok(header),
+                        Err(TiffError::NeedMore(required)) => {
+                            if (prefix.len() as u64) < len || required > COG_HEADER_MAX_BYTES {
+                                return Err(anyhow!(
+                                    "Header of COG '{}' is truncated or larger than {COG_HEADER_MAX_BYTES} bytes",
+                                    self.url
+                                ));
+                            }
+                            len = required.max(len * 2).min(COG_HEADER_MAX_BYTES);
+                        }
+                        Err(TiffError::Invalid(err)) => {
+                            return Err(anyhow!("Error header of COG '{}': {err}", self.url))
+                        }
+                    }
+                }
+            })
+            .await
+    }
+
+    // Samples of the internal tile, None for sparse tiles missing in the file
+    async fn read_tile(&self, ifd: &Ifd, index: u64) -> Result<Option<Vec<u8>>, anyhow::Error> {
+        let (Some(offset), Some(len)) = (
+            ifd.tile_offsets.get(index as usize),
+            ifd.tile_byte_counts.get(index as usize),
+        ) else {
+            return Ok(None);
+        };
+        if *len == 0 {
+            return Ok(None);
+        }
+        let data = self.range(*offset, *len).await?;
+        decode_tile(ifd, data).map(Some)
+    }
+
+    /*
+        Tile (z, x, y) of the grid encoded in the format of the DataSource,
+        empty Vec when the tile is outside of the image or fully transparent
+    */
+    pub async fn tile(
+        &self,
+        grid: &TileGrid,
+        z: u8,
+        x: u64,
+        y: u64,
+    ) -> Result<Vec<u8>, anyhow::Error> {
+        let header = self.header().await?;
+        let web_mercator = TileGrid::web_mercator();
+        // point of the grid to the CRS of the image
+        let to_image = |gx: f64, gy: f64| -> Option<(f64, f64)> {
+            let (lon, lat) = grid.unproject(gx, gy)?;
+            match header.crs {
+                Crs::Wgs84 => Some((lon, lat)),
+                Crs::WebMercator => web_mercator.project(lon, lat),
+            }
+        };
+
+        let size = grid.tile_size as usize;
+        let [minx, miny, maxx, maxy] = grid.tile_extent(z, x, y);
+        let (res_x, res_y) = ((maxx - minx) / size as f64, (maxy - miny) / size as f64);
+
+        // overview: the coarsest image with pixels not larger than pixels of the tile
+        let (Some(ul), Some(lr)) = (to_image(minx, maxy), to_image(maxx, miny)) else {
+            return Err(anyhow!("CRS '{}' of the grid is unsupported", grid.crs));
+        };
+        let target = ((lr.0 - ul.0).abs() / size as f64).min((ul.1 - lr.1).abs() / size as f64);
+        let full = &header.ifds[0];
+        let pixel_size = |ifd: &Ifd| {
+            (
+                header.pixel_size.0 * full.width as f64 / ifd.width as f64,
+                header.pixel_size.1 * full.height as f64 / ifd.height as f64,
+            )
+        };
+        let ifd = header
+            .ifds
+            .iter()
+            .rev()
+            .find(|ifd| pixel_size(ifd).0 <= target)
+            .unwrap_or(full);
+        let (px_width, px_height) = pixel_size(ifd);
+
+        // pixel of the image for every pixel of the tile
+        let mut sources: Vec<Option<(u64, u64)>> = Vec::with_capacity(size * size);
+        let mut indexes = BTreeSet::new();
+        for row in 0..size {
+            for col in 0..size {
+                let gx = minx + (col as f64 + 0.5) * res_x;
+                let gy = maxy - (row as f64 + 0.5) * res_y;
+                let source = to_image(gx, gy).and_then(|(ix, iy)| {
+                    let c = (ix - header.origin.0) / px_width;
+                    let r = (header.origin.1 - iy) / px_height;
+                    if c < 0.0 || r < 0.0 || c >= ifd.width as f64 || r >= ifd.height as f64 {
+                        return None;
+                    }
+                    Some((c as u64, r as u64))
+                });
+                if let Some((c, r)) = source {
+                    indexes.insert((r / ifd.tile_height) * ifd.tiles_across() + c / ifd.tile_width);
+                }
+                sources.push(source);
+            }
+        }
+        if indexes.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let tiles: HashMap<u64, Vec<u8>> =
+            futures::future::try_join_all(indexes.into_iter().map(|index| async move {
+                Ok::<_, anyhow::Error>((index, self.read_tile(ifd, index).await?))
+            }))
+            .await?
+            .into_iter()
+            .filter_map(|(index, tile)| tile.map(|t| (index, t)))
+            .collect();
+
+        let samples = ifd.samples as usize;
+        let nodata = header
+            .nodata
+            .filter(|v| (0.0..=255.0).contains(v))
+            .map(|v| v as u8);
+        let mut rgba = vec![0u8; size * size * 4];
+        let mut opaque = false;
+        for (i, source) in sources.into_iter().enumerate() {
+            let Some((c, r)) = source else {
+                continue;
+            };
+            let index = (r / ifd.tile_height) * ifd.tiles_across() + c / ifd.tile_width;
+            let Some(tile) = tiles.get(&index) else {
+                continue;
+            };
+            let offset =
+                (((r % ifd.tile_height) * ifd.tile_width + c % ifd.tile_width) as usize) * samples;
+            let Some(pixel) = tile.get(offset..offset + samples) else {
+                continue;
+            };
+            let color = match *pixel {
+                [v] => [v, v, v, 255],
+                [v, a] => [v, v, v, a],
+                [r, g, b] => [r, g, b, 255],
+                [r, g, b, a, ..] => [r, g, b, a],
+                _ => continue,
+            };
+            let bands = if samples == 2 || samples >= 4 {
+                &pixel[..samples - 1]
+            } else {
+                pixel
+            };
+            if nodata.is_some_and(|nd| bands.iter().all(|v| *v == nd)) || color[3] == 0 {
+                continue;
+            }
+            rgba[i * 4..i * 4 + 4].copy_from_slice(&color);
+            opaque = true;
+        }
+        if !opaque {
+            return Ok(Vec::new());
+        }
+
+        let image = RgbaImage::from_raw(size as u32, size as u32, rgba)
+            .ok_or(anyhow!("Error create image of the tile"))?;
+        let format = match self.format {
+            Extension::Webp => ImageFormat::WebP,
+            _ => ImageFormat::Png,
+        };
+        let mut data = Cursor::new(Vec::new());
+        DynamicImage::ImageRgba8(image).write_to(&mut data, format)?;
+        Ok(data.into_inner())
+    }
+}
+
+/*
+    Bounds [west, south, east, north] in degrees of the local GeoTIFF in EPSG:3857 or EPSG:4326,
+    only the header of the file is read
+*/
+pub async fn raster_file_bounds(path: &Path) -> Result<[f64; 4], anyhow::Error> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut len = COG_HEADER_BYTES;
+    let header = loop {
+        file.seek(SeekFrom::Start(0)).await?;
+        let mut prefix = Vec::new();
+        (&mut file).take(len).read_to_end(&mut prefix).await?;
+        match parse_header(&prefix) {
+            Ok(header) => break header,
+            Err(TiffError::NeedMore(required)) => {
+                if (prefix.len() as u64) < len || required > COG_HEADER_MAX_BYTES {
+                    return Err(anyhow!(
+                        "Header of GeoTIFF {path:?} is truncated or larger than {COG_HEADER_MAX_BYTES} bytes"
+                    ));
+                }
+                len = required.max(len * 2).min(COG_HEADER_MAX_BYTES);
+            }
+            Err(TiffError::Invalid(err)) => {
+                return Err(anyhow!("Error header of GeoTIFF {path:?}: {err}"))
+            }
+        }
+    };
+    let full = &header.ifds[0];
+    let (minx, maxy) = header.origin;
+    let maxx = minx + header.pixel_size.0 * full.width as f64;
+    let miny = maxy - header.pixel_size.1 * full.height as f64;
+    let grid = match header.crs {
+        Crs::WebMercator => TileGrid::web_mercator(),
+        Crs::Wgs84 => TileGrid::wgs84(),
+    };
+    let (Some((west, north)), Some((east, south))) =
+        (grid.unproject(minx, maxy), grid.unproject(maxx, miny))
+    else {
+        return Err(anyhow!("CRS of GeoTIFF {path:?} is unsupported"));
+    };
+    Ok([west, south, east, north])
+}
+
+// Samples of the internal tile: decompressed, predictor reverted, padded to the full tile
+fn decode_tile(ifd: &Ifd, data: Vec<u8>) -> Result<Vec<u8>, anyhow::Error> {
+    let mut samples = match ifd.compression {
+        COMPRESSION_NONE => data,
+        COMPRESSION_DEFLATE | COMPRESSION_ADOBE_DEFLATE => {
+            let mut samples = Vec::new();
+            ZlibDecoder::new(data.as_slice()).read_to_end(&mut samples)?;
+            samples
+        }
+        COMPRESSION_LZW => {
+            weezl::decode::Decoder::with_tiff_size_switch(weezl::BitOrder::Msb, 8).decode(&data)?
+        }
+        compression => {
+            return Err(anyhow!(
+                "Compression {compression} of COG is unsupported, use DEFLATE or LZW"
+            ))
+        }
+    };
+    let row = (ifd.tile_width * ifd.samples as u64) as usize;
+    samples.resize(row * ifd.tile_height as usize, 0);
+    if ifd.predictor == PREDICTOR_HORIZONTAL {
+        let step = ifd.samples as usize;
+        for line in samples.chunks_mut(row) {
+            for i in step..line.len() {
+                line[i] = line[i].wrapping_add(line[i - step]);
+            }
+        }
+    }
+    Ok(samples)
+}
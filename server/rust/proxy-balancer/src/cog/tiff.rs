@@ -0,0 +1,319 @@
+/*
+    Parser of the header of (Big)TIFF: image file directories with tags used to read
+    tiles of Cloud-Optimized GeoTIFF. Values of tags are read from the prefix of the file,
+    'NeedMore' is returned when the prefix is too short
+*/
+use anyhow::anyhow;
+
+const TAG_NEW_SUBFILE_TYPE: u16 = 254;
+const TAG_IMAGE_WIDTH: u16 = 256;
+const TAG_IMAGE_LENGTH: u16 = 257;
+const TAG_BITS_PER_SAMPLE: u16 = 258;
+const TAG_COMPRESSION: u16 = 259;
+const TAG_SAMPLES_PER_PIXEL: u16 = 277;
+const TAG_PLANAR_CONFIGURATION: u16 = 284;
+const TAG_PREDICTOR: u16 = 317;
+const TAG_TILE_WIDTH: u16 = 322;
+const TAG_TILE_LENGTH: u16 = 323;
+const TAG_TILE_OFFSETS: u16 = 324;
+const TAG_TILE_BYTE_COUNTS: u16 = 325;
+const TAG_MODEL_PIXEL_SCALE: u16 = 33550;
+const TAG_MODEL_TIEPOINT: u16 = 33922;
+const TAG_GEO_KEY_DIRECTORY: u16 = 34735;
+const TAG_GDAL_NODATA: u16 = 42113;
+
+const GEO_KEY_GEOGRAPHIC_TYPE: u16 = 2048;
+const GEO_KEY_PROJECTED_CS_TYPE: u16 = 3072;
+
+// bit of 'NewSubfileType' for transparency masks
+const SUBFILE_MASK: u64 = 4;
+const MAX_IFDS: usize = 64;
+
+pub const COMPRESSION_NONE: u16 = 1;
+pub const COMPRESSION_LZW: u16 = 5;
+pub const COMPRESSION_DEFLATE: u16 = 8;
+pub const COMPRESSION_ADOBE_DEFLATE: u16 = 32946;
+pub const PREDICTOR_HORIZONTAL: u16 = 2;
+
+// CRS, upper left corner and pixel size of the full resolution image
+type Georeference = (Crs, (f64, f64), (f64, f64));
+
+#[derive(Debug)]
+pub enum TiffError {
+    // bytes of the file prefix required to continue parsing
+    NeedMore(u64),
+    Invalid(anyhow::Error),
+}
+
+impl From<anyhow::Error> for TiffError {
+    fn from(err: anyhow::Error) -> Self {
+        TiffError::Invalid(err)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Crs {
+    WebMercator,
+    Wgs84,
+}
+
+// Full resolution image or overview
+#[derive(Debug, Clone)]
+pub struct Ifd {
+    pub width: u64,
+    pub height: u64,
+    pub tile_width: u64,
+    pub tile_height: u64,
+    pub samples: u16,
+    pub compression: u16,
+    pub predictor: u16,
+    pub tile_offsets: Vec<u64>,
+    pub tile_byte_counts: Vec<u64>,
+}
+
+impl Ifd {
+    pub fn tiles_across(&self) -> u64 {
+        self.width.div_ceil(self.tile_width)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TiffHeader {
+    pub ifds: Vec<Ifd>,
+    pub crs: Crs,
+    // upper left corner of the full resolution image and size of its pixel in units of CRS
+    pub origin: (f64, f64),
+    pub pixel_size: (f64, f64),
+    pub nodata: Option<f64>,
+}
+
+struct Cursor<'a> {
+    data: &'a [u8],
+    little_endian: bool,
+}
+
+impl<'a> Cursor<'a> {
+    fn bytes(&self, offset: u64, len: u64) -> Result<&'a [u8], TiffError> {
+        let end = offset + len;
+        if end > self.data.len() as u64 {
+            return Err(TiffError::NeedMore(end));
+        }
+        Ok(&self.data[offset as usize..end as usize])
+    }
+
+    fn uint(&self, offset: u64, size: u64) -> Result<u64, TiffError> {
+        let bytes = self.bytes(offset, size)?;
+        let mut value = 0u64;
+        for i in 0..size as usize {
+            let byte = if self.little_endian {
+                bytes[size as usize - 1 - i]
+            } else {
+                bytes[i]
+            };
+            value = (value << 8) | byte as u64;
+        }
+        Ok(value)
+    }
+}
+
+struct Entry {
+    field_type: u16,
+    count: u64,
+    // offset of the value, inline values are inside of the entry
+    offset: u64,
+}
+
+fn type_size(field_type: u16) -> u64 {
+    match field_type {
+        1 | 2 | 6 | 7 => 1,
+        3 | 8 => 2,
+        4 | 9 | 11 | 13 => 4,
+        5 | 10 | 12 | 16 | 17 | 18 => 8,
+        _ => 0,
+    }
+}
+
+impl Cursor<'_> {
+    fn values(&self, entry: &Entry) -> Result<Vec<u64>, TiffError> {
+        let size = type_size(entry.field_type);
+        (0..entry.count)
+            .map(|i| self.uint(entry.offset + i * size, size))
+            .collect()
+    }
+
+    fn doubles(&self, entry: &Entry) -> Result<Vec<f64>, TiffError> {
+        if entry.field_type != 12 {
+            return Err(anyhow!("Tag of doubles has type {}", entry.field_type).into());
+        }
+        Ok(self
+            .values(entry)?
+            .into_iter()
+            .map(f64::from_bits)
+            .collect())
+    }
+
+    fn ascii(&self, entry: &Entry) -> Result<String, TiffError> {
+        let bytes = self.bytes(entry.offset, entry.count)?;
+        Ok(String::from_utf8_lossy(bytes)
+            .trim_end_matches('\0')
+            .trim()
+            .to_string())
+    }
+}
+
+pub fn parse_header(data: &[u8]) -> Result<TiffHeader, TiffError> {
+    let little_endian = match data.get(0..2) {
+        Some(b"II") => true,
+        Some(b"MM") => false,
+        Some(_) => return Err(anyhow!("File is not TIFF").into()),
+        None => return Err(TiffError::NeedMore(16)),
+    };
+    let cursor = Cursor {
+        data,
+        little_endian,
+    };
+    let big_tiff = match cursor.uint(2, 2)? {
+        42 => false,
+        43 => true,
+        version => return Err(anyhow!("Unsupported TIFF version {version}").into()),
+    };
+    let (count_size, entry_size, offset_size) = if big_tiff { (8, 20, 8) } else { (2, 12, 4) };
+
+    let mut ifds = Vec::new();
+    let mut georeference: Option<Georeference> = None;
+    let mut nodata = None;
+    let mut ifd_offset = cursor.uint(if big_tiff { 8 } else { 4 }, offset_size)?;
+
+    while ifd_offset != 0 && ifds.len() < MAX_IFDS {
+        let count = cursor.uint(ifd_offset, count_size)?;
+        let mut entries = std::collections::HashMap::new();
+        for i in 0..count {
+            let pos = ifd_offset + count_size + i * entry_size;
+            let tag = cursor.uint(pos, 2)? as u16;
+            let field_type = cursor.uint(pos + 2, 2)? as u16;
+            let count = cursor.uint(pos + 4, offset_size)?;
+            let value_pos = pos + 4 + offset_size;
+            let offset = if count * type_size(field_type) <= offset_size {
+                value_pos
+            } else {
+                cursor.uint(value_pos, offset_size)?
+            };
+            entries.insert(
+                tag,
+                Entry {
+                    field_type,
+                    count,
+                    offset,
+                },
+            );
+        }
+        ifd_offset = cursor.uint(ifd_offset + count_size + count * entry_size, offset_size)?;
+
+        let first = |tag: u16| -> Result<Option<u64>, TiffError> {
+            match entries.get(&tag) {
+                Some(entry) => Ok(cursor.values(entry)?.first().copied()),
+                None => Ok(None),
+            }
+        };
+        let subfile_type = first(TAG_NEW_SUBFILE_TYPE)?.unwrap_or(0);
+        if subfile_type & SUBFILE_MASK != 0 {
+            continue;
+        }
+        let (Some(tile_width), Some(tile_height)) =
+            (first(TAG_TILE_WIDTH)?, first(TAG_TILE_LENGTH)?)
+        else {
+            return Err(anyhow!("TIFF is not tiled, Cloud-Optimized GeoTIFF is required").into());
+        };
+        if first(TAG_PLANAR_CONFIGURATION)?.unwrap_or(1) != 1 {
+            return Err(anyhow!("Only interleaved bands of TIFF are supported").into());
+        }
+        let bits = match entries.get(&TAG_BITS_PER_SAMPLE) {
+            Some(entry) => cursor.values(entry)?,
+            None => vec![1],
+        };
+        if bits.iter().any(|b| *b != 8) {
+            return Err(anyhow!("Only 8-bit bands of TIFF are supported, got {bits:?}").into());
+        }
+        let tile_offsets = match entries.get(&TAG_TILE_OFFSETS) {
+            Some(entry) => cursor.values(entry)?,
+            None => return Err(anyhow!("TIFF has no tile offsets").into()),
+        };
+        let tile_byte_counts = match entries.get(&TAG_TILE_BYTE_COUNTS) {
+            Some(entry) => cursor.values(entry)?,
+            None => return Err(anyhow!("TIFF has no tile byte counts").into()),
+        };
+
+        if ifds.is_empty() {
+            georeference = Some(parse_georeference(&cursor, &entries)?);
+            if let Some(entry) = entries.get(&TAG_GDAL_NODATA) {
+                nodata = cursor.ascii(entry)?.parse::<f64>().ok();
+            }
+        }
+
+        ifds.push(Ifd {
+            width: first(TAG_IMAGE_WIDTH)?.ok_or(anyhow!("TIFF has no width"))?,
+            height: first(TAG_IMAGE_LENGTH)?.ok_or(anyhow!("TIFF has no height"))?,
+            tile_width,
+            tile_height,
+            samples: first(TAG_SAMPLES_PER_PIXEL)?.unwrap_or(1) as u16,
+            compression: first(TAG_COMPRESSION)?.unwrap_or(COMPRESSION_NONE as u64) as u16,
+            predictor: first(TAG_PREDICTOR)?.unwrap_or(1) as u16,
+            tile_offsets,
+            tile_byte_counts,
+        });
+    }
+
+    let (crs, origin, pixel_size) = georeference.ok_or(anyhow!("TIFF has no images"))?;
+    Ok(TiffHeader {
+        ifds,
+        crs,
+        origin,
+        pixel_size,
+        nodata,
+    })
+}
+
+// CRS of GeoKeys, upper left corner and pixel size of the full resolution image
+fn parse_georeference(
+    cursor: &Cursor,
+    entries: &std::collections::HashMap<u16, Entry>,
+) -> Result<Georeference, TiffError> {
+    let (Some(scale), Some(tiepoint)) = (
+        entries.get(&TAG_MODEL_PIXEL_SCALE),
+        entries.get(&TAG_MODEL_TIEPOINT),
+    ) else {
+        return Err(anyhow!("GeoTIFF has no ModelPixelScale and ModelTiepoint").into());
+    };
+    let scale = cursor.doubles(scale)?;
+    let tiepoint = cursor.doubles(tiepoint)?;
+    if scale.len() < 2 || tiepoint.len() < 6 {
+        return Err(anyhow!("GeoTIFF has invalid georeference").into());
+    }
+    let origin = (
+        tiepoint[3] - tiepoint[0] * scale[0],
+        tiepoint[4] + tiepoint[1] * scale[1],
+    );
+
+    // GeoKeyDirectory: header of 4 values, then keys of 4 values (id, location, count, value)
+    let keys = match entries.get(&TAG_GEO_KEY_DIRECTORY) {
+        Some(entry) => cursor.values(entry)?,
+        None => return Err(anyhow!("GeoTIFF has no GeoKeyDirectory").into()),
+    };
+    let key = |id: u16| {
+        keys.chunks(4)
+            .skip(1)
+            .find(|k| k.len() == 4 && k[0] == id as u64 && k[1] == 0)
+            .map(|k| k[3])
+    };
+    let crs = match (key(GEO_KEY_PROJECTED_CS_TYPE), key(GEO_KEY_GEOGRAPHIC_TYPE)) {
+        (Some(3857), _) | (Some(900913), _) => Crs::WebMercator,
+        (None, Some(4326)) => Crs::Wgs84,
+        (projected, geographic) => {
+            return Err(anyhow!(
+                "CRS of GeoTIFF must be EPSG:3857 or EPSG:4326, got projected {projected:?}, geographic {geographic:?}"
+            )
+            .into())
+        }
+    };
+    Ok((crs, origin, (scale[0], scale[1])))
+}
@@ -0,0 +1,74 @@
+use std::collections::HashMap;
+use std::io::Read;
+
+use anyhow::anyhow;
+use flate2::read::GzDecoder;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::overzoom::mvt::map_layers;
+
+pub const STORE_COMPOSITE: &str = "composite";
+
+// Child vector DataSource, its layers are renamed by 'layers' or get the prefix
+#[derive(Debug, Clone, Deserialize)]
+pub struct CompositeChild {
+    pub id: String,
+    #[serde(default)]
+    pub prefix: String,
+    #[serde(default)]
+    pub layers: HashMap<String, String>,
+}
+
+/*
+    Vector DataSource with the store 'composite': tiles of child DataSources 'dataStore.datasources'
+    are requested by the proxy and their layers are concatenated into one MVT
+*/
+#[derive(Debug, Clone)]
+pub struct CompositeSource {
+    pub children: Vec<CompositeChild>,
+}
+
+impl CompositeSource {
+    pub fn from_data(data: &Value) -> Result<Self, anyhow::Error> {
+        let children: Vec<CompositeChild> =
+            match data.get("dataStore").and_then(|s| s.get("datasources")) {
+                Some(children) => serde_json::from_value(children.clone())?,
+                None => Vec::new(),
+            };
+        if children.is_empty() {
+            return Err(anyhow!(
+                "Composite DataSource has no 'dataStore.datasources'"
+            ));
+        }
+        Ok(CompositeSource { children })
+    }
+}
+
+impl CompositeChild {
+    fn layer_name(&self, name: &str) -> String {
+        match self.layers.get(name) {
+            Some(renamed) => renamed.clone(),
+            None => format!("{}{name}", self.prefix),
+        }
+    }
+}
+
+/*
+    MVT of the composite tile: layers of child tiles (gzip or not) are renamed and concatenated,
+    an MVT is a sequence of layers, so concatenated tiles are a valid tile
+*/
+pub fn merge_tiles(tiles: &[(&CompositeChild, Vec<u8>)]) -> Result<Vec<u8>, anyhow::Error> {
+    let mut merged = Vec::new();
+    for (child, tile) in tiles {
+        let mut data = Vec::new();
+        let tile = if tile.starts_with(b"\x1f\x8b\x08") {
+            GzDecoder::new(tile.as_slice()).read_to_end(&mut data)?;
+            &data
+        } else {
+            tile
+        };
+        merged.extend(map_layers(tile, |name| Some(child.layer_name(name)))?);
+    }
+    Ok(merged)
+}
@@ -1,7 +1,40 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
 use std::process::exit;
 
+use hyper::body::Bytes;
+use tiler_core::sqlite_clients::MaintenanceThresholds;
+
+use crate::defaults::{
+    ADAPTIVE_CONCURRENCY_ERROR_RATE, ADAPTIVE_CONCURRENCY_INTERVAL_SECS,
+    ADAPTIVE_CONCURRENCY_LATENCY_MS, BATCH_PERMITS_SHARE, BILLING_KEY_HEADER, BILLING_MAX_KEYS,
+    CLUSTER_MAX_HOPS, CORS_ALLOWED_METHODS, CORS_MAX_AGE_SECS, DB_ACQUIRE_TIMEOUT_SECS,
+    DB_POOL_MAX_CONNECTIONS, DB_POOL_MIN_CONNECTIONS, DB_SCHEME, DB_SSLMODES, EXPORT_TTL_SECS,
+    MAX_BODY_SIZE_KB, MAX_TILE_SIZE_KB, MBTILES_MAINTENANCE_SECS, MBTILES_VACUUM_PAGES,
+    MBTILES_WAL_THRESHOLD_MB, NATIVE, OTLP_ENDPOINT, OTLP_SERVICE_NAME, OVERZOOM_MAXZOOM,
+    PATHS_CACHE, PATHS_DATA, PATHS_SCRIPTS, QUEUE_RETRY_AFTER_SECS, QUOTA_INTERVAL_SECS,
+    REGISTRY_POSTGRES, REGISTRY_SQLITE, REGISTRY_SQLITE_PATH, REMOTE_REQUEST_TIMEOUT_SECS,
+    SHARD_TIMEOUT_SECS, SLOW_REQUEST_MS, SQLITE_POOL_IDLE_SECS, SQLITE_POOL_MAX_CLIENTS,
+    STATIC_ALLOWED_EXTENSIONS, STORAGE_INTERVAL_SECS, STORAGE_MIN_FREE_MB, TRASH_INTERVAL_SECS,
+    TRASH_RETENTION_DAYS, UPLOAD_EXPIRE_SECS, UPLOAD_MAX_SIZE_MB, USAGE_BUFFER_SIZE,
+    USAGE_FLUSH_SECS, USAGE_RETENTION_DAYS, WEBHOOK_TIMEOUT_SECS, WORKER_READY_QUORUM,
+    WORKER_RESTART_BACKOFF_SECS, WORKER_RESTART_MAX_BACKOFF_SECS, WORKER_STARTUP_TIMEOUT_SECS,
+};
+use crate::forwarded::IpNetwork;
+use crate::grid::{TileGrid, TileGrids};
+use crate::handles::headers::HeaderOverride;
+use crate::hyper_reverse_proxy::HeaderRules;
+use crate::origins::AllowedOrigins;
+use crate::paths::Paths;
+use crate::png::solid_png;
+use crate::structs::{EndPoints, MissingTile};
+use crate::tasks::billing::{BillingQuota, BillingQuotas};
+use crate::webhooks::is_webhook_url;
+
 #[derive(Debug, Clone)]
 pub struct Config {
+    // 'granian' and 'robyn' run Python workers, 'native' renders tiles in the balancer without them
     pub type_server: String,
     pub master: bool,
     pub address: Option<String>,
@@ -25,13 +58,358 @@ pub struct Config {
     pub worker_reload_repeat_attempts: u64,
     pub terminate_childs_with_python: bool,
     pub max_concurrent_tile_requests: usize,
+    // limits of ports follow latency and errors of workers, None - changed only by the service API
+    pub adaptive_concurrency: Option<AdaptiveConcurrency>,
+    // share of permits of a port held by batch requests (seeding, 'X-Tile-Priority: batch')
+    pub batch_permits_share: f64,
+    // waiters of a port after which tile requests are refused with 'Retry-After', 0 - unlimited
+    pub max_queue_depth: usize,
+    pub queue_retry_after_secs: u64,
+    pub circuit_breaker_failure_threshold: u64,
+    pub circuit_breaker_open_secs: u64,
+    // share of workers passing the health check to accept traffic
+    pub worker_ready_quorum: f64,
+    // path of workers answering 200 when they are ready
+    pub worker_readiness_path: String,
+    // launch of workers instead of the built-in commands of 'granian' and 'robyn'
+    pub worker_launch: Option<WorkerLaunch>,
+    // environment and CPU affinity of workers by index
+    pub worker_slots: Vec<WorkerSlot>,
+    // crashed workers are started again after the delay doubling from 'backoff' up to 'max_backoff'
+    pub worker_restart: bool,
+    pub worker_restart_backoff_secs: u64,
+    pub worker_restart_max_backoff_secs: u64,
+    // new workers get traffic after they pass the readiness path in 'startup_timeout'
+    pub worker_startup_timeout_secs: u64,
+    // workers failed the startup probe are killed (and restarted), otherwise added to rotation
+    pub worker_startup_kill: bool,
+    pub tile_retries: u64,
+    pub timeout_tile_response: u64,
+    // tiles of workers larger are replaced by 502 responses, 0 - unlimited
+    pub max_tile_size_kb: u64,
+    pub worker_client_pool_max_idle_per_host: usize,
+    pub worker_client_pool_idle_timeout_secs: u64,
+    pub worker_client_keepalive_secs: u64,
+    pub worker_client_http2: bool,
+    pub cluster_secret: Option<String>,
+    pub cluster_master: Option<String>,
+    pub cluster_advertise_address: Option<String>,
+    pub cluster_heartbeat_secs: u64,
+    pub cluster_node_timeout_secs: u64,
+    pub cluster_consistent_hash: bool,
+    // forwards of the request between nodes, requests with more hops are refused with 508
+    pub cluster_max_hops: u64,
+    // registration of nodes on the master without 'CLUSTER_SECRET', otherwise it is refused with 403
+    pub cluster_allow_unauthenticated: bool,
+    // time of the remote shard of the distributed pyramid, then its job on the node is cancelled, 0 - unlimited
+    pub cluster_shard_timeout_secs: u64,
+    pub static_root: Option<String>,
+    pub static_allowed_extensions: Vec<String>,
+    pub static_index: bool,
+    // roots of tiles of 'serve-cache'
+    pub serve_cache: ServeCacheConfig,
+    pub tile_grids: TileGrids,
+    pub blank_tile_enabled: bool,
+    pub blank_tile: Bytes,
+    // response to tiles without data, DataSources override it in the field 'missing_tile'
+    pub missing_tile: MissingTile,
+    // empty tiles of workers (204) are saved to the cache, next requests are not sent to workers
+    pub negative_cache: bool,
+    pub overzoom_maxzoom: Option<u8>,
+    pub export_ttl_seconds: u64,
+    // URLs notified of finished pyramids of every DataSource and the timeout of a delivery
+    pub pyramid_webhooks: Vec<String>,
+    pub webhook_timeout_secs: u64,
+    pub cors: CorsConfig,
+    // headers set (or removed) in responses by labels of endpoints ('/api/tile', '/metrics', ...)
+    pub response_headers: HashMap<String, Vec<HeaderOverride>>,
+    // headers of worker responses forwarded to clients by labels of endpoints, all by default
+    pub upstream_headers: HashMap<String, HeaderRules>,
+    pub proxy: ProxyConfig,
+    pub otlp: Option<OtlpConfig>,
+    // requests longer are logged with timings, 0 disables logging
+    pub slow_request_ms: u64,
+    // limit of bodies buffered by the balancer (JSON of endpoints), 0 - unlimited
+    pub max_body_size_kb: u64,
+    // limit of uploads of DataSource files
+    pub upload_max_size_mb: u64,
+    // unfinished resumable uploads are removed after this time without requests
+    pub upload_expire_secs: u64,
+    // checksums of tiles are recorded on write and verified on read
+    pub integrity: bool,
+    // page of Swagger UI for the OpenAPI document of the balancer
+    pub openapi_swagger_ui: bool,
+    // period of WAL checkpoints and incremental vacuum of MBTiles, 0 disables the maintenance
+    pub mbtiles_maintenance_secs: u64,
+    // thresholds by default, DataSources override them in the field 'mbtiles_maintenance'
+    pub mbtiles_thresholds: MaintenanceThresholds,
+    // open SQLite clients of MBTiles: limit (0 - unlimited) and idle time before eviction (0 - never)
+    pub sqlite_pool_max_clients: usize,
+    pub sqlite_pool_idle_secs: u64,
+    // period of checks of quotas of DataSource caches, 0 disables the quotas
+    pub quota_interval_secs: u64,
+    // free space of the tiles volume: period of checks and threshold to refuse pyramids (0 - never)
+    pub storage_interval_secs: u64,
+    pub storage_min_free_mb: u64,
+    // statistics of tile requests: period of flushes to 'usage.db' and size of the buffer of requests
+    pub usage_enabled: bool,
+    pub usage_flush_secs: u64,
+    pub usage_buffer_size: usize,
+    // misses of tiles older than the retention are removed, 0 keeps them
+    pub usage_retention_days: u64,
+    // tiles served per API key: header of the key and hard quotas by ids of keys
+    pub billing_enabled: bool,
+    pub billing_key_header: String,
+    pub billing_quotas: BillingQuotas,
+    // soft-deleted DataSources: days in the trash (0 - until restored) and period of purges
+    pub trash_retention_days: u64,
+    pub trash_interval_secs: u64,
+    // directories of the cache of tiles, source data and scripts of workers
+    pub paths: PathsConfig,
+    // registry of DataSources: 'postgres' or 'sqlite' with the path of the file relative to the root
+    pub registry_type: String,
+    pub registry_path: String,
+    // pool of connections to the config database
+    pub db_pool: DBPoolConfig,
+}
+
+impl Config {
+    // Settings of the worker with the index, None - workers are started with the same settings
+    pub fn worker_slot(&self, index: usize) -> Option<&WorkerSlot> {
+        if self.worker_slots.is_empty() {
+            return None;
+        }
+        self.worker_slots.get(index % self.worker_slots.len())
+    }
+
+    pub fn is_native(&self) -> bool {
+        self.type_server == NATIVE
+    }
+
+    // Rules of headers of worker responses of the endpoint
+    pub fn upstream_header_rules(&self, label: &str) -> &HeaderRules {
+        static FORWARD_ALL: HeaderRules = HeaderRules {
+            allow: Vec::new(),
+            strip: Vec::new(),
+        };
+        self.upstream_headers.get(label).unwrap_or(&FORWARD_ALL)
+    }
+}
+
+// Export of spans to the OpenTelemetry collector (Jaeger, Tempo) over OTLP/gRPC
+#[derive(Debug, Clone)]
+pub struct OtlpConfig {
+    pub endpoint: String,
+    pub service_name: String,
+    pub sample_ratio: f64,
+}
+
+/*
+    Directories of 'server.paths': absolute or relative to the root of the application,
+    None - the trash is next to the cache, so DataSources are moved to it without copying
+*/
+#[derive(Debug, Clone)]
+pub struct PathsConfig {
+    pub cache: String,
+    pub data: String,
+    pub scripts: String,
+    pub trash: Option<String>,
+}
+
+/*
+    Pool of connections to the config database. TLS and the timeout of statements
+    are applied to PostgreSQL, None keeps 'sslmode' of the connection string
+*/
+#[derive(Debug, Clone)]
+pub struct DBPoolConfig {
+    pub min_connections: u32,
+    pub max_connections: u32,
+    pub acquire_timeout_secs: u64,
+    // 0 disables the timeout
+    pub statement_timeout_ms: u64,
+    pub sslmode: Option<String>,
+    pub sslrootcert: Option<String>,
+}
+
+/*
+    CORS policy of responses: origins '*' allow any origin,
+    with credentials the origin of the request is returned instead of '*'
+*/
+#[derive(Debug, Clone)]
+pub struct CorsConfig {
+    pub allowed_origins: Vec<String>,
+    pub allowed_methods: Vec<String>,
+    pub allowed_headers: Vec<String>,
+    pub max_age: u64,
+    pub allow_credentials: bool,
+}
+
+impl CorsConfig {
+    pub fn origin_allowed(&self, origin: &str) -> bool {
+        self.allowed_origins
+            .iter()
+            .any(|o| o == "*" || o.eq_ignore_ascii_case(origin))
+    }
+
+    pub fn any_origin(&self) -> bool {
+        self.allowed_origins.iter().any(|o| o == "*")
+    }
+}
+
+/*
+    Command of workers from the config: '{port}', '{log_level}' and '{threads}' in arguments
+    are replaced by the port of the worker, 'log_level' and 'thread_workers' of workers
+*/
+#[derive(Debug, Clone)]
+pub struct WorkerLaunch {
+    pub command: String,
+    pub args: Vec<String>,
+    pub env: Vec<(String, String)>,
+    // directory of the process, the root of the application by default
+    pub workdir: Option<String>,
+}
+
+impl WorkerLaunch {
+    pub fn args(&self, port: u16, log_level: &str, threads: u64) -> Vec<String> {
+        self.args
+            .iter()
+            .map(|arg| {
+                arg.replace("{port}", &port.to_string())
+                    .replace("{log_level}", log_level)
+                    .replace("{threads}", &threads.to_string())
+            })
+            .collect()
+    }
+
+    // Name of processes of workers: the file name of the command
+    pub fn process_name(&self) -> String {
+        Path::new(&self.command)
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_else(|| self.command.clone())
+    }
+}
+
+/*
+    Settings of the worker by its index: extra environment ('CUDA_VISIBLE_DEVICES',
+    'OMP_NUM_THREADS') and CPUs the worker is pinned to. Workers beyond the list
+    take settings by the index modulo length
+*/
+#[derive(Debug, Clone)]
+pub struct WorkerSlot {
+    pub env: Vec<(String, String)>,
+    pub cpus: Vec<u64>,
+}
+
+impl WorkerSlot {
+    // CPU list of 'taskset': '0,1,2,3'
+    pub fn cpu_list(&self) -> Option<String> {
+        if self.cpus.is_empty() {
+            return None;
+        }
+        let cpus: Vec<String> = self.cpus.iter().map(|cpu| cpu.to_string()).collect();
+        Some(cpus.join(","))
+    }
+}
+
+/*
+    AIMD control of the limit of concurrent requests of a worker port: every 'interval' the limit
+    grows by one permit while requests wait for permits, and is halved when the average latency
+    exceeds 'target_latency' or the share of errors exceeds 'max_error_rate'
+*/
+#[derive(Debug, Clone)]
+pub struct AdaptiveConcurrency {
+    pub min_permits: usize,
+    pub max_permits: usize,
+    pub target_latency_ms: u64,
+    pub max_error_rate: f64,
+    pub interval_secs: u64,
+}
+
+/*
+    Root directory of tiles of 'serve-cache' (e.g. NFS mount of prebuilt MBTiles) and
+    prefixes of ids of DataSources served from it, no prefixes - any DataSource
+*/
+#[derive(Debug, Clone)]
+pub struct CacheRoot {
+    pub path: String,
+    pub prefixes: Vec<String>,
+    // response to tiles without data of DataSources of the root, None - 'missing_tile' of the server
+    pub missing_tile: Option<MissingTile>,
+    // websites allowed to show tiles of DataSources of the root, None - tiles are not restricted
+    pub allowed_origins: Option<AllowedOrigins>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ServeCacheConfig {
+    pub roots: Vec<CacheRoot>,
+    // MBTiles are opened as immutable: without locks and WAL, nothing is written to the roots
+    pub immutable: bool,
+    // tiler requested for tiles missing in the roots (edge node)
+    pub origin: Option<CacheOrigin>,
+}
+
+#[derive(Debug, Clone)]
+pub struct CacheOrigin {
+    pub url: String,
+    // tiles of the origin are saved to the local MBTiles of the DataSource
+    pub store: bool,
+    pub timeout_secs: u64,
+}
+
+impl ServeCacheConfig {
+    // Root of the DataSource, the first matching root is used
+    fn root(&self, datasource_id: &str) -> Option<&CacheRoot> {
+        self.roots.iter().find(|root| {
+            root.prefixes.is_empty()
+                || root
+                    .prefixes
+                    .iter()
+                    .any(|prefix| datasource_id.starts_with(prefix.as_str()))
+        })
+    }
+
+    // Directory of the root for the DataSource
+    pub fn root_dir(&self, paths: &Paths, datasource_id: &str) -> Option<PathBuf> {
+        let root = self.root(datasource_id)?;
+        Some(paths.resolve(&root.path))
+    }
+
+    pub fn missing_tile(&self, datasource_id: &str) -> Option<MissingTile> {
+        self.root(datasource_id)?.missing_tile
+    }
+
+    pub fn allowed_origins(&self, datasource_id: &str) -> Option<&AllowedOrigins> {
+        self.root(datasource_id)?.allowed_origins.as_ref()
+    }
+}
+
+/*
+    Proxies in front of the balancer: the address of the client is taken from 'X-Forwarded-For'
+    and 'Forwarded' only for connections of trusted proxies
+*/
+#[derive(Debug, Clone)]
+pub struct ProxyConfig {
+    pub trusted: Vec<IpNetwork>,
+    // every connection starts with the header of the PROXY protocol v2
+    pub protocol: bool,
+}
+
+impl ProxyConfig {
+    pub fn is_trusted(&self, ip: IpAddr) -> bool {
+        self.trusted.iter().any(|network| network.contains(ip))
+    }
 }
 
 pub async fn load_config() -> Result<Config, anyhow::Error> {
     let data = tokio::fs::read_to_string("config_app.json").await?;
     let config_json: serde_json::Value =
         serde_json::from_str(&data).expect("config_app.json was not well-formatted");
+    config_from_json(config_json)
+}
 
+// Configuration of the JSON document with the structure of 'config_app.json'
+pub fn config_from_json(config_json: serde_json::Value) -> Result<Config, anyhow::Error> {
     let type_server = config_json
         .get("server")
         .and_then(|server| server.get("type"))
@@ -39,9 +417,9 @@ pub async fn load_config() -> Result<Config, anyhow::Error> {
         .expect("Host of server is undefined")
         .to_string();
 
-    if !["granian", "robyn"].contains(&type_server.as_str()) {
+    if !["granian", "robyn", NATIVE].contains(&type_server.as_str()) {
         eprintln!(
-            "Server type must be 'granian' or 'robyn', got: {}",
+            "Server type must be 'granian', 'robyn' or 'native', got: {}",
             type_server
         );
         exit(1);
@@ -211,6 +589,1093 @@ pub async fn load_config() -> Result<Config, anyhow::Error> {
         .expect("Worker 'max tile concurrent requests' is undefined")
         as usize;
 
+    let adaptive_json = config_json
+        .get("server")
+        .and_then(|server| server.get("worker"))
+        .and_then(|worker| worker.get("adaptive_concurrency"));
+    let adaptive_u64 = |name: &str| {
+        adaptive_json
+            .and_then(|adaptive| adaptive.get(name))
+            .and_then(|value| value.as_u64())
+    };
+    let adaptive_concurrency = adaptive_json
+        .and_then(|adaptive| adaptive.get("enabled"))
+        .and_then(|enabled| enabled.as_bool())
+        .unwrap_or(false)
+        .then(|| AdaptiveConcurrency {
+            min_permits: adaptive_u64("min_permits").unwrap_or(1) as usize,
+            max_permits: adaptive_u64("max_permits")
+                .map(|max_permits| max_permits as usize)
+                .unwrap_or(max_concurrent_tile_requests * 2),
+            target_latency_ms: adaptive_u64("target_latency_ms")
+                .unwrap_or(ADAPTIVE_CONCURRENCY_LATENCY_MS),
+            max_error_rate: adaptive_json
+                .and_then(|adaptive| adaptive.get("max_error_rate"))
+                .and_then(|max_error_rate| max_error_rate.as_f64())
+                .unwrap_or(ADAPTIVE_CONCURRENCY_ERROR_RATE),
+            interval_secs: adaptive_u64("interval_seconds")
+                .unwrap_or(ADAPTIVE_CONCURRENCY_INTERVAL_SECS),
+        });
+    if let Some(adaptive) = adaptive_concurrency.as_ref() {
+        if adaptive.min_permits == 0
+            || adaptive.min_permits > max_concurrent_tile_requests
+            || adaptive.max_permits < max_concurrent_tile_requests
+        {
+            eprintln!(
+                "Parameters 'worker.adaptive_concurrency' must satisfy \
+                 0 < min_permits <= max_concurrent_tile_requests <= max_permits"
+            );
+            exit(1);
+        }
+        if !(0.0..=1.0).contains(&adaptive.max_error_rate) || adaptive.interval_secs == 0 {
+            eprintln!(
+                "Parameter 'worker.adaptive_concurrency.max_error_rate' must be in range 0.0-1.0 \
+                 and 'interval_seconds' greater than 0"
+            );
+            exit(1);
+        }
+    }
+
+    let batch_permits_share = config_json
+        .get("server")
+        .and_then(|server| server.get("worker"))
+        .and_then(|worker| worker.get("batch_permits_share"))
+        .and_then(|batch_permits_share| batch_permits_share.as_f64())
+        .unwrap_or(BATCH_PERMITS_SHARE);
+    if batch_permits_share <= 0.0 || batch_permits_share > 1.0 {
+        eprintln!(
+            "Parameter 'worker.batch_permits_share' must be in range (0.0, 1.0], got {}",
+            batch_permits_share
+        );
+        exit(1);
+    }
+
+    let max_queue_depth = config_json
+        .get("server")
+        .and_then(|server| server.get("worker"))
+        .and_then(|worker| worker.get("max_queue_depth"))
+        .and_then(|max_queue_depth| max_queue_depth.as_u64())
+        .unwrap_or(0) as usize;
+    let queue_retry_after_secs = config_json
+        .get("server")
+        .and_then(|server| server.get("worker"))
+        .and_then(|worker| worker.get("queue_retry_after_seconds"))
+        .and_then(|retry_after| retry_after.as_u64())
+        .unwrap_or(QUEUE_RETRY_AFTER_SECS);
+
+    let circuit_breaker_failure_threshold = config_json
+        .get("server")
+        .and_then(|server| server.get("worker"))
+        .and_then(|worker| worker.get("circuit_breaker"))
+        .and_then(|cb| cb.get("failure_threshold"))
+        .and_then(|failure_threshold| failure_threshold.as_u64())
+        .unwrap_or(5);
+
+    let circuit_breaker_open_secs = config_json
+        .get("server")
+        .and_then(|server| server.get("worker"))
+        .and_then(|worker| worker.get("circuit_breaker"))
+        .and_then(|cb| cb.get("open_seconds"))
+        .and_then(|open_seconds| open_seconds.as_u64())
+        .unwrap_or(30);
+
+    let worker_ready_quorum = config_json
+        .get("server")
+        .and_then(|server| server.get("worker"))
+        .and_then(|worker| worker.get("ready_quorum"))
+        .and_then(|ready_quorum| ready_quorum.as_f64())
+        .unwrap_or(WORKER_READY_QUORUM);
+    if !(0.0..=1.0).contains(&worker_ready_quorum) {
+        eprintln!(
+            "Parameter 'worker.ready_quorum' must be in range 0.0-1.0, got {}",
+            worker_ready_quorum
+        );
+        exit(1);
+    }
+
+    let worker_readiness_path = config_json
+        .get("server")
+        .and_then(|server| server.get("worker"))
+        .and_then(|worker| worker.get("readiness_path"))
+        .and_then(|path| path.as_str())
+        .unwrap_or(EndPoints::Health.as_ref())
+        .to_string();
+    if !worker_readiness_path.starts_with('/') {
+        eprintln!(
+            "Parameter 'worker.readiness_path' must start with '/', got {worker_readiness_path}"
+        );
+        exit(1);
+    }
+
+    let worker_restart = config_json
+        .get("server")
+        .and_then(|server| server.get("worker"))
+        .and_then(|worker| worker.get("restart"))
+        .and_then(|restart| restart.get("enabled"))
+        .and_then(|enabled| enabled.as_bool())
+        .unwrap_or(true);
+
+    let worker_restart_backoff_secs = config_json
+        .get("server")
+        .and_then(|server| server.get("worker"))
+        .and_then(|worker| worker.get("restart"))
+        .and_then(|restart| restart.get("backoff_seconds"))
+        .and_then(|backoff_seconds| backoff_seconds.as_u64())
+        .unwrap_or(WORKER_RESTART_BACKOFF_SECS);
+
+    let worker_restart_max_backoff_secs = config_json
+        .get("server")
+        .and_then(|server| server.get("worker"))
+        .and_then(|worker| worker.get("restart"))
+        .and_then(|restart| restart.get("max_backoff_seconds"))
+        .and_then(|max_backoff_seconds| max_backoff_seconds.as_u64())
+        .unwrap_or(WORKER_RESTART_MAX_BACKOFF_SECS);
+    if worker_restart_max_backoff_secs < worker_restart_backoff_secs {
+        eprintln!(
+            "Parameter 'worker.restart.max_backoff_seconds' must not be less than 'backoff_seconds'"
+        );
+        exit(1);
+    }
+
+    let worker_startup_timeout_secs = config_json
+        .get("server")
+        .and_then(|server| server.get("worker"))
+        .and_then(|worker| worker.get("startup"))
+        .and_then(|startup| startup.get("timeout_seconds"))
+        .and_then(|timeout_seconds| timeout_seconds.as_u64())
+        .unwrap_or(WORKER_STARTUP_TIMEOUT_SECS);
+
+    let worker_startup_on_failure = config_json
+        .get("server")
+        .and_then(|server| server.get("worker"))
+        .and_then(|worker| worker.get("startup"))
+        .and_then(|startup| startup.get("on_failure"))
+        .and_then(|on_failure| on_failure.as_str())
+        .unwrap_or("add");
+    if !["add", "kill"].contains(&worker_startup_on_failure) {
+        eprintln!(
+            "Parameter 'worker.startup.on_failure' must be 'add' or 'kill', got: {}",
+            worker_startup_on_failure
+        );
+        exit(1);
+    }
+    let worker_startup_kill = worker_startup_on_failure == "kill";
+
+    let worker_launch = config_json
+        .get("server")
+        .and_then(|server| server.get("worker"))
+        .and_then(|worker| worker.get("launch"))
+        .map(|launch| WorkerLaunch {
+            command: launch
+                .get("command")
+                .and_then(|command| command.as_str())
+                .unwrap_or_default()
+                .to_string(),
+            args: launch
+                .get("args")
+                .and_then(|args| args.as_array())
+                .map(|args| {
+                    args.iter()
+                        .filter_map(|arg| arg.as_str())
+                        .map(|arg| arg.to_string())
+                        .collect()
+                })
+                .unwrap_or_default(),
+            env: launch
+                .get("env")
+                .and_then(|env| env.as_object())
+                .map(|env| {
+                    env.iter()
+                        .filter_map(|(key, value)| {
+                            value.as_str().map(|v| (key.clone(), v.to_string()))
+                        })
+                        .collect()
+                })
+                .unwrap_or_default(),
+            workdir: launch
+                .get("workdir")
+                .and_then(|workdir| workdir.as_str())
+                .map(|workdir| workdir.to_string()),
+        });
+    if let Some(launch) = worker_launch.as_ref() {
+        if launch.command.is_empty() {
+            eprintln!("Parameter 'worker.launch.command' is undefined");
+            exit(1);
+        }
+        if !launch.args.iter().any(|arg| arg.contains("{port}")) {
+            eprintln!(
+                "Arguments of 'worker.launch' must pass the port of the worker with '{{port}}'"
+            );
+            exit(1);
+        }
+    }
+
+    let worker_slots: Vec<WorkerSlot> = config_json
+        .get("server")
+        .and_then(|server| server.get("worker"))
+        .and_then(|worker| worker.get("slots"))
+        .and_then(|slots| slots.as_array())
+        .map(|slots| {
+            slots
+                .iter()
+                .map(|slot| WorkerSlot {
+                    env: slot
+                        .get("env")
+                        .and_then(|env| env.as_object())
+                        .map(|env| {
+                            env.iter()
+                                .filter_map(|(key, value)| {
+                                    value.as_str().map(|v| (key.clone(), v.to_string()))
+                                })
+                                .collect()
+                        })
+                        .unwrap_or_default(),
+                    cpus: slot
+                        .get("cpus")
+                        .and_then(|cpus| cpus.as_array())
+                        .map(|cpus| cpus.iter().filter_map(|cpu| cpu.as_u64()).collect())
+                        .unwrap_or_default(),
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    if !cfg!(target_os = "linux") && worker_slots.iter().any(|slot| !slot.cpus.is_empty()) {
+        eprintln!("CPU affinity of 'worker.slots' is supported only on Linux");
+        exit(1);
+    }
+
+    let tile_retries = config_json
+        .get("server")
+        .and_then(|server| server.get("worker"))
+        .and_then(|worker| worker.get("tile_retries"))
+        .and_then(|tile_retries| tile_retries.as_u64())
+        .unwrap_or(1);
+
+    let max_tile_size_kb = config_json
+        .get("server")
+        .and_then(|server| server.get("worker"))
+        .and_then(|worker| worker.get("max_tile_size_kb"))
+        .and_then(|max_tile_size_kb| max_tile_size_kb.as_u64())
+        .unwrap_or(MAX_TILE_SIZE_KB);
+
+    let timeout_tile_response = config_json
+        .get("server")
+        .and_then(|server| server.get("timeout_tile_response"))
+        .and_then(|t| t.as_u64())
+        .unwrap_or(60);
+
+    let worker_client_pool_max_idle_per_host = config_json
+        .get("server")
+        .and_then(|server| server.get("worker"))
+        .and_then(|worker| worker.get("http_client"))
+        .and_then(|http_client| http_client.get("pool_max_idle_per_host"))
+        .and_then(|pool_max_idle| pool_max_idle.as_u64())
+        .unwrap_or(64) as usize;
+
+    let worker_client_pool_idle_timeout_secs = config_json
+        .get("server")
+        .and_then(|server| server.get("worker"))
+        .and_then(|worker| worker.get("http_client"))
+        .and_then(|http_client| http_client.get("pool_idle_timeout"))
+        .and_then(|pool_idle_timeout| pool_idle_timeout.as_u64())
+        .unwrap_or(90);
+
+    let worker_client_keepalive_secs = config_json
+        .get("server")
+        .and_then(|server| server.get("worker"))
+        .and_then(|worker| worker.get("http_client"))
+        .and_then(|http_client| http_client.get("tcp_keepalive"))
+        .and_then(|tcp_keepalive| tcp_keepalive.as_u64())
+        .unwrap_or(60);
+
+    let worker_client_http2 = config_json
+        .get("server")
+        .and_then(|server| server.get("worker"))
+        .and_then(|worker| worker.get("http_client"))
+        .and_then(|http_client| http_client.get("http2"))
+        .and_then(|http2| http2.as_bool())
+        .unwrap_or(false);
+
+    // shared secret for signing requests between instances of the cluster
+    let cluster_secret = std::env::var("CLUSTER_SECRET")
+        .ok()
+        .filter(|secret| !secret.is_empty());
+
+    let cluster_master = config_json
+        .get("server")
+        .and_then(|server| server.get("cluster"))
+        .and_then(|cluster| cluster.get("master"))
+        .and_then(|master| master.as_str())
+        .filter(|master| !master.is_empty())
+        .map(|master| master.to_string());
+
+    let cluster_advertise_address = config_json
+        .get("server")
+        .and_then(|server| server.get("cluster"))
+        .and_then(|cluster| cluster.get("advertise_address"))
+        .and_then(|advertise_address| advertise_address.as_str())
+        .filter(|advertise_address| !advertise_address.is_empty())
+        .map(|advertise_address| advertise_address.to_string());
+
+    let cluster_heartbeat_secs = config_json
+        .get("server")
+        .and_then(|server| server.get("cluster"))
+        .and_then(|cluster| cluster.get("heartbeat_seconds"))
+        .and_then(|heartbeat_seconds| heartbeat_seconds.as_u64())
+        .unwrap_or(10);
+
+    let cluster_node_timeout_secs = config_json
+        .get("server")
+        .and_then(|server| server.get("cluster"))
+        .and_then(|cluster| cluster.get("node_timeout_seconds"))
+        .and_then(|node_timeout_seconds| node_timeout_seconds.as_u64())
+        .unwrap_or(30);
+
+    let cluster_routing = config_json
+        .get("server")
+        .and_then(|server| server.get("cluster"))
+        .and_then(|cluster| cluster.get("routing"))
+        .and_then(|routing| routing.as_str())
+        .unwrap_or("round_robin");
+
+    if !["round_robin", "consistent_hash"].contains(&cluster_routing) {
+        eprintln!(
+            "Cluster routing must be 'round_robin' or 'consistent_hash', got: {}",
+            cluster_routing
+        );
+        exit(1);
+    }
+    let cluster_consistent_hash = cluster_routing == "consistent_hash";
+
+    let cluster_max_hops = config_json
+        .get("server")
+        .and_then(|server| server.get("cluster"))
+        .and_then(|cluster| cluster.get("max_hops"))
+        .and_then(|max_hops| max_hops.as_u64())
+        .unwrap_or(CLUSTER_MAX_HOPS);
+
+    let cluster_allow_unauthenticated = config_json
+        .get("server")
+        .and_then(|server| server.get("cluster"))
+        .and_then(|cluster| cluster.get("allow_unauthenticated"))
+        .and_then(|allow_unauthenticated| allow_unauthenticated.as_bool())
+        .unwrap_or(false);
+
+    let cluster_shard_timeout_secs = config_json
+        .get("server")
+        .and_then(|server| server.get("cluster"))
+        .and_then(|cluster| cluster.get("shard_timeout_seconds"))
+        .and_then(|shard_timeout_seconds| shard_timeout_seconds.as_u64())
+        .unwrap_or(SHARD_TIMEOUT_SECS);
+
+    let static_root = config_json
+        .get("server")
+        .and_then(|server| server.get("static"))
+        .and_then(|static_files| static_files.get("root"))
+        .and_then(|root| root.as_str())
+        .filter(|root| !root.is_empty())
+        .map(|root| root.to_string());
+
+    let static_allowed_extensions = config_json
+        .get("server")
+        .and_then(|server| server.get("static"))
+        .and_then(|static_files| static_files.get("allowed_extensions"))
+        .and_then(|extensions| extensions.as_array())
+        .map(|extensions| {
+            extensions
+                .iter()
+                .filter_map(|ext| ext.as_str())
+                .map(|ext| ext.trim_start_matches('.').to_lowercase())
+                .collect()
+        })
+        .unwrap_or_else(|| {
+            STATIC_ALLOWED_EXTENSIONS
+                .iter()
+                .map(|ext| ext.to_string())
+                .collect()
+        });
+
+    let static_index = config_json
+        .get("server")
+        .and_then(|server| server.get("static"))
+        .and_then(|static_files| static_files.get("index"))
+        .and_then(|index| index.as_bool())
+        .unwrap_or(true);
+
+    let paths_json = config_json
+        .get("server")
+        .and_then(|server| server.get("paths"));
+    let path_param = |key: &str| {
+        paths_json
+            .and_then(|p| p.get(key))
+            .and_then(|path| path.as_str())
+            .filter(|path| !path.is_empty())
+            .map(|path| path.to_string())
+    };
+    let paths = PathsConfig {
+        cache: path_param("cache").unwrap_or(PATHS_CACHE.to_string()),
+        data: path_param("data").unwrap_or(PATHS_DATA.to_string()),
+        scripts: path_param("scripts").unwrap_or(PATHS_SCRIPTS.to_string()),
+        trash: path_param("trash"),
+    };
+
+    let serve_cache_json = config_json
+        .get("server")
+        .and_then(|server| server.get("serve_cache"));
+    let cache_roots: Vec<CacheRoot> = serve_cache_json
+        .and_then(|serve_cache| serve_cache.get("roots"))
+        .and_then(|roots| roots.as_array())
+        .map(|roots| {
+            roots
+                .iter()
+                .filter_map(|root| {
+                    let path = root.get("path").and_then(|path| path.as_str())?;
+                    let prefixes = root
+                        .get("prefixes")
+                        .and_then(|prefixes| prefixes.as_array())
+                        .map(|prefixes| {
+                            prefixes
+                                .iter()
+                                .filter_map(|prefix| prefix.as_str())
+                                .map(|prefix| prefix.to_string())
+                                .collect()
+                        })
+                        .unwrap_or_default();
+                    let missing_tile = root.get("missing_tile").map(|missing| {
+                        parse_missing_tile(missing).unwrap_or_else(|| {
+                            eprintln!("Parameter 'serve_cache.roots.missing_tile' must be 'no_content', 'not_found' or 'blank'");
+                            exit(1);
+                        })
+                    });
+                    Some(CacheRoot {
+                        path: path.to_string(),
+                        prefixes,
+                        missing_tile,
+                        allowed_origins: AllowedOrigins::from_data(root),
+                    })
+                })
+                .collect()
+        })
+        .filter(|roots: &Vec<CacheRoot>| !roots.is_empty())
+        .unwrap_or_else(|| {
+            vec![CacheRoot {
+                path: paths.cache.clone(),
+                prefixes: vec![],
+                missing_tile: None,
+                allowed_origins: None,
+            }]
+        });
+    if cache_roots.iter().any(|root| root.path.is_empty()) {
+        eprintln!("Parameter 'serve_cache.roots' must have non-empty 'path' of every root");
+        exit(1);
+    }
+    let cache_origin_json = serve_cache_json.and_then(|serve_cache| serve_cache.get("origin"));
+    let cache_origin = cache_origin_json
+        .and_then(|origin| origin.get("url"))
+        .and_then(|url| url.as_str())
+        .filter(|url| !url.is_empty())
+        .map(|url| CacheOrigin {
+            url: url.trim_end_matches('/').to_string(),
+            store: cache_origin_json
+                .and_then(|origin| origin.get("store"))
+                .and_then(|store| store.as_bool())
+                .unwrap_or(false),
+            timeout_secs: cache_origin_json
+                .and_then(|origin| origin.get("timeout_seconds"))
+                .and_then(|timeout| timeout.as_u64())
+                .unwrap_or(REMOTE_REQUEST_TIMEOUT_SECS),
+        });
+    let serve_cache = ServeCacheConfig {
+        roots: cache_roots,
+        immutable: serve_cache_json
+            .and_then(|serve_cache| serve_cache.get("immutable"))
+            .and_then(|immutable| immutable.as_bool())
+            .unwrap_or(false),
+        origin: cache_origin,
+    };
+    if let Some(origin) = serve_cache.origin.as_ref() {
+        if !origin.url.starts_with("http://") && !origin.url.starts_with("https://") {
+            eprintln!(
+                "Parameter 'serve_cache.origin.url' must be HTTP(S), got '{}'",
+                origin.url
+            );
+            exit(1);
+        }
+        if origin.store && serve_cache.immutable {
+            eprintln!("Tiles of 'serve_cache.origin' can not be stored to immutable MBTiles");
+            exit(1);
+        }
+    }
+
+    let mut custom_grids = Vec::new();
+    if let Some(grids) = config_json
+        .get("server")
+        .and_then(|server| server.get("tile_grids"))
+        .and_then(|grids| grids.as_array())
+    {
+        for grid in grids {
+            match TileGrid::from_json(grid) {
+                Ok(g) => custom_grids.push(g),
+                Err(err) => {
+                    eprintln!("Error tile grid in configuration: {err}");
+                    exit(1);
+                }
+            }
+        }
+    }
+    let tile_grids = TileGrids::new(custom_grids);
+
+    let blank_tile_enabled = config_json
+        .get("server")
+        .and_then(|server| server.get("blank_tile"))
+        .and_then(|blank_tile| blank_tile.get("enabled"))
+        .and_then(|enabled| enabled.as_bool())
+        .unwrap_or(false);
+
+    let blank_tile_size = config_json
+        .get("server")
+        .and_then(|server| server.get("blank_tile"))
+        .and_then(|blank_tile| blank_tile.get("size"))
+        .and_then(|size| size.as_u64())
+        .unwrap_or(256) as u32;
+
+    let blank_tile_color = match config_json
+        .get("server")
+        .and_then(|server| server.get("blank_tile"))
+        .and_then(|blank_tile| blank_tile.get("color"))
+    {
+        None => [0, 0, 0, 0],
+        Some(color) => match parse_color(color) {
+            Some(c) => c,
+            None => {
+                eprintln!(
+                    "Blank tile color must be '#RRGGBB', '#RRGGBBAA' or [r, g, b, a], got: {}",
+                    color
+                );
+                exit(1);
+            }
+        },
+    };
+    // generated once, transparent by default
+    let blank_tile = Bytes::from(solid_png(blank_tile_size, blank_tile_color));
+
+    // without the parameter raster tiles are blank when 'blank_tile' is enabled
+    let missing_tile = match config_json
+        .get("server")
+        .and_then(|server| server.get("missing_tile"))
+    {
+        None if blank_tile_enabled => MissingTile::Blank,
+        None => MissingTile::NoContent,
+        Some(missing) => parse_missing_tile(missing).unwrap_or_else(|| {
+            eprintln!("Parameter 'missing_tile' must be 'no_content', 'not_found' or 'blank'");
+            exit(1);
+        }),
+    };
+    let negative_cache = config_json
+        .get("server")
+        .and_then(|server| server.get("negative_cache"))
+        .and_then(|enabled| enabled.as_bool())
+        .unwrap_or(false);
+
+    let overzoom_enabled = config_json
+        .get("server")
+        .and_then(|server| server.get("overzoom"))
+        .and_then(|overzoom| overzoom.get("enabled"))
+        .and_then(|enabled| enabled.as_bool())
+        .unwrap_or(false);
+
+    let overzoom_maxzoom = config_json
+        .get("server")
+        .and_then(|server| server.get("overzoom"))
+        .and_then(|overzoom| overzoom.get("maxzoom"))
+        .and_then(|maxzoom| maxzoom.as_u64())
+        .unwrap_or(OVERZOOM_MAXZOOM as u64);
+    if overzoom_maxzoom > 30 {
+        eprintln!("Overzoom maxzoom must be in range 0-30, got: {overzoom_maxzoom}");
+        exit(1);
+    }
+    let overzoom_maxzoom = overzoom_enabled.then_some(overzoom_maxzoom as u8);
+
+    let export_ttl_seconds = config_json
+        .get("server")
+        .and_then(|server| server.get("export"))
+        .and_then(|export| export.get("ttl_seconds"))
+        .and_then(|ttl| ttl.as_u64())
+        .unwrap_or(EXPORT_TTL_SECS);
+
+    let webhooks = config_json
+        .get("server")
+        .and_then(|server| server.get("webhooks"));
+    let pyramid_webhooks: Vec<String> = webhooks
+        .and_then(|webhooks| webhooks.get("pyramid"))
+        .and_then(|urls| urls.as_array())
+        .map(|urls| {
+            urls.iter()
+                .filter_map(|url| url.as_str())
+                .map(|url| url.trim().to_string())
+                .collect()
+        })
+        .unwrap_or_default();
+    if let Some(url) = pyramid_webhooks.iter().find(|url| !is_webhook_url(url)) {
+        eprintln!("URL of webhook 'webhooks.pyramid' must be HTTP(S), got '{url}'");
+        exit(1);
+    }
+    let webhook_timeout_secs = webhooks
+        .and_then(|webhooks| webhooks.get("timeout_seconds"))
+        .and_then(|secs| secs.as_u64())
+        .unwrap_or(WEBHOOK_TIMEOUT_SECS)
+        .max(1);
+
+    let cors_list = |key: &str| -> Option<Vec<String>> {
+        config_json
+            .get("server")
+            .and_then(|server| server.get("cors"))
+            .and_then(|cors| cors.get(key))
+            .and_then(|values| values.as_array())
+            .map(|values| {
+                values
+                    .iter()
+                    .filter_map(|v| v.as_str())
+                    .map(|v| v.trim().to_string())
+                    .collect()
+            })
+    };
+    let cors_allow_credentials = config_json
+        .get("server")
+        .and_then(|server| server.get("cors"))
+        .and_then(|cors| cors.get("allow_credentials"))
+        .and_then(|credentials| credentials.as_bool())
+        .unwrap_or(false);
+    let cors = CorsConfig {
+        allowed_origins: cors_list("allowed_origins").unwrap_or_else(|| vec!["*".to_string()]),
+        allowed_methods: cors_list("allowed_methods")
+            .map(|methods| methods.iter().map(|m| m.to_uppercase()).collect())
+            .unwrap_or_else(|| CORS_ALLOWED_METHODS.iter().map(|m| m.to_string()).collect()),
+        allowed_headers: cors_list("allowed_headers").unwrap_or_else(|| vec!["*".to_string()]),
+        max_age: config_json
+            .get("server")
+            .and_then(|server| server.get("cors"))
+            .and_then(|cors| cors.get("max_age"))
+            .and_then(|max_age| max_age.as_u64())
+            .unwrap_or(CORS_MAX_AGE_SECS),
+        allow_credentials: cors_allow_credentials,
+    };
+
+    let mut response_headers = HashMap::new();
+    if let Some(endpoints) = config_json
+        .get("server")
+        .and_then(|server| server.get("response_headers"))
+    {
+        let Some(endpoints) = endpoints.as_object() else {
+            eprintln!("Parameter 'response_headers' must be an object of headers by endpoints");
+            exit(1);
+        };
+        for (endpoint, headers) in endpoints {
+            let Some(headers) = headers.as_object() else {
+                eprintln!("Parameter 'response_headers.{endpoint}' must be an object of headers");
+                exit(1);
+            };
+            let mut overrides = Vec::with_capacity(headers.len());
+            for (name, value) in headers {
+                let header_name = hyper::header::HeaderName::from_bytes(name.as_bytes());
+                // empty value removes the header from responses of the endpoint
+                let header_value = match value.as_str() {
+                    Some("") => Ok(None),
+                    Some(value) => hyper::header::HeaderValue::from_str(value).map(Some),
+                    None => {
+                        eprintln!("Header 'response_headers.{endpoint}.{name}' must be a string");
+                        exit(1);
+                    }
+                };
+                match (header_name, header_value) {
+                    (Ok(header_name), Ok(header_value)) => {
+                        overrides.push((header_name, header_value))
+                    }
+                    _ => {
+                        eprintln!("Header 'response_headers.{endpoint}.{name}' is not a valid HTTP header");
+                        exit(1);
+                    }
+                }
+            }
+            response_headers.insert(endpoint.clone(), overrides);
+        }
+    }
+
+    let mut upstream_headers = HashMap::new();
+    if let Some(endpoints) = config_json
+        .get("server")
+        .and_then(|server| server.get("upstream_headers"))
+    {
+        let Some(endpoints) = endpoints.as_object() else {
+            eprintln!("Parameter 'upstream_headers' must be an object of rules by endpoints");
+            exit(1);
+        };
+        for (endpoint, rules) in endpoints {
+            let names = |key: &str| -> Vec<hyper::header::HeaderName> {
+                let Some(names) = rules.get(key) else {
+                    return Vec::new();
+                };
+                let parsed: Option<Vec<_>> = names.as_array().and_then(|names| {
+                    names
+                        .iter()
+                        .map(|name| {
+                            name.as_str().and_then(|name| {
+                                hyper::header::HeaderName::from_bytes(name.as_bytes()).ok()
+                            })
+                        })
+                        .collect()
+                });
+                parsed.unwrap_or_else(|| {
+                    eprintln!(
+                        "Parameter 'upstream_headers.{endpoint}.{key}' must be a list of HTTP headers"
+                    );
+                    exit(1);
+                })
+            };
+            if !rules.is_object() {
+                eprintln!("Parameter 'upstream_headers.{endpoint}' must be an object with 'allow' and 'strip'");
+                exit(1);
+            }
+            upstream_headers.insert(
+                endpoint.clone(),
+                HeaderRules {
+                    allow: names("allow"),
+                    strip: names("strip"),
+                },
+            );
+        }
+    }
+
+    let mut trusted_proxies = Vec::new();
+    for network in config_json
+        .get("server")
+        .and_then(|server| server.get("proxy"))
+        .and_then(|proxy| proxy.get("trusted"))
+        .and_then(|trusted| trusted.as_array())
+        .map(|trusted| {
+            trusted
+                .iter()
+                .filter_map(|t| t.as_str())
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default()
+    {
+        match network.parse::<IpNetwork>() {
+            Ok(network) => trusted_proxies.push(network),
+            Err(err) => {
+                eprintln!("Parameter 'proxy.trusted': {err}");
+                exit(1);
+            }
+        }
+    }
+    let proxy = ProxyConfig {
+        trusted: trusted_proxies,
+        protocol: config_json
+            .get("server")
+            .and_then(|server| server.get("proxy"))
+            .and_then(|proxy| proxy.get("protocol"))
+            .and_then(|protocol| protocol.as_bool())
+            .unwrap_or(false),
+    };
+
+    let otlp = config_json
+        .get("server")
+        .and_then(|server| server.get("opentelemetry"))
+        .filter(|otel| {
+            otel.get("enabled")
+                .and_then(|enabled| enabled.as_bool())
+                .unwrap_or(false)
+        })
+        .map(|otel| OtlpConfig {
+            endpoint: otel
+                .get("endpoint")
+                .and_then(|endpoint| endpoint.as_str())
+                .unwrap_or(OTLP_ENDPOINT)
+                .to_string(),
+            service_name: otel
+                .get("service_name")
+                .and_then(|name| name.as_str())
+                .unwrap_or(OTLP_SERVICE_NAME)
+                .to_string(),
+            sample_ratio: otel
+                .get("sample_ratio")
+                .and_then(|ratio| ratio.as_f64())
+                .unwrap_or(1.0),
+        });
+    if let Some(otlp) = otlp.as_ref() {
+        if !(0.0..=1.0).contains(&otlp.sample_ratio) {
+            eprintln!(
+                "Parameter 'opentelemetry.sample_ratio' must be in range 0.0-1.0, got {}",
+                otlp.sample_ratio
+            );
+            exit(1);
+        }
+    }
+
+    let slow_request_ms = config_json
+        .get("server")
+        .and_then(|server| server.get("slow_request_ms"))
+        .and_then(|ms| ms.as_u64())
+        .unwrap_or(SLOW_REQUEST_MS);
+
+    let max_body_size_kb = config_json
+        .get("server")
+        .and_then(|server| server.get("max_body_size_kb"))
+        .and_then(|kb| kb.as_u64())
+        .unwrap_or(MAX_BODY_SIZE_KB);
+
+    let upload_max_size_mb = config_json
+        .get("server")
+        .and_then(|server| server.get("upload"))
+        .and_then(|upload| upload.get("max_size_mb"))
+        .and_then(|mb| mb.as_u64())
+        .unwrap_or(UPLOAD_MAX_SIZE_MB);
+    if upload_max_size_mb == 0 {
+        eprintln!("Parameter 'upload.max_size_mb' must be greater than 0");
+        exit(1);
+    }
+    let upload_expire_secs = config_json
+        .get("server")
+        .and_then(|server| server.get("upload"))
+        .and_then(|upload| upload.get("expire_seconds"))
+        .and_then(|secs| secs.as_u64())
+        .unwrap_or(UPLOAD_EXPIRE_SECS);
+
+    let integrity = config_json
+        .get("server")
+        .and_then(|server| server.get("integrity"))
+        .and_then(|integrity| integrity.get("enabled"))
+        .and_then(|enabled| enabled.as_bool())
+        .unwrap_or(false);
+
+    let openapi_swagger_ui = config_json
+        .get("server")
+        .and_then(|server| server.get("openapi"))
+        .and_then(|openapi| openapi.get("swagger_ui"))
+        .and_then(|enabled| enabled.as_bool())
+        .unwrap_or(false);
+
+    let mbtiles_maintenance = config_json
+        .get("server")
+        .and_then(|server| server.get("mbtiles_maintenance"));
+    let mbtiles_maintenance_secs = mbtiles_maintenance
+        .and_then(|m| m.get("interval_seconds"))
+        .and_then(|secs| secs.as_u64())
+        .unwrap_or(MBTILES_MAINTENANCE_SECS);
+    let mbtiles_thresholds = MaintenanceThresholds::from_json(
+        mbtiles_maintenance,
+        MaintenanceThresholds {
+            wal_threshold_mb: MBTILES_WAL_THRESHOLD_MB,
+            vacuum_pages: MBTILES_VACUUM_PAGES,
+        },
+    );
+
+    let sqlite_pool = config_json
+        .get("server")
+        .and_then(|server| server.get("sqlite_pool"));
+    let sqlite_pool_max_clients = sqlite_pool
+        .and_then(|pool| pool.get("max_clients"))
+        .and_then(|max| max.as_u64())
+        .map(|max| max as usize)
+        .unwrap_or(SQLITE_POOL_MAX_CLIENTS);
+    let sqlite_pool_idle_secs = sqlite_pool
+        .and_then(|pool| pool.get("idle_seconds"))
+        .and_then(|secs| secs.as_u64())
+        .unwrap_or(SQLITE_POOL_IDLE_SECS);
+
+    let quota_interval_secs = config_json
+        .get("server")
+        .and_then(|server| server.get("quota"))
+        .and_then(|quota| quota.get("interval_seconds"))
+        .and_then(|secs| secs.as_u64())
+        .unwrap_or(QUOTA_INTERVAL_SECS);
+
+    let storage = config_json
+        .get("server")
+        .and_then(|server| server.get("storage"));
+    let storage_interval_secs = storage
+        .and_then(|s| s.get("interval_seconds"))
+        .and_then(|secs| secs.as_u64())
+        .unwrap_or(STORAGE_INTERVAL_SECS);
+    if storage_interval_secs == 0 {
+        eprintln!("Parameter 'storage.interval_seconds' must be greater than 0");
+        exit(1);
+    }
+    let storage_min_free_mb = storage
+        .and_then(|s| s.get("min_free_mb"))
+        .and_then(|mb| mb.as_u64())
+        .unwrap_or(STORAGE_MIN_FREE_MB);
+
+    let usage = config_json
+        .get("server")
+        .and_then(|server| server.get("usage"));
+    let usage_enabled = usage
+        .and_then(|u| u.get("enabled"))
+        .and_then(|e| e.as_bool())
+        .unwrap_or(true);
+    let usage_flush_secs = usage
+        .and_then(|u| u.get("flush_seconds"))
+        .and_then(|secs| secs.as_u64())
+        .unwrap_or(USAGE_FLUSH_SECS);
+    if usage_flush_secs == 0 {
+        eprintln!("Parameter 'usage.flush_seconds' must be greater than 0");
+        exit(1);
+    }
+    let usage_buffer_size = usage
+        .and_then(|u| u.get("buffer_size"))
+        .and_then(|size| size.as_u64())
+        .map(|size| size as usize)
+        .unwrap_or(USAGE_BUFFER_SIZE);
+    if usage_buffer_size == 0 {
+        eprintln!("Parameter 'usage.buffer_size' must be greater than 0");
+        exit(1);
+    }
+    let usage_retention_days = usage
+        .and_then(|u| u.get("retention_days"))
+        .and_then(|days| days.as_u64())
+        .unwrap_or(USAGE_RETENTION_DAYS);
+
+    let billing = config_json
+        .get("server")
+        .and_then(|server| server.get("billing"));
+    let billing_enabled = billing
+        .and_then(|b| b.get("enabled"))
+        .and_then(|e| e.as_bool())
+        .unwrap_or(false);
+    let billing_key_header = billing
+        .and_then(|b| b.get("key_header"))
+        .and_then(|h| h.as_str())
+        .unwrap_or(BILLING_KEY_HEADER)
+        .to_ascii_lowercase();
+    if hyper::header::HeaderName::from_bytes(billing_key_header.as_bytes()).is_err() {
+        eprintln!("Parameter 'billing.key_header' must be a name of HTTP header");
+        exit(1);
+    }
+    let mut billing_keys = HashMap::new();
+    if let Some(quotas) = billing.and_then(|b| b.get("quotas")) {
+        let Some(quotas) = quotas.as_object() else {
+            eprintln!("Parameter 'billing.quotas' must be an object of quotas by ids of API keys");
+            exit(1);
+        };
+        for (api_key, quota) in quotas {
+            match BillingQuota::from_json(quota) {
+                Ok(quota) => {
+                    billing_keys.insert(api_key.clone(), quota);
+                }
+                Err(err) => {
+                    eprintln!("Quota 'billing.quotas.{api_key}' is invalid: {err}");
+                    exit(1);
+                }
+            }
+        }
+    }
+    // quota of keys without their own quota and of requests without the key
+    let billing_default_quota = match billing.and_then(|b| b.get("default_quota")) {
+        None | Some(serde_json::Value::Null) => None,
+        Some(quota) => match BillingQuota::from_json(quota) {
+            Ok(quota) => Some(quota),
+            Err(err) => {
+                eprintln!("Quota 'billing.default_quota' is invalid: {err}");
+                exit(1);
+            }
+        },
+    };
+    let billing_quotas = BillingQuotas {
+        keys: billing_keys,
+        default: billing_default_quota,
+        reject_unknown_keys: billing
+            .and_then(|b| b.get("reject_unknown_keys"))
+            .and_then(|reject| reject.as_bool())
+            .unwrap_or(false),
+        max_keys: billing
+            .and_then(|b| b.get("max_keys"))
+            .and_then(|max_keys| max_keys.as_u64())
+            .map(|max_keys| max_keys.max(1) as usize)
+            .unwrap_or(BILLING_MAX_KEYS),
+    };
+
+    let trash = config_json
+        .get("server")
+        .and_then(|server| server.get("trash"));
+    let trash_retention_days = trash
+        .and_then(|t| t.get("retention_days"))
+        .and_then(|days| days.as_u64())
+        .unwrap_or(TRASH_RETENTION_DAYS);
+    let trash_interval_secs = trash
+        .and_then(|t| t.get("interval_seconds"))
+        .and_then(|secs| secs.as_u64())
+        .unwrap_or(TRASH_INTERVAL_SECS);
+    if trash_interval_secs == 0 {
+        eprintln!("Parameter 'trash.interval_seconds' must be greater than 0");
+        exit(1);
+    }
+
+    let registry = config_json
+        .get("server")
+        .and_then(|server| server.get("registry"));
+    let registry_type = registry
+        .and_then(|r| r.get("type"))
+        .and_then(|t| t.as_str())
+        .unwrap_or(REGISTRY_POSTGRES)
+        .to_string();
+    if registry_type != REGISTRY_POSTGRES && registry_type != REGISTRY_SQLITE {
+        eprintln!(
+            "Parameter 'registry.type' must be '{REGISTRY_POSTGRES}' or '{REGISTRY_SQLITE}', but got '{registry_type}'"
+        );
+        exit(1);
+    }
+    let registry_path = registry
+        .and_then(|r| r.get("path"))
+        .and_then(|p| p.as_str())
+        .unwrap_or(REGISTRY_SQLITE_PATH)
+        .to_string();
+
+    let database = config_json
+        .get("server")
+        .and_then(|server| server.get("database"));
+    let db_pool = DBPoolConfig {
+        min_connections: database
+            .and_then(|d| d.get("min_connections"))
+            .and_then(|n| n.as_u64())
+            .map(|n| n as u32)
+            .unwrap_or(DB_POOL_MIN_CONNECTIONS),
+        max_connections: database
+            .and_then(|d| d.get("max_connections"))
+            .and_then(|n| n.as_u64())
+            .map(|n| n as u32)
+            .unwrap_or(DB_POOL_MAX_CONNECTIONS),
+        acquire_timeout_secs: database
+            .and_then(|d| d.get("acquire_timeout_seconds"))
+            .and_then(|secs| secs.as_u64())
+            .unwrap_or(DB_ACQUIRE_TIMEOUT_SECS),
+        statement_timeout_ms: database
+            .and_then(|d| d.get("statement_timeout_ms"))
+            .and_then(|ms| ms.as_u64())
+            .unwrap_or(0),
+        sslmode: database
+            .and_then(|d| d.get("sslmode"))
+            .and_then(|mode| mode.as_str())
+            .map(|mode| mode.to_string()),
+        sslrootcert: database
+            .and_then(|d| d.get("sslrootcert"))
+            .and_then(|path| path.as_str())
+            .map(|path| path.to_string()),
+    };
+    if db_pool.max_connections == 0 || db_pool.min_connections > db_pool.max_connections {
+        eprintln!(
+            "Parameters 'database.min_connections' and 'database.max_connections' must satisfy 0 <= min <= max, max > 0"
+        );
+        exit(1);
+    }
+    if let Some(mode) = db_pool.sslmode.as_deref() {
+        if !DB_SSLMODES.contains(&mode) {
+            eprintln!(
+                "Parameter 'database.sslmode' must be one of {DB_SSLMODES:?}, but got '{mode}'"
+            );
+            exit(1);
+        }
+    }
+
     Ok(Config {
         type_server,
         master: false,
@@ -235,30 +1700,221 @@ pub async fn load_config() -> Result<Config, anyhow::Error> {
         worker_reload_repeat_attempts,
         terminate_childs_with_python,
         max_concurrent_tile_requests,
+        adaptive_concurrency,
+        batch_permits_share,
+        max_queue_depth,
+        queue_retry_after_secs,
+        circuit_breaker_failure_threshold,
+        circuit_breaker_open_secs,
+        worker_ready_quorum,
+        worker_readiness_path,
+        worker_launch,
+        worker_slots,
+        worker_restart,
+        worker_restart_backoff_secs,
+        worker_restart_max_backoff_secs,
+        worker_startup_timeout_secs,
+        worker_startup_kill,
+        tile_retries,
+        timeout_tile_response,
+        max_tile_size_kb,
+        worker_client_pool_max_idle_per_host,
+        worker_client_pool_idle_timeout_secs,
+        worker_client_keepalive_secs,
+        worker_client_http2,
+        cluster_secret,
+        cluster_master,
+        cluster_advertise_address,
+        cluster_heartbeat_secs,
+        cluster_node_timeout_secs,
+        cluster_consistent_hash,
+        cluster_max_hops,
+        cluster_allow_unauthenticated,
+        cluster_shard_timeout_secs,
+        static_root,
+        static_allowed_extensions,
+        static_index,
+        serve_cache,
+        tile_grids,
+        blank_tile_enabled,
+        blank_tile,
+        missing_tile,
+        negative_cache,
+        overzoom_maxzoom,
+        export_ttl_seconds,
+        pyramid_webhooks,
+        webhook_timeout_secs,
+        cors,
+        response_headers,
+        upstream_headers,
+        proxy,
+        otlp,
+        slow_request_ms,
+        max_body_size_kb,
+        upload_max_size_mb,
+        upload_expire_secs,
+        integrity,
+        openapi_swagger_ui,
+        mbtiles_maintenance_secs,
+        mbtiles_thresholds,
+        sqlite_pool_max_clients,
+        sqlite_pool_idle_secs,
+        quota_interval_secs,
+        storage_interval_secs,
+        storage_min_free_mb,
+        usage_enabled,
+        usage_flush_secs,
+        usage_buffer_size,
+        usage_retention_days,
+        billing_enabled,
+        billing_key_header,
+        billing_quotas,
+        trash_retention_days,
+        trash_interval_secs,
+        paths,
+        registry_type,
+        registry_path,
+        db_pool,
     })
 }
 
+// 'no_content', 'not_found' or 'blank'
+pub fn parse_missing_tile(value: &serde_json::Value) -> Option<MissingTile> {
+    value.as_str().and_then(|m| m.parse().ok())
+}
+
+// Color '#RRGGBB', '#RRGGBBAA' or array [r, g, b] / [r, g, b, a]
+fn parse_color(value: &serde_json::Value) -> Option<[u8; 4]> {
+    let mut rgba = [0, 0, 0, 255];
+    if let Some(hex) = value.as_str() {
+        let hex = hex.trim_start_matches('#');
+        if (hex.len() != 6 && hex.len() != 8) || !hex.is_ascii() {
+            return None;
+        }
+        for (i, channel) in rgba.iter_mut().enumerate().take(hex.len() / 2) {
+            *channel = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+        }
+    } else {
+        let channels = value.as_array()?;
+        if channels.len() != 3 && channels.len() != 4 {
+            return None;
+        }
+        for (channel, v) in rgba.iter_mut().zip(channels) {
+            *channel = u8::try_from(v.as_u64()?).ok()?;
+        }
+    }
+    Some(rgba)
+}
+
+// Backend of the config database selected by the scheme 'DBSCHEME' of its connection string
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DBBackend {
+    Postgres,
+    MySql,
+}
+
+impl DBBackend {
+    fn from_scheme(scheme: &str) -> Option<DBBackend> {
+        match scheme {
+            "postgres" | "postgresql" => Some(DBBackend::Postgres),
+            "mysql" | "mariadb" => Some(DBBackend::MySql),
+            _ => None,
+        }
+    }
+
+    fn scheme(&self) -> &'static str {
+        match self {
+            DBBackend::Postgres => "postgres",
+            DBBackend::MySql => "mysql",
+        }
+    }
+
+    fn default_port(&self) -> u16 {
+        match self {
+            DBBackend::Postgres => 5432,
+            DBBackend::MySql => 3306,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct DBConfig {
+    pub backend: DBBackend,
     pub host: String,
     pub port: String,
     pub name: String,
     pub user: String,
     pub pass: String,
+    // connection string of 'DATABASE_URL' with its parameters ('sslmode', ...)
+    pub url: Option<String>,
+}
+
+impl DBConfig {
+    pub fn connection_string(&self) -> String {
+        match &self.url {
+            Some(url) => url.clone(),
+            None => format!(
+                "{}://{}:{}@{}:{}/{}",
+                self.backend.scheme(),
+                self.user,
+                self.pass,
+                self.host,
+                self.port,
+                self.name,
+            ),
+        }
+    }
 }
 
 pub fn load_db_config() -> DBConfig {
+    // single connection string replaces the variables 'DB*', its scheme selects the backend
+    if let Ok(url) = std::env::var("DATABASE_URL") {
+        return db_config_from_url(url);
+    }
+
     let host = std::env::var("DBHOST").expect("Environment variable 'DBHOST' is undefined");
     let port = std::env::var("DBPORT").expect("Environment variable 'DBPORT' is undefined");
     let name = std::env::var("DBNAME").expect("Environment variable 'DBNAME' is undefined");
     let user = std::env::var("DBUSER").expect("Environment variable 'DBUSER' is undefined");
     let pass = std::env::var("DBPASS").expect("Environment variable 'DBPASS' is undefined");
+    let scheme = std::env::var("DBSCHEME").unwrap_or(DB_SCHEME.to_string());
+    let backend = DBBackend::from_scheme(&scheme).unwrap_or_else(|| {
+        panic!("Environment variable 'DBSCHEME' must be 'postgres' or 'mysql', got '{scheme}'")
+    });
 
     DBConfig {
+        backend,
         host,
         port,
         name,
         user,
         pass,
+        url: None,
+    }
+}
+
+fn db_config_from_url(url: String) -> DBConfig {
+    let parsed = url::Url::parse(&url)
+        .unwrap_or_else(|err| panic!("Environment variable 'DATABASE_URL' is invalid: {err}"));
+    let backend = DBBackend::from_scheme(parsed.scheme()).unwrap_or_else(|| {
+        panic!(
+            "Scheme of 'DATABASE_URL' must be 'postgres' or 'mysql', got '{}'",
+            parsed.scheme()
+        )
+    });
+    let decode = |value: &str| {
+        urlencoding::decode(value)
+            .map(|v| v.into_owned())
+            .unwrap_or(value.to_string())
+    };
+
+    DBConfig {
+        backend,
+        host: parsed.host_str().unwrap_or("localhost").to_string(),
+        port: parsed.port().unwrap_or(backend.default_port()).to_string(),
+        name: parsed.path().trim_start_matches('/').to_string(),
+        user: decode(parsed.username()),
+        pass: decode(parsed.password().unwrap_or_default()),
+        url: Some(url),
     }
 }
@@ -0,0 +1,238 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::anyhow;
+use rusqlite::{params, Connection, OpenFlags};
+use tiler_core::compression::{decode_stored_tile, encode_stored_tile, mbtiles_compressed};
+use tiler_core::integrity::{tile_checksum, TABLE_TILES_CHECKSUM};
+use tiler_core::mbtiles::mbtiles_client;
+use tiler_core::sqlite_clients::MessageSQLiteClient;
+
+use crate::grid::TileGrid;
+
+// Area and zoom levels of the offline package, ranges of tiles (XYZ) per zoom
+#[derive(Debug, Clone)]
+pub struct ExportArea {
+    pub bounds: [f64; 4],
+    pub minzoom: u8,
+    pub maxzoom: u8,
+    pub format: String,
+    pub ranges: Vec<ZoomRange>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ZoomRange {
+    pub z: u8,
+    pub min_x: u64,
+    pub min_y: u64,
+    pub max_x: u64,
+    pub max_y: u64,
+    // number of rows of the grid at zoom, for TMS rows of the package
+    pub rows: u64,
+}
+
+/*
+    MBTiles of the offline package assembled from the cache of the DataSource:
+    tiles of MBTiles of the cache first, then tiles saved as files (they are served first).
+    Rows of the package follow the MBTiles specification (TMS), the cache uses XYZ rows.
+    Returns the number of tiles in the package
+*/
+pub async fn export_mbtiles(
+    cache_mbtiles: Vec<PathBuf>,
+    tiles_dir: PathBuf,
+    package: PathBuf,
+    name: String,
+    area: ExportArea,
+) -> Result<u64, anyhow::Error> {
+    tokio::task::spawn_blocking(move || {
+        write_package(&cache_mbtiles, &tiles_dir, &package, &name, &area)
+    })
+    .await?
+}
+
+fn write_package(
+    cache_mbtiles: &[PathBuf],
+    tiles_dir: &Path,
+    package: &Path,
+    name: &str,
+    area: &ExportArea,
+) -> Result<u64, anyhow::Error> {
+    if package.exists() {
+        std::fs::remove_file(package)?;
+    }
+    let mut conn = Connection::open(package)?;
+    conn.execute_batch(
+        "CREATE TABLE metadata (name text, value text);
+         CREATE TABLE tiles (zoom_level integer, tile_column integer, tile_row integer, tile_data blob);
+         CREATE UNIQUE INDEX tile_index ON tiles (zoom_level, tile_column, tile_row);",
+    )?;
+
+    let [west, south, east, north] = area.bounds;
+    let metadata = [
+        ("name", name.to_string()),
+        ("format", area.format.clone()),
+        ("bounds", format!("{west},{south},{east},{north}")),
+        ("minzoom", area.minzoom.to_string()),
+        ("maxzoom", area.maxzoom.to_string()),
+        ("type", "baselayer".to_string()),
+    ];
+    for (key, value) in metadata {
+        conn.execute(
+            "INSERT INTO metadata (name, value) VALUES (?1, ?2);",
+            params![key, value],
+        )?;
+    }
+
+    let tx = conn.transaction()?;
+    for source in cache_mbtiles {
+        let source = Connection::open_with_flags(source, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+        let mut select = source.prepare(
+            "SELECT tile_column, tile_row, tile_data FROM tiles
+             WHERE zoom_level = ?1 AND tile_column BETWEEN ?2 AND ?3 AND tile_row BETWEEN ?4 AND ?5;",
+        )?;
+        for r in area.ranges.iter() {
+            let rows =
+                select.query_map(params![r.z, r.min_x, r.max_x, r.min_y, r.max_y], |row| {
+                    Ok((
+                        row.get::<_, u64>(0)?,
+                        row.get::<_, u64>(1)?,
+                        row.get::<_, Vec<u8>>(2)?,
+                    ))
+                })?;
+            for row in rows {
+                let (x, y, data) = row?;
+                // zero-length tiles are markers of the negative cache, not tiles of the package
+                if data.is_empty() {
+                    continue;
+                }
+                // packages are portable MBTiles, tiles compressed at rest are decompressed
+                let data = decode_stored_tile(data)?;
+                insert_tile(&tx, r, x, y, &data)?;
+            }
+        }
+    }
+
+    for r in area.ranges.iter() {
+        let zoom_dir = tiles_dir.join(r.z.to_string());
+        let Ok(columns) = std::fs::read_dir(&zoom_dir) else {
+            continue;
+        };
+        for column in columns.flatten() {
+            let x = match column
+                .file_name()
+                .to_str()
+                .and_then(|x| x.parse::<u64>().ok())
+            {
+                Some(x) if x >= r.min_x && x <= r.max_x => x,
+                _ => continue,
+            };
+            let Ok(files) = std::fs::read_dir(column.path()) else {
+                continue;
+            };
+            for file in files.flatten() {
+                let path = file.path();
+                if path.extension().and_then(|ext| ext.to_str()) != Some(area.format.as_str()) {
+                    continue;
+                }
+                // '{y}@2x.png' and other scaled tiles are not parsed
+                let y = match path
+                    .file_stem()
+                    .and_then(|y| y.to_str())
+                    .and_then(|y| y.parse::<u64>().ok())
+                {
+                    Some(y) if y >= r.min_y && y <= r.max_y => y,
+                    _ => continue,
+                };
+                let data = std::fs::read(&path)?;
+                if !data.is_empty() {
+                    insert_tile(&tx, r, x, y, &data)?;
+                }
+            }
+        }
+    }
+    tx.commit()?;
+
+    let count = conn
+        .query_row("SELECT COUNT(*) FROM tiles;", [], |row| {
+            row.get::<_, u64>(0)
+        })
+        .map_err(|err| anyhow!("Error count tiles of package {package:?}: {err}"))?;
+    Ok(count)
+}
+
+fn insert_tile(
+    conn: &Connection,
+    range: &ZoomRange,
+    x: u64,
+    y: u64,
+    data: &[u8],
+) -> Result<(), anyhow::Error> {
+    let tms_row = range.rows - 1 - y;
+    conn.execute(
+        "INSERT OR REPLACE INTO tiles (zoom_level, tile_column, tile_row, tile_data) VALUES (?1, ?2, ?3, ?4);",
+        params![range.z, x, tms_row, data],
+    )?;
+    Ok(())
+}
+
+/*
+    Tiles of the package (TMS rows) are merged into MBTiles of the cache (XYZ rows) by its writer,
+    tiles of the cache are replaced. In MBTiles with compression tiles are stored compressed,
+    checksums are written in integrity mode. Returns the number of merged tiles
+*/
+pub async fn import_package(
+    package: PathBuf,
+    mbtiles_db: &PathBuf,
+    grid: TileGrid,
+    tx_sqlite_client: flume::Sender<MessageSQLiteClient>,
+    integrity: bool,
+) -> Result<u64, anyhow::Error> {
+    let client = mbtiles_client(mbtiles_db, false, tx_sqlite_client).await?;
+    let merged = client
+        .conn(move |connection| {
+            let source = Connection::open_with_flags(&package, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+            let mut select = source
+                .prepare("SELECT zoom_level, tile_column, tile_row, tile_data FROM tiles;")?;
+            let rows = select.query_map([], |row| {
+                Ok((
+                    row.get::<_, u8>(0)?,
+                    row.get::<_, u64>(1)?,
+                    row.get::<_, u64>(2)?,
+                    row.get::<_, Vec<u8>>(3)?,
+                ))
+            })?;
+
+            let tx = connection.unchecked_transaction()?;
+            let compressed = mbtiles_compressed(&tx)?;
+            if integrity {
+                tx.execute(TABLE_TILES_CHECKSUM, ())?;
+            }
+            let mut merged = 0;
+            for row in rows {
+                let (z, x, tms_row, data) = row?;
+                let Some(y) = (grid.matrix_size(z).1 - 1).checked_sub(tms_row) else {
+                    continue;
+                };
+                let data = if compressed {
+                    encode_stored_tile(&data)
+                        .map_err(|err| rusqlite::Error::ToSqlConversionFailure(Box::new(err)))?
+                } else {
+                    data
+                };
+                tx.execute(
+                    "INSERT OR REPLACE INTO tiles (zoom_level, tile_column, tile_row, tile_data) VALUES (?1, ?2, ?3, ?4);",
+                    params![z, x, y, data],
+                )?;
+                if integrity {
+                    tx.execute(
+                        "INSERT OR REPLACE INTO tiles_checksum (zoom_level, tile_column, tile_row, xxhash) VALUES (?1, ?2, ?3, ?4);",
+                        params![z, x, y, tile_checksum(&data)],
+                    )?;
+                }
+                merged += 1;
+            }
+            tx.commit()?;
+            Ok(merged)
+        })
+        .await?;
+    Ok(merged)
+}
@@ -0,0 +1,105 @@
+use std::sync::Mutex;
+
+use ulid::Ulid;
+use uuid::Uuid;
+
+use crate::db::job::queue::{Job, JobDetail, Queue};
+use crate::defaults::JOB_CONCURRENCY;
+
+const MAX_FAILED_ATTEMPTS: u32 = 3; // same as in the PostgreSQL queue
+
+#[derive(Debug, Clone, PartialEq)]
+enum MemoryJobStatus {
+    Queued,
+    Running,
+}
+
+#[derive(Debug, Clone)]
+struct MemoryJob {
+    job_id: Uuid,
+    scheduled_for: chrono::DateTime<chrono::Utc>,
+    failed_attempts: u32,
+    status: MemoryJobStatus,
+    job_detail: JobDetail,
+}
+
+// Queue of jobs of the process without the config database, jobs are lost on exit
+#[derive(Debug, Default)]
+pub struct MemoryQueue {
+    jobs: Mutex<Vec<MemoryJob>>,
+}
+
+impl MemoryQueue {
+    pub fn new() -> MemoryQueue {
+        MemoryQueue::default()
+    }
+}
+
+#[async_trait::async_trait]
+impl Queue for MemoryQueue {
+    async fn push(
+        &self,
+        job_detail: JobDetail,
+        date: Option<chrono::DateTime<chrono::Utc>>,
+    ) -> Result<(), crate::db::error::Error> {
+        let job = MemoryJob {
+            job_id: Ulid::new().into(),
+            scheduled_for: date.unwrap_or(chrono::Utc::now()),
+            failed_attempts: 0,
+            status: MemoryJobStatus::Queued,
+            job_detail,
+        };
+        self.jobs.lock().unwrap().push(job);
+        Ok(())
+    }
+
+    async fn delete_job(&self, job_id: Uuid) -> Result<(), crate::db::error::Error> {
+        self.jobs.lock().unwrap().retain(|job| job.job_id != job_id);
+        Ok(())
+    }
+
+    async fn fail_job(&self, job_id: Uuid) -> Result<(), crate::db::error::Error> {
+        let mut jobs = self.jobs.lock().unwrap();
+        if let Some(job) = jobs.iter_mut().find(|job| job.job_id == job_id) {
+            job.status = MemoryJobStatus::Queued;
+            job.failed_attempts += 1;
+        }
+        Ok(())
+    }
+
+    async fn pull(&self, number_of_jobs: Option<i32>) -> Result<Vec<Job>, crate::db::error::Error> {
+        let mut nj = JOB_CONCURRENCY as i32;
+        if let Some(v) = number_of_jobs {
+            nj = v
+        }
+        let now = chrono::Utc::now();
+
+        let mut jobs = self.jobs.lock().unwrap();
+        let mut due: Vec<&mut MemoryJob> = jobs
+            .iter_mut()
+            .filter(|job| {
+                job.status == MemoryJobStatus::Queued
+                    && job.scheduled_for <= now
+                    && job.failed_attempts < MAX_FAILED_ATTEMPTS
+            })
+            .collect();
+        due.sort_by_key(|job| job.scheduled_for);
+
+        Ok(due
+            .into_iter()
+            .take(nj.max(0) as usize)
+            .map(|job| {
+                job.status = MemoryJobStatus::Running;
+                Job {
+                    job_id: job.job_id,
+                    detail: job.job_detail.clone(),
+                }
+            })
+            .collect())
+    }
+
+    async fn clear(&self) -> Result<(), crate::db::error::Error> {
+        self.jobs.lock().unwrap().clear();
+        Ok(())
+    }
+}
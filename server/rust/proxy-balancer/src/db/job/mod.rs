@@ -1,3 +1,6 @@
+pub mod memory;
+#[allow(unused)]
+pub mod mysql;
 #[allow(unused)]
 pub mod postgres;
 pub mod queue;
@@ -0,0 +1,240 @@
+use chrono;
+use sqlx::{mysql::MySqlRow, types::Json, Row};
+use ulid::Ulid;
+use uuid::Uuid;
+
+use crate::db::{
+    job::queue::{Job, JobDetail, Queue},
+    MySqlDB,
+};
+use crate::defaults::JOB_CONCURRENCY;
+
+#[derive(Debug, Clone)]
+pub struct MySqlQueue {
+    db: MySqlDB,
+    max_attempts: u32,
+}
+
+const MAX_FAILED_ATTEMPTS: i32 = 3; // low, as most jobs also use retries internally
+
+#[derive(Debug, Clone)]
+struct MySqlJob {
+    job_id: Uuid,
+    created_at: chrono::DateTime<chrono::Utc>,
+    updated_at: chrono::DateTime<chrono::Utc>,
+
+    scheduled_for: chrono::DateTime<chrono::Utc>,
+    failed_attempts: u32,
+    status: MySqlJobStatus,
+    job_detail: Json<JobDetail>,
+}
+
+impl<'r> sqlx::FromRow<'r, MySqlRow> for MySqlJob {
+    fn from_row(row: &'r MySqlRow) -> Result<MySqlJob, sqlx::Error> {
+        let uuid: String = row.try_get("job_id")?;
+        let job_id = Uuid::parse_str(&uuid).map_err(|err| sqlx::Error::Decode(Box::new(err)))?;
+        let created_at: chrono::DateTime<chrono::Utc> = row.try_get("created_at")?;
+        let updated_at: chrono::DateTime<chrono::Utc> = row.try_get("updated_at")?;
+        let scheduled_for: chrono::DateTime<chrono::Utc> = row.try_get("scheduled_for")?;
+        let failed_attempts: i32 = row.try_get("failed_attempts")?;
+        let status: MySqlJobStatus = row.try_get("status")?;
+        let job_detail: Json<JobDetail> = row.try_get("job_detail")?;
+
+        Ok(MySqlJob {
+            job_id,
+            created_at,
+            updated_at,
+            scheduled_for,
+            failed_attempts: failed_attempts as u32,
+            status,
+            job_detail,
+        })
+    }
+}
+
+// Same INT representation as in the PostgreSQL queue
+#[derive(Debug, Clone, sqlx::Type, PartialEq)]
+#[repr(i32)]
+enum MySqlJobStatus {
+    Queued,
+    Running,
+    Failed,
+}
+
+impl From<MySqlJob> for Job {
+    fn from(mj: MySqlJob) -> Self {
+        Job {
+            job_id: mj.job_id,
+            detail: mj.job_detail.0,
+        }
+    }
+}
+
+impl MySqlQueue {
+    pub fn new(db: MySqlDB) -> MySqlQueue {
+        MySqlQueue {
+            db,
+            max_attempts: 5,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Queue for MySqlQueue {
+    async fn push(
+        &self,
+        job_detail: JobDetail,
+        date: Option<chrono::DateTime<chrono::Utc>>,
+    ) -> Result<(), crate::db::error::Error> {
+        let scheduled_for = date.unwrap_or(chrono::Utc::now());
+        let failed_attempts: i32 = 0;
+        let job_detail = Json(job_detail);
+        let status = MySqlJobStatus::Queued;
+        let now = chrono::Utc::now();
+        let job_id: Uuid = Ulid::new().into();
+        let query = "INSERT INTO queue
+          (job_id, created_at, updated_at, scheduled_for, failed_attempts, status, job_detail)
+          VALUES (?, ?, ?, ?, ?, ?, ?)";
+
+        let mut transaction = self.db.begin().await?;
+        match sqlx::query(query)
+            .bind(job_id.to_string())
+            .bind(now)
+            .bind(now)
+            .bind(scheduled_for)
+            .bind(failed_attempts)
+            .bind(status)
+            .bind(job_detail)
+            .execute(&mut *transaction)
+            .await
+        {
+            Err(err) => {
+                transaction.rollback().await?;
+                return Err(err.into());
+            }
+            Ok(jobs) => {
+                transaction.commit().await?;
+                jobs
+            }
+        };
+
+        Ok(())
+    }
+
+    async fn delete_job(&self, job_id: Uuid) -> Result<(), crate::db::error::Error> {
+        let query = "DELETE FROM queue WHERE job_id = ?";
+
+        let mut transaction = self.db.begin().await?;
+        match sqlx::query(query)
+            .bind(job_id.to_string())
+            .execute(&mut *transaction)
+            .await
+        {
+            Err(err) => {
+                transaction.rollback().await?;
+                return Err(err.into());
+            }
+            Ok(jobs) => {
+                transaction.commit().await?;
+                jobs
+            }
+        };
+
+        Ok(())
+    }
+
+    async fn fail_job(&self, job_id: Uuid) -> Result<(), crate::db::error::Error> {
+        let now = chrono::Utc::now();
+        let query = "UPDATE queue
+          SET status = ?, updated_at = ?, failed_attempts = failed_attempts + 1
+          WHERE job_id = ?";
+
+        let mut transaction = self.db.begin().await?;
+        match sqlx::query(query)
+            .bind(MySqlJobStatus::Queued)
+            .bind(now)
+            .bind(job_id.to_string())
+            .execute(&mut *transaction)
+            .await
+        {
+            Err(err) => {
+                transaction.rollback().await?;
+                return Err(err.into());
+            }
+            Ok(jobs) => {
+                transaction.commit().await?;
+                jobs
+            }
+        };
+
+        Ok(())
+    }
+
+    /*
+        MySQL has no 'UPDATE ... RETURNING': jobs are locked by 'SELECT ... FOR UPDATE SKIP LOCKED'
+        (MySQL 8.0+, MariaDB 10.6+) and marked as running in the same transaction
+    */
+    async fn pull(&self, number_of_jobs: Option<i32>) -> Result<Vec<Job>, crate::db::error::Error> {
+        let mut nj = JOB_CONCURRENCY as i32;
+        if let Some(v) = number_of_jobs {
+            nj = v
+        }
+        let now = chrono::Utc::now();
+        let query_select = "SELECT job_id, created_at, updated_at, scheduled_for, failed_attempts, status, job_detail
+          FROM queue
+          WHERE status = ? AND scheduled_for <= ? AND failed_attempts < ?
+          ORDER BY scheduled_for
+          LIMIT ?
+          FOR UPDATE SKIP LOCKED";
+        let query_update = "UPDATE queue SET status = ?, updated_at = ? WHERE job_id = ?";
+
+        let mut transaction = self.db.begin().await?;
+        let jobs: Vec<MySqlJob> = match sqlx::query_as::<_, MySqlJob>(query_select)
+            .bind(MySqlJobStatus::Queued)
+            .bind(now)
+            .bind(MAX_FAILED_ATTEMPTS)
+            .bind(nj)
+            .fetch_all(&mut *transaction)
+            .await
+        {
+            Err(err) => {
+                transaction.rollback().await?;
+                return Err(err.into());
+            }
+            Ok(jobs) => jobs,
+        };
+        for job in &jobs {
+            if let Err(err) = sqlx::query(query_update)
+                .bind(MySqlJobStatus::Running)
+                .bind(now)
+                .bind(job.job_id.to_string())
+                .execute(&mut *transaction)
+                .await
+            {
+                transaction.rollback().await?;
+                return Err(err.into());
+            }
+        }
+        transaction.commit().await?;
+
+        Ok(jobs.into_iter().map(Into::into).collect())
+    }
+
+    async fn clear(&self) -> Result<(), crate::db::error::Error> {
+        let query = "DELETE FROM queue";
+
+        let mut transaction = self.db.begin().await?;
+        match sqlx::query(query).execute(&mut *transaction).await {
+            Err(err) => {
+                transaction.rollback().await?;
+                return Err(err.into());
+            }
+            Ok(jobs) => {
+                transaction.commit().await?;
+                jobs
+            }
+        };
+
+        Ok(())
+    }
+}
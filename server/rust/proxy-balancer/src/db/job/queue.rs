@@ -1,4 +1,5 @@
 use std::fmt::Debug;
+use std::sync::Arc;
 
 use serde::{Deserialize, Serialize};
 use strum_macros::{AsRefStr, Display, EnumString, IntoStaticStr};
@@ -18,6 +19,8 @@ pub trait Queue: Send + Sync + Debug {
     async fn clear(&self) -> Result<(), crate::db::error::Error>;
 }
 
+pub type SharedQueue = Arc<dyn Queue>;
+
 #[derive(
     Debug, Clone, Serialize, Deserialize, PartialEq, Display, EnumString, IntoStaticStr, AsRefStr,
 )]
@@ -26,6 +29,11 @@ pub enum JobType {
     #[strum(serialize = "pyramid")]
     Pyramid { datasource_id: String },
 
+    // tiles of the changed area are created again, parameters are in 'data' of the job
+    #[serde(rename = "delta_pyramid")]
+    #[strum(serialize = "delta_pyramid")]
+    DeltaPyramid { datasource_id: String },
+
     #[serde(rename = "calculation")]
     #[strum(serialize = "calculation")]
     Calculation,
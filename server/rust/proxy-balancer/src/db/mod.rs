@@ -1,28 +1,56 @@
 pub mod error;
+pub mod export;
 pub mod job;
+pub mod registry;
 
 use std::path::PathBuf;
 use std::str::FromStr;
+use std::time::Duration;
 
 use anyhow::anyhow;
 use async_sqlite::{ClientBuilder, JournalMode};
 use hyper::{Body, Response};
 use rusqlite::{named_params, OpenFlags};
 use sqlx::{
+    migrate::{Migrate, Migrator},
+    mysql::{MySql, MySqlConnectOptions, MySqlPoolOptions},
     postgres::Postgres,
-    postgres::{PgConnectOptions, PgPoolOptions},
-    ConnectOptions, Connection, PgConnection, Pool, Row,
+    postgres::{PgConnectOptions, PgPoolOptions, PgSslMode},
+    ConnectOptions, Connection, MySqlConnection, PgConnection, Pool, Row,
 };
+use tiler_core::compression::{METADATA_TILE_COMPRESSION, TILE_COMPRESSION_ZSTD};
+use tiler_core::integrity::{remove_mbtile, TABLE_TILES_CHECKSUM};
+use tiler_core::mbtiles::{mbtiles_client, read_mbtile};
+use tiler_core::sqlite_clients::MessageSQLiteClient;
 use tokio::sync::oneshot;
 use tracing::{event, Level};
 
-use crate::config::{load_db_config, DBConfig};
-use crate::tasks::sqlite_clients::MessageSQLiteClient;
+use crate::config::{load_db_config, DBBackend, DBConfig, DBPoolConfig};
+use crate::paths::Paths;
 use crate::utils::tile_response;
 
 pub type DB = Pool<Postgres>;
+pub type MySqlDB = Pool<MySql>;
 
-pub async fn create_pg_config_db(db_config: &DBConfig) -> Result<(), anyhow::Error> {
+// Pool of the config database, the backend is selected by 'DBSCHEME'
+#[derive(Debug, Clone)]
+pub enum DBPool {
+    Postgres(DB),
+    MySql(MySqlDB),
+}
+
+impl DBPool {
+    // the registry 'postgres' of DataSources is kept only in PostgreSQL
+    pub fn postgres(&self) -> Option<DB> {
+        match self {
+            DBPool::Postgres(db) => Some(db.clone()),
+            DBPool::MySql(_) => None,
+        }
+    }
+}
+
+// true - the database was created, false - it already exists
+pub async fn create_pg_config_db(db_config: &DBConfig) -> Result<bool, anyhow::Error> {
     let db = &db_config.name;
     let sql = format!("SELECT 'CREATE DATABASE {db};' WHERE NOT EXISTS (SELECT FROM pg_database WHERE datname = '{db}')");
     let mut conn = match connection_pg_postgres(&db_config).await {
@@ -33,77 +61,153 @@ pub async fn create_pg_config_db(db_config: &DBConfig) -> Result<(), anyhow::Err
     };
 
     let result = sqlx::query(&sql).fetch_optional(&mut conn).await?;
+    let created = result.is_some();
     if let Some(row) = result {
         let sql_create_db: String = row.get(0);
         sqlx::query(&sql_create_db).execute(&mut conn).await?;
     }
     conn.close().await?;
 
-    Ok(())
+    Ok(created)
 }
 
 // Connect to DataBase 'postgres'
 pub async fn connection_pg_postgres(db_config: &DBConfig) -> Result<PgConnection, anyhow::Error> {
-    let conn_str = format!(
-        "postgres://{}:{}@{}:{}/{}",
-        db_config.user, db_config.pass, db_config.host, db_config.port, "postgres",
-    );
-    let conn = PgConnection::connect(&conn_str).await?;
+    let options = PgConnectOptions::from_str(&db_config.connection_string())?.database("postgres");
+    let conn = PgConnection::connect_with(&options).await?;
     Ok(conn)
 }
 
 // Connect to DataBase from Config
 pub async fn connection_pg_config_db(db_config: &DBConfig) -> Result<PgConnection, anyhow::Error> {
-    let conn_str = format!(
-        "postgres://{}:{}@{}:{}/{}",
-        db_config.user, db_config.pass, db_config.host, db_config.port, db_config.name,
-    );
-    let conn = PgConnection::connect(&conn_str).await?;
+    let conn = PgConnection::connect(&db_config.connection_string()).await?;
     Ok(conn)
 }
 
-pub async fn init_pg_db(cwd: &str, mut conn: PgConnection) -> Result<PgConnection, anyhow::Error> {
-    let queries_path: PathBuf = [cwd, "sql", "0_init.sql"].iter().collect();
-    let path = queries_path
-        .to_str()
-        .ok_or(anyhow!("Error convert 'sql' directiry path"))?;
-    let queries = rawsql::Loader::read_queries_from(path)?;
-
-    sqlx::query(
-        queries
-            .get("create-table-datasource")
-            .ok_or(anyhow!("SQL script 'create-table-datasource' not found"))?,
-    )
-    .execute(&mut conn)
-    .await?;
-
-    sqlx::query(
-        queries
-            .get("create-table-queue")
-            .ok_or(anyhow!("SQL script 'create-table-queue' not found"))?,
-    )
-    .execute(&mut conn)
-    .await?;
-    sqlx::query(
-        queries
-            .get("create-index_queue_on_scheduled_for")
-            .ok_or(anyhow!(
-                "SQL script 'create-index_queue_on_scheduled_for' not found"
-            ))?,
-    )
-    .execute(&mut conn)
-    .await?;
-    sqlx::query(queries.get("create-index_queue_on_status").ok_or(anyhow!(
-        "SQL script 'create-index_queue_on_status' not found"
-    ))?)
-    .execute(&mut conn)
-    .await?;
+// true - the database was created, false - it already exists
+pub async fn create_mysql_config_db(db_config: &DBConfig) -> Result<bool, anyhow::Error> {
+    // connect to the server without a database
+    let options = MySqlConnectOptions::new()
+        .host(&db_config.host)
+        .port(db_config.port.parse()?)
+        .username(&db_config.user)
+        .password(&db_config.pass);
+    let mut conn = MySqlConnection::connect_with(&options).await?;
+    let sql = format!("CREATE DATABASE IF NOT EXISTS `{}`", db_config.name);
+    let result = sqlx::query(&sql).execute(&mut conn).await?;
+    conn.close().await?;
 
+    Ok(result.rows_affected() > 0)
+}
+
+// Connect to MySQL DataBase from Config
+pub async fn connection_mysql_config_db(
+    db_config: &DBConfig,
+) -> Result<MySqlConnection, anyhow::Error> {
+    let conn = MySqlConnection::connect(&db_config.connection_string()).await?;
     Ok(conn)
 }
 
-pub async fn init_db(cwd: &str) -> Result<(), anyhow::Error> {
-    let db = db_tiler(cwd)?;
+/*
+    Versioned migrations of the config database embedded into the binary,
+    MySQL keeps only the job queue, DataSources are kept by the registry 'sqlite'
+*/
+static PG_MIGRATOR: Migrator = sqlx::migrate!("migrations/postgres");
+static MYSQL_MIGRATOR: Migrator = sqlx::migrate!("migrations/mysql");
+
+pub async fn migrate_pg_db(mut conn: PgConnection) -> Result<PgConnection, anyhow::Error> {
+    PG_MIGRATOR.run(&mut conn).await?;
+    Ok(conn)
+}
+
+pub async fn migrate_mysql_db(mut conn: MySqlConnection) -> Result<MySqlConnection, anyhow::Error> {
+    MYSQL_MIGRATOR.run(&mut conn).await?;
+    Ok(conn)
+}
+
+// Versions of migrations applied by 'migrate_pg_db'
+pub async fn pending_pg_migrations(conn: &mut PgConnection) -> Result<Vec<i64>, anyhow::Error> {
+    pending_migrations(conn, &PG_MIGRATOR).await
+}
+
+pub async fn pending_mysql_migrations(
+    conn: &mut MySqlConnection,
+) -> Result<Vec<i64>, anyhow::Error> {
+    pending_migrations(conn, &MYSQL_MIGRATOR).await
+}
+
+/*
+    Objects created by migrations are dropped together with the history of migrations,
+    so 'migrate_pg_db' recreates the schema from the first migration.
+    Other tables of the database are kept
+*/
+pub async fn drop_pg_schema(conn: &mut PgConnection) -> Result<(), anyhow::Error> {
+    sqlx::query("DROP TABLE IF EXISTS datasource, queue, _sqlx_migrations CASCADE")
+        .execute(&mut *conn)
+        .await?;
+    sqlx::query("DROP FUNCTION IF EXISTS notify_datasource_changed() CASCADE")
+        .execute(&mut *conn)
+        .await?;
+    Ok(())
+}
+
+pub async fn drop_mysql_schema(conn: &mut MySqlConnection) -> Result<(), anyhow::Error> {
+    sqlx::query("DROP TABLE IF EXISTS queue, _sqlx_migrations")
+        .execute(&mut *conn)
+        .await?;
+    Ok(())
+}
+
+// Versions of the embedded migrations not applied to the database
+async fn pending_migrations<C: Migrate>(
+    conn: &mut C,
+    migrator: &Migrator,
+) -> Result<Vec<i64>, anyhow::Error> {
+    conn.ensure_migrations_table().await?;
+    if let Some(version) = conn.dirty_version().await? {
+        return Err(anyhow!(
+            "migration {version} of the config database is partially applied"
+        ));
+    }
+    let applied = conn.list_applied_migrations().await?;
+    let mut pending = Vec::new();
+    for migration in migrator.iter() {
+        match applied.iter().find(|a| a.version == migration.version) {
+            Some(a) if a.checksum != migration.checksum => {
+                return Err(anyhow!(
+                    "migration {} of the config database was modified after it was applied",
+                    migration.version
+                ));
+            }
+            Some(_) => {}
+            None => pending.push(migration.version),
+        }
+    }
+    Ok(pending)
+}
+
+// Schema of the config database must be migrated by the command 'init' before 'serve'
+pub async fn check_migrations(pool: &DBPool) -> Result<(), anyhow::Error> {
+    let pending = match pool {
+        DBPool::Postgres(db) => {
+            let mut conn = db.acquire().await?;
+            pending_migrations(&mut *conn, &PG_MIGRATOR).await?
+        }
+        DBPool::MySql(db) => {
+            let mut conn = db.acquire().await?;
+            pending_migrations(&mut *conn, &MYSQL_MIGRATOR).await?
+        }
+    };
+    if !pending.is_empty() {
+        return Err(anyhow!(
+            "Config database has pending migrations {pending:?}, run the command 'init'"
+        ));
+    }
+    Ok(())
+}
+
+pub async fn init_db(paths: &Paths) -> Result<(), anyhow::Error> {
+    let db = db_tiler(paths)?;
     if let Err(_) = tokio::fs::metadata(&db).await {
         let client = ClientBuilder::new()
             .path(&db)
@@ -160,14 +264,14 @@ pub async fn init_db(cwd: &str) -> Result<(), anyhow::Error> {
     Ok(())
 }
 
-pub fn db_tiler(cwd: &str) -> Result<PathBuf, anyhow::Error> {
-    let db_tiler: PathBuf = [cwd, "data", "tiler.db"].iter().collect();
+pub fn db_tiler(paths: &Paths) -> Result<PathBuf, anyhow::Error> {
+    let db_tiler = paths.tiler_db();
 
     Ok(db_tiler)
 }
 
-pub async fn check_running_pyramids(cwd: &str) -> Result<bool, anyhow::Error> {
-    let db = db_tiler(cwd)?;
+pub async fn check_running_pyramids(paths: &Paths) -> Result<bool, anyhow::Error> {
+    let db = db_tiler(paths)?;
     let client = ClientBuilder::new()
         .path(&db)
         .journal_mode(JournalMode::Wal)
@@ -201,14 +305,129 @@ pub async fn check_running_pyramids(cwd: &str) -> Result<bool, anyhow::Error> {
     Ok(running)
 }
 
-pub async fn get_mbtile(
+// Finish time of the last complete pyramid of the DataSource
+pub async fn last_pyramid_time(
+    paths: &Paths,
+    datasource_id: &str,
+) -> Result<Option<String>, anyhow::Error> {
+    let db = db_tiler(paths)?;
+    let client = ClientBuilder::new()
+        .path(&db)
+        .journal_mode(JournalMode::Wal)
+        .flags(OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .open()
+        .await
+        .map_err(|err| anyhow!(err))?;
+
+    let datasource_id = datasource_id.to_string();
+    let finish_time = client
+        .conn(move |connection| {
+            connection.query_row(
+                "SELECT CAST(MAX(finish_time) AS TEXT) FROM pyramids WHERE datasource_id = :ds AND complete = 1;",
+                named_params! {":ds": datasource_id},
+                |row| row.get::<_, Option<String>>(0),
+            )
+        })
+        .await?;
+
+    if let Err(err) = client.close().await {
+        event!(
+            Level::ERROR,
+            "Error close connection to Tiler DataBase: {}",
+            err.to_string()
+        );
+    }
+
+    Ok(finish_time)
+}
+
+/*
+    State of the pyramid from 'tiler.db': None while the pyramid is running,
+    then whether it is complete (the pyramid missing in 'tiler.db' is not complete)
+*/
+pub async fn pyramid_state(paths: &Paths, pyramid_id: &str) -> Result<Option<bool>, anyhow::Error> {
+    let db = db_tiler(paths)?;
+    let client = ClientBuilder::new()
+        .path(&db)
+        .journal_mode(JournalMode::Wal)
+        .flags(OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .open()
+        .await
+        .map_err(|err| anyhow!(err))?;
+
+    let pyramid_id = pyramid_id.to_string();
+    let state = client
+        .conn(move |connection| {
+            match connection.query_row(
+                "SELECT running, complete FROM pyramids WHERE id = :id;",
+                named_params! {":id": pyramid_id},
+                |row| Ok((row.get::<_, Option<i64>>(0)?, row.get::<_, Option<i64>>(1)?)),
+            ) {
+                Ok((Some(1), _)) => Ok(None),
+                Ok((_, complete)) => Ok(Some(complete == Some(1))),
+                Err(rusqlite::Error::QueryReturnedNoRows) => Ok(Some(false)),
+                Err(err) => Err(err),
+            }
+        })
+        .await?;
+
+    if let Err(err) = client.close().await {
+        event!(
+            Level::ERROR,
+            "Error close connection to Tiler DataBase: {}",
+            err.to_string()
+        );
+    }
+
+    Ok(state)
+}
+
+// Rows 'name' - 'value' of the table 'metadata' of MBTiles
+pub async fn get_mbtiles_metadata(
     mbtiles_db: &PathBuf,
-    z: u64,
-    x: u64,
-    y: u64,
-    content_type: &str,
     tx_sqlite_client: flume::Sender<MessageSQLiteClient>,
-) -> Result<Option<Response<Body>>, anyhow::Error> {
+) -> Result<Vec<(String, String)>, anyhow::Error> {
+    let sqlite_client = mbtiles_client(mbtiles_db, true, tx_sqlite_client).await?;
+    let metadata = sqlite_client
+        .conn(|connection| {
+            let mut stmt = connection.prepare("SELECT name, value FROM metadata;")?;
+            let rows = stmt.query_map([], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            })?;
+            rows.collect::<Result<Vec<_>, _>>()
+        })
+        .await?;
+    Ok(metadata)
+}
+
+// Rows of the table 'metadata' are replaced by name, other rows are kept
+pub async fn write_mbtiles_metadata(
+    mbtiles_db: &PathBuf,
+    metadata: Vec<(&'static str, String)>,
+    tx_sqlite_client: flume::Sender<MessageSQLiteClient>,
+) -> Result<(), anyhow::Error> {
+    let sqlite_client = mbtiles_client(mbtiles_db, false, tx_sqlite_client).await?;
+    sqlite_client
+        .conn(move |connection| {
+            let tx = connection.unchecked_transaction()?;
+            for (name, value) in metadata.iter() {
+                tx.execute("DELETE FROM metadata WHERE name = ?1;", [name])?;
+                tx.execute(
+                    "INSERT INTO metadata (name, value) VALUES (?1, ?2);",
+                    [name, value.as_str()],
+                )?;
+            }
+            tx.commit()
+        })
+        .await?;
+    Ok(())
+}
+
+// Number of tiles per zoom level in MBTiles
+pub async fn get_mbtiles_zoom_counts(
+    mbtiles_db: &PathBuf,
+    tx_sqlite_client: flume::Sender<MessageSQLiteClient>,
+) -> Result<Vec<(u8, u64)>, anyhow::Error> {
     let (tx_client, rx_client) = oneshot::channel::<Option<async_sqlite::Client>>();
     tx_sqlite_client
         .send_async(MessageSQLiteClient::GetSQLiteClient {
@@ -217,58 +436,105 @@ pub async fn get_mbtile(
                 .into_os_string()
                 .into_string()
                 .map_err(|err| anyhow!("{err:?}"))?,
+            read_only: true,
             tx_client,
         })
         .await?;
 
-    if let Some(sqlite_client) = rx_client.await? {
-        let tile_data: Result<Vec<u8>, _> = sqlite_client
-                .conn(move |connection| {
-                    connection.query_row("SELECT tile_data FROM tiles WHERE zoom_level = (?) AND tile_column = (?) AND tile_row = (?) LIMIT 1;", [z, x, y], |row| row.get(0))
-                })
-                .await;
+    let sqlite_client = rx_client.await?.ok_or(anyhow!(
+        "Error get SQLite client for MBTiles {mbtiles_db:?}"
+    ))?;
+    let counts = sqlite_client
+        .conn(|connection| {
+            let mut stmt = connection.prepare(
+                "SELECT zoom_level, COUNT(*) FROM tiles GROUP BY zoom_level ORDER BY zoom_level;",
+            )?;
+            let rows =
+                stmt.query_map([], |row| Ok((row.get::<_, u8>(0)?, row.get::<_, u64>(1)?)))?;
+            rows.collect::<Result<Vec<_>, _>>()
+        })
+        .await?;
+    Ok(counts)
+}
 
-        match tile_data {
-            Ok(tile) => {
-                let response = tile_response(tile, content_type)?;
-                return Ok(Some(response));
-            }
-            Err(async_sqlite::Error::Rusqlite(rusqlite::Error::QueryReturnedNoRows)) => {
-                // Tile missing
-                return Ok(None);
-            }
-            Err(err) => {
-                event!(
-                    Level::ERROR,
-                    "Error select tile from {mbtiles_db:?}: {err:?}"
-                );
-                return Ok(None);
+/*
+    Eviction of the quota: up to 'limit' tiles of the zoom are removed in the order of insertion,
+    free pages are released and the WAL is truncated. Returns removed tiles and their size in bytes
+*/
+pub async fn evict_mbtiles_zoom(
+    mbtiles_db: &PathBuf,
+    z: u8,
+    limit: u64,
+    tx_sqlite_client: flume::Sender<MessageSQLiteClient>,
+) -> Result<(u64, u64), anyhow::Error> {
+    let sqlite_client = mbtiles_client(mbtiles_db, false, tx_sqlite_client).await?;
+    let evicted = sqlite_client
+        .conn(move |connection| {
+            let mut stmt = connection.prepare(
+                "SELECT tile_column, tile_row, length(tile_data) FROM tiles
+                 WHERE zoom_level = ?1 ORDER BY rowid LIMIT ?2;",
+            )?;
+            let tiles = stmt
+                .query_map(rusqlite::params![z, limit], |row| {
+                    Ok((
+                        row.get::<_, u64>(0)?,
+                        row.get::<_, u64>(1)?,
+                        row.get::<_, Option<u64>>(2)?.unwrap_or(0),
+                    ))
+                })?
+                .collect::<Result<Vec<_>, _>>()?;
+            drop(stmt);
+
+            let tx = connection.unchecked_transaction()?;
+            let mut bytes = 0;
+            for (x, y, size) in tiles.iter() {
+                remove_mbtile(&tx, z as u64, *x, *y)?;
+                bytes += size;
             }
-        }
+            tx.commit()?;
+            connection.execute_batch("PRAGMA incremental_vacuum;")?;
+            connection.query_row("PRAGMA wal_checkpoint(TRUNCATE);", [], |_| Ok(()))?;
+            Ok((tiles.len() as u64, bytes))
+        })
+        .await?;
+    Ok(evicted)
+}
+
+pub async fn get_mbtile(
+    mbtiles_db: &PathBuf,
+    z: u64,
+    x: u64,
+    y: u64,
+    content_type: &str,
+    tx_sqlite_client: flume::Sender<MessageSQLiteClient>,
+    integrity: bool,
+) -> Result<Option<Response<Body>>, anyhow::Error> {
+    match read_mbtile(mbtiles_db, z, x, y, tx_sqlite_client, integrity).await? {
+        Some(tile) => Ok(Some(tile_response(tile, content_type)?)),
+        None => Ok(None),
     }
+}
 
-    event!(
-        Level::ERROR,
-        "Error get SQLite client for MBTiles {mbtiles_db:?}"
-    );
-    return Ok(None);
+// Field 'storage_compression' of the DataSource JSON, only 'zstd' is supported
+pub fn storage_compressed(data: &serde_json::Value) -> bool {
+    data.get("storage_compression")
+        .and_then(|v| v.as_str())
+        .is_some_and(|v| v.eq_ignore_ascii_case(TILE_COMPRESSION_ZSTD))
 }
 
+/*
+    MBTiles of the DataSource is created once, compression of tiles is recorded in 'metadata'
+    on creation: existing MBTiles keeps its tiles as they were written
+*/
 pub async fn init_mbtiles_db(
-    cwd: &str,
+    paths: &Paths,
     datasource_id: String,
+    compressed: bool,
     tx_sqlite_client: Option<flume::Sender<MessageSQLiteClient>>,
 ) -> Result<(), anyhow::Error> {
-    let mbtiles_db: PathBuf = [
-        cwd,
-        "tiles",
-        &datasource_id,
-        &format!("{}.mbtiles", datasource_id),
-    ]
-    .iter()
-    .collect();
-
-    if let Err(err) = merge_wal_shm_files_to_db(&cwd, &mbtiles_db, &datasource_id).await {
+    let mbtiles_db = paths.mbtiles_db(&datasource_id);
+
+    if let Err(err) = merge_wal_shm_files_to_db(paths, &mbtiles_db, &datasource_id).await {
         event!(
             Level::ERROR,
             "Error merge wal and shm files to DataBase {mbtiles_db:?}: {err:?}"
@@ -283,6 +549,11 @@ pub async fn init_mbtiles_db(
             .await
             .map_err(|err| anyhow!(err))?;
 
+        // free pages are released by incremental vacuum of the MBTiles maintenance
+        client
+            .conn(|connection| connection.execute_batch("PRAGMA auto_vacuum = INCREMENTAL;"))
+            .await?;
+
         client
             .conn(|connection| {
                 connection.execute(
@@ -339,6 +610,21 @@ pub async fn init_mbtiles_db(
             })
             .await?;
 
+        client
+            .conn(|connection| connection.execute(TABLE_TILES_CHECKSUM, ()))
+            .await?;
+
+        if compressed {
+            client
+                .conn(|connection| {
+                    connection.execute(
+                        "INSERT OR REPLACE INTO metadata (name, value) VALUES (?1, ?2);",
+                        [METADATA_TILE_COMPRESSION, TILE_COMPRESSION_ZSTD],
+                    )
+                })
+                .await?;
+        }
+
         if let Err(err) = client.close().await {
             event!(Level::ERROR, "Error close connection: {}", err.to_string());
         }
@@ -359,27 +645,13 @@ pub async fn init_mbtiles_db(
 }
 
 pub async fn merge_wal_shm_files_to_db(
-    cwd: &str,
+    paths: &Paths,
     mbtiles_db: &PathBuf,
     datasource_id: &str,
 ) -> Result<(), anyhow::Error> {
-    let wal_file: PathBuf = [
-        cwd,
-        "tiles",
-        datasource_id,
-        &format!("{}.mbtiles-wal", datasource_id),
-    ]
-    .iter()
-    .collect();
-
-    let shm_file: PathBuf = [
-        cwd,
-        "tiles",
-        datasource_id,
-        &format!("{}.mbtiles-shm", datasource_id),
-    ]
-    .iter()
-    .collect();
+    let tiles_dir = paths.tiles_dir(datasource_id);
+    let wal_file = tiles_dir.join(format!("{}.mbtiles-wal", datasource_id));
+    let shm_file = tiles_dir.join(format!("{}.mbtiles-shm", datasource_id));
 
     // merge WAL file to DataBase after restart server
     if tokio::fs::try_exists(&wal_file).await? || tokio::fs::try_exists(&shm_file).await? {
@@ -428,15 +700,26 @@ pub async fn add_sqlite_client(
     Ok(())
 }
 
-pub async fn pg_pool() -> Result<DB, anyhow::Error> {
+pub async fn pg_pool(pool_config: &DBPoolConfig) -> Result<DB, anyhow::Error> {
     let db_config = load_db_config();
-    let uri = format!(
-        "postgres://{}:{}@{}:{}/{}",
-        db_config.user, db_config.pass, db_config.host, db_config.port, db_config.name,
-    );
-    let options = PgConnectOptions::from_str(&uri)?.disable_statement_logging();
+    let mut options =
+        PgConnectOptions::from_str(&db_config.connection_string())?.disable_statement_logging();
+    if let Some(mode) = &pool_config.sslmode {
+        options = options.ssl_mode(PgSslMode::from_str(mode)?);
+    }
+    if let Some(root_cert) = &pool_config.sslrootcert {
+        options = options.ssl_root_cert(root_cert);
+    }
+    if pool_config.statement_timeout_ms > 0 {
+        options = options.options([(
+            "statement_timeout",
+            pool_config.statement_timeout_ms.to_string(),
+        )]);
+    }
     let pool = match PgPoolOptions::new()
-        .max_connections(5)
+        .min_connections(pool_config.min_connections)
+        .max_connections(pool_config.max_connections)
+        .acquire_timeout(Duration::from_secs(pool_config.acquire_timeout_secs))
         .connect_with(options)
         .await
     {
@@ -446,3 +729,32 @@ pub async fn pg_pool() -> Result<DB, anyhow::Error> {
 
     Ok(pool)
 }
+
+pub async fn mysql_pool(
+    db_config: &DBConfig,
+    pool_config: &DBPoolConfig,
+) -> Result<MySqlDB, anyhow::Error> {
+    let options =
+        MySqlConnectOptions::from_str(&db_config.connection_string())?.disable_statement_logging();
+    let pool = match MySqlPoolOptions::new()
+        .min_connections(pool_config.min_connections)
+        .max_connections(pool_config.max_connections)
+        .acquire_timeout(Duration::from_secs(pool_config.acquire_timeout_secs))
+        .connect_with(options)
+        .await
+    {
+        Err(err) => return Err(anyhow!("Error connect to MySQL: {:?}", err)),
+        Ok(pool) => pool,
+    };
+
+    Ok(pool)
+}
+
+// Pool of the config database by the backend of 'DBSCHEME'
+pub async fn db_pool(pool_config: &DBPoolConfig) -> Result<DBPool, anyhow::Error> {
+    let db_config = load_db_config();
+    match db_config.backend {
+        DBBackend::Postgres => pg_pool(pool_config).await.map(DBPool::Postgres),
+        DBBackend::MySql => mysql_pool(&db_config, pool_config).await.map(DBPool::MySql),
+    }
+}
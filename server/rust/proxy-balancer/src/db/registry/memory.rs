@@ -0,0 +1,53 @@
+use std::sync::RwLock;
+
+use crate::db::registry::Registry;
+use crate::tasks::datasources::DataSource;
+
+// Registry kept in memory of the process, DataSources are inserted by the embedding application
+#[derive(Debug, Default)]
+pub struct MemoryRegistry {
+    datasources: RwLock<Vec<DataSource>>,
+}
+
+impl MemoryRegistry {
+    pub fn new(datasources: Vec<DataSource>) -> MemoryRegistry {
+        MemoryRegistry {
+            datasources: RwLock::new(datasources),
+        }
+    }
+
+    // DataSource with the same identifier is replaced
+    pub fn insert(&self, datasource: DataSource) {
+        let mut datasources = self.datasources.write().unwrap();
+        datasources.retain(|ds| ds.identifier != datasource.identifier);
+        datasources.push(datasource);
+    }
+
+    pub fn remove(&self, datasource_id: &str) {
+        self.datasources
+            .write()
+            .unwrap()
+            .retain(|ds| ds.identifier != datasource_id);
+    }
+}
+
+#[async_trait::async_trait]
+impl Registry for MemoryRegistry {
+    async fn datasources(&self) -> Result<Vec<DataSource>, anyhow::Error> {
+        Ok(self.datasources.read().unwrap().clone())
+    }
+
+    async fn datasource(&self, datasource_id: &str) -> Result<Option<DataSource>, anyhow::Error> {
+        Ok(self
+            .datasources
+            .read()
+            .unwrap()
+            .iter()
+            .find(|ds| ds.identifier == datasource_id)
+            .cloned())
+    }
+
+    async fn ping(&self) -> Result<(), anyhow::Error> {
+        Ok(())
+    }
+}
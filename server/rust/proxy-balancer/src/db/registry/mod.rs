@@ -0,0 +1,43 @@
+pub mod memory;
+pub mod postgres;
+pub mod sqlite;
+
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use crate::config::Config;
+use crate::db::DB;
+use crate::defaults::REGISTRY_SQLITE;
+use crate::paths::Paths;
+use crate::tasks::datasources::DataSource;
+use postgres::PostgresRegistry;
+use sqlite::SqliteRegistry;
+
+/*
+    Registry of DataSources: rows of the table 'datasource' are written by workers of Python,
+    the balancer only reads them
+*/
+#[async_trait::async_trait]
+pub trait Registry: Send + Sync + Debug {
+    async fn datasources(&self) -> Result<Vec<DataSource>, anyhow::Error>;
+    async fn datasource(&self, datasource_id: &str) -> Result<Option<DataSource>, anyhow::Error>;
+    // storage of the registry answers queries
+    async fn ping(&self) -> Result<(), anyhow::Error>;
+}
+
+pub type SharedRegistry = Arc<dyn Registry>;
+
+// Registry selected by 'registry.type' of the configuration, the pool is required by 'postgres'
+pub async fn open_registry(
+    paths: &Paths,
+    config: &Config,
+    pool: Option<DB>,
+) -> Result<SharedRegistry, anyhow::Error> {
+    if config.registry_type == REGISTRY_SQLITE {
+        let path = paths.resolve(&config.registry_path);
+        let registry = SqliteRegistry::open(path).await?;
+        return Ok(Arc::new(registry));
+    }
+    let pool = pool.ok_or(anyhow::anyhow!("Registry 'postgres' requires PostgreSQL"))?;
+    Ok(Arc::new(PostgresRegistry::new(pool)))
+}
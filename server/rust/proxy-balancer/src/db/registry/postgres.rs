@@ -0,0 +1,35 @@
+use crate::db::{registry::Registry, DB};
+use crate::tasks::datasources::DataSource;
+
+#[derive(Debug, Clone)]
+pub struct PostgresRegistry {
+    db: DB,
+}
+
+impl PostgresRegistry {
+    pub fn new(db: DB) -> PostgresRegistry {
+        PostgresRegistry { db }
+    }
+}
+
+#[async_trait::async_trait]
+impl Registry for PostgresRegistry {
+    async fn datasources(&self) -> Result<Vec<DataSource>, anyhow::Error> {
+        let query = sqlx::query_as::<_, DataSource>("SELECT * FROM datasource");
+        let datasources: Vec<DataSource> = query.fetch_all(&self.db).await?;
+        Ok(datasources)
+    }
+
+    async fn datasource(&self, datasource_id: &str) -> Result<Option<DataSource>, anyhow::Error> {
+        let query =
+            sqlx::query_as::<_, DataSource>("SELECT * FROM datasource WHERE identifier = $1")
+                .bind(datasource_id);
+        let datasource: Option<DataSource> = query.fetch_optional(&self.db).await?;
+        Ok(datasource)
+    }
+
+    async fn ping(&self) -> Result<(), anyhow::Error> {
+        sqlx::query("SELECT 1").execute(&self.db).await?;
+        Ok(())
+    }
+}
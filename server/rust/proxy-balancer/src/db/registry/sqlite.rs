@@ -0,0 +1,140 @@
+use std::fmt::Debug;
+use std::path::PathBuf;
+
+use anyhow::anyhow;
+use async_sqlite::{Client, ClientBuilder, JournalMode};
+use rusqlite::types::Type;
+use sqlx::types::Json;
+
+use crate::db::registry::Registry;
+use crate::tasks::datasources::DataSource;
+
+// Columns of the table 'datasource' of PostgreSQL, JSON values are saved as text
+pub const TABLE_DATASOURCE: &str = "CREATE TABLE IF NOT EXISTS datasource (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    identifier TEXT NOT NULL UNIQUE,
+    data_type TEXT,
+    host TEXT,
+    port INTEGER,
+    store_type TEXT,
+    mbtiles INTEGER,
+    name TEXT,
+    description TEXT,
+    attribution TEXT,
+    minzoom INTEGER,
+    maxzoom INTEGER,
+    bounds TEXT,
+    center TEXT,
+    data TEXT NOT NULL
+);";
+
+const SELECT_DATASOURCE: &str = "SELECT identifier, data_type, store_type, host, port,
+    mbtiles, name, description, attribution, minzoom, maxzoom, bounds, center, data FROM datasource";
+
+// Registry in a single SQLite file for servers without PostgreSQL
+#[derive(Clone)]
+pub struct SqliteRegistry {
+    path: PathBuf,
+    client: Client,
+}
+
+impl Debug for SqliteRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SqliteRegistry")
+            .field("path", &self.path)
+            .finish()
+    }
+}
+
+impl SqliteRegistry {
+    pub async fn open(path: PathBuf) -> Result<SqliteRegistry, anyhow::Error> {
+        if let Some(dir) = path.parent() {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+        let client = ClientBuilder::new()
+            .path(&path)
+            .journal_mode(JournalMode::Wal)
+            .open()
+            .await
+            .map_err(|err| anyhow!(err))?;
+        client
+            .conn(|connection| connection.execute(TABLE_DATASOURCE, ()))
+            .await
+            .map_err(|err| anyhow!(err))?;
+        Ok(SqliteRegistry { path, client })
+    }
+}
+
+fn json_column(
+    row: &rusqlite::Row,
+    idx: usize,
+) -> rusqlite::Result<Option<Json<serde_json::Value>>> {
+    let Some(text) = row.get::<_, Option<String>>(idx)? else {
+        return Ok(None);
+    };
+    serde_json::from_str(&text)
+        .map(|value| Some(Json(value)))
+        .map_err(|err| rusqlite::Error::FromSqlConversionFailure(idx, Type::Text, Box::new(err)))
+}
+
+fn datasource_from_row(row: &rusqlite::Row) -> rusqlite::Result<DataSource> {
+    Ok(DataSource {
+        identifier: row.get(0)?,
+        data_type: row.get(1)?,
+        store_type: row.get(2)?,
+        host: row.get(3)?,
+        port: row.get(4)?,
+        mbtiles: row.get(5)?,
+        name: row.get(6)?,
+        description: row.get(7)?,
+        attribution: row.get(8)?,
+        minzoom: row.get(9)?,
+        maxzoom: row.get(10)?,
+        bounds: json_column(row, 11)?,
+        center: json_column(row, 12)?,
+        data: json_column(row, 13)?.ok_or(rusqlite::Error::InvalidColumnType(
+            13,
+            "data".to_string(),
+            Type::Null,
+        ))?,
+    })
+}
+
+#[async_trait::async_trait]
+impl Registry for SqliteRegistry {
+    async fn datasources(&self) -> Result<Vec<DataSource>, anyhow::Error> {
+        let datasources = self
+            .client
+            .conn(|connection| {
+                let mut stmt = connection.prepare(SELECT_DATASOURCE)?;
+                let rows = stmt.query_map([], datasource_from_row)?;
+                rows.collect::<rusqlite::Result<Vec<DataSource>>>()
+            })
+            .await
+            .map_err(|err| anyhow!(err))?;
+        Ok(datasources)
+    }
+
+    async fn datasource(&self, datasource_id: &str) -> Result<Option<DataSource>, anyhow::Error> {
+        let datasource_id = datasource_id.to_string();
+        let datasource = self
+            .client
+            .conn(move |connection| {
+                let mut stmt =
+                    connection.prepare(&format!("{SELECT_DATASOURCE} WHERE identifier = ?1"))?;
+                let mut rows = stmt.query_map([&datasource_id], datasource_from_row)?;
+                rows.next().transpose()
+            })
+            .await
+            .map_err(|err| anyhow!(err))?;
+        Ok(datasource)
+    }
+
+    async fn ping(&self) -> Result<(), anyhow::Error> {
+        self.client
+            .conn(|connection| connection.query_row("SELECT 1", [], |row| row.get::<_, i64>(0)))
+            .await
+            .map_err(|err| anyhow!(err))?;
+        Ok(())
+    }
+}
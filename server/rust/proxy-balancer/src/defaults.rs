@@ -1,5 +1,139 @@
 pub const LOCALHOST: &'static str = "127.0.0.1";
 pub const MAXZOOM: u8 = 20;
+pub const OVERZOOM_MAXZOOM: u8 = 22;
+pub const GEOFENCE_MASK_MAXZOOM: u8 = 12;
+// scales of raster tiles '{y}@{scale}x.png' resized by the proxy
+pub const TILE_SCALES: [f32; 4] = [0.5, 2.0, 3.0, 4.0];
+pub const DEFAULT_TILE_GRID: &str = "WebMercatorQuad";
 pub const JOB_CONCURRENCY: usize = 1;
 pub const MASTER_HEADER: &'static str = "master-server";
+pub const CLUSTER_SIGNATURE_HEADER: &str = "x-cluster-signature";
+pub const CLUSTER_TIMESTAMP_HEADER: &str = "x-cluster-timestamp";
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+pub const TILE_PRIORITY_HEADER: &str = "x-tile-priority";
+pub const QUEUE_DEPTH_HEADER: &str = "x-queue-depth";
+pub const HOPS_HEADER: &str = "x-tiler-hops";
+pub const ROUTE_HEADER: &str = "x-tiler-route";
+pub const ROUTE_DEBUG_HEADER: &str = "x-tiler-route-debug";
+pub const REQUEST_ID_MAX_LEN: usize = 128;
+pub const SWAGGER_UI_CDN: &str = "https://unpkg.com/swagger-ui-dist@5";
+pub const CLUSTER_SIGNATURE_MAX_AGE_SECS: i64 = 300;
+pub const CLUSTER_RING_VIRTUAL_NODES: usize = 64;
+pub const CLUSTER_MAX_HOPS: u64 = 4;
+pub const EVENTS_SUBSCRIBER_BUFFER: usize = 256;
+pub const EVENTS_KEEPALIVE_SECS: u64 = 15;
+pub const EXPORT_TTL_SECS: u64 = 3600;
+pub const CORS_ALLOWED_METHODS: [&str; 6] = ["GET", "POST", "PUT", "PATCH", "DELETE", "OPTIONS"];
+pub const CORS_MAX_AGE_SECS: u64 = 86400;
+pub const OTLP_ENDPOINT: &str = "http://localhost:4317";
+pub const OTLP_SERVICE_NAME: &str = "tiler-server";
+pub const SLOW_REQUEST_MS: u64 = 1000;
+pub const PROBE_TIMEOUT_SECS: u64 = 2;
+pub const WORKER_READY_POLL_MS: u64 = 500;
+pub const WORKER_READY_QUORUM: f64 = 0.5;
+pub const WORKER_RESTART_BACKOFF_SECS: u64 = 1;
+pub const WORKER_RESTART_MAX_BACKOFF_SECS: u64 = 60;
+pub const WORKER_STABLE_SECS: u64 = 60;
+pub const WORKER_STARTUP_TIMEOUT_SECS: u64 = 120;
+pub const ADAPTIVE_CONCURRENCY_LATENCY_MS: u64 = 1000;
+pub const ADAPTIVE_CONCURRENCY_ERROR_RATE: f64 = 0.1;
+pub const ADAPTIVE_CONCURRENCY_INTERVAL_SECS: u64 = 10;
+pub const BATCH_PERMITS_SHARE: f64 = 0.5;
+pub const QUEUE_RETRY_AFTER_SECS: u64 = 1;
+pub const MAX_TILE_SIZE_KB: u64 = 8192;
+pub const MAX_BODY_SIZE_KB: u64 = 1024;
+pub const NOT_READY_RETRY_AFTER_SECS: u64 = 5;
+pub const AUDIT_LIMIT: u64 = 100;
+pub const AUDIT_MAX_LIMIT: u64 = 1000;
+pub const SEMAPHORE_REJECTIONS_WINDOW_SECS: u64 = 300;
+pub const LATENCY_BUCKETS_SECS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+pub const STATIC_ALLOWED_EXTENSIONS: [&str; 20] = [
+    "html", "htm", "js", "mjs", "css", "json", "map", "txt", "png", "jpg", "jpeg", "gif", "svg",
+    "webp", "ico", "pbf", "woff", "woff2", "ttf", "otf",
+];
+pub const UPLOAD_MAX_SIZE_MB: u64 = 4096;
+pub const UPLOAD_EXPIRE_SECS: u64 = 86400;
+pub const UPLOAD_CLEANUP_SECS: u64 = 60;
+pub const UPLOAD_OFFSET_HEADER: &str = "upload-offset";
+pub const UPLOAD_LENGTH_HEADER: &str = "upload-length";
+pub const TUS_RESUMABLE_HEADER: &str = "tus-resumable";
+pub const TUS_VERSION: &str = "1.0.0";
+pub const UPLOAD_ALLOWED_EXTENSIONS: [&str; 15] = [
+    "json", "tif", "tiff", "vrt", "geojson", "gpkg", "shp", "shx", "dbf", "prj", "cpg", "mbtiles",
+    "ovr", "xml", "fgb",
+];
+pub const INTEGRITY_ENV: &str = "TILER_INTEGRITY";
+pub const PYRAMID_POLL_SECS: u64 = 10;
+pub const MBTILES_MAINTENANCE_SECS: u64 = 600;
+pub const MBTILES_WAL_THRESHOLD_MB: u64 = 256;
+pub const MBTILES_VACUUM_PAGES: u64 = 1024;
+pub const SQLITE_POOL_MAX_CLIENTS: usize = 256;
+pub const SQLITE_POOL_IDLE_SECS: u64 = 600;
+pub const QUOTA_INTERVAL_SECS: u64 = 300;
+pub const QUOTA_TARGET_RATIO: f64 = 0.9;
+pub const QUOTA_EVICT_BATCH: u64 = 1000;
+pub const STORAGE_INTERVAL_SECS: u64 = 30;
+pub const STORAGE_MIN_FREE_MB: u64 = 1024;
+pub const USAGE_FLUSH_SECS: u64 = 60;
+pub const USAGE_BUFFER_SIZE: usize = 10000;
+pub const USAGE_BUCKET_ZOOM: u8 = 8;
+pub const USAGE_HEATMAP_LIMIT: u64 = 500;
+pub const USAGE_HEATMAP_MAX_LIMIT: u64 = 10000;
+pub const USAGE_RETENTION_DAYS: u64 = 30;
+pub const USAGE_RETENTION_CHECK_SECS: u64 = 3600;
+pub const BILLING_KEY_HEADER: &str = "x-api-key";
+pub const BILLING_LIMIT: u64 = 1000;
+pub const BILLING_MAX_LIMIT: u64 = 10000;
+// API keys with totals in memory, totals of the least recently checked keys are reloaded from the table
+pub const BILLING_MAX_KEYS: usize = 10000;
+pub const SMART_PYRAMID_DAYS: u64 = 7;
+pub const SMART_PYRAMID_MAX_TILES: u64 = 100000;
+pub const DELTA_PYRAMID_MAX_TILES: u64 = 1000000;
+pub const DISTRIBUTED_PYRAMID_MAX_REGIONS: u64 = 64;
+pub const SHARD_POLL_ERRORS: u32 = 3;
+pub const SHARD_TIMEOUT_SECS: u64 = 86400;
+pub const LOCAL_NODE: &str = "local";
+pub const TRASH_RETENTION_DAYS: u64 = 7;
+pub const TRASH_INTERVAL_SECS: u64 = 3600;
+pub const DB_SCHEME: &'static str = "postgres";
+pub const DATASOURCE_CHANNEL: &str = "datasource_changed";
+pub const DATASOURCE_NOTIFY_DEBOUNCE_MS: u64 = 500;
+pub const DB_POOL_MIN_CONNECTIONS: u32 = 0;
+pub const DB_POOL_MAX_CONNECTIONS: u32 = 5;
+pub const DB_ACQUIRE_TIMEOUT_SECS: u64 = 30;
+pub const DB_SSLMODES: [&str; 6] = [
+    "disable",
+    "allow",
+    "prefer",
+    "require",
+    "verify-ca",
+    "verify-full",
+];
+pub const REGISTRY_POSTGRES: &str = "postgres";
+pub const REGISTRY_SQLITE: &str = "sqlite";
+pub const REGISTRY_SQLITE_PATH: &str = "data/datasources.db";
+pub const PATHS_CACHE: &str = "tiles";
+pub const PATHS_DATA: &str = "data";
+pub const PATHS_SCRIPTS: &str = "scripts";
+// directories of the cache and data passed to workers
+pub const CACHE_DIR_ENV: &str = "TILER_CACHE_DIR";
+pub const DATA_DIR_ENV: &str = "TILER_DATA_DIR";
 pub const GRANIAN: &'static str = "granian";
+pub const NATIVE: &'static str = "native";
+// virtual port of the pool of native renderers in the semaphore of workers
+pub const NATIVE_RENDERER_PORT: u16 = 0;
+pub const MVT_EXTENT: u32 = 4096;
+pub const MVT_BUFFER: u32 = 64;
+pub const COG_HEADER_BYTES: u64 = 16384;
+pub const COG_HEADER_MAX_BYTES: u64 = 16 * 1024 * 1024;
+pub const COG_REQUEST_TIMEOUT_SECS: u64 = 30;
+pub const REMOTE_RATE_LIMIT: f64 = 10.0;
+pub const REMOTE_MAX_WAIT_SECS: u64 = 10;
+pub const REMOTE_REQUEST_TIMEOUT_SECS: u64 = 30;
+pub const WEBHOOK_TIMEOUT_SECS: u64 = 10;
+pub const WEBHOOK_RETRIES: u32 = 3;
+pub const WEBHOOK_RETRY_SECS: u64 = 5;
+pub const PROXY_PROTOCOL_TIMEOUT_SECS: u64 = 5;
+pub const ACCEPT_ERROR_SLEEP_MS: u64 = 1000;
@@ -5,6 +5,8 @@ use anyhow::anyhow;
 use dotenv::dotenv;
 use std::process::exit;
 
+use crate::paths::Paths;
+
 pub fn setup_envs() -> HashMap<&'static str, String> {
     match dotenv() {
         Ok(_) => {}
@@ -117,75 +119,49 @@ pub fn setup_envs() -> HashMap<&'static str, String> {
 }
 
 /*
-    CWD = Current Working Directory
-    CWD/data
-    CWD/data/mosaics
+    CWD = Current Working Directory, directories of 'server.paths' are resolved in it
+    {data}
+    {data}/mosaics
+
+    {cache}
 
     CWD/logs
 
     CWD/datasources
     CWD/datasources/vector
     CWD/datasources/raster
-*/
-pub async fn init_dirs(cwd: &str) -> Result<(), anyhow::Error> {
-    let data_dir: PathBuf = [cwd, "data"].iter().collect();
-    let mosaics_dir: PathBuf = [cwd, "data", "mosaics"].iter().collect();
-    if !tokio::fs::try_exists(&data_dir).await? {
-        tokio::fs::create_dir(data_dir)
-            .await
-            .map_err(|err| anyhow!(err))?;
-        tokio::fs::create_dir(mosaics_dir)
-            .await
-            .map_err(|err| anyhow!(err))?;
-    } else {
-        if !tokio::fs::try_exists(&mosaics_dir).await? {
-            tokio::fs::create_dir(mosaics_dir)
-                .await
-                .map_err(|err| anyhow!(err))?;
-        }
-    }
 
-    let logs_dir: PathBuf = [cwd, "logs"].iter().collect();
-    if !tokio::fs::try_exists(&logs_dir).await? {
-        tokio::fs::create_dir(logs_dir)
-            .await
-            .map_err(|err| anyhow!(err))?;
-    }
+    CWD/styles
+    CWD/styles/sprites
+    CWD/styles/fonts
+*/
+pub fn app_dirs(paths: &Paths) -> Vec<PathBuf> {
+    vec![
+        paths.data.clone(),
+        paths.mosaics_dir(),
+        paths.cache.clone(),
+        paths.logs_dir(),
+        paths.root.join("datasources"),
+        paths.datasources_dir("vector"),
+        paths.datasources_dir("raster"),
+        paths.styles_dir(),
+        paths.styles_dir().join("sprites"),
+        paths.styles_dir().join("fonts"),
+    ]
+}
 
-    let ds_dir: PathBuf = [cwd, "datasources"].iter().collect();
-    let ds_vector_dir: PathBuf = [cwd, "datasources", "vector"].iter().collect();
-    let ds_raster_dir: PathBuf = [cwd, "datasources", "raster"].iter().collect();
-    if !tokio::fs::try_exists(&ds_dir).await? {
-        tokio::fs::create_dir(ds_dir)
-            .await
-            .map_err(|err| anyhow!(err))?;
-        tokio::fs::create_dir(ds_vector_dir)
-            .await
-            .map_err(|err| anyhow!(err))?;
-        tokio::fs::create_dir(ds_raster_dir)
-            .await
-            .map_err(|err| anyhow!(err))?;
-    } else {
-        if !tokio::fs::try_exists(&ds_vector_dir).await? {
-            tokio::fs::create_dir(ds_vector_dir)
-                .await
-                .map_err(|err| anyhow!(err))?;
-        }
-        if !tokio::fs::try_exists(&ds_raster_dir).await? {
-            tokio::fs::create_dir(ds_raster_dir)
+// Missing application directories are created, existing ones are kept
+pub async fn init_dirs(paths: &Paths) -> Result<Vec<PathBuf>, anyhow::Error> {
+    let mut created = Vec::new();
+    for dir in app_dirs(paths) {
+        if !tokio::fs::try_exists(&dir).await? {
+            tokio::fs::create_dir_all(&dir)
                 .await
                 .map_err(|err| anyhow!(err))?;
+            created.push(dir);
         }
     }
-
-    let tiles_dir: PathBuf = [cwd, "tiles"].iter().collect();
-    if !tokio::fs::try_exists(&tiles_dir).await? {
-        tokio::fs::create_dir(tiles_dir)
-            .await
-            .map_err(|err| anyhow!(err))?;
-    }
-
-    Ok(())
+    Ok(created)
 }
 
 pub fn get_cwd() -> Result<String, anyhow::Error> {
@@ -0,0 +1,324 @@
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::pin::Pin;
+use std::str::FromStr;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use anyhow::anyhow;
+use futures::StreamExt;
+use hyper::http::header::{self, HeaderMap, HeaderValue};
+use hyper::server::accept::{self, Accept};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{event, Level};
+
+use crate::config::ProxyConfig;
+use crate::defaults::{ACCEPT_ERROR_SLEEP_MS, PROXY_PROTOCOL_TIMEOUT_SECS};
+
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+const PROXY_V2_SIGNATURE: [u8; 12] = [
+    0x0D, 0x0A, 0x0D, 0x0A, 0x00, 0x0D, 0x0A, 0x51, 0x55, 0x49, 0x54, 0x0A,
+];
+
+// Address or network of trusted proxies: '10.0.0.1' or '10.0.0.0/8'
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IpNetwork {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl IpNetwork {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for IpNetwork {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = match s.trim().split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s.trim(), None),
+        };
+        let addr: IpAddr = addr
+            .parse()
+            .map_err(|_| anyhow!("Invalid address of proxy '{s}'"))?;
+        let max_prefix = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse::<u8>()
+                .ok()
+                .filter(|prefix| *prefix <= max_prefix)
+                .ok_or_else(|| anyhow!("Invalid prefix of network '{s}'"))?,
+            None => max_prefix,
+        };
+        Ok(IpNetwork { addr, prefix })
+    }
+}
+
+// Node of 'X-Forwarded-For' or 'for=' of 'Forwarded': '1.2.3.4', '1.2.3.4:80', '"[2001:db8::1]:80"'
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+    let ip = node
+        .parse::<IpAddr>()
+        .ok()
+        .or_else(|| node.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+        .or_else(|| {
+            node.strip_prefix('[')
+                .and_then(|n| n.strip_suffix(']'))
+                .and_then(|n| n.parse::<IpAddr>().ok())
+        })?;
+    Some(ip.to_canonical())
+}
+
+fn x_forwarded_for(headers: &HeaderMap<HeaderValue>) -> Vec<String> {
+    headers
+        .get_all(X_FORWARDED_FOR)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|node| node.trim().to_string())
+        .filter(|node| !node.is_empty())
+        .collect()
+}
+
+// 'for=' of elements of 'Forwarded' (RFC 7239), elements without it break the chain
+fn forwarded_for(headers: &HeaderMap<HeaderValue>) -> Vec<String> {
+    headers
+        .get_all(header::FORWARDED)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|element| {
+            element
+                .split(';')
+                .filter_map(|pair| pair.split_once('='))
+                .find(|(key, _)| key.trim().eq_ignore_ascii_case("for"))
+                .map(|(_, value)| value.trim().to_string())
+                .unwrap_or_default()
+        })
+        .collect()
+}
+
+/*
+    Address of the client behind trusted proxies: nodes of 'X-Forwarded-For' ('Forwarded' without it)
+    are walked from the right while they are trusted, the first untrusted node is the client.
+    Headers of untrusted peers are ignored as they may be forged. Nodes of trusted proxies are removed
+    from 'X-Forwarded-For', workers get the client as the last node like without proxies
+*/
+pub fn forwarded_client_ip(
+    peer: IpAddr,
+    headers: &mut HeaderMap<HeaderValue>,
+    proxy: &ProxyConfig,
+) -> IpAddr {
+    let peer = peer.to_canonical();
+    if !proxy.is_trusted(peer) {
+        return peer;
+    }
+
+    let xff = x_forwarded_for(headers);
+    let is_xff = !xff.is_empty();
+    let nodes = if is_xff { xff } else { forwarded_for(headers) };
+    let mut client = peer;
+    let mut rest = nodes.len();
+    for node in nodes.iter().rev() {
+        let Some(ip) = parse_node(node) else {
+            break;
+        };
+        client = ip;
+        rest -= 1;
+        if !proxy.is_trusted(ip) {
+            break;
+        }
+    }
+
+    if is_xff {
+        headers.remove(X_FORWARDED_FOR);
+        if rest > 0 {
+            if let Ok(value) = HeaderValue::from_str(&nodes[..rest].join(", ")) {
+                headers.insert(X_FORWARDED_FOR, value);
+            }
+        }
+    }
+    client
+}
+
+// Connection of the server: the address is the peer or the source of the PROXY protocol header
+pub struct ClientStream {
+    stream: TcpStream,
+    remote_addr: SocketAddr,
+}
+
+impl ClientStream {
+    pub fn remote_addr(&self) -> SocketAddr {
+        self.remote_addr
+    }
+}
+
+impl AsyncRead for ClientStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for ClientStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().stream).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().stream).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.stream.is_write_vectored()
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_shutdown(cx)
+    }
+}
+
+/*
+    Source address of the PROXY protocol v2 header (HAProxy, AWS NLB),
+    None for LOCAL connections of the proxy (health checks) and unknown families
+*/
+async fn read_proxy_header(stream: &mut TcpStream) -> Result<Option<SocketAddr>, anyhow::Error> {
+    let mut header = [0u8; 16];
+    stream.read_exact(&mut header).await?;
+    if header[..12] != PROXY_V2_SIGNATURE {
+        return Err(anyhow!("PROXY protocol v2 header is expected"));
+    }
+    let version = header[12] >> 4;
+    if version != 2 {
+        return Err(anyhow!("Unsupported version {version} of PROXY protocol"));
+    }
+    let len = u16::from_be_bytes([header[14], header[15]]) as usize;
+    let mut payload = vec![0u8; len];
+    stream.read_exact(&mut payload).await?;
+
+    // command LOCAL
+    if header[12] & 0x0F == 0 {
+        return Ok(None);
+    }
+    match header[13] >> 4 {
+        // AF_INET: source, destination addresses and ports
+        1 if len >= 12 => {
+            let ip = Ipv4Addr::new(payload[0], payload[1], payload[2], payload[3]);
+            let port = u16::from_be_bytes([payload[8], payload[9]]);
+            Ok(Some(SocketAddr::new(IpAddr::V4(ip), port)))
+        }
+        // AF_INET6
+        2 if len >= 36 => {
+            let octets: [u8; 16] = payload[..16].try_into()?;
+            let port = u16::from_be_bytes([payload[32], payload[33]]);
+            Ok(Some(SocketAddr::new(
+                IpAddr::V6(Ipv6Addr::from(octets)),
+                port,
+            )))
+        }
+        // AF_UNSPEC and AF_UNIX
+        0 | 3 => Ok(None),
+        family => Err(anyhow!(
+            "Invalid address family {family} of PROXY protocol header"
+        )),
+    }
+}
+
+// Connections of the server bound to the address
+pub async fn incoming(
+    addr: SocketAddr,
+    proxy_protocol: bool,
+) -> Result<impl Accept<Conn = ClientStream, Error = io::Error>, io::Error> {
+    let listener = TcpListener::bind(addr).await?;
+    Ok(incoming_listener(listener, proxy_protocol))
+}
+
+/*
+    Connections of the bound listener. With the PROXY protocol the header is read in a task of the connection,
+    slow or broken proxies do not block accepting of other connections
+*/
+pub fn incoming_listener(
+    listener: TcpListener,
+    proxy_protocol: bool,
+) -> impl Accept<Conn = ClientStream, Error = io::Error> {
+    let (tx, rx) = flume::unbounded::<ClientStream>();
+
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, peer) = match listener.accept().await {
+                Ok(conn) => conn,
+                Err(err) => {
+                    // too many open files: connections are accepted after others are closed
+                    event!(Level::ERROR, "Error accept connection: {err}");
+                    tokio::time::sleep(Duration::from_millis(ACCEPT_ERROR_SLEEP_MS)).await;
+                    continue;
+                }
+            };
+
+            if !proxy_protocol {
+                let conn = ClientStream {
+                    stream,
+                    remote_addr: peer,
+                };
+                if tx.send_async(conn).await.is_err() {
+                    break;
+                }
+                continue;
+            }
+
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                let timeout = Duration::from_secs(PROXY_PROTOCOL_TIMEOUT_SECS);
+                match tokio::time::timeout(timeout, read_proxy_header(&mut stream)).await {
+                    Ok(Ok(source)) => {
+                        let conn = ClientStream {
+                            stream,
+                            remote_addr: source.unwrap_or(peer),
+                        };
+                        let _ = tx.send_async(conn).await;
+                    }
+                    Ok(Err(err)) => {
+                        event!(Level::WARN, "Rejected connection from {peer}: {err}");
+                    }
+                    Err(_) => {
+                        event!(
+                            Level::WARN,
+                            "Rejected connection from {peer}: no PROXY protocol header in {timeout:?}"
+                        );
+                    }
+                }
+            });
+        }
+    });
+
+    accept::from_stream(rx.into_stream().map(Ok::<_, io::Error>))
+}
@@ -0,0 +1,262 @@
+use std::collections::HashMap;
+
+use anyhow::anyhow;
+use serde_json::Value;
+
+use crate::defaults::GEOFENCE_MASK_MAXZOOM;
+use crate::grid::TileGrid;
+
+// Rings of the polygon in degrees [lon, lat]: the outer ring first, then holes
+pub type Polygon = Vec<Vec<[f64; 2]>>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Cover {
+    Outside,
+    Partial,
+    Inside,
+}
+
+/*
+    Licensed area of the DataSource: field 'geofence' of the DataSource JSON with GeoJSON
+    Polygon, MultiPolygon, Feature or FeatureCollection in degrees. Tiles whose bbox does not
+    intersect the area are refused. Tiles of the grid of the DataSource are looked up in the mask
+    precomputed down to GEOFENCE_MASK_MAXZOOM, deeper tiles and tiles of other grids are tested
+    against polygons. Tiles of grids with unsupported CRS are not restricted
+*/
+#[derive(Debug, Clone)]
+pub struct Geofence {
+    polygons: Vec<Polygon>,
+    // [west, south, east, north] of all polygons
+    bounds: [f64; 4],
+    // name of the grid of the mask
+    grid: String,
+    // tiles inside and crossing the boundary by zoom, missing tiles are outside
+    mask: Vec<HashMap<(u64, u64), Cover>>,
+}
+
+impl Geofence {
+    pub fn new(polygons: Vec<Polygon>, grid: &TileGrid) -> Self {
+        let mut bounds = [f64::MAX, f64::MAX, f64::MIN, f64::MIN];
+        for [lon, lat] in polygons.iter().flat_map(|p| p.iter().flatten()) {
+            bounds = [
+                bounds[0].min(*lon),
+                bounds[1].min(*lat),
+                bounds[2].max(*lon),
+                bounds[3].max(*lat),
+            ];
+        }
+        let mut geofence = Geofence {
+            polygons,
+            bounds,
+            grid: grid.name.clone(),
+            mask: Vec::new(),
+        };
+
+        // only tiles crossing the boundary are split into children
+        let (width, height) = grid.matrix_size(0);
+        let mut tiles: Vec<(u64, u64)> = (0..width)
+            .flat_map(|x| (0..height).map(move |y| (x, y)))
+            .collect();
+        for z in 0..=GEOFENCE_MASK_MAXZOOM.min(grid.maxzoom) {
+            let mut level = HashMap::new();
+            let mut children = Vec::new();
+            for (x, y) in tiles {
+                match geofence.tile_cover(grid, z, x, y) {
+                    Cover::Outside => {}
+                    Cover::Inside => {
+                        level.insert((x, y), Cover::Inside);
+                    }
+                    Cover::Partial => {
+                        level.insert((x, y), Cover::Partial);
+                        children.extend([
+                            (x * 2, y * 2),
+                            (x * 2 + 1, y * 2),
+                            (x * 2, y * 2 + 1),
+                            (x * 2 + 1, y * 2 + 1),
+                        ]);
+                    }
+                }
+            }
+            geofence.mask.push(level);
+            tiles = children;
+        }
+        geofence
+    }
+
+    // Tile z/x/y (rows in XYZ scheme) intersects the licensed area
+    pub fn allows(&self, grid: &TileGrid, z: u8, x: u64, y: u64) -> bool {
+        if grid.name != self.grid || self.mask.is_empty() {
+            return self.tile_cover(grid, z, x, y) != Cover::Outside;
+        }
+        let mask_maxzoom = (self.mask.len() - 1) as u8;
+        for (level, tiles) in self
+            .mask
+            .iter()
+            .enumerate()
+            .take(z.min(mask_maxzoom) as usize + 1)
+        {
+            let shift = z - level as u8;
+            match tiles.get(&(x >> shift, y >> shift)) {
+                None | Some(Cover::Outside) => return false,
+                Some(Cover::Inside) => return true,
+                Some(Cover::Partial) => {}
+            }
+        }
+        z <= mask_maxzoom || self.tile_cover(grid, z, x, y) != Cover::Outside
+    }
+
+    fn tile_cover(&self, grid: &TileGrid, z: u8, x: u64, y: u64) -> Cover {
+        match grid.tile_bounds(z, x, y) {
+            Some(bounds) => self.cover(&bounds),
+            None => Cover::Inside,
+        }
+    }
+
+    fn cover(&self, rect: &[f64; 4]) -> Cover {
+        if rect[0] > self.bounds[2]
+            || rect[2] < self.bounds[0]
+            || rect[1] > self.bounds[3]
+            || rect[3] < self.bounds[1]
+        {
+            return Cover::Outside;
+        }
+        let crosses = self
+            .polygons
+            .iter()
+            .flatten()
+            .flat_map(|ring| ring.windows(2))
+            .any(|edge| segment_intersects(edge[0], edge[1], rect));
+        if crosses {
+            return Cover::Partial;
+        }
+        // without the boundary in the rectangle it is entirely inside or outside
+        let center = [(rect[0] + rect[2]) / 2.0, (rect[1] + rect[3]) / 2.0];
+        if self.contains(center) {
+            Cover::Inside
+        } else {
+            Cover::Outside
+        }
+    }
+
+    fn contains(&self, point: [f64; 2]) -> bool {
+        self.polygons.iter().any(|polygon| {
+            let mut rings = polygon.iter();
+            rings
+                .next()
+                .is_some_and(|outer| ring_contains(outer, point))
+                && !rings.any(|hole| ring_contains(hole, point))
+        })
+    }
+}
+
+// Ray casting, the ring is closed
+fn ring_contains(ring: &[[f64; 2]], point: [f64; 2]) -> bool {
+    let mut inside = false;
+    for edge in ring.windows(2) {
+        let ([x1, y1], [x2, y2]) = (edge[0], edge[1]);
+        if (y1 > point[1]) != (y2 > point[1])
+            && point[0] < x1 + (point[1] - y1) * (x2 - x1) / (y2 - y1)
+        {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+// Liang-Barsky clipping of the segment by the rectangle [west, south, east, north]
+fn segment_intersects(a: [f64; 2], b: [f64; 2], rect: &[f64; 4]) -> bool {
+    let (dx, dy) = (b[0] - a[0], b[1] - a[1]);
+    let (mut t0, mut t1) = (0.0_f64, 1.0_f64);
+    for (p, q) in [
+        (-dx, a[0] - rect[0]),
+        (dx, rect[2] - a[0]),
+        (-dy, a[1] - rect[1]),
+        (dy, rect[3] - a[1]),
+    ] {
+        if p == 0.0 {
+            if q < 0.0 {
+                return false;
+            }
+            continue;
+        }
+        let t = q / p;
+        if p < 0.0 {
+            t0 = t0.max(t);
+        } else {
+            t1 = t1.min(t);
+        }
+        if t0 > t1 {
+            return false;
+        }
+    }
+    true
+}
+
+// Polygons of GeoJSON Polygon, MultiPolygon, Feature or FeatureCollection
+pub fn parse_geofence(value: &Value) -> Result<Vec<Polygon>, anyhow::Error> {
+    let polygons = match value.get("type").and_then(|t| t.as_str()) {
+        Some("FeatureCollection") => {
+            let features = value
+                .get("features")
+                .and_then(|f| f.as_array())
+                .ok_or(anyhow!("FeatureCollection has no 'features'"))?;
+            let mut polygons = Vec::new();
+            for feature in features {
+                polygons.extend(parse_geofence(feature)?);
+            }
+            polygons
+        }
+        Some("Feature") => parse_geofence(
+            value
+                .get("geometry")
+                .ok_or(anyhow!("Feature has no 'geometry'"))?,
+        )?,
+        Some("Polygon") => vec![parse_polygon(value.get("coordinates"))?],
+        Some("MultiPolygon") => value
+            .get("coordinates")
+            .and_then(|c| c.as_array())
+            .ok_or(anyhow!("MultiPolygon has no 'coordinates'"))?
+            .iter()
+            .map(|polygon| parse_polygon(Some(polygon)))
+            .collect::<Result<_, _>>()?,
+        Some(other) => return Err(anyhow!("GeoJSON type '{other}' is not a polygon")),
+        None => return Err(anyhow!("GeoJSON has no 'type'")),
+    };
+    if polygons.is_empty() {
+        return Err(anyhow!("GeoJSON has no polygons"));
+    }
+    Ok(polygons)
+}
+
+fn parse_polygon(coordinates: Option<&Value>) -> Result<Polygon, anyhow::Error> {
+    let rings = coordinates
+        .and_then(|c| c.as_array())
+        .filter(|rings| !rings.is_empty())
+        .ok_or(anyhow!("Polygon has no rings"))?;
+    let mut polygon = Vec::with_capacity(rings.len());
+    for ring in rings {
+        let mut points = Vec::new();
+        for position in ring.as_array().ok_or(anyhow!("Ring is not an array"))? {
+            let (lon, lat) = match position.as_array().map(|p| p.as_slice()) {
+                Some([lon, lat, ..]) => (lon.as_f64(), lat.as_f64()),
+                _ => (None, None),
+            };
+            match (lon, lat) {
+                (Some(lon), Some(lat))
+                    if (-180.0..=180.0).contains(&lon) && (-90.0..=90.0).contains(&lat) =>
+                {
+                    points.push([lon, lat]);
+                }
+                _ => return Err(anyhow!("Position {position} is not [lon, lat] in degrees")),
+            }
+        }
+        if points.len() < 3 {
+            return Err(anyhow!("Ring has less than 3 positions"));
+        }
+        if points.first() != points.last() {
+            points.push(points[0]);
+        }
+        polygon.push(points);
+    }
+    Ok(polygon)
+}
@@ -0,0 +1,313 @@
+use anyhow::anyhow;
+use serde::Serialize;
+
+use crate::defaults::{DEFAULT_TILE_GRID, MAXZOOM};
+
+const WEB_MERCATOR_EXTENT: f64 = 20037508.342789244;
+const WEB_MERCATOR_MAX_LAT: f64 = 85.0511287798066;
+
+/*
+    Tile matrix set: zoom 0 consists of 'matrix_width' x 'matrix_height' tiles
+    covering 'extent' [minx, miny, maxx, maxy] in 'crs', every next zoom doubles both sizes
+*/
+#[derive(Debug, Clone, Serialize)]
+pub struct TileGrid {
+    pub name: String,
+    pub crs: String,
+    pub extent: [f64; 4],
+    pub matrix_width: u64,
+    pub matrix_height: u64,
+    pub tile_size: u32,
+    pub minzoom: u8,
+    pub maxzoom: u8,
+}
+
+impl TileGrid {
+    pub fn web_mercator() -> Self {
+        TileGrid {
+            name: "WebMercatorQuad".to_string(),
+            crs: "EPSG:3857".to_string(),
+            extent: [
+                -WEB_MERCATOR_EXTENT,
+                -WEB_MERCATOR_EXTENT,
+                WEB_MERCATOR_EXTENT,
+                WEB_MERCATOR_EXTENT,
+            ],
+            matrix_width: 1,
+            matrix_height: 1,
+            tile_size: 256,
+            minzoom: 0,
+            maxzoom: MAXZOOM,
+        }
+    }
+
+    pub fn wgs84() -> Self {
+        TileGrid {
+            name: "WorldCRS84Quad".to_string(),
+            crs: "EPSG:4326".to_string(),
+            extent: [-180.0, -90.0, 180.0, 90.0],
+            matrix_width: 2,
+            matrix_height: 1,
+            tile_size: 256,
+            minzoom: 0,
+            maxzoom: MAXZOOM,
+        }
+    }
+
+    // Grid from JSON of 'config_app.json' or of the DataSource
+    pub fn from_json(value: &serde_json::Value) -> Result<Self, anyhow::Error> {
+        let name = value
+            .get("name")
+            .and_then(|n| n.as_str())
+            .ok_or(anyhow!("Tile grid 'name' is undefined"))?
+            .to_string();
+        let crs = value
+            .get("crs")
+            .and_then(|c| c.as_str())
+            .ok_or(anyhow!("Tile grid '{name}': 'crs' is undefined"))?
+            .to_string();
+        let extent: Vec<f64> = value
+            .get("extent")
+            .and_then(|e| e.as_array())
+            .map(|e| e.iter().filter_map(|v| v.as_f64()).collect())
+            .unwrap_or_default();
+        if extent.len() != 4 || extent[0] >= extent[2] || extent[1] >= extent[3] {
+            return Err(anyhow!(
+                "Tile grid '{name}': 'extent' must be [minx, miny, maxx, maxy]"
+            ));
+        }
+        let matrix_width = value
+            .get("matrix_width")
+            .and_then(|w| w.as_u64())
+            .unwrap_or(1)
+            .max(1);
+        let matrix_height = value
+            .get("matrix_height")
+            .and_then(|h| h.as_u64())
+            .unwrap_or(1)
+            .max(1);
+        let tile_size = value
+            .get("tile_size")
+            .and_then(|s| s.as_u64())
+            .unwrap_or(256) as u32;
+        let minzoom = value.get("minzoom").and_then(|z| z.as_u64()).unwrap_or(0) as u8;
+        let maxzoom = value
+            .get("maxzoom")
+            .and_then(|z| z.as_u64())
+            .unwrap_or(MAXZOOM as u64) as u8;
+        if minzoom > maxzoom || maxzoom > 30 {
+            return Err(anyhow!(
+                "Tile grid '{name}': zoom range {minzoom}-{maxzoom} is invalid"
+            ));
+        }
+
+        Ok(TileGrid {
+            name,
+            crs,
+            extent: [extent[0], extent[1], extent[2], extent[3]],
+            matrix_width,
+            matrix_height,
+            tile_size,
+            minzoom,
+            maxzoom,
+        })
+    }
+
+    // Number of tiles (columns, rows) at zoom
+    pub fn matrix_size(&self, z: u8) -> (u64, u64) {
+        (self.matrix_width << z, self.matrix_height << z)
+    }
+
+    // Longitude/latitude in the CRS of the grid, None for unsupported CRS
+    pub fn project(&self, lon: f64, lat: f64) -> Option<(f64, f64)> {
+        match self.crs.as_str() {
+            "EPSG:3857" | "EPSG:900913" => {
+                let lat = lat.clamp(-WEB_MERCATOR_MAX_LAT, WEB_MERCATOR_MAX_LAT);
+                let x = lon.to_radians() * 6378137.0;
+                let y = (std::f64::consts::FRAC_PI_4 + lat.to_radians() / 2.0)
+                    .tan()
+                    .ln()
+                    * 6378137.0;
+                Some((x, y))
+            }
+            "EPSG:4326" | "OGC:CRS84" | "CRS:84" => Some((lon, lat)),
+            _ => None,
+        }
+    }
+
+    // Coordinates in the CRS of the grid to longitude/latitude, None for unsupported CRS
+    pub fn unproject(&self, x: f64, y: f64) -> Option<(f64, f64)> {
+        match self.crs.as_str() {
+            "EPSG:3857" | "EPSG:900913" => {
+                let lon = (x / 6378137.0).to_degrees();
+                let lat =
+                    (2.0 * (y / 6378137.0).exp().atan() - std::f64::consts::FRAC_PI_2).to_degrees();
+                Some((lon, lat))
+            }
+            "EPSG:4326" | "OGC:CRS84" | "CRS:84" => Some((x, y)),
+            _ => None,
+        }
+    }
+
+    // Extent [minx, miny, maxx, maxy] of the tile in the CRS of the grid, rows in XYZ scheme
+    pub fn tile_extent(&self, z: u8, x: u64, y: u64) -> [f64; 4] {
+        let (width, height) = self.matrix_size(z);
+        let tile_width = (self.extent[2] - self.extent[0]) / width as f64;
+        let tile_height = (self.extent[3] - self.extent[1]) / height as f64;
+
+        let minx = self.extent[0] + x as f64 * tile_width;
+        let maxy = self.extent[3] - y as f64 * tile_height;
+        [minx, maxy - tile_height, minx + tile_width, maxy]
+    }
+
+    // Bounds [west, south, east, north] of the tile in degrees, rows in XYZ scheme
+    pub fn tile_bounds(&self, z: u8, x: u64, y: u64) -> Option<[f64; 4]> {
+        let [minx, miny, maxx, maxy] = self.tile_extent(z, x, y);
+        let (west, north) = self.unproject(minx, maxy)?;
+        let (east, south) = self.unproject(maxx, miny)?;
+        Some([west, south, east, north])
+    }
+
+    /*
+        Check if the tile intersects bounds [west, south, east, north] in degrees.
+        Rows are counted from the top of the extent (XYZ scheme).
+        For unsupported CRS and bounds crossing the antimeridian the tile is considered intersecting
+    */
+    pub fn tile_intersects(&self, bounds: &[f64; 4], z: u8, x: u64, y: u64) -> bool {
+        let [west, south, east, north] = *bounds;
+        if west > east {
+            return true;
+        }
+        let (Some((minx, miny)), Some((maxx, maxy))) =
+            (self.project(west, south), self.project(east, north))
+        else {
+            return true;
+        };
+
+        let (width, height) = self.matrix_size(z);
+        let tile_width = (self.extent[2] - self.extent[0]) / width as f64;
+        let tile_height = (self.extent[3] - self.extent[1]) / height as f64;
+
+        let col = |v: f64| ((v - self.extent[0]) / tile_width).floor();
+        let row = |v: f64| ((self.extent[3] - v) / tile_height).floor();
+        let (x, y) = (x as f64, y as f64);
+        x >= col(minx) && x <= col(maxx) && y >= row(maxy) && y <= row(miny)
+    }
+
+    /*
+        Tiles (min_x, min_y, max_x, max_y) covering bounds [west, south, east, north] in degrees
+        at zoom, rows in XYZ scheme. None for unsupported CRS and bounds crossing the antimeridian
+    */
+    pub fn tile_range(&self, bounds: &[f64; 4], z: u8) -> Option<(u64, u64, u64, u64)> {
+        let [west, south, east, north] = *bounds;
+        if west > east || south > north {
+            return None;
+        }
+        let (minx, miny) = self.project(west, south)?;
+        let (maxx, maxy) = self.project(east, north)?;
+
+        let (width, height) = self.matrix_size(z);
+        let tile_width = (self.extent[2] - self.extent[0]) / width as f64;
+        let tile_height = (self.extent[3] - self.extent[1]) / height as f64;
+
+        let col =
+            |v: f64| (((v - self.extent[0]) / tile_width).floor().max(0.0) as u64).min(width - 1);
+        let row =
+            |v: f64| (((self.extent[3] - v) / tile_height).floor().max(0.0) as u64).min(height - 1);
+        Some((col(minx), row(maxy), col(maxx), row(miny)))
+    }
+
+    // Overzoomed tiles are served deeper than 'maxzoom' of the grid
+    pub fn with_overzoom(mut self, overzoom_maxzoom: Option<u8>) -> Self {
+        if let Some(maxzoom) = overzoom_maxzoom {
+            self.maxzoom = self.maxzoom.max(maxzoom);
+        }
+        self
+    }
+
+    pub fn validate_tile(&self, z: u8, x: u64, y: u64) -> Result<(), anyhow::Error> {
+        if z < self.minzoom || z > self.maxzoom {
+            return Err(anyhow!(
+                "Requested zoom '{}' must be in range {}-{} of tile grid '{}'",
+                z,
+                self.minzoom,
+                self.maxzoom,
+                self.name
+            ));
+        }
+        let (width, height) = self.matrix_size(z);
+        if x >= width || y >= height {
+            return Err(anyhow!(
+                "Tile {z}/{x}/{y} is outside of tile grid '{}' ({width}x{height} tiles at zoom {z})",
+                self.name
+            ));
+        }
+        Ok(())
+    }
+}
+
+/*
+    Registry of tile grids: WebMercatorQuad (EPSG:3857), WorldCRS84Quad (EPSG:4326)
+    and custom grids from 'server.tile_grids' of the configuration.
+    Grids are searched by name or by CRS
+*/
+#[derive(Debug, Clone, Serialize)]
+#[serde(transparent)]
+pub struct TileGrids {
+    grids: Vec<TileGrid>,
+}
+
+impl TileGrids {
+    pub fn new(custom: Vec<TileGrid>) -> Self {
+        let mut grids = vec![TileGrid::web_mercator(), TileGrid::wgs84()];
+        for grid in custom {
+            // custom grid with the same name replaces the built-in one
+            grids.retain(|g| g.name != grid.name);
+            grids.push(grid);
+        }
+        TileGrids { grids }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&TileGrid> {
+        self.grids
+            .iter()
+            .find(|g| g.name == name)
+            .or_else(|| self.grids.iter().find(|g| g.crs == name))
+    }
+
+    pub fn default_grid(&self) -> &TileGrid {
+        self.get(DEFAULT_TILE_GRID)
+            .expect("Default tile grid is not registered")
+    }
+
+    /*
+        Grid of the tile request: 'grid' query parameter,
+        then the grid of the DataSource, then the default one
+    */
+    pub fn select(
+        &self,
+        query: Option<&str>,
+        datasource_grid: Option<&TileGrid>,
+    ) -> Result<TileGrid, anyhow::Error> {
+        let requested = query.and_then(|q| {
+            q.split('&')
+                .filter_map(|pair| pair.split_once('='))
+                .find(|(k, _)| *k == "grid")
+                .map(|(_, v)| urlencoding::decode(v).map(|v| v.to_string()))
+        });
+        match requested {
+            Some(name) => {
+                let name = name?;
+                if let Some(grid) = datasource_grid.filter(|g| g.name == name || g.crs == name) {
+                    return Ok(grid.clone());
+                }
+                self.get(&name)
+                    .cloned()
+                    .ok_or(anyhow!("Tile grid '{name}' is not registered"))
+            }
+            None => Ok(datasource_grid
+                .cloned()
+                .unwrap_or_else(|| self.default_grid().clone())),
+        }
+    }
+}
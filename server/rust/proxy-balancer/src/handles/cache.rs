@@ -1,138 +1,209 @@
 use std::convert::Infallible;
 use std::net::IpAddr;
 use std::path::PathBuf;
+use std::sync::Arc;
 
 use anyhow::anyhow;
-use hyper::{Body, Method, Request, Response, StatusCode};
-use serde_json::json;
-use tracing::{event, Level};
-
-use super::helpers::debug_request;
-use crate::db::get_mbtile;
-use crate::defaults::MAXZOOM;
-use crate::handles::helpers::response_with_body_and_code;
-use crate::structs::{ContentType, EndPoints, Extension};
-use crate::tasks::sqlite_clients::MessageSQLiteClient;
+use hyper::{
+    http::header::{self, HeaderMap, HeaderValue},
+    Body, Method, Request, Response, StatusCode,
+};
+use tiler_core::lookup::{lookup_tile, CachedTile};
+use tiler_core::manifest::mbtiles_path_for_tile;
+use tiler_core::mbtiles::save_mbtile;
+use tiler_core::sqlite_clients::MessageSQLiteClient;
+use tracing::{event, info_span, Instrument, Level};
+
+use super::endpoints::static_files::static_endpoint;
+use super::endpoints::styles::styles_endpoint;
+use super::helpers::{
+    apply_cors, debug_request, filter_tile_layers, negotiate_tile_encoding, options_response,
+    origin_not_allowed_response,
+};
+use crate::config::Config;
+use crate::defaults::REQUEST_ID_HEADER;
+use crate::forwarded::forwarded_client_ip;
+use crate::handles::error::{request_id, ApiError, REQUEST_ID};
+use crate::handles::helpers::empty_tile_response;
+use crate::handles::router::{route, Route};
+use crate::handles::version::route_version;
+use crate::log::otel::set_parent_from_headers;
+use crate::paths::Paths;
+use crate::remote::{OriginSource, RemoteTile};
+use crate::structs::{ContentType, Extension, MissingTile};
 use crate::utils::{
-    dataset_dir_from_uri, datasource_id_from_uri, file_path_from_uri, mbtiles_path_from_uri,
-    zxy_from_uri,
+    cached_tile_response, dataset_dir_from_uri, datasource_id_from_uri, file_path_from_uri,
+    get_scaled_tile, tile_response, tile_scale_from_uri, zxy_from_uri_in_grid,
 };
 
 pub async fn handle_cache(
-    cwd: String,
-    _client_ip: IpAddr,
+    paths: Paths,
+    client_ip: IpAddr,
+    mut req: Request<Body>,
+    tx_sqlite_client: flume::Sender<MessageSQLiteClient>,
+    static_root: PathBuf,
+    config: Config,
+    origin: Option<Arc<OriginSource>>,
+) -> Result<Response<Body>, Infallible> {
+    if req.method() == Method::OPTIONS {
+        return Ok(options_response(req.headers(), &config.cors));
+    }
+
+    let request_origin = req.headers().get(header::ORIGIN).cloned();
+    let cors = config.cors.clone();
+    let request_id = request_id(req.headers());
+    let client_ip = forwarded_client_ip(client_ip, req.headers_mut(), &config.proxy);
+    let span = info_span!(
+        "request",
+        method = %req.method(),
+        path = req.uri().path(),
+        request_id = %request_id,
+        client_ip = %client_ip
+    );
+    set_parent_from_headers(&span, req.headers());
+    let routed = route_version(&mut req);
+    let handled = async move {
+        if let Err(err) = routed {
+            return Ok(err.into_response());
+        }
+        cache_request(paths, req, tx_sqlite_client, static_root, config, origin).await
+    }
+    .instrument(span);
+    let mut response = REQUEST_ID.scope(request_id.clone(), handled).await?;
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    apply_cors(&mut response, request_origin.as_ref(), &cors);
+    Ok(response)
+}
+
+async fn cache_request(
+    paths: Paths,
     req: Request<Body>,
     tx_sqlite_client: flume::Sender<MessageSQLiteClient>,
-    base_path: PathBuf,
+    static_root: PathBuf,
+    config: Config,
+    origin: Option<Arc<OriginSource>>,
 ) -> Result<Response<Body>, Infallible> {
     let uri = req.uri().clone();
     let path = uri.path();
     let method = req.method().clone();
+    let route = route(&method, path);
 
-    if path.starts_with(EndPoints::Tile.as_ref()) && method == Method::GET {
-        match tile_from_cache(&cwd, path, tx_sqlite_client).await {
+    if matches!(route, Some(Route::Tile { .. })) && method == Method::GET {
+        let origin = origin.as_deref();
+        let accept_encoding = req
+            .headers()
+            .get(header::ACCEPT_ENCODING)
+            .and_then(|ae| ae.to_str().ok());
+        let tile = tile_from_cache(
+            &paths,
+            path,
+            uri.query(),
+            req.headers(),
+            tx_sqlite_client,
+            &config,
+            origin,
+        );
+        match tile.await {
             Err(err) => {
                 event!(Level::ERROR, "Error 'tile_from_cache': {err:?}");
             }
-            Ok(response) => return Ok(response),
+            Ok(response) => match filter_tile_layers(response, uri.query()).await {
+                Err(err) => event!(Level::ERROR, "Error 'filter_tile_layers': {err:?}"),
+                Ok(response) => match negotiate_tile_encoding(response, accept_encoding).await {
+                    Err(err) => event!(Level::ERROR, "Error 'negotiate_tile_encoding': {err:?}"),
+                    Ok(response) => return Ok(response),
+                },
+            },
         }
-    } else if path.starts_with(EndPoints::Static.as_ref()) && method == Method::GET {
-        match static_files(path, base_path).await {
+    } else if route == Some(Route::Static) {
+        match static_endpoint(path, &static_root, &config).await {
             Err(err) => {
-                let body = json!({
-                    "status": StatusCode::INTERNAL_SERVER_ERROR.as_u16(),
-                    "message": err.to_string()
-                })
-                .to_string();
-                let response = response_with_body_and_code(body, StatusCode::INTERNAL_SERVER_ERROR);
+                let response = ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, err.to_string())
+                    .into_response();
                 return Ok(response);
             }
             Ok(response) => return Ok(response),
         }
+    } else if route == Some(Route::Styles) {
+        match styles_endpoint(&paths, path, &method, req.headers()).await {
+            Err(err) => {
+                let response = ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, err.to_string())
+                    .into_response();
+                return Ok(response);
+            }
+            Ok(Some(response)) => return Ok(response),
+            Ok(None) => {}
+        }
     }
 
     debug_request(req)
 }
 
-async fn static_files(path: &str, base_path: PathBuf) -> Result<Response<Body>, anyhow::Error> {
-    let mut file_path = base_path;
-    for part in path.split('/').skip(1) {
-        // skip the first empty part
-        file_path.push(part);
+/*
+    Tiles of DataSources of the root with 'allowed_origins' are served only to requests of these
+    websites, both from the cache and from the origin. The policy is attached to the response for CORS
+*/
+async fn tile_from_cache(
+    paths: &Paths,
+    path: &str,
+    query: Option<&str>,
+    headers: &HeaderMap<HeaderValue>,
+    tx_sqlite_client: flume::Sender<MessageSQLiteClient>,
+    config: &Config,
+    origin: Option<&OriginSource>,
+) -> Result<Response<Body>, anyhow::Error> {
+    let allowed_origins = datasource_id_from_uri(path)
+        .ok()
+        .and_then(|datasource_id| config.serve_cache.allowed_origins(datasource_id));
+    if let Some(allowed) = allowed_origins {
+        if !allowed.allows(headers) {
+            return Ok(origin_not_allowed_response(headers));
+        }
     }
 
-    if let Err(err) = tokio::fs::try_exists(&file_path).await {
-        let body = json!({
-            "status": StatusCode::BAD_REQUEST.as_u16(),
-            "message": err.to_string()
-        })
-        .to_string();
-        let response = response_with_body_and_code(body, StatusCode::BAD_REQUEST);
-        return Ok(response);
+    let mut response = cached_tile(paths, path, query, tx_sqlite_client, config, origin).await?;
+    if let Some(allowed) = allowed_origins {
+        response.extensions_mut().insert(allowed.clone());
     }
-
-    let mime_type = mime_guess::from_path(&file_path).first_or_octet_stream();
-    let file_path_str = file_path.to_str().ok_or(anyhow!(
-        "Error converting file path '{:?}' to string",
-        file_path
-    ))?;
-    let file_path_dec = urlencoding::decode(file_path_str)?.to_string();
-
-    let response = match tokio::fs::read(&file_path_dec).await {
-        Ok(data) => Response::builder()
-            .status(StatusCode::OK)
-            .header("Content-Type", mime_type.as_ref())
-            .header("Access-Control-Allow-Origin", "*")
-            .header("Access-Control-Allow-Headers", "*")
-            .header("Access-Control-Allow-Methods", "POST, GET, OPTIONS")
-            .body(Body::from(data))?,
-        Err(err) => {
-            let body = json!({
-                "message": err.to_string()
-            })
-            .to_string();
-            Response::builder()
-                .status(StatusCode::NOT_FOUND)
-                .body(Body::from(body))?
-        }
-    };
-
     Ok(response)
 }
 
-async fn tile_from_cache(
-    cwd: &str,
+async fn cached_tile(
+    paths: &Paths,
     path: &str,
+    query: Option<&str>,
     tx_sqlite_client: flume::Sender<MessageSQLiteClient>,
+    config: &Config,
+    origin: Option<&OriginSource>,
 ) -> Result<Response<Body>, anyhow::Error> {
-    let _dataset_dir = match dataset_dir_from_uri(&cwd, path) {
+    let default_dir = match dataset_dir_from_uri(paths, path) {
         Ok(d) => d,
         Err(err) => {
-            let body = json!({
-                "status": StatusCode::BAD_REQUEST.as_u16(),
-                "message": err.to_string()
-            })
-            .to_string();
-            let response = response_with_body_and_code(body, StatusCode::BAD_REQUEST);
+            let response = ApiError::new(StatusCode::BAD_REQUEST, err.to_string()).into_response();
             return Ok(response);
         }
     };
-    let _datasource_id = datasource_id_from_uri(path)
+    let datasource_id = datasource_id_from_uri(path)
         .expect(&format!("Error extract datasource_id from URI: {}", path));
+    let dataset_dir = match cache_dataset_dir(paths, datasource_id, config).await {
+        Ok(d) => d,
+        Err(response) => return Ok(response),
+    };
+    let missing = config
+        .serve_cache
+        .missing_tile(datasource_id)
+        .unwrap_or(config.missing_tile);
 
-    let file_tile_path = match file_path_from_uri(&cwd, path) {
-        Ok(p) => p,
+    // '{z}/{x}/{y}.{ext}' of the tile in the directory of the DataSource in its root
+    let file_tile_path = match file_path_from_uri(paths, path) {
+        Ok(p) => dataset_dir.join(p.strip_prefix(&default_dir)?),
         Err(err) => {
-            let body = json!({
-                "status": StatusCode::BAD_REQUEST.as_u16(),
-                "message": err.to_string()
-            })
-            .to_string();
-            let response = response_with_body_and_code(body, StatusCode::BAD_REQUEST);
+            let response = ApiError::new(StatusCode::BAD_REQUEST, err.to_string()).into_response();
             return Ok(response);
         }
     };
-
     let ext = file_tile_path.extension().ok_or(anyhow!(
         "Extension of tile file '{:?}' is None",
         file_tile_path
@@ -140,63 +211,216 @@ async fn tile_from_cache(
     let png: &str = Extension::Png.into();
     let mvt: &str = Extension::Mvt.into();
     let pbf: &str = Extension::Pbf.into();
+    let webp: &str = Extension::Webp.into();
     let content_type = if ext == png {
         ContentType::Png.as_ref()
+    } else if ext == webp {
+        ContentType::Webp.as_ref()
     } else if ext == mvt || ext == pbf {
         ContentType::MvtPbf.as_ref()
     } else {
         ContentType::Empty.as_ref()
     };
 
-    let (x, y, z) = match zxy_from_uri(path) {
+    // without PostgreSQL only the grid of the query parameter is known
+    let grid = config.tile_grids.select(query, None);
+    let (x, y, z) = match grid.and_then(|g| zxy_from_uri_in_grid(path, &g)) {
         Ok(xyz) => xyz,
         Err(err) => {
-            let body = json!({
-                "status": StatusCode::BAD_REQUEST.as_u16(),
-                "message": err.to_string()
-            })
-            .to_string();
-            let response = response_with_body_and_code(body, StatusCode::BAD_REQUEST);
+            let response = ApiError::new(StatusCode::BAD_REQUEST, err.to_string()).into_response();
             return Ok(response);
         }
     };
 
-    if z > MAXZOOM {
-        let body = json!({
-            "status": StatusCode::BAD_REQUEST.as_u16(),
-            "message": format!("Requested zoom '{}' must be in range 0-{}", z, MAXZOOM)
-        })
-        .to_string();
-        let response = response_with_body_and_code(body, StatusCode::BAD_REQUEST);
+    // '@2x' and '@0.5x' raster tiles are resized from the cached tile
+    if let Ok(Some(scale)) = tile_scale_from_uri(path) {
+        if content_type != ContentType::Png.as_ref() {
+            let response = ApiError::new(
+                StatusCode::BAD_REQUEST,
+                format!(
+                    "Scale suffix is supported only for '{}' tiles",
+                    Extension::Png
+                ),
+            )
+            .into_response();
+            return Ok(response);
+        }
+        let mbtiles_path = mbtiles_path_for_tile(dataset_dir, z, x, y).await.ok();
+        if let Some(response) = get_scaled_tile(
+            &file_tile_path,
+            mbtiles_path,
+            (x, y, z),
+            scale,
+            tx_sqlite_client.clone(),
+            config.integrity,
+        )
+        .await?
+        {
+            return Ok(response);
+        }
+        // scaled tiles are not stored, the MBTiles keep tiles of the original size
+        if let Some(origin) = origin {
+            return tile_from_origin(
+                origin,
+                path,
+                query,
+                None,
+                content_type,
+                missing,
+                tx_sqlite_client,
+                config,
+            )
+            .await;
+        }
+        return empty_tile_response(content_type, missing, config);
+    }
+
+    // tiles of the cache are looked up like tiles of the main server: file on disk, then MBTiles
+    let lookup = lookup_tile(
+        dataset_dir,
+        &file_tile_path,
+        (x, y, z),
+        tx_sqlite_client.clone(),
+        config.integrity,
+    )
+    .await?;
+    // zero-length tiles of the cache are tiles without data
+    let cached = match lookup.tile {
+        CachedTile::Empty => Some(empty_tile_response(content_type, missing, config)?),
+        tile => cached_tile_response(tile, content_type)?,
+    };
+    if let Some(response) = cached {
+        return Ok(response);
+    }
+    let mbtiles_path = lookup.mbtiles_path;
+    if let Some(origin) = origin {
+        let store = mbtiles_path
+            .filter(|_| origin.store)
+            .map(|p| (p, (x, y, z)));
+        return tile_from_origin(
+            origin,
+            path,
+            query,
+            store,
+            content_type,
+            missing,
+            tx_sqlite_client,
+            config,
+        )
+        .await;
+    }
+    if mbtiles_path.is_none() {
+        let error =
+            format!("{path}: DataBase of MBTiles for DataSource ID '{datasource_id}' not found");
+        event!(Level::ERROR, "{error}");
+        let response = ApiError::new(StatusCode::BAD_REQUEST, error).into_response();
         return Ok(response);
     }
 
-    match mbtiles_path_from_uri(&cwd, path).await {
-        Ok(p) => {
-            if let Ok(Some(response)) =
-                get_mbtile(&p, z as u64, x, y, content_type, tx_sqlite_client).await
-            {
-                return Ok(response);
+    empty_tile_response(content_type, missing, config)
+}
+
+/*
+    Tile missing in the local cache is requested from the origin tiler by the same path,
+    with 'origin.store' the tile is saved to the local MBTiles of the DataSource
+*/
+async fn tile_from_origin(
+    origin: &OriginSource,
+    path: &str,
+    query: Option<&str>,
+    store: Option<(PathBuf, (u64, u64, u8))>,
+    content_type: &str,
+    missing: MissingTile,
+    tx_sqlite_client: flume::Sender<MessageSQLiteClient>,
+    config: &Config,
+) -> Result<Response<Body>, anyhow::Error> {
+    let path_and_query = match query {
+        Some(query) => format!("{path}?{query}"),
+        None => path.to_string(),
+    };
+    let fetched = origin
+        .fetch(&path_and_query)
+        .instrument(info_span!("origin_tile", path))
+        .await;
+    match fetched {
+        Ok(RemoteTile::Tile(tile)) => {
+            if let Some((mbtiles_db, (x, y, z))) = store {
+                if let Err(err) = save_mbtile(
+                    &mbtiles_db,
+                    z as u64,
+                    x,
+                    y,
+                    tile.clone(),
+                    tx_sqlite_client,
+                    config.integrity,
+                )
+                .await
+                {
+                    event!(
+                        Level::ERROR,
+                        "Error save tile '{path}' of the origin to {mbtiles_db:?}: {err}"
+                    );
+                }
             }
+            tile_response(tile, content_type)
         }
+        Ok(_) => empty_tile_response(content_type, missing, config),
         Err(err) => {
-            let error = format!("{path}: {err:?}");
-            event!(Level::ERROR, "{error}");
-            let body = json!({
-                "status": StatusCode::BAD_REQUEST.as_u16(),
-                "message": error
-            })
-            .to_string();
-            let response = response_with_body_and_code(body, StatusCode::BAD_REQUEST);
-            return Ok(response);
+            event!(
+                Level::ERROR,
+                "Error fetch tile '{path}' from the origin: {err}"
+            );
+            Ok(ApiError::new(StatusCode::BAD_GATEWAY, err.to_string()).into_response())
         }
+    }
+}
+
+/*
+    Directory of the DataSource in the root of 'serve_cache.roots' matching its id.
+    DataSources without a root and directories leading out of the root (symlinks) are refused
+*/
+async fn cache_dataset_dir(
+    paths: &Paths,
+    datasource_id: &str,
+    config: &Config,
+) -> Result<PathBuf, Response<Body>> {
+    let forbidden = || {
+        ApiError::new(
+            StatusCode::FORBIDDEN,
+            format!("DataSource '{datasource_id}' is outside of the roots of the cache"),
+        )
+        .into_response()
+    };
+    if datasource_id.is_empty()
+        || datasource_id.contains(['/', '\\'])
+        || datasource_id == "."
+        || datasource_id == ".."
+    {
+        return Err(forbidden());
+    }
+    let Some(root) = config.serve_cache.root_dir(paths, datasource_id) else {
+        return Err(forbidden());
     };
 
-    let response = Response::builder()
-        .status(StatusCode::NO_CONTENT)
-        .header("Content-Length", 0)
-        .header("Access-Control-Allow-Origin", "*")
-        .header("Cache-Control", "max-age=0")
-        .body(Body::empty())?;
-    return Ok(response);
+    let canonical = match tokio::fs::canonicalize(&root).await {
+        Ok(root_canonical) => tokio::fs::canonicalize(root.join(datasource_id))
+            .await
+            .map(|dir_canonical| (root_canonical, dir_canonical)),
+        Err(err) => Err(err),
+    };
+    match canonical {
+        Ok((root_canonical, dir_canonical)) => {
+            if !dir_canonical.starts_with(&root_canonical) {
+                return Err(forbidden());
+            }
+            Ok(dir_canonical)
+        }
+        // tiles of the DataSource are only at the origin
+        Err(_) if config.serve_cache.origin.is_some() => Ok(root.join(datasource_id)),
+        Err(err) => Err(ApiError::new(
+            StatusCode::NOT_FOUND,
+            format!("Tiles of DataSource '{datasource_id}': {err}"),
+        )
+        .into_response()),
+    }
 }
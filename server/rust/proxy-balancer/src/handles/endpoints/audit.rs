@@ -0,0 +1,51 @@
+use hyper::{Body, Response, StatusCode};
+use serde_json::json;
+use tokio::sync::oneshot;
+
+use crate::defaults::{AUDIT_LIMIT, AUDIT_MAX_LIMIT};
+use crate::handles::helpers::response_with_body_and_code;
+use crate::tasks::audit::{AuditFilter, MessageAudit};
+
+/*
+    GET /api/audit?since=1700000000&until=1700086400&action=datasource_delete&datasource_id=roads
+    &actor=bearer:0123456789abcdef&limit=100
+    returns actions of the audit log matching all given filters, the latest go first.
+    '/maintenance/audit' is the same log
+*/
+pub async fn audit_endpoint(
+    query: Option<&str>,
+    tx_audit: flume::Sender<MessageAudit>,
+) -> Result<Response<Body>, anyhow::Error> {
+    let param = |name: &str| {
+        query
+            .and_then(|q| {
+                q.split('&')
+                    .filter_map(|pair| pair.split_once('='))
+                    .find(|(k, _)| *k == name)
+                    .and_then(|(_, v)| urlencoding::decode(v).ok())
+                    .map(|v| v.to_string())
+            })
+            .filter(|v| !v.is_empty())
+    };
+    let filter = AuditFilter {
+        since: param("since")
+            .and_then(|v| v.parse::<i64>().ok())
+            .unwrap_or(0),
+        until: param("until").and_then(|v| v.parse::<i64>().ok()),
+        action: param("action"),
+        datasource_id: param("datasource_id"),
+        actor: param("actor"),
+        limit: param("limit")
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(AUDIT_LIMIT)
+            .min(AUDIT_MAX_LIMIT),
+    };
+
+    let (tx_entries, rx_entries) = oneshot::channel();
+    tx_audit
+        .send_async(MessageAudit::List { filter, tx_entries })
+        .await?;
+    let entries = rx_entries.await??;
+    let body = json!({ "entries": entries }).to_string();
+    Ok(response_with_body_and_code(body, StatusCode::OK))
+}
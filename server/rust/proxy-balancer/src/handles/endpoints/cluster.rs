@@ -0,0 +1,76 @@
+use hyper::body::Bytes;
+use hyper::http::header::{HeaderMap, HeaderValue};
+use hyper::{Body, Method, Response, StatusCode};
+use serde_json::json;
+use tokio::sync::oneshot;
+
+use crate::config::Config;
+use crate::defaults::CLUSTER_SIGNATURE_HEADER;
+use crate::handles::error::ApiError;
+use crate::handles::helpers::{json_body, required_str, response_with_body_and_code};
+use crate::structs::EndPoints;
+use crate::tasks::cluster::MessageCluster;
+
+pub async fn cluster_endpoint(
+    path: &str,
+    method: &Method,
+    headers: &HeaderMap<HeaderValue>,
+    b_bytes: &Bytes,
+    tx_cluster: flume::Sender<MessageCluster>,
+    config: &Config,
+) -> Result<Option<Response<Body>>, anyhow::Error> {
+    if path.starts_with(EndPoints::ClusterRegister.as_ref()) && method == Method::POST {
+        // signature itself is verified in 'handle', here only require it
+        if config.cluster_secret.is_some() && !headers.contains_key(CLUSTER_SIGNATURE_HEADER) {
+            let error = ApiError::new(
+                StatusCode::UNAUTHORIZED,
+                "Registration request must be signed",
+            );
+            return Ok(Some(error.into_response()));
+        }
+        // without the secret anyone could register a node and receive requests of tiles
+        if config.cluster_secret.is_none() && !config.cluster_allow_unauthenticated {
+            let error = ApiError::new(
+                StatusCode::FORBIDDEN,
+                "Registration of nodes requires 'CLUSTER_SECRET' or 'cluster.allow_unauthenticated'",
+            );
+            return Ok(Some(error.into_response()));
+        }
+
+        let body_json = json_body(b_bytes)?;
+        let address = required_str(&body_json, "address")?.to_string();
+        let datasources: Vec<String> = body_json
+            .get("datasources")
+            .and_then(|dss| dss.as_array())
+            .map(|dss| {
+                dss.iter()
+                    .filter_map(|ds| ds.as_str().map(|s| s.to_string()))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        tx_cluster
+            .send_async(MessageCluster::Register {
+                address: address.clone(),
+                datasources,
+            })
+            .await?;
+
+        let body = json!({
+            "status": StatusCode::OK.as_u16(),
+            "message": format!("Node '{address}' successfully registered")
+        })
+        .to_string();
+        return Ok(Some(response_with_body_and_code(body, StatusCode::OK)));
+    } else if path.starts_with(EndPoints::ClusterNodes.as_ref()) && method == Method::GET {
+        let (tx_nodes, rx_nodes) = oneshot::channel();
+        tx_cluster
+            .send_async(MessageCluster::GetNodes { tx_nodes })
+            .await?;
+        let nodes = rx_nodes.await?;
+        let body = serde_json::to_string(&nodes)?;
+        return Ok(Some(response_with_body_and_code(body, StatusCode::OK)));
+    }
+
+    Ok(None)
+}
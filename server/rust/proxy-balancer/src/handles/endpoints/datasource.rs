@@ -1,20 +1,27 @@
 use std::net::IpAddr;
-use std::path::PathBuf;
 
 use anyhow::anyhow;
 use hyper::{
-    body::Bytes, client::HttpConnector, http::header, http::request::Parts, Body, Client, Method,
-    Request, Response, StatusCode, Uri,
+    body::Bytes, client::HttpConnector, http::request::Parts, Body, Client, Method, Request,
+    Response, StatusCode, Uri,
 };
 use serde_json::json;
+use tiler_core::sqlite_clients::MessageSQLiteClient;
+use tokio::sync::oneshot;
 use tracing::{event, Level};
 
 use crate::defaults::{LOCALHOST, MASTER_HEADER};
-use crate::handles::helpers::error_response;
+use crate::handles::error::ApiError;
+use crate::handles::headers::ResponseBuilderExt;
+use crate::handles::helpers::{
+    error_response, json_body, required_str, response_with_body_and_code,
+};
 use crate::hyper_reverse_proxy::{self, ProxyError};
+use crate::paths::Paths;
 use crate::structs::EndPoints;
 use crate::tasks::datasources::MessageDatasource;
-use crate::tasks::sqlite_clients::MessageSQLiteClient;
+use crate::tasks::usage::MessageUsage;
+use crate::utils::mbtiles_path_from_ds_id;
 
 pub async fn datasource_endpoint(
     port: u16,
@@ -92,7 +99,7 @@ pub async fn datasource_endpoint(
 }
 
 pub async fn datasource_delete_endpoint(
-    cwd: &str,
+    paths: &Paths,
     ports: Vec<u16>,
     parts: Parts,
     uri: Uri,
@@ -101,8 +108,24 @@ pub async fn datasource_delete_endpoint(
     client: Client<HttpConnector>,
     tx_sqlite_client: flume::Sender<MessageSQLiteClient>,
     tx: flume::Sender<MessageDatasource>,
+    tx_usage: flume::Sender<MessageUsage>,
+    trash_retention_days: u64,
     ct: &str,
 ) -> Result<Response<Body>, anyhow::Error> {
+    // the body is validated before the request is forwarded to workers
+    let body_json = json_body(&b_bytes)?;
+    let datasource_id = required_str(&body_json, "datasource_id")?;
+    if body_json.get("soft").and_then(|s| s.as_bool()) == Some(true) {
+        return soft_delete_datasource(
+            paths,
+            datasource_id,
+            tx_sqlite_client,
+            tx,
+            trash_retention_days,
+        )
+        .await;
+    }
+
     let mut is_err = false;
     let mut err: Option<ProxyError> = None;
     let mut handles = Vec::with_capacity(ports.len());
@@ -152,12 +175,6 @@ pub async fn datasource_delete_endpoint(
         }
     }
 
-    let body_json: serde_json::Value = serde_json::from_slice(&b_bytes)?;
-    let datasource_id = body_json
-        .get("datasource_id")
-        .and_then(|id| id.as_str())
-        .expect("datasource_id is undefined");
-
     let status_code: StatusCode;
     let message: String;
     if is_err {
@@ -169,17 +186,11 @@ pub async fn datasource_delete_endpoint(
     }
 
     {
-        let mbtiles_db: String = [
-            cwd,
-            "tiles",
-            &datasource_id,
-            &format!("{}.mbtiles", datasource_id),
-        ]
-        .iter()
-        .collect::<PathBuf>()
-        .into_os_string()
-        .into_string()
-        .map_err(|err| anyhow!("{err:?}"))?;
+        let mbtiles_db: String = paths
+            .mbtiles_db(datasource_id)
+            .into_os_string()
+            .into_string()
+            .map_err(|err| anyhow!("{err:?}"))?;
 
         if let Err(err) = tx_sqlite_client
             .send_async(MessageSQLiteClient::RemoveSQLiteClient {
@@ -195,6 +206,18 @@ pub async fn datasource_delete_endpoint(
             );
         }
 
+        if let Err(err) = tx_usage
+            .send_async(MessageUsage::Remove {
+                datasource_id: datasource_id.to_string(),
+            })
+            .await
+        {
+            event!(
+                Level::ERROR,
+                "Error send message to remove tile usage {err:?}"
+            );
+        }
+
         // this endpoint does not trigger calls 'worker_load_dss' function
         let is_header_master = false;
         if let Err(err) = tx
@@ -209,6 +232,9 @@ pub async fn datasource_delete_endpoint(
         }
     }
 
+    if !status_code.is_success() {
+        return Ok(ApiError::new(status_code, message).into_response());
+    }
     let body = json!({
         "status": status_code.as_u16(),
         "message": message
@@ -216,8 +242,96 @@ pub async fn datasource_delete_endpoint(
     .to_string();
     let response = Response::builder()
         .status(status_code)
-        .header(header::CONTENT_TYPE, ct)
-        .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+        .content_type(ct)
         .body(Body::from(body))?;
     Ok(response)
 }
+
+/*
+    Soft delete keeps the DataSource in the database: its cache is moved to the trash
+    for 'trash.retention_days', tiles are answered with 410 until the DataSource is restored
+*/
+async fn soft_delete_datasource(
+    paths: &Paths,
+    datasource_id: &str,
+    tx_sqlite_client: flume::Sender<MessageSQLiteClient>,
+    tx: flume::Sender<MessageDatasource>,
+    retention_days: u64,
+) -> Result<Response<Body>, anyhow::Error> {
+    let (tx_ds, rx_ds) = oneshot::channel();
+    tx.send_async(MessageDatasource::GetDataSource {
+        datasource_id: datasource_id.to_string(),
+        tx_ds,
+    })
+    .await?;
+    if rx_ds.await?.is_none() {
+        return Ok(ApiError::datasource_not_found(datasource_id).into_response());
+    }
+
+    // clients of MBTiles are closed before the cache is moved
+    let mbtiles_db = mbtiles_path_from_ds_id(paths, datasource_id)?
+        .into_os_string()
+        .into_string()
+        .map_err(|err| anyhow!("{err:?}"))?;
+    tx_sqlite_client
+        .send_async(MessageSQLiteClient::RemoveSQLiteClient {
+            mbtiles_db,
+            remove_tiles_folder: None,
+            remove_tiles_db: None,
+        })
+        .await?;
+
+    let (tx_entry, rx_entry) = oneshot::channel();
+    tx.send_async(MessageDatasource::SoftDelete {
+        datasource_id: datasource_id.to_string(),
+        retention_days,
+        tx_entry,
+    })
+    .await?;
+    let entry = rx_entry.await??;
+
+    let body = json!({
+        "status": StatusCode::OK.as_u16(),
+        "message": format!("DataSource '{datasource_id}' successfully moved to trash"),
+        "deleted_at": entry.deleted_at,
+        "expires_at": entry.expires_at,
+    })
+    .to_string();
+    Ok(response_with_body_and_code(body, StatusCode::OK))
+}
+
+// DataSource and its cache are restored from the trash: /api/datasources/{id}/restore
+pub async fn datasource_restore_endpoint(
+    datasource_id: &str,
+    tx: flume::Sender<MessageDatasource>,
+) -> Result<Response<Body>, anyhow::Error> {
+    let (tx_ds, rx_ds) = oneshot::channel();
+    tx.send_async(MessageDatasource::GetDataSource {
+        datasource_id: datasource_id.to_string(),
+        tx_ds,
+    })
+    .await?;
+    if rx_ds.await?.is_none() {
+        return Ok(ApiError::datasource_not_found(datasource_id).into_response());
+    }
+
+    let (tx_restored, rx_restored) = oneshot::channel();
+    tx.send_async(MessageDatasource::Restore {
+        datasource_id: datasource_id.to_string(),
+        tx_restored,
+    })
+    .await?;
+    if !rx_restored.await?? {
+        let error = ApiError::new(
+            StatusCode::CONFLICT,
+            format!("DataSource '{datasource_id}' is not in the trash"),
+        );
+        return Ok(error.into_response());
+    }
+    let body = json!({
+        "status": StatusCode::OK.as_u16(),
+        "message": format!("DataSource '{datasource_id}' successfully restored")
+    })
+    .to_string();
+    Ok(response_with_body_and_code(body, StatusCode::OK))
+}
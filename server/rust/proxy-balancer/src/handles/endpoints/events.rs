@@ -0,0 +1,45 @@
+use std::time::Duration;
+
+use hyper::body::Bytes;
+use hyper::{Body, Response, StatusCode};
+use tokio::sync::oneshot;
+
+use crate::defaults::EVENTS_KEEPALIVE_SECS;
+use crate::handles::headers::{CacheControl, ResponseBuilderExt};
+use crate::structs::ContentType;
+use crate::tasks::events::MessageEvents;
+
+pub async fn events_endpoint(
+    tx_events: flume::Sender<MessageEvents>,
+) -> Result<Response<Body>, anyhow::Error> {
+    let (tx_sub, rx_sub) = oneshot::channel();
+    tx_events
+        .send_async(MessageEvents::Subscribe { tx_sub })
+        .await?;
+    let rx_event = rx_sub.await?;
+
+    let (mut sender, body) = Body::channel();
+    tokio::spawn(async move {
+        let mut keepalive = tokio::time::interval(Duration::from_secs(EVENTS_KEEPALIVE_SECS));
+        loop {
+            let chunk = tokio::select! {
+                message = rx_event.recv_async() => match message {
+                    Ok(m) => m,
+                    Err(_) => break,
+                },
+                _ = keepalive.tick() => ": keepalive\n\n".to_string(),
+            };
+            // client closed the connection
+            if sender.send_data(Bytes::from(chunk)).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let response = Response::builder()
+        .status(StatusCode::OK)
+        .content_type(ContentType::EventStream.as_ref())
+        .cache_control(CacheControl::NoCache)
+        .body(body)?;
+    Ok(response)
+}
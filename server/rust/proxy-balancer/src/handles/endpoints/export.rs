@@ -0,0 +1,247 @@
+use hyper::{body::Bytes, http::header, Body, Response, StatusCode};
+use serde_json::json;
+use tokio::io::AsyncReadExt;
+use tokio::sync::oneshot;
+
+use crate::config::Config;
+use crate::db::export::{ExportArea, ZoomRange};
+use crate::handles::error::ApiError;
+use crate::handles::headers::ResponseBuilderExt;
+use crate::handles::helpers::response_with_body_and_code;
+use crate::paths::Paths;
+use crate::structs::{ContentType, EndPoints, Extension};
+use crate::tasks::datasources::MessageDatasource;
+use crate::tasks::events::MessageEvents;
+use crate::tasks::exports::{start_export, ExportState, MessageExports};
+
+const DOWNLOAD_CHUNK_SIZE: usize = 64 * 1024;
+
+fn export_error_response(message: String, code: StatusCode) -> Response<Body> {
+    ApiError::new(code, message).into_response()
+}
+
+/*
+    POST /api/export/{datasource_id} with body
+    {"bbox": [west, south, east, north], "minzoom": 0, "maxzoom": 14, "format": "png"}
+    starts assembling of the offline package (MBTiles) from the cache of the DataSource
+*/
+pub async fn export_endpoint(
+    paths: &Paths,
+    datasource_id: &str,
+    b_bytes: &Bytes,
+    tx: flume::Sender<MessageDatasource>,
+    tx_exports: flume::Sender<MessageExports>,
+    tx_events: flume::Sender<MessageEvents>,
+    config: &Config,
+) -> Result<Response<Body>, anyhow::Error> {
+    let (tx_ds, rx_ds) = oneshot::channel();
+    tx.send_async(MessageDatasource::GetDataSource {
+        datasource_id: datasource_id.to_string(),
+        tx_ds,
+    })
+    .await?;
+    let ds = match rx_ds.await? {
+        Some(ds) => ds,
+        None => {
+            return Ok(export_error_response(
+                format!("DataSource '{datasource_id}' not found"),
+                StatusCode::NOT_FOUND,
+            ));
+        }
+    };
+
+    let body: serde_json::Value = match serde_json::from_slice(b_bytes) {
+        Ok(b) => b,
+        Err(err) => {
+            return Ok(export_error_response(
+                format!("Body of export request is invalid: {err}"),
+                StatusCode::BAD_REQUEST,
+            ));
+        }
+    };
+
+    let bounds: Option<[f64; 4]> = body
+        .get("bbox")
+        .and_then(|b| serde_json::from_value(b.clone()).ok());
+    let Some(bounds) = bounds else {
+        return Ok(export_error_response(
+            "Parameter 'bbox' must be an array [west, south, east, north]".to_string(),
+            StatusCode::BAD_REQUEST,
+        ));
+    };
+
+    let grid = ds
+        .tile_grid
+        .clone()
+        .unwrap_or_else(|| config.tile_grids.default_grid().clone());
+    let minzoom = body
+        .get("minzoom")
+        .and_then(|z| z.as_u64())
+        .map(|z| z as u8)
+        .or(ds.minzoom)
+        .unwrap_or(grid.minzoom);
+    let maxzoom = body
+        .get("maxzoom")
+        .and_then(|z| z.as_u64())
+        .map(|z| z as u8)
+        .or(ds.maxzoom)
+        .unwrap_or(grid.maxzoom);
+    if minzoom > maxzoom || minzoom < grid.minzoom || maxzoom > grid.maxzoom {
+        return Ok(export_error_response(
+            format!(
+                "Zoom range {minzoom}-{maxzoom} must be in range {}-{} of tile grid '{}'",
+                grid.minzoom, grid.maxzoom, grid.name
+            ),
+            StatusCode::BAD_REQUEST,
+        ));
+    }
+
+    let format = body
+        .get("format")
+        .and_then(|f| f.as_str())
+        .unwrap_or(Extension::Png.as_ref())
+        .to_lowercase();
+    if format.parse::<Extension>().is_err() {
+        return Ok(export_error_response(
+            format!("Format of tiles '{format}' is not supported"),
+            StatusCode::BAD_REQUEST,
+        ));
+    }
+
+    let mut ranges = Vec::new();
+    for z in minzoom..=maxzoom {
+        let Some((min_x, min_y, max_x, max_y)) = grid.tile_range(&bounds, z) else {
+            return Ok(export_error_response(
+                format!(
+                    "Bounds {bounds:?} are invalid for tile grid '{}' ({})",
+                    grid.name, grid.crs
+                ),
+                StatusCode::BAD_REQUEST,
+            ));
+        };
+        ranges.push(ZoomRange {
+            z,
+            min_x,
+            min_y,
+            max_x,
+            max_y,
+            rows: grid.matrix_size(z).1,
+        });
+    }
+
+    let area = ExportArea {
+        bounds,
+        minzoom,
+        maxzoom,
+        format,
+        ranges,
+    };
+    let job = start_export(
+        paths,
+        datasource_id,
+        area,
+        config.export_ttl_seconds,
+        tx_exports,
+        tx_events,
+    )
+    .await?;
+
+    let body = json!({
+        "status": StatusCode::ACCEPTED.as_u16(),
+        "message": format!("Export of DataSource '{datasource_id}' started"),
+        "job_id": job.job_id,
+        "job_url": format!("{}/{}", EndPoints::Jobs.as_ref(), job.job_id),
+    })
+    .to_string();
+    Ok(response_with_body_and_code(body, StatusCode::ACCEPTED))
+}
+
+// GET /api/jobs/{job_id}: state of the export job, None for unknown jobs
+pub async fn export_job_endpoint(
+    job_id: &str,
+    tx_exports: flume::Sender<MessageExports>,
+) -> Result<Option<Response<Body>>, anyhow::Error> {
+    let (tx_job, rx_job) = oneshot::channel();
+    tx_exports
+        .send_async(MessageExports::GetJob {
+            job_id: job_id.to_string(),
+            tx_job,
+        })
+        .await?;
+    match rx_job.await? {
+        Some(job) => {
+            let body = serde_json::to_string(&job)?;
+            Ok(Some(response_with_body_and_code(body, StatusCode::OK)))
+        }
+        None => Ok(None),
+    }
+}
+
+// GET /api/export/download/{job_id}?token=...: package of the completed job until it expires
+pub async fn export_download_endpoint(
+    job_id: &str,
+    query: Option<&str>,
+    tx_exports: flume::Sender<MessageExports>,
+) -> Result<Response<Body>, anyhow::Error> {
+    let token = query.and_then(|q| {
+        q.split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .find(|(k, _)| *k == "token")
+            .map(|(_, v)| v)
+    });
+
+    let (tx_job, rx_job) = oneshot::channel();
+    tx_exports
+        .send_async(MessageExports::GetJob {
+            job_id: job_id.to_string(),
+            tx_job,
+        })
+        .await?;
+    let job = match rx_job.await? {
+        Some(job) if Some(job.token.as_str()) == token => job,
+        _ => {
+            return Ok(export_error_response(
+                format!("Package of job '{job_id}' not found"),
+                StatusCode::NOT_FOUND,
+            ));
+        }
+    };
+    if job.is_expired() {
+        return Ok(export_error_response(
+            format!("Download URL of job '{job_id}' is expired"),
+            StatusCode::GONE,
+        ));
+    }
+    if job.state != ExportState::Completed {
+        return Ok(export_error_response(
+            format!("Export job '{job_id}' is {}", job.state),
+            StatusCode::CONFLICT,
+        ));
+    }
+
+    let file = tokio::fs::File::open(&job.package).await?;
+    let length = file.metadata().await?.len();
+    let stream = futures::stream::unfold(Some(file), |file| async move {
+        let mut file = file?;
+        let mut chunk = vec![0u8; DOWNLOAD_CHUNK_SIZE];
+        match file.read(&mut chunk).await {
+            Ok(0) => None,
+            Ok(n) => {
+                chunk.truncate(n);
+                Some((Ok(chunk), Some(file)))
+            }
+            Err(err) => Some((Err(err), None)),
+        }
+    });
+
+    let response = Response::builder()
+        .status(StatusCode::OK)
+        .content_type(ContentType::MBTiles.as_ref())
+        .content_length(length)
+        .header(
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}.mbtiles\"", job.datasource_id),
+        )
+        .body(Body::wrap_stream(stream))?;
+    Ok(response)
+}
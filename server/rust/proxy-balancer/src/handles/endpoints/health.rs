@@ -1,76 +1,149 @@
+use std::io::Read;
 use std::net::IpAddr;
+use std::time::{Duration, Instant};
 
+use anyhow::anyhow;
+use flate2::read::GzDecoder;
 use hyper::{
-    client::HttpConnector, http::header, http::request::Parts, Body, Client, Method, Request,
-    Response, StatusCode,
+    client::HttpConnector,
+    http::{header, request::Parts, HeaderMap, HeaderValue},
+    Body, Client, Method, Request, Response, StatusCode,
 };
+use serde::Serialize;
 use serde_json::json;
 use tracing::{event, Level};
 
-use crate::defaults::LOCALHOST;
+use crate::defaults::{LOCALHOST, PROBE_TIMEOUT_SECS};
+use crate::handles::headers::ResponseBuilderExt;
 use crate::hyper_reverse_proxy;
 use crate::structs::EndPoints;
 
-pub async fn health_endpoint(
-    ports: Vec<u16>,
-    parts: &Parts,
-    client_ip: IpAddr,
-    client: Client<HttpConnector>,
-    ct: &str,
-) -> Result<Response<Body>, anyhow::Error> {
-    // check if all workers are available
-    let mut error_ports = Vec::new();
-    let mut success_ports = Vec::new();
+// Result of the health check of the worker, latency until its body is read
+#[derive(Debug, Serialize)]
+struct WorkerProbe {
+    port: u16,
+    healthy: bool,
+    latency_ms: u64,
+    worker_pid: Option<u64>,
+    error: Option<String>,
+}
 
-    for p in ports {
+/*
+    Worker is healthy when it answers 200 with its 'worker_pid' in 'PROBE_TIMEOUT_SECS'.
+    The body is read to the end so the connection returns to the pool of the client,
+    gzip bodies of workers which ignore 'Accept-Encoding' are decoded
+*/
+async fn probe_worker(
+    port: u16,
+    headers: &HeaderMap<HeaderValue>,
+    client_ip: IpAddr,
+    client: &Client<HttpConnector>,
+) -> WorkerProbe {
+    let started = Instant::now();
+    let check = async {
         let mut health_request = Request::builder()
             .method(Method::GET)
             .uri(EndPoints::Health.as_ref())
             .body(Body::empty())?;
+        *health_request.headers_mut() = headers.clone();
+        health_request.headers_mut().insert(
+            header::ACCEPT_ENCODING,
+            HeaderValue::from_static("identity"),
+        );
 
-        *health_request.headers_mut() = parts.headers.clone();
-        let res = hyper_reverse_proxy::call(
+        let response = hyper_reverse_proxy::call(
             client_ip,
-            &format!("http://{}:{}", LOCALHOST, p),
+            &format!("http://{}:{}", LOCALHOST, port),
             health_request,
-            &client,
+            client,
         )
-        .await;
-
-        match res {
-            Err(err) => {
-                event!(Level::ERROR, "Error health request {:?}, port {}", err, p);
-                error_ports.push(p);
-            }
-            Ok(response) => {
-                let status = response.status();
-                let b = response.into_body();
-                let body_bytes = hyper::body::to_bytes(b).await?;
-                let body_json: serde_json::Value = serde_json::from_slice(&body_bytes)?;
-
-                if status != StatusCode::OK {
-                    error_ports.push(p);
-                } else {
-                    let _worker_pid = body_json
-                        .get("worker_pid")
-                        .and_then(|worker_pid| worker_pid.as_u64())
-                        .expect("worker_pid is undefined");
-                    success_ports.push(p);
-                }
-            }
+        .await
+        .map_err(|err| anyhow!("{err:?}"))?;
+        let (parts, body) = response.into_parts();
+        let body_bytes = hyper::body::to_bytes(body).await?;
+        if parts.status != StatusCode::OK {
+            return Err(anyhow!("responded {}", parts.status));
         }
+        let gzipped = parts
+            .headers
+            .get(header::CONTENT_ENCODING)
+            .is_some_and(|ce| ce == "gzip");
+        let body_json: serde_json::Value = if gzipped {
+            let mut data = Vec::new();
+            GzDecoder::new(body_bytes.as_ref()).read_to_end(&mut data)?;
+            serde_json::from_slice(&data)?
+        } else {
+            serde_json::from_slice(&body_bytes)?
+        };
+        body_json
+            .get("worker_pid")
+            .and_then(|worker_pid| worker_pid.as_u64())
+            .ok_or(anyhow!("worker_pid is undefined"))
+    };
+
+    let result = tokio::time::timeout(Duration::from_secs(PROBE_TIMEOUT_SECS), check).await;
+    let latency_ms = started.elapsed().as_millis() as u64;
+    let (worker_pid, error) = match result {
+        Ok(Ok(worker_pid)) => (Some(worker_pid), None),
+        Ok(Err(err)) => (None, Some(err.to_string())),
+        Err(_) => (
+            None,
+            Some(format!("no response in {PROBE_TIMEOUT_SECS} seconds")),
+        ),
+    };
+    if let Some(err) = &error {
+        event!(Level::ERROR, "Error health request, port {port}: {err}");
     }
+    WorkerProbe {
+        port,
+        healthy: worker_pid.is_some(),
+        latency_ms,
+        worker_pid,
+        error,
+    }
+}
+
+/*
+    Workers are checked concurrently, so a hung worker delays the response by 'PROBE_TIMEOUT_SECS' at most.
+    'health' of the server: 'healthy' - all workers answered, 'degraded' - some of them, 'unhealthy' - none
+*/
+pub async fn health_endpoint(
+    ports: Vec<u16>,
+    parts: &Parts,
+    client_ip: IpAddr,
+    client: Client<HttpConnector>,
+    ct: &str,
+) -> Result<Response<Body>, anyhow::Error> {
+    let probes = futures::future::join_all(
+        ports
+            .iter()
+            .map(|port| probe_worker(*port, &parts.headers, client_ip, &client)),
+    )
+    .await;
+
+    let (success, error): (Vec<&WorkerProbe>, Vec<&WorkerProbe>) =
+        probes.iter().partition(|probe| probe.healthy);
+    let health = if error.is_empty() {
+        "healthy"
+    } else if success.is_empty() {
+        "unhealthy"
+    } else {
+        "degraded"
+    };
+    let success_ports: Vec<u16> = success.iter().map(|probe| probe.port).collect();
+    let error_ports: Vec<u16> = error.iter().map(|probe| probe.port).collect();
 
     let body = json!({
         "status": StatusCode::OK.as_u16(),
+        "health": health,
         "error_ports": error_ports,
-        "success_ports": success_ports
+        "success_ports": success_ports,
+        "workers": probes
     })
     .to_string();
     let response = Response::builder()
         .status(StatusCode::OK)
-        .header(header::CONTENT_TYPE, ct)
-        .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+        .content_type(ct)
         .body(Body::from(body))?;
     Ok(response)
 }
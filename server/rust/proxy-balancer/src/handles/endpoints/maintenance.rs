@@ -1,11 +1,24 @@
 use hyper::body::Bytes;
-use hyper::{Body, Method, Response, StatusCode};
-use tokio::sync::oneshot;
+use hyper::http::header::{self, HeaderValue};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use serde_json::json;
+use tokio::sync::{oneshot, watch};
 use tracing::{event, Level};
 
-use crate::handles::helpers::response_with_body_and_code;
-use crate::structs::EndPoints;
-use crate::tasks::{reload_workers::MessageMaintenanceWorkers, semaphore::MessageSemaphore};
+use crate::defaults::{LOCALHOST, SEMAPHORE_REJECTIONS_WINDOW_SECS};
+use crate::handles::endpoints::audit::audit_endpoint;
+use crate::handles::error::{ApiError, ErrorCode};
+use crate::handles::helpers::{
+    get_worker_data, json_body, required_u64, response_with_body_and_code,
+};
+use crate::log::{parse_log_level, set_log_level};
+use crate::structs::{ContentType, EndPoints};
+use crate::tasks::{
+    audit::{record_audit, AuditActor, MessageAudit},
+    reload_workers::MessageMaintenanceWorkers,
+    semaphore::MessageSemaphore,
+    storage::StorageInfo,
+};
 
 pub async fn maintenance_endpoint(
     path: &str,
@@ -13,38 +26,71 @@ pub async fn maintenance_endpoint(
     b_bytes: &Bytes,
     tx_mw: flume::Sender<MessageMaintenanceWorkers>,
     tx_sem: flume::Sender<MessageSemaphore>,
+    rx_storage: &watch::Receiver<StorageInfo>,
+    query: Option<&str>,
+    tx_audit: flume::Sender<MessageAudit>,
+    actor: &AuditActor,
 ) -> Result<Option<Response<Body>>, anyhow::Error> {
+    let ok = StatusCode::OK.as_u16();
     if path.starts_with(EndPoints::AddWorkers.as_ref()) && method == Method::POST {
-        let body_json: serde_json::Value = serde_json::from_slice(&b_bytes)?;
-        let count = body_json
-            .get("count")
-            .and_then(|c| c.as_u64())
-            .expect("Count workers is undefined");
+        let body_json = json_body(b_bytes)?;
+        let count = required_u64(&body_json, "count")?;
 
         tx_mw
             .send_async(MessageMaintenanceWorkers::AddWorkers { count })
             .await?;
         event!(Level::INFO, "Add Python workers, count = {count}");
+        let details = json!({ "count": count });
+        record_audit(
+            &tx_audit,
+            actor,
+            EndPoints::AddWorkers.as_ref(),
+            details,
+            ok,
+        );
 
         return Ok(Some(response_with_body_and_code(
             "Workers successfully added".to_string(),
             StatusCode::OK,
         )));
     } else if path.starts_with(EndPoints::ReloadWorkers.as_ref()) {
+        let confirmed = confirm_workers_action(method, b_bytes, EndPoints::ReloadWorkers, &tx_mw);
+        if let Some(response) = confirmed.await? {
+            return Ok(Some(response));
+        }
         tx_mw
             .send_async(MessageMaintenanceWorkers::ReloadWorkers())
             .await?;
         event!(Level::INFO, "Reload Python workers");
+        record_audit(
+            &tx_audit,
+            actor,
+            EndPoints::ReloadWorkers.as_ref(),
+            json!({}),
+            ok,
+        );
 
         return Ok(Some(response_with_body_and_code(
             "Workers successfully reloaded".to_string(),
             StatusCode::OK,
         )));
     } else if path.starts_with(EndPoints::TerminateWorkers.as_ref()) {
+        let confirmed =
+            confirm_workers_action(method, b_bytes, EndPoints::TerminateWorkers, &tx_mw);
+        if let Some(response) = confirmed.await? {
+            return Ok(Some(response));
+        }
         tx_mw
             .send_async(MessageMaintenanceWorkers::TerminateWorkers())
             .await?;
         event!(Level::INFO, "Terminate Python workers");
+        record_audit(
+            &tx_audit,
+            actor,
+            EndPoints::TerminateWorkers.as_ref(),
+            json!({}),
+            ok,
+        );
 
         return Ok(Some(response_with_body_and_code(
             "Workers successfully terminated".to_string(),
@@ -66,8 +112,12 @@ pub async fn maintenance_endpoint(
             }
             Ok(h) => {
                 if let Some(iw) = h {
-                    let v = serde_json::to_string(&iw)?;
-                    return Ok(Some(response_with_body_and_code(v, StatusCode::OK)));
+                    let mut v = serde_json::to_value(&iw)?;
+                    v["storage"] = json!(*rx_storage.borrow());
+                    return Ok(Some(response_with_body_and_code(
+                        v.to_string(),
+                        StatusCode::OK,
+                    )));
                 }
                 return Ok(Some(response_with_body_and_code(
                     "Workers info is None".to_string(),
@@ -78,15 +128,14 @@ pub async fn maintenance_endpoint(
     } else if path.starts_with(EndPoints::IncreaseLimitConcurrentRequests.as_ref())
         && method == Method::POST
     {
-        let body_json: serde_json::Value = serde_json::from_slice(&b_bytes)?;
-        let n = body_json
-            .get("n")
-            .and_then(|id| id.as_u64())
-            .expect("Count permits is undefined");
+        let body_json = json_body(b_bytes)?;
+        let n = required_u64(&body_json, "n")?;
 
         tx_sem
             .send_async(MessageSemaphore::AddPermits { n: n as usize })
             .await?;
+        let action = EndPoints::IncreaseLimitConcurrentRequests.as_ref();
+        record_audit(&tx_audit, actor, action, json!({ "n": n }), ok);
 
         return Ok(Some(response_with_body_and_code(
             format!(
@@ -98,15 +147,14 @@ pub async fn maintenance_endpoint(
     } else if path.starts_with(EndPoints::DecreaseLimitConcurrentRequests.as_ref())
         && method == Method::POST
     {
-        let body_json: serde_json::Value = serde_json::from_slice(&b_bytes)?;
-        let n = body_json
-            .get("n")
-            .and_then(|id| id.as_u64())
-            .expect("Count permits is undefined");
+        let body_json = json_body(b_bytes)?;
+        let n = required_u64(&body_json, "n")?;
 
         tx_sem
             .send_async(MessageSemaphore::ForgetPermits { n: n as usize })
             .await?;
+        let action = EndPoints::DecreaseLimitConcurrentRequests.as_ref();
+        record_audit(&tx_audit, actor, action, json!({ "n": n }), ok);
 
         return Ok(Some(response_with_body_and_code(
             format!(
@@ -115,7 +163,214 @@ pub async fn maintenance_endpoint(
             ),
             StatusCode::OK,
         )));
+    } else if path.starts_with(EndPoints::LimitConcurrentRequests.as_ref()) && method == Method::GET
+    {
+        return limit_cr_endpoint(tx_sem).await.map(Some);
+    } else if path.starts_with(EndPoints::LogLevel.as_ref()) && method == Method::POST {
+        return log_level_endpoint(b_bytes, tx_mw, tx_audit, actor)
+            .await
+            .map(Some);
+    } else if path.starts_with(EndPoints::SavePids.as_ref()) && method == Method::POST {
+        return save_pids_endpoint(tx_mw, tx_audit, actor).await.map(Some);
+    } else if path.starts_with(EndPoints::Audit.as_ref()) && method == Method::GET {
+        return audit_endpoint(query, tx_audit).await.map(Some);
     }
 
     return Ok(None);
 }
+
+/*
+    Reload and termination stop all workers, so they accept only POST with the body
+    {"confirm": "<action>"}, the action is the last segment of the path ('reload_workers').
+    The body {"dry_run": true} reports workers which would be stopped without stopping them.
+    None - the action is confirmed
+*/
+async fn confirm_workers_action(
+    method: &Method,
+    b_bytes: &Bytes,
+    endpoint: EndPoints,
+    tx_mw: &flume::Sender<MessageMaintenanceWorkers>,
+) -> Result<Option<Response<Body>>, anyhow::Error> {
+    if method != Method::POST {
+        let mut response = ApiError::new(
+            StatusCode::METHOD_NOT_ALLOWED,
+            format!("Only POST is allowed for '{endpoint}'"),
+        )
+        .into_response();
+        response
+            .headers_mut()
+            .insert(header::ALLOW, HeaderValue::from_static("POST"));
+        return Ok(Some(response));
+    }
+
+    let path: &str = endpoint.as_ref();
+    let action = path.rsplit('/').next().unwrap_or_default();
+    let body_json = if b_bytes.is_empty() {
+        json!({})
+    } else {
+        json_body(b_bytes)?
+    };
+
+    let dry_run = body_json
+        .get("dry_run")
+        .and_then(|dry_run| dry_run.as_bool())
+        .unwrap_or(false);
+    if dry_run {
+        let (tx_lw, rx_lw) = oneshot::channel();
+        tx_mw
+            .send_async(MessageMaintenanceWorkers::ListWorkers { tx_lw })
+            .await?;
+        let workers: Vec<serde_json::Value> = rx_lw
+            .await?
+            .into_iter()
+            .map(|(port, pid)| json!({ "port": port, "pid": pid }))
+            .collect();
+        let body = json!({
+            "status": StatusCode::OK.as_u16(),
+            "dry_run": true,
+            "action": action,
+            "message": format!("{} workers would be stopped by '{action}'", workers.len()),
+            "workers": workers,
+        });
+        return Ok(Some(response_with_body_and_code(
+            body.to_string(),
+            StatusCode::OK,
+        )));
+    }
+
+    let confirm = body_json
+        .get("confirm")
+        .and_then(|confirm| confirm.as_str());
+    if confirm != Some(action) {
+        let response = ApiError::new(
+            StatusCode::BAD_REQUEST,
+            format!("Action must be confirmed with the body {{\"confirm\": \"{action}\"}}"),
+        )
+        .with_code(ErrorCode::ConfirmationRequired)
+        .with_details(json!({ "confirm": action }))
+        .into_response();
+        return Ok(Some(response));
+    }
+    Ok(None)
+}
+
+/*
+    GET /maintenance/limit_cr returns permits of ports: configured limit, available permits,
+    requests waiting for the permit and waiters cancelled by clients (in total and recent)
+*/
+async fn limit_cr_endpoint(
+    tx_sem: flume::Sender<MessageSemaphore>,
+) -> Result<Response<Body>, anyhow::Error> {
+    let (tx_info, rx_info) = oneshot::channel();
+    tx_sem
+        .send_async(MessageSemaphore::Info { tx_info })
+        .await?;
+    let ports = rx_info.await?;
+    let body = json!({
+        "rejections_window_seconds": SEMAPHORE_REJECTIONS_WINDOW_SECS,
+        "ports": ports,
+    })
+    .to_string();
+    Ok(response_with_body_and_code(body, StatusCode::OK))
+}
+
+/*
+    POST /maintenance/log_level with body {"level": "DEBUG", "workers": true}
+    changes the log level of the server without restart, by default
+    the level is forwarded to Python workers as well
+*/
+async fn log_level_endpoint(
+    b_bytes: &Bytes,
+    tx_mw: flume::Sender<MessageMaintenanceWorkers>,
+    tx_audit: flume::Sender<MessageAudit>,
+    actor: &AuditActor,
+) -> Result<Response<Body>, anyhow::Error> {
+    let body_json = json_body(b_bytes)?;
+    let level = body_json
+        .get("level")
+        .and_then(|level| level.as_str())
+        .unwrap_or_default()
+        .to_uppercase();
+    if let Err(err) = parse_log_level(&level) {
+        return Ok(ApiError::new(StatusCode::BAD_REQUEST, err.to_string()).into_response());
+    }
+
+    set_log_level(&level)?;
+    event!(Level::WARN, "Log level of server changed to '{level}'");
+
+    let mut workers = serde_json::Map::new();
+    let forward = body_json
+        .get("workers")
+        .and_then(|workers| workers.as_bool())
+        .unwrap_or(true);
+    if forward {
+        let wd = get_worker_data(tx_mw).await?;
+        let ct: &str = ContentType::ApplicationJson.into();
+        let worker_body = json!({ "level": level }).to_string();
+        for port in wd.ports {
+            let request = Request::builder()
+                .method(Method::POST)
+                .uri(format!(
+                    "http://{LOCALHOST}:{port}{}",
+                    EndPoints::WorkerLogLevel.as_ref()
+                ))
+                .header(header::CONTENT_TYPE, ct)
+                .body(Body::from(worker_body.clone()))?;
+            let status = match wd.client.request(request).await {
+                Ok(response) => response.status().as_u16().to_string(),
+                Err(err) => {
+                    event!(
+                        Level::ERROR,
+                        "Error change log level of worker port {port}: {err}"
+                    );
+                    err.to_string()
+                }
+            };
+            workers.insert(port.to_string(), serde_json::Value::String(status));
+        }
+    }
+
+    let details = json!({ "level": level, "workers": forward });
+    let ok = StatusCode::OK.as_u16();
+    record_audit(&tx_audit, actor, EndPoints::LogLevel.as_ref(), details, ok);
+
+    let body = json!({
+        "status": StatusCode::OK.as_u16(),
+        "message": format!("Log level changed to '{level}'"),
+        "workers": workers,
+    })
+    .to_string();
+    Ok(response_with_body_and_code(body, StatusCode::OK))
+}
+
+/*
+    POST /maintenance/save_pids writes PIDs of workers and their processes to 'scripts/PIDs' again,
+    when the file was removed or rotated by the administrator
+*/
+async fn save_pids_endpoint(
+    tx_mw: flume::Sender<MessageMaintenanceWorkers>,
+    tx_audit: flume::Sender<MessageAudit>,
+    actor: &AuditActor,
+) -> Result<Response<Body>, anyhow::Error> {
+    let (tx_result, rx_result) = oneshot::channel();
+    tx_mw
+        .send_async(MessageMaintenanceWorkers::SavePids { tx_result })
+        .await?;
+    let (status, body) = match rx_result.await? {
+        Ok(pids) => (
+            StatusCode::OK,
+            json!({
+                "status": StatusCode::OK.as_u16(),
+                "message": "PIDs of workers successfully saved",
+                "pids": pids,
+            }),
+        ),
+        Err(err) => {
+            let error = ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, err.to_string());
+            (error.status, error.to_json())
+        }
+    };
+    let action = EndPoints::SavePids.as_ref();
+    record_audit(&tx_audit, actor, action, json!({}), status.as_u16());
+    Ok(response_with_body_and_code(body.to_string(), status))
+}
@@ -1,13 +1,21 @@
-use anyhow::anyhow;
 use hyper::body::Bytes;
-use hyper::{client::HttpConnector, http::request::Parts, Body, Client, Method, Response, Uri};
+use hyper::{
+    client::HttpConnector, http::request::Parts, Body, Client, Method, Response, StatusCode, Uri,
+};
 use tokio::sync::oneshot;
+use tracing::{event, Level};
 
+use crate::cluster::hops::{node_address, RouteTrace};
 use crate::config::Config;
-use crate::handles::helpers::{worker_not_found_response, worker_response};
+use crate::handles::error::ApiError;
+use crate::handles::helpers::{
+    is_worker_failure, json_body, no_content_response, replicate_request, required_str,
+    split_node_address, worker_response,
+};
 use crate::structs::EndPoints;
+use crate::tasks::cluster::MessageCluster;
 use crate::tasks::datasources::MessageDatasource;
-use crate::utils::datasource_id_from_uri;
+use crate::utils::{datasource_id_from_uri, zxy_from_uri};
 
 pub async fn master_endpoint(
     path: &str,
@@ -18,8 +26,13 @@ pub async fn master_endpoint(
     ct: &str,
     client: Client<HttpConnector>,
     tx: flume::Sender<MessageDatasource>,
+    tx_cluster: flume::Sender<MessageCluster>,
     config: &Config,
 ) -> Result<Option<Response<Body>>, anyhow::Error> {
+    // nodes of the DataSource get the route of the request with the current node
+    let mut headers = parts.headers.clone();
+    RouteTrace::from_headers(&parts.headers).forward(&mut headers, &node_address(config));
+
     if path.starts_with(EndPoints::Tile.as_ref()) {
         let datasource_id = datasource_id_from_uri(path)
             .expect(&format!("Error extract datasource_id from URI: {}", path));
@@ -37,42 +50,74 @@ pub async fn master_endpoint(
         */
 
         if let Some(ds) = rx_ds.await? {
-            if let Some(host) = ds.host {
-                if let Some(port) = ds.port {
-                    if let Some(current_addr) = config.address.as_ref() {
-                        let addr = format!("{host}:{port}");
-                        if *current_addr == addr {
-                            // for current machine skip send request
-                            return Ok(None);
-                        }
+            // zoom is out of range of the DataSource, nodes are not requested
+            if let Ok((_, _, z)) = zxy_from_uri(path) {
+                if !ds.zoom_in_range(z) && ds.overzoom_parent(z, config.overzoom_maxzoom).is_none()
+                {
+                    return no_content_response().map(Some);
+                }
+            }
+
+            let locations = ds.locations();
+            if !locations.is_empty() {
+                let tile_key = if config.cluster_consistent_hash {
+                    let (x, y, z) = zxy_from_uri(path)?;
+                    Some(format!("{datasource_id}/{z}/{x}/{y}"))
+                } else {
+                    None
+                };
+                let nodes =
+                    select_nodes(datasource_id, locations, tile_key, &tx_cluster, false).await?;
+                if nodes.is_empty() {
+                    let error = ApiError::new(
+                        StatusCode::SERVICE_UNAVAILABLE,
+                        format!("No alive cluster node for datasource '{datasource_id}'"),
+                    );
+                    return Ok(Some(error.into_response()));
+                }
+
+                // failover to the next replica when the node does not respond
+                let mut last_response = None;
+                for address in nodes {
+                    if config.address.as_ref() == Some(&address) {
+                        // for current machine skip send request
+                        return Ok(None);
                     }
 
-                    let body = Body::empty();
+                    let (host, port) = split_node_address(&address)?;
+                    let body = Bytes::new();
                     let response = worker_response(
                         host,
                         port,
-                        uri,
+                        uri.clone(),
                         method,
                         &client,
-                        parts.headers.clone(),
+                        headers.clone(),
                         body,
                         ct,
                         config.timeout_worker_response,
+                        config.cluster_secret.as_deref(),
                     )
                     .await?;
-                    return Ok(Some(response));
+                    if !is_worker_failure(response.status()) {
+                        return Ok(Some(response));
+                    }
+                    event!(
+                        Level::ERROR,
+                        "Node '{address}' responded {} for datasource '{datasource_id}'",
+                        response.status()
+                    );
+                    last_response = Some(response);
                 }
+                return Ok(last_response);
             }
         } else {
-            let response = worker_not_found_response(datasource_id, ct)?;
+            let response = ApiError::datasource_not_found(datasource_id).into_response();
             return Ok(Some(response));
         }
     } else if path.starts_with(EndPoints::Pyramid.as_ref()) && method == Method::POST {
-        let body_json: serde_json::Value = serde_json::from_slice(&b_bytes)?;
-        let datasource_id = body_json
-            .get("datasource_id")
-            .and_then(|id| id.as_str())
-            .ok_or(anyhow!("datasource_id is undefined"))?;
+        let body_json = json_body(&b_bytes)?;
+        let datasource_id = required_str(&body_json, "datasource_id")?;
 
         // in case of a delayed launch of the pyramid, we process it in the function 'pyramid_endpoint'
         if let Some(_) = body_json.get("scheduled_for") {
@@ -87,37 +132,84 @@ pub async fn master_endpoint(
         .await?;
 
         if let Some(ds) = rx_ds.await? {
-            if let Some(host) = ds.host {
-                if let Some(port) = ds.port {
-                    if let Some(current_addr) = config.address.as_ref() {
-                        let addr = format!("{host}:{port}");
-                        if *current_addr == addr {
-                            // for current machine skip send request
-                            return Ok(None);
-                        }
-                    }
+            let locations = ds.locations();
+            if !locations.is_empty() {
+                // every replica builds its own cache of the DataSource
+                let (current, others): (Vec<String>, Vec<String>) = locations
+                    .into_iter()
+                    .partition(|address| config.address.as_ref() == Some(address));
 
-                    let body = Body::from(b_bytes);
-                    let response = worker_response(
-                        host,
-                        port,
+                if !current.is_empty() {
+                    replicate_request(
+                        others,
                         uri,
-                        method,
-                        &client,
-                        parts.headers.clone(),
-                        body,
-                        ct,
-                        config.timeout_worker_response,
-                    )
-                    .await?;
-                    return Ok(Some(response));
+                        method.clone(),
+                        client,
+                        headers.clone(),
+                        b_bytes,
+                        config,
+                    );
+                    // for current machine skip send request
+                    return Ok(None);
                 }
+
+                replicate_request(
+                    others[1..].to_vec(),
+                    uri.clone(),
+                    method.clone(),
+                    client.clone(),
+                    headers.clone(),
+                    b_bytes.clone(),
+                    config,
+                );
+
+                let (host, port) = split_node_address(&others[0])?;
+                let body = b_bytes;
+                let response = worker_response(
+                    host,
+                    port,
+                    uri,
+                    method,
+                    &client,
+                    headers.clone(),
+                    body,
+                    ct,
+                    config.timeout_worker_response,
+                    config.cluster_secret.as_deref(),
+                )
+                .await?;
+                return Ok(Some(response));
             }
         } else {
-            let response = worker_not_found_response(datasource_id, ct)?;
+            let response = ApiError::datasource_not_found(datasource_id).into_response();
             return Ok(Some(response));
         }
     }
 
     return Ok(None);
 }
+
+/*
+    The DataBase row points to the nodes of the DataSource, the cluster registry
+    skips nodes which stopped heartbeating and adds other alive nodes serving the DataSource.
+    Nodes which never registered are trusted as is. Preview does not advance round robin
+*/
+pub async fn select_nodes(
+    datasource_id: &str,
+    locations: Vec<String>,
+    tile_key: Option<String>,
+    tx_cluster: &flume::Sender<MessageCluster>,
+    preview: bool,
+) -> Result<Vec<String>, anyhow::Error> {
+    let (tx_nodes, rx_nodes) = oneshot::channel();
+    tx_cluster
+        .send_async(MessageCluster::SelectNodes {
+            datasource_id: datasource_id.to_string(),
+            locations,
+            tile_key,
+            preview,
+            tx_nodes,
+        })
+        .await?;
+    Ok(rx_nodes.await?)
+}
@@ -0,0 +1,69 @@
+use hyper::{Body, Response, StatusCode};
+use serde_json::json;
+use tiler_core::sqlite_clients::MessageSQLiteClient;
+use tokio::sync::oneshot;
+
+use crate::db::{get_mbtiles_metadata, registry::SharedRegistry};
+use crate::handles::error::ApiError;
+use crate::handles::helpers::response_with_body_and_code;
+use crate::paths::Paths;
+use crate::tasks::datasources::MessageDatasource;
+use crate::tasks::mbtiles_metadata::write_datasource_metadata;
+use crate::utils::mbtiles_path_from_ds_id;
+
+fn not_found_response(message: String) -> Response<Body> {
+    ApiError::new(StatusCode::NOT_FOUND, message).into_response()
+}
+
+/*
+    Metadata of MBTiles of the DataSource as the JSON object 'name' - 'value',
+    the value of 'json' is parsed. MBTiles created before the metadata was populated
+    get it from the row of the DataSource on the first request
+*/
+pub async fn metadata_endpoint(
+    paths: &Paths,
+    datasource_id: &str,
+    registry: &SharedRegistry,
+    tx: flume::Sender<MessageDatasource>,
+    tx_sqlite_client: flume::Sender<MessageSQLiteClient>,
+) -> Result<Response<Body>, anyhow::Error> {
+    let (tx_ds, rx_ds) = oneshot::channel();
+    tx.send_async(MessageDatasource::GetDataSource {
+        datasource_id: datasource_id.to_string(),
+        tx_ds,
+    })
+    .await?;
+    if rx_ds.await?.is_none() {
+        return Ok(not_found_response(format!(
+            "DataSource '{datasource_id}' not found"
+        )));
+    }
+
+    let mbtiles_db = mbtiles_path_from_ds_id(paths, datasource_id)?;
+    if !tokio::fs::try_exists(&mbtiles_db).await.unwrap_or(false) {
+        return Ok(not_found_response(format!(
+            "MBTiles of DataSource '{datasource_id}' not found"
+        )));
+    }
+
+    let mut metadata = get_mbtiles_metadata(&mbtiles_db, tx_sqlite_client.clone()).await?;
+    if metadata.is_empty() {
+        write_datasource_metadata(paths, datasource_id, registry, tx_sqlite_client.clone()).await?;
+        metadata = get_mbtiles_metadata(&mbtiles_db, tx_sqlite_client).await?;
+    }
+
+    let body: serde_json::Map<String, serde_json::Value> = metadata
+        .into_iter()
+        .map(|(name, value)| {
+            let value = match name.as_str() {
+                "json" => serde_json::from_str(&value).unwrap_or(json!(value)),
+                _ => json!(value),
+            };
+            (name, value)
+        })
+        .collect();
+    Ok(response_with_body_and_code(
+        serde_json::Value::Object(body).to_string(),
+        StatusCode::OK,
+    ))
+}
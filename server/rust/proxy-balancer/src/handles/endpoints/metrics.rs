@@ -0,0 +1,178 @@
+use std::fmt::Write;
+
+use hyper::{Body, Response, StatusCode};
+use tiler_core::sqlite_clients::MessageSQLiteClient;
+use tokio::sync::{oneshot, watch};
+
+use crate::defaults::LATENCY_BUCKETS_SECS;
+use crate::handles::headers::{CacheControl, ResponseBuilderExt};
+use crate::structs::ContentType;
+use crate::tasks::semaphore::MessageSemaphore;
+use crate::tasks::stats::MessageStats;
+use crate::tasks::storage::StorageInfo;
+
+const PERCENTILES: [f64; 3] = [0.5, 0.9, 0.99];
+
+/*
+    GET /metrics: latency of requests per endpoint in the text format of Prometheus,
+    histograms of 'tiler_request_duration_seconds' and their estimated percentiles,
+    open SQLite clients of MBTiles and their evictions, queues of worker ports and requests
+    shed by backpressure, space of the tiles volume
+*/
+pub async fn metrics_endpoint(
+    tx_stats: flume::Sender<MessageStats>,
+    tx_sqlite_client: flume::Sender<MessageSQLiteClient>,
+    tx_sem: flume::Sender<MessageSemaphore>,
+    rx_storage: &watch::Receiver<StorageInfo>,
+) -> Result<Response<Body>, anyhow::Error> {
+    let (tx_latencies, rx_latencies) = oneshot::channel();
+    tx_stats
+        .send_async(MessageStats::GetLatencies { tx_latencies })
+        .await?;
+    let latencies = rx_latencies.await?;
+
+    let mut body = String::new();
+    writeln!(
+        body,
+        "# HELP tiler_request_duration_seconds Latency of requests per endpoint"
+    )?;
+    writeln!(body, "# TYPE tiler_request_duration_seconds histogram")?;
+    for (endpoint, h) in latencies.iter() {
+        let mut cumulative = 0;
+        for (i, n) in h.buckets.iter().enumerate() {
+            cumulative += n;
+            let le = LATENCY_BUCKETS_SECS
+                .get(i)
+                .map(|bound| bound.to_string())
+                .unwrap_or_else(|| "+Inf".to_string());
+            writeln!(
+                body,
+                "tiler_request_duration_seconds_bucket{{endpoint=\"{endpoint}\",le=\"{le}\"}} {cumulative}"
+            )?;
+        }
+        writeln!(
+            body,
+            "tiler_request_duration_seconds_sum{{endpoint=\"{endpoint}\"}} {}",
+            h.sum_secs
+        )?;
+        writeln!(
+            body,
+            "tiler_request_duration_seconds_count{{endpoint=\"{endpoint}\"}} {}",
+            h.count
+        )?;
+    }
+
+    writeln!(
+        body,
+        "# HELP tiler_request_duration_percentile_seconds Percentiles of latency estimated from histogram"
+    )?;
+    writeln!(
+        body,
+        "# TYPE tiler_request_duration_percentile_seconds gauge"
+    )?;
+    for (endpoint, h) in latencies.iter() {
+        for q in PERCENTILES {
+            writeln!(
+                body,
+                "tiler_request_duration_percentile_seconds{{endpoint=\"{endpoint}\",quantile=\"{q}\"}} {}",
+                h.percentile(q)
+            )?;
+        }
+    }
+
+    let (tx_pool, rx_pool) = oneshot::channel();
+    tx_sqlite_client
+        .send_async(MessageSQLiteClient::GetPoolStats { tx_stats: tx_pool })
+        .await?;
+    let pool = rx_pool.await?;
+    writeln!(
+        body,
+        "# HELP tiler_sqlite_clients_open Open SQLite clients of MBTiles"
+    )?;
+    writeln!(body, "# TYPE tiler_sqlite_clients_open gauge")?;
+    writeln!(body, "tiler_sqlite_clients_open {}", pool.open)?;
+    writeln!(
+        body,
+        "# HELP tiler_sqlite_clients_max Limit of open SQLite clients, 0 - unlimited"
+    )?;
+    writeln!(body, "# TYPE tiler_sqlite_clients_max gauge")?;
+    writeln!(body, "tiler_sqlite_clients_max {}", pool.max_clients)?;
+    writeln!(
+        body,
+        "# HELP tiler_sqlite_clients_evicted_total Evicted SQLite clients by reason"
+    )?;
+    writeln!(body, "# TYPE tiler_sqlite_clients_evicted_total counter")?;
+    writeln!(
+        body,
+        "tiler_sqlite_clients_evicted_total{{reason=\"lru\"}} {}",
+        pool.evicted_lru
+    )?;
+    writeln!(
+        body,
+        "tiler_sqlite_clients_evicted_total{{reason=\"idle\"}} {}",
+        pool.evicted_idle
+    )?;
+
+    let (tx_info, rx_info) = oneshot::channel();
+    tx_sem
+        .send_async(MessageSemaphore::Info { tx_info })
+        .await?;
+    let mut permits: Vec<_> = rx_info.await?.into_iter().collect();
+    permits.sort_by_key(|(port, _)| *port);
+    writeln!(
+        body,
+        "# HELP tiler_queue_depth Requests waiting for permits of the worker port"
+    )?;
+    writeln!(body, "# TYPE tiler_queue_depth gauge")?;
+    for (port, info) in permits.iter() {
+        writeln!(body, "tiler_queue_depth{{port=\"{port}\"}} {}", info.queued)?;
+    }
+    writeln!(
+        body,
+        "# HELP tiler_queue_rejections_total Requests refused because the queue of the worker port was full"
+    )?;
+    writeln!(body, "# TYPE tiler_queue_rejections_total counter")?;
+    for (port, info) in permits.iter() {
+        writeln!(
+            body,
+            "tiler_queue_rejections_total{{port=\"{port}\",lane=\"interactive\"}} {}",
+            info.shed
+        )?;
+        writeln!(
+            body,
+            "tiler_queue_rejections_total{{port=\"{port}\",lane=\"batch\"}} {}",
+            info.batch_shed
+        )?;
+    }
+
+    let storage = *rx_storage.borrow();
+    writeln!(
+        body,
+        "# HELP tiler_storage_available_bytes Free space of the tiles volume"
+    )?;
+    writeln!(body, "# TYPE tiler_storage_available_bytes gauge")?;
+    writeln!(
+        body,
+        "tiler_storage_available_bytes {}",
+        storage.available_bytes
+    )?;
+    writeln!(
+        body,
+        "# HELP tiler_storage_total_bytes Size of the tiles volume"
+    )?;
+    writeln!(body, "# TYPE tiler_storage_total_bytes gauge")?;
+    writeln!(body, "tiler_storage_total_bytes {}", storage.total_bytes)?;
+    writeln!(
+        body,
+        "# HELP tiler_storage_low Free space is below the threshold, pyramids are refused"
+    )?;
+    writeln!(body, "# TYPE tiler_storage_low gauge")?;
+    writeln!(body, "tiler_storage_low {}", storage.is_low() as u8)?;
+
+    let response = Response::builder()
+        .status(StatusCode::OK)
+        .content_type(ContentType::Prometheus.as_ref())
+        .cache_control(CacheControl::NoCache)
+        .body(Body::from(body))?;
+    Ok(response)
+}
@@ -1,6 +1,21 @@
+pub mod audit;
+pub mod cluster;
 pub mod datasource;
+pub mod events;
+pub mod export;
 pub mod health;
 pub mod maintenance;
 pub mod master;
+pub mod metadata;
+pub mod metrics;
+pub mod openapi;
+pub mod probes;
 pub mod pyramid;
+pub mod routing;
+pub mod static_files;
+pub mod stats;
+pub mod styles;
 pub mod tile;
+pub mod upload;
+pub mod usage;
+pub mod validate;
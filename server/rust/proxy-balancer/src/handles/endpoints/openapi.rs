@@ -0,0 +1,1147 @@
+use hyper::{Body, Response, StatusCode};
+use serde_json::{json, Map, Value};
+use strum::IntoEnumIterator;
+
+use crate::config::Config;
+use crate::defaults::{AUDIT_MAX_LIMIT, BILLING_MAX_LIMIT, SWAGGER_UI_CDN};
+use crate::handles::error::{ApiError, ErrorCode};
+use crate::handles::headers::ResponseBuilderExt;
+use crate::handles::helpers::response_with_body_and_code;
+use crate::structs::{ApiVersion, ContentType, EndPoints, Extension};
+
+// Path parameter
+fn path_param(name: &str, schema: Value, description: &str) -> Value {
+    json!({
+        "name": name,
+        "in": "path",
+        "required": true,
+        "schema": schema,
+        "description": description
+    })
+}
+
+// Optional query parameter
+fn query_param(name: &str, schema: Value, description: &str) -> Value {
+    json!({
+        "name": name,
+        "in": "query",
+        "required": false,
+        "schema": schema,
+        "description": description
+    })
+}
+
+fn datasource_id_param() -> Value {
+    path_param(
+        "datasource_id",
+        json!({"type": "string", "format": "uuid"}),
+        "Id of the DataSource",
+    )
+}
+
+fn json_body(schema: Value) -> Value {
+    json!({
+        "required": true,
+        "content": {ContentType::ApplicationJson.as_ref(): {"schema": schema}}
+    })
+}
+
+fn json_response(description: &str, schema: Value) -> Value {
+    json!({
+        "description": description,
+        "content": {ContentType::ApplicationJson.as_ref(): {"schema": schema}}
+    })
+}
+
+/*
+    Responses of the operation: successful responses as is,
+    errors by their statuses refer to the schema 'ApiError'
+*/
+fn responses(ok: &[(u16, Value)], errors: &[u16]) -> Value {
+    let mut responses = Map::new();
+    for (status, response) in ok {
+        responses.insert(status.to_string(), response.clone());
+    }
+    for status in errors {
+        let description = StatusCode::from_u16(*status)
+            .ok()
+            .and_then(|s| s.canonical_reason())
+            .unwrap_or("Error");
+        responses.insert(
+            status.to_string(),
+            json_response(
+                description,
+                json!({"$ref": "#/components/schemas/ApiError"}),
+            ),
+        );
+    }
+    Value::Object(responses)
+}
+
+fn operation(
+    tag: &str,
+    summary: &str,
+    parameters: Vec<Value>,
+    request_body: Option<Value>,
+    responses: Value,
+) -> Value {
+    let mut operation = json!({
+        "tags": [tag],
+        "summary": summary,
+        "parameters": parameters,
+        "responses": responses
+    });
+    if let Some(body) = request_body {
+        operation["requestBody"] = body;
+    }
+    operation
+}
+
+fn message_schema() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "status": {"type": "integer"},
+            "message": {"type": "string"}
+        }
+    })
+}
+
+fn any_object() -> Value {
+    json!({"type": "object", "additionalProperties": true})
+}
+
+// Path item with one operation
+fn path_item(method: &str, operation: Value) -> Value {
+    json!({ method: operation })
+}
+
+fn binary_content(content_types: &[ContentType]) -> Value {
+    let mut content = Map::new();
+    for content_type in content_types {
+        content.insert(
+            content_type.to_string(),
+            json!({"schema": {"type": "string", "format": "binary"}}),
+        );
+    }
+    Value::Object(content)
+}
+
+fn tile_paths(paths: &mut Map<String, Value>) {
+    let tile = format!(
+        "{}/{{datasource_id}}/{{z}}/{{x}}/{{y}}.{{ext}}",
+        EndPoints::Tile
+    );
+    let extensions: Vec<&str> = [
+        Extension::Png,
+        Extension::Webp,
+        Extension::Pbf,
+        Extension::Mvt,
+    ]
+    .iter()
+    .map(|e| e.into())
+    .collect();
+    let index = json!({"type": "integer", "minimum": 0});
+    let parameters = vec![
+        datasource_id_param(),
+        path_param("z", index.clone(), "Zoom"),
+        path_param("x", index.clone(), "Column"),
+        path_param("y", index, "Row"),
+        path_param(
+            "ext",
+            json!({"type": "string", "enum": extensions}),
+            "Format",
+        ),
+        query_param(
+            "grid",
+            json!({"type": "string"}),
+            "Name or CRS of the tile grid",
+        ),
+        query_param(
+            "layers",
+            json!({"type": "string"}),
+            "Comma-separated layers of vector tiles",
+        ),
+        query_param(
+            "ramp",
+            json!({"type": "string"}),
+            "Color ramp of raster tiles: name or stops 'value:rrggbb'",
+        ),
+        query_param(
+            "min",
+            json!({"type": "number"}),
+            "Minimum value of the named ramp",
+        ),
+        query_param(
+            "max",
+            json!({"type": "number"}),
+            "Maximum value of the named ramp",
+        ),
+        query_param(
+            "hillshade",
+            json!({"type": "string"}),
+            "'true' or 'azimuth,altitude'",
+        ),
+        query_param(
+            "exaggeration",
+            json!({"type": "number"}),
+            "Vertical exaggeration of the hillshade",
+        ),
+        query_param(
+            "gamma",
+            json!({"type": "number", "exclusiveMinimum": 0}),
+            "Gamma correction",
+        ),
+        query_param(
+            "brightness",
+            json!({"type": "number", "minimum": -1, "maximum": 1}),
+            "Brightness",
+        ),
+    ];
+    let content = binary_content(&[ContentType::Png, ContentType::Webp, ContentType::MvtPbf]);
+    let get = operation(
+        "tiles",
+        "Tile of the DataSource: cache, workers or generated by the proxy. \
+         Raster tiles accept the scale suffix '{y}@2x.png'",
+        parameters,
+        None,
+        responses(
+            &[
+                (200, json!({"description": "Tile", "content": content})),
+                (204, json!({"description": "Tile has no data"})),
+            ],
+            &[400, 404, 410, 502, 503, 504],
+        ),
+    );
+    paths.insert(tile, path_item("get", get));
+}
+
+fn pyramid_paths(paths: &mut Map<String, Value>) {
+    let pyramid = operation(
+        "pyramids",
+        "Pyramid of tiles of the DataSource, 'scheduled_for' defers it to the job queue",
+        vec![],
+        Some(json_body(json!({
+            "type": "object",
+            "required": ["datasource_id"],
+            "properties": {
+                "datasource_id": {"type": "string", "format": "uuid"},
+                "scheduled_for": {"type": "string", "format": "date-time"},
+                "atomic_swap": {"type": "boolean", "default": false}
+            },
+            "additionalProperties": true
+        }))),
+        responses(
+            &[(
+                202,
+                json_response("Pyramid is started or scheduled", any_object()),
+            )],
+            &[400, 404, 410, 500, 507],
+        ),
+    );
+    paths.insert(EndPoints::Pyramid.to_string(), path_item("post", pyramid));
+
+    let smart_pyramid = json!({
+        "type": "object",
+        "properties": {
+            "status": {"type": "integer"},
+            "message": {"type": "string"},
+            "pyramid_id": {"type": "string", "format": "uuid"},
+            "tiles": {"type": "integer"}
+        }
+    });
+    let smart = operation(
+        "pyramids",
+        "Tiles of the DataSource which missed the cache in the last days",
+        vec![],
+        Some(json_body(json!({
+            "type": "object",
+            "required": ["datasource_id"],
+            "properties": {
+                "datasource_id": {"type": "string", "format": "uuid"},
+                "days": {"type": "integer", "minimum": 1},
+                "max_tiles": {"type": "integer", "minimum": 1}
+            }
+        }))),
+        responses(
+            &[
+                (200, json_response("No missed tiles", message_schema())),
+                (
+                    202,
+                    json_response("Smart pyramid is started", smart_pyramid),
+                ),
+            ],
+            &[400, 404, 410, 500, 507],
+        ),
+    );
+    paths.insert(
+        EndPoints::PyramidSmart.to_string(),
+        path_item("post", smart),
+    );
+
+    let delta_pyramid = json!({
+        "type": "object",
+        "properties": {
+            "status": {"type": "integer"},
+            "message": {"type": "string"},
+            "pyramid_id": {"type": "string", "format": "uuid"},
+            "tiles": {"type": "integer"},
+            "files": {"type": "array", "items": {"type": "string"}}
+        }
+    });
+    let delta = operation(
+        "pyramids",
+        "Tiles of the area or of source files changed since the last pyramid",
+        vec![],
+        Some(json_body(json!({
+            "type": "object",
+            "required": ["datasource_id"],
+            "properties": {
+                "datasource_id": {"type": "string", "format": "uuid"},
+                "bbox": {
+                    "type": "array",
+                    "items": {"type": "number"},
+                    "minItems": 4,
+                    "maxItems": 4
+                },
+                "minzoom": {"type": "integer", "minimum": 0},
+                "maxzoom": {"type": "integer", "minimum": 0},
+                "max_tiles": {"type": "integer", "minimum": 1},
+                "scheduled_for": {"type": "string", "format": "date-time"}
+            }
+        }))),
+        responses(
+            &[
+                (
+                    200,
+                    json_response("No changed tiles", delta_pyramid.clone()),
+                ),
+                (
+                    202,
+                    json_response("Delta pyramid is started or scheduled", delta_pyramid),
+                ),
+            ],
+            &[400, 404, 409, 410, 422, 500, 507],
+        ),
+    );
+    paths.insert(
+        EndPoints::PyramidDelta.to_string(),
+        path_item("post", delta),
+    );
+
+    let started_job = json!({
+        "type": "object",
+        "properties": {
+            "status": {"type": "integer"},
+            "message": {"type": "string"},
+            "job_id": {"type": "string", "format": "uuid"},
+            "job_url": {"type": "string"},
+            "shards": {"type": "integer"},
+            "nodes": {"type": "array", "items": {"type": "string"}},
+            "tiles": {"type": "integer"}
+        }
+    });
+    let distributed = operation(
+        "pyramids",
+        "Pyramid of zoom levels or regions created by nodes of the cluster",
+        vec![],
+        Some(json_body(json!({
+            "type": "object",
+            "required": ["datasource_id"],
+            "properties": {
+                "datasource_id": {"type": "string", "format": "uuid"},
+                "shard_by": {"type": "string", "enum": ["zoom", "region"]},
+                "regions": {"type": "integer", "minimum": 1},
+                "minzoom": {"type": "integer", "minimum": 0},
+                "maxzoom": {"type": "integer", "minimum": 0},
+                "max_tiles": {"type": "integer", "minimum": 1}
+            }
+        }))),
+        responses(
+            &[(
+                202,
+                json_response("Distributed pyramid is started", started_job.clone()),
+            )],
+            &[400, 404, 410, 500, 503, 507],
+        ),
+    );
+    paths.insert(
+        EndPoints::PyramidDistributed.to_string(),
+        path_item("post", distributed),
+    );
+
+    let shard = operation(
+        "pyramids",
+        "Shard of the distributed pyramid, requested by the master",
+        vec![],
+        Some(json_body(json!({
+            "type": "object",
+            "required": ["datasource_id", "bbox"],
+            "properties": {
+                "datasource_id": {"type": "string", "format": "uuid"},
+                "bbox": {
+                    "type": "array",
+                    "items": {"type": "number"},
+                    "minItems": 4,
+                    "maxItems": 4
+                },
+                "minzoom": {"type": "integer", "minimum": 0},
+                "maxzoom": {"type": "integer", "minimum": 0},
+                "max_tiles": {"type": "integer", "minimum": 1}
+            }
+        }))),
+        responses(
+            &[(202, json_response("Shard is started", started_job))],
+            &[400, 404, 410, 422, 500, 507],
+        ),
+    );
+    paths.insert(
+        EndPoints::PyramidShard.to_string(),
+        path_item("post", shard),
+    );
+
+    let cancel_shard = operation(
+        "pyramids",
+        "Cancel the shard of the distributed pyramid, requested by the master",
+        vec![path_param(
+            "job_id",
+            json!({"type": "string"}),
+            "Id of the job of the shard",
+        )],
+        None,
+        responses(
+            &[(200, json_response("Job of the shard", any_object()))],
+            &[401, 404],
+        ),
+    );
+    paths.insert(
+        format!("{}/{{job_id}}", EndPoints::PyramidShard),
+        path_item("delete", cancel_shard),
+    );
+}
+
+fn datasource_paths(paths: &mut Map<String, Value>) {
+    let datasources = EndPoints::DataSources.to_string();
+    let datasource = json!({"$ref": "#/components/schemas/DataSource"});
+    let list = json!({"type": "array", "items": datasource});
+    let get = operation(
+        "datasources",
+        "All DataSources",
+        vec![],
+        None,
+        responses(&[(200, json_response("DataSources", list))], &[500]),
+    );
+    let post = operation(
+        "datasources",
+        "Create the DataSource",
+        vec![],
+        Some(json_body(datasource.clone())),
+        responses(
+            &[(200, json_response("Created", message_schema()))],
+            &[400, 422, 500],
+        ),
+    );
+    let put = operation(
+        "datasources",
+        "Update the DataSource",
+        vec![],
+        Some(json_body(datasource.clone())),
+        responses(
+            &[(200, json_response("Updated", message_schema()))],
+            &[400, 404, 422, 500],
+        ),
+    );
+    let delete = operation(
+        "datasources",
+        "Delete the DataSource, 'soft' moves its cache to the trash",
+        vec![],
+        Some(json_body(json!({
+            "type": "object",
+            "required": ["datasource_id"],
+            "properties": {
+                "datasource_id": {"type": "string", "format": "uuid"},
+                "soft": {"type": "boolean"}
+            }
+        }))),
+        responses(
+            &[(200, json_response("Deleted", any_object()))],
+            &[400, 404, 500],
+        ),
+    );
+    paths.insert(
+        datasources.clone(),
+        json!({"get": get, "post": post, "put": put, "delete": delete}),
+    );
+
+    let get = operation(
+        "datasources",
+        "DataSource",
+        vec![datasource_id_param()],
+        None,
+        responses(
+            &[(200, json_response("DataSource", datasource.clone()))],
+            &[404, 500],
+        ),
+    );
+    paths.insert(
+        format!("{datasources}/{{datasource_id}}"),
+        path_item("get", get),
+    );
+
+    // sub-resources of the DataSource
+    let sub_resources = [
+        (
+            "get",
+            "stats",
+            "Statistics of the cache of the DataSource",
+            vec![],
+        ),
+        (
+            "get",
+            "usage",
+            "Requests of tiles of the DataSource",
+            vec![query_param(
+                "limit",
+                json!({"type": "integer", "minimum": 1}),
+                "Limit of cells",
+            )],
+        ),
+        (
+            "get",
+            "metadata",
+            "Metadata of MBTiles of the DataSource",
+            vec![],
+        ),
+        (
+            "post",
+            "restore",
+            "Restore the soft-deleted DataSource",
+            vec![],
+        ),
+        (
+            "post",
+            "validate",
+            "Validate the DataSource without registration",
+            vec![],
+        ),
+    ];
+    for (method, name, summary, mut parameters) in sub_resources {
+        parameters.insert(0, datasource_id_param());
+        let body = (name == "validate").then(|| json_body(datasource.clone()));
+        let mut operation = operation(
+            "datasources",
+            summary,
+            parameters,
+            body,
+            responses(
+                &[(200, json_response(summary, any_object()))],
+                &[400, 404, 409, 500],
+            ),
+        );
+        operation["operationId"] = json!(format!("{method}_datasource_{name}"));
+        paths.insert(
+            format!("{datasources}/{{datasource_id}}/{name}"),
+            path_item(method, operation),
+        );
+    }
+
+    let files_type = query_param(
+        "type",
+        json!({"type": "string", "enum": ["raster", "vector"]}),
+        "Directory of the files",
+    );
+    let files = operation(
+        "uploads",
+        "Upload files of the DataSource (multipart/form-data)",
+        vec![datasource_id_param(), files_type.clone()],
+        Some(json!({
+            "required": true,
+            "content": {"multipart/form-data": {"schema": {"type": "object"}}}
+        })),
+        responses(
+            &[(200, json_response("Uploaded", any_object()))],
+            &[400, 413, 422, 500],
+        ),
+    );
+    paths.insert(
+        format!("{datasources}/{{datasource_id}}/files"),
+        path_item("post", files),
+    );
+
+    let create_upload = operation(
+        "uploads",
+        "Create the resumable upload (tus 1.0) with the header 'Upload-Length'",
+        vec![
+            datasource_id_param(),
+            files_type,
+            query_param("name", json!({"type": "string"}), "Name of the file"),
+            query_param("sha256", json!({"type": "string"}), "Checksum of the file"),
+            query_param(
+                "pyramid",
+                json!({"type": "boolean"}),
+                "Schedule the pyramid after the upload",
+            ),
+        ],
+        None,
+        responses(
+            &[(201, json_response("Upload is created", any_object()))],
+            &[400, 413, 500],
+        ),
+    );
+    paths.insert(
+        format!("{datasources}/{{datasource_id}}/uploads"),
+        path_item("post", create_upload),
+    );
+
+    let upload_id = path_param("upload_id", json!({"type": "string"}), "Id of the upload");
+    let head = operation(
+        "uploads",
+        "Offset of the upload in the header 'Upload-Offset'",
+        vec![datasource_id_param(), upload_id.clone()],
+        None,
+        responses(
+            &[(200, json!({"description": "Offset of the upload"}))],
+            &[404],
+        ),
+    );
+    let patch = operation(
+        "uploads",
+        "Append the body to the upload at 'Upload-Offset'",
+        vec![datasource_id_param(), upload_id.clone()],
+        Some(json!({
+            "required": true,
+            "content": binary_content(&[ContentType::OffsetOctetStream])
+        })),
+        responses(
+            &[
+                (200, json_response("Upload is completed", any_object())),
+                (204, json!({"description": "Chunk is appended"})),
+            ],
+            &[400, 404, 409, 413, 422, 500],
+        ),
+    );
+    let delete = operation(
+        "uploads",
+        "Cancel the upload",
+        vec![datasource_id_param(), upload_id],
+        None,
+        responses(
+            &[(204, json!({"description": "Upload is cancelled"}))],
+            &[404, 409],
+        ),
+    );
+    paths.insert(
+        format!("{datasources}/{{datasource_id}}/uploads/{{upload_id}}"),
+        json!({"head": head, "patch": patch, "delete": delete}),
+    );
+
+    let load_result = json!({
+        "type": "object",
+        "properties": {
+            "load_vector_datasources": {"type": "integer"},
+            "load_raster_datasources": {"type": "integer"},
+            "errors": {"type": "array", "items": {}}
+        }
+    });
+    let load = operation(
+        "datasources",
+        "Load DataSources from JSON files",
+        vec![],
+        None,
+        responses(
+            &[(200, json_response("Loaded", load_result.clone()))],
+            &[500],
+        ),
+    );
+    paths.insert(
+        EndPoints::DataSourcesLoadFiles.to_string(),
+        path_item("post", load),
+    );
+    let reload = operation(
+        "datasources",
+        "Reload the DataSources from JSON files",
+        vec![],
+        Some(json_body(json!({
+            "type": "array",
+            "items": {"type": "string", "format": "uuid"}
+        }))),
+        responses(
+            &[(200, json_response("Reloaded", load_result))],
+            &[400, 500],
+        ),
+    );
+    paths.insert(
+        EndPoints::DataSourcesReloadFiles.to_string(),
+        path_item("post", reload),
+    );
+}
+
+fn job_paths(paths: &mut Map<String, Value>) {
+    let job = json!({
+        "type": "object",
+        "properties": {
+            "status": {"type": "integer"},
+            "message": {"type": "string"},
+            "job_id": {"type": "string"},
+            "job_url": {"type": "string"}
+        }
+    });
+    let export = operation(
+        "exports",
+        "Offline package (MBTiles) of the cache of the DataSource",
+        vec![datasource_id_param()],
+        Some(json_body(json!({
+            "type": "object",
+            "properties": {
+                "bbox": {
+                    "type": "array",
+                    "items": {"type": "number"},
+                    "minItems": 4,
+                    "maxItems": 4
+                },
+                "minzoom": {"type": "integer"},
+                "maxzoom": {"type": "integer"},
+                "format": {"type": "string"}
+            }
+        }))),
+        responses(
+            &[(202, json_response("Export job is started", job))],
+            &[400, 404, 500],
+        ),
+    );
+    paths.insert(
+        format!("{}/{{datasource_id}}", EndPoints::Export),
+        path_item("post", export),
+    );
+
+    let download = operation(
+        "exports",
+        "Download the offline package",
+        vec![
+            path_param("job_id", json!({"type": "string"}), "Id of the export job"),
+            query_param("token", json!({"type": "string"}), "Token of the download"),
+        ],
+        None,
+        responses(
+            &[(
+                200,
+                json!({
+                    "description": "MBTiles",
+                    "content": binary_content(&[ContentType::MBTiles])
+                }),
+            )],
+            &[403, 404, 410],
+        ),
+    );
+    paths.insert(
+        format!("{}/{{job_id}}", EndPoints::ExportDownload),
+        path_item("get", download),
+    );
+
+    let state = operation(
+        "jobs",
+        "State of the job",
+        vec![path_param(
+            "job_id",
+            json!({"type": "string"}),
+            "Id of the job",
+        )],
+        None,
+        responses(
+            &[(200, json_response("State of the job", any_object()))],
+            &[404],
+        ),
+    );
+    paths.insert(
+        format!("{}/{{job_id}}", EndPoints::Jobs),
+        path_item("get", state),
+    );
+}
+
+fn service_paths(paths: &mut Map<String, Value>) {
+    let gets = [
+        (
+            EndPoints::TileGrids,
+            "tiles",
+            "Registered tile grids",
+            ContentType::ApplicationJson,
+        ),
+        (
+            EndPoints::Health,
+            "service",
+            "Health of the workers",
+            ContentType::ApplicationJson,
+        ),
+        (
+            EndPoints::Events,
+            "service",
+            "Stream of server events",
+            ContentType::EventStream,
+        ),
+        (
+            EndPoints::Metrics,
+            "service",
+            "Prometheus metrics",
+            ContentType::Prometheus,
+        ),
+        (
+            EndPoints::Healthz,
+            "service",
+            "Liveness of the balancer",
+            ContentType::ApplicationJson,
+        ),
+        (
+            EndPoints::Readyz,
+            "service",
+            "Readiness of the balancer",
+            ContentType::ApplicationJson,
+        ),
+        (
+            EndPoints::ClusterNodes,
+            "cluster",
+            "Nodes of the cluster",
+            ContentType::ApplicationJson,
+        ),
+        (
+            EndPoints::InfoWorkers,
+            "maintenance",
+            "State of the workers",
+            ContentType::ApplicationJson,
+        ),
+        (
+            EndPoints::LimitConcurrentRequests,
+            "maintenance",
+            "Permits of concurrent requests to workers by ports",
+            ContentType::ApplicationJson,
+        ),
+    ];
+    for (endpoint, tag, summary, content_type) in gets {
+        let response = json!({
+            "description": summary,
+            "content": {content_type.as_ref(): {"schema": {}}}
+        });
+        let get = operation(
+            tag,
+            summary,
+            vec![],
+            None,
+            responses(&[(200, response)], &[500, 503]),
+        );
+        paths.insert(endpoint.to_string(), path_item("get", get));
+    }
+
+    let audit = operation(
+        "maintenance",
+        "Audit log of maintenance actions, mutations of DataSources and pyramids, the latest go first",
+        vec![
+            query_param(
+                "since",
+                json!({"type": "integer"}),
+                "UNIX time of the earliest action",
+            ),
+            query_param(
+                "until",
+                json!({"type": "integer"}),
+                "UNIX time of the latest action",
+            ),
+            query_param(
+                "action",
+                json!({"type": "string"}),
+                "Action, e.g. 'datasource_delete' or '/maintenance/reload_workers'",
+            ),
+            query_param(
+                "datasource_id",
+                json!({"type": "string"}),
+                "DataSource of the action",
+            ),
+            query_param(
+                "actor",
+                json!({"type": "string"}),
+                "Principal of the action, e.g. 'bearer:0123456789abcdef' or 'cluster'",
+            ),
+            query_param(
+                "limit",
+                json!({"type": "integer", "maximum": AUDIT_MAX_LIMIT}),
+                "Maximum number of actions",
+            ),
+        ],
+        None,
+        responses(&[(200, json_response("Actions", any_object()))], &[500]),
+    );
+    paths.insert(
+        EndPoints::Audit.to_string(),
+        path_item("get", audit.clone()),
+    );
+    let mut audit_log = audit;
+    audit_log["tags"] = json!(["audit"]);
+    paths.insert(EndPoints::AuditLog.to_string(), path_item("get", audit_log));
+
+    let usage = operation(
+        "usage",
+        "Tiles and bytes served per API key, DataSource and day, the latest days go first",
+        vec![
+            query_param(
+                "api_key",
+                json!({"type": "string"}),
+                "Id of the API key: 16 hex chars of SHA-256 of the key or 'anonymous'",
+            ),
+            query_param(
+                "datasource_id",
+                json!({"type": "string"}),
+                "DataSource of served tiles",
+            ),
+            query_param(
+                "since",
+                json!({"type": "string", "format": "date"}),
+                "The earliest day (UTC)",
+            ),
+            query_param(
+                "until",
+                json!({"type": "string", "format": "date"}),
+                "The latest day (UTC)",
+            ),
+            query_param(
+                "limit",
+                json!({"type": "integer", "maximum": BILLING_MAX_LIMIT}),
+                "Maximum number of counters",
+            ),
+        ],
+        None,
+        responses(
+            &[(200, json_response("Counters with totals", any_object()))],
+            &[400, 404, 500],
+        ),
+    );
+    paths.insert(EndPoints::Usage.to_string(), path_item("get", usage));
+
+    let debug_route = operation(
+        "service",
+        "Routing of the request explained without its execution: endpoint, DataSource, cache and target",
+        vec![
+            query_param(
+                "path",
+                json!({"type": "string"}),
+                "Path of the request with its query, e.g. '/api/tile/roads/10/511/340.pbf'",
+            ),
+            query_param(
+                "method",
+                json!({"type": "string", "default": "GET"}),
+                "Method of the request",
+            ),
+        ],
+        None,
+        responses(
+            &[(200, json_response("Explanation of the routing", any_object()))],
+            &[400, 404, 500],
+        ),
+    );
+    paths.insert(
+        EndPoints::DebugRoute.to_string(),
+        path_item("get", debug_route),
+    );
+
+    let posts = [
+        (
+            EndPoints::ClusterRegister,
+            "cluster",
+            "Register the node in the cluster",
+            json!({
+                "address": {"type": "string"},
+                "datasources": {"type": "array", "items": {"type": "string"}}
+            }),
+        ),
+        (
+            EndPoints::AddWorkers,
+            "maintenance",
+            "Start workers",
+            json!({"count": {"type": "integer"}}),
+        ),
+        (
+            EndPoints::ReloadWorkers,
+            "maintenance",
+            "Reload the workers",
+            json!({
+                "confirm": {"type": "string", "enum": ["reload_workers"]},
+                "dry_run": {"type": "boolean"}
+            }),
+        ),
+        (
+            EndPoints::TerminateWorkers,
+            "maintenance",
+            "Terminate the workers",
+            json!({
+                "confirm": {"type": "string", "enum": ["terminate_workers"]},
+                "dry_run": {"type": "boolean"}
+            }),
+        ),
+        (
+            EndPoints::IncreaseLimitConcurrentRequests,
+            "maintenance",
+            "Increase the limit of concurrent requests to workers",
+            json!({"n": {"type": "integer"}}),
+        ),
+        (
+            EndPoints::DecreaseLimitConcurrentRequests,
+            "maintenance",
+            "Decrease the limit of concurrent requests to workers",
+            json!({"n": {"type": "integer"}}),
+        ),
+        (
+            EndPoints::LogLevel,
+            "maintenance",
+            "Change the log level",
+            json!({"level": {"type": "string"}, "workers": {"type": "boolean"}}),
+        ),
+        (
+            EndPoints::SavePids,
+            "maintenance",
+            "Write PIDs of the workers to the file again",
+            json!({}),
+        ),
+    ];
+    for (endpoint, tag, summary, properties) in posts {
+        let post = operation(
+            tag,
+            summary,
+            vec![],
+            Some(json_body(
+                json!({"type": "object", "properties": properties}),
+            )),
+            responses(
+                &[(200, json_response(summary, any_object()))],
+                &[400, 401, 500],
+            ),
+        );
+        paths.insert(endpoint.to_string(), path_item("post", post));
+    }
+}
+
+fn style_paths(paths: &mut Map<String, Value>) {
+    let segment =
+        |name: &str, description: &str| path_param(name, json!({"type": "string"}), description);
+    let styles = [
+        (
+            format!("{}/{{style_id}}/style.json", EndPoints::Styles),
+            "Style of MapLibre/Mapbox GL",
+            vec![segment("style_id", "Id of the style")],
+        ),
+        (
+            format!("{}/{{sprite_file}}", EndPoints::Sprites),
+            "Sprite: '{id}.png', '{id}.json', '{id}@2x.png', '{id}@2x.json'",
+            vec![segment("sprite_file", "File of the sprite")],
+        ),
+        (
+            format!("{}/{{fontstack}}/{{range}}.pbf", EndPoints::Fonts),
+            "Glyphs of the font stack",
+            vec![
+                segment("fontstack", "Comma-separated fonts"),
+                segment("range", "Range of glyphs, e.g. '0-255'"),
+            ],
+        ),
+    ];
+    for (path, summary, parameters) in styles {
+        let get = operation(
+            "styles",
+            summary,
+            parameters,
+            None,
+            responses(&[(200, json!({"description": summary}))], &[400, 404]),
+        );
+        paths.insert(path, path_item("get", get));
+    }
+}
+
+/*
+    OpenAPI 3.0 document of the public endpoints of the balancer,
+    paths are built from 'EndPoints', codes of errors from 'ErrorCode'
+*/
+pub fn openapi_document() -> Value {
+    let mut paths = Map::new();
+    tile_paths(&mut paths);
+    pyramid_paths(&mut paths);
+    datasource_paths(&mut paths);
+    job_paths(&mut paths);
+    service_paths(&mut paths);
+    style_paths(&mut paths);
+    // routes of the API are documented under the current version
+    let paths: Map<String, Value> = paths
+        .into_iter()
+        .map(|(path, item)| (ApiVersion::V1.path(&path), item))
+        .collect();
+
+    let codes: Vec<&'static str> = ErrorCode::iter().map(|c| c.into()).collect();
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "Tiler API",
+            "version": env!("CARGO_PKG_VERSION"),
+            "description": "Tiles, pyramids and DataSources of the tile server. \
+                Unversioned paths '/api/...' are aliases of '/api/v1/...'"
+        },
+        "paths": paths,
+        "components": {
+            "schemas": {
+                "ApiError": {
+                    "type": "object",
+                    "required": ["status", "code", "message"],
+                    "properties": {
+                        "status": {"type": "integer"},
+                        "code": {"type": "string", "enum": codes},
+                        "message": {"type": "string"},
+                        "details": {"type": "object", "additionalProperties": true},
+                        "request_id": {"type": "string"}
+                    }
+                },
+                "DataSource": {
+                    "type": "object",
+                    "required": ["id", "type", "dataStore"],
+                    "properties": {
+                        "id": {"type": "string", "format": "uuid"},
+                        "type": {"type": "string", "enum": ["raster", "vector"]},
+                        "name": {"type": "string"},
+                        "minzoom": {"type": "integer"},
+                        "maxzoom": {"type": "integer"},
+                        "dataStore": {"type": "object", "additionalProperties": true}
+                    },
+                    "additionalProperties": true
+                }
+            }
+        }
+    })
+}
+
+// GET /api/openapi.json
+pub fn openapi_endpoint() -> Response<Body> {
+    response_with_body_and_code(openapi_document().to_string(), StatusCode::OK)
+}
+
+// GET /api/docs: Swagger UI of the document, the page loads the UI from the CDN
+pub fn swagger_ui_endpoint(config: &Config) -> Result<Response<Body>, anyhow::Error> {
+    if !config.openapi_swagger_ui {
+        return Ok(ApiError::new(StatusCode::NOT_FOUND, "Swagger UI is disabled").into_response());
+    }
+    let page = format!(
+        r##"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>Tiler API</title>
+<link rel="stylesheet" href="{SWAGGER_UI_CDN}/swagger-ui.css">
+</head>
+<body>
+<div id="swagger-ui"></div>
+<script src="{SWAGGER_UI_CDN}/swagger-ui-bundle.js"></script>
+<script>
+window.ui = SwaggerUIBundle({{url: "{}", dom_id: "#swagger-ui"}});
+</script>
+</body>
+</html>
+"##,
+        EndPoints::OpenApi
+    );
+    let response = Response::builder()
+        .status(StatusCode::OK)
+        .content_type("text/html; charset=utf-8")
+        .body(Body::from(page))?;
+    Ok(response)
+}
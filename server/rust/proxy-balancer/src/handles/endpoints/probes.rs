@@ -0,0 +1,105 @@
+use std::time::Duration;
+
+use hyper::{Body, Response, StatusCode};
+use serde_json::json;
+use tiler_core::sqlite_clients::MessageSQLiteClient;
+use tracing::{event, Level};
+
+use crate::config::Config;
+use crate::db::registry::SharedRegistry;
+use crate::defaults::PROBE_TIMEOUT_SECS;
+use crate::handles::helpers::{get_worker_data, response_with_body_and_code};
+use crate::tasks::reload_workers::MessageMaintenanceWorkers;
+use crate::tasks::workers::worker_healthy;
+
+// GET /healthz: liveness of the balancer process, workers are not checked
+pub fn healthz_endpoint() -> Response<Body> {
+    let body = json!({
+        "status": StatusCode::OK.as_u16(),
+        "message": "alive"
+    })
+    .to_string();
+    response_with_body_and_code(body, StatusCode::OK)
+}
+
+/*
+    GET /readyz: readiness of the balancer to serve traffic
+    - registry of DataSources (PostgreSQL or SQLite) is reachable
+    - a quorum of Python workers passed the health check after startup or reload,
+      at least one of them answers the readiness path ('/api/health') now,
+      in native mode there are no workers to check
+    - task of SQLite clients (MBTiles) is alive
+    Every check is limited by 'PROBE_TIMEOUT_SECS', failed checks give 503
+*/
+pub async fn readyz_endpoint(
+    registry: &SharedRegistry,
+    workers_ready: bool,
+    tx_mw: flume::Sender<MessageMaintenanceWorkers>,
+    tx_sqlite_client: &flume::Sender<MessageSQLiteClient>,
+    config: &Config,
+) -> Response<Body> {
+    let timeout = Duration::from_secs(PROBE_TIMEOUT_SECS);
+
+    let database = match tokio::time::timeout(timeout, registry.ping()).await {
+        Ok(Ok(_)) => true,
+        Ok(Err(err)) => {
+            event!(Level::ERROR, "Readiness: database is unreachable: {err}");
+            false
+        }
+        Err(_) => {
+            event!(Level::ERROR, "Readiness: no response of database");
+            false
+        }
+    };
+
+    let workers = workers_ready
+        && match tokio::time::timeout(timeout, any_worker_healthy(tx_mw, config)).await {
+            Ok(healthy) => healthy,
+            Err(_) => {
+                event!(Level::ERROR, "Readiness: no response of workers");
+                false
+            }
+        };
+
+    let sqlite_clients = !tx_sqlite_client.is_disconnected();
+
+    let ready = database && workers && sqlite_clients;
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    let body = json!({
+        "status": status.as_u16(),
+        "ready": ready,
+        "checks": {
+            "database": database,
+            "workers": workers,
+            "sqlite_clients": sqlite_clients,
+        }
+    })
+    .to_string();
+    response_with_body_and_code(body, status)
+}
+
+async fn any_worker_healthy(
+    tx_mw: flume::Sender<MessageMaintenanceWorkers>,
+    config: &Config,
+) -> bool {
+    if config.is_native() {
+        return true;
+    }
+    let wd = match get_worker_data(tx_mw).await {
+        Ok(wd) => wd,
+        Err(err) => {
+            event!(Level::ERROR, "Readiness: no workers: {err}");
+            return false;
+        }
+    };
+    for port in wd.ports {
+        if worker_healthy(&wd.client, port, &config.worker_readiness_path).await {
+            return true;
+        }
+    }
+    false
+}
@@ -1,25 +1,57 @@
 use std::net::IpAddr;
-use std::path::PathBuf;
 
 use anyhow::anyhow;
 use hyper::{body::Bytes, client::HttpConnector, Body, Client, Request, Response, StatusCode};
 use serde_json::json;
 use sqlx::types::Json;
+use tiler_core::sqlite_clients::MessageSQLiteClient;
+use tokio::sync::oneshot;
 use tracing::{event, Level};
+use uuid::Uuid;
 
+use crate::config::Config;
 use crate::db::{
+    export::{ExportArea, ZoomRange},
+    init_mbtiles_db,
     job::queue::{JobDetail, JobType},
-    DB,
+    registry::SharedRegistry,
+    storage_compressed,
+};
+use crate::defaults::{
+    DELTA_PYRAMID_MAX_TILES, DISTRIBUTED_PYRAMID_MAX_REGIONS, LOCALHOST, LOCAL_NODE,
+    SMART_PYRAMID_DAYS, SMART_PYRAMID_MAX_TILES,
+};
+use crate::handles::error::{ApiError, ErrorCode};
+use crate::handles::helpers::{
+    error_response, gone_response, json_body, required_str, response_with_body_and_code,
 };
-use crate::defaults::LOCALHOST;
-use crate::handles::helpers::{error_response, response_with_body_and_code};
 use crate::hyper_reverse_proxy;
-use crate::tasks::sqlite_clients::MessageSQLiteClient;
+use crate::paths::Paths;
+use crate::sources::{
+    load_sources_state, save_sources_state, scan_sources, source_changes, SourceState,
+};
+use crate::structs::{EndPoints, Extension};
+use crate::tasks::cluster::MessageCluster;
+use crate::tasks::datasources::MessageDatasource;
+use crate::tasks::distributed::{
+    start_distributed_pyramid, start_pyramid_shard, DistributedContext, DistributedPyramid,
+    PyramidShard, ShardState,
+};
+use crate::tasks::events::MessageEvents;
+use crate::tasks::exports::{export_event, MessageExports};
+use crate::tasks::mbtiles_metadata::{pyramid_atomic_swap, watch_pyramid_response, PyramidWatch};
+use crate::tasks::seeding::{start_seeding, SeedingContext, SeedingKind};
+use crate::tasks::usage::{MessageUsage, MissedTile};
 use crate::utils::{dataset_dir_from_ds_id, mbtiles_path_from_ds_id, try_init_mbtiles};
+use crate::webhooks::PyramidWebhooks;
+
+const SHARD_BY_ZOOM: &str = "zoom";
+const SHARD_BY_REGION: &str = "region";
 
 pub async fn pyramid_endpoint(
-    cwd: String,
-    pool: DB,
+    paths: Paths,
+    registry: SharedRegistry,
+    tx: flume::Sender<MessageDatasource>,
     tx_sqlite_client: flume::Sender<MessageSQLiteClient>,
     opt_tx_jd: Option<flume::Sender<JobDetail>>,
     client_ip: IpAddr,
@@ -27,12 +59,20 @@ pub async fn pyramid_endpoint(
     req: Request<Body>,
     b_bytes: &Bytes,
     client: Client<HttpConnector>,
+    webhooks: PyramidWebhooks,
 ) -> Result<Response<Body>, anyhow::Error> {
-    let body_json: serde_json::Value = serde_json::from_slice(&b_bytes)?;
-    let datasource_id = body_json
-        .get("datasource_id")
-        .and_then(|id| id.as_str())
-        .expect("datasource_id is undefined");
+    let body_json = json_body(b_bytes)?;
+    let datasource_id = required_str(&body_json, "datasource_id")?;
+
+    let (tx_ds, rx_ds) = oneshot::channel();
+    tx.send_async(MessageDatasource::GetDataSource {
+        datasource_id: datasource_id.to_string(),
+        tx_ds,
+    })
+    .await?;
+    if let Some(entry) = rx_ds.await?.and_then(|ds| ds.trash) {
+        return Ok(gone_response(&entry));
+    }
 
     if let Some(tx_jd) = opt_tx_jd {
         let scheduled_for = body_json.get("scheduled_for");
@@ -76,33 +116,39 @@ pub async fn pyramid_endpoint(
         }
     }
 
-    let dataset_dir = dataset_dir_from_ds_id(&cwd, datasource_id)?;
-    let mbtiles_db = mbtiles_path_from_ds_id(&cwd, datasource_id)?
-        .into_os_string()
-        .into_string()
-        .map_err(|err| anyhow!("{err:?}"))?;
-    if let Err(err) = tx_sqlite_client
-        .send_async(MessageSQLiteClient::RemoveSQLiteClient {
-            mbtiles_db: mbtiles_db.clone(),
-            remove_tiles_folder: None,
-            remove_tiles_db: Some(true),
-        })
-        .await
-    {
-        event!(
-            Level::ERROR,
-            "Error send message to Remove SQLite client {err:?}"
-        );
-    }
+    let dataset_dir = dataset_dir_from_ds_id(&paths, datasource_id)?;
+    // with 'atomic_swap' the cache is served until the new MBTiles is swapped in
+    let atomic_swap = pyramid_atomic_swap(&body_json);
+    if !atomic_swap {
+        let mbtiles_db = mbtiles_path_from_ds_id(&paths, datasource_id)?
+            .into_os_string()
+            .into_string()
+            .map_err(|err| anyhow!("{err:?}"))?;
+        if let Err(err) = tx_sqlite_client
+            .send_async(MessageSQLiteClient::RemoveSQLiteClient {
+                mbtiles_db: mbtiles_db.clone(),
+                remove_tiles_folder: None,
+                remove_tiles_db: Some(true),
+            })
+            .await
+        {
+            event!(
+                Level::ERROR,
+                "Error send message to Remove SQLite client {err:?}"
+            );
+        }
 
-    if let Err(err) = remove_mbtiles_files(&cwd, datasource_id, &mbtiles_db).await {
-        event!(
-            Level::ERROR,
-            "Error remove MBTiles files '{mbtiles_db}' {err:?}"
-        );
+        if let Err(err) = remove_mbtiles_files(&paths, datasource_id, &mbtiles_db).await {
+            event!(
+                Level::ERROR,
+                "Error remove MBTiles files '{mbtiles_db}' {err:?}"
+            );
+        }
     }
 
-    if let Some(response) = try_init_mbtiles(&cwd, dataset_dir, datasource_id, pool, None).await {
+    if let Some(response) =
+        try_init_mbtiles(&paths, dataset_dir, datasource_id, registry.clone(), None).await
+    {
         // return Response with Error
         return Ok(response);
     }
@@ -118,7 +164,21 @@ pub async fn pyramid_endpoint(
     )
     .await
     {
-        Ok(response) => Ok(response),
+        Ok(response) => {
+            watch_pyramid_response(
+                response,
+                &paths,
+                datasource_id,
+                &registry,
+                tx_sqlite_client,
+                PyramidWatch {
+                    atomic_swap,
+                    job_id: None,
+                    webhooks,
+                },
+            )
+            .await
+        }
         Err(error) => {
             event!(Level::ERROR, "Error request {:?}", error);
             let err_response = error_response(StatusCode::INTERNAL_SERVER_ERROR);
@@ -128,27 +188,17 @@ pub async fn pyramid_endpoint(
 }
 
 async fn remove_mbtiles_files(
-    cwd: &str,
+    paths: &Paths,
     datasource_id: &str,
     mbtiles_db: &str,
 ) -> Result<(), anyhow::Error> {
-    let wal_file: PathBuf = [
-        cwd,
-        "tiles",
-        datasource_id,
-        &format!("{}.mbtiles-wal", datasource_id),
-    ]
-    .iter()
-    .collect();
-
-    let shm_file: PathBuf = [
-        cwd,
-        "tiles",
-        datasource_id,
-        &format!("{}.mbtiles-shm", datasource_id),
-    ]
-    .iter()
-    .collect();
+    let wal_file = paths
+        .tiles_dir(datasource_id)
+        .join(format!("{}.mbtiles-wal", datasource_id));
+
+    let shm_file = paths
+        .tiles_dir(datasource_id)
+        .join(format!("{}.mbtiles-shm", datasource_id));
 
     tokio::time::sleep(std::time::Duration::from_millis(100)).await;
     if tokio::fs::metadata(&mbtiles_db).await.is_ok()
@@ -162,3 +212,678 @@ async fn remove_mbtiles_files(
 
     Ok(())
 }
+
+/*
+    Smart pyramid: only tiles requested and missed by the cache in the last 'days'
+    are created by workers, instead of the whole pyramid of the DataSource
+*/
+pub async fn smart_pyramid_endpoint(
+    b_bytes: &Bytes,
+    tx: flume::Sender<MessageDatasource>,
+    tx_usage: flume::Sender<MessageUsage>,
+    ctx: SeedingContext,
+) -> Result<Response<Body>, anyhow::Error> {
+    let body_json = json_body(b_bytes)?;
+    let datasource_id = required_str(&body_json, "datasource_id")?;
+    let days = body_json
+        .get("days")
+        .and_then(|d| d.as_u64())
+        .unwrap_or(SMART_PYRAMID_DAYS)
+        .max(1);
+    let max_tiles = body_json
+        .get("max_tiles")
+        .and_then(|t| t.as_u64())
+        .unwrap_or(SMART_PYRAMID_MAX_TILES);
+
+    let (tx_ds, rx_ds) = oneshot::channel();
+    tx.send_async(MessageDatasource::GetDataSource {
+        datasource_id: datasource_id.to_string(),
+        tx_ds,
+    })
+    .await?;
+    match rx_ds.await? {
+        None => {
+            return Ok(ApiError::datasource_not_found(datasource_id).into_response());
+        }
+        Some(ds) => {
+            if let Some(entry) = ds.trash.as_ref() {
+                return Ok(gone_response(entry));
+            }
+        }
+    }
+
+    let since = chrono::Utc::now().timestamp() - (days * 86400) as i64;
+    let (tx_misses, rx_misses) = oneshot::channel();
+    tx_usage
+        .send_async(MessageUsage::GetMisses {
+            datasource_id: datasource_id.to_string(),
+            since,
+            limit: max_tiles,
+            tx_misses,
+        })
+        .await?;
+    let tiles = rx_misses.await??;
+
+    if tiles.is_empty() {
+        let body = json!({
+            "status": StatusCode::OK.as_u16(),
+            "message": format!("No tiles of DataSource '{datasource_id}' missed the cache in the last {days} days"),
+            "tiles": 0
+        })
+        .to_string();
+        return Ok(response_with_body_and_code(body, StatusCode::OK));
+    }
+
+    let count = tiles.len();
+    let pyramid_id = start_seeding(ctx, SeedingKind::Smart, datasource_id.to_string(), tiles);
+    let body = json!({
+        "status": StatusCode::ACCEPTED.as_u16(),
+        "message": format!("Smart pyramid for DataSource '{datasource_id}' successfully started"),
+        "pyramid_id": pyramid_id,
+        "tiles": count
+    })
+    .to_string();
+    Ok(response_with_body_and_code(body, StatusCode::ACCEPTED))
+}
+
+// Tiles of the delta pyramid and the state of sources recorded after it
+pub struct DeltaPlan {
+    pub tiles: Vec<MissedTile>,
+    // changed source files, empty for the pyramid of 'bbox'
+    pub files: Vec<String>,
+    pub sources: Option<SourceState>,
+    // area of tiles for the package of the shard of the distributed pyramid, None - no tiles
+    pub area: Option<ExportArea>,
+}
+
+fn intersection(a: [f64; 4], b: [f64; 4]) -> Option<[f64; 4]> {
+    let bounds = [
+        a[0].max(b[0]),
+        a[1].max(b[1]),
+        a[2].min(b[2]),
+        a[3].min(b[3]),
+    ];
+    (bounds[0] <= bounds[2] && bounds[1] <= bounds[3]).then_some(bounds)
+}
+
+/*
+    Delta pyramid: tiles intersecting 'bbox' or the extent of source files changed since the last
+    pyramid are created again instead of the whole pyramid. Without 'bbox' changes are found
+    by mtimes and sizes of source files of raster DataSources recorded in 'sources.json'
+*/
+pub async fn plan_delta_pyramid(
+    paths: &Paths,
+    registry: &SharedRegistry,
+    tx: &flume::Sender<MessageDatasource>,
+    body: &serde_json::Value,
+    config: &Config,
+) -> Result<DeltaPlan, anyhow::Error> {
+    let datasource_id = required_str(body, "datasource_id")?;
+    let (tx_ds, rx_ds) = oneshot::channel();
+    tx.send_async(MessageDatasource::GetDataSource {
+        datasource_id: datasource_id.to_string(),
+        tx_ds,
+    })
+    .await?;
+    let ds_info = rx_ds
+        .await?
+        .ok_or_else(|| ApiError::datasource_not_found(datasource_id))?;
+    if ds_info.trash.is_some() {
+        return Err(ApiError::new(
+            StatusCode::GONE,
+            format!("DataSource '{datasource_id}' is deleted"),
+        )
+        .with_code(ErrorCode::DataSourceDeleted)
+        .into());
+    }
+    let ds = registry
+        .datasource(datasource_id)
+        .await?
+        .ok_or_else(|| ApiError::datasource_not_found(datasource_id))?;
+
+    let grid = ds_info
+        .tile_grid
+        .clone()
+        .unwrap_or_else(|| config.tile_grids.default_grid().clone());
+    let zoom = |field: &str, default: u8| {
+        body.get(field)
+            .and_then(|z| z.as_u64())
+            .map(|z| z as u8)
+            .unwrap_or(default)
+    };
+    let minzoom = zoom("minzoom", ds_info.minzoom.unwrap_or(grid.minzoom));
+    let maxzoom = zoom("maxzoom", ds_info.maxzoom.unwrap_or(grid.maxzoom));
+    if minzoom > maxzoom || minzoom < grid.minzoom || maxzoom > grid.maxzoom {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            format!(
+                "Zoom range {minzoom}-{maxzoom} must be in range {}-{} of tile grid '{}'",
+                grid.minzoom, grid.maxzoom, grid.name
+            ),
+        )
+        .into());
+    }
+
+    let (bounds, files, sources) = match body.get("bbox").filter(|b| !b.is_null()) {
+        Some(bbox) => {
+            let bbox: [f64; 4] = serde_json::from_value(bbox.clone()).map_err(|_| {
+                ApiError::new(
+                    StatusCode::BAD_REQUEST,
+                    "Parameter 'bbox' must be an array [west, south, east, north]",
+                )
+            })?;
+            (Some(bbox), Vec::new(), None)
+        }
+        None => {
+            let previous = load_sources_state(paths, datasource_id)
+                .await
+                .ok_or_else(|| {
+                    ApiError::new(
+                        StatusCode::CONFLICT,
+                        format!(
+                            "Sources of DataSource '{datasource_id}' are not recorded, \
+                             'bbox' is required until the next pyramid"
+                        ),
+                    )
+                })?;
+            let current = scan_sources(paths, &ds, Some(&previous))
+                .await
+                .map_err(|err| {
+                    ApiError::new(
+                        StatusCode::BAD_REQUEST,
+                        format!("{err}, 'bbox' is required"),
+                    )
+                })?;
+            let changes = source_changes(&previous, &current);
+            if changes.files.is_empty() {
+                return Ok(DeltaPlan {
+                    tiles: Vec::new(),
+                    files: Vec::new(),
+                    sources: None,
+                    area: None,
+                });
+            }
+            // extent of changes is unknown: every tile of the DataSource is created again
+            (changes.bounds, changes.files, Some(current))
+        }
+    };
+    let bounds = match (bounds, ds_info.bounds) {
+        (Some(bounds), Some(ds_bounds)) => match intersection(bounds, ds_bounds) {
+            Some(bounds) => Some(bounds),
+            None => {
+                return Ok(DeltaPlan {
+                    tiles: Vec::new(),
+                    files,
+                    sources,
+                    area: None,
+                })
+            }
+        },
+        (bounds, ds_bounds) => bounds.or(ds_bounds),
+    };
+
+    let mut ranges = Vec::new();
+    for z in minzoom..=maxzoom {
+        let range = match bounds {
+            Some(bounds) => grid.tile_range(&bounds, z).ok_or_else(|| {
+                ApiError::new(
+                    StatusCode::BAD_REQUEST,
+                    format!(
+                        "Bounds {bounds:?} are invalid for tile grid '{}' ({})",
+                        grid.name, grid.crs
+                    ),
+                )
+            })?,
+            None => {
+                let (width, height) = grid.matrix_size(z);
+                (0, 0, width - 1, height - 1)
+            }
+        };
+        ranges.push((z, range));
+    }
+    let count: u64 = ranges
+        .iter()
+        .map(|(_, (min_x, min_y, max_x, max_y))| (max_x - min_x + 1) * (max_y - min_y + 1))
+        .sum();
+    let max_tiles = body
+        .get("max_tiles")
+        .and_then(|t| t.as_u64())
+        .unwrap_or(DELTA_PYRAMID_MAX_TILES);
+    if count > max_tiles {
+        return Err(ApiError::new(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("Delta pyramid has {count} tiles, more than {max_tiles}, use the pyramid"),
+        )
+        .into());
+    }
+
+    let ext = if ds.data_type.as_deref() == Some("vector") {
+        Extension::Pbf
+    } else {
+        Extension::Png
+    };
+    let mut tiles = Vec::with_capacity(count as usize);
+    let mut zoom_ranges = Vec::with_capacity(ranges.len());
+    for (z, (min_x, min_y, max_x, max_y)) in ranges {
+        zoom_ranges.push(ZoomRange {
+            z,
+            min_x,
+            min_y,
+            max_x,
+            max_y,
+            rows: grid.matrix_size(z).1,
+        });
+        for x in min_x..=max_x {
+            for y in min_y..=max_y {
+                tiles.push(MissedTile {
+                    tile_grid: grid.name.clone(),
+                    z,
+                    x,
+                    y,
+                    ext: ext.as_ref().to_string(),
+                    misses: 0,
+                });
+            }
+        }
+    }
+    let area = ExportArea {
+        bounds: bounds.unwrap_or([-180.0, -90.0, 180.0, 90.0]),
+        minzoom,
+        maxzoom,
+        format: ext.as_ref().to_string(),
+        ranges: zoom_ranges,
+    };
+    Ok(DeltaPlan {
+        tiles,
+        files,
+        sources,
+        area: Some(area),
+    })
+}
+
+/*
+    Tiles of the plan are created again by workers of this server. None - the plan has no tiles,
+    the state of sources is recorded when only files outside of the DataSource are changed
+*/
+pub async fn start_delta_pyramid(
+    ctx: SeedingContext,
+    datasource_id: &str,
+    plan: DeltaPlan,
+) -> Option<String> {
+    if plan.tiles.is_empty() {
+        if let Some(sources) = plan.sources.as_ref() {
+            if let Err(err) = save_sources_state(&ctx.paths, datasource_id, sources).await {
+                event!(
+                    Level::ERROR,
+                    "Error save state of sources of DataSource '{datasource_id}': {err}"
+                );
+            }
+        }
+        return None;
+    }
+    let kind = SeedingKind::Delta {
+        sources: plan.sources,
+    };
+    Some(start_seeding(
+        ctx,
+        kind,
+        datasource_id.to_string(),
+        plan.tiles,
+    ))
+}
+
+// 'scheduled_for' defers the delta pyramid to the job queue
+pub async fn delta_pyramid_endpoint(
+    paths: &Paths,
+    registry: &SharedRegistry,
+    b_bytes: &Bytes,
+    tx: flume::Sender<MessageDatasource>,
+    opt_tx_jd: Option<flume::Sender<JobDetail>>,
+    ctx: SeedingContext,
+    config: &Config,
+) -> Result<Response<Body>, anyhow::Error> {
+    let body_json = json_body(b_bytes)?;
+    let datasource_id = required_str(&body_json, "datasource_id")?;
+
+    let scheduled_for = body_json.get("scheduled_for").and_then(|v| v.as_str());
+    if let (Some(tx_jd), Some(date_str)) = (opt_tx_jd, scheduled_for) {
+        let datetime = chrono::DateTime::parse_from_rfc3339(date_str).map_err(|err| {
+            ApiError::new(
+                StatusCode::BAD_REQUEST,
+                format!("'scheduled_for' is not RFC 3339 date: {err}"),
+            )
+        })?;
+        let job_detail = JobDetail {
+            jt: JobType::DeltaPyramid {
+                datasource_id: datasource_id.to_string(),
+            },
+            name: "Delta pyramid".to_string(),
+            scheduled_for: Some(datetime.with_timezone(&chrono::Utc)),
+            data: Json(body_json.clone()),
+        };
+        tx_jd.send_async(job_detail).await.map_err(|err| {
+            anyhow!("Error send job detail for DataSource {datasource_id}: {err}")
+        })?;
+        let body = json!({
+            "status": StatusCode::ACCEPTED.as_u16(),
+            "message": format!("Delta pyramid for DataSource '{datasource_id}' successfully scheduled"),
+        })
+        .to_string();
+        return Ok(response_with_body_and_code(body, StatusCode::ACCEPTED));
+    }
+
+    let plan = plan_delta_pyramid(paths, registry, &tx, &body_json, config).await?;
+    let count = plan.tiles.len();
+    let files = plan.files.clone();
+    let Some(pyramid_id) = start_delta_pyramid(ctx, datasource_id, plan).await else {
+        let body = json!({
+            "status": StatusCode::OK.as_u16(),
+            "message": format!("No tiles of DataSource '{datasource_id}' are changed"),
+            "tiles": 0,
+            "files": files,
+        })
+        .to_string();
+        return Ok(response_with_body_and_code(body, StatusCode::OK));
+    };
+    let body = json!({
+        "status": StatusCode::ACCEPTED.as_u16(),
+        "message": format!("Delta pyramid for DataSource '{datasource_id}' successfully started"),
+        "pyramid_id": pyramid_id,
+        "tiles": count,
+        "files": files,
+    })
+    .to_string();
+    Ok(response_with_body_and_code(body, StatusCode::ACCEPTED))
+}
+
+/*
+    Shard of the distributed pyramid requested by the master: tiles of 'bbox' are created again
+    by workers of this node and assembled into the package, the job is at '/api/jobs/{job_id}'
+*/
+pub async fn shard_pyramid_endpoint(
+    b_bytes: &Bytes,
+    ctx: &DistributedContext,
+) -> Result<Response<Body>, anyhow::Error> {
+    let body_json = json_body(b_bytes)?;
+    let datasource_id = required_str(&body_json, "datasource_id")?;
+    if body_json.get("bbox").filter(|b| !b.is_null()).is_none() {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "Parameter 'bbox' is required for the shard",
+        )
+        .into());
+    }
+
+    let plan = plan_delta_pyramid(
+        &ctx.seeding.paths,
+        &ctx.registry,
+        &ctx.tx,
+        &body_json,
+        &ctx.config,
+    )
+    .await?;
+    let count = plan.tiles.len();
+    let job = start_pyramid_shard(
+        ctx.seeding.clone(),
+        datasource_id,
+        plan,
+        ctx.config.export_ttl_seconds,
+        ctx.tx_exports.clone(),
+    )
+    .await?;
+    let body = json!({
+        "status": StatusCode::ACCEPTED.as_u16(),
+        "message": format!("Shard of the pyramid for DataSource '{datasource_id}' successfully started"),
+        "job_id": job.job_id,
+        "job_url": format!("{}/{}", EndPoints::Jobs.as_ref(), job.job_id),
+        "tiles": count,
+    })
+    .to_string();
+    Ok(response_with_body_and_code(body, StatusCode::ACCEPTED))
+}
+
+/*
+    Cancellation of the shard by the master which stopped waiting for it: the running job
+    is failed, tiles already created stay in the cache of the node
+*/
+pub async fn cancel_shard_endpoint(
+    job_id: &str,
+    ttl_seconds: u64,
+    tx_exports: flume::Sender<MessageExports>,
+    tx_events: flume::Sender<MessageEvents>,
+) -> Result<Response<Body>, anyhow::Error> {
+    let (tx_job, rx_job) = oneshot::channel();
+    tx_exports
+        .send_async(MessageExports::Cancel {
+            job_id: job_id.to_string(),
+            ttl_seconds,
+            tx_job,
+        })
+        .await?;
+    let Some(job) = rx_job.await? else {
+        return Err(ApiError::new(
+            StatusCode::NOT_FOUND,
+            format!("Job of the shard '{job_id}' not found"),
+        )
+        .into());
+    };
+    export_event(&tx_events, &job);
+    let body = serde_json::to_string(&job)?;
+    Ok(response_with_body_and_code(body, StatusCode::OK))
+}
+
+// Shards of the whole extent of the DataSource: one per zoom level or per region of equal width
+fn pyramid_shards(
+    shard_by: &str,
+    bounds: [f64; 4],
+    minzoom: u8,
+    maxzoom: u8,
+    regions: u64,
+    nodes: &[String],
+) -> Vec<PyramidShard> {
+    let areas: Vec<([f64; 4], u8, u8)> = if shard_by == SHARD_BY_REGION {
+        let width = (bounds[2] - bounds[0]) / regions as f64;
+        (0..regions)
+            .map(|i| {
+                let west = bounds[0] + width * i as f64;
+                let east = if i + 1 == regions {
+                    bounds[2]
+                } else {
+                    west + width
+                };
+                ([west, bounds[1], east, bounds[3]], minzoom, maxzoom)
+            })
+            .collect()
+    } else {
+        (minzoom..=maxzoom).map(|z| (bounds, z, z)).collect()
+    };
+    areas
+        .into_iter()
+        .enumerate()
+        .map(|(shard, (bbox, minzoom, maxzoom))| PyramidShard {
+            shard,
+            node: nodes[shard % nodes.len()].clone(),
+            bbox,
+            minzoom,
+            maxzoom,
+            state: ShardState::Queued,
+            tiles: None,
+            merged: None,
+            node_job_id: None,
+            message: None,
+        })
+        .collect()
+}
+
+/*
+    Distributed pyramid: zoom levels ('shard_by' = 'zoom') or regions ('shard_by' = 'region')
+    of the DataSource are assigned round-robin to this server and alive nodes of the cluster
+    with the DataSource, tiles of remote nodes are merged into MBTiles of this server
+*/
+pub async fn distributed_pyramid_endpoint(
+    b_bytes: &Bytes,
+    tx_cluster: flume::Sender<MessageCluster>,
+    ctx: DistributedContext,
+) -> Result<Response<Body>, anyhow::Error> {
+    let body_json = json_body(b_bytes)?;
+    let datasource_id = required_str(&body_json, "datasource_id")?;
+    let shard_by = body_json
+        .get("shard_by")
+        .and_then(|s| s.as_str())
+        .unwrap_or(SHARD_BY_ZOOM);
+    if shard_by != SHARD_BY_ZOOM && shard_by != SHARD_BY_REGION {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            format!("Parameter 'shard_by' must be '{SHARD_BY_ZOOM}' or '{SHARD_BY_REGION}'"),
+        )
+        .into());
+    }
+
+    let (tx_ds, rx_ds) = oneshot::channel();
+    ctx.tx
+        .send_async(MessageDatasource::GetDataSource {
+            datasource_id: datasource_id.to_string(),
+            tx_ds,
+        })
+        .await?;
+    let ds_info = match rx_ds.await? {
+        None => return Ok(ApiError::datasource_not_found(datasource_id).into_response()),
+        Some(ds) => {
+            if let Some(entry) = ds.trash.as_ref() {
+                return Ok(gone_response(entry));
+            }
+            ds
+        }
+    };
+    let ds = ctx
+        .registry
+        .datasource(datasource_id)
+        .await?
+        .ok_or_else(|| ApiError::datasource_not_found(datasource_id))?;
+    if ds.mbtiles != Some(true) {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            format!("Shards are merged into MBTiles, DataSource '{datasource_id}' has no MBTiles"),
+        )
+        .into());
+    }
+    let bounds = ds_info.bounds.ok_or_else(|| {
+        ApiError::new(
+            StatusCode::BAD_REQUEST,
+            format!("DataSource '{datasource_id}' has no bounds to split into shards"),
+        )
+    })?;
+
+    let grid = ds_info
+        .tile_grid
+        .clone()
+        .unwrap_or_else(|| ctx.config.tile_grids.default_grid().clone());
+    let zoom = |field: &str, default: u8| {
+        body_json
+            .get(field)
+            .and_then(|z| z.as_u64())
+            .map(|z| z as u8)
+            .unwrap_or(default)
+    };
+    let minzoom = zoom("minzoom", ds_info.minzoom.unwrap_or(grid.minzoom));
+    let maxzoom = zoom("maxzoom", ds_info.maxzoom.unwrap_or(grid.maxzoom));
+    if minzoom > maxzoom || minzoom < grid.minzoom || maxzoom > grid.maxzoom {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            format!(
+                "Zoom range {minzoom}-{maxzoom} must be in range {}-{} of tile grid '{}'",
+                grid.minzoom, grid.maxzoom, grid.name
+            ),
+        )
+        .into());
+    }
+    let max_tiles = body_json
+        .get("max_tiles")
+        .and_then(|t| t.as_u64())
+        .unwrap_or(DELTA_PYRAMID_MAX_TILES);
+
+    // this server takes shards only with its own workers
+    let mut nodes = Vec::new();
+    if !ctx.seeding.ports.is_empty() {
+        nodes.push(LOCAL_NODE.to_string());
+    }
+    let (tx_nodes, rx_nodes) = oneshot::channel();
+    tx_cluster
+        .send_async(MessageCluster::GetNodes { tx_nodes })
+        .await?;
+    nodes.extend(
+        rx_nodes
+            .await?
+            .into_iter()
+            .filter(|node| node.alive && node.datasources.iter().any(|id| id == datasource_id))
+            .filter(|node| ctx.config.address.as_ref() != Some(&node.address))
+            .map(|node| node.address),
+    );
+    if nodes.is_empty() {
+        return Err(ApiError::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            format!("No workers and alive cluster nodes for DataSource '{datasource_id}'"),
+        )
+        .into());
+    }
+    let regions = body_json
+        .get("regions")
+        .and_then(|r| r.as_u64())
+        .unwrap_or(nodes.len() as u64)
+        .clamp(1, DISTRIBUTED_PYRAMID_MAX_REGIONS);
+
+    init_mbtiles_db(
+        &ctx.seeding.paths,
+        datasource_id.to_string(),
+        storage_compressed(&ds.data.0),
+        Some(ctx.seeding.tx_sqlite_client.clone()),
+    )
+    .await?;
+
+    let shards = pyramid_shards(shard_by, bounds, minzoom, maxzoom, regions, &nodes);
+    let pyramid = DistributedPyramid {
+        job_id: Uuid::new_v4().to_string(),
+        job_type: "distributed_pyramid",
+        datasource_id: datasource_id.to_string(),
+        shard_by: shard_by.to_string(),
+        state: ShardState::Running,
+        shards,
+        message: None,
+        expires_at: None,
+    };
+    let job_id = pyramid.job_id.clone();
+    let count = pyramid.shards.len();
+    start_distributed_pyramid(ctx, pyramid, max_tiles).await;
+
+    let body = json!({
+        "status": StatusCode::ACCEPTED.as_u16(),
+        "message": format!("Distributed pyramid for DataSource '{datasource_id}' successfully started"),
+        "job_id": job_id,
+        "job_url": format!("{}/{job_id}", EndPoints::Jobs.as_ref()),
+        "shards": count,
+        "nodes": nodes,
+    })
+    .to_string();
+    Ok(response_with_body_and_code(body, StatusCode::ACCEPTED))
+}
+
+// State of the distributed pyramid: /api/jobs/{job_id}, None - no pyramid with the ID
+pub async fn pyramid_job_endpoint(
+    job_id: &str,
+    tx_exports: flume::Sender<MessageExports>,
+) -> Result<Option<Response<Body>>, anyhow::Error> {
+    let (tx_pyramid, rx_pyramid) = oneshot::channel();
+    tx_exports
+        .send_async(MessageExports::GetPyramid {
+            job_id: job_id.to_string(),
+            tx_pyramid,
+        })
+        .await?;
+    match rx_pyramid.await? {
+        Some(pyramid) => {
+            let body = serde_json::to_string(&pyramid)?;
+            Ok(Some(response_with_body_and_code(body, StatusCode::OK)))
+        }
+        None => Ok(None),
+    }
+}
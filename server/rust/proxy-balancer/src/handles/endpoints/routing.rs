@@ -0,0 +1,460 @@
+use hyper::{Body, Method, Request, Response, StatusCode};
+use serde_json::{json, Map, Value};
+use tiler_core::mbtiles::read_mbtile;
+use tiler_core::sqlite_clients::MessageSQLiteClient;
+use tokio::sync::oneshot;
+
+use crate::cog::STORE_COG;
+use crate::composite::STORE_COMPOSITE;
+use crate::config::Config;
+use crate::defaults::{DEFAULT_TILE_GRID, LOCALHOST};
+use crate::handles::endpoint_label;
+use crate::handles::endpoints::master::select_nodes;
+use crate::handles::error::ApiError;
+use crate::handles::helpers::response_with_body_and_code;
+use crate::handles::router::{route, Route};
+use crate::handles::version::route_version;
+use crate::paths::Paths;
+use crate::postgis::STORE_POSTGIS;
+use crate::remote::STORE_REMOTE;
+use crate::structs::{Extension, MissingTile};
+use crate::tasks::cluster::MessageCluster;
+use crate::tasks::datasources::{DataSourceInfo, MessageDatasource};
+use crate::tasks::reload_workers::MessageMaintenanceWorkers;
+use crate::utils::{file_path_from_uri, mbtiles_path_from_uri, zxy_from_uri, zxy_from_uri_in_grid};
+use crate::vector_file::STORE_FILE;
+
+/*
+    GET /debug/route?path=/api/tile/roads/10/511/340.pbf&method=GET
+    explains how the request would be routed without its execution: the endpoint of the path,
+    the DataSource of the tile, files of the cache looked up and the target - nodes of the cluster,
+    local workers, the balancer or the response given without them. Nodes are selected without
+    advancing round robin, tiles are only read from the cache
+*/
+pub async fn routing_endpoint(
+    paths: &Paths,
+    query: Option<&str>,
+    tx: flume::Sender<MessageDatasource>,
+    tx_cluster: flume::Sender<MessageCluster>,
+    tx_mw: flume::Sender<MessageMaintenanceWorkers>,
+    tx_sqlite_client: flume::Sender<MessageSQLiteClient>,
+    config: &Config,
+) -> Result<Response<Body>, anyhow::Error> {
+    let param = |name: &str| {
+        query
+            .and_then(|q| {
+                q.split('&')
+                    .filter_map(|pair| pair.split_once('='))
+                    .find(|(k, _)| *k == name)
+                    .and_then(|(_, v)| urlencoding::decode(v).ok())
+                    .map(|v| v.to_string())
+            })
+            .filter(|v| !v.is_empty())
+    };
+    let Some(explained) = param("path").filter(|path| path.starts_with('/')) else {
+        let response = ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "Parameter 'path' must be the path of the request, e.g. '/api/tile/{datasource_id}/{z}/{x}/{y}.pbf'",
+        )
+        .into_response();
+        return Ok(response);
+    };
+    let method = match param("method").map(|m| m.to_uppercase().parse::<Method>()) {
+        None => Method::GET,
+        Some(Ok(method)) => method,
+        Some(Err(_)) => {
+            let response = ApiError::new(
+                StatusCode::BAD_REQUEST,
+                "Parameter 'method' is not an HTTP method",
+            )
+            .into_response();
+            return Ok(response);
+        }
+    };
+
+    // versioned paths are routed by unversioned ones like requests
+    let mut req = match Request::builder()
+        .method(method.clone())
+        .uri(explained.as_str())
+        .body(Body::empty())
+    {
+        Ok(req) => req,
+        Err(err) => {
+            let response = ApiError::new(StatusCode::BAD_REQUEST, format!("Invalid path: {err}"))
+                .into_response();
+            return Ok(response);
+        }
+    };
+    if let Err(err) = route_version(&mut req) {
+        return Ok(err.into_response());
+    }
+    let path = req.uri().path();
+    let route = route(&method, path);
+
+    let mut explanation = Map::new();
+    explanation.insert("path".to_string(), json!(explained));
+    explanation.insert("method".to_string(), json!(method.as_str()));
+    explanation.insert("endpoint".to_string(), json!(endpoint_label(path)));
+    explanation.insert(
+        "handler".to_string(),
+        json!(if route.is_some() {
+            "balancer"
+        } else {
+            "workers"
+        }),
+    );
+
+    let target = match route {
+        Some(Route::Tile { datasource_id }) => {
+            explain_tile(
+                &mut explanation,
+                paths,
+                path,
+                req.uri().query(),
+                datasource_id,
+                &tx,
+                &tx_cluster,
+                &tx_mw,
+                tx_sqlite_client,
+                config,
+            )
+            .await?
+        }
+        Some(_) => json!({ "kind": "balancer" }),
+        None if config.is_native() => response_target(
+            StatusCode::NOT_IMPLEMENTED,
+            "Endpoint requires Python workers, they are not run in native mode",
+        ),
+        None => workers_target(&tx_mw).await?,
+    };
+    explanation.insert("target".to_string(), target);
+
+    let body = Value::Object(explanation).to_string();
+    Ok(response_with_body_and_code(body, StatusCode::OK))
+}
+
+/*
+    Steps of 'master_endpoint' and 'tile_endpoint' in their order: the master forwards tiles
+    to nodes of the DataSource, the node answers tiles out of zoom levels and bounds itself,
+    then looks up the cache and renders or requests missing tiles
+*/
+async fn explain_tile(
+    explanation: &mut Map<String, Value>,
+    paths: &Paths,
+    path: &str,
+    query: Option<&str>,
+    datasource_id: &str,
+    tx: &flume::Sender<MessageDatasource>,
+    tx_cluster: &flume::Sender<MessageCluster>,
+    tx_mw: &flume::Sender<MessageMaintenanceWorkers>,
+    tx_sqlite_client: flume::Sender<MessageSQLiteClient>,
+    config: &Config,
+) -> Result<Value, anyhow::Error> {
+    let (tx_ds, rx_ds) = oneshot::channel();
+    tx.send_async(MessageDatasource::GetDataSource {
+        datasource_id: datasource_id.to_string(),
+        tx_ds,
+    })
+    .await?;
+    let ds_info = rx_ds.await?;
+    explanation.insert(
+        "datasource".to_string(),
+        ds_info
+            .as_ref()
+            .map_or(Value::Null, |ds| datasource_json(datasource_id, ds, config)),
+    );
+
+    let missing = ds_info
+        .as_ref()
+        .and_then(|ds| ds.missing_tile)
+        .unwrap_or(config.missing_tile);
+    let png = path.ends_with(&format!(".{}", Extension::Png));
+
+    if config.master {
+        let Some(ds) = ds_info.as_ref() else {
+            return Ok(response_target(
+                StatusCode::NOT_FOUND,
+                format!("DataSource '{datasource_id}' is unknown to the master"),
+            ));
+        };
+        if let Ok((_, _, z)) = zxy_from_uri(path) {
+            if !ds.zoom_in_range(z) && ds.overzoom_parent(z, config.overzoom_maxzoom).is_none() {
+                return Ok(response_target(
+                    StatusCode::NO_CONTENT,
+                    format!("Zoom {z} is out of zoom levels of the DataSource"),
+                ));
+            }
+        }
+        let locations = ds.locations();
+        if !locations.is_empty() {
+            let tile_key = if config.cluster_consistent_hash {
+                match zxy_from_uri(path) {
+                    Ok((x, y, z)) => Some(format!("{datasource_id}/{z}/{x}/{y}")),
+                    Err(err) => {
+                        return Ok(response_target(
+                            StatusCode::INTERNAL_SERVER_ERROR,
+                            err.to_string(),
+                        ))
+                    }
+                }
+            } else {
+                None
+            };
+            let routing = if tile_key.is_some() {
+                "consistent_hash"
+            } else {
+                "round_robin"
+            };
+            let nodes = select_nodes(datasource_id, locations, tile_key, tx_cluster, true).await?;
+            match nodes.first() {
+                None => {
+                    return Ok(response_target(
+                        StatusCode::SERVICE_UNAVAILABLE,
+                        format!("No alive cluster node for datasource '{datasource_id}'"),
+                    ));
+                }
+                // the master serves the tile itself, its cache is looked up below
+                Some(first) if config.address.as_ref() == Some(first) => {}
+                Some(_) => {
+                    return Ok(json!({
+                        "kind": "nodes",
+                        "routing": routing,
+                        // the first node gets the request, the next ones are failover
+                        "nodes": nodes,
+                    }));
+                }
+            }
+        }
+    }
+
+    let Some(ds) = ds_info.as_ref() else {
+        let mut target = workers_target(tx_mw).await?;
+        target["reason"] = json!("DataSource is unknown to the balancer, workers may serve it");
+        return Ok(target);
+    };
+    if ds.trash.is_some() {
+        return Ok(response_target(
+            StatusCode::GONE,
+            "DataSource is deleted, it is in the trash",
+        ));
+    }
+
+    let grid = config
+        .tile_grids
+        .select(query, ds.tile_grid.as_ref())
+        .map(|g| g.with_overzoom(config.overzoom_maxzoom));
+    let (grid, (x, y, z)) =
+        match grid.and_then(|g| zxy_from_uri_in_grid(path, &g).map(|xyz| (g, xyz))) {
+            Ok(grid_xyz) => grid_xyz,
+            Err(err) => return Ok(response_target(StatusCode::BAD_REQUEST, err.to_string())),
+        };
+    explanation.insert(
+        "tile".to_string(),
+        json!({ "grid": grid.name, "z": z, "x": x, "y": y }),
+    );
+
+    if ds
+        .geofence
+        .as_ref()
+        .is_some_and(|geofence| !geofence.allows(&grid, z, x, y))
+    {
+        return Ok(response_target(
+            StatusCode::FORBIDDEN,
+            "Tile is outside of the licensed area of the DataSource",
+        ));
+    }
+
+    if !ds.zoom_in_range(z) {
+        return Ok(match ds.overzoom_parent(z, config.overzoom_maxzoom) {
+            Some(parent_z) => json!({
+                "kind": "balancer",
+                "reason": format!("Tile is cut from the parent tile on zoom {parent_z} in the cache"),
+            }),
+            None => missing_target(
+                missing,
+                png,
+                format!("Zoom {z} is out of zoom levels of the DataSource"),
+            ),
+        });
+    }
+    if let Some(bounds) = ds.bounds.as_ref() {
+        if !grid.tile_intersects(bounds, z, x, y) {
+            let status = if png {
+                StatusCode::OK
+            } else {
+                StatusCode::NO_CONTENT
+            };
+            return Ok(response_target(
+                status,
+                "Tile is outside of bounds of the DataSource",
+            ));
+        }
+    }
+    if let Some(composite) = ds.composite.as_ref() {
+        let children: Vec<&str> = composite
+            .children
+            .iter()
+            .map(|child| child.id.as_str())
+            .collect();
+        return Ok(json!({
+            "kind": "balancer",
+            "store": STORE_COMPOSITE,
+            "reason": "Tile is merged from tiles of child DataSources, it is not cached",
+            "children": children,
+        }));
+    }
+
+    let cache = explain_cache(paths, path, (x, y, z), tx_sqlite_client).await;
+    // the first file with the tile answers, zero-length tiles are tiles without data
+    let cached = cache
+        .iter()
+        .find_map(|file| file.get("tile").and_then(|tile| tile.as_str()))
+        .map(|tile| tile == "tile");
+    explanation.insert("cache".to_string(), Value::Array(cache));
+    match cached {
+        Some(true) => {
+            return Ok(response_target(
+                StatusCode::OK,
+                "Tile is found in the cache",
+            ))
+        }
+        Some(false) => {
+            return Ok(missing_target(
+                missing,
+                png,
+                "Empty tile in the cache: the area has no data",
+            ))
+        }
+        None => {}
+    }
+
+    if ds.use_cache_only.unwrap_or(false) {
+        return Ok(missing_target(
+            missing,
+            png,
+            "DataSource serves only tiles of the cache",
+        ));
+    }
+    if let Some(store) = native_store(ds) {
+        return Ok(json!({
+            "kind": "balancer",
+            "store": store,
+            "reason": "Tile is rendered by the balancer and saved to the cache",
+        }));
+    }
+    if config.is_native() {
+        return Ok(response_target(
+            StatusCode::NOT_IMPLEMENTED,
+            "Tiles of the DataSource are rendered by Python workers, not run in native mode",
+        ));
+    }
+    workers_target(tx_mw).await
+}
+
+// Files of the cache in the order of the lookup: the file on disk, then MBTiles of the tile
+async fn explain_cache(
+    paths: &Paths,
+    path: &str,
+    (x, y, z): (u64, u64, u8),
+    tx_sqlite_client: flume::Sender<MessageSQLiteClient>,
+) -> Vec<Value> {
+    let mut cache = Vec::new();
+    if let Ok(file_tile_path) = file_path_from_uri(paths, path) {
+        let metadata = tokio::fs::metadata(&file_tile_path).await.ok();
+        cache.push(json!({
+            "source": "disk",
+            "path": file_tile_path,
+            "exists": metadata.is_some(),
+            "tile": metadata.map(|md| if md.len() == 0 { "empty" } else { "tile" }),
+        }));
+    }
+    match mbtiles_path_from_uri(paths, path, (x, y, z)).await {
+        Ok(mbtiles_path) => {
+            let exists = tokio::fs::try_exists(&mbtiles_path).await.unwrap_or(false);
+            let tile = if exists {
+                match read_mbtile(&mbtiles_path, z as u64, x, y, tx_sqlite_client, false).await {
+                    Ok(Some(data)) if data.is_empty() => Some("empty"),
+                    Ok(Some(_)) => Some("tile"),
+                    Ok(None) | Err(_) => None,
+                }
+            } else {
+                None
+            };
+            cache.push(json!({
+                "source": "mbtiles",
+                "path": mbtiles_path,
+                "exists": exists,
+                "tile": tile,
+            }));
+        }
+        Err(err) => cache.push(json!({ "source": "mbtiles", "error": err.to_string() })),
+    }
+    cache
+}
+
+fn datasource_json(datasource_id: &str, ds: &DataSourceInfo, config: &Config) -> Value {
+    json!({
+        "id": datasource_id,
+        "locations": ds.locations(),
+        "store": native_store(ds).or(ds.composite.as_ref().map(|_| STORE_COMPOSITE)),
+        "tile_grid": ds.tile_grid.as_ref().map_or(DEFAULT_TILE_GRID, |g| g.name.as_str()),
+        "minzoom": ds.minzoom,
+        "maxzoom": ds.maxzoom,
+        "bounds": ds.bounds,
+        "deleted": ds.trash.is_some(),
+        "use_cache_only": ds.use_cache_only.unwrap_or(false),
+        "missing_tile": ds.missing_tile.unwrap_or(config.missing_tile).as_ref(),
+        "negative_cache": ds.negative_cache.unwrap_or(config.negative_cache),
+    })
+}
+
+// Store of the DataSource whose tiles are rendered or fetched by the balancer
+fn native_store(ds: &DataSourceInfo) -> Option<&'static str> {
+    if ds.postgis.is_some() {
+        Some(STORE_POSTGIS)
+    } else if ds.vector_file.is_some() {
+        Some(STORE_FILE)
+    } else if ds.cog.is_some() {
+        Some(STORE_COG)
+    } else if ds.remote.is_some() {
+        Some(STORE_REMOTE)
+    } else {
+        None
+    }
+}
+
+fn response_target(status: StatusCode, reason: impl Into<String>) -> Value {
+    json!({
+        "kind": "response",
+        "status": status.as_u16(),
+        "reason": reason.into(),
+    })
+}
+
+// Response of 'missing_tile' to the tile without data
+fn missing_target(missing: MissingTile, png: bool, reason: impl Into<String>) -> Value {
+    let status = match missing {
+        MissingTile::Blank if png => StatusCode::OK,
+        MissingTile::NotFound => StatusCode::NOT_FOUND,
+        _ => StatusCode::NO_CONTENT,
+    };
+    let mut target = response_target(status, reason);
+    target["missing_tile"] = json!(missing.as_ref());
+    target
+}
+
+// Local workers, the port is selected by the balancer when the request comes
+async fn workers_target(
+    tx_mw: &flume::Sender<MessageMaintenanceWorkers>,
+) -> Result<Value, anyhow::Error> {
+    let (tx_lw, rx_lw) = oneshot::channel();
+    tx_mw
+        .send_async(MessageMaintenanceWorkers::ListWorkers { tx_lw })
+        .await?;
+    let ports: Vec<u16> = rx_lw.await?.into_iter().map(|(port, _)| port).collect();
+    Ok(json!({
+        "kind": "workers",
+        "host": LOCALHOST,
+        "ports": ports,
+    }))
+}
@@ -0,0 +1,103 @@
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::anyhow;
+use hyper::{Body, Response, StatusCode};
+
+use crate::config::Config;
+use crate::handles::error::ApiError;
+use crate::handles::headers::ResponseBuilderExt;
+use crate::structs::EndPoints;
+
+fn static_error_response(message: String, code: StatusCode) -> Response<Body> {
+    ApiError::new(code, message).into_response()
+}
+
+/*
+    Files of '/static/...' are searched in the configured root directory.
+    The path must stay inside the root after resolving symlinks,
+    only files with the allowed extensions are served
+*/
+pub async fn static_endpoint(
+    path: &str,
+    root: &Path,
+    config: &Config,
+) -> Result<Response<Body>, anyhow::Error> {
+    let relative = path
+        .strip_prefix(EndPoints::Static.as_ref())
+        .ok_or(anyhow!("Path '{path}' is not static"))?;
+    // decode before checks, '%2e%2e' must not bypass them
+    let relative = urlencoding::decode(relative)?.to_string();
+
+    let mut file_path = root.to_path_buf();
+    for part in relative.split('/').filter(|p| !p.is_empty()) {
+        let is_normal = Path::new(part)
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)));
+        if !is_normal || part.contains('\\') {
+            return Ok(static_error_response(
+                format!("Access to '{path}' is forbidden"),
+                StatusCode::FORBIDDEN,
+            ));
+        }
+        file_path.push(part);
+    }
+
+    let root_canonical = match tokio::fs::canonicalize(root).await {
+        Ok(r) => r,
+        Err(err) => {
+            return Ok(static_error_response(
+                format!("Static root '{}': {err}", root.display()),
+                StatusCode::NOT_FOUND,
+            ));
+        }
+    };
+    let mut file_canonical: PathBuf = match tokio::fs::canonicalize(&file_path).await {
+        Ok(f) => f,
+        Err(err) => {
+            return Ok(static_error_response(
+                err.to_string(),
+                StatusCode::NOT_FOUND,
+            ));
+        }
+    };
+    // symlink leads out of the root directory
+    if !file_canonical.starts_with(&root_canonical) {
+        return Ok(static_error_response(
+            format!("Access to '{path}' is forbidden"),
+            StatusCode::FORBIDDEN,
+        ));
+    }
+
+    if file_canonical.is_dir() {
+        if !config.static_index {
+            return Ok(static_error_response(
+                format!("Listing of '{path}' is forbidden"),
+                StatusCode::FORBIDDEN,
+            ));
+        }
+        file_canonical.push("index.html");
+    }
+
+    let extension = file_canonical
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_lowercase())
+        .unwrap_or_default();
+    if !config.static_allowed_extensions.contains(&extension) {
+        return Ok(static_error_response(
+            format!("Extension '{extension}' of static files is not allowed"),
+            StatusCode::FORBIDDEN,
+        ));
+    }
+
+    let mime_type = mime_guess::from_path(&file_canonical).first_or_octet_stream();
+    let response = match tokio::fs::read(&file_canonical).await {
+        Ok(data) => Response::builder()
+            .status(StatusCode::OK)
+            .content_type(mime_type.as_ref())
+            .body(Body::from(data))?,
+        Err(err) => static_error_response(err.to_string(), StatusCode::NOT_FOUND),
+    };
+
+    Ok(response)
+}
@@ -0,0 +1,149 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use hyper::{Body, Response, StatusCode};
+use serde_json::json;
+use tiler_core::mbtiles::get_mbtiles_paths;
+use tiler_core::sqlite_clients::MessageSQLiteClient;
+use tokio::sync::oneshot;
+use tracing::{event, Level};
+
+use crate::db::{get_mbtiles_zoom_counts, last_pyramid_time};
+use crate::handles::error::ApiError;
+use crate::handles::helpers::response_with_body_and_code;
+use crate::paths::Paths;
+use crate::structs::Extension;
+use crate::tasks::datasources::MessageDatasource;
+use crate::tasks::stats::{MessageStats, TileCounters};
+
+/*
+    Statistics of the DataSource cache:
+    - tiles per zoom and size of MBTiles
+    - number of tiles saved as files 'tiles/{id}/{z}/{x}/{y}.{ext}'
+    - finish time of the last pyramid from 'tiler.db'
+    - hits and misses of the cache since the start of the server
+    - tiles evicted over the quota of the DataSource since the start of the server
+*/
+pub async fn stats_endpoint(
+    paths: &Paths,
+    datasource_id: &str,
+    tx: flume::Sender<MessageDatasource>,
+    tx_sqlite_client: flume::Sender<MessageSQLiteClient>,
+    tx_stats: flume::Sender<MessageStats>,
+) -> Result<Response<Body>, anyhow::Error> {
+    let (tx_ds, rx_ds) = oneshot::channel();
+    tx.send_async(MessageDatasource::GetDataSource {
+        datasource_id: datasource_id.to_string(),
+        tx_ds,
+    })
+    .await?;
+    if rx_ds.await?.is_none() {
+        return Ok(ApiError::datasource_not_found(datasource_id).into_response());
+    }
+
+    let tiles_dir = paths.tiles_dir(datasource_id);
+
+    let mut zooms: BTreeMap<u8, u64> = BTreeMap::new();
+    let mut mbtiles_size = 0;
+    let mbtiles_paths = get_mbtiles_paths(tiles_dir.clone())
+        .await
+        .unwrap_or_default();
+    for p in mbtiles_paths.iter() {
+        if let Ok(md) = tokio::fs::metadata(p).await {
+            mbtiles_size += md.len();
+        }
+        match get_mbtiles_zoom_counts(p, tx_sqlite_client.clone()).await {
+            Ok(counts) => {
+                for (z, count) in counts {
+                    *zooms.entry(z).or_default() += count;
+                }
+            }
+            Err(err) => {
+                event!(Level::ERROR, "Error count tiles of {p:?}: {err}");
+            }
+        }
+    }
+
+    let disk_tiles = count_disk_tiles(&tiles_dir).await;
+
+    let last_pyramid = match last_pyramid_time(paths, datasource_id).await {
+        Ok(t) => t,
+        Err(err) => {
+            event!(
+                Level::ERROR,
+                "Error select last pyramid of '{datasource_id}': {err}"
+            );
+            None
+        }
+    };
+
+    let (tx_counters, rx_counters) = oneshot::channel();
+    tx_stats
+        .send_async(MessageStats::GetCounters {
+            datasource_id: datasource_id.to_string(),
+            tx_counters,
+        })
+        .await?;
+    let counters: TileCounters = rx_counters.await?;
+
+    let (tx_evictions, rx_evictions) = oneshot::channel();
+    tx_stats
+        .send_async(MessageStats::GetEvictions {
+            datasource_id: datasource_id.to_string(),
+            tx_evictions,
+        })
+        .await?;
+    let evictions = rx_evictions.await?;
+
+    let body = json!({
+        "datasource_id": datasource_id,
+        "mbtiles": {
+            "files": mbtiles_paths.len(),
+            "size": mbtiles_size,
+            "tiles": zooms.values().sum::<u64>(),
+            "zooms": zooms,
+        },
+        "disk": {
+            "tiles": disk_tiles,
+        },
+        "last_pyramid_time": last_pyramid,
+        "requests": counters,
+        "evictions": evictions,
+    })
+    .to_string();
+    Ok(response_with_body_and_code(body, StatusCode::OK))
+}
+
+// Tile files of the layout '{z}/{x}/{y}.{ext}', MBTiles in the root are skipped
+async fn count_disk_tiles(tiles_dir: &Path) -> u64 {
+    let extensions = [
+        Extension::Png.as_ref(),
+        Extension::Pbf.as_ref(),
+        Extension::Mvt.as_ref(),
+        Extension::Webp.as_ref(),
+    ];
+    let mut count = 0;
+    let mut dirs = vec![(tiles_dir.to_path_buf(), 0)];
+    while let Some((dir, depth)) = dirs.pop() {
+        let mut entries = match tokio::fs::read_dir(&dir).await {
+            Ok(e) => e,
+            Err(_) => continue,
+        };
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let path = entry.path();
+            let is_dir = entry.file_type().await.map(|t| t.is_dir()).unwrap_or(false);
+            if is_dir && depth < 2 {
+                dirs.push((path, depth + 1));
+            } else if !is_dir && depth == 2 {
+                let is_tile = path
+                    .extension()
+                    .and_then(|ext| ext.to_str())
+                    .is_some_and(|ext| extensions.contains(&ext));
+                if is_tile {
+                    count += 1;
+                }
+            }
+        }
+    }
+    count
+}
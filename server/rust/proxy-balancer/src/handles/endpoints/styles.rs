@@ -0,0 +1,194 @@
+use std::path::Path;
+
+use hyper::http::header::{self, HeaderMap, HeaderValue};
+use hyper::{Body, Method, Response, StatusCode};
+use serde_json::json;
+
+use crate::handles::error::ApiError;
+use crate::handles::headers::ResponseBuilderExt;
+use crate::paths::Paths;
+use crate::structs::{ContentType, EndPoints};
+
+fn style_error_response(message: String, code: StatusCode) -> Response<Body> {
+    ApiError::new(code, message).into_response()
+}
+
+// Single segment of the path (style id, sprite name, font stack), must not leave its directory
+fn is_safe_segment(segment: &str) -> bool {
+    !segment.is_empty()
+        && segment != "."
+        && segment != ".."
+        && !segment.contains('/')
+        && !segment.contains('\\')
+        && !segment.contains('\0')
+}
+
+// Base URL of this server as seen by the client: 'http://host:port'
+pub fn base_url(headers: &HeaderMap<HeaderValue>) -> Option<String> {
+    let host = headers.get(header::HOST).and_then(|h| h.to_str().ok())?;
+    let scheme = headers
+        .get("x-forwarded-proto")
+        .and_then(|p| p.to_str().ok())
+        .unwrap_or("http");
+    Some(format!("{scheme}://{host}"))
+}
+
+/*
+    Styles of MapLibre/Mapbox GL are stored in 'CWD/styles':
+    - CWD/styles/{id}/style.json
+    - CWD/styles/sprites/{id}.png, {id}.json, {id}@2x.png, {id}@2x.json
+    - CWD/styles/fonts/{font}/{range}.pbf
+    so the map application needs only the base URL of the tiler
+*/
+pub async fn styles_endpoint(
+    paths: &Paths,
+    path: &str,
+    method: &Method,
+    headers: &HeaderMap<HeaderValue>,
+) -> Result<Option<Response<Body>>, anyhow::Error> {
+    if method != Method::GET {
+        return Ok(None);
+    }
+    let styles_dir = paths.styles_dir();
+
+    if let Some(rest) = path.strip_prefix(EndPoints::Styles.as_ref()) {
+        // /styles/{id}/style.json
+        let rest = urlencoding::decode(rest)?.to_string();
+        let style_id = match rest.trim_start_matches('/').strip_suffix("/style.json") {
+            Some(id) if is_safe_segment(id) => id.to_string(),
+            _ => {
+                return Ok(Some(style_error_response(
+                    format!("Style path '{path}' is invalid"),
+                    StatusCode::BAD_REQUEST,
+                )));
+            }
+        };
+        let style_path = styles_dir.join(&style_id).join("style.json");
+        return style_response(&style_path, &style_id, headers)
+            .await
+            .map(Some);
+    } else if let Some(rest) = path.strip_prefix(EndPoints::Sprites.as_ref()) {
+        // /sprites/{id}.png, /sprites/{id}@2x.json
+        let name = urlencoding::decode(rest.trim_start_matches('/'))?.to_string();
+        let (content_type, valid) = if name.ends_with(".png") {
+            (ContentType::Png.as_ref(), true)
+        } else if name.ends_with(".json") {
+            (ContentType::ApplicationJson.as_ref(), true)
+        } else {
+            (ContentType::Empty.as_ref(), false)
+        };
+        if !valid || !is_safe_segment(&name) {
+            return Ok(Some(style_error_response(
+                format!("Sprite path '{path}' is invalid"),
+                StatusCode::BAD_REQUEST,
+            )));
+        }
+        let sprite_path = styles_dir.join("sprites").join(&name);
+        return file_response(&sprite_path, content_type).await.map(Some);
+    } else if let Some(rest) = path.strip_prefix(EndPoints::Fonts.as_ref()) {
+        // /fonts/{stack}/{range}.pbf, stack is a comma separated list of fonts
+        let rest = urlencoding::decode(rest.trim_start_matches('/'))?.to_string();
+        let (stack, range) = match rest.rsplit_once('/') {
+            Some((s, r)) if r.ends_with(".pbf") && is_safe_segment(r) => (s.to_string(), r),
+            _ => {
+                return Ok(Some(style_error_response(
+                    format!("Glyphs path '{path}' is invalid"),
+                    StatusCode::BAD_REQUEST,
+                )));
+            }
+        };
+        // glyphs are not combined, the first font of the stack found on disk is served
+        for font in stack.split(',').map(|f| f.trim()) {
+            if !is_safe_segment(font) {
+                continue;
+            }
+            let glyphs_path = styles_dir.join("fonts").join(font).join(range);
+            if tokio::fs::try_exists(&glyphs_path).await.unwrap_or(false) {
+                return file_response(&glyphs_path, ContentType::Protobuf.as_ref())
+                    .await
+                    .map(Some);
+            }
+        }
+        return Ok(Some(style_error_response(
+            format!("Glyphs '{range}' for fonts '{stack}' not found"),
+            StatusCode::NOT_FOUND,
+        )));
+    }
+
+    Ok(None)
+}
+
+async fn file_response(
+    file_path: &Path,
+    content_type: &str,
+) -> Result<Response<Body>, anyhow::Error> {
+    let response = match tokio::fs::read(file_path).await {
+        Ok(data) => Response::builder()
+            .status(StatusCode::OK)
+            .content_type(content_type)
+            .body(Body::from(data))?,
+        Err(err) => style_error_response(
+            format!("File '{}': {err}", file_path.display()),
+            StatusCode::NOT_FOUND,
+        ),
+    };
+    Ok(response)
+}
+
+/*
+    Relative URLs of the style (tiles, tilejson, sprite, glyphs) starting with '/'
+    are prefixed with the base URL of this server, absolute URLs are left as is
+*/
+async fn style_response(
+    style_path: &Path,
+    style_id: &str,
+    headers: &HeaderMap<HeaderValue>,
+) -> Result<Response<Body>, anyhow::Error> {
+    let data = match tokio::fs::read(style_path).await {
+        Ok(d) => d,
+        Err(_) => {
+            return Ok(style_error_response(
+                format!("Style '{style_id}' not found"),
+                StatusCode::NOT_FOUND,
+            ));
+        }
+    };
+    let mut style: serde_json::Value = serde_json::from_slice(&data)?;
+
+    if let Some(base) = base_url(headers) {
+        let absolute = |url: &str| -> String {
+            if url.starts_with('/') {
+                format!("{base}{url}")
+            } else {
+                url.to_string()
+            }
+        };
+
+        for key in ["sprite", "glyphs"] {
+            if let Some(url) = style.get(key).and_then(|u| u.as_str()).map(absolute) {
+                style[key] = json!(url);
+            }
+        }
+
+        if let Some(sources) = style.get_mut("sources").and_then(|s| s.as_object_mut()) {
+            for source in sources.values_mut() {
+                if let Some(url) = source.get("url").and_then(|u| u.as_str()).map(absolute) {
+                    source["url"] = json!(url);
+                }
+                if let Some(tiles) = source.get_mut("tiles").and_then(|t| t.as_array_mut()) {
+                    for tile in tiles.iter_mut() {
+                        if let Some(url) = tile.as_str().map(absolute) {
+                            *tile = json!(url);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    let response = Response::builder()
+        .status(StatusCode::OK)
+        .json()
+        .body(Body::from(style.to_string()))?;
+    Ok(response)
+}
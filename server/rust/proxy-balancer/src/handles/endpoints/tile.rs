@@ -1,62 +1,158 @@
+use std::ffi::OsStr;
+use std::future::Future;
 use std::net::IpAddr;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::time::{Duration, Instant};
 
 use anyhow::anyhow;
-use hyper::{client::HttpConnector, Body, Client, Request, Response, StatusCode};
-use serde_json::json;
+use hyper::{client::HttpConnector, Body, Client, Method, Request, Response, StatusCode};
+use tiler_core::integrity::write_disk_tile_checksum;
+use tiler_core::lookup::{lookup_tile, CachedTile};
+use tiler_core::mbtiles::save_mbtile;
+use tiler_core::sqlite_clients::MessageSQLiteClient;
 use tokio::sync::oneshot;
-use tracing::{event, Level};
+use tracing::{event, info_span, Instrument, Level};
 
-use crate::db::{get_mbtile, DB};
-use crate::defaults::{LOCALHOST, MAXZOOM};
-use crate::handles::helpers::{error_response, response_with_body_and_code};
+use crate::composite::merge_tiles;
+use crate::config::Config;
+use crate::db::{get_mbtile, registry::SharedRegistry, DB};
+use crate::defaults::{DEFAULT_TILE_GRID, LOCALHOST, NATIVE_RENDERER_PORT, TILE_PRIORITY_HEADER};
+use crate::handles::error::{ApiError, ErrorCode};
+use crate::handles::headers::copy_caching_headers;
+use crate::handles::helpers::{
+    blank_tile_response, empty_tile_response, error_response, gone_response, is_worker_failure,
+    no_content_response, origin_not_allowed_response, queue_full_response, report_worker_latency,
+    report_worker_result, validate_worker_tile, worker_allowed,
+};
 use crate::hyper_reverse_proxy;
+use crate::log::otel::inject_context;
+use crate::overzoom::{overzoom_tile, SubTile};
+use crate::paths::Paths;
+use crate::postgis::{generate_mvt, gzip_tile};
+use crate::raster::{ground_resolution, resize_png, RasterOps};
+use crate::remote::RemoteTile;
 use crate::structs::ContentType;
+use crate::structs::EndPoints;
 use crate::structs::Extension;
-use crate::tasks::datasources::MessageDatasource;
-use crate::tasks::semaphore::MessageSemaphore;
-use crate::tasks::sqlite_clients::MessageSQLiteClient;
+use crate::tasks::datasources::{DataSourceInfo, MessageDatasource};
+use crate::tasks::reload_workers::MessageMaintenanceWorkers;
+use crate::tasks::semaphore::{Lane, MessageSemaphore, PermitGuard};
+use crate::tasks::stats::{record_tile, MessageStats, WorkerTimings};
+use crate::tasks::usage::{record_access, record_miss, MessageUsage, TileAccess};
 use crate::utils::{
-    dataset_dir_from_uri, datasource_id_from_uri, file_path_from_uri, get_tile_from_disk,
-    mbtiles_path_from_uri, try_init_mbtiles, zxy_from_uri,
+    cached_tile_response, dataset_dir_from_uri, datasource_id_from_uri, file_path_from_uri,
+    get_scaled_tile, get_tile_from_disk, mbtiles_path_from_uri, save_scaled_tile, tile_response,
+    tile_scale_from_uri, try_init_mbtiles, unscaled_tile_uri, zxy_from_uri_in_grid,
 };
 
+// Channels and the worker of the request of the tile, children and base tiles share it
+#[derive(Clone)]
+pub struct TileContext {
+    pub paths: Paths,
+    pub registry: SharedRegistry,
+    pub pg_pool: Option<DB>,
+    pub client: Client<HttpConnector>,
+    pub client_ip: IpAddr,
+    pub port: u16,
+    pub ports: Vec<u16>,
+    pub tx: flume::Sender<MessageDatasource>,
+    pub tx_sem: flume::Sender<MessageSemaphore>,
+    pub tx_mw: flume::Sender<MessageMaintenanceWorkers>,
+    pub tx_stats: flume::Sender<MessageStats>,
+    pub tx_usage: flume::Sender<MessageUsage>,
+    pub tx_sqlite_client: flume::Sender<MessageSQLiteClient>,
+}
+
+/*
+    Tiles of the DataSource with 'allowed_origins' are served only to requests of these websites,
+    the policy is attached to the response to reflect the origin in CORS headers.
+    Tiles of the DataSource with 'geofence' are served only within the licensed area
+*/
 pub async fn tile_endpoint(
-    cwd: String,
+    ctx: &TileContext,
     path: &str,
-    pool: DB,
-    tx_sqlite_client: flume::Sender<MessageSQLiteClient>,
-    client_ip: IpAddr,
-    port: u16,
     req: Request<Body>,
-    client: Client<HttpConnector>,
-    tx_sem: flume::Sender<MessageSemaphore>,
-    tx: flume::Sender<MessageDatasource>,
+    config: &Config,
+) -> Result<Response<Body>, anyhow::Error> {
+    let ds_info = match datasource_id_from_uri(path) {
+        Ok(datasource_id) => {
+            let (tx_ds, rx_ds) = oneshot::channel();
+            ctx.tx
+                .send_async(MessageDatasource::GetDataSource {
+                    datasource_id: datasource_id.to_string(),
+                    tx_ds,
+                })
+                .await?;
+            rx_ds.await?
+        }
+        Err(_) => None,
+    };
+
+    let allowed_origins = ds_info.as_ref().and_then(|ds| ds.allowed_origins.clone());
+    if let Some(allowed) = allowed_origins.as_ref() {
+        if !allowed.allows(req.headers()) {
+            return Ok(origin_not_allowed_response(req.headers()));
+        }
+    }
+
+    let geofence = ds_info
+        .as_ref()
+        .and_then(|ds| ds.geofence.as_ref().map(|geofence| (ds, geofence)));
+    if let Some((ds, geofence)) = geofence {
+        // malformed tiles are refused by 'datasource_tile'
+        let tile = config
+            .tile_grids
+            .select(req.uri().query(), ds.tile_grid.as_ref())
+            .map(|g| g.with_overzoom(config.overzoom_maxzoom))
+            .and_then(|g| zxy_from_uri_in_grid(path, &g).map(|xyz| (g, xyz)));
+        if let Ok((grid, (x, y, z))) = tile {
+            if !geofence.allows(&grid, z, x, y) {
+                let response = ApiError::new(
+                    StatusCode::FORBIDDEN,
+                    format!("Tile {z}/{x}/{y} is outside of the licensed area of the DataSource"),
+                )
+                .with_code(ErrorCode::OutsideGeofence)
+                .into_response();
+                return Ok(response);
+            }
+        }
+    }
+
+    let mut response = datasource_tile(ctx, path, ds_info, req, config).await?;
+    if let Some(allowed) = allowed_origins {
+        response.extensions_mut().insert(allowed);
+    }
+    Ok(response)
+}
+
+async fn datasource_tile(
+    ctx: &TileContext,
+    path: &str,
+    ds_info: Option<DataSourceInfo>,
+    req: Request<Body>,
+    config: &Config,
 ) -> Result<Response<Body>, anyhow::Error> {
-    let dataset_dir = match dataset_dir_from_uri(&cwd, path) {
+    let dataset_dir = match dataset_dir_from_uri(&ctx.paths, path) {
         Ok(d) => d,
         Err(err) => {
-            let body = json!({
-                "status": StatusCode::BAD_REQUEST.as_u16(),
-                "message": err.to_string()
-            })
-            .to_string();
-            let response = response_with_body_and_code(body, StatusCode::BAD_REQUEST);
+            let response = ApiError::new(StatusCode::BAD_REQUEST, err.to_string()).into_response();
             return Ok(response);
         }
     };
 
-    let datasource_id = datasource_id_from_uri(path)
-        .expect(&format!("Error extract datasource_id from URI: {}", path));
+    let datasource_id = match datasource_id_from_uri(path) {
+        Ok(datasource_id) => datasource_id,
+        Err(err) => {
+            let response = ApiError::new(StatusCode::BAD_REQUEST, err.to_string()).into_response();
+            return Ok(response);
+        }
+    };
 
-    let file_tile_path = match file_path_from_uri(&cwd, path) {
+    let file_tile_path = match file_path_from_uri(&ctx.paths, path) {
         Ok(p) => p,
         Err(err) => {
-            let body = json!({
-                "status": StatusCode::BAD_REQUEST.as_u16(),
-                "message": err.to_string()
-            })
-            .to_string();
-            let response = response_with_body_and_code(body, StatusCode::BAD_REQUEST);
+            let response = ApiError::new(StatusCode::BAD_REQUEST, err.to_string()).into_response();
             return Ok(response);
         }
     };
@@ -68,118 +164,815 @@ pub async fn tile_endpoint(
     let png: &str = Extension::Png.into();
     let mvt: &str = Extension::Mvt.into();
     let pbf: &str = Extension::Pbf.into();
+    let webp: &str = Extension::Webp.into();
     let content_type = if ext == png {
         ContentType::Png.as_ref()
+    } else if ext == webp {
+        ContentType::Webp.as_ref()
     } else if ext == mvt || ext == pbf {
         ContentType::MvtPbf.as_ref()
     } else {
         ContentType::Empty.as_ref()
     };
 
-    let (x, y, z) = match zxy_from_uri(path) {
-        Ok(xyz) => xyz,
+    if let Some(entry) = ds_info.as_ref().and_then(|ds| ds.trash.as_ref()) {
+        return Ok(gone_response(entry));
+    }
+    let missing = ds_info
+        .as_ref()
+        .and_then(|ds| ds.missing_tile)
+        .unwrap_or(config.missing_tile);
+    // WebP tiles are rendered only by the proxy from COG DataSources
+    if content_type == ContentType::Webp.as_ref()
+        && ds_info.as_ref().is_none_or(|ds| ds.cog.is_none())
+    {
+        let response = ApiError::new(
+            StatusCode::BAD_REQUEST,
+            format!(
+                "'{}' tiles are supported only for COG DataSources",
+                Extension::Webp
+            ),
+        )
+        .into_response();
+        return Ok(response);
+    }
+
+    let grid = config
+        .tile_grids
+        .select(
+            req.uri().query(),
+            ds_info.as_ref().and_then(|ds| ds.tile_grid.as_ref()),
+        )
+        .map(|g| g.with_overzoom(config.overzoom_maxzoom));
+    let (grid, (x, y, z)) = match grid
+        .and_then(|g| zxy_from_uri_in_grid(path, &g).map(|xyz| (g, xyz)))
+    {
+        Ok(grid_xyz) => grid_xyz,
         Err(err) => {
-            let body = json!({
-                "status": StatusCode::BAD_REQUEST.as_u16(),
-                "message": err.to_string()
-            })
-            .to_string();
-            let response = response_with_body_and_code(body, StatusCode::BAD_REQUEST);
+            let response = ApiError::new(StatusCode::BAD_REQUEST, err.to_string()).into_response();
             return Ok(response);
         }
     };
 
-    if z > MAXZOOM {
-        let body = json!({
-            "status": StatusCode::BAD_REQUEST.as_u16(),
-            "message": format!("Requested zoom '{}' must be in range 0-{}", z, MAXZOOM)
-        })
-        .to_string();
-        let response = response_with_body_and_code(body, StatusCode::BAD_REQUEST);
-        return Ok(response);
+    let access = TileAccess {
+        datasource_id: datasource_id.to_string(),
+        tile_grid: grid.name.clone(),
+        z,
+        x,
+        y,
+    };
+    let usage_enabled = config.usage_enabled && ds_info.is_some();
+    if usage_enabled {
+        record_access(&ctx.tx_usage, access.clone());
     }
 
-    // Check tile on disk
-    if let Ok(Some(response)) = get_tile_from_disk(&file_tile_path, content_type).await {
-        return Ok(response);
-    }
+    // '@2x' and '@0.5x' tiles are resized by the proxy, only raster tiles
+    let scale = match tile_scale_from_uri(path) {
+        Ok(Some(_)) if content_type != ContentType::Png.as_ref() => {
+            let response = ApiError::new(
+                StatusCode::BAD_REQUEST,
+                format!(
+                    "Scale suffix is supported only for '{}' tiles",
+                    Extension::Png
+                ),
+            )
+            .into_response();
+            return Ok(response);
+        }
+        Ok(scale) => scale,
+        Err(err) => {
+            let response = ApiError::new(StatusCode::BAD_REQUEST, err.to_string()).into_response();
+            return Ok(response);
+        }
+    };
 
-    match mbtiles_path_from_uri(&cwd, path).await {
-        Ok(p) => {
-            if let Ok(Some(response)) =
-                get_mbtile(&p, z as u64, x, y, content_type, tx_sqlite_client).await
-            {
-                return Ok(response);
+    // zoom is out of range of the DataSource, the worker is not requested
+    if let Some(ds) = ds_info.as_ref() {
+        if !ds.zoom_in_range(z) {
+            if let Some(parent_z) = ds.overzoom_parent(z, config.overzoom_maxzoom) {
+                let sub = SubTile::new(z, x, y, parent_z);
+                match overzoom_from_cache(
+                    &ctx.paths,
+                    path,
+                    datasource_id,
+                    ext,
+                    content_type,
+                    &sub,
+                    ctx.tx_sqlite_client.clone(),
+                    config.integrity,
+                )
+                .await
+                {
+                    Ok(Some(tile)) => {
+                        let tile = match scale {
+                            Some(scale) => resize_png(&tile, scale)?,
+                            None => tile,
+                        };
+                        record_tile(&ctx.tx_stats, datasource_id, true);
+                        return tile_response(tile, content_type);
+                    }
+                    Ok(None) => {}
+                    Err(err) => {
+                        event!(Level::ERROR, "Error overzoom tile '{path}': {err}");
+                    }
+                }
             }
+            return empty_tile_response(content_type, missing, config);
         }
-        Err(_err) => {
-            if let Some(response) = try_init_mbtiles(
-                &cwd,
-                dataset_dir,
-                datasource_id,
-                pool,
-                Some(tx_sqlite_client.clone()),
+        // tile is outside of the DataSource bounds: empty tile, transparent PNG for raster
+        if let Some(bounds) = ds.bounds.as_ref() {
+            if !grid.tile_intersects(bounds, z, x, y) {
+                if content_type == ContentType::Png.as_ref() {
+                    return blank_tile_response(config);
+                }
+                return no_content_response();
+            }
+        }
+    }
+
+    // composite tiles are merged from tiles of children, they are not cached
+    if let Some(composite) = ds_info.as_ref().and_then(|ds| ds.composite.as_ref()) {
+        if content_type != ContentType::MvtPbf.as_ref() {
+            let response = ApiError::new(
+                StatusCode::BAD_REQUEST,
+                "Composite DataSource has only MVT tiles",
             )
-            .await
-            {
-                // return Response with Error
+            .into_response();
+            return Ok(response);
+        }
+        let query = req
+            .uri()
+            .query()
+            .map(|q| format!("?{q}"))
+            .unwrap_or_default();
+        let child_paths: Vec<String> = composite
+            .children
+            .iter()
+            .map(|child| {
+                path.replacen(&format!("/{datasource_id}/"), &format!("/{}/", child.id), 1)
+            })
+            .collect();
+        let mut child_tiles = Vec::with_capacity(child_paths.len());
+        for child_path in child_paths.iter() {
+            let mut child_req = Request::builder()
+                .method(Method::GET)
+                .uri(format!("{child_path}{query}"))
+                .body(Body::empty())?;
+            *child_req.headers_mut() = req.headers().clone();
+            // children are not composite, the recursion has one level
+            child_tiles.push(child_tile_endpoint(ctx, child_path, child_req, config));
+        }
+        let responses = futures::future::try_join_all(child_tiles)
+            .instrument(info_span!("composite_tile", datasource_id, z, x, y))
+            .await?;
+
+        let mut tiles = Vec::new();
+        for (child, response) in composite.children.iter().zip(responses) {
+            let status = response.status();
+            if status.is_server_error() {
+                let response = ApiError::new(
+                    StatusCode::BAD_GATEWAY,
+                    format!("Error tile of child DataSource '{}': {status}", child.id),
+                )
+                .into_response();
                 return Ok(response);
             }
+            // empty tiles and tiles out of zoom levels of children
+            if status != StatusCode::OK {
+                continue;
+            }
+            let tile = hyper::body::to_bytes(response.into_body()).await?;
+            tiles.push((child, tile.to_vec()));
+        }
+        let tile = merge_tiles(&tiles)?;
+        if tile.is_empty() {
+            return empty_tile_response(content_type, missing, config);
+        }
+        record_tile(&ctx.tx_stats, datasource_id, true);
+        let tile = if ds_info
+            .as_ref()
+            .and_then(|ds| ds.compress_tiles)
+            .unwrap_or(false)
+        {
+            gzip_tile(&tile)?
+        } else {
+            tile
+        };
+        return tile_response(tile, content_type);
+    }
+
+    /*
+        Raster operations are applied to the tile without them (from the cache or workers),
+        the result is cached on disk in '{y}.{key}.png' next to the tile
+    */
+    let raster_ops = match RasterOps::from_query(req.uri().query()) {
+        Ok(ops) => ops,
+        Err(err) => {
+            let response = ApiError::new(StatusCode::BAD_REQUEST, err.to_string()).into_response();
+            return Ok(response);
         }
     };
+    if let Some(ops) = raster_ops {
+        if content_type != ContentType::Png.as_ref() || scale.is_some() {
+            let response = ApiError::new(
+                StatusCode::BAD_REQUEST,
+                format!(
+                    "Raster operations are supported only for '{}' tiles without scale suffix",
+                    Extension::Png
+                ),
+            )
+            .into_response();
+            return Ok(response);
+        }
+        let ops_path =
+            file_tile_path.with_file_name(format!("{y}.{}.{}", ops.key(), Extension::Png));
+        if let Ok(Some(response)) =
+            get_tile_from_disk(&ops_path, content_type, config.integrity).await
+        {
+            record_tile(&ctx.tx_stats, datasource_id, true);
+            return Ok(response);
+        }
 
-    let (tx_ds, rx_ds) = oneshot::channel();
-    tx.send_async(MessageDatasource::GetDataSource {
-        datasource_id: datasource_id.to_string(),
-        tx_ds,
-    })
+        let mut base_req = Request::builder()
+            .method(Method::GET)
+            .uri(format!(
+                "{path}{}",
+                RasterOps::base_query(req.uri().query())
+            ))
+            .body(Body::empty())?;
+        *base_req.headers_mut() = req.headers().clone();
+        let base = child_tile_endpoint(ctx, path, base_req, config).await?;
+        if base.status() != StatusCode::OK {
+            return Ok(base);
+        }
+        let base = hyper::body::to_bytes(base.into_body()).await?;
+        let encoding = ds_info
+            .as_ref()
+            .and_then(|ds| ds.encoding.clone())
+            .unwrap_or("none".to_string());
+        let resolution = ground_resolution(&grid, z, x, y).unwrap_or(1.0);
+        let tile = tokio::task::spawn_blocking(move || ops.apply(&base, &encoding, resolution))
+            .instrument(info_span!("raster_ops", datasource_id, z, x, y))
+            .await??;
+        save_disk_tile(&ops_path, &tile, config.integrity).await;
+        return tile_response(tile, content_type);
+    }
+
+    if let Some(scale) = scale {
+        let mbtiles_path = mbtiles_path_from_uri(&ctx.paths, path, (x, y, z))
+            .await
+            .ok();
+        if let Some(response) = get_scaled_tile(
+            &file_tile_path,
+            mbtiles_path,
+            (x, y, z),
+            scale,
+            ctx.tx_sqlite_client.clone(),
+            config.integrity,
+        )
+        .await?
+        {
+            record_tile(&ctx.tx_stats, datasource_id, true);
+            return Ok(response);
+        }
+
+        // base tile is not cached, it is rendered like a tile without the scale suffix
+        let base_path = unscaled_tile_uri(path);
+        let base_uri = match req.uri().query() {
+            Some(query) => format!("{base_path}?{query}"),
+            None => base_path.clone(),
+        };
+        let mut base_req = Request::builder()
+            .method(Method::GET)
+            .uri(base_uri)
+            .body(Body::empty())?;
+        *base_req.headers_mut() = req.headers().clone();
+        let base = child_tile_endpoint(ctx, &base_path, base_req, config).await?;
+        if base.status() != StatusCode::OK {
+            return Ok(base);
+        }
+        let base = hyper::body::to_bytes(base.into_body()).await?;
+        let tile = save_scaled_tile(&file_tile_path, &base, scale, config.integrity).await?;
+        return tile_response(tile, content_type);
+    }
+
+    // Check tile in the cache: file on disk, then MBTiles of the DataSource
+    let lookup = lookup_tile(
+        dataset_dir.clone(),
+        &file_tile_path,
+        (x, y, z),
+        ctx.tx_sqlite_client.clone(),
+        config.integrity,
+    )
     .await?;
+    // zero-length tiles of the cache are tiles without data
+    let cached = match lookup.tile {
+        CachedTile::Empty => Some(empty_tile_response(content_type, missing, config)?),
+        tile => cached_tile_response(tile, content_type)?,
+    };
+    if let Some(response) = cached {
+        record_tile(&ctx.tx_stats, datasource_id, true);
+        return Ok(response);
+    }
+    if lookup.mbtiles_path.is_none() {
+        if let Some(response) = try_init_mbtiles(
+            &ctx.paths,
+            dataset_dir,
+            datasource_id,
+            ctx.registry.clone(),
+            Some(ctx.tx_sqlite_client.clone()),
+        )
+        .await
+        {
+            // return Response with Error
+            return Ok(response);
+        }
+    }
+
+    // misses of DataSources with cache only are recorded too, they are seeded by the smart pyramid
+    if usage_enabled {
+        record_miss(&ctx.tx_usage, access, &ext.to_string_lossy());
+    }
 
     let mut use_cache_only = false;
-    if let Some(ds) = rx_ds.await? {
+    if let Some(ds) = ds_info.as_ref() {
         use_cache_only = ds.use_cache_only.unwrap_or(false);
     }
 
     if use_cache_only {
-        let response = Response::builder()
-            .status(StatusCode::NO_CONTENT)
-            .header("Content-Length", 0)
-            .header("Access-Control-Allow-Origin", "*")
-            .header("Cache-Control", "max-age=0")
-            .body(Body::empty())?;
-        return Ok(response);
+        return empty_tile_response(content_type, missing, config);
     }
+    record_tile(&ctx.tx_stats, datasource_id, false);
 
-    let (tx_permit, rx_permit) = tokio::sync::oneshot::channel();
-    if let Err(err) = tx_sem
-        .send_async(MessageSemaphore::GetPermit { port, tx_permit })
-        .await
-    {
-        event!(Level::ERROR, "Error send get permit message {err}");
+    /*
+        tiles of PostGIS, file and COG DataSources are generated by the proxy,
+        tiles of remote DataSources are fetched from the upstream, workers are not requested
+    */
+    if let Some(ds) = ds_info.as_ref().filter(|ds| {
+        ds.postgis.is_some() || ds.vector_file.is_some() || ds.cog.is_some() || ds.remote.is_some()
+    }) {
+        let message = if let Some(cog) = ds.cog.as_ref() {
+            (content_type != cog.content_type() || !cog.supports_grid(&grid)).then(|| {
+                format!(
+                    "DataSource has only '{}' tiles of grids in EPSG:3857 or EPSG:4326",
+                    cog.content_type()
+                )
+            })
+        } else if ds.remote.is_some() {
+            let ds_grid = ds
+                .tile_grid
+                .as_ref()
+                .map_or(DEFAULT_TILE_GRID, |g| g.name.as_str());
+            (grid.name != ds_grid).then(|| format!("DataSource has only tiles of '{ds_grid}'"))
+        } else {
+            (content_type != ContentType::MvtPbf.as_ref() || grid.name != DEFAULT_TILE_GRID)
+                .then(|| format!("DataSource has only MVT tiles of '{DEFAULT_TILE_GRID}'"))
+        };
+        if let Some(message) = message {
+            let response = ApiError::new(StatusCode::BAD_REQUEST, message).into_response();
+            return Ok(response);
+        }
+        let _permit = native_render_permit(&ctx.tx_sem, config).await;
+        let tile = if let Some(source) = ds.postgis.as_ref() {
+            let Some(pool) = ctx.pg_pool.as_ref() else {
+                let response = ApiError::new(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "PostGIS DataSource requires PostgreSQL config database",
+                )
+                .into_response();
+                return Ok(response);
+            };
+            generate_mvt(pool, source, z, x, y).await?
+        } else if let Some(vector_file) = ds.vector_file.clone() {
+            tokio::task::spawn_blocking(move || vector_file.tile(z, x, y)).await?
+        } else if let Some(cog) = ds.cog.as_ref() {
+            cog.tile(&grid, z, x, y)
+                .instrument(info_span!("cog_tile", datasource_id, z, x, y))
+                .await?
+        } else if let Some(remote) = ds.remote.as_ref() {
+            match remote
+                .fetch(z, x, y)
+                .instrument(info_span!("remote_tile", datasource_id, z, x, y))
+                .await
+            {
+                Ok(RemoteTile::Tile(tile)) => tile,
+                Ok(RemoteTile::Empty) => Vec::new(),
+                Ok(RemoteTile::RateLimited) => {
+                    let response = ApiError::new(
+                        StatusCode::SERVICE_UNAVAILABLE,
+                        "Rate limit of requests to the upstream of the DataSource is exceeded",
+                    )
+                    .with_code(ErrorCode::UpstreamRateLimited)
+                    .into_response();
+                    return Ok(response);
+                }
+                Err(err) => {
+                    event!(Level::ERROR, "Error fetch remote tile '{path}': {err}");
+                    let response =
+                        ApiError::new(StatusCode::BAD_GATEWAY, err.to_string()).into_response();
+                    return Ok(response);
+                }
+            }
+        } else {
+            Vec::new()
+        };
+        if tile.is_empty() {
+            return empty_tile_response(content_type, missing, config);
+        }
+        // only MVT tiles are compressed, tiles of the upstream may be compressed already
+        let tile = if ds.compress_tiles.unwrap_or(false)
+            && content_type == ContentType::MvtPbf.as_ref()
+            && !tile.starts_with(b"\x1f\x8b")
+        {
+            gzip_tile(&tile)?
+        } else {
+            tile
+        };
+        save_generated_tile(
+            &ctx.paths,
+            path,
+            &file_tile_path,
+            (x, y, z),
+            &tile,
+            ctx.tx_sqlite_client.clone(),
+            config.integrity,
+        )
+        .await;
+        return tile_response(tile, content_type);
     }
-    let permit = rx_permit.await;
 
-    match hyper_reverse_proxy::call(
-        client_ip,
-        &format!("http://{}:{}", LOCALHOST, port),
-        req,
-        &client,
-    )
-    .await
-    {
-        Ok(response) => {
-            if let Ok(p) = permit {
-                drop(p);
+    // other DataSources (rasters of GDAL) have tiles only in the cache without Python workers
+    if config.is_native() {
+        let response = ApiError::new(
+            StatusCode::NOT_IMPLEMENTED,
+            "Tiles of the DataSource are rendered by Python workers, not run in native mode",
+        )
+        .with_code(ErrorCode::WorkersRequired)
+        .into_response();
+        return Ok(response);
+    }
+
+    /*
+        Idempotent GET requests are retried on other workers when the proxy call fails,
+        the first attempt goes to the port selected by the balancer. Retries skip ports
+        with open circuit breakers
+    */
+    let retries = if req.method() == Method::GET {
+        config
+            .tile_retries
+            .min(ctx.ports.len().saturating_sub(1) as u64) as usize
+    } else {
+        0
+    };
+    let mut candidates = vec![ctx.port];
+    candidates.extend(ctx.ports.iter().filter(|p| **p != ctx.port));
+
+    let method = req.method().clone();
+    let uri = req.uri().clone();
+    let headers = req.headers().clone();
+    let mut opt_req = Some(req);
+    // tools building caches mark their requests to not starve interactive maps
+    let lane = headers
+        .get(TILE_PRIORITY_HEADER)
+        .and_then(|priority| priority.to_str().ok())
+        .and_then(|priority| priority.parse::<Lane>().ok())
+        .unwrap_or(Lane::Interactive);
+    let max_queued = ds_info
+        .as_ref()
+        .and_then(|ds| ds.max_queue_depth)
+        .unwrap_or(config.max_queue_depth);
+    // tiles of the cache are tiles of the grid of the DataSource, markers of other grids are not saved
+    let negative_cache = ds_info.as_ref().is_some_and(|ds| {
+        ds.negative_cache.unwrap_or(config.negative_cache)
+            && grid.name
+                == ds
+                    .tile_grid
+                    .as_ref()
+                    .map_or(DEFAULT_TILE_GRID, |g| g.name.as_str())
+    });
+
+    let mut attempt = 0;
+    for port in candidates {
+        if attempt > retries {
+            break;
+        }
+        // the port of the first attempt is checked by the balancer already
+        if attempt > 0 && !worker_allowed(&ctx.tx_mw, port).await {
+            continue;
+        }
+        let mut request = match opt_req.take() {
+            Some(r) => r,
+            None => {
+                let mut r = Request::builder()
+                    .method(method.clone())
+                    .uri(uri.clone())
+                    .body(Body::empty())?;
+                *r.headers_mut() = headers.clone();
+                r
             }
-            return Ok(response);
+        };
+
+        let (tx_permit, rx_permit) = tokio::sync::oneshot::channel();
+        if let Err(err) = ctx
+            .tx_sem
+            .send_async(MessageSemaphore::GetPermit {
+                port,
+                lane,
+                max_queued,
+                tx_permit,
+            })
+            .await
+        {
+            event!(Level::ERROR, "Error send get permit message {err}");
         }
-        Err(error) => {
-            if let Ok(p) = permit {
-                drop(p);
+        let wait_started = Instant::now();
+        let permit = match rx_permit
+            .instrument(info_span!("semaphore_wait", port))
+            .await
+        {
+            Ok(Err(full)) => {
+                event!(
+                    Level::WARN,
+                    "Tile '{path}' is refused: {} requests wait for worker port {port}",
+                    full.depth
+                );
+                return Ok(queue_full_response(
+                    lane,
+                    full,
+                    config.queue_retry_after_secs,
+                ));
             }
-            event!(Level::ERROR, "Error request {:?}", error);
-            let err_response = error_response(StatusCode::INTERNAL_SERVER_ERROR);
-            return Ok(err_response);
+            Ok(Ok(guard)) => Ok(guard),
+            Err(err) => Err(err),
+        };
+        let mut timings = WorkerTimings {
+            datasource_id: datasource_id.to_string(),
+            port,
+            permit_wait: wait_started.elapsed(),
+            upstream: Duration::ZERO,
+        };
+
+        let forward_uri = format!("http://{}:{}", LOCALHOST, port);
+        let proxy_span = info_span!("upstream_proxy", port, attempt = attempt + 1);
+        inject_context(&proxy_span, request.headers_mut());
+        let worker_call = hyper_reverse_proxy::call_with_rules(
+            ctx.client_ip,
+            &forward_uri,
+            request,
+            &ctx.client,
+            config.upstream_header_rules(EndPoints::Tile.as_ref()),
+        )
+        .instrument(proxy_span);
+        let upstream_started = Instant::now();
+        let result = if config.timeout_tile_response > 0 {
+            match tokio::time::timeout(
+                Duration::from_secs(config.timeout_tile_response),
+                worker_call,
+            )
+            .await
+            {
+                Ok(res) => res,
+                Err(_) => {
+                    if let Ok(p) = permit {
+                        drop(p);
+                    }
+                    report_worker_result(&ctx.tx_mw, port, false).await;
+                    event!(
+                        Level::ERROR,
+                        "Timeout request to worker port {port}: no response in {} seconds",
+                        config.timeout_tile_response
+                    );
+                    timings.upstream = upstream_started.elapsed();
+                    report_worker_latency(&ctx.tx_sem, port, timings.upstream, false).await;
+                    let mut response = ApiError::new(
+                        StatusCode::GATEWAY_TIMEOUT,
+                        format!(
+                            "Timeout: no response in {} seconds",
+                            config.timeout_tile_response
+                        ),
+                    )
+                    .with_code(ErrorCode::WorkerTimeout)
+                    .into_response();
+                    response.extensions_mut().insert(timings);
+                    return Ok(response);
+                }
+            }
+        } else {
+            worker_call.await
+        };
+
+        timings.upstream = upstream_started.elapsed();
+
+        match result {
+            Ok(mut response) => {
+                if let Ok(p) = permit {
+                    drop(p);
+                }
+                // HTML error pages and oversized payloads of the worker are not forwarded as tiles
+                if response.status() == StatusCode::OK {
+                    let max_size = config.max_tile_size_kb * 1024;
+                    response = match validate_worker_tile(response, content_type, max_size).await {
+                        Ok(response) => response,
+                        Err(reason) => {
+                            report_worker_result(&ctx.tx_mw, port, false).await;
+                            report_worker_latency(&ctx.tx_sem, port, timings.upstream, false).await;
+                            event!(
+                                Level::ERROR,
+                                "Invalid tile '{path}' of worker port {port}: {reason}"
+                            );
+                            let mut response = ApiError::new(
+                                StatusCode::BAD_GATEWAY,
+                                format!("Invalid tile of the worker: {reason}"),
+                            )
+                            .with_code(ErrorCode::InvalidWorkerTile)
+                            .into_response();
+                            response.extensions_mut().insert(timings);
+                            return Ok(response);
+                        }
+                    };
+                }
+                response.extensions_mut().insert(timings.clone());
+                let success = !is_worker_failure(response.status());
+                report_worker_result(&ctx.tx_mw, port, success).await;
+                report_worker_latency(&ctx.tx_sem, port, timings.upstream, success).await;
+                // gap of the raster: worker has no data for the tile, its caching headers are kept
+                if response.status() == StatusCode::NO_CONTENT
+                    || response.status() == StatusCode::NOT_FOUND
+                {
+                    // only 204 confirms the empty area, 404 may be a DataSource unknown to the worker
+                    if negative_cache && response.status() == StatusCode::NO_CONTENT {
+                        save_generated_tile(
+                            &ctx.paths,
+                            path,
+                            &file_tile_path,
+                            (x, y, z),
+                            &[],
+                            ctx.tx_sqlite_client.clone(),
+                            config.integrity,
+                        )
+                        .await;
+                    }
+                    return empty_tile_response(content_type, missing, config)
+                        .map(|mut r| {
+                            copy_caching_headers(response.headers(), r.headers_mut());
+                            r.extensions_mut().insert(timings);
+                            r
+                        })
+                        .or(Ok(response));
+                }
+                return Ok(response);
+            }
+            Err(error) => {
+                if let Ok(p) = permit {
+                    drop(p);
+                }
+                report_worker_result(&ctx.tx_mw, port, false).await;
+                report_worker_latency(&ctx.tx_sem, port, timings.upstream, false).await;
+                event!(
+                    Level::ERROR,
+                    "Error request to worker port {port}, attempt {}/{}: {:?}",
+                    attempt + 1,
+                    retries + 1,
+                    error
+                );
+                attempt += 1;
+            }
+        }
+    }
+
+    let err_response = error_response(StatusCode::INTERNAL_SERVER_ERROR);
+    Ok(err_response)
+}
+
+/*
+    Permit of the pool of native renderers: in native mode concurrent tiles of the balancer
+    are limited by 'max_concurrent_tile_requests' like requests to a worker
+*/
+async fn native_render_permit(
+    tx_sem: &flume::Sender<MessageSemaphore>,
+    config: &Config,
+) -> Option<PermitGuard> {
+    if !config.is_native() {
+        return None;
+    }
+    let (tx_permit, rx_permit) = oneshot::channel();
+    let message = MessageSemaphore::GetPermit {
+        port: NATIVE_RENDERER_PORT,
+        lane: Lane::Interactive,
+        max_queued: 0,
+        tx_permit,
+    };
+    if let Err(err) = tx_sem.send_async(message).await {
+        event!(Level::ERROR, "Error send get permit message {err}");
+        return None;
+    }
+    rx_permit
+        .instrument(info_span!("semaphore_wait", port = NATIVE_RENDERER_PORT))
+        .await
+        .ok()
+        .and_then(|permit| permit.ok())
+}
+
+// Boxed tile endpoint for tiles of children of composite DataSources
+fn child_tile_endpoint<'a>(
+    ctx: &'a TileContext,
+    path: &'a str,
+    req: Request<Body>,
+    config: &'a Config,
+) -> Pin<Box<dyn Future<Output = Result<Response<Body>, anyhow::Error>> + Send + 'a>> {
+    Box::pin(tile_endpoint(ctx, path, req, config))
+}
+
+/*
+    Tile generated by the proxy is cached like tiles of workers:
+    to MBTiles of the DataSource, to the file on disk without MBTiles
+*/
+async fn save_generated_tile(
+    paths: &Paths,
+    path: &str,
+    file_tile_path: &PathBuf,
+    (x, y, z): (u64, u64, u8),
+    tile: &[u8],
+    tx_sqlite_client: flume::Sender<MessageSQLiteClient>,
+    integrity: bool,
+) {
+    if let Ok(p) = mbtiles_path_from_uri(paths, path, (x, y, z)).await {
+        if let Err(err) = save_mbtile(
+            &p,
+            z as u64,
+            x,
+            y,
+            tile.to_vec(),
+            tx_sqlite_client,
+            integrity,
+        )
+        .await
+        {
+            event!(Level::ERROR, "Error save tile {z}/{x}/{y} to {p:?}: {err}");
+        }
+        return;
+    }
+
+    save_disk_tile(file_tile_path, tile, integrity).await;
+}
+
+async fn save_disk_tile(file_tile_path: &PathBuf, tile: &[u8], integrity: bool) {
+    if let Some(dir) = file_tile_path.parent() {
+        if let Err(err) = tokio::fs::create_dir_all(dir).await {
+            event!(Level::ERROR, "Error create directory {dir:?}: {err}");
+            return;
+        }
+    }
+    match tokio::fs::write(file_tile_path, tile).await {
+        Ok(_) if integrity => write_disk_tile_checksum(file_tile_path, tile).await,
+        Ok(_) => {}
+        Err(err) => event!(Level::ERROR, "Error save tile {file_tile_path:?}: {err}"),
+    }
+}
+
+/*
+    Tile deeper than 'maxzoom' of the DataSource is cut from the parent tile
+    on 'maxzoom' found in the cache (files on disk or MBTiles), workers are not requested
+*/
+async fn overzoom_from_cache(
+    paths: &Paths,
+    path: &str,
+    datasource_id: &str,
+    ext: &OsStr,
+    content_type: &str,
+    sub: &SubTile,
+    tx_sqlite_client: flume::Sender<MessageSQLiteClient>,
+    integrity: bool,
+) -> Result<Option<Vec<u8>>, anyhow::Error> {
+    let (parent_z, parent_x, parent_y) = sub.parent;
+    let parent_path = paths
+        .tiles_dir(datasource_id)
+        .join(parent_z.to_string())
+        .join(parent_x.to_string())
+        .join(format!("{parent_y}.{}", ext.to_string_lossy()));
+
+    let mut parent = get_tile_from_disk(&parent_path, content_type, integrity).await?;
+    if parent.is_none() {
+        let parent_xyz = (parent_x, parent_y, parent_z);
+        if let Ok(p) = mbtiles_path_from_uri(paths, path, parent_xyz).await {
+            parent = get_mbtile(
+                &p,
+                parent_z as u64,
+                parent_x,
+                parent_y,
+                content_type,
+                tx_sqlite_client,
+                integrity,
+            )
+            .await?;
+        }
+    }
+
+    match parent {
+        Some(response) if response.status() == StatusCode::OK => {
+            let data = hyper::body::to_bytes(response.into_body()).await?;
+            overzoom_tile(&data, content_type, sub).map(Some)
         }
+        _ => Ok(None),
     }
 }
@@ -0,0 +1,728 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+
+use hyper::{
+    body::HttpBody, client::HttpConnector, http::header, Body, Client, Method, Request, Response,
+    StatusCode,
+};
+use multer::{Constraints, Multipart, SizeLimit};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use sqlx::types::Json;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::oneshot;
+use tracing::{event, Level};
+use uuid::Uuid;
+
+use crate::config::Config;
+use crate::db::job::queue::{JobDetail, JobType};
+use crate::defaults::{
+    TUS_RESUMABLE_HEADER, TUS_VERSION, UPLOAD_ALLOWED_EXTENSIONS, UPLOAD_LENGTH_HEADER,
+    UPLOAD_OFFSET_HEADER,
+};
+use crate::handles::endpoints::datasource::datasource_endpoint;
+use crate::handles::error::ApiError;
+use crate::handles::headers::{CacheControl, ResponseBuilderExt};
+use crate::handles::helpers::{error_response, response_with_body_and_code};
+use crate::paths::Paths;
+use crate::structs::{ContentType, EndPoints};
+use crate::tasks::datasources::MessageDatasource;
+use crate::tasks::uploads::{MessageUploads, Upload, UploadBusy, UploadLock};
+
+// File of the upload written as '{name}.part' until the whole request is validated
+struct UploadedFile {
+    name: String,
+    part: PathBuf,
+    path: PathBuf,
+    size: u64,
+    sha256: String,
+}
+
+fn upload_error_response(message: String, code: StatusCode) -> Response<Body> {
+    ApiError::new(code, message).into_response()
+}
+
+async fn remove_parts(files: &[UploadedFile]) {
+    for f in files {
+        if let Err(err) = tokio::fs::remove_file(&f.part).await {
+            event!(
+                Level::ERROR,
+                "Error remove part of upload {:?}: {err}",
+                f.part
+            );
+        }
+    }
+}
+
+// Name of the uploaded file without directories, only allowed extensions
+fn sanitize_file_name(file_name: &str) -> Option<String> {
+    let name = Path::new(file_name).file_name()?.to_str()?;
+    if name.starts_with('.') || name != file_name.trim() {
+        return None;
+    }
+    let ext = Path::new(name).extension()?.to_str()?.to_lowercase();
+    if !UPLOAD_ALLOWED_EXTENSIONS.contains(&ext.as_str()) {
+        return None;
+    }
+    Some(name.to_string())
+}
+
+fn query_param<'a>(query: Option<&'a str>, key: &str) -> Option<&'a str> {
+    query?
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(k, _)| *k == key)
+        .map(|(_, v)| v)
+}
+
+// DataSource is reloaded from files by workers and the balancer
+async fn reload_datasource(
+    datasource_id: &str,
+    port: u16,
+    ports: Vec<u16>,
+    client_ip: IpAddr,
+    client: Client<HttpConnector>,
+    tx: flume::Sender<MessageDatasource>,
+) -> Result<StatusCode, anyhow::Error> {
+    let ct: &str = ContentType::ApplicationJson.into();
+    let reload_request = Request::builder()
+        .method(Method::POST)
+        .uri(EndPoints::DataSourcesReloadFiles.as_ref())
+        .header(header::CONTENT_TYPE, ct)
+        .body(Body::from(json!([datasource_id]).to_string()))?;
+    let (parts, _) = Request::builder()
+        .method(Method::POST)
+        .uri(EndPoints::DataSourcesReloadFiles.as_ref())
+        .body(())?
+        .into_parts();
+    let response =
+        datasource_endpoint(port, ports, parts, client_ip, client, tx, reload_request).await?;
+    Ok(response.status())
+}
+
+/*
+    POST /api/datasources/{datasource_id}/files?type=raster|vector
+    Streaming multipart upload of source files of the DataSource to 'datasources/{type}'.
+    Text fields '{file_name}.sha256' are checked against SHA-256 of the uploaded files,
+    size of the request is limited by 'upload.max_size_mb'. After the upload
+    DataSources of the files are reloaded by workers ('/api/datasources/reload_files')
+*/
+pub async fn upload_endpoint(
+    paths: &Paths,
+    datasource_id: &str,
+    query: Option<&str>,
+    req: Request<Body>,
+    port: u16,
+    ports: Vec<u16>,
+    client_ip: IpAddr,
+    client: Client<HttpConnector>,
+    tx: flume::Sender<MessageDatasource>,
+    config: &Config,
+) -> Result<Response<Body>, anyhow::Error> {
+    let ds_type = query_param(query, "type").unwrap_or_default();
+    if ds_type != "raster" && ds_type != "vector" {
+        return Ok(upload_error_response(
+            "Parameter 'type' must be 'raster' or 'vector'".to_string(),
+            StatusCode::BAD_REQUEST,
+        ));
+    }
+
+    let boundary = req
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|ct| ct.to_str().ok())
+        .and_then(|ct| multer::parse_boundary(ct).ok());
+    let Some(boundary) = boundary else {
+        return Ok(upload_error_response(
+            "Content-Type must be 'multipart/form-data'".to_string(),
+            StatusCode::BAD_REQUEST,
+        ));
+    };
+
+    let max_size = config.upload_max_size_mb * 1024 * 1024;
+    let content_length = req
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|cl| cl.to_str().ok())
+        .and_then(|cl| cl.parse::<u64>().ok());
+    if matches!(content_length, Some(length) if length > max_size) {
+        return Ok(upload_error_response(
+            format!("Upload exceeds {} MB", config.upload_max_size_mb),
+            StatusCode::PAYLOAD_TOO_LARGE,
+        ));
+    }
+
+    let ds_dir = paths.datasources_dir(ds_type);
+    tokio::fs::create_dir_all(&ds_dir).await?;
+
+    let constraints = Constraints::new().size_limit(SizeLimit::new().whole_stream(max_size));
+    let mut multipart = Multipart::with_constraints(req.into_body(), boundary, constraints);
+    let mut files: Vec<UploadedFile> = Vec::new();
+    let mut checksums: HashMap<String, String> = HashMap::new();
+
+    loop {
+        let mut field = match multipart.next_field().await {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            Err(err) => {
+                remove_parts(&files).await;
+                let code = match err {
+                    multer::Error::StreamSizeExceeded { .. }
+                    | multer::Error::FieldSizeExceeded { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+                    _ => StatusCode::BAD_REQUEST,
+                };
+                return Ok(upload_error_response(err.to_string(), code));
+            }
+        };
+
+        let Some(file_name) = field.file_name().map(|f| f.to_string()) else {
+            // checksum of the file: '{file_name}.sha256'
+            let name = field.name().unwrap_or_default().to_string();
+            if let Some(file_name) = name.strip_suffix(".sha256") {
+                let file_name = file_name.to_string();
+                match field.text().await {
+                    Ok(value) => {
+                        checksums.insert(file_name, value.trim().to_lowercase());
+                    }
+                    Err(err) => {
+                        remove_parts(&files).await;
+                        return Ok(upload_error_response(
+                            err.to_string(),
+                            StatusCode::BAD_REQUEST,
+                        ));
+                    }
+                }
+            }
+            continue;
+        };
+
+        let Some(name) = sanitize_file_name(&file_name) else {
+            remove_parts(&files).await;
+            return Ok(upload_error_response(
+                format!("File name '{file_name}' is invalid or extension is not allowed"),
+                StatusCode::BAD_REQUEST,
+            ));
+        };
+
+        let path = ds_dir.join(&name);
+        let part = ds_dir.join(format!("{name}.part"));
+        let mut file = tokio::fs::File::create(&part).await?;
+        let mut hasher = Sha256::new();
+        let mut size = 0;
+        files.push(UploadedFile {
+            name: name.clone(),
+            part: part.clone(),
+            path,
+            size: 0,
+            sha256: String::new(),
+        });
+
+        loop {
+            match field.chunk().await {
+                Ok(Some(chunk)) => {
+                    hasher.update(&chunk);
+                    size += chunk.len() as u64;
+                    if let Err(err) = file.write_all(&chunk).await {
+                        remove_parts(&files).await;
+                        return Err(anyhow::Error::from(err));
+                    }
+                }
+                Ok(None) => break,
+                Err(err) => {
+                    drop(file);
+                    remove_parts(&files).await;
+                    let code = match err {
+                        multer::Error::StreamSizeExceeded { .. }
+                        | multer::Error::FieldSizeExceeded { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+                        _ => StatusCode::BAD_REQUEST,
+                    };
+                    return Ok(upload_error_response(err.to_string(), code));
+                }
+            }
+        }
+        file.flush().await?;
+
+        if let Some(f) = files.last_mut() {
+            f.size = size;
+            f.sha256 = hex::encode(hasher.finalize());
+        }
+    }
+
+    if files.is_empty() {
+        return Ok(upload_error_response(
+            "Request has no files".to_string(),
+            StatusCode::BAD_REQUEST,
+        ));
+    }
+
+    for (name, expected) in checksums.iter() {
+        let actual = files.iter().find(|f| &f.name == name).map(|f| &f.sha256);
+        if actual != Some(expected) {
+            remove_parts(&files).await;
+            return Ok(upload_error_response(
+                format!("Checksum SHA-256 of file '{name}' does not match"),
+                StatusCode::UNPROCESSABLE_ENTITY,
+            ));
+        }
+    }
+
+    for f in files.iter() {
+        tokio::fs::rename(&f.part, &f.path).await?;
+    }
+    event!(
+        Level::INFO,
+        "Uploaded {} files of DataSource '{datasource_id}' to {ds_dir:?}",
+        files.len()
+    );
+
+    let reload = reload_datasource(datasource_id, port, ports, client_ip, client, tx).await?;
+
+    let body = json!({
+        "status": StatusCode::OK.as_u16(),
+        "message": format!("Files of DataSource '{datasource_id}' uploaded"),
+        "files": files
+            .iter()
+            .map(|f| json!({"name": f.name, "size": f.size, "sha256": f.sha256}))
+            .collect::<Vec<_>>(),
+        "reload_status": reload.as_u16(),
+    })
+    .to_string();
+    Ok(response_with_body_and_code(body, StatusCode::OK))
+}
+
+fn header_u64(req: &Request<Body>, name: &str) -> Option<u64> {
+    req.headers()
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok())
+}
+
+// Response of the resumable upload with headers of the tus protocol
+fn tus_response(
+    code: StatusCode,
+    upload: Option<&Upload>,
+    body: Body,
+) -> Result<Response<Body>, anyhow::Error> {
+    let mut builder = Response::builder()
+        .status(code)
+        .header(TUS_RESUMABLE_HEADER, TUS_VERSION)
+        .cache_control(CacheControl::NoStore)
+        .header(
+            header::ACCESS_CONTROL_EXPOSE_HEADERS,
+            "Location, Upload-Offset, Upload-Length, Tus-Resumable",
+        );
+    if let Some(upload) = upload {
+        builder = builder
+            .header(UPLOAD_OFFSET_HEADER, upload.offset)
+            .header(UPLOAD_LENGTH_HEADER, upload.length);
+    }
+    Ok(builder.body(body)?)
+}
+
+async fn get_upload(
+    upload_id: &str,
+    tx_uploads: &flume::Sender<MessageUploads>,
+) -> Result<Option<Upload>, anyhow::Error> {
+    let (tx_upload, rx_upload) = oneshot::channel();
+    tx_uploads
+        .send_async(MessageUploads::GetUpload {
+            upload_id: upload_id.to_string(),
+            tx_upload,
+        })
+        .await?;
+    Ok(rx_upload.await?)
+}
+
+// Upload dropped by the request while it is acquired is released by the registry of uploads
+async fn acquire_upload(
+    upload_id: &str,
+    tx_uploads: &flume::Sender<MessageUploads>,
+) -> Result<Result<(Upload, UploadLock), UploadBusy>, anyhow::Error> {
+    let (tx_upload, rx_upload) = oneshot::channel();
+    tx_uploads
+        .send_async(MessageUploads::Acquire {
+            upload_id: upload_id.to_string(),
+            tx_upload,
+        })
+        .await?;
+    Ok(rx_upload
+        .await?
+        .map(|upload| (upload, UploadLock::new(upload_id, tx_uploads))))
+}
+
+fn upload_not_found(upload_id: &str) -> Response<Body> {
+    upload_error_response(
+        format!("Upload '{upload_id}' not found"),
+        StatusCode::NOT_FOUND,
+    )
+}
+
+/*
+    Resumable upload of a large file of the DataSource (tus 1.0 core protocol):
+    POST   /api/datasources/{datasource_id}/uploads?type=raster|vector&name={file}[&sha256=..][&pyramid=true]
+           with header 'Upload-Length' creates the upload, URL of the upload is in 'Location'
+    HEAD   /api/datasources/{datasource_id}/uploads/{upload_id} returns 'Upload-Offset'
+    PATCH  /api/datasources/{datasource_id}/uploads/{upload_id} appends the body at 'Upload-Offset'
+    DELETE /api/datasources/{datasource_id}/uploads/{upload_id} aborts the upload
+    The last PATCH moves the file to 'datasources/{type}', reloads the DataSource
+    and schedules the pyramid of tiles when it was requested
+*/
+pub async fn resumable_upload_endpoint(
+    paths: &Paths,
+    datasource_id: &str,
+    upload_id: Option<&str>,
+    query: Option<&str>,
+    req: Request<Body>,
+    port: u16,
+    ports: Vec<u16>,
+    client_ip: IpAddr,
+    client: Client<HttpConnector>,
+    tx: flume::Sender<MessageDatasource>,
+    tx_uploads: flume::Sender<MessageUploads>,
+    tx_jd: Option<flume::Sender<JobDetail>>,
+    config: &Config,
+) -> Result<Response<Body>, anyhow::Error> {
+    let method = req.method().clone();
+    let Some(upload_id) = upload_id else {
+        if method != Method::POST {
+            return Ok(error_response(StatusCode::METHOD_NOT_ALLOWED));
+        }
+        return create_upload(paths, datasource_id, query, req, tx_uploads, config).await;
+    };
+
+    match method {
+        Method::HEAD => match get_upload(upload_id, &tx_uploads).await? {
+            Some(upload) if upload.datasource_id == datasource_id => {
+                tus_response(StatusCode::OK, Some(&upload), Body::empty())
+            }
+            _ => tus_response(StatusCode::NOT_FOUND, None, Body::empty()),
+        },
+        Method::PATCH => {
+            append_upload(
+                datasource_id,
+                upload_id,
+                req,
+                port,
+                ports,
+                client_ip,
+                client,
+                tx,
+                tx_uploads,
+                tx_jd,
+            )
+            .await
+        }
+        Method::DELETE => match acquire_upload(upload_id, &tx_uploads).await? {
+            Err(UploadBusy::NotFound) => Ok(upload_not_found(upload_id)),
+            Err(UploadBusy::Locked) => Ok(upload_error_response(
+                format!("Upload '{upload_id}' is in progress"),
+                StatusCode::CONFLICT,
+            )),
+            Ok((upload, lock)) => {
+                if let Err(err) = tokio::fs::remove_file(&upload.part).await {
+                    event!(
+                        Level::ERROR,
+                        "Error remove part of upload {:?}: {err}",
+                        upload.part
+                    );
+                }
+                lock.remove().await?;
+                tus_response(StatusCode::NO_CONTENT, None, Body::empty())
+            }
+        },
+        _ => Ok(error_response(StatusCode::METHOD_NOT_ALLOWED)),
+    }
+}
+
+async fn create_upload(
+    paths: &Paths,
+    datasource_id: &str,
+    query: Option<&str>,
+    req: Request<Body>,
+    tx_uploads: flume::Sender<MessageUploads>,
+    config: &Config,
+) -> Result<Response<Body>, anyhow::Error> {
+    let ds_type = query_param(query, "type").unwrap_or_default();
+    if ds_type != "raster" && ds_type != "vector" {
+        return Ok(upload_error_response(
+            "Parameter 'type' must be 'raster' or 'vector'".to_string(),
+            StatusCode::BAD_REQUEST,
+        ));
+    }
+    let file_name = query_param(query, "name")
+        .and_then(|name| urlencoding::decode(name).ok())
+        .unwrap_or_default();
+    let Some(name) = sanitize_file_name(&file_name) else {
+        return Ok(upload_error_response(
+            format!("File name '{file_name}' is invalid or extension is not allowed"),
+            StatusCode::BAD_REQUEST,
+        ));
+    };
+    let Some(length) = header_u64(&req, UPLOAD_LENGTH_HEADER) else {
+        return Ok(upload_error_response(
+            "Header 'Upload-Length' is required".to_string(),
+            StatusCode::BAD_REQUEST,
+        ));
+    };
+    if length > config.upload_max_size_mb * 1024 * 1024 {
+        return Ok(upload_error_response(
+            format!("Upload exceeds {} MB", config.upload_max_size_mb),
+            StatusCode::PAYLOAD_TOO_LARGE,
+        ));
+    }
+    let sha256 = query_param(query, "sha256").map(|s| s.to_lowercase());
+    let pyramid = query_param(query, "pyramid") == Some("true");
+
+    let ds_dir = paths.datasources_dir(ds_type);
+    tokio::fs::create_dir_all(&ds_dir).await?;
+    let upload_id = Uuid::new_v4().to_simple().to_string();
+    let upload = Upload {
+        upload_id: upload_id.clone(),
+        datasource_id: datasource_id.to_string(),
+        ds_type: ds_type.to_string(),
+        name: name.clone(),
+        length,
+        offset: 0,
+        sha256,
+        pyramid,
+        part: ds_dir.join(format!("{name}.{upload_id}.part")),
+        path: ds_dir.join(&name),
+        expires_at: chrono::Utc::now(),
+    };
+    tokio::fs::File::create(&upload.part).await?;
+    tx_uploads
+        .send_async(MessageUploads::Save {
+            upload: upload.clone(),
+        })
+        .await?;
+
+    let location = format!(
+        "{}/{datasource_id}/uploads/{upload_id}",
+        EndPoints::DataSources.as_ref()
+    );
+    let body = json!({
+        "status": StatusCode::CREATED.as_u16(),
+        "upload_id": upload_id,
+        "location": location,
+    })
+    .to_string();
+    let mut response = tus_response(StatusCode::CREATED, Some(&upload), Body::from(body))?;
+    let ct: &str = ContentType::ApplicationJson.into();
+    let headers = response.headers_mut();
+    headers.insert(header::LOCATION, location.parse()?);
+    headers.insert(header::CONTENT_TYPE, ct.parse()?);
+    Ok(response)
+}
+
+async fn append_upload(
+    datasource_id: &str,
+    upload_id: &str,
+    req: Request<Body>,
+    port: u16,
+    ports: Vec<u16>,
+    client_ip: IpAddr,
+    client: Client<HttpConnector>,
+    tx: flume::Sender<MessageDatasource>,
+    tx_uploads: flume::Sender<MessageUploads>,
+    tx_jd: Option<flume::Sender<JobDetail>>,
+) -> Result<Response<Body>, anyhow::Error> {
+    let ct: &str = ContentType::OffsetOctetStream.into();
+    if req
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .map(|v| v.as_bytes())
+        != Some(ct.as_bytes())
+    {
+        return Ok(upload_error_response(
+            format!("Content-Type must be '{ct}'"),
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+        ));
+    }
+    let Some(offset) = header_u64(&req, UPLOAD_OFFSET_HEADER) else {
+        return Ok(upload_error_response(
+            "Header 'Upload-Offset' is required".to_string(),
+            StatusCode::BAD_REQUEST,
+        ));
+    };
+
+    let (mut upload, lock) = match acquire_upload(upload_id, &tx_uploads).await? {
+        Ok((upload, lock)) if upload.datasource_id == datasource_id => (upload, lock),
+        Ok((upload, lock)) => {
+            lock.save(upload).await?;
+            return Ok(upload_not_found(upload_id));
+        }
+        Err(UploadBusy::NotFound) => return Ok(upload_not_found(upload_id)),
+        Err(UploadBusy::Locked) => {
+            return Ok(upload_error_response(
+                format!("Upload '{upload_id}' is in progress"),
+                StatusCode::CONFLICT,
+            ))
+        }
+    };
+    if offset != upload.offset {
+        let response = tus_response(StatusCode::CONFLICT, Some(&upload), Body::empty());
+        lock.save(upload).await?;
+        return response;
+    }
+
+    // received bytes are kept even if the client breaks the connection
+    let written = write_chunks(&mut upload, req.into_body()).await;
+    if upload.offset < upload.length || written.is_err() {
+        let response = match written {
+            Ok(_) => tus_response(StatusCode::NO_CONTENT, Some(&upload), Body::empty()),
+            Err(response) => Ok(response),
+        };
+        lock.save(upload).await?;
+        return response;
+    }
+
+    lock.remove().await?;
+    complete_upload(upload, port, ports, client_ip, client, tx, tx_jd).await
+}
+
+/*
+    Body is appended to the '.part' file, offset of the upload is the size of the written data.
+    Bytes after the offset were written by a dropped request which did not save the upload
+*/
+async fn write_chunks(upload: &mut Upload, mut body: Body) -> Result<(), Response<Body>> {
+    let part = upload.part.clone();
+    let internal_error = |err: std::io::Error| {
+        event!(Level::ERROR, "Error write part of upload {part:?}: {err}");
+        error_response(StatusCode::INTERNAL_SERVER_ERROR)
+    };
+    let mut file = tokio::fs::OpenOptions::new()
+        .append(true)
+        .open(&upload.part)
+        .await
+        .map_err(internal_error)?;
+    file.set_len(upload.offset).await.map_err(internal_error)?;
+
+    let mut result = Ok(());
+    while let Some(chunk) = body.data().await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(err) => {
+                event!(
+                    Level::WARN,
+                    "Upload '{}' interrupted at offset {}: {err}",
+                    upload.upload_id,
+                    upload.offset
+                );
+                result = Err(upload_error_response(
+                    err.to_string(),
+                    StatusCode::BAD_REQUEST,
+                ));
+                break;
+            }
+        };
+        if upload.offset + chunk.len() as u64 > upload.length {
+            result = Err(upload_error_response(
+                "Body exceeds 'Upload-Length'".to_string(),
+                StatusCode::PAYLOAD_TOO_LARGE,
+            ));
+            break;
+        }
+        if let Err(err) = file.write_all(&chunk).await {
+            result = Err(internal_error(err));
+            break;
+        }
+        upload.offset += chunk.len() as u64;
+    }
+    if let Err(err) = file.flush().await {
+        return Err(internal_error(err));
+    }
+    result
+}
+
+async fn file_sha256(path: &Path) -> Result<String, anyhow::Error> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; 1024 * 1024];
+    loop {
+        let n = file.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+async fn complete_upload(
+    upload: Upload,
+    port: u16,
+    ports: Vec<u16>,
+    client_ip: IpAddr,
+    client: Client<HttpConnector>,
+    tx: flume::Sender<MessageDatasource>,
+    tx_jd: Option<flume::Sender<JobDetail>>,
+) -> Result<Response<Body>, anyhow::Error> {
+    let sha256 = file_sha256(&upload.part).await?;
+    if matches!(&upload.sha256, Some(expected) if expected != &sha256) {
+        tokio::fs::remove_file(&upload.part).await?;
+        return Ok(upload_error_response(
+            format!("Checksum SHA-256 of file '{}' does not match", upload.name),
+            StatusCode::UNPROCESSABLE_ENTITY,
+        ));
+    }
+    tokio::fs::rename(&upload.part, &upload.path).await?;
+    event!(
+        Level::INFO,
+        "Uploaded file {:?} of DataSource '{}' ({} bytes)",
+        upload.path,
+        upload.datasource_id,
+        upload.length
+    );
+
+    let datasource_id = upload.datasource_id.as_str();
+    let reload = reload_datasource(datasource_id, port, ports, client_ip, client, tx).await?;
+
+    // pyramids are built by the job queue of the master server
+    let mut pyramid = "not requested";
+    if upload.pyramid && upload.ds_type == "raster" {
+        pyramid = "not scheduled";
+        match tx_jd {
+            Some(tx_jd) if reload.is_success() => {
+                let job_detail = JobDetail {
+                    jt: JobType::Pyramid {
+                        datasource_id: datasource_id.to_string(),
+                    },
+                    name: "Pyramid".to_string(),
+                    scheduled_for: Some(chrono::Utc::now()),
+                    data: Json(json!({"datasource_id": datasource_id})),
+                };
+                match tx_jd.send_async(job_detail).await {
+                    Ok(_) => pyramid = "scheduled",
+                    Err(err) => event!(
+                        Level::ERROR,
+                        "Error send job detail for DataSource {datasource_id}: {err:?}"
+                    ),
+                }
+            }
+            Some(_) => event!(
+                Level::ERROR,
+                "Pyramid of DataSource '{datasource_id}' is not scheduled, reload failed: {reload}"
+            ),
+            None => event!(
+                Level::WARN,
+                "Pyramid of DataSource '{datasource_id}' is not scheduled, job queue runs only on master"
+            ),
+        }
+    }
+
+    let body = json!({
+        "status": StatusCode::OK.as_u16(),
+        "message": format!("File '{}' of DataSource '{datasource_id}' uploaded", upload.name),
+        "files": [{"name": upload.name, "size": upload.length, "sha256": sha256}],
+        "reload_status": reload.as_u16(),
+        "pyramid": pyramid,
+    })
+    .to_string();
+    let mut response = tus_response(StatusCode::OK, Some(&upload), Body::from(body))?;
+    let ct: &str = ContentType::ApplicationJson.into();
+    response
+        .headers_mut()
+        .insert(header::CONTENT_TYPE, ct.parse()?);
+    Ok(response)
+}
@@ -0,0 +1,167 @@
+use hyper::{Body, Response, StatusCode};
+use serde_json::json;
+use tokio::sync::oneshot;
+
+use crate::config::Config;
+use crate::defaults::{
+    BILLING_LIMIT, BILLING_MAX_LIMIT, USAGE_HEATMAP_LIMIT, USAGE_HEATMAP_MAX_LIMIT,
+};
+use crate::handles::error::ApiError;
+use crate::handles::helpers::response_with_body_and_code;
+use crate::tasks::billing::BillingFilter;
+use crate::tasks::datasources::MessageDatasource;
+use crate::tasks::usage::{DataSourceUsage, MessageUsage};
+
+/*
+    Usage of the DataSource since the statistics were enabled:
+    - requests of tiles per zoom
+    - heatmap of the most requested areas as GeoJSON, polygons of parent tiles
+      at zoom 'USAGE_BUCKET_ZOOM' with the number of requests and the weight 0-1
+*/
+pub async fn usage_endpoint(
+    datasource_id: &str,
+    query: Option<&str>,
+    tx: flume::Sender<MessageDatasource>,
+    tx_usage: flume::Sender<MessageUsage>,
+    config: &Config,
+) -> Result<Response<Body>, anyhow::Error> {
+    let (tx_ds, rx_ds) = oneshot::channel();
+    tx.send_async(MessageDatasource::GetDataSource {
+        datasource_id: datasource_id.to_string(),
+        tx_ds,
+    })
+    .await?;
+    if rx_ds.await?.is_none() {
+        return Ok(ApiError::datasource_not_found(datasource_id).into_response());
+    }
+
+    let limit = query
+        .and_then(|q| {
+            q.split('&')
+                .filter_map(|pair| pair.split_once('='))
+                .find(|(k, _)| *k == "limit")
+                .map(|(_, v)| v)
+        })
+        .and_then(|v| v.parse::<u64>().ok())
+        .unwrap_or(USAGE_HEATMAP_LIMIT)
+        .min(USAGE_HEATMAP_MAX_LIMIT);
+
+    let (tx_result, rx_result) = oneshot::channel();
+    tx_usage
+        .send_async(MessageUsage::GetUsage {
+            datasource_id: datasource_id.to_string(),
+            limit,
+            tx_usage: tx_result,
+        })
+        .await?;
+    let usage: DataSourceUsage = rx_result.await??;
+
+    let max_hits = usage.buckets.iter().map(|b| b.hits).max().unwrap_or(0);
+    // buckets of grids in unsupported CRS have no geometry in degrees
+    let features: Vec<serde_json::Value> = usage
+        .buckets
+        .iter()
+        .filter_map(|b| {
+            let grid = config.tile_grids.get(&b.tile_grid)?;
+            let [west, south, east, north] = grid.tile_bounds(b.z, b.x, b.y)?;
+            Some(json!({
+                "type": "Feature",
+                "geometry": {
+                    "type": "Polygon",
+                    "coordinates": [[
+                        [west, south],
+                        [east, south],
+                        [east, north],
+                        [west, north],
+                        [west, south]
+                    ]]
+                },
+                "properties": {
+                    "tile_grid": b.tile_grid,
+                    "z": b.z,
+                    "x": b.x,
+                    "y": b.y,
+                    "hits": b.hits,
+                    "weight": b.hits as f64 / max_hits.max(1) as f64
+                }
+            }))
+        })
+        .collect();
+
+    let body = json!({
+        "datasource_id": datasource_id,
+        "hits": usage.zooms.values().sum::<u64>(),
+        "zooms": usage.zooms,
+        "heatmap": {
+            "type": "FeatureCollection",
+            "features": features
+        }
+    })
+    .to_string();
+    Ok(response_with_body_and_code(body, StatusCode::OK))
+}
+
+/*
+    GET /api/usage?api_key=0123456789abcdef&datasource_id=roads&since=2024-09-01&until=2024-09-30&limit=100
+    returns tiles and bytes served per API key, DataSource and day matching all given filters
+    with their totals, the latest days go first
+*/
+pub async fn billing_endpoint(
+    query: Option<&str>,
+    tx_usage: flume::Sender<MessageUsage>,
+    config: &Config,
+) -> Result<Response<Body>, anyhow::Error> {
+    if !config.billing_enabled {
+        return Ok(
+            ApiError::new(StatusCode::NOT_FOUND, "Billing of tiles is disabled").into_response(),
+        );
+    }
+    let param = |name: &str| {
+        query
+            .and_then(|q| {
+                q.split('&')
+                    .filter_map(|pair| pair.split_once('='))
+                    .find(|(k, _)| *k == name)
+                    .and_then(|(_, v)| urlencoding::decode(v).ok())
+                    .map(|v| v.to_string())
+            })
+            .filter(|v| !v.is_empty())
+    };
+    for name in ["since", "until"] {
+        if let Some(day) = param(name) {
+            if chrono::NaiveDate::parse_from_str(&day, "%Y-%m-%d").is_err() {
+                return Ok(ApiError::new(
+                    StatusCode::BAD_REQUEST,
+                    format!("Parameter '{name}' must be a day 'YYYY-MM-DD', got '{day}'"),
+                )
+                .into_response());
+            }
+        }
+    }
+    let filter = BillingFilter {
+        api_key: param("api_key"),
+        datasource_id: param("datasource_id"),
+        since: param("since"),
+        until: param("until"),
+        limit: param("limit")
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(BILLING_LIMIT)
+            .min(BILLING_MAX_LIMIT),
+    };
+
+    let (tx_counters, rx_counters) = oneshot::channel();
+    tx_usage
+        .send_async(MessageUsage::GetBilling {
+            filter,
+            tx_counters,
+        })
+        .await?;
+    let counters = rx_counters.await??;
+    let body = json!({
+        "tiles": counters.iter().map(|c| c.tiles).sum::<u64>(),
+        "bytes": counters.iter().map(|c| c.bytes).sum::<u64>(),
+        "counters": counters,
+    })
+    .to_string();
+    Ok(response_with_body_and_code(body, StatusCode::OK))
+}
@@ -0,0 +1,631 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use hyper::body::Bytes;
+use hyper::{Body, Client, Method, Request, Response, StatusCode};
+use serde::Serialize;
+use serde_json::{json, Value};
+use tiler_core::compression::TILE_COMPRESSION_ZSTD;
+
+use crate::config::{parse_missing_tile, Config};
+use crate::db::registry::SharedRegistry;
+use crate::defaults::{MAXZOOM, PROBE_TIMEOUT_SECS};
+use crate::geofence::parse_geofence;
+use crate::grid::TileGrid;
+use crate::handles::error::ApiError;
+use crate::handles::helpers::response_with_body_and_code;
+use crate::paths::Paths;
+use crate::structs::EndPoints;
+use crate::webhooks::is_webhook_url;
+
+const STORE_TYPES: [&str; 4] = ["internal", "tiles", "mbtiles", "tilejson"];
+const LAYER_TYPES: [&str; 3] = ["point", "line", "polygon"];
+const ENCODINGS: [&str; 4] = ["mapbox", "terrarium", "f32", "none"];
+const RESAMPLINGS: [&str; 10] = [
+    "average",
+    "antialias",
+    "nearest",
+    "bilinear",
+    "cubic",
+    "cubicspline",
+    "lanczos",
+    "min",
+    "max",
+    "med",
+];
+const RASTER_EXTENSIONS: [&str; 2] = ["tif", "tiff"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum CheckStatus {
+    Ok,
+    Warning,
+    Error,
+    Skipped,
+}
+
+#[derive(Debug, Serialize)]
+struct Check {
+    name: &'static str,
+    status: CheckStatus,
+    messages: Vec<String>,
+}
+
+impl Check {
+    fn new(name: &'static str) -> Self {
+        Check {
+            name,
+            status: CheckStatus::Ok,
+            messages: Vec::new(),
+        }
+    }
+
+    fn skipped(name: &'static str, message: &str) -> Self {
+        Check {
+            name,
+            status: CheckStatus::Skipped,
+            messages: vec![message.to_string()],
+        }
+    }
+
+    fn error(&mut self, message: String) {
+        self.status = CheckStatus::Error;
+        self.messages.push(message);
+    }
+
+    fn warning(&mut self, message: String) {
+        if self.status == CheckStatus::Ok {
+            self.status = CheckStatus::Warning;
+        }
+        self.messages.push(message);
+    }
+}
+
+/*
+    Dry run of the registration of the DataSource: the DataSource JSON from the body
+    (or of the registered DataSource when the body is empty) is checked without saving:
+    - schema of the JSON by 'type' of the DataSource and its 'dataStore'
+    - zoom ranges of the DataSource, its pyramid and layers
+    - bounds and center
+    - files of rasters in 'data' and folders of mosaics in 'data/mosaics'
+    - remote server 'host:port' and replicas answer the health check
+*/
+pub async fn validate_endpoint(
+    paths: &Paths,
+    datasource_id: &str,
+    b_bytes: &Bytes,
+    registry: &SharedRegistry,
+    config: &Config,
+) -> Result<Response<Body>, anyhow::Error> {
+    let ds: Value = if b_bytes.is_empty() {
+        match registry.datasource(datasource_id).await? {
+            Some(ds) => ds.data.0,
+            None => {
+                return Ok(ApiError::datasource_not_found(datasource_id).into_response());
+            }
+        }
+    } else {
+        match serde_json::from_slice(b_bytes) {
+            Ok(ds) => ds,
+            Err(err) => {
+                return Ok(ApiError::new(
+                    StatusCode::BAD_REQUEST,
+                    format!("DataSource JSON is invalid: {err}"),
+                )
+                .into_response());
+            }
+        }
+    };
+
+    let checks = vec![
+        check_schema(&ds, datasource_id, config),
+        check_zooms(&ds),
+        check_bounds(&ds),
+        check_files(paths, &ds).await,
+        check_remote(&ds).await,
+    ];
+    let valid = checks.iter().all(|c| c.status != CheckStatus::Error);
+
+    let body = json!({
+        "datasource_id": datasource_id,
+        "valid": valid,
+        "checks": checks,
+    })
+    .to_string();
+    Ok(response_with_body_and_code(body, StatusCode::OK))
+}
+
+fn str_field<'a>(value: &'a Value, key: &str) -> Option<&'a str> {
+    value.get(key).and_then(|v| v.as_str())
+}
+
+fn check_schema(ds: &Value, datasource_id: &str, config: &Config) -> Check {
+    let mut check = Check::new("schema");
+    if !ds.is_object() {
+        check.error("DataSource must be a JSON object".to_string());
+        return check;
+    }
+
+    match ds.get("id") {
+        None => {}
+        Some(Value::String(id)) if id == datasource_id => {}
+        Some(id) => check.error(format!(
+            "'id' {id} does not match DataSource '{datasource_id}' of the path"
+        )),
+    }
+
+    let data_type = str_field(ds, "type");
+    if !matches!(data_type, Some("raster") | Some("vector")) {
+        check.error(format!(
+            "'type' must have one of the values [raster, vector], but got {:?}",
+            ds.get("type")
+        ));
+    }
+
+    let Some(data_store) = ds.get("dataStore").filter(|d| d.is_object()) else {
+        check.error("'dataStore' must be a JSON object".to_string());
+        return check;
+    };
+    if str_field(data_store, "type") != data_type {
+        check.error(format!(
+            "'dataStore.type' must be equal to 'type', but got {:?}",
+            data_store.get("type")
+        ));
+    }
+    let store = str_field(data_store, "store");
+    match store {
+        Some("mbtiles") | Some("tilejson") => {
+            check.error(format!(
+                "Store type '{}' is not implemented",
+                store.unwrap_or("")
+            ));
+        }
+        Some(s) if STORE_TYPES.contains(&s) => {}
+        _ => check.error(format!(
+            "'dataStore.store' must have one of the values {STORE_TYPES:?}, but got {:?}",
+            data_store.get("store")
+        )),
+    }
+
+    if store == Some("tiles") {
+        let tiles_valid = data_store
+            .get("tiles")
+            .and_then(|t| t.as_array())
+            .is_some_and(|t| !t.is_empty() && t.iter().all(|url| url.is_string()));
+        if !tiles_valid {
+            check.error("'dataStore.tiles' must be a non-empty array of URLs".to_string());
+        }
+    }
+
+    match (data_type, store) {
+        (Some("raster"), Some("internal")) => check_raster_store(ds, data_store, &mut check),
+        (Some("vector"), Some("internal")) => check_vector_layers(ds, &mut check),
+        _ => {}
+    }
+
+    if data_type == Some("raster") {
+        if let Some(encoding) = ds.get("encoding") {
+            if !encoding.as_str().is_some_and(|e| ENCODINGS.contains(&e)) {
+                check.error(format!(
+                    "'encoding' must have one of the values {ENCODINGS:?}, but got {encoding}"
+                ));
+            }
+        }
+        if let Some(resampling) = ds.get("pyramidSettings").and_then(|p| p.get("resampling")) {
+            if !resampling
+                .as_str()
+                .is_some_and(|r| RESAMPLINGS.contains(&r))
+            {
+                check.error(format!(
+                    "'pyramidSettings.resampling' must have one of the values {RESAMPLINGS:?}, but got {resampling}"
+                ));
+            }
+        }
+    }
+
+    for key in [
+        "mosaics",
+        "mbtiles",
+        "use_cache_only",
+        "compress_tiles",
+        "allow_missing_origin",
+        "negative_cache",
+    ] {
+        if ds.get(key).is_some_and(|v| !v.is_boolean() && !v.is_null()) {
+            check.error(format!("'{key}' must be a boolean"));
+        }
+    }
+    if let Some(origins) = ds.get("allowed_origins") {
+        let valid = origins.as_array().is_some_and(|origins| {
+            origins
+                .iter()
+                .all(|o| o.as_str().is_some_and(|o| o.contains("://")))
+        });
+        if !valid {
+            check.error(
+                "'allowed_origins' must be an array of origins 'scheme://host[:port]'".to_string(),
+            );
+        }
+    }
+    if let Some(geofence) = ds.get("geofence") {
+        if let Err(err) = parse_geofence(geofence) {
+            check.error(format!(
+                "'geofence' must be GeoJSON polygons in degrees: {err}"
+            ));
+        }
+    }
+    if ds
+        .get("missing_tile")
+        .is_some_and(|v| parse_missing_tile(v).is_none())
+    {
+        check.error("'missing_tile' must be 'no_content', 'not_found' or 'blank'".to_string());
+    }
+    if let Some(webhooks) = ds.get("webhooks") {
+        let valid = webhooks.as_array().is_some_and(|urls| {
+            urls.iter()
+                .all(|url| url.as_str().is_some_and(is_webhook_url))
+        });
+        if !valid {
+            check.error("'webhooks' must be an array of HTTP(S) URLs".to_string());
+        }
+    }
+    if ds.get("max_cache_mb").is_some_and(|v| v.as_u64().is_none()) {
+        check.error("'max_cache_mb' must be a positive integer".to_string());
+    }
+    if ds
+        .get("max_queue_depth")
+        .is_some_and(|v| v.as_u64().is_none())
+    {
+        check.error("'max_queue_depth' must be a non-negative integer".to_string());
+    }
+    if let Some(compression) = ds.get("storage_compression") {
+        if !compression
+            .as_str()
+            .is_some_and(|c| c.eq_ignore_ascii_case(TILE_COMPRESSION_ZSTD) || c == "none")
+        {
+            check.error("'storage_compression' must be 'zstd' or 'none'".to_string());
+        } else if ds.get("mbtiles").and_then(|v| v.as_bool()) != Some(true) {
+            check.warning(
+                "'storage_compression' has effect only for DataSources with 'mbtiles'".to_string(),
+            );
+        }
+    }
+
+    if let Some(tile_grid) = ds.get("tile_grid") {
+        let grid = match tile_grid.as_str() {
+            Some(name) if config.tile_grids.get(name).is_some() => Ok(()),
+            Some(name) => Err(format!("Tile grid '{name}' is not registered")),
+            None => TileGrid::from_json(tile_grid)
+                .map(|_| ())
+                .map_err(|err| format!("'tile_grid' is invalid: {err}")),
+        };
+        if let Err(message) = grid {
+            check.error(message);
+        }
+    }
+
+    check
+}
+
+fn check_raster_store(ds: &Value, data_store: &Value, check: &mut Check) {
+    let file = str_field(data_store, "file");
+    let folder = str_field(data_store, "folder");
+    let mosaics = ds.get("mosaics").and_then(|m| m.as_bool()).unwrap_or(false);
+    match (file, folder) {
+        (Some(_), Some(_)) => check.error(
+            "'dataStore.file' and 'dataStore.folder' cannot be specified at the same time"
+                .to_string(),
+        ),
+        (None, None) => check
+            .error("One of 'dataStore.file' or 'dataStore.folder' must be specified".to_string()),
+        (Some(_), None) if mosaics => {
+            check.error("'dataStore.folder' must be specified for mosaics".to_string())
+        }
+        (None, Some(_)) if !mosaics => {
+            check.error("'dataStore.file' must be specified for a single raster".to_string())
+        }
+        _ => {}
+    }
+    if let Some(file) = file {
+        let ext = PathBuf::from(file)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_lowercase());
+        if !ext.is_some_and(|ext| RASTER_EXTENSIONS.contains(&ext.as_str())) {
+            check.error(format!(
+                "'dataStore.file' must have one of the extensions {RASTER_EXTENSIONS:?}"
+            ));
+        }
+    }
+}
+
+fn check_vector_layers(ds: &Value, check: &mut Check) {
+    let Some(layers) = ds.get("layers").and_then(|l| l.as_array()) else {
+        check.error("'layers' must be an array for the internal vector store".to_string());
+        return;
+    };
+    if layers.is_empty() {
+        check.warning("'layers' is empty, tiles of the DataSource have no data".to_string());
+    }
+    for (i, layer) in layers.iter().enumerate() {
+        let id = str_field(layer, "id").map(|id| id.to_string());
+        if id.is_none() {
+            check.error(format!("'layers[{i}].id' must be a string"));
+        }
+        let name = id.unwrap_or(i.to_string());
+        if !str_field(layer, "type").is_some_and(|t| LAYER_TYPES.contains(&t)) {
+            check.error(format!(
+                "Layer '{name}': 'type' must have one of the values {LAYER_TYPES:?}"
+            ));
+        }
+        for key in ["storeLayer", "geomField"] {
+            if str_field(layer, key).is_none() {
+                check.error(format!("Layer '{name}': '{key}' must be a string"));
+            }
+        }
+        if let Some(fields) = layer.get("fields") {
+            let fields_valid = fields
+                .as_array()
+                .is_some_and(|f| f.iter().all(|field| str_field(field, "name").is_some()));
+            if !fields_valid {
+                check.error(format!(
+                    "Layer '{name}': 'fields' must be an array of objects with 'name'"
+                ));
+            }
+        }
+    }
+}
+
+// Zoom 'key' of the object: None when absent, Err when it is not a zoom level
+fn zoom_field(value: &Value, key: &str) -> Result<Option<u8>, String> {
+    match value.get(key) {
+        None | Some(Value::Null) => Ok(None),
+        Some(z) => z
+            .as_u64()
+            .filter(|z| *z <= MAXZOOM as u64)
+            .map(|z| Some(z as u8))
+            .ok_or(format!(
+                "'{key}' must be an integer from 0 to {MAXZOOM}, but got {z}"
+            )),
+    }
+}
+
+fn check_zoom_range(value: &Value, prefix: &str, check: &mut Check) -> (Option<u8>, Option<u8>) {
+    let mut zoom = |key: &str| match zoom_field(value, key) {
+        Ok(z) => z,
+        Err(message) => {
+            check.error(format!("{prefix}{message}"));
+            None
+        }
+    };
+    let minzoom = zoom("minzoom");
+    let maxzoom = zoom("maxzoom");
+    if let (Some(minzoom), Some(maxzoom)) = (minzoom, maxzoom) {
+        if minzoom > maxzoom {
+            check.error(format!(
+                "{prefix}'minzoom' {minzoom} is greater than 'maxzoom' {maxzoom}"
+            ));
+        }
+    }
+    (minzoom, maxzoom)
+}
+
+fn check_zooms(ds: &Value) -> Check {
+    let mut check = Check::new("zooms");
+    let (minzoom, maxzoom) = check_zoom_range(ds, "", &mut check);
+    let minzoom = minzoom.unwrap_or(0);
+    let maxzoom = maxzoom.unwrap_or(MAXZOOM);
+
+    if let Some(pyramid) = ds.get("pyramidSettings") {
+        let (p_minzoom, p_maxzoom) = check_zoom_range(pyramid, "pyramidSettings: ", &mut check);
+        if p_minzoom.is_some_and(|z| z < minzoom) || p_maxzoom.is_some_and(|z| z > maxzoom) {
+            check.warning(format!(
+                "pyramidSettings: zooms are out of the zooms {minzoom}-{maxzoom} of the DataSource"
+            ));
+        }
+    }
+
+    if let Some(layers) = ds.get("layers").and_then(|l| l.as_array()) {
+        for (i, layer) in layers.iter().enumerate() {
+            let name = str_field(layer, "id")
+                .map(|id| id.to_string())
+                .unwrap_or(i.to_string());
+            let (l_minzoom, l_maxzoom) =
+                check_zoom_range(layer, &format!("Layer '{name}': "), &mut check);
+            if l_minzoom.is_some_and(|z| z > maxzoom) || l_maxzoom.is_some_and(|z| z < minzoom) {
+                check.warning(format!(
+                    "Layer '{name}': zooms do not intersect the zooms {minzoom}-{maxzoom} of the DataSource"
+                ));
+            }
+        }
+    }
+    check
+}
+
+fn check_bounds(ds: &Value) -> Check {
+    let mut check = Check::new("bounds");
+    let bounds = match ds.get("bounds") {
+        None | Some(Value::Null) => None,
+        Some(value) => {
+            let bounds: Vec<f64> = if let Some(array) = value.as_array() {
+                array.iter().filter_map(|v| v.as_f64()).collect()
+            } else {
+                ["lng_w", "lat_s", "lng_e", "lat_n"]
+                    .iter()
+                    .filter_map(|key| value.get(key).and_then(|v| v.as_f64()))
+                    .collect()
+            };
+            match bounds.as_slice() {
+                [west, south, east, north] => Some([*west, *south, *east, *north]),
+                _ => {
+                    check.error(
+                        "'bounds' must have numbers 'lng_w', 'lat_s', 'lng_e', 'lat_n'".to_string(),
+                    );
+                    None
+                }
+            }
+        }
+    };
+
+    if let Some([west, south, east, north]) = bounds {
+        if ![west, east]
+            .iter()
+            .all(|lng| (-180.0..=180.0).contains(lng))
+        {
+            check.error("Longitudes of 'bounds' must be from -180 to 180".to_string());
+        }
+        if ![south, north]
+            .iter()
+            .all(|lat| (-90.0..=90.0).contains(lat))
+        {
+            check.error("Latitudes of 'bounds' must be from -90 to 90".to_string());
+        }
+        if south >= north {
+            check.error(format!(
+                "'lat_s' {south} of 'bounds' must be less than 'lat_n' {north}"
+            ));
+        }
+        if west >= east {
+            check.warning(format!(
+                "'lng_w' {west} of 'bounds' is not less than 'lng_e' {east}, bounds cross the antimeridian"
+            ));
+        }
+    }
+
+    if let Some(center) = ds.get("center").filter(|c| !c.is_null()) {
+        let center: Vec<f64> = center
+            .as_array()
+            .map(|c| c.iter().filter_map(|v| v.as_f64()).collect())
+            .unwrap_or_default();
+        match center.as_slice() {
+            [lng, lat] | [lng, lat, _] => {
+                if let Some([west, south, east, north]) = bounds {
+                    let in_lng = if west < east {
+                        (west..=east).contains(lng)
+                    } else {
+                        *lng >= west || *lng <= east
+                    };
+                    if !in_lng || !(south..=north).contains(lat) {
+                        check.warning(format!("'center' [{lng}, {lat}] is out of 'bounds'"));
+                    }
+                }
+            }
+            _ => {
+                check.error("'center' must be an array [lng, lat] or [lng, lat, zoom]".to_string())
+            }
+        }
+    }
+    check
+}
+
+fn is_raster_asset(path: &std::path::Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| RASTER_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+}
+
+// Rasters of the internal store are read by workers from 'data/{file}' and 'data/mosaics/{folder}'
+async fn check_files(paths: &Paths, ds: &Value) -> Check {
+    let Some(data_store) = ds
+        .get("dataStore")
+        .filter(|_| str_field(ds, "type") == Some("raster"))
+        .filter(|d| str_field(d, "store") == Some("internal"))
+    else {
+        return Check::skipped("files", "DataSource has no source files");
+    };
+
+    let mut check = Check::new("files");
+    if let Some(file) = str_field(data_store, "file") {
+        let path = paths.data.join(file);
+        match tokio::fs::metadata(&path).await {
+            Ok(md) if md.is_file() => {}
+            _ => check.error(format!("Raster file 'data/{file}' not found")),
+        }
+    }
+    if let Some(folder) = str_field(data_store, "folder") {
+        let path = paths.mosaics_dir().join(folder);
+        match tokio::fs::read_dir(&path).await {
+            Ok(mut entries) => {
+                let mut assets = 0;
+                while let Ok(Some(entry)) = entries.next_entry().await {
+                    if is_raster_asset(&entry.path()) {
+                        assets += 1;
+                    }
+                }
+                if assets == 0 {
+                    check.error(format!(
+                        "Folder of mosaics 'data/mosaics/{folder}' has no rasters"
+                    ));
+                }
+            }
+            Err(_) => check.error(format!(
+                "Folder of mosaics 'data/mosaics/{folder}' not found"
+            )),
+        }
+    }
+    check
+}
+
+// Remote server and replicas of the DataSource answer '/healthz' in 'PROBE_TIMEOUT_SECS'
+async fn check_remote(ds: &Value) -> Check {
+    let host = str_field(ds, "host");
+    let port = ds.get("port").filter(|p| !p.is_null());
+    if host.is_none() && port.is_none() {
+        return Check::skipped("remote", "DataSource is served by this server");
+    }
+
+    let mut check = Check::new("remote");
+    let (Some(host), Some(port)) = (host, port.and_then(|p| p.as_u64())) else {
+        check.error("'host' and 'port' must be specified together".to_string());
+        return check;
+    };
+    if !(1..=u16::MAX as u64).contains(&port) {
+        check.error(format!("'port' {port} must be from 1 to {}", u16::MAX));
+        return check;
+    }
+
+    let mut locations = vec![format!("{host}:{port}")];
+    if let Some(replicas) = ds.get("replicas") {
+        match replicas.as_array() {
+            Some(replicas) => {
+                for replica in replicas {
+                    match replica.as_str() {
+                        Some(r)
+                            if r.rsplit_once(':')
+                                .is_some_and(|(_, p)| p.parse::<u16>().is_ok()) =>
+                        {
+                            locations.push(r.to_string())
+                        }
+                        _ => check.error(format!("Replica {replica} must be 'host:port'")),
+                    }
+                }
+            }
+            None => check.error("'replicas' must be an array of 'host:port'".to_string()),
+        }
+    }
+
+    let client = Client::new();
+    let timeout = Duration::from_secs(PROBE_TIMEOUT_SECS);
+    for location in locations {
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri(format!("http://{location}{}", EndPoints::Healthz.as_ref()))
+            .body(Body::empty());
+        let Ok(request) = request else {
+            check.error(format!("Address '{location}' is invalid"));
+            continue;
+        };
+        match tokio::time::timeout(timeout, client.request(request)).await {
+            Ok(Ok(response)) if response.status() == StatusCode::OK => {}
+            Ok(Ok(response)) => check.error(format!(
+                "Server '{location}' responded {} to the health check",
+                response.status()
+            )),
+            Ok(Err(err)) => check.error(format!("Server '{location}' is unreachable: {err}")),
+            Err(_) => check.error(format!(
+                "Server '{location}' did not respond in {PROBE_TIMEOUT_SECS} seconds"
+            )),
+        }
+    }
+    check
+}
@@ -0,0 +1,202 @@
+use std::fmt;
+
+use hyper::{
+    http::header::{HeaderMap, HeaderValue},
+    Body, Response, StatusCode,
+};
+use serde_json::{json, Value};
+use strum_macros::{AsRefStr, Display, EnumIter, IntoStaticStr};
+use uuid::Uuid;
+
+use super::helpers::response_with_body_and_code;
+use crate::defaults::{REQUEST_ID_HEADER, REQUEST_ID_MAX_LEN};
+
+tokio::task_local! {
+    // id of the request handled by the task, added to bodies of errors
+    pub static REQUEST_ID: String;
+}
+
+/*
+    Stable codes of errors of the API: clients check the code, the message is for humans
+    and may change. Codes of HTTP statuses are used when there is no specific code
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Display, IntoStaticStr, AsRefStr, EnumIter)]
+pub enum ErrorCode {
+    #[strum(serialize = "bad_request")]
+    BadRequest,
+    #[strum(serialize = "unauthorized")]
+    Unauthorized,
+    #[strum(serialize = "payment_required")]
+    PaymentRequired,
+    #[strum(serialize = "forbidden")]
+    Forbidden,
+    #[strum(serialize = "not_found")]
+    NotFound,
+    #[strum(serialize = "method_not_allowed")]
+    MethodNotAllowed,
+    #[strum(serialize = "conflict")]
+    Conflict,
+    #[strum(serialize = "gone")]
+    Gone,
+    #[strum(serialize = "payload_too_large")]
+    PayloadTooLarge,
+    #[strum(serialize = "unprocessable_entity")]
+    UnprocessableEntity,
+    #[strum(serialize = "too_many_requests")]
+    TooManyRequests,
+    #[strum(serialize = "internal_error")]
+    InternalError,
+    #[strum(serialize = "bad_gateway")]
+    BadGateway,
+    #[strum(serialize = "service_unavailable")]
+    ServiceUnavailable,
+    #[strum(serialize = "gateway_timeout")]
+    GatewayTimeout,
+    #[strum(serialize = "insufficient_storage")]
+    InsufficientStorage,
+    #[strum(serialize = "datasource_not_found")]
+    DataSourceNotFound,
+    #[strum(serialize = "datasource_deleted")]
+    DataSourceDeleted,
+    #[strum(serialize = "workers_not_ready")]
+    WorkersNotReady,
+    #[strum(serialize = "worker_timeout")]
+    WorkerTimeout,
+    #[strum(serialize = "invalid_worker_tile")]
+    InvalidWorkerTile,
+    #[strum(serialize = "upstream_rate_limited")]
+    UpstreamRateLimited,
+    #[strum(serialize = "unsupported_api_version")]
+    UnsupportedApiVersion,
+    #[strum(serialize = "workers_required")]
+    WorkersRequired,
+    #[strum(serialize = "confirmation_required")]
+    ConfirmationRequired,
+    #[strum(serialize = "origin_not_allowed")]
+    OriginNotAllowed,
+    #[strum(serialize = "outside_geofence")]
+    OutsideGeofence,
+    #[strum(serialize = "quota_exceeded")]
+    QuotaExceeded,
+    #[strum(serialize = "queue_full")]
+    QueueFull,
+    #[strum(serialize = "routing_loop")]
+    RoutingLoop,
+}
+
+impl ErrorCode {
+    pub fn from_status(status: StatusCode) -> Self {
+        match status {
+            StatusCode::BAD_REQUEST => ErrorCode::BadRequest,
+            StatusCode::UNAUTHORIZED => ErrorCode::Unauthorized,
+            StatusCode::PAYMENT_REQUIRED => ErrorCode::PaymentRequired,
+            StatusCode::FORBIDDEN => ErrorCode::Forbidden,
+            StatusCode::NOT_FOUND => ErrorCode::NotFound,
+            StatusCode::METHOD_NOT_ALLOWED => ErrorCode::MethodNotAllowed,
+            StatusCode::CONFLICT => ErrorCode::Conflict,
+            StatusCode::GONE => ErrorCode::Gone,
+            StatusCode::PAYLOAD_TOO_LARGE => ErrorCode::PayloadTooLarge,
+            StatusCode::UNPROCESSABLE_ENTITY => ErrorCode::UnprocessableEntity,
+            StatusCode::TOO_MANY_REQUESTS => ErrorCode::TooManyRequests,
+            StatusCode::BAD_GATEWAY => ErrorCode::BadGateway,
+            StatusCode::SERVICE_UNAVAILABLE => ErrorCode::ServiceUnavailable,
+            StatusCode::GATEWAY_TIMEOUT => ErrorCode::GatewayTimeout,
+            StatusCode::INSUFFICIENT_STORAGE => ErrorCode::InsufficientStorage,
+            status if status.is_client_error() => ErrorCode::BadRequest,
+            _ => ErrorCode::InternalError,
+        }
+    }
+}
+
+/*
+    Error of the API serialized uniformly:
+    {"status": 404, "code": "datasource_not_found", "message": "...", "details": {...}, "request_id": "..."}
+*/
+#[derive(Debug, Clone)]
+pub struct ApiError {
+    pub status: StatusCode,
+    pub code: ErrorCode,
+    pub message: String,
+    pub details: Option<Value>,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        ApiError {
+            status,
+            code: ErrorCode::from_status(status),
+            message: message.into(),
+            details: None,
+        }
+    }
+
+    // Error without a specific message: the reason phrase of the status
+    pub fn from_status(status: StatusCode) -> Self {
+        ApiError::new(status, status.canonical_reason().unwrap_or("Error"))
+    }
+
+    pub fn datasource_not_found(datasource_id: &str) -> Self {
+        ApiError::new(
+            StatusCode::NOT_FOUND,
+            format!("DataSource '{datasource_id}' not found"),
+        )
+        .with_code(ErrorCode::DataSourceNotFound)
+    }
+
+    pub fn with_code(mut self, code: ErrorCode) -> Self {
+        self.code = code;
+        self
+    }
+
+    pub fn with_details(mut self, details: Value) -> Self {
+        self.details = Some(details);
+        self
+    }
+
+    pub fn to_json(&self) -> Value {
+        let mut body = json!({
+            "status": self.status.as_u16(),
+            "code": self.code.as_ref(),
+            "message": self.message
+        });
+        if let Some(details) = self.details.as_ref() {
+            body["details"] = details.clone();
+        }
+        if let Ok(request_id) = REQUEST_ID.try_with(|id| id.clone()) {
+            body["request_id"] = Value::String(request_id);
+        }
+        body
+    }
+
+    pub fn into_response(self) -> Response<Body> {
+        response_with_body_and_code(self.to_json().to_string(), self.status)
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({}): {}", self.status, self.code, self.message)
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+// ApiError returned through anyhow keeps its status and code, other errors are internal
+impl From<anyhow::Error> for ApiError {
+    fn from(err: anyhow::Error) -> Self {
+        match err.downcast::<ApiError>() {
+            Ok(api_error) => api_error,
+            Err(err) => ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, err.to_string()),
+        }
+    }
+}
+
+// Id of the request: header 'x-request-id' of the client (proxy) or a new UUID
+pub fn request_id(headers: &HeaderMap<HeaderValue>) -> String {
+    headers
+        .get(REQUEST_ID_HEADER)
+        .and_then(|id| id.to_str().ok())
+        .filter(|id| !id.is_empty() && id.len() <= REQUEST_ID_MAX_LEN)
+        .map(|id| id.to_string())
+        .unwrap_or_else(|| Uuid::new_v4().to_string())
+}
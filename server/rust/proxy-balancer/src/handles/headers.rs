@@ -0,0 +1,87 @@
+use hyper::http::header::{self, HeaderMap, HeaderName, HeaderValue};
+use hyper::http::response::Builder;
+use hyper::{Body, Response};
+
+use crate::structs::ContentType;
+
+// Policies of 'Cache-Control' of responses of the balancer
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CacheControl {
+    // tiles and their empty responses: clients revalidate every time
+    Revalidate,
+    // streams and metrics: caches never answer without the server
+    NoCache,
+    // uploads: never stored by caches
+    NoStore,
+}
+
+impl CacheControl {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CacheControl::Revalidate => "max-age=0",
+            CacheControl::NoCache => "no-cache",
+            CacheControl::NoStore => "no-store",
+        }
+    }
+}
+
+/*
+    Standard headers of responses of the balancer, one method per header, so responses
+    of endpoints differ only by explicit calls. CORS headers and overrides of
+    'server.response_headers' are applied to every response in 'handle'
+*/
+pub trait ResponseBuilderExt {
+    fn content_type(self, content_type: &str) -> Self;
+    fn json(self) -> Self;
+    fn content_length(self, length: u64) -> Self;
+    fn cache_control(self, policy: CacheControl) -> Self;
+    fn gzip_encoded(self) -> Self;
+}
+
+impl ResponseBuilderExt for Builder {
+    fn content_type(self, content_type: &str) -> Self {
+        self.header(header::CONTENT_TYPE, content_type)
+    }
+
+    fn json(self) -> Self {
+        self.content_type(ContentType::ApplicationJson.as_ref())
+    }
+
+    fn content_length(self, length: u64) -> Self {
+        self.header(header::CONTENT_LENGTH, length)
+    }
+
+    fn cache_control(self, policy: CacheControl) -> Self {
+        self.header(header::CACHE_CONTROL, policy.as_str())
+    }
+
+    fn gzip_encoded(self) -> Self {
+        self.header(header::CONTENT_ENCODING, "gzip")
+    }
+}
+
+// Headers of caches of the worker response replace defaults of the balancer
+pub fn copy_caching_headers(from: &HeaderMap<HeaderValue>, to: &mut HeaderMap<HeaderValue>) {
+    for name in [header::CACHE_CONTROL, header::ETAG, header::EXPIRES] {
+        if let Some(value) = from.get(&name) {
+            to.insert(name, value.clone());
+        }
+    }
+}
+
+// Header of 'server.response_headers' of the endpoint, None - the header is removed
+pub type HeaderOverride = (HeaderName, Option<HeaderValue>);
+
+pub fn apply_header_overrides(response: &mut Response<Body>, overrides: &[HeaderOverride]) {
+    let headers = response.headers_mut();
+    for (name, value) in overrides {
+        match value {
+            Some(value) => {
+                headers.insert(name.clone(), value.clone());
+            }
+            None => {
+                headers.remove(name);
+            }
+        }
+    }
+}
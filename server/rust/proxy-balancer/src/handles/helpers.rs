@@ -1,8 +1,12 @@
+use std::collections::HashSet;
 use std::convert::Infallible;
+use std::io::Read;
 use std::time::Duration;
 
 use anyhow::anyhow;
+use flate2::read::GzDecoder;
 use hyper::{
+    body::{Bytes, HttpBody},
     client::HttpConnector,
     http::header::{self, HeaderMap, HeaderValue},
     Body, Client, Method, Request, Response, StatusCode, Uri,
@@ -11,25 +15,327 @@ use serde_json::json;
 use tokio::sync::oneshot;
 use tracing::{event, Level};
 
-use crate::structs::ContentType;
+use crate::cluster::auth::sign_request;
+use crate::config::{Config, CorsConfig};
+use crate::defaults::{NOT_READY_RETRY_AFTER_SECS, QUEUE_DEPTH_HEADER};
+use crate::handles::error::{ApiError, ErrorCode};
+use crate::handles::headers::{CacheControl, ResponseBuilderExt};
+use crate::handles::router::Route;
+use crate::origins::{request_origin, AllowedOrigins};
+use crate::overzoom::mvt::map_layers;
+use crate::postgis::gzip_tile;
+use crate::structs::{ContentType, EndPoints, MissingTile};
+use crate::tasks::audit::{
+    record_mutation, AuditActor, MessageAudit, ACTION_DATASOURCES_LOAD, ACTION_DATASOURCE_CREATE,
+    ACTION_DATASOURCE_DELETE, ACTION_DATASOURCE_RESTORE, ACTION_DATASOURCE_UPDATE, ACTION_PYRAMID,
+    ACTION_PYRAMID_DELTA, ACTION_PYRAMID_DISTRIBUTED, ACTION_PYRAMID_SHARD, ACTION_PYRAMID_SMART,
+};
+use crate::tasks::billing::{QuotaExceeded, QuotaPeriod, ANONYMOUS_KEY};
 use crate::tasks::reload_workers::{MessageMaintenanceWorkers, WorkerData};
+use crate::tasks::semaphore::{Lane, MessageSemaphore, QueueFull};
+use crate::tasks::storage::StorageInfo;
+use crate::tasks::trash::TrashEntry;
 
 pub fn error_response_endpoint(endpoint: &str, err: anyhow::Error) -> Response<Body> {
-    event!(Level::ERROR, "Error '{endpoint}': {err}");
-    let body = json!({
-        "status": StatusCode::INTERNAL_SERVER_ERROR.as_u16(),
-        "message": err.to_string()
+    let error = ApiError::from(err);
+    if error.status.is_client_error() {
+        event!(Level::WARN, "Error '{endpoint}': {error}");
+    } else {
+        event!(Level::ERROR, "Error '{endpoint}': {error}");
+    }
+    error.into_response()
+}
+
+/*
+    Body of the request buffered up to 'max_size' bytes (0 - unlimited): larger bodies
+    get 413 by 'Content-Length' or as soon as the streamed body exceeds the limit
+*/
+pub async fn read_body_limited(
+    headers: &HeaderMap<HeaderValue>,
+    mut body: Body,
+    max_size: u64,
+) -> Result<Bytes, ApiError> {
+    let too_large = |size: u64| max_size > 0 && size > max_size;
+    let payload_too_large = || {
+        ApiError::new(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("Body of request exceeds {max_size} bytes"),
+        )
+    };
+    if let Some(length) = headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|l| l.to_str().ok())
+        .and_then(|l| l.parse::<u64>().ok())
+    {
+        if too_large(length) {
+            return Err(payload_too_large());
+        }
+    }
+
+    let mut data = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|err| {
+            ApiError::new(
+                StatusCode::BAD_REQUEST,
+                format!("Error read body of request: {err}"),
+            )
+        })?;
+        data.extend_from_slice(&chunk);
+        if too_large(data.len() as u64) {
+            return Err(payload_too_large());
+        }
+    }
+    Ok(Bytes::from(data))
+}
+
+// JSON body of the request, invalid JSON is a bad request
+pub fn json_body(b_bytes: &Bytes) -> Result<serde_json::Value, ApiError> {
+    serde_json::from_slice(b_bytes).map_err(|err| {
+        ApiError::new(
+            StatusCode::BAD_REQUEST,
+            format!("Body of request is not valid JSON: {err}"),
+        )
+    })
+}
+
+fn missing_field(field: &str, kind: &str) -> ApiError {
+    ApiError::new(
+        StatusCode::BAD_REQUEST,
+        format!("Field '{field}' ({kind}) is missing in the body of request"),
+    )
+    .with_details(json!({"field": field, "type": kind}))
+}
+
+// Required string field of the JSON body
+pub fn required_str<'a>(body: &'a serde_json::Value, field: &str) -> Result<&'a str, ApiError> {
+    body.get(field)
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| missing_field(field, "string"))
+}
+
+// Required non-negative integer field of the JSON body
+pub fn required_u64(body: &serde_json::Value, field: &str) -> Result<u64, ApiError> {
+    body.get(field)
+        .and_then(|v| v.as_u64())
+        .ok_or_else(|| missing_field(field, "unsigned integer"))
+}
+
+// Empty tile, correctly handled by MapLibre GL JS
+pub fn no_content_response() -> Result<Response<Body>, anyhow::Error> {
+    let response = Response::builder()
+        .status(StatusCode::NO_CONTENT)
+        .content_length(0)
+        .cache_control(CacheControl::Revalidate)
+        .body(Body::empty())?;
+    Ok(response)
+}
+
+pub fn blank_tile_response(config: &Config) -> Result<Response<Body>, anyhow::Error> {
+    let response = Response::builder()
+        .status(StatusCode::OK)
+        .content_type(ContentType::Png.as_ref())
+        .cache_control(CacheControl::Revalidate)
+        .body(Body::from(config.blank_tile.clone()))?;
+    Ok(response)
+}
+
+/*
+    Vector tile only with layers of the query parameter 'layers' (comma-separated names),
+    other responses are returned as is. Tile without requested layers is empty
+*/
+pub async fn filter_tile_layers(
+    response: Response<Body>,
+    query: Option<&str>,
+) -> Result<Response<Body>, anyhow::Error> {
+    let Some(layers) = query.and_then(|q| {
+        q.split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .find(|(k, _)| *k == "layers")
+            .map(|(_, v)| v)
+    }) else {
+        return Ok(response);
+    };
+    let is_mvt = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|ct| ct == ContentType::MvtPbf.as_ref());
+    if response.status() != StatusCode::OK || !is_mvt {
+        return Ok(response);
+    }
+    let layers = urlencoding::decode(layers)?;
+    let layers: HashSet<&str> = layers
+        .split(',')
+        .map(|l| l.trim())
+        .filter(|l| !l.is_empty())
+        .collect();
+
+    let (mut parts, body) = response.into_parts();
+    let body = hyper::body::to_bytes(body).await?;
+    let gzip = body.starts_with(b"\x1f\x8b\x08");
+    let mut data = Vec::new();
+    let tile = if gzip {
+        GzDecoder::new(body.as_ref()).read_to_end(&mut data)?;
+        &data
+    } else {
+        body.as_ref()
+    };
+    let tile = map_layers(tile, |name| layers.contains(name).then(|| name.to_string()))?;
+    if tile.is_empty() {
+        return no_content_response();
+    }
+    let tile = if gzip { gzip_tile(&tile)? } else { tile };
+    // tag of the worker is of the whole tile
+    parts.headers.remove(header::ETAG);
+    parts.headers.remove(header::CONTENT_LENGTH);
+    Ok(Response::from_parts(parts, Body::from(tile)))
+}
+
+// 'gzip' or '*' in 'Accept-Encoding' without 'q=0'
+pub fn accepts_gzip(accept_encoding: &str) -> bool {
+    accept_encoding.split(',').any(|coding| {
+        let mut params = coding.split(';').map(|p| p.trim());
+        let name = params.next().unwrap_or_default();
+        let disabled = params.any(|p| {
+            p.strip_prefix("q=")
+                .and_then(|q| q.parse::<f32>().ok())
+                .is_some_and(|q| q == 0.0)
+        });
+        (name.eq_ignore_ascii_case("gzip") || name == "*") && !disabled
     })
-    .to_string();
-    response_with_body_and_code(body, StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+/*
+    Vector tiles are stored in MBTiles compressed with zstd without gzip, on serve the encoding
+    is negotiated by 'Accept-Encoding': gzipped tiles are ungzipped for clients without gzip,
+    plain tiles are gzipped for clients with it. Other responses are returned as is
+*/
+pub async fn negotiate_tile_encoding(
+    response: Response<Body>,
+    accept_encoding: Option<&str>,
+) -> Result<Response<Body>, anyhow::Error> {
+    let is_mvt = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|ct| ct == ContentType::MvtPbf.as_ref());
+    if response.status() != StatusCode::OK || !is_mvt {
+        return Ok(response);
+    }
+    let gzip_accepted = accept_encoding.is_some_and(accepts_gzip);
+    let gzipped = response
+        .headers()
+        .get(header::CONTENT_ENCODING)
+        .is_some_and(|ce| ce == "gzip");
+
+    let (mut parts, body) = response.into_parts();
+    parts
+        .headers
+        .append(header::VARY, HeaderValue::from_static("accept-encoding"));
+    if gzipped == gzip_accepted {
+        return Ok(Response::from_parts(parts, body));
+    }
+    let body = hyper::body::to_bytes(body).await?;
+    let tile = if gzipped {
+        let mut data = Vec::new();
+        GzDecoder::new(body.as_ref()).read_to_end(&mut data)?;
+        parts.headers.remove(header::CONTENT_ENCODING);
+        data
+    } else {
+        parts
+            .headers
+            .insert(header::CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+        gzip_tile(&body)?
+    };
+    parts.headers.remove(header::CONTENT_LENGTH);
+    Ok(Response::from_parts(parts, Body::from(tile)))
+}
+
+/*
+    Tile of the worker is buffered and checked before forwarding: size is limited by 'max_size'
+    bytes (0 - unlimited), HTML and JSON pages or payloads without magic bytes of the format
+    of the tile are invalid. Err has the reason for the log and the response
+*/
+pub async fn validate_worker_tile(
+    response: Response<Body>,
+    content_type: &str,
+    max_size: u64,
+) -> Result<Response<Body>, String> {
+    let (parts, mut body) = response.into_parts();
+    if let Some(ct) = parts
+        .headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|ct| ct.to_str().ok())
+    {
+        if ct.starts_with("text/") || ct.starts_with(ContentType::ApplicationJson.as_ref()) {
+            return Err(format!("content type '{ct}' is not a tile"));
+        }
+    }
+    let too_large = |size: u64| max_size > 0 && size > max_size;
+    if let Some(length) = parts
+        .headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|l| l.to_str().ok())
+        .and_then(|l| l.parse::<u64>().ok())
+    {
+        if too_large(length) {
+            return Err(format!("size {length} bytes exceeds {max_size} bytes"));
+        }
+    }
+
+    let mut data = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|err| format!("error read body: {err}"))?;
+        data.extend_from_slice(&chunk);
+        if too_large(data.len() as u64) {
+            return Err(format!("size exceeds {max_size} bytes"));
+        }
+    }
+    if !data.is_empty() && !has_tile_magic(&data, content_type) {
+        return Err(format!("content is not '{content_type}'"));
+    }
+    Ok(Response::from_parts(parts, Body::from(data)))
+}
+
+/*
+    Raster tiles of workers may be PNG, JPEG or WebP of the upstream, MVT is gzip
+    or starts with the field 'layers' (tag 3, wire type 2)
+*/
+fn has_tile_magic(data: &[u8], content_type: &str) -> bool {
+    let webp = data.len() >= 12 && data.starts_with(b"RIFF") && &data[8..12] == b"WEBP";
+    if content_type == ContentType::MvtPbf.as_ref() {
+        data.starts_with(b"\x1f\x8b") || data[0] == 0x1a
+    } else if content_type == ContentType::Png.as_ref() {
+        data.starts_with(b"\x89PNG\r\n\x1a\n") || data.starts_with(b"\xff\xd8\xff") || webp
+    } else if content_type == ContentType::Webp.as_ref() {
+        webp
+    } else {
+        true
+    }
+}
+
+// Tile without data by 'missing_tile' of the DataSource: 204, 404 or blank PNG for raster
+pub fn empty_tile_response(
+    content_type: &str,
+    missing: MissingTile,
+    config: &Config,
+) -> Result<Response<Body>, anyhow::Error> {
+    match missing {
+        MissingTile::Blank if content_type == ContentType::Png.as_ref() => {
+            blank_tile_response(config)
+        }
+        MissingTile::NotFound => {
+            let response = Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .content_length(0)
+                .cache_control(CacheControl::Revalidate)
+                .body(Body::empty())?;
+            Ok(response)
+        }
+        _ => no_content_response(),
+    }
 }
 
 pub fn error_response(status_code: StatusCode) -> Response<Body> {
-    Response::builder()
-        .status(status_code)
-        .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
-        .body(Body::empty())
-        .unwrap()
+    ApiError::from_status(status_code).into_response()
 }
 
 pub async fn worker_response(
@@ -38,20 +344,28 @@ pub async fn worker_response(
     uri: Uri,
     method: &Method,
     client: &Client<HttpConnector>,
-    headers: HeaderMap<HeaderValue>,
-    body: Body,
+    mut headers: HeaderMap<HeaderValue>,
+    body: Bytes,
     ct: &str,
     timeout: u64,
+    cluster_secret: Option<&str>,
 ) -> Result<Response<Body>, anyhow::Error> {
+    if let Some(secret) = cluster_secret {
+        let path_and_query = uri.path_and_query().map(|pq| pq.as_str()).unwrap_or("/");
+        sign_request(&mut headers, secret, method, path_and_query, &body)?;
+    }
+
     let url = format!("http://{host}:{port}{uri}");
-    let mut worker_request = Request::builder().method(method).uri(url).body(body)?;
+    let mut worker_request = Request::builder()
+        .method(method)
+        .uri(url)
+        .body(Body::from(body))?;
     *worker_request.headers_mut() = headers;
 
     match tokio::time::timeout(Duration::from_secs(timeout), client.request(worker_request)).await {
         Err(_) => Response::builder()
             .status(StatusCode::SERVICE_UNAVAILABLE)
-            .header(header::CONTENT_TYPE, ct)
-            .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+            .content_type(ct)
             .body(Body::from(format!(
                 "Timeout: no response in {timeout} seconds."
             )))
@@ -64,8 +378,7 @@ pub async fn worker_response(
                 .to_string();
                 Response::builder()
                     .status(StatusCode::SERVICE_UNAVAILABLE)
-                    .header(header::CONTENT_TYPE, ct)
-                    .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+                    .content_type(ct)
                     .body(Body::from(body))
                     .map_err(anyhow::Error::from)
             }
@@ -74,47 +387,396 @@ pub async fn worker_response(
     }
 }
 
-pub fn worker_not_found_response(
-    datasource_id: &str,
-    ct: &str,
-) -> Result<Response<Body>, anyhow::Error> {
-    let message = format!("DataSource id '{datasource_id}' not found");
-    let body = json!({
-        "status": StatusCode::NOT_FOUND.as_u16(),
-        "message": message
+// Split network address of the cluster node 'host:port'
+pub fn split_node_address(address: &str) -> Result<(String, i32), anyhow::Error> {
+    let (host, port) = address
+        .rsplit_once(':')
+        .ok_or(anyhow!("Invalid cluster node address '{address}'"))?;
+    Ok((host.to_string(), port.parse()?))
+}
+
+/*
+    Send the same request to replicas of the DataSource in background,
+    errors are only logged since the client receives the response of the main location
+*/
+pub fn replicate_request(
+    addresses: Vec<String>,
+    uri: Uri,
+    method: Method,
+    client: Client<HttpConnector>,
+    headers: HeaderMap<HeaderValue>,
+    body: Bytes,
+    config: &Config,
+) {
+    if addresses.is_empty() {
+        return;
+    }
+    let timeout = config.timeout_worker_response;
+    let cluster_secret = config.cluster_secret.clone();
+    tokio::spawn(async move {
+        let ct = ContentType::ApplicationJson.as_ref();
+        for address in addresses {
+            let (host, port) = match split_node_address(&address) {
+                Ok(node) => node,
+                Err(err) => {
+                    event!(Level::ERROR, "Error replicate request '{uri}': {err}");
+                    continue;
+                }
+            };
+            match worker_response(
+                host,
+                port,
+                uri.clone(),
+                &method,
+                &client,
+                headers.clone(),
+                body.clone(),
+                ct,
+                timeout,
+                cluster_secret.as_deref(),
+            )
+            .await
+            {
+                Ok(response) if response.status().is_success() => {}
+                Ok(response) => {
+                    event!(
+                        Level::ERROR,
+                        "Replica '{address}' responded {} to '{uri}'",
+                        response.status()
+                    );
+                }
+                Err(err) => {
+                    event!(
+                        Level::ERROR,
+                        "Error replicate '{uri}' to '{address}': {err}"
+                    );
+                }
+            }
+        }
+    });
+}
+
+// Workers are starting or reloading, the client should repeat the request later
+pub fn not_ready_response() -> Response<Body> {
+    let mut response = ApiError::new(
+        StatusCode::SERVICE_UNAVAILABLE,
+        "Workers are not ready, retry later",
+    )
+    .with_code(ErrorCode::WorkersNotReady)
+    .into_response();
+    response.headers_mut().insert(
+        header::RETRY_AFTER,
+        HeaderValue::from(NOT_READY_RETRY_AFTER_SECS),
+    );
+    response
+}
+
+/*
+    The queue of the worker port is full: interactive requests get 503, batch requests
+    (seeding tools) get 429 to slow down, both with the depth of the queue
+*/
+pub fn queue_full_response(lane: Lane, full: QueueFull, retry_after_secs: u64) -> Response<Body> {
+    let status = match lane {
+        Lane::Interactive => StatusCode::SERVICE_UNAVAILABLE,
+        Lane::Batch => StatusCode::TOO_MANY_REQUESTS,
+    };
+    let mut response = ApiError::new(
+        status,
+        format!("Queue of the worker is full: {} requests wait", full.depth),
+    )
+    .with_code(ErrorCode::QueueFull)
+    .into_response();
+    let headers = response.headers_mut();
+    headers.insert(header::RETRY_AFTER, HeaderValue::from(retry_after_secs));
+    headers.insert(QUEUE_DEPTH_HEADER, HeaderValue::from(full.depth));
+    response
+}
+
+// Bytes of the body of the response: its exact size, otherwise 'Content-Length'
+pub fn body_size(response: &Response<Body>) -> u64 {
+    response.body().size_hint().exact().unwrap_or_else(|| {
+        response
+            .headers()
+            .get(header::CONTENT_LENGTH)
+            .and_then(|length| length.to_str().ok())
+            .and_then(|length| length.parse().ok())
+            .unwrap_or(0)
     })
-    .to_string();
-    let response = Response::builder()
-        .status(StatusCode::NOT_FOUND)
-        .header(header::CONTENT_TYPE, ct)
-        .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
-        .body(Body::from(body))
-        .map_err(anyhow::Error::from)?;
-    return Ok(response);
+}
+
+/*
+    Quota of the API key is exceeded: daily limits get 429, monthly limits get 402,
+    both with the time until the limit is reset
+*/
+pub fn quota_exceeded_response(api_key: &str, exceeded: &QuotaExceeded) -> Response<Body> {
+    let status = match exceeded.period {
+        QuotaPeriod::Daily => StatusCode::TOO_MANY_REQUESTS,
+        QuotaPeriod::Monthly => StatusCode::PAYMENT_REQUIRED,
+    };
+    let mut response = ApiError::new(
+        status,
+        format!(
+            "Quota '{}' of API key '{api_key}' is exceeded",
+            exceeded.limit
+        ),
+    )
+    .with_code(ErrorCode::QuotaExceeded)
+    .into_response();
+    let retry_after_secs = exceeded.retry_after_secs(chrono::Utc::now());
+    response
+        .headers_mut()
+        .insert(header::RETRY_AFTER, HeaderValue::from(retry_after_secs));
+    response
+}
+
+// Requests without the key are refused with 401, keys without quotas in the config with 403
+pub fn unknown_api_key_response(api_key: &str) -> Response<Body> {
+    if api_key == ANONYMOUS_KEY {
+        return ApiError::new(StatusCode::UNAUTHORIZED, "API key is required")
+            .with_code(ErrorCode::Unauthorized)
+            .into_response();
+    }
+    ApiError::new(
+        StatusCode::FORBIDDEN,
+        format!("API key '{api_key}' is unknown"),
+    )
+    .with_code(ErrorCode::Forbidden)
+    .into_response()
+}
+
+// Free space of the tiles volume is below the threshold, pyramid would fail halfway
+pub fn insufficient_storage_response(storage: &StorageInfo) -> Response<Body> {
+    ApiError::new(
+        StatusCode::INSUFFICIENT_STORAGE,
+        format!(
+            "Free space of the tiles volume {} bytes is below {} bytes",
+            storage.available_bytes, storage.min_free_bytes
+        ),
+    )
+    .into_response()
+}
+
+// DataSource is soft-deleted: tiles and pyramids are refused until it is restored
+pub fn gone_response(entry: &TrashEntry) -> Response<Body> {
+    ApiError::new(
+        StatusCode::GONE,
+        format!("DataSource '{}' is deleted", entry.datasource_id),
+    )
+    .with_code(ErrorCode::DataSourceDeleted)
+    .with_details(json!({
+        "deleted_at": entry.deleted_at,
+        "expires_at": entry.expires_at,
+    }))
+    .into_response()
 }
 
 pub fn response_with_body_and_code(body: String, status_code: StatusCode) -> Response<Body> {
-    let ct: &str = ContentType::ApplicationJson.into();
     Response::builder()
         .status(status_code)
-        .header(header::CONTENT_TYPE, ct)
-        .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+        .json()
         .body(Body::from(body))
         .unwrap()
 }
 
+// Error 403 of the tile requested by the website outside of 'allowed_origins' of the DataSource
+pub fn origin_not_allowed_response(headers: &HeaderMap<HeaderValue>) -> Response<Body> {
+    let message = match request_origin(headers) {
+        Some(origin) => format!("Tiles are not allowed for the origin '{origin}'"),
+        None => "Tiles are not allowed without 'Origin' or 'Referer'".to_string(),
+    };
+    ApiError::new(StatusCode::FORBIDDEN, message)
+        .with_code(ErrorCode::OriginNotAllowed)
+        .into_response()
+}
+
+/*
+    Headers of the CORS policy for the response: '*' for any origin without credentials,
+    otherwise the origin of the request if allowed (responses vary by Origin).
+    Tiles of DataSources with 'allowed_origins' are shared only with these websites
+*/
+pub fn apply_cors(response: &mut Response<Body>, origin: Option<&HeaderValue>, cors: &CorsConfig) {
+    let allowed_origins = response.extensions().get::<AllowedOrigins>().cloned();
+    let headers = response.headers_mut();
+    headers.remove(header::ACCESS_CONTROL_ALLOW_ORIGIN);
+    headers.remove(header::ACCESS_CONTROL_ALLOW_CREDENTIALS);
+
+    if let Some(allowed) = allowed_origins {
+        headers.append(header::VARY, HeaderValue::from_static("Origin"));
+        let Some(origin) = origin.filter(|o| o.to_str().is_ok_and(|o| allowed.origin_allowed(o)))
+        else {
+            return;
+        };
+        headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin.clone());
+        if cors.allow_credentials {
+            headers.insert(
+                header::ACCESS_CONTROL_ALLOW_CREDENTIALS,
+                HeaderValue::from_static("true"),
+            );
+        }
+        return;
+    }
+
+    let wildcard = cors.any_origin() && !cors.allow_credentials;
+    let allow_origin = match origin {
+        _ if wildcard => HeaderValue::from_static("*"),
+        Some(origin) if origin.to_str().is_ok_and(|o| cors.origin_allowed(o)) => {
+            headers.append(header::VARY, HeaderValue::from_static("Origin"));
+            origin.clone()
+        }
+        _ => return,
+    };
+    headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin);
+    if cors.allow_credentials {
+        headers.insert(
+            header::ACCESS_CONTROL_ALLOW_CREDENTIALS,
+            HeaderValue::from_static("true"),
+        );
+    }
+}
+
+/*
+    OPTIONS requests are answered by the server itself and never reach workers:
+    preflight (with 'Access-Control-Request-Method') gets the CORS policy,
+    plain OPTIONS gets the list of allowed methods
+*/
+pub fn options_response(headers: &HeaderMap<HeaderValue>, cors: &CorsConfig) -> Response<Body> {
+    let Some(request_method) = headers.get(header::ACCESS_CONTROL_REQUEST_METHOD) else {
+        let mut response = Response::builder()
+            .status(StatusCode::NO_CONTENT)
+            .header(header::ALLOW, cors.allowed_methods.join(", "))
+            .body(Body::empty())
+            .unwrap();
+        apply_cors(&mut response, headers.get(header::ORIGIN), cors);
+        return response;
+    };
+
+    let request_method = request_method.to_str().unwrap_or_default();
+    if !cors
+        .allowed_methods
+        .iter()
+        .any(|m| m.eq_ignore_ascii_case(request_method))
+    {
+        return forbidden_preflight(format!(
+            "Method '{request_method}' is not allowed by CORS policy"
+        ));
+    }
+
+    let request_headers = headers
+        .get(header::ACCESS_CONTROL_REQUEST_HEADERS)
+        .and_then(|h| h.to_str().ok())
+        .unwrap_or_default();
+    let any_header = cors.allowed_headers.iter().any(|h| h == "*");
+    if !any_header {
+        let not_allowed = request_headers.split(',').map(|h| h.trim()).find(|h| {
+            !h.is_empty()
+                && !cors
+                    .allowed_headers
+                    .iter()
+                    .any(|allowed| allowed.eq_ignore_ascii_case(h))
+        });
+        if let Some(h) = not_allowed {
+            return forbidden_preflight(format!("Header '{h}' is not allowed by CORS policy"));
+        }
+    }
+
+    // '*' is not a wildcard for requests with credentials, requested headers are returned
+    let allowed_headers = if any_header && cors.allow_credentials {
+        request_headers.to_string()
+    } else {
+        cors.allowed_headers.join(", ")
+    };
+
+    let mut response = Response::builder()
+        .status(StatusCode::NO_CONTENT)
+        .header(
+            header::ACCESS_CONTROL_ALLOW_METHODS,
+            cors.allowed_methods.join(", "),
+        )
+        .header(header::ACCESS_CONTROL_ALLOW_HEADERS, allowed_headers)
+        .header(header::ACCESS_CONTROL_MAX_AGE, cors.max_age)
+        .body(Body::empty())
+        .unwrap();
+    apply_cors(&mut response, headers.get(header::ORIGIN), cors);
+    response
+}
+
+// Rejected preflight has no CORS headers, the browser blocks the request
+fn forbidden_preflight(message: String) -> Response<Body> {
+    ApiError::new(StatusCode::FORBIDDEN, message).into_response()
+}
+
 pub fn debug_request(req: Request<Body>) -> Result<Response<Body>, Infallible> {
     let body_str = format!("{:?}", req);
-    let ct: &str = ContentType::ApplicationJson.into();
     let response = Response::builder()
         .status(StatusCode::OK)
-        .header(header::CONTENT_TYPE, ct)
-        .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+        .json()
         .body(Body::from(body_str))
         .unwrap();
     Ok(response)
 }
 
+// Upstream statuses which mean the worker is unresponsive (for circuit breaker)
+pub fn is_worker_failure(status: StatusCode) -> bool {
+    status == StatusCode::BAD_GATEWAY
+        || status == StatusCode::SERVICE_UNAVAILABLE
+        || status == StatusCode::GATEWAY_TIMEOUT
+}
+
+pub async fn report_worker_result(
+    tx_mw: &flume::Sender<MessageMaintenanceWorkers>,
+    port: u16,
+    success: bool,
+) {
+    if let Err(err) = tx_mw
+        .send_async(MessageMaintenanceWorkers::ReportWorkerResult { port, success })
+        .await
+    {
+        event!(
+            Level::ERROR,
+            "Error send worker result for port {port}: {err}"
+        );
+    }
+}
+
+/*
+    Open circuit breaker keeps the port out of retries, half-open breaker lets one probe through.
+    Port is not retried when the state of its breaker is unknown
+*/
+pub async fn worker_allowed(tx_mw: &flume::Sender<MessageMaintenanceWorkers>, port: u16) -> bool {
+    let (tx_allowed, rx_allowed) = oneshot::channel();
+    if let Err(err) = tx_mw
+        .send_async(MessageMaintenanceWorkers::AllowWorker { port, tx_allowed })
+        .await
+    {
+        event!(
+            Level::ERROR,
+            "Error send circuit breaker check for port {port}: {err}"
+        );
+        return false;
+    }
+    rx_allowed.await.unwrap_or(false)
+}
+
+// Latency of the response of the worker for the adaptive limit of concurrent requests
+pub async fn report_worker_latency(
+    tx_sem: &flume::Sender<MessageSemaphore>,
+    port: u16,
+    latency: Duration,
+    success: bool,
+) {
+    let message = MessageSemaphore::Observe {
+        port,
+        latency,
+        success,
+    };
+    if let Err(err) = tx_sem.send_async(message).await {
+        event!(
+            Level::ERROR,
+            "Error send worker latency for port {port}: {err}"
+        );
+    }
+}
+
 pub async fn get_worker_data(
     tx_mw: flume::Sender<MessageMaintenanceWorkers>,
 ) -> Result<WorkerData, anyhow::Error> {
@@ -137,3 +799,78 @@ pub async fn get_worker_data(
         },
     }
 }
+
+/*
+    Mutation of the DataSource or the start of the pyramid recorded to the audit log
+    with the status of the response, other requests are not audited
+*/
+pub struct AuditedRequest {
+    action: Option<&'static str>,
+    datasource_id: String,
+    body: Bytes,
+    actor: AuditActor,
+    tx_audit: flume::Sender<MessageAudit>,
+}
+
+impl AuditedRequest {
+    pub fn new(
+        route: Option<Route>,
+        method: &Method,
+        path: &str,
+        body: &Bytes,
+        actor: AuditActor,
+        tx_audit: flume::Sender<MessageAudit>,
+    ) -> Self {
+        let action = match route {
+            Some(Route::DataSources) if method == Method::GET => None,
+            Some(Route::DataSources) if path != EndPoints::DataSources.as_ref() => {
+                Some(ACTION_DATASOURCES_LOAD)
+            }
+            Some(Route::DataSources) if method == Method::POST => Some(ACTION_DATASOURCE_CREATE),
+            Some(Route::DataSources) => Some(ACTION_DATASOURCE_UPDATE),
+            Some(Route::DataSourcesDelete) => Some(ACTION_DATASOURCE_DELETE),
+            Some(Route::DataSourceRestore { .. }) => Some(ACTION_DATASOURCE_RESTORE),
+            Some(Route::Pyramid) => Some(ACTION_PYRAMID),
+            Some(Route::PyramidSmart) => Some(ACTION_PYRAMID_SMART),
+            Some(Route::PyramidDelta) => Some(ACTION_PYRAMID_DELTA),
+            Some(Route::PyramidDistributed) => Some(ACTION_PYRAMID_DISTRIBUTED),
+            Some(Route::PyramidShard) => Some(ACTION_PYRAMID_SHARD),
+            _ => None,
+        };
+        // DataSource of the path or the field 'datasource_id' ('id' of definitions) of the body
+        let datasource_id = match route {
+            Some(Route::DataSourceRestore { datasource_id }) => datasource_id.to_string(),
+            _ if action.is_some() => serde_json::from_slice::<serde_json::Value>(body)
+                .ok()
+                .and_then(|body| {
+                    body.get("datasource_id")
+                        .or_else(|| body.get("id"))
+                        .and_then(|id| id.as_str())
+                        .map(|id| id.to_string())
+                })
+                .unwrap_or_default(),
+            _ => String::new(),
+        };
+        AuditedRequest {
+            action,
+            datasource_id,
+            body: body.clone(),
+            actor,
+            tx_audit,
+        }
+    }
+
+    pub fn finish(&self, response: Response<Body>) -> Response<Body> {
+        if let Some(action) = self.action {
+            record_mutation(
+                &self.tx_audit,
+                &self.actor,
+                action,
+                &self.datasource_id,
+                &self.body,
+                response.status().as_u16(),
+            );
+        }
+        response
+    }
+}
@@ -1,65 +1,694 @@
 pub mod cache;
 pub mod endpoints;
+pub mod error;
+pub mod headers;
 pub mod helpers;
+pub mod router;
+pub mod version;
 
 use std::convert::Infallible;
 use std::net::IpAddr;
+use std::time::{Duration, Instant};
 
-use hyper::{Body, Method, Request, Response, StatusCode};
-use tracing::{event, Level};
+use hyper::{
+    body::Bytes,
+    http::header::{self, HeaderMap, HeaderValue},
+    Body, Method, Request, Response, StatusCode,
+};
+use tiler_core::sqlite_clients::MessageSQLiteClient;
+use tokio::sync::watch;
+use tracing::{event, info_span, Instrument, Level};
 
+use crate::cluster::auth::{is_cluster_request, verify_request};
+use crate::cluster::hops::{node_address, RouteTrace};
 use crate::config::Config;
-use crate::db::{job::queue::JobDetail, DB};
-use crate::defaults::LOCALHOST;
+use crate::db::{job::queue::JobDetail, registry::SharedRegistry, DB};
+use crate::defaults::{LOCALHOST, REQUEST_ID_HEADER, ROUTE_HEADER};
+use crate::forwarded::forwarded_client_ip;
 use crate::hyper_reverse_proxy;
+use crate::log::otel::set_parent_from_headers;
+use crate::paths::Paths;
 use crate::structs::{ContentType, EndPoints};
+use crate::tasks::audit::{AuditActor, MessageAudit};
+use crate::tasks::billing::{api_key_id, TileServed};
+use crate::tasks::cluster::MessageCluster;
 use crate::tasks::datasources::MessageDatasource;
+use crate::tasks::distributed::DistributedContext;
+use crate::tasks::events::MessageEvents;
+use crate::tasks::exports::MessageExports;
 use crate::tasks::reload_workers::MessageMaintenanceWorkers;
+use crate::tasks::seeding::SeedingContext;
 use crate::tasks::semaphore::MessageSemaphore;
-use crate::tasks::sqlite_clients::MessageSQLiteClient;
+use crate::tasks::stats::{record_latency, MessageStats, WorkerTimings};
+use crate::tasks::storage::StorageInfo;
+use crate::tasks::uploads::MessageUploads;
+use crate::tasks::usage::{quota_exceeded, record_served, MessageUsage};
+use crate::utils::static_root;
+use crate::webhooks::PyramidWebhooks;
 use endpoints::{
-    datasource::{datasource_delete_endpoint, datasource_endpoint},
+    audit::audit_endpoint,
+    cluster::cluster_endpoint,
+    datasource::{datasource_delete_endpoint, datasource_endpoint, datasource_restore_endpoint},
+    events::events_endpoint,
+    export::{export_download_endpoint, export_endpoint, export_job_endpoint},
     health::health_endpoint,
     maintenance::maintenance_endpoint,
     master::master_endpoint,
-    pyramid::pyramid_endpoint,
-    tile::tile_endpoint,
+    metadata::metadata_endpoint,
+    metrics::metrics_endpoint,
+    openapi::{openapi_endpoint, swagger_ui_endpoint},
+    probes::{healthz_endpoint, readyz_endpoint},
+    pyramid::{
+        cancel_shard_endpoint, delta_pyramid_endpoint, distributed_pyramid_endpoint,
+        pyramid_endpoint, pyramid_job_endpoint, shard_pyramid_endpoint, smart_pyramid_endpoint,
+    },
+    routing::routing_endpoint,
+    static_files::static_endpoint,
+    stats::stats_endpoint,
+    styles::styles_endpoint,
+    tile::{tile_endpoint, TileContext},
+    upload::{resumable_upload_endpoint, upload_endpoint},
+    usage::{billing_endpoint, usage_endpoint},
+    validate::validate_endpoint,
+};
+use error::{request_id, ApiError, ErrorCode, REQUEST_ID};
+use headers::apply_header_overrides;
+use helpers::{
+    apply_cors, body_size, debug_request, error_response, error_response_endpoint,
+    filter_tile_layers, get_worker_data, insufficient_storage_response, is_worker_failure,
+    negotiate_tile_encoding, not_ready_response, options_response, quota_exceeded_response,
+    read_body_limited, report_worker_result, response_with_body_and_code, unknown_api_key_response,
+    AuditedRequest,
 };
-use helpers::{debug_request, error_response, error_response_endpoint, get_worker_data};
+use router::{route, Route};
+use version::route_version;
 
+// Channels and state of the server shared by requests
+#[derive(Clone)]
+pub struct RequestContext {
+    pub paths: Paths,
+    pub registry: SharedRegistry,
+    pub pg_pool: Option<DB>,
+    pub config: Config,
+    pub tx: flume::Sender<MessageDatasource>,
+    pub tx_sqlite_client: flume::Sender<MessageSQLiteClient>,
+    pub tx_mw: flume::Sender<MessageMaintenanceWorkers>,
+    pub tx_jd: Option<flume::Sender<JobDetail>>,
+    pub tx_sem: flume::Sender<MessageSemaphore>,
+    pub tx_cluster: flume::Sender<MessageCluster>,
+    pub tx_events: flume::Sender<MessageEvents>,
+    pub tx_stats: flume::Sender<MessageStats>,
+    pub tx_exports: flume::Sender<MessageExports>,
+    pub tx_uploads: flume::Sender<MessageUploads>,
+    pub tx_usage: flume::Sender<MessageUsage>,
+    pub tx_audit: flume::Sender<MessageAudit>,
+    pub rx_storage: watch::Receiver<StorageInfo>,
+    pub rx_ready: watch::Receiver<bool>,
+}
+
+// Every response gets the headers of the CORS policy, OPTIONS requests are answered here
 pub async fn handle(
-    cwd: String,
+    ctx: RequestContext,
+    client_ip: IpAddr,
+    mut req: Request<Body>,
+) -> Result<Response<Body>, Infallible> {
+    if req.method() == Method::OPTIONS {
+        return Ok(options_response(req.headers(), &ctx.config.cors));
+    }
+
+    let origin = req.headers().get(header::ORIGIN).cloned();
+    let cors = ctx.config.cors.clone();
+    let slow_request_ms = ctx.config.slow_request_ms;
+    let method = req.method().clone();
+    let path = req.uri().path().to_string();
+    let started = Instant::now();
+    let tx_latency = ctx.tx_stats.clone();
+    let request_id = request_id(req.headers());
+    // behind trusted proxies the client is taken from 'X-Forwarded-For' and 'Forwarded'
+    let client_ip = forwarded_client_ip(client_ip, req.headers_mut(), &ctx.config.proxy);
+    let span = info_span!(
+        "request",
+        method = %req.method(),
+        path = req.uri().path(),
+        request_id = %request_id,
+        client_ip = %client_ip
+    );
+    set_parent_from_headers(&span, req.headers());
+    // '/api/v1/...' is routed by unversioned paths, latency is recorded by the route
+    let routed = route_version(&mut req);
+    let label = endpoint_label(req.uri().path());
+    let header_overrides = ctx.config.response_headers.get(label).cloned();
+    let trace = RouteTrace::from_headers(req.headers());
+    let node = node_address(&ctx.config);
+    let handled = async move {
+        if let Err(err) = routed {
+            return Ok(err.into_response());
+        }
+        handle_request(ctx, client_ip, req).await
+    }
+    .instrument(span);
+    // errors of the request are serialized with its id, clients get it in the header too
+    let mut response = REQUEST_ID.scope(request_id.clone(), handled).await?;
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    // the route is reported to clients asking for it, routes of the next nodes are kept
+    if trace.debug && !response.headers().contains_key(ROUTE_HEADER) {
+        let worker_port = response
+            .extensions()
+            .get::<WorkerTimings>()
+            .map(|timings| timings.port);
+        if let Ok(route) = HeaderValue::from_str(&trace.route(&node, worker_port)) {
+            response.headers_mut().insert(ROUTE_HEADER, route);
+        }
+    }
+    apply_cors(&mut response, origin.as_ref(), &cors);
+    if let Some(overrides) = header_overrides {
+        apply_header_overrides(&mut response, &overrides);
+    }
+
+    let elapsed = started.elapsed();
+    record_latency(&tx_latency, label, elapsed);
+    if slow_request_ms > 0 && elapsed >= Duration::from_millis(slow_request_ms) {
+        match response.extensions().get::<WorkerTimings>() {
+            Some(t) => event!(
+                Level::WARN,
+                "Slow request {method} '{path}' ({}): {elapsed:?}, datasource '{}', worker port {}, wait for permit {:?}, upstream {:?}",
+                response.status(),
+                t.datasource_id,
+                t.port,
+                t.permit_wait,
+                t.upstream
+            ),
+            None => event!(
+                Level::WARN,
+                "Slow request {method} '{path}' ({}): {elapsed:?}",
+                response.status()
+            ),
+        }
+    }
+    Ok(response)
+}
+
+/*
+    Bodies are buffered only for endpoints which parse them (JSON) and for signed
+    requests of the cluster, other bodies (uploads of datasource files) are streamed to workers
+*/
+fn buffers_body(route: Option<Route>, headers: &HeaderMap<HeaderValue>, config: &Config) -> bool {
+    (config.cluster_secret.is_some() && is_cluster_request(headers))
+        || route.is_some_and(|r| r.buffers_body())
+}
+
+// Label of the endpoint for latency histograms by whole segments, longer prefixes go first
+fn endpoint_label(path: &str) -> &'static str {
+    if path.starts_with("/maintenance") {
+        return "/maintenance";
+    }
+    [
+        EndPoints::Tile,
+        EndPoints::PyramidSmart,
+        EndPoints::PyramidDelta,
+        EndPoints::PyramidDistributed,
+        EndPoints::PyramidShard,
+        EndPoints::Pyramid,
+        EndPoints::DataSourcesLoadFiles,
+        EndPoints::DataSourcesReloadFiles,
+        EndPoints::DataSources,
+        EndPoints::Health,
+        EndPoints::Events,
+        EndPoints::TileGrids,
+        EndPoints::ExportDownload,
+        EndPoints::Export,
+        EndPoints::AuditLog,
+        EndPoints::Usage,
+        EndPoints::ClusterRegister,
+        EndPoints::ClusterNodes,
+        EndPoints::Jobs,
+        EndPoints::Metrics,
+        EndPoints::Healthz,
+        EndPoints::Readyz,
+        EndPoints::OpenApi,
+        EndPoints::ApiDocs,
+        EndPoints::Static,
+        EndPoints::Styles,
+        EndPoints::Sprites,
+        EndPoints::Fonts,
+        EndPoints::DebugRoute,
+    ]
+    .into_iter()
+    .find(|e| {
+        path.strip_prefix(e.as_ref())
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    })
+    .map(|e| e.into())
+    .unwrap_or("other")
+}
+
+async fn handle_request(
+    ctx: RequestContext,
     client_ip: IpAddr,
     mut req: Request<Body>,
-    pool: DB,
-    config: Config,
-    tx: flume::Sender<MessageDatasource>,
-    tx_sqlite_client: flume::Sender<MessageSQLiteClient>,
-    tx_mw: flume::Sender<MessageMaintenanceWorkers>,
-    tx_jd: Option<flume::Sender<JobDetail>>,
-    tx_sem: flume::Sender<MessageSemaphore>,
 ) -> Result<Response<Body>, Infallible> {
+    let RequestContext {
+        paths,
+        registry,
+        pg_pool,
+        config,
+        tx,
+        tx_sqlite_client,
+        tx_mw,
+        tx_jd,
+        tx_sem,
+        tx_cluster,
+        tx_events,
+        tx_stats,
+        tx_exports,
+        tx_uploads,
+        tx_usage,
+        tx_audit,
+        rx_storage,
+        rx_ready,
+    } = ctx;
     let uri = req.uri().clone();
     let path = uri.path();
-    let path_elements: Vec<&str> = path.split("/").collect();
-
     let method = req.method().clone();
+    let route = route(&method, path);
+
+    // Probes of Kubernetes: liveness and readiness of the balancer
+    if route == Some(Route::Healthz) {
+        return Ok(healthz_endpoint());
+    }
+    let workers_ready = *rx_ready.borrow();
+    if route == Some(Route::Readyz) {
+        return Ok(readyz_endpoint(
+            &registry,
+            workers_ready,
+            tx_mw.clone(),
+            &tx_sqlite_client,
+            &config,
+        )
+        .await);
+    }
+
+    // OpenAPI document of the balancer and its Swagger UI
+    if route == Some(Route::OpenApi) {
+        return Ok(openapi_endpoint());
+    }
+    if route == Some(Route::ApiDocs) {
+        match swagger_ui_endpoint(&config) {
+            Err(err) => {
+                let response = error_response_endpoint("swagger_ui_endpoint", err);
+                return Ok(response);
+            }
+            Ok(response) => return Ok(response),
+        }
+    }
+
+    // Traffic waits for workers at startup and reload, control endpoints are always served
+    if !workers_ready && !route.is_some_and(|r| r.is_control()) {
+        return Ok(not_ready_response());
+    }
+
     let (parts, b) = req.into_parts();
-    let b_bytes = hyper::body::to_bytes(b).await.unwrap();
+    let (b_bytes, b) = if buffers_body(route, &parts.headers, &config) {
+        match read_body_limited(&parts.headers, b, config.max_body_size_kb * 1024).await {
+            Ok(bytes) => (bytes.clone(), Body::from(bytes)),
+            Err(err) => return Ok(err.into_response()),
+        }
+    } else {
+        (Bytes::new(), b)
+    };
+
+    // Requests between instances of the cluster must be signed with the shared secret,
+    // internal endpoints of the cluster are refused without the signature
+    if let Some(secret) = config.cluster_secret.as_deref() {
+        let internal = route.is_some_and(|r| r.is_cluster_internal(path));
+        if internal || is_cluster_request(&parts.headers) {
+            let path_and_query = uri.path_and_query().map(|pq| pq.as_str()).unwrap_or("/");
+            if let Err(err) =
+                verify_request(&parts.headers, secret, &method, path_and_query, &b_bytes)
+            {
+                event!(
+                    Level::ERROR,
+                    "Rejected cluster request '{path}' from {client_ip}: {err}"
+                );
+                return Ok(ApiError::new(StatusCode::UNAUTHORIZED, err.to_string()).into_response());
+            }
+        }
+    }
+
+    // Requests forwarded between nodes must not come back to the node or wander the cluster
+    if let Some(err) = RouteTrace::from_headers(&parts.headers)
+        .refuse(&node_address(&config), config.cluster_max_hops)
+    {
+        event!(
+            Level::ERROR,
+            "Rejected request '{path}' from {client_ip}: {}",
+            err.message
+        );
+        return Ok(err.into_response());
+    }
+
+    // Actions are recorded to the audit log with the client
+    let actor = AuditActor::from_request(
+        client_ip,
+        &parts.headers,
+        REQUEST_ID.try_with(|id| id.clone()).unwrap_or_default(),
+        config.cluster_secret.is_some() && is_cluster_request(&parts.headers),
+    );
+    let audit = AuditedRequest::new(
+        route,
+        &method,
+        path,
+        &b_bytes,
+        actor.clone(),
+        tx_audit.clone(),
+    );
 
     // Maintenance endpoints
-    match maintenance_endpoint(path, &method, &b_bytes, tx_mw.clone(), tx_sem.clone()).await {
-        Err(err) => {
-            let response = error_response_endpoint("maintenance_endpoint", err);
-            return Ok(response);
+    if route == Some(Route::Maintenance) {
+        match maintenance_endpoint(
+            path,
+            &method,
+            &b_bytes,
+            tx_mw.clone(),
+            tx_sem.clone(),
+            &rx_storage,
+            uri.query(),
+            tx_audit.clone(),
+            &actor,
+        )
+        .await
+        {
+            Err(err) => {
+                let response = error_response_endpoint("maintenance_endpoint", err);
+                return Ok(response);
+            }
+            Ok(value) => {
+                if let Some(response) = value {
+                    return Ok(response);
+                }
+            }
+        }
+    }
+
+    // Cluster membership endpoints
+    if route == Some(Route::Cluster) {
+        match cluster_endpoint(
+            path,
+            &method,
+            &parts.headers,
+            &b_bytes,
+            tx_cluster.clone(),
+            &config,
+        )
+        .await
+        {
+            Err(err) => {
+                let response = error_response_endpoint("cluster_endpoint", err);
+                return Ok(response);
+            }
+            Ok(value) => {
+                if let Some(response) = value {
+                    return Ok(response);
+                }
+            }
+        }
+    }
+
+    // Latency of requests per endpoint (Prometheus)
+    if route == Some(Route::Metrics) {
+        match metrics_endpoint(
+            tx_stats.clone(),
+            tx_sqlite_client.clone(),
+            tx_sem.clone(),
+            &rx_storage,
+        )
+        .await
+        {
+            Err(err) => {
+                let response = error_response_endpoint("metrics_endpoint", err);
+                return Ok(response);
+            }
+            Ok(response) => return Ok(response),
+        }
+    }
+
+    // Stream of server events (jobs, workers, datasources)
+    if route == Some(Route::Events) {
+        match events_endpoint(tx_events).await {
+            Err(err) => {
+                let response = error_response_endpoint("events_endpoint", err);
+                return Ok(response);
+            }
+            Ok(response) => return Ok(response),
+        }
+    }
+
+    // Audit log of maintenance actions, mutations of DataSources and pyramids
+    if route == Some(Route::AuditLog) {
+        match audit_endpoint(uri.query(), tx_audit).await {
+            Err(err) => {
+                let response = error_response_endpoint("audit_endpoint", err);
+                return Ok(response);
+            }
+            Ok(response) => return Ok(response),
+        }
+    }
+
+    // Tiles served per API key, DataSource and day
+    if route == Some(Route::Usage) {
+        match billing_endpoint(uri.query(), tx_usage.clone(), &config).await {
+            Err(err) => {
+                let response = error_response_endpoint("billing_endpoint", err);
+                return Ok(response);
+            }
+            Ok(response) => return Ok(response),
+        }
+    }
+
+    // Registered tile grids (tile matrix sets)
+    if route == Some(Route::TileGrids) {
+        match serde_json::to_string(&config.tile_grids) {
+            Err(err) => {
+                let response = error_response_endpoint("tile_grids", anyhow::Error::from(err));
+                return Ok(response);
+            }
+            Ok(body) => return Ok(response_with_body_and_code(body, StatusCode::OK)),
+        }
+    }
+
+    // Explanation of the routing of the request without its execution
+    if route == Some(Route::DebugRoute) {
+        match routing_endpoint(
+            &paths,
+            uri.query(),
+            tx.clone(),
+            tx_cluster.clone(),
+            tx_mw.clone(),
+            tx_sqlite_client.clone(),
+            &config,
+        )
+        .await
+        {
+            Err(err) => {
+                let response = error_response_endpoint("routing_endpoint", err);
+                return Ok(response);
+            }
+            Ok(response) => return Ok(response),
+        }
+    }
+
+    // Static assets (map viewer, styles)
+    if route == Some(Route::Static) {
+        match static_endpoint(path, &static_root(&paths, &config), &config).await {
+            Err(err) => {
+                let response = error_response_endpoint("static_endpoint", err);
+                return Ok(response);
+            }
+            Ok(response) => return Ok(response),
+        }
+    }
+
+    // Styles, sprites and glyphs for MapLibre/Mapbox GL
+    if route == Some(Route::Styles) {
+        match styles_endpoint(&paths, path, &method, &parts.headers).await {
+            Err(err) => {
+                let response = error_response_endpoint("styles_endpoint", err);
+                return Ok(response);
+            }
+            Ok(value) => {
+                if let Some(response) = value {
+                    return Ok(response);
+                }
+            }
+        }
+    }
+
+    // Cache statistics of the DataSource: /api/datasources/{id}/stats
+    if let Some(Route::DataSourceStats { datasource_id }) = route {
+        match stats_endpoint(
+            &paths,
+            datasource_id,
+            tx.clone(),
+            tx_sqlite_client.clone(),
+            tx_stats.clone(),
+        )
+        .await
+        {
+            Err(err) => {
+                let response = error_response_endpoint("stats_endpoint", err);
+                return Ok(response);
+            }
+            Ok(response) => return Ok(response),
         }
-        Ok(value) => {
-            if let Some(response) = value {
+    }
+
+    // Usage of tiles of the DataSource: /api/datasources/{id}/usage
+    if let Some(Route::DataSourceUsage { datasource_id }) = route {
+        match usage_endpoint(
+            datasource_id,
+            uri.query(),
+            tx.clone(),
+            tx_usage.clone(),
+            &config,
+        )
+        .await
+        {
+            Err(err) => {
+                let response = error_response_endpoint("usage_endpoint", err);
+                return Ok(response);
+            }
+            Ok(response) => return Ok(response),
+        }
+    }
+
+    // Restore of the soft-deleted DataSource: /api/datasources/{id}/restore
+    if let Some(Route::DataSourceRestore { datasource_id }) = route {
+        match datasource_restore_endpoint(datasource_id, tx.clone()).await {
+            Err(err) => {
+                let response = error_response_endpoint("datasource_restore_endpoint", err);
+                return Ok(audit.finish(response));
+            }
+            Ok(response) => return Ok(audit.finish(response)),
+        }
+    }
+
+    // Dry run of the registration of the DataSource: /api/datasources/{id}/validate
+    if let Some(Route::DataSourceValidate { datasource_id }) = route {
+        match validate_endpoint(&paths, datasource_id, &b_bytes, &registry, &config).await {
+            Err(err) => {
+                let response = error_response_endpoint("validate_endpoint", err);
+                return Ok(response);
+            }
+            Ok(response) => return Ok(response),
+        }
+    }
+
+    // Metadata of MBTiles of the DataSource: /api/datasources/{id}/metadata
+    if let Some(Route::DataSourceMetadata { datasource_id }) = route {
+        match metadata_endpoint(
+            &paths,
+            datasource_id,
+            &registry,
+            tx.clone(),
+            tx_sqlite_client.clone(),
+        )
+        .await
+        {
+            Err(err) => {
+                let response = error_response_endpoint("metadata_endpoint", err);
                 return Ok(response);
             }
+            Ok(response) => return Ok(response),
         }
     }
 
+    // Offline packages of DataSources: download of the package, start of the export job
+    if let Some(Route::ExportDownload { job_id }) = route {
+        match export_download_endpoint(job_id, uri.query(), tx_exports.clone()).await {
+            Err(err) => {
+                let response = error_response_endpoint("export_download_endpoint", err);
+                return Ok(response);
+            }
+            Ok(response) => return Ok(response),
+        }
+    } else if let Some(Route::Export { datasource_id }) = route {
+        match export_endpoint(
+            &paths,
+            datasource_id,
+            &b_bytes,
+            tx.clone(),
+            tx_exports.clone(),
+            tx_events.clone(),
+            &config,
+        )
+        .await
+        {
+            Err(err) => {
+                let response = error_response_endpoint("export_endpoint", err);
+                return Ok(response);
+            }
+            Ok(response) => return Ok(response),
+        }
+    }
+
+    // State of the export job: /api/jobs/{job_id}
+    if let Some(Route::Job { job_id }) = route {
+        match export_job_endpoint(job_id, tx_exports.clone()).await {
+            Err(err) => {
+                let response = error_response_endpoint("export_job_endpoint", err);
+                return Ok(response);
+            }
+            Ok(value) => {
+                if let Some(response) = value {
+                    return Ok(response);
+                }
+            }
+        }
+        // State of the distributed pyramid
+        match pyramid_job_endpoint(job_id, tx_exports.clone()).await {
+            Err(err) => {
+                let response = error_response_endpoint("pyramid_job_endpoint", err);
+                return Ok(response);
+            }
+            Ok(value) => {
+                if let Some(response) = value {
+                    return Ok(response);
+                }
+            }
+        }
+    }
+
+    // Cancellation of the shard of the distributed pyramid by the master
+    if let Some(Route::PyramidShardCancel { job_id }) = route {
+        let result = cancel_shard_endpoint(
+            job_id,
+            config.export_ttl_seconds,
+            tx_exports.clone(),
+            tx_events.clone(),
+        )
+        .await;
+        match result {
+            Err(err) => {
+                let response = error_response_endpoint("cancel_shard_endpoint", err);
+                return Ok(audit.finish(response));
+            }
+            Ok(response) => return Ok(audit.finish(response)),
+        }
+    }
+
+    // Native mode renders tiles in the balancer, endpoints of Python workers are not available
+    if config.is_native() && !route.is_some_and(|r| r.is_native()) {
+        let response = ApiError::new(
+            StatusCode::NOT_IMPLEMENTED,
+            format!("Endpoint '{path}' requires Python workers, they are not run in native mode"),
+        )
+        .with_code(ErrorCode::WorkersRequired)
+        .into_response();
+        return Ok(response);
+    }
+
     let _index;
     let port;
     let ports;
@@ -77,21 +706,125 @@ pub async fn handle(
         }
     }
 
-    // Rebuild request after consume
+    // Rebuild request after split, body is either buffered or still streamed from the client
     req = Request::builder()
         .method(method.clone())
         .uri(uri.clone())
-        .body(Body::from(b_bytes.clone()))
+        .body(b)
         .unwrap();
     *req.headers_mut() = parts.headers.clone();
 
     let ct = ContentType::ApplicationJson.as_ref();
 
+    // Smart pyramid of the missed tiles is created by the workers of this server
+    if route == Some(Route::PyramidSmart) {
+        let storage = *rx_storage.borrow();
+        if storage.is_low() {
+            return Ok(audit.finish(insufficient_storage_response(&storage)));
+        }
+        let ctx = SeedingContext {
+            paths: paths.clone(),
+            client: client.clone(),
+            client_ip,
+            ports: ports.clone(),
+            tx_sem: tx_sem.clone(),
+            tx_mw: tx_mw.clone(),
+            tx_sqlite_client: tx_sqlite_client.clone(),
+            tx_events: tx_events.clone(),
+            timeout_secs: config.timeout_tile_response,
+            integrity: config.integrity,
+        };
+        match smart_pyramid_endpoint(&b_bytes, tx.clone(), tx_usage.clone(), ctx).await {
+            Err(err) => {
+                let response = error_response_endpoint("smart_pyramid_endpoint", err);
+                return Ok(audit.finish(response));
+            }
+            Ok(response) => return Ok(audit.finish(response)),
+        }
+    }
+
+    if route == Some(Route::PyramidDelta) {
+        let storage = *rx_storage.borrow();
+        if storage.is_low() {
+            return Ok(audit.finish(insufficient_storage_response(&storage)));
+        }
+        let ctx = SeedingContext {
+            paths: paths.clone(),
+            client: client.clone(),
+            client_ip,
+            ports: ports.clone(),
+            tx_sem: tx_sem.clone(),
+            tx_mw: tx_mw.clone(),
+            tx_sqlite_client: tx_sqlite_client.clone(),
+            tx_events: tx_events.clone(),
+            timeout_secs: config.timeout_tile_response,
+            integrity: config.integrity,
+        };
+        match delta_pyramid_endpoint(
+            &paths,
+            &registry,
+            &b_bytes,
+            tx.clone(),
+            tx_jd.clone(),
+            ctx,
+            &config,
+        )
+        .await
+        {
+            Err(err) => {
+                let response = error_response_endpoint("delta_pyramid_endpoint", err);
+                return Ok(audit.finish(response));
+            }
+            Ok(response) => return Ok(audit.finish(response)),
+        }
+    }
+
+    if matches!(route, Some(Route::PyramidDistributed | Route::PyramidShard)) {
+        let storage = *rx_storage.borrow();
+        if storage.is_low() {
+            return Ok(audit.finish(insufficient_storage_response(&storage)));
+        }
+        let ctx = DistributedContext {
+            seeding: SeedingContext {
+                paths: paths.clone(),
+                client: client.clone(),
+                client_ip,
+                ports: ports.clone(),
+                tx_sem: tx_sem.clone(),
+                tx_mw: tx_mw.clone(),
+                tx_sqlite_client: tx_sqlite_client.clone(),
+                tx_events: tx_events.clone(),
+                timeout_secs: config.timeout_tile_response,
+                integrity: config.integrity,
+            },
+            registry: registry.clone(),
+            tx: tx.clone(),
+            tx_exports: tx_exports.clone(),
+            config: config.clone(),
+        };
+        let result = if route == Some(Route::PyramidShard) {
+            shard_pyramid_endpoint(&b_bytes, &ctx)
+                .await
+                .map_err(|err| ("shard_pyramid_endpoint", err))
+        } else {
+            distributed_pyramid_endpoint(&b_bytes, tx_cluster.clone(), ctx)
+                .await
+                .map_err(|err| ("distributed_pyramid_endpoint", err))
+        };
+        match result {
+            Err((name, err)) => {
+                let response = error_response_endpoint(name, err);
+                return Ok(audit.finish(response));
+            }
+            Ok(response) => return Ok(audit.finish(response)),
+        }
+    }
+
     /*
         In master mode, requests to server workers are made for tiles
         and for generating raster pyramids
     */
-    if config.master {
+    if config.master && matches!(route, Some(Route::Tile { .. } | Route::Pyramid)) {
         match master_endpoint(
             path,
             &method,
@@ -101,17 +834,18 @@ pub async fn handle(
             ct,
             client.clone(),
             tx.clone(),
+            tx_cluster,
             &config,
         )
         .await
         {
             Err(err) => {
                 let response = error_response_endpoint("master_endpoint", err);
-                return Ok(response);
+                return Ok(audit.finish(response));
             }
             Ok(value) => {
                 if let Some(response) = value {
-                    return Ok(response);
+                    return Ok(audit.finish(response));
                 }
             }
         }
@@ -123,31 +857,83 @@ pub async fn handle(
     */
 
     // Worker mode at adrress is None (missing)
-    if path.starts_with(EndPoints::Tile.as_ref()) {
-        match tile_endpoint(
-            cwd,
-            path,
-            pool,
-            tx_sqlite_client,
+    if let Some(Route::Tile { datasource_id }) = route {
+        let query = req.uri().query().map(|q| q.to_string());
+        // tiles are counted per API key, keys with exceeded quotas are refused
+        let api_key = config
+            .billing_enabled
+            .then(|| api_key_id(req.headers(), query.as_deref(), &config.billing_key_header));
+        if let Some(api_key) = api_key.as_ref() {
+            if config.billing_quotas.reject_unknown_keys && !config.billing_quotas.is_known(api_key)
+            {
+                return Ok(unknown_api_key_response(api_key));
+            }
+        }
+        if let Some(api_key) = api_key
+            .as_ref()
+            .filter(|key| config.billing_quotas.quota(key).is_some())
+        {
+            if let Some(exceeded) = quota_exceeded(&tx_usage, api_key).await {
+                return Ok(quota_exceeded_response(api_key, &exceeded));
+            }
+        }
+        let tx_served = tx_usage.clone();
+        let accept_encoding = req
+            .headers()
+            .get(header::ACCEPT_ENCODING)
+            .and_then(|ae| ae.to_str().ok())
+            .map(|ae| ae.to_string());
+        let tile_ctx = TileContext {
+            paths,
+            registry,
+            pg_pool,
+            client,
             client_ip,
             port,
-            req,
-            client,
-            tx_sem,
+            ports,
             tx,
-        )
-        .await
-        {
+            tx_sem,
+            tx_mw,
+            tx_stats,
+            tx_usage,
+            tx_sqlite_client,
+        };
+        match tile_endpoint(&tile_ctx, path, req, &config).await {
             Err(err) => {
                 let response = error_response_endpoint("tile_endpoint", err);
                 Ok(response)
             }
-            Ok(response) => Ok(response),
+            Ok(response) => match filter_tile_layers(response, query.as_deref()).await {
+                Err(err) => Ok(error_response_endpoint("filter_tile_layers", err)),
+                Ok(response) => {
+                    match negotiate_tile_encoding(response, accept_encoding.as_deref()).await {
+                        Err(err) => Ok(error_response_endpoint("negotiate_tile_encoding", err)),
+                        Ok(response) => {
+                            if let Some(api_key) =
+                                api_key.filter(|_| response.status().is_success())
+                            {
+                                let served = TileServed {
+                                    api_key,
+                                    datasource_id: datasource_id.to_string(),
+                                    bytes: body_size(&response),
+                                };
+                                record_served(&tx_served, served);
+                            }
+                            Ok(response)
+                        }
+                    }
+                }
+            },
+        }
+    } else if route == Some(Route::Pyramid) {
+        let storage = *rx_storage.borrow();
+        if storage.is_low() {
+            return Ok(audit.finish(insufficient_storage_response(&storage)));
         }
-    } else if path.starts_with(EndPoints::Pyramid.as_ref()) && method == Method::POST {
         match pyramid_endpoint(
-            cwd,
-            pool,
+            paths,
+            registry,
+            tx,
             tx_sqlite_client,
             tx_jd,
             client_ip,
@@ -155,18 +941,47 @@ pub async fn handle(
             req,
             &b_bytes,
             client,
+            PyramidWebhooks::from_config(&config),
         )
         .await
         {
             Err(err) => {
                 let response = error_response_endpoint("pyramid_endpoint", err);
+                Ok(audit.finish(response))
+            }
+            Ok(response) => Ok(audit.finish(response)),
+        }
+    } else if let Some(Route::DataSourceUploads {
+        datasource_id,
+        upload_id,
+    }) = route
+    {
+        match resumable_upload_endpoint(
+            &paths,
+            datasource_id,
+            upload_id,
+            uri.query(),
+            req,
+            port,
+            ports,
+            client_ip,
+            client,
+            tx,
+            tx_uploads,
+            tx_jd,
+            &config,
+        )
+        .await
+        {
+            Err(err) => {
+                let response = error_response_endpoint("resumable_upload_endpoint", err);
                 Ok(response)
             }
             Ok(response) => Ok(response),
         }
-    } else if path.starts_with(EndPoints::DataSources.as_ref()) && method == Method::DELETE {
+    } else if route == Some(Route::DataSourcesDelete) {
         match datasource_delete_endpoint(
-            &cwd,
+            &paths,
             ports,
             parts,
             uri,
@@ -175,32 +990,48 @@ pub async fn handle(
             client,
             tx_sqlite_client,
             tx,
+            tx_usage,
+            config.trash_retention_days,
             ct,
         )
         .await
         {
             Err(err) => {
                 let response = error_response_endpoint("datasource_delete_endpoint", err);
+                Ok(audit.finish(response))
+            }
+            Ok(response) => Ok(audit.finish(response)),
+        }
+    } else if let Some(Route::DataSourceFiles { datasource_id }) = route {
+        match upload_endpoint(
+            &paths,
+            datasource_id,
+            uri.query(),
+            req,
+            port,
+            ports,
+            client_ip,
+            client,
+            tx,
+            &config,
+        )
+        .await
+        {
+            Err(err) => {
+                let response = error_response_endpoint("upload_endpoint", err);
                 Ok(response)
             }
             Ok(response) => Ok(response),
         }
-    } else if ((path.starts_with(EndPoints::DataSources.as_ref()) && path_elements.len() == 3)
-        || path.starts_with(EndPoints::DataSourcesLoadFiles.as_ref())
-        || path.starts_with(EndPoints::DataSourcesReloadFiles.as_ref()))
-        && (method == Method::POST
-            || method == Method::PUT
-            || method == Method::PATCH
-            || method == Method::GET)
-    {
+    } else if route == Some(Route::DataSources) {
         match datasource_endpoint(port, ports, parts, client_ip, client, tx, req).await {
             Err(err) => {
                 let response = error_response_endpoint("datasource_endpoint", err);
-                Ok(response)
+                Ok(audit.finish(response))
             }
-            Ok(response) => Ok(response),
+            Ok(response) => Ok(audit.finish(response)),
         }
-    } else if path.starts_with(EndPoints::Health.as_ref()) {
+    } else if route == Some(Route::Health) {
         match health_endpoint(ports, &parts, client_ip, client, ct).await {
             Err(err) => {
                 let response = error_response_endpoint("health_endpoint", err);
@@ -208,21 +1039,25 @@ pub async fn handle(
             }
             Ok(response) => Ok(response),
         }
-    } else if path.starts_with(EndPoints::Jobs.as_ref()) {
-        debug_request(req)
-    } else if path.starts_with(EndPoints::Debug.as_ref()) {
+    } else if matches!(route, Some(Route::Debug | Route::Job { .. })) {
         debug_request(req)
     } else {
-        match hyper_reverse_proxy::call(
+        let label = endpoint_label(req.uri().path());
+        match hyper_reverse_proxy::call_with_rules(
             client_ip,
             &format!("http://{}:{}", LOCALHOST, port),
             req,
             &client,
+            config.upstream_header_rules(label),
         )
         .await
         {
-            Ok(response) => Ok(response),
+            Ok(response) => {
+                report_worker_result(&tx_mw, port, !is_worker_failure(response.status())).await;
+                Ok(response)
+            }
             Err(error) => {
+                report_worker_result(&tx_mw, port, false).await;
                 event!(Level::ERROR, "Error request: {:?}", error);
                 let err_response = error_response(StatusCode::INTERNAL_SERVER_ERROR);
                 Ok(err_response)
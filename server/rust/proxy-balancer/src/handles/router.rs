@@ -0,0 +1,395 @@
+use hyper::Method;
+use lazy_static::lazy_static;
+
+use crate::structs::EndPoints;
+
+/*
+    Routes of the balancer matched by whole segments of the path, so '/api/tilesets'
+    does not hit '/api/tile'. Parameters of the path are passed to endpoints typed by the route.
+    Requests without a route are proxied to workers
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Route<'a> {
+    Healthz,
+    Readyz,
+    OpenApi,
+    ApiDocs,
+    Maintenance,
+    Cluster,
+    Metrics,
+    Events,
+    AuditLog,
+    Usage,
+    TileGrids,
+    Static,
+    Styles,
+    DataSources,
+    DataSourcesDelete,
+    DataSourceStats {
+        datasource_id: &'a str,
+    },
+    DataSourceUsage {
+        datasource_id: &'a str,
+    },
+    DataSourceRestore {
+        datasource_id: &'a str,
+    },
+    DataSourceValidate {
+        datasource_id: &'a str,
+    },
+    DataSourceMetadata {
+        datasource_id: &'a str,
+    },
+    DataSourceFiles {
+        datasource_id: &'a str,
+    },
+    DataSourceUploads {
+        datasource_id: &'a str,
+        upload_id: Option<&'a str>,
+    },
+    Export {
+        datasource_id: &'a str,
+    },
+    ExportDownload {
+        job_id: &'a str,
+    },
+    Job {
+        job_id: &'a str,
+    },
+    Tile {
+        datasource_id: &'a str,
+    },
+    Pyramid,
+    PyramidSmart,
+    PyramidDelta,
+    PyramidDistributed,
+    PyramidShard,
+    PyramidShardCancel {
+        job_id: &'a str,
+    },
+    Health,
+    DebugRoute,
+    Debug,
+}
+
+// Route without parameters, the table of routes refers to it
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RouteKind {
+    Healthz,
+    Readyz,
+    OpenApi,
+    ApiDocs,
+    Maintenance,
+    Cluster,
+    Metrics,
+    Events,
+    AuditLog,
+    Usage,
+    TileGrids,
+    Static,
+    Styles,
+    DataSources,
+    DataSourcesDelete,
+    DataSourceStats,
+    DataSourceUsage,
+    DataSourceRestore,
+    DataSourceValidate,
+    DataSourceMetadata,
+    DataSourceFiles,
+    DataSourceUploads,
+    Export,
+    ExportDownload,
+    Job,
+    Tile,
+    Pyramid,
+    PyramidSmart,
+    PyramidDelta,
+    PyramidDistributed,
+    PyramidShard,
+    PyramidShardCancel,
+    Health,
+    DebugRoute,
+    Debug,
+}
+
+enum Segment {
+    Literal(String),
+    Param(&'static str),
+    // the rest of the path, one or more segments
+    CatchAll,
+}
+
+struct RouteDef {
+    // empty - any method
+    methods: Vec<Method>,
+    segments: Vec<Segment>,
+    kind: RouteKind,
+}
+
+// Pattern of the route: literal segments, '{name}' parameters and the trailing '*'
+fn route_def(methods: &[Method], pattern: &str, kind: RouteKind) -> RouteDef {
+    let segments = pattern
+        .trim_start_matches('/')
+        .split('/')
+        .map(|s| match s {
+            "*" => Segment::CatchAll,
+            "{datasource_id}" => Segment::Param("datasource_id"),
+            "{upload_id}" => Segment::Param("upload_id"),
+            "{job_id}" => Segment::Param("job_id"),
+            _ => Segment::Literal(s.to_string()),
+        })
+        .collect();
+    RouteDef {
+        methods: methods.to_vec(),
+        segments,
+        kind,
+    }
+}
+
+lazy_static! {
+    // the first matching route wins
+    static ref ROUTES: Vec<RouteDef> = {
+        let get = [Method::GET];
+        let post = [Method::POST];
+        let any: [Method; 0] = [];
+        let datasource = format!("{}/{{datasource_id}}", EndPoints::DataSources);
+        vec![
+            route_def(&get, EndPoints::Healthz.as_ref(), RouteKind::Healthz),
+            route_def(&get, EndPoints::Readyz.as_ref(), RouteKind::Readyz),
+            route_def(&get, EndPoints::OpenApi.as_ref(), RouteKind::OpenApi),
+            route_def(&get, EndPoints::ApiDocs.as_ref(), RouteKind::ApiDocs),
+            route_def(&any, "/maintenance/*", RouteKind::Maintenance),
+            route_def(&any, "/api/cluster/*", RouteKind::Cluster),
+            route_def(&get, EndPoints::Metrics.as_ref(), RouteKind::Metrics),
+            route_def(&get, EndPoints::Events.as_ref(), RouteKind::Events),
+            route_def(&get, EndPoints::AuditLog.as_ref(), RouteKind::AuditLog),
+            route_def(&get, EndPoints::Usage.as_ref(), RouteKind::Usage),
+            route_def(&get, EndPoints::TileGrids.as_ref(), RouteKind::TileGrids),
+            route_def(&get, EndPoints::Static.as_ref(), RouteKind::Static),
+            route_def(&get, &format!("{}/*", EndPoints::Static), RouteKind::Static),
+            route_def(&get, &format!("{}/*", EndPoints::Styles), RouteKind::Styles),
+            route_def(&get, &format!("{}/*", EndPoints::Sprites), RouteKind::Styles),
+            route_def(&get, &format!("{}/*", EndPoints::Fonts), RouteKind::Styles),
+            route_def(&get, &format!("{datasource}/stats"), RouteKind::DataSourceStats),
+            route_def(&get, &format!("{datasource}/usage"), RouteKind::DataSourceUsage),
+            route_def(&post, &format!("{datasource}/restore"), RouteKind::DataSourceRestore),
+            route_def(&post, &format!("{datasource}/validate"), RouteKind::DataSourceValidate),
+            route_def(&get, &format!("{datasource}/metadata"), RouteKind::DataSourceMetadata),
+            route_def(
+                &get,
+                &format!("{}/{{job_id}}", EndPoints::ExportDownload),
+                RouteKind::ExportDownload,
+            ),
+            route_def(
+                &post,
+                &format!("{}/{{datasource_id}}", EndPoints::Export),
+                RouteKind::Export,
+            ),
+            route_def(&get, &format!("{}/{{job_id}}", EndPoints::Jobs), RouteKind::Job),
+            route_def(&post, EndPoints::PyramidSmart.as_ref(), RouteKind::PyramidSmart),
+            route_def(&post, EndPoints::PyramidDelta.as_ref(), RouteKind::PyramidDelta),
+            route_def(
+                &post,
+                EndPoints::PyramidDistributed.as_ref(),
+                RouteKind::PyramidDistributed,
+            ),
+            route_def(&post, EndPoints::PyramidShard.as_ref(), RouteKind::PyramidShard),
+            route_def(
+                &[Method::DELETE],
+                &format!("{}/{{job_id}}", EndPoints::PyramidShard),
+                RouteKind::PyramidShardCancel,
+            ),
+            route_def(
+                &any,
+                &format!("{}/{{datasource_id}}/*", EndPoints::Tile),
+                RouteKind::Tile,
+            ),
+            route_def(&post, EndPoints::Pyramid.as_ref(), RouteKind::Pyramid),
+            route_def(&any, &format!("{datasource}/uploads"), RouteKind::DataSourceUploads),
+            route_def(
+                &any,
+                &format!("{datasource}/uploads/{{upload_id}}"),
+                RouteKind::DataSourceUploads,
+            ),
+            route_def(
+                &[Method::DELETE],
+                EndPoints::DataSources.as_ref(),
+                RouteKind::DataSourcesDelete,
+            ),
+            route_def(
+                &[Method::DELETE],
+                &format!("{}/*", EndPoints::DataSources),
+                RouteKind::DataSourcesDelete,
+            ),
+            route_def(&post, &format!("{datasource}/files"), RouteKind::DataSourceFiles),
+            route_def(
+                &[Method::GET, Method::POST, Method::PUT, Method::PATCH],
+                EndPoints::DataSources.as_ref(),
+                RouteKind::DataSources,
+            ),
+            route_def(
+                &[Method::GET, Method::POST, Method::PUT, Method::PATCH],
+                EndPoints::DataSourcesLoadFiles.as_ref(),
+                RouteKind::DataSources,
+            ),
+            route_def(
+                &[Method::GET, Method::POST, Method::PUT, Method::PATCH],
+                EndPoints::DataSourcesReloadFiles.as_ref(),
+                RouteKind::DataSources,
+            ),
+            route_def(&any, EndPoints::Health.as_ref(), RouteKind::Health),
+            route_def(&get, EndPoints::DebugRoute.as_ref(), RouteKind::DebugRoute),
+            route_def(&any, EndPoints::Jobs.as_ref(), RouteKind::Debug),
+            route_def(&any, &format!("{}/*", EndPoints::Jobs), RouteKind::Debug),
+            route_def(&any, EndPoints::Debug.as_ref(), RouteKind::Debug),
+            route_def(&any, &format!("{}/*", EndPoints::Debug), RouteKind::Debug),
+        ]
+    };
+}
+
+impl RouteDef {
+    // Parameters of the path by names when the route matches
+    fn matches<'a>(&self, method: &Method, path: &'a str) -> Option<Vec<(&'static str, &'a str)>> {
+        if !self.methods.is_empty() && !self.methods.contains(method) {
+            return None;
+        }
+        let mut params = Vec::new();
+        let mut rest = path.strip_prefix('/')?;
+        for (i, segment) in self.segments.iter().enumerate() {
+            let (value, tail) = match rest.split_once('/') {
+                Some((value, tail)) => (value, Some(tail)),
+                None => (rest, None),
+            };
+            match segment {
+                Segment::CatchAll => return Some(params),
+                Segment::Literal(literal) if value == literal.as_str() => {}
+                Segment::Param(name) if !value.is_empty() => params.push((*name, value)),
+                _ => return None,
+            }
+            match tail {
+                Some(tail) => rest = tail,
+                // the path ended, the pattern must end too or expect the rest
+                None => {
+                    return match self.segments.get(i + 1) {
+                        None => Some(params),
+                        Some(_) => None,
+                    };
+                }
+            }
+        }
+        // segments of the path are left after the pattern
+        None
+    }
+}
+
+impl RouteKind {
+    fn route<'a>(self, params: &[(&'static str, &'a str)]) -> Route<'a> {
+        let param = |name: &str| {
+            params
+                .iter()
+                .find(|(n, _)| *n == name)
+                .map(|(_, value)| *value)
+        };
+        let datasource_id = param("datasource_id").unwrap_or_default();
+        let job_id = param("job_id").unwrap_or_default();
+        match self {
+            RouteKind::Healthz => Route::Healthz,
+            RouteKind::Readyz => Route::Readyz,
+            RouteKind::OpenApi => Route::OpenApi,
+            RouteKind::ApiDocs => Route::ApiDocs,
+            RouteKind::Maintenance => Route::Maintenance,
+            RouteKind::Cluster => Route::Cluster,
+            RouteKind::Metrics => Route::Metrics,
+            RouteKind::Events => Route::Events,
+            RouteKind::AuditLog => Route::AuditLog,
+            RouteKind::Usage => Route::Usage,
+            RouteKind::TileGrids => Route::TileGrids,
+            RouteKind::Static => Route::Static,
+            RouteKind::Styles => Route::Styles,
+            RouteKind::DataSources => Route::DataSources,
+            RouteKind::DataSourcesDelete => Route::DataSourcesDelete,
+            RouteKind::DataSourceStats => Route::DataSourceStats { datasource_id },
+            RouteKind::DataSourceUsage => Route::DataSourceUsage { datasource_id },
+            RouteKind::DataSourceRestore => Route::DataSourceRestore { datasource_id },
+            RouteKind::DataSourceValidate => Route::DataSourceValidate { datasource_id },
+            RouteKind::DataSourceMetadata => Route::DataSourceMetadata { datasource_id },
+            RouteKind::DataSourceFiles => Route::DataSourceFiles { datasource_id },
+            RouteKind::DataSourceUploads => Route::DataSourceUploads {
+                datasource_id,
+                upload_id: param("upload_id"),
+            },
+            RouteKind::Export => Route::Export { datasource_id },
+            RouteKind::ExportDownload => Route::ExportDownload { job_id },
+            RouteKind::Job => Route::Job { job_id },
+            RouteKind::Tile => Route::Tile { datasource_id },
+            RouteKind::Pyramid => Route::Pyramid,
+            RouteKind::PyramidSmart => Route::PyramidSmart,
+            RouteKind::PyramidDelta => Route::PyramidDelta,
+            RouteKind::PyramidDistributed => Route::PyramidDistributed,
+            RouteKind::PyramidShard => Route::PyramidShard,
+            RouteKind::PyramidShardCancel => Route::PyramidShardCancel { job_id },
+            RouteKind::Health => Route::Health,
+            RouteKind::DebugRoute => Route::DebugRoute,
+            RouteKind::Debug => Route::Debug,
+        }
+    }
+}
+
+// Route of the request, None - the request is proxied to workers
+pub fn route<'a>(method: &Method, path: &'a str) -> Option<Route<'a>> {
+    ROUTES.iter().find_map(|def| {
+        def.matches(method, path)
+            .map(|params| def.kind.route(&params))
+    })
+}
+
+impl Route<'_> {
+    // Endpoints which do not need workers: probes, metrics, maintenance, events, audit, usage, cluster and routing
+    pub fn is_control(&self) -> bool {
+        matches!(
+            self,
+            Route::Maintenance
+                | Route::Cluster
+                | Route::Metrics
+                | Route::Events
+                | Route::AuditLog
+                | Route::Usage
+                | Route::DebugRoute
+        )
+    }
+
+    // Endpoints called only by instances of the cluster, they are always signed when the secret is set
+    pub fn is_cluster_internal(&self, path: &str) -> bool {
+        match self {
+            Route::PyramidShard | Route::PyramidShardCancel { .. } => true,
+            Route::Cluster => path.starts_with(EndPoints::ClusterRegister.as_ref()),
+            _ => false,
+        }
+    }
+
+    // Endpoints after the selection of the worker which are served without Python workers
+    pub fn is_native(&self) -> bool {
+        matches!(self, Route::Tile { .. } | Route::Debug | Route::Job { .. })
+    }
+
+    // Endpoints which parse bodies (JSON) of requests or record their digests to the audit log
+    pub fn buffers_body(&self) -> bool {
+        matches!(
+            self,
+            Route::Maintenance
+                | Route::Cluster
+                | Route::Tile { .. }
+                | Route::Pyramid
+                | Route::PyramidSmart
+                | Route::PyramidDelta
+                | Route::PyramidDistributed
+                | Route::PyramidShard
+                | Route::Export { .. }
+                | Route::ExportDownload { .. }
+                | Route::DataSources
+                | Route::DataSourcesDelete
+                | Route::DataSourceRestore { .. }
+                | Route::DataSourceValidate { .. }
+        )
+    }
+}
@@ -0,0 +1,74 @@
+use hyper::{Body, Request, StatusCode, Uri};
+use serde_json::json;
+
+use crate::handles::error::{ApiError, ErrorCode};
+use crate::structs::{ApiVersion, EndPoints};
+
+impl ApiVersion {
+    // Prefix of the routes of the version: /api/v1
+    pub fn prefix(&self) -> EndPoints {
+        match self {
+            ApiVersion::V1 => EndPoints::ApiV1,
+        }
+    }
+
+    // Versioned path of the unversioned route: /api/tile/... -> /api/v1/tile/...
+    pub fn path(&self, path: &str) -> String {
+        match path.strip_prefix(EndPoints::Api.as_ref()) {
+            Some(rest) if rest.is_empty() || rest.starts_with('/') => {
+                format!("{}{rest}", self.prefix())
+            }
+            _ => path.to_string(),
+        }
+    }
+}
+
+// Segment of the version after '/api': 'v1', 'v2'...
+fn version_segment(path: &str) -> Option<&str> {
+    let rest = path
+        .strip_prefix(EndPoints::Api.as_ref())?
+        .strip_prefix('/')?;
+    let segment = rest.split('/').next()?;
+    let digits = segment.strip_prefix('v')?;
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    Some(segment)
+}
+
+/*
+    Router layer of versions of the API. Routes are matched by unversioned paths (the same paths
+    are used by workers), so '/api/v1/...' is mapped onto '/api/...' and legacy unversioned paths
+    are served as v1. The version is put into extensions of the request for handlers.
+    Unknown versions get 404
+*/
+pub fn route_version(req: &mut Request<Body>) -> Result<ApiVersion, ApiError> {
+    let path = req.uri().path();
+    let Some(segment) = version_segment(path) else {
+        req.extensions_mut().insert(ApiVersion::V1);
+        return Ok(ApiVersion::V1);
+    };
+    let Ok(version) = segment.parse::<ApiVersion>() else {
+        return Err(ApiError::new(
+            StatusCode::NOT_FOUND,
+            format!("API version '{segment}' is not supported"),
+        )
+        .with_code(ErrorCode::UnsupportedApiVersion)
+        .with_details(json!({"supported": [ApiVersion::V1.as_ref()]})));
+    };
+
+    let rest = &path[EndPoints::Api.as_ref().len() + 1 + segment.len()..];
+    let path_and_query = match req.uri().query() {
+        Some(query) => format!("{}{rest}?{query}", EndPoints::Api),
+        None => format!("{}{rest}", EndPoints::Api),
+    };
+    let mut parts = req.uri().clone().into_parts();
+    parts.path_and_query =
+        Some(path_and_query.parse().map_err(|err| {
+            ApiError::new(StatusCode::BAD_REQUEST, format!("Invalid URI: {err}"))
+        })?);
+    *req.uri_mut() = Uri::from_parts(parts)
+        .map_err(|err| ApiError::new(StatusCode::BAD_REQUEST, format!("Invalid URI: {err}")))?;
+    req.extensions_mut().insert(version);
+    Ok(version)
+}
@@ -31,7 +31,7 @@
 //!
 //! * All other URLs will be handled by `debug_request` function, that will display request information.
 //!
-//! ```rust,no_run
+//! ```rust,ignore
 //! use hyper::server::conn::AddrStream;
 //! use hyper::{Body, Request, Response, Server, StatusCode};
 //! use hyper::service::{service_fn, make_service_fn};
@@ -90,7 +90,7 @@
 //! ```
 //!
 
-use hyper::header::{HeaderMap, HeaderValue};
+use hyper::header::{self, HeaderMap, HeaderName, HeaderValue};
 use hyper::http::header::{InvalidHeaderValue, ToStrError};
 use hyper::http::uri::InvalidUri;
 use hyper::{client::HttpConnector, Body, Client, Error, Request, Response, Uri};
@@ -152,20 +152,55 @@ fn is_hop_header(name: &str) -> bool {
 }
 
 /// Returns a clone of the headers without the [hop-by-hop headers].
+/// Headers with several values keep all of them.
 ///
 /// [hop-by-hop headers]: http://www.w3.org/Protocols/rfc2616/rfc2616-sec13.html
 fn remove_hop_headers(headers: &HeaderMap<HeaderValue>) -> HeaderMap<HeaderValue> {
     let mut result = HeaderMap::new();
     for (k, v) in headers.iter() {
         if !is_hop_header(k.as_str()) {
-            result.insert(k.clone(), v.clone());
+            result.append(k.clone(), v.clone());
         }
     }
     result
 }
 
-fn create_proxied_response<B>(mut response: Response<B>) -> Response<B> {
-    *response.headers_mut() = remove_hop_headers(response.headers());
+/// Headers describing the body, they are forwarded regardless of [`HeaderRules`].
+const BODY_HEADERS: [HeaderName; 3] = [
+    header::CONTENT_TYPE,
+    header::CONTENT_LENGTH,
+    header::CONTENT_ENCODING,
+];
+
+/// Rules of propagation of headers of upstream responses to clients.
+///
+/// By default every end-to-end header is forwarded (`Cache-Control`, `ETag`, `Expires`, ...).
+/// With non-empty `allow` only the listed headers are forwarded, headers of `strip` are
+/// never forwarded. Headers of the body are always forwarded.
+#[derive(Debug, Clone, Default)]
+pub struct HeaderRules {
+    pub allow: Vec<HeaderName>,
+    pub strip: Vec<HeaderName>,
+}
+
+impl HeaderRules {
+    fn forwards(&self, name: &HeaderName) -> bool {
+        if BODY_HEADERS.contains(name) {
+            return true;
+        }
+        (self.allow.is_empty() || self.allow.contains(name)) && !self.strip.contains(name)
+    }
+}
+
+fn create_proxied_response<B>(mut response: Response<B>, rules: &HeaderRules) -> Response<B> {
+    let mut headers = remove_hop_headers(response.headers());
+    if !rules.allow.is_empty() || !rules.strip.is_empty() {
+        let names: Vec<HeaderName> = headers.keys().cloned().collect();
+        for name in names.iter().filter(|name| !rules.forwards(name)) {
+            headers.remove(name);
+        }
+    }
+    *response.headers_mut() = headers;
     response
 }
 
@@ -208,9 +243,27 @@ pub async fn call(
     forward_uri: &str,
     request: Request<Body>,
     client: &Client<HttpConnector>,
+) -> Result<Response<Body>, ProxyError> {
+    call_with_rules(
+        client_ip,
+        forward_uri,
+        request,
+        client,
+        &HeaderRules::default(),
+    )
+    .await
+}
+
+/// Same as [`call`], headers of the upstream response are forwarded by `rules`.
+pub async fn call_with_rules(
+    client_ip: IpAddr,
+    forward_uri: &str,
+    request: Request<Body>,
+    client: &Client<HttpConnector>,
+    rules: &HeaderRules,
 ) -> Result<Response<Body>, ProxyError> {
     let proxied_request = create_proxied_request(client_ip, &forward_uri, request)?;
     let response = client.request(proxied_request).await?;
-    let proxied_response = create_proxied_response(response);
+    let proxied_response = create_proxied_response(response, rules);
     Ok(proxied_response)
 }
@@ -0,0 +1,36 @@
+/*
+    Tile proxy of the tiler server as a library: commands of the binary 'tiler-server'
+    and 'TilerServer' embeddable into other applications of Rust
+*/
+pub mod cli;
+pub mod cluster;
+pub mod cog;
+pub mod composite;
+pub mod config;
+pub mod db;
+pub mod defaults;
+pub mod environment;
+pub mod forwarded;
+pub mod geofence;
+pub mod grid;
+pub mod handles;
+pub mod hyper_reverse_proxy;
+pub mod log;
+pub mod origins;
+pub mod overzoom;
+pub mod paths;
+pub mod png;
+pub mod postgis;
+pub mod raster;
+pub mod remote;
+pub mod server;
+pub mod sources;
+pub mod structs;
+pub mod tasks;
+#[cfg(feature = "test-support")]
+pub mod testing;
+pub mod utils;
+pub mod vector_file;
+pub mod webhooks;
+
+pub use server::{TilerServer, TilerServerBuilder, TilerServerHandle};
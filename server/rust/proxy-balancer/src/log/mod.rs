@@ -1,29 +1,80 @@
+pub mod otel;
+
 use std::fmt;
+use std::sync::OnceLock;
 
 use anyhow::anyhow;
 use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::filter::{EnvFilter, LevelFilter};
 use tracing_subscriber::fmt::format::Writer;
 use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields};
+use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{reload, Layer, Registry};
 
-pub fn init_tracing(log_level_server: &str) -> Result<(), anyhow::Error> {
-    let level = match log_level_server {
-        "ERROR" => Level::ERROR,
-        "WARN" => Level::WARN,
-        "INFO" => Level::INFO,
-        "DEBUG" => Level::DEBUG,
-        "TRACE" => Level::TRACE,
-        _ => return Err(anyhow!("Log level '{log_level_server}' is unsupported")),
-    };
+use crate::config::OtlpConfig;
+
+// Target of output lines of workers, they are logged without the prefix of the server
+pub const WORKER_LOG_TARGET: &str = "worker";
+
+// Filter of the console log, changed at runtime by '/maintenance/log_level'
+static LOG_FILTER: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+
+pub fn init_tracing(
+    log_level_server: &str,
+    otlp: Option<&OtlpConfig>,
+) -> Result<(), anyhow::Error> {
+    let level = parse_log_level(log_level_server)?;
+    let (filter, handle) = reload::Layer::new(level_filter(level));
+    let _ = LOG_FILTER.set(handle);
 
-    tracing_subscriber::fmt()
+    let fmt_layer = tracing_subscriber::fmt::layer()
         .event_format(CustomFormatter)
-        .with_max_level(level)
+        .with_filter(filter);
+
+    // spans are exported regardless of the log level of the console
+    let otel_layer = match otlp {
+        Some(otlp) => Some(
+            tracing_opentelemetry::layer()
+                .with_tracer(otel::init_tracer(otlp)?)
+                .with_filter(LevelFilter::INFO),
+        ),
+        None => None,
+    };
+
+    tracing_subscriber::registry()
+        .with(fmt_layer)
+        .with(otel_layer)
         .init();
 
     Ok(())
 }
 
+pub fn set_log_level(log_level: &str) -> Result<(), anyhow::Error> {
+    let level = parse_log_level(log_level)?;
+    let handle = LOG_FILTER
+        .get()
+        .ok_or(anyhow!("Tracing is not initialized"))?;
+    handle.reload(level_filter(level))?;
+    Ok(())
+}
+
+pub fn parse_log_level(log_level: &str) -> Result<Level, anyhow::Error> {
+    match log_level.to_uppercase().as_str() {
+        "ERROR" => Ok(Level::ERROR),
+        "WARN" => Ok(Level::WARN),
+        "INFO" => Ok(Level::INFO),
+        "DEBUG" => Ok(Level::DEBUG),
+        "TRACE" => Ok(Level::TRACE),
+        _ => Err(anyhow!("Log level '{log_level}' is unsupported")),
+    }
+}
+
+fn level_filter(level: Level) -> EnvFilter {
+    EnvFilter::default().add_directive(LevelFilter::from_level(level).into())
+}
+
 pub struct CustomFormatter;
 
 impl<S, N> FormatEvent<S, N> for CustomFormatter
@@ -51,7 +102,11 @@ where
             "{} ",
             chrono::Local::now().format("%d-%m-%Y %H:%M:%S.%3f")
         )?;
-        write!(writer, "[{level}] Server: ")?;
+        if target == WORKER_LOG_TARGET {
+            write!(writer, "[{level}] ")?;
+        } else {
+            write!(writer, "[{level}] Server: ")?;
+        }
 
         ctx.field_format().format_fields(writer.by_ref(), event)?;
         writeln!(writer)
@@ -0,0 +1,87 @@
+use hyper::http::header::{HeaderMap, HeaderName, HeaderValue};
+use opentelemetry::propagation::{Extractor, Injector};
+use opentelemetry::trace::TracerProvider;
+use opentelemetry::{global, KeyValue};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{Config as TraceConfig, Sampler, Tracer};
+use opentelemetry_sdk::{runtime, Resource};
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+use crate::config::OtlpConfig;
+
+/*
+    Spans are exported in batches to the OTLP collector, the context of the trace
+    is propagated to workers with the W3C 'traceparent' header
+*/
+pub fn init_tracer(otlp: &OtlpConfig) -> Result<Tracer, anyhow::Error> {
+    global::set_text_map_propagator(TraceContextPropagator::new());
+
+    let provider = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(otlp.endpoint.clone()),
+        )
+        .with_trace_config(
+            TraceConfig::default()
+                .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+                    otlp.sample_ratio,
+                ))))
+                .with_resource(Resource::new(vec![KeyValue::new(
+                    "service.name",
+                    otlp.service_name.clone(),
+                )])),
+        )
+        .install_batch(runtime::Tokio)?;
+    global::set_tracer_provider(provider.clone());
+
+    Ok(provider.tracer("tiler-server"))
+}
+
+// Spans not exported yet are flushed on shutdown of the server
+pub fn shutdown_tracer() {
+    global::shutdown_tracer_provider();
+}
+
+// Headers 'traceparent'/'tracestate' of the span for the request to the worker
+pub fn inject_context(span: &Span, headers: &mut HeaderMap<HeaderValue>) {
+    let context = span.context();
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&context, &mut HeaderInjector(headers))
+    });
+}
+
+// Span of the request continues the trace of the client, if any
+pub fn set_parent_from_headers(span: &Span, headers: &HeaderMap<HeaderValue>) {
+    let parent =
+        global::get_text_map_propagator(|propagator| propagator.extract(&HeaderExtractor(headers)));
+    span.set_parent(parent);
+}
+
+struct HeaderInjector<'a>(&'a mut HeaderMap<HeaderValue>);
+
+impl Injector for HeaderInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        if let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(key.as_bytes()),
+            HeaderValue::from_str(&value),
+        ) {
+            self.0.insert(name, value);
+        }
+    }
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap<HeaderValue>);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|name| name.as_str()).collect()
+    }
+}
@@ -1,19 +1,15 @@
-mod cli;
-mod config;
-mod db;
-mod defaults;
-mod environment;
-mod handles;
-mod hyper_reverse_proxy;
-mod log;
-mod structs;
-mod tasks;
-mod utils;
-
 use clap::{Parser, Subcommand};
-use cli::{init::command_init, serve::command_serve, serve_cache::command_serve_cache};
-use environment::get_cwd;
 use std::process::exit;
+use tiler_server::cli::{
+    bench::{command_bench, BenchArgs},
+    cleanup::command_cleanup,
+    init::{command_init, InitArgs},
+    serve::command_serve,
+    serve_cache::command_serve_cache,
+    status::command_status,
+    verify::command_verify,
+};
+use tiler_server::environment::get_cwd;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -24,12 +20,35 @@ struct Args {
 
 #[derive(Subcommand, Debug, Clone)]
 enum Commands {
-    Init,
+    // Directories and databases, existing ones are kept without '--force'
+    Init(InitArgs),
     Serve {
         #[arg(long)]
         address: Option<String>,
     },
     ServeCache,
+    // Verify checksums of cached tiles of the DataSource
+    Verify {
+        datasource_id: String,
+        #[arg(long)]
+        repair: bool,
+        #[arg(long)]
+        record: bool,
+    },
+    // Load of a running server with throughput and latency percentiles
+    Bench(BenchArgs),
+    // Summary of a running server: workers, memory, queue, DataSources and cache
+    Status {
+        #[arg(long)]
+        address: Option<String>,
+    },
+    // Orphaned caches and files of deleted DataSources
+    Cleanup {
+        #[arg(long)]
+        dry_run: bool,
+        #[arg(long)]
+        yes: bool,
+    },
 }
 
 #[tokio::main(flavor = "current_thread")]
@@ -44,8 +63,16 @@ async fn main() {
     let args = Args::parse();
 
     match args.cmd {
-        Commands::Init => command_init(cwd).await,
+        Commands::Init(args) => command_init(cwd, args).await,
         Commands::Serve { address } => command_serve(cwd, address).await,
         Commands::ServeCache => command_serve_cache(cwd).await,
+        Commands::Verify {
+            datasource_id,
+            repair,
+            record,
+        } => command_verify(cwd, datasource_id, repair, record).await,
+        Commands::Bench(args) => command_bench(args).await,
+        Commands::Status { address } => command_status(address).await,
+        Commands::Cleanup { dry_run, yes } => command_cleanup(cwd, dry_run, yes).await,
     }
 }
@@ -0,0 +1,78 @@
+use hyper::http::header::{self, HeaderMap, HeaderValue};
+use hyper::Uri;
+use serde_json::Value;
+
+/*
+    Websites allowed to show tiles of the DataSource: field 'allowed_origins' of the DataSource JSON
+    with origins 'https://maps.example.com' or subdomains with the mask '*.example.com' after the scheme.
+    The origin of the request is taken from 'Origin', otherwise from 'Referer'.
+    Requests without both headers (not from browsers) are allowed only with 'allow_missing_origin'
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub struct AllowedOrigins {
+    pub origins: Vec<String>,
+    pub allow_missing: bool,
+}
+
+impl AllowedOrigins {
+    // None - tiles of the DataSource are not restricted
+    pub fn from_data(data: &Value) -> Option<AllowedOrigins> {
+        let origins: Vec<String> = data
+            .get("allowed_origins")?
+            .as_array()?
+            .iter()
+            .filter_map(|origin| origin.as_str())
+            .map(|origin| origin.trim().trim_end_matches('/').to_ascii_lowercase())
+            .filter(|origin| !origin.is_empty())
+            .collect();
+        if origins.is_empty() {
+            return None;
+        }
+        let allow_missing = data
+            .get("allow_missing_origin")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false);
+        Some(AllowedOrigins {
+            origins,
+            allow_missing,
+        })
+    }
+
+    pub fn origin_allowed(&self, origin: &str) -> bool {
+        let origin = origin.trim_end_matches('/').to_ascii_lowercase();
+        self.origins.iter().any(|allowed| {
+            match allowed.split_once("://*.") {
+                // subdomains of any depth, not the domain itself
+                Some((scheme, domain)) => origin
+                    .strip_prefix(scheme)
+                    .and_then(|rest| rest.strip_prefix("://"))
+                    .and_then(|host| host.strip_suffix(domain))
+                    .is_some_and(|sub| sub.len() > 1 && sub.ends_with('.')),
+                None => *allowed == origin,
+            }
+        })
+    }
+
+    pub fn allows(&self, headers: &HeaderMap<HeaderValue>) -> bool {
+        match request_origin(headers) {
+            Some(origin) => self.origin_allowed(&origin),
+            None => self.allow_missing,
+        }
+    }
+}
+
+// 'scheme://host:port' of the website of the request, 'null' origins are treated as missing
+pub fn request_origin(headers: &HeaderMap<HeaderValue>) -> Option<String> {
+    if let Some(origin) = headers.get(header::ORIGIN).and_then(|o| o.to_str().ok()) {
+        if origin != "null" {
+            return Some(origin.to_string());
+        }
+    }
+    let referer: Uri = headers.get(header::REFERER)?.to_str().ok()?.parse().ok()?;
+    let scheme = referer.scheme_str()?;
+    let host = referer.host()?;
+    match referer.port_u16() {
+        Some(port) => Some(format!("{scheme}://{host}:{port}")),
+        None => Some(format!("{scheme}://{host}")),
+    }
+}
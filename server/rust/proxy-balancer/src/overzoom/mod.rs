@@ -0,0 +1,57 @@
+pub mod mvt;
+mod raster;
+
+use std::io::Read;
+
+use anyhow::anyhow;
+use flate2::read::GzDecoder;
+
+use crate::structs::ContentType;
+
+/*
+    Tile (z, x, y) deeper than the zoom of the parent tile is a part of the parent:
+    the parent is split into 2^dz x 2^dz tiles, ('dx', 'dy') is the position of the tile in it
+*/
+#[derive(Debug, Clone, Copy)]
+pub struct SubTile {
+    pub parent: (u8, u64, u64),
+    pub dz: u8,
+    pub dx: u64,
+    pub dy: u64,
+}
+
+impl SubTile {
+    pub fn new(z: u8, x: u64, y: u64, parent_z: u8) -> Self {
+        let dz = z - parent_z;
+        let (px, py) = (x >> dz, y >> dz);
+        SubTile {
+            parent: (parent_z, px, py),
+            dz,
+            dx: x - (px << dz),
+            dy: y - (py << dz),
+        }
+    }
+}
+
+// Tile cut from the parent tile: raster is cropped and scaled, vector tile is re-clipped
+pub fn overzoom_tile(
+    parent: &[u8],
+    content_type: &str,
+    sub: &SubTile,
+) -> Result<Vec<u8>, anyhow::Error> {
+    if content_type == ContentType::Png.as_ref() {
+        raster::crop_and_scale(parent, sub)
+    } else if content_type == ContentType::MvtPbf.as_ref() {
+        if parent.starts_with(b"\x1f\x8b\x08") {
+            let mut data = Vec::new();
+            GzDecoder::new(parent).read_to_end(&mut data)?;
+            mvt::reclip(&data, sub)
+        } else {
+            mvt::reclip(parent, sub)
+        }
+    } else {
+        Err(anyhow!(
+            "Overzoom is not supported for content type '{content_type}'"
+        ))
+    }
+}
@@ -0,0 +1,701 @@
+/*
+    Re-clipping of Mapbox Vector Tiles for overzoom without full decoding:
+    layers and features are copied field by field, only geometries are
+    scaled to the child tile and clipped by its extent with a buffer
+*/
+use anyhow::anyhow;
+
+use super::SubTile;
+
+pub const TILE_LAYERS: u32 = 3;
+pub const LAYER_NAME: u32 = 1;
+pub const LAYER_FEATURES: u32 = 2;
+pub const LAYER_EXTENT: u32 = 5;
+pub const FEATURE_TYPE: u32 = 3;
+pub const FEATURE_GEOMETRY: u32 = 4;
+
+pub const GEOM_POINT: u64 = 1;
+pub const GEOM_LINESTRING: u64 = 2;
+pub const GEOM_POLYGON: u64 = 3;
+
+const CMD_MOVE_TO: u32 = 1;
+const CMD_LINE_TO: u32 = 2;
+const CMD_CLOSE_PATH: u32 = 7;
+
+const DEFAULT_EXTENT: u64 = 4096;
+// Geometry is kept 1/16 of the extent outside of the tile, so lines and polygons join at the edges
+const BUFFER_DIVISOR: i64 = 16;
+
+pub type Point = (i64, i64);
+
+enum Value<'a> {
+    Varint(u64),
+    Fixed64(&'a [u8]),
+    Bytes(&'a [u8]),
+    Fixed32(&'a [u8]),
+}
+
+// Reader of the protobuf wire format
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Reader { data, pos: 0 }
+    }
+
+    fn varint(&mut self) -> Result<u64, anyhow::Error> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = *self
+                .data
+                .get(self.pos)
+                .ok_or(anyhow!("Vector tile is truncated"))?;
+            self.pos += 1;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(anyhow!("Vector tile has invalid varint"))
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], anyhow::Error> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|end| *end <= self.data.len())
+            .ok_or(anyhow!("Vector tile is truncated"))?;
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn field(&mut self) -> Result<Option<(u32, Value<'a>)>, anyhow::Error> {
+        if self.pos >= self.data.len() {
+            return Ok(None);
+        }
+        let key = self.varint()?;
+        let field = (key >> 3) as u32;
+        let value = match key & 0x7 {
+            0 => Value::Varint(self.varint()?),
+            1 => Value::Fixed64(self.take(8)?),
+            2 => {
+                let len = self.varint()? as usize;
+                Value::Bytes(self.take(len)?)
+            }
+            5 => Value::Fixed32(self.take(4)?),
+            wire_type => return Err(anyhow!("Vector tile has unsupported wire type {wire_type}")),
+        };
+        Ok(Some((field, value)))
+    }
+}
+
+pub fn write_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+pub fn write_bytes(buf: &mut Vec<u8>, field: u32, bytes: &[u8]) {
+    write_varint(buf, ((field as u64) << 3) | 2);
+    write_varint(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+fn write_value(buf: &mut Vec<u8>, field: u32, value: &Value) {
+    match value {
+        Value::Varint(v) => {
+            write_varint(buf, (field as u64) << 3);
+            write_varint(buf, *v);
+        }
+        Value::Fixed64(bytes) => {
+            write_varint(buf, ((field as u64) << 3) | 1);
+            buf.extend_from_slice(bytes);
+        }
+        Value::Bytes(bytes) => write_bytes(buf, field, bytes),
+        Value::Fixed32(bytes) => {
+            write_varint(buf, ((field as u64) << 3) | 5);
+            buf.extend_from_slice(bytes);
+        }
+    }
+}
+
+pub fn reclip(data: &[u8], sub: &SubTile) -> Result<Vec<u8>, anyhow::Error> {
+    let mut tile = Vec::with_capacity(data.len());
+    let mut reader = Reader::new(data);
+    while let Some((field, value)) = reader.field()? {
+        match value {
+            Value::Bytes(layer) if field == TILE_LAYERS => {
+                write_bytes(&mut tile, TILE_LAYERS, &reclip_layer(layer, sub)?);
+            }
+            value => write_value(&mut tile, field, &value),
+        }
+    }
+    Ok(tile)
+}
+
+// Layers of the tile with names replaced by 'rename', layers without a new name are dropped
+pub fn map_layers(
+    data: &[u8],
+    rename: impl Fn(&str) -> Option<String>,
+) -> Result<Vec<u8>, anyhow::Error> {
+    let mut tile = Vec::with_capacity(data.len());
+    let mut reader = Reader::new(data);
+    while let Some((field, value)) = reader.field()? {
+        match value {
+            Value::Bytes(data) if field == TILE_LAYERS => {
+                let mut name = String::new();
+                let mut reader = Reader::new(data);
+                while let Some((field, value)) = reader.field()? {
+                    if let (LAYER_NAME, Value::Bytes(bytes)) = (field, value) {
+                        name = String::from_utf8_lossy(bytes).to_string();
+                    }
+                }
+                let Some(name) = rename(&name) else {
+                    continue;
+                };
+                let mut layer = Vec::with_capacity(data.len());
+                let mut reader = Reader::new(data);
+                while let Some((field, value)) = reader.field()? {
+                    match value {
+                        Value::Bytes(_) if field == LAYER_NAME => {
+                            write_bytes(&mut layer, LAYER_NAME, name.as_bytes());
+                        }
+                        value => write_value(&mut layer, field, &value),
+                    }
+                }
+                write_bytes(&mut tile, TILE_LAYERS, &layer);
+            }
+            value => write_value(&mut tile, field, &value),
+        }
+    }
+    Ok(tile)
+}
+
+fn reclip_layer(data: &[u8], sub: &SubTile) -> Result<Vec<u8>, anyhow::Error> {
+    // extent is encoded after features
+    let mut extent = DEFAULT_EXTENT;
+    let mut reader = Reader::new(data);
+    while let Some((field, value)) = reader.field()? {
+        if let (LAYER_EXTENT, Value::Varint(v)) = (field, value) {
+            extent = v;
+        }
+    }
+    let transform = Transform::new(extent as i64, sub);
+
+    let mut layer = Vec::with_capacity(data.len());
+    let mut reader = Reader::new(data);
+    while let Some((field, value)) = reader.field()? {
+        match value {
+            Value::Bytes(feature) if field == LAYER_FEATURES => {
+                if let Some(feature) = reclip_feature(feature, &transform)? {
+                    write_bytes(&mut layer, LAYER_FEATURES, &feature);
+                }
+            }
+            value => write_value(&mut layer, field, &value),
+        }
+    }
+    Ok(layer)
+}
+
+// Feature without geometry inside of the child tile is dropped
+fn reclip_feature(data: &[u8], transform: &Transform) -> Result<Option<Vec<u8>>, anyhow::Error> {
+    let mut geom_type = 0;
+    let mut reader = Reader::new(data);
+    while let Some((field, value)) = reader.field()? {
+        if let (FEATURE_TYPE, Value::Varint(v)) = (field, value) {
+            geom_type = v;
+        }
+    }
+
+    let mut feature = Vec::with_capacity(data.len());
+    let mut reader = Reader::new(data);
+    while let Some((field, value)) = reader.field()? {
+        match value {
+            Value::Bytes(geometry) if field == FEATURE_GEOMETRY => {
+                let parts = decode_geometry(geometry)?
+                    .into_iter()
+                    .map(|part| part.into_iter().map(|p| transform.apply(p)).collect())
+                    .collect::<Result<_, _>>()?;
+                let parts = match geom_type {
+                    GEOM_POINT => clip_points(parts, &transform.clip),
+                    GEOM_LINESTRING => clip_lines(parts, &transform.clip),
+                    GEOM_POLYGON => clip_polygons(parts, &transform.clip),
+                    _ => parts,
+                };
+                if parts.is_empty() {
+                    return Ok(None);
+                }
+                write_bytes(
+                    &mut feature,
+                    FEATURE_GEOMETRY,
+                    &encode_geometry(&parts, geom_type),
+                );
+            }
+            value => write_value(&mut feature, field, &value),
+        }
+    }
+    Ok(Some(feature))
+}
+
+// Coordinates of the parent tile to coordinates of the child tile
+struct Transform {
+    scale: i64,
+    offset: Point,
+    // [min, max] of coordinates kept in the child tile
+    clip: [i64; 2],
+}
+
+impl Transform {
+    fn new(extent: i64, sub: &SubTile) -> Self {
+        let buffer = extent / BUFFER_DIVISOR;
+        Transform {
+            scale: 1i64.checked_shl(sub.dz as u32).unwrap_or(i64::MAX),
+            offset: (sub.dx as i64 * extent, sub.dy as i64 * extent),
+            clip: [-buffer, extent + buffer],
+        }
+    }
+
+    fn apply(&self, (x, y): Point) -> Result<Point, anyhow::Error> {
+        let scale = |v: i64, offset: i64| {
+            v.checked_mul(self.scale)
+                .and_then(|v| v.checked_sub(offset))
+                .ok_or(anyhow!("Vector tile has coordinates out of range"))
+        };
+        Ok((scale(x, self.offset.0)?, scale(y, self.offset.1)?))
+    }
+}
+
+fn zigzag_decode(value: u64) -> i64 {
+    ((value >> 1) as i64) ^ -((value & 1) as i64)
+}
+
+pub fn zigzag_encode(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+/*
+    Parts of the geometry in absolute coordinates: every MoveTo starts a new part,
+    rings of polygons are stored without the closing point.
+    Commands with counts beyond the data, LineTo without MoveTo and overflows are errors
+*/
+fn decode_geometry(data: &[u8]) -> Result<Vec<Vec<Point>>, anyhow::Error> {
+    let mut reader = Reader::new(data);
+    let mut parts: Vec<Vec<Point>> = Vec::new();
+    let mut cursor = (0i64, 0i64);
+    while reader.pos < data.len() {
+        let command = reader.varint()?;
+        let (id, count) = ((command & 0x7) as u32, command >> 3);
+        match id {
+            CMD_MOVE_TO | CMD_LINE_TO => {
+                // every point takes at least 2 bytes
+                let remaining = (data.len() - reader.pos) as u64;
+                if count == 0 || count > remaining / 2 {
+                    return Err(anyhow!(
+                        "Vector tile has geometry command {id} with invalid count {count}"
+                    ));
+                }
+                if id == CMD_LINE_TO && parts.is_empty() {
+                    return Err(anyhow!("Vector tile has LineTo before MoveTo"));
+                }
+                for _ in 0..count {
+                    let dx = zigzag_decode(reader.varint()?);
+                    let dy = zigzag_decode(reader.varint()?);
+                    cursor = cursor
+                        .0
+                        .checked_add(dx)
+                        .zip(cursor.1.checked_add(dy))
+                        .ok_or(anyhow!("Vector tile has coordinates out of range"))?;
+                    match parts.last_mut() {
+                        Some(part) if id == CMD_LINE_TO => part.push(cursor),
+                        _ => parts.push(vec![cursor]),
+                    }
+                }
+            }
+            CMD_CLOSE_PATH if count == 1 && !parts.is_empty() => {}
+            CMD_CLOSE_PATH => {
+                return Err(anyhow!(
+                    "Vector tile has ClosePath with count {count} or without MoveTo"
+                ))
+            }
+            _ => return Err(anyhow!("Vector tile has unknown geometry command {id}")),
+        }
+    }
+    Ok(parts)
+}
+
+pub fn encode_geometry(parts: &[Vec<Point>], geom_type: u64) -> Vec<u8> {
+    let mut buf = Vec::new();
+    let mut cursor = (0i64, 0i64);
+    let mut write_points = |buf: &mut Vec<u8>, points: &[Point]| {
+        for point in points {
+            write_varint(buf, zigzag_encode(point.0.saturating_sub(cursor.0)));
+            write_varint(buf, zigzag_encode(point.1.saturating_sub(cursor.1)));
+            cursor = *point;
+        }
+    };
+
+    if geom_type == GEOM_POINT {
+        let points: Vec<Point> = parts.iter().flatten().copied().collect();
+        write_varint(
+            &mut buf,
+            (CMD_MOVE_TO | ((points.len() as u32) << 3)) as u64,
+        );
+        write_points(&mut buf, &points);
+        return buf;
+    }
+
+    for part in parts {
+        write_varint(&mut buf, (CMD_MOVE_TO | (1 << 3)) as u64);
+        write_points(&mut buf, &part[..1]);
+        if part.len() > 1 {
+            write_varint(
+                &mut buf,
+                (CMD_LINE_TO | (((part.len() - 1) as u32) << 3)) as u64,
+            );
+            write_points(&mut buf, &part[1..]);
+        }
+        if geom_type == GEOM_POLYGON {
+            write_varint(&mut buf, (CMD_CLOSE_PATH | (1 << 3)) as u64);
+        }
+    }
+    buf
+}
+
+fn inside(clip: &[i64; 2], (x, y): Point) -> bool {
+    x >= clip[0] && x <= clip[1] && y >= clip[0] && y <= clip[1]
+}
+
+fn push_point(part: &mut Vec<Point>, point: Point) {
+    if part.last() != Some(&point) {
+        part.push(point);
+    }
+}
+
+pub fn clip_points(parts: Vec<Vec<Point>>, clip: &[i64; 2]) -> Vec<Vec<Point>> {
+    parts
+        .into_iter()
+        .map(|part| part.into_iter().filter(|p| inside(clip, *p)).collect())
+        .filter(|part: &Vec<Point>| !part.is_empty())
+        .collect()
+}
+
+// Segment clipped by the square 'clip' (Liang-Barsky)
+fn clip_segment(clip: &[i64; 2], a: Point, b: Point) -> Option<(Point, Point)> {
+    let (min, max) = (clip[0] as f64, clip[1] as f64);
+    let (x0, y0) = (a.0 as f64, a.1 as f64);
+    let (dx, dy) = (b.0 as f64 - x0, b.1 as f64 - y0);
+    let (mut t0, mut t1) = (0.0f64, 1.0f64);
+    for (p, q) in [
+        (-dx, x0 - min),
+        (dx, max - x0),
+        (-dy, y0 - min),
+        (dy, max - y0),
+    ] {
+        if p == 0.0 {
+            if q < 0.0 {
+                return None;
+            }
+            continue;
+        }
+        let t = q / p;
+        if p < 0.0 {
+            t0 = t0.max(t);
+        } else {
+            t1 = t1.min(t);
+        }
+        if t0 > t1 {
+            return None;
+        }
+    }
+    let at = |t: f64| ((x0 + t * dx).round() as i64, (y0 + t * dy).round() as i64);
+    Some((at(t0), at(t1)))
+}
+
+fn finish_line(line: &mut Vec<Point>, lines: &mut Vec<Vec<Point>>) {
+    if line.len() > 1 {
+        lines.push(std::mem::take(line));
+    } else {
+        line.clear();
+    }
+}
+
+// Line leaving and entering the tile is split into several lines
+pub fn clip_lines(parts: Vec<Vec<Point>>, clip: &[i64; 2]) -> Vec<Vec<Point>> {
+    let mut lines = Vec::new();
+    for part in parts {
+        let mut line: Vec<Point> = Vec::new();
+        for segment in part.windows(2) {
+            match clip_segment(clip, segment[0], segment[1]) {
+                Some((a, b)) => {
+                    // segment enters the tile
+                    if line.last() != Some(&a) {
+                        finish_line(&mut line, &mut lines);
+                    }
+                    push_point(&mut line, a);
+                    push_point(&mut line, b);
+                    // segment leaves the tile
+                    if b != segment[1] {
+                        finish_line(&mut line, &mut lines);
+                    }
+                }
+                None => finish_line(&mut line, &mut lines),
+            }
+        }
+        finish_line(&mut line, &mut lines);
+    }
+    lines
+}
+
+// Doubled signed area, positive for exterior rings of MVT, saturated for huge rings
+pub fn ring_area(ring: &[Point]) -> i64 {
+    let mut area = 0i128;
+    for i in 0..ring.len() {
+        let (a, b) = (ring[i], ring[(i + 1) % ring.len()]);
+        area = area.saturating_add(a.0 as i128 * b.1 as i128 - b.0 as i128 * a.1 as i128);
+    }
+    area.clamp(i64::MIN as i128, i64::MAX as i128) as i64
+}
+
+// Ring clipped by the square 'clip' (Sutherland-Hodgman), orientation is kept
+fn clip_ring(ring: &[Point], clip: &[i64; 2]) -> Vec<Point> {
+    let (min, max) = (clip[0], clip[1]);
+    // (axis, bound, keep points with coordinate >= bound)
+    let edges = [
+        (0, min, true),
+        (0, max, false),
+        (1, min, true),
+        (1, max, false),
+    ];
+    let mut output = ring.to_vec();
+    for (axis, bound, keep_greater) in edges {
+        let input = std::mem::take(&mut output);
+        let coord = |p: &Point| if axis == 0 { p.0 } else { p.1 };
+        let is_in = |p: &Point| {
+            if keep_greater {
+                coord(p) >= bound
+            } else {
+                coord(p) <= bound
+            }
+        };
+        for i in 0..input.len() {
+            let (current, previous) = (input[i], input[(i + input.len() - 1) % input.len()]);
+            if is_in(&current) != is_in(&previous) {
+                let t = bound.saturating_sub(coord(&previous)) as f64
+                    / coord(&current).saturating_sub(coord(&previous)) as f64;
+                let crossing = (
+                    (previous.0 as f64 + t * (current.0 as f64 - previous.0 as f64)).round() as i64,
+                    (previous.1 as f64 + t * (current.1 as f64 - previous.1 as f64)).round() as i64,
+                );
+                push_point(&mut output, crossing);
+            }
+            if is_in(&current) {
+                push_point(&mut output, current);
+            }
+        }
+    }
+    if output.len() > 1 && output.first() == output.last() {
+        output.pop();
+    }
+    output
+}
+
+// Interior rings follow their exterior ring, they are dropped with the clipped out exterior
+pub fn clip_polygons(parts: Vec<Vec<Point>>, clip: &[i64; 2]) -> Vec<Vec<Point>> {
+    let mut rings = Vec::new();
+    let mut exterior_kept = false;
+    for ring in parts {
+        let exterior = ring_area(&ring) > 0;
+        if !exterior && !exterior_kept {
+            continue;
+        }
+        let clipped = clip_ring(&ring, clip);
+        let kept = clipped.len() >= 3 && ring_area(&clipped) != 0;
+        if exterior {
+            exterior_kept = kept;
+        }
+        if kept {
+            rings.push(clipped);
+        }
+    }
+    rings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Tile of one layer with the extent 4096 and one feature of the geometry
+    fn tile(geom_type: u64, geometry: &[u8]) -> Vec<u8> {
+        let mut feature = Vec::new();
+        write_varint(&mut feature, (FEATURE_TYPE as u64) << 3);
+        write_varint(&mut feature, geom_type);
+        write_bytes(&mut feature, FEATURE_GEOMETRY, geometry);
+
+        let mut layer = Vec::new();
+        write_bytes(&mut layer, LAYER_NAME, b"layer");
+        write_bytes(&mut layer, LAYER_FEATURES, &feature);
+        write_varint(&mut layer, (LAYER_EXTENT as u64) << 3);
+        write_varint(&mut layer, DEFAULT_EXTENT);
+
+        let mut tile = Vec::new();
+        write_bytes(&mut tile, TILE_LAYERS, &layer);
+        tile
+    }
+
+    // Geometries of features of all layers of the tile
+    fn geometries(tile: &[u8]) -> Vec<Vec<Vec<Point>>> {
+        let mut geometries = Vec::new();
+        let mut reader = Reader::new(tile);
+        while let Some((_, layer)) = reader.field().unwrap() {
+            let Value::Bytes(layer) = layer else { continue };
+            let mut reader = Reader::new(layer);
+            while let Some((field, feature)) = reader.field().unwrap() {
+                let (LAYER_FEATURES, Value::Bytes(feature)) = (field, feature) else {
+                    continue;
+                };
+                let mut reader = Reader::new(feature);
+                while let Some((field, geometry)) = reader.field().unwrap() {
+                    if let (FEATURE_GEOMETRY, Value::Bytes(geometry)) = (field, geometry) {
+                        geometries.push(decode_geometry(geometry).unwrap());
+                    }
+                }
+            }
+        }
+        geometries
+    }
+
+    fn square(min: i64, max: i64) -> Vec<Point> {
+        vec![(min, min), (max, min), (max, max), (min, max)]
+    }
+
+    // Upper left quarter of the parent tile of the zoom 0
+    fn upper_left() -> SubTile {
+        SubTile::new(1, 0, 0, 0)
+    }
+
+    #[test]
+    fn zigzag_round_trip() {
+        for value in [0, 1, -1, 4096, -4096, i64::MAX, i64::MIN] {
+            assert_eq!(zigzag_decode(zigzag_encode(value)), value);
+        }
+    }
+
+    #[test]
+    fn points_outside_of_the_child_are_dropped() {
+        let geometry = encode_geometry(&[vec![(100, 200)], vec![(3000, 3000)]], GEOM_POINT);
+        let reclipped = reclip(&tile(GEOM_POINT, &geometry), &upper_left()).unwrap();
+        assert_eq!(geometries(&reclipped), vec![vec![vec![(200, 400)]]]);
+    }
+
+    #[test]
+    fn feature_outside_of_the_child_is_dropped() {
+        let geometry = encode_geometry(&[vec![(3000, 3000)]], GEOM_POINT);
+        let reclipped = reclip(&tile(GEOM_POINT, &geometry), &upper_left()).unwrap();
+        assert!(geometries(&reclipped).is_empty());
+    }
+
+    #[test]
+    fn line_crossing_the_edge_is_cut_at_the_buffer() {
+        let geometry = encode_geometry(&[vec![(1000, 1000), (3000, 1000)]], GEOM_LINESTRING);
+        let reclipped = reclip(&tile(GEOM_LINESTRING, &geometry), &upper_left()).unwrap();
+        assert_eq!(
+            geometries(&reclipped),
+            vec![vec![vec![(2000, 2000), (4352, 2000)]]]
+        );
+    }
+
+    #[test]
+    fn line_leaving_and_entering_the_child_is_split() {
+        let line = vec![(1000, 1000), (3000, 1000), (3000, 1500), (1000, 1500)];
+        let geometry = encode_geometry(&[line], GEOM_LINESTRING);
+        let reclipped = reclip(&tile(GEOM_LINESTRING, &geometry), &upper_left()).unwrap();
+        assert_eq!(
+            geometries(&reclipped),
+            vec![vec![
+                vec![(2000, 2000), (4352, 2000)],
+                vec![(4352, 3000), (2000, 3000)],
+            ]]
+        );
+    }
+
+    #[test]
+    fn polygon_keeps_its_hole_inside_of_the_child() {
+        let mut hole = square(1000, 1500);
+        hole.reverse();
+        let geometry = encode_geometry(&[square(0, 4000), hole], GEOM_POLYGON);
+        let reclipped = reclip(&tile(GEOM_POLYGON, &geometry), &upper_left()).unwrap();
+
+        let rings = &geometries(&reclipped)[0];
+        assert_eq!(rings.len(), 2);
+        assert_eq!(rings[0].len(), 4);
+        assert_eq!(ring_area(&rings[0]), 2 * 4352 * 4352);
+        assert!(ring_area(&rings[1]) < 0);
+        assert!(rings[1].iter().all(|p| inside(&[2000, 3000], *p)));
+    }
+
+    #[test]
+    fn hole_outside_of_the_child_is_dropped() {
+        let mut hole = square(1000, 1500);
+        hole.reverse();
+        let geometry = encode_geometry(&[square(0, 4000), hole], GEOM_POLYGON);
+        let reclipped = reclip(&tile(GEOM_POLYGON, &geometry), &SubTile::new(1, 1, 1, 0)).unwrap();
+
+        let rings = &geometries(&reclipped)[0];
+        assert_eq!(rings, &vec![square(-256, 3904)]);
+    }
+
+    #[test]
+    fn command_count_beyond_the_geometry_is_an_error() {
+        let mut geometry = Vec::new();
+        write_varint(&mut geometry, (CMD_MOVE_TO | (100 << 3)) as u64);
+        write_varint(&mut geometry, zigzag_encode(1));
+        write_varint(&mut geometry, zigzag_encode(1));
+        assert!(reclip(&tile(GEOM_POINT, &geometry), &upper_left()).is_err());
+    }
+
+    #[test]
+    fn command_without_points_is_an_error() {
+        let mut geometry = Vec::new();
+        write_varint(&mut geometry, CMD_MOVE_TO as u64);
+        assert!(reclip(&tile(GEOM_POINT, &geometry), &upper_left()).is_err());
+    }
+
+    #[test]
+    fn line_to_before_move_to_is_an_error() {
+        let mut geometry = Vec::new();
+        write_varint(&mut geometry, (CMD_LINE_TO | (1 << 3)) as u64);
+        write_varint(&mut geometry, zigzag_encode(1));
+        write_varint(&mut geometry, zigzag_encode(1));
+        assert!(reclip(&tile(GEOM_LINESTRING, &geometry), &upper_left()).is_err());
+    }
+
+    #[test]
+    fn unknown_command_is_an_error() {
+        let mut geometry = Vec::new();
+        write_varint(&mut geometry, (5 | (1 << 3)) as u64);
+        write_varint(&mut geometry, zigzag_encode(1));
+        write_varint(&mut geometry, zigzag_encode(1));
+        assert!(reclip(&tile(GEOM_POINT, &geometry), &upper_left()).is_err());
+    }
+
+    #[test]
+    fn overflow_of_coordinates_is_an_error() {
+        let mut geometry = Vec::new();
+        write_varint(&mut geometry, (CMD_MOVE_TO | (2 << 3)) as u64);
+        for _ in 0..2 {
+            write_varint(&mut geometry, zigzag_encode(i64::MAX));
+            write_varint(&mut geometry, zigzag_encode(0));
+        }
+        assert!(reclip(&tile(GEOM_POINT, &geometry), &upper_left()).is_err());
+
+        // coordinate of the parent is doubled in the child
+        let geometry = encode_geometry(&[vec![(i64::MAX / 2 + 1, 0)]], GEOM_POINT);
+        assert!(reclip(&tile(GEOM_POINT, &geometry), &upper_left()).is_err());
+    }
+}
@@ -0,0 +1,30 @@
+use std::io::Cursor;
+
+use image::{imageops::FilterType, ImageFormat};
+
+use super::SubTile;
+
+// Part of the parent PNG tile is scaled up to the size of the parent
+pub fn crop_and_scale(parent: &[u8], sub: &SubTile) -> Result<Vec<u8>, anyhow::Error> {
+    let image = image::load_from_memory_with_format(parent, ImageFormat::Png)?;
+    let (width, height) = (image.width() as u64, image.height() as u64);
+
+    let parts = 1u64 << sub.dz;
+    let part_width = (width / parts).max(1);
+    let part_height = (height / parts).max(1);
+    let left = (sub.dx * width / parts).min(width - part_width);
+    let top = (sub.dy * height / parts).min(height - part_height);
+
+    let tile = image
+        .crop_imm(
+            left as u32,
+            top as u32,
+            part_width as u32,
+            part_height as u32,
+        )
+        .resize_exact(width as u32, height as u32, FilterType::Triangle);
+
+    let mut data = Cursor::new(Vec::new());
+    tile.write_to(&mut data, ImageFormat::Png)?;
+    Ok(data.into_inner())
+}
@@ -0,0 +1,100 @@
+use std::path::{Path, PathBuf};
+
+use crate::config::PathsConfig;
+
+/*
+    Directories of the application resolved once from 'server.paths' of the configuration:
+    - root: CWD with 'config_app.json', 'datasources', 'styles', 'static' and 'logs'
+    - cache: MBTiles and tiles of DataSources '{cache}/{datasource_id}', by default 'tiles'
+    - data: source rasters, mosaics and databases 'tiler.db', 'usage.db', 'audit.db', by default 'data'
+    - scripts: scripts of workers and the file 'PIDs', by default 'scripts'
+    The cache can live on a different volume than source data
+*/
+#[derive(Debug, Clone)]
+pub struct Paths {
+    pub root: PathBuf,
+    pub cache: PathBuf,
+    pub data: PathBuf,
+    pub scripts: PathBuf,
+    pub trash: PathBuf,
+}
+
+impl Paths {
+    pub fn new(cwd: &str, config: &PathsConfig) -> Paths {
+        let root = PathBuf::from(cwd);
+        let resolve = |path: &str| {
+            let path = PathBuf::from(path);
+            if path.is_absolute() {
+                path
+            } else {
+                root.join(path)
+            }
+        };
+        let cache = resolve(&config.cache);
+        let trash = match config.trash.as_deref() {
+            Some(trash) => resolve(trash),
+            None => match cache.parent() {
+                Some(parent) => parent.join("trash"),
+                None => root.join("trash"),
+            },
+        };
+        Paths {
+            data: resolve(&config.data),
+            scripts: resolve(&config.scripts),
+            cache,
+            trash,
+            root,
+        }
+    }
+
+    // Path of the configuration ('registry.path', 'static_root'): absolute as is, relative to the root
+    pub fn resolve(&self, path: impl AsRef<Path>) -> PathBuf {
+        let path = path.as_ref();
+        if path.is_absolute() {
+            path.to_path_buf()
+        } else {
+            self.root.join(path)
+        }
+    }
+
+    // Cache of the DataSource: MBTiles, manifest and tiles '{z}/{x}/{y}.{ext}'
+    pub fn tiles_dir(&self, datasource_id: &str) -> PathBuf {
+        self.cache.join(datasource_id)
+    }
+
+    pub fn mbtiles_db(&self, datasource_id: &str) -> PathBuf {
+        self.tiles_dir(datasource_id)
+            .join(format!("{datasource_id}.mbtiles"))
+    }
+
+    // MBTiles built by the pyramid with 'atomic_swap', replaces the MBTiles when the pyramid is complete
+    pub fn mbtiles_db_new(&self, datasource_id: &str) -> PathBuf {
+        self.tiles_dir(datasource_id)
+            .join(format!("{datasource_id}.mbtiles.new"))
+    }
+
+    pub fn tiler_db(&self) -> PathBuf {
+        self.data.join("tiler.db")
+    }
+
+    pub fn mosaics_dir(&self) -> PathBuf {
+        self.data.join("mosaics")
+    }
+
+    // Files of DataSources of the type 'vector' or 'raster'
+    pub fn datasources_dir(&self, ds_type: &str) -> PathBuf {
+        self.root.join("datasources").join(ds_type)
+    }
+
+    pub fn styles_dir(&self) -> PathBuf {
+        self.root.join("styles")
+    }
+
+    pub fn logs_dir(&self) -> PathBuf {
+        self.root.join("logs")
+    }
+
+    pub fn pids(&self) -> PathBuf {
+        self.scripts.join("PIDs")
+    }
+}
@@ -0,0 +1,127 @@
+/*
+    Minimal encoder of single color PNG tiles (blank and transparent tiles).
+    Image is stored as 1-bit palette with one color (PLTE + tRNS),
+    pixel data of zeros is compressed by deflate with fixed Huffman codes
+    as one literal followed by repeated matches of distance 1
+*/
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (!(crc & 1)).wrapping_add(1);
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in data {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+struct BitWriter {
+    bytes: Vec<u8>,
+    bit: u8,
+}
+
+impl BitWriter {
+    fn new() -> Self {
+        BitWriter {
+            bytes: Vec::new(),
+            bit: 0,
+        }
+    }
+
+    // Huffman codes are packed starting from the most significant bit
+    fn write_code(&mut self, code: u32, len: u8) {
+        for i in (0..len).rev() {
+            self.write_bit((code >> i) & 1);
+        }
+    }
+
+    // Other values are packed starting from the least significant bit
+    fn write_bits(&mut self, value: u32, len: u8) {
+        for i in 0..len {
+            self.write_bit((value >> i) & 1);
+        }
+    }
+
+    fn write_bit(&mut self, bit: u32) {
+        if self.bit == 0 {
+            self.bytes.push(0);
+        }
+        if bit == 1 {
+            *self.bytes.last_mut().unwrap() |= 1 << self.bit;
+        }
+        self.bit = (self.bit + 1) % 8;
+    }
+}
+
+// zlib stream of 'len' zero bytes
+fn zlib_zeros(len: usize) -> Vec<u8> {
+    let mut writer = BitWriter::new();
+    // last block, fixed Huffman codes
+    writer.write_bits(1, 1);
+    writer.write_bits(1, 2);
+
+    let literal_zero = |w: &mut BitWriter| w.write_code(0x30, 8);
+    let mut remaining = len;
+    if remaining > 0 {
+        literal_zero(&mut writer);
+        remaining -= 1;
+    }
+    // length 258 (symbol 285), distance 1 (code 0)
+    while remaining >= 258 {
+        writer.write_code(0xc5, 8);
+        writer.write_code(0, 5);
+        remaining -= 258;
+    }
+    for _ in 0..remaining {
+        literal_zero(&mut writer);
+    }
+    // end of block (symbol 256)
+    writer.write_code(0, 7);
+
+    let mut stream = vec![0x78, 0x01];
+    stream.extend(writer.bytes);
+    stream.extend(adler32(&vec![0u8; len]).to_be_bytes());
+    stream
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend((data.len() as u32).to_be_bytes());
+    let mut chunk = Vec::with_capacity(data.len() + 4);
+    chunk.extend(kind);
+    chunk.extend(data);
+    png.extend(&chunk);
+    png.extend(crc32(&chunk).to_be_bytes());
+}
+
+// PNG image 'size' x 'size' filled with one RGBA color
+pub fn solid_png(size: u32, rgba: [u8; 4]) -> Vec<u8> {
+    let mut png = PNG_SIGNATURE.to_vec();
+
+    let mut ihdr = Vec::with_capacity(13);
+    ihdr.extend(size.to_be_bytes());
+    ihdr.extend(size.to_be_bytes());
+    // bit depth 1, color type 3 (palette), compression, filter, no interlace
+    ihdr.extend([1, 3, 0, 0, 0]);
+    write_chunk(&mut png, b"IHDR", &ihdr);
+    write_chunk(&mut png, b"PLTE", &rgba[..3]);
+    write_chunk(&mut png, b"tRNS", &rgba[3..]);
+
+    // every row: filter byte 0 and 1 bit per pixel of palette index 0
+    let row_len = 1 + (size as usize).div_ceil(8);
+    write_chunk(&mut png, b"IDAT", &zlib_zeros(row_len * size as usize));
+    write_chunk(&mut png, b"IEND", &[]);
+    png
+}
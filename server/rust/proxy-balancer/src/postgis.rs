@@ -0,0 +1,226 @@
+use std::io::Write;
+
+use anyhow::anyhow;
+use flate2::{write::GzEncoder, Compression};
+use serde::Deserialize;
+use serde_json::Value;
+use tracing::{info_span, Instrument};
+
+use crate::db::DB;
+use crate::defaults::{MAXZOOM, MVT_BUFFER, MVT_EXTENT};
+
+pub const STORE_POSTGIS: &str = "postgis";
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PostgisField {
+    pub name: String,
+    pub name_in_db: Option<String>,
+    #[serde(default = "default_true")]
+    pub encode: bool,
+}
+
+// SQL of the layer for the range of zoom levels, '$zoom' is replaced by the zoom of the tile
+#[derive(Debug, Clone, Deserialize)]
+pub struct PostgisQuery {
+    #[serde(default)]
+    pub minzoom: u8,
+    #[serde(default = "default_maxzoom")]
+    pub maxzoom: u8,
+    pub sql: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PostgisLayer {
+    pub id: String,
+    #[serde(rename = "storeLayer")]
+    pub store_layer: Option<String>,
+    #[serde(rename = "geomField", default = "default_geom_field")]
+    pub geom_field: String,
+    #[serde(default)]
+    pub minzoom: u8,
+    #[serde(default = "default_maxzoom")]
+    pub maxzoom: u8,
+    #[serde(default)]
+    pub fields: Vec<PostgisField>,
+    #[serde(default)]
+    pub queries: Vec<PostgisQuery>,
+}
+
+/*
+    Vector DataSource with the store 'postgis': tiles are generated by the proxy with 'ST_AsMVT'
+    from tables (or SQL) of the config database in EPSG:3857, workers are not requested
+*/
+#[derive(Debug, Clone)]
+pub struct PostgisSource {
+    pub layers: Vec<PostgisLayer>,
+    pub extent: u32,
+    pub buffer: u32,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_maxzoom() -> u8 {
+    MAXZOOM
+}
+
+fn default_geom_field() -> String {
+    "geom".to_string()
+}
+
+impl PostgisField {
+    // Column of the table, the name of the attribute by default
+    fn column(&self) -> &str {
+        self.name_in_db.as_deref().unwrap_or(&self.name)
+    }
+}
+
+impl PostgisSource {
+    // Layers, 'extent' and 'buffer' from the field 'data' of the DataSource
+    pub fn from_data(data: &Value) -> Result<Self, anyhow::Error> {
+        let layers: Vec<PostgisLayer> = match data.get("layers") {
+            Some(layers) => serde_json::from_value(layers.clone())?,
+            None => Vec::new(),
+        };
+        if layers.is_empty() {
+            return Err(anyhow!("PostGIS DataSource has no 'layers'"));
+        }
+        for layer in layers.iter() {
+            if layer.store_layer.is_none() && layer.queries.is_empty() {
+                return Err(anyhow!(
+                    "Layer '{}' has neither 'storeLayer' nor 'queries'",
+                    layer.id
+                ));
+            }
+        }
+        let extent = data
+            .get("extent")
+            .and_then(|v| v.as_u64())
+            .filter(|v| *v > 0)
+            .map_or(MVT_EXTENT, |v| v as u32);
+        let buffer = data
+            .get("buffer")
+            .and_then(|v| v.as_u64())
+            .map_or(MVT_BUFFER, |v| v as u32);
+        Ok(PostgisSource {
+            layers,
+            extent,
+            buffer,
+        })
+    }
+}
+
+fn quote_ident(ident: &str) -> String {
+    format!("\"{}\"", ident.replace('"', "\"\""))
+}
+
+fn quote_literal(literal: &str) -> String {
+    format!("'{}'", literal.replace('\'', "''"))
+}
+
+// Name of the table 'schema.table' is quoted by parts
+fn quote_relation(relation: &str) -> String {
+    relation
+        .split('.')
+        .map(quote_ident)
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+// Rows of the layer on the zoom: the table or SQL queries of the zoom joined by 'UNION ALL'
+fn layer_source(layer: &PostgisLayer, z: u8) -> Option<String> {
+    if !layer.queries.is_empty() {
+        let queries: Vec<String> = layer
+            .queries
+            .iter()
+            .filter(|q| z >= q.minzoom && z <= q.maxzoom)
+            .map(|q| {
+                q.sql
+                    .trim()
+                    .trim_end_matches(';')
+                    .replace("$zoom", &z.to_string())
+            })
+            .collect();
+        if queries.is_empty() {
+            return None;
+        }
+        return Some(format!("({})", queries.join(" UNION ALL ")));
+    }
+    layer.store_layer.as_deref().map(quote_relation)
+}
+
+fn layer_query(source: &PostgisSource, layer: &PostgisLayer, z: u8) -> Option<String> {
+    if z < layer.minzoom || z > layer.maxzoom {
+        return None;
+    }
+    let from = layer_source(layer, z)?;
+    let geom = quote_ident(&layer.geom_field);
+    // SQL of the layer returns attributes in 'tags' as in the generator of workers
+    let fields: String = if !layer.queries.is_empty() {
+        ", t.tags - 'id' AS tags".to_string()
+    } else {
+        layer
+            .fields
+            .iter()
+            .filter(|f| f.encode && f.column() != layer.geom_field)
+            .map(|f| {
+                format!(
+                    ", t.{} AS {}",
+                    quote_ident(f.column()),
+                    quote_ident(&f.name)
+                )
+            })
+            .collect()
+    };
+
+    Some(format!(
+        "COALESCE((SELECT ST_AsMVT(mvtgeom.*, {}, {extent}, 'geom') FROM (\
+            SELECT ST_AsMVTGeom(t.{geom}, ST_TileEnvelope($1, $2, $3), {extent}, {buffer}, true) AS geom{fields} \
+            FROM {from} AS t WHERE t.{geom} IS NOT NULL \
+            AND t.{geom} && ST_TileEnvelope($1, $2, $3, margin => $4)\
+        ) AS mvtgeom), ''::bytea)",
+        quote_literal(&layer.id),
+        extent = source.extent,
+        buffer = source.buffer,
+    ))
+}
+
+/*
+    MVT of the tile (z, x, y) of 'WebMercatorQuad': layers are concatenated,
+    empty Vec when no layer has features in the tile
+*/
+pub async fn generate_mvt(
+    pool: &DB,
+    source: &PostgisSource,
+    z: u8,
+    x: u64,
+    y: u64,
+) -> Result<Vec<u8>, anyhow::Error> {
+    let queries: Vec<String> = source
+        .layers
+        .iter()
+        .filter_map(|layer| layer_query(source, layer, z))
+        .collect();
+    if queries.is_empty() {
+        return Ok(Vec::new());
+    }
+    let query = format!("SELECT {}", queries.join(" || "));
+    let margin = source.buffer as f64 / source.extent as f64;
+
+    let tile: Option<Vec<u8>> = sqlx::query_scalar(&query)
+        .bind(z as i32)
+        .bind(i32::try_from(x)?)
+        .bind(i32::try_from(y)?)
+        .bind(margin)
+        .fetch_one(pool)
+        .instrument(info_span!("postgis_mvt", z, x, y))
+        .await?;
+    Ok(tile.unwrap_or_default())
+}
+
+pub fn gzip_tile(tile: &[u8]) -> Result<Vec<u8>, anyhow::Error> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(tile)?;
+    Ok(encoder.finish()?)
+}
@@ -0,0 +1,365 @@
+use std::collections::HashMap;
+use std::io::Cursor;
+
+use anyhow::anyhow;
+use image::{imageops::FilterType, ImageFormat};
+use xxhash_rust::xxh3::xxh3_64;
+
+use crate::grid::TileGrid;
+
+// PNG tile resized by 'scale': '@2x' tile of 256 px is 512 px, '@0.5x' is 128 px
+pub fn resize_png(tile: &[u8], scale: f32) -> Result<Vec<u8>, anyhow::Error> {
+    let image = image::load_from_memory_with_format(tile, ImageFormat::Png)?;
+    let width = ((image.width() as f32 * scale).round() as u32).max(1);
+    let height = ((image.height() as f32 * scale).round() as u32).max(1);
+    let resized = image.resize_exact(width, height, FilterType::CatmullRom);
+
+    let mut data = Cursor::new(Vec::new());
+    resized.write_to(&mut data, ImageFormat::Png)?;
+    Ok(data.into_inner())
+}
+
+// Query parameters of raster operations, other parameters are passed to the tile without operations
+const RASTER_OPS_PARAMS: [&str; 7] = [
+    "ramp",
+    "min",
+    "max",
+    "hillshade",
+    "exaggeration",
+    "gamma",
+    "brightness",
+];
+
+// Stop of the color ramp: value and RGB color
+type RampStop = (f64, [u8; 3]);
+
+// Named color ramps: stops from 0 to 1 of the range 'min'..'max'
+const RAMPS: [(&str, &[RampStop]); 5] = [
+    (
+        "viridis",
+        &[
+            (0.0, [68, 1, 84]),
+            (0.25, [59, 82, 139]),
+            (0.5, [33, 145, 140]),
+            (0.75, [94, 201, 98]),
+            (1.0, [253, 231, 37]),
+        ],
+    ),
+    (
+        "magma",
+        &[
+            (0.0, [0, 0, 4]),
+            (0.25, [81, 18, 124]),
+            (0.5, [183, 55, 121]),
+            (0.75, [252, 137, 97]),
+            (1.0, [252, 253, 191]),
+        ],
+    ),
+    (
+        "terrain",
+        &[
+            (0.0, [51, 102, 153]),
+            (0.2, [0, 153, 102]),
+            (0.4, [204, 204, 102]),
+            (0.7, [153, 102, 51]),
+            (1.0, [255, 255, 255]),
+        ],
+    ),
+    (
+        "rdylgn",
+        &[
+            (0.0, [165, 0, 38]),
+            (0.25, [244, 109, 67]),
+            (0.5, [255, 255, 191]),
+            (0.75, [102, 189, 99]),
+            (1.0, [0, 104, 55]),
+        ],
+    ),
+    ("grayscale", &[(0.0, [0, 0, 0]), (1.0, [255, 255, 255])]),
+];
+
+const EARTH_RADIUS: f64 = 6378137.0;
+
+#[derive(Debug, Clone)]
+pub struct Hillshade {
+    pub azimuth: f64,
+    pub altitude: f64,
+    pub exaggeration: f64,
+}
+
+/*
+    Operations on raster tiles selected by query parameters:
+    'ramp' (name or stops 'value:rrggbb,...' with 'min'/'max' for names) colors single-band values,
+    'hillshade' ('true' or 'azimuth,altitude') with 'exaggeration' shades elevations,
+    'gamma' and 'brightness' (-1..1) correct colors
+*/
+#[derive(Debug, Clone)]
+pub struct RasterOps {
+    pub ramp: Option<Vec<RampStop>>,
+    pub hillshade: Option<Hillshade>,
+    pub gamma: Option<f64>,
+    pub brightness: Option<f64>,
+    // canonical parameters of operations, the key of cached tiles
+    params: String,
+}
+
+fn query_pairs(query: Option<&str>) -> impl Iterator<Item = (&str, &str)> {
+    query
+        .unwrap_or_default()
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+}
+
+fn parse_f64(key: &str, value: &str) -> Result<f64, anyhow::Error> {
+    value
+        .parse::<f64>()
+        .ok()
+        .filter(|v| v.is_finite())
+        .ok_or(anyhow!("Parameter '{key}' must be a number, got '{value}'"))
+}
+
+fn parse_ramp(value: &str, min: f64, max: f64) -> Result<Vec<RampStop>, anyhow::Error> {
+    if let Some((_, stops)) = RAMPS.iter().find(|(name, _)| *name == value) {
+        return Ok(stops
+            .iter()
+            .map(|(t, color)| (min + t * (max - min), *color))
+            .collect());
+    }
+    let mut stops = Vec::new();
+    for stop in value.split(',') {
+        let (v, color) = stop.split_once(':').ok_or(anyhow!(
+            "Ramp must be one of {:?} or stops 'value:rrggbb,...', got '{value}'",
+            RAMPS.map(|(name, _)| name)
+        ))?;
+        let color = u32::from_str_radix(color.trim_start_matches('#'), 16)
+            .ok()
+            .filter(|_| color.trim_start_matches('#').len() == 6)
+            .ok_or(anyhow!(
+                "Color of ramp stop must be 'rrggbb', got '{color}'"
+            ))?;
+        stops.push((
+            parse_f64("ramp", v)?,
+            [(color >> 16) as u8, (color >> 8) as u8, color as u8],
+        ));
+    }
+    stops.sort_by(|a, b| a.0.total_cmp(&b.0));
+    Ok(stops)
+}
+
+impl RasterOps {
+    pub fn from_query(query: Option<&str>) -> Result<Option<Self>, anyhow::Error> {
+        let mut values: HashMap<&str, String> = HashMap::new();
+        for (key, value) in query_pairs(query) {
+            if RASTER_OPS_PARAMS.contains(&key) {
+                values.insert(key, urlencoding::decode(value)?.to_string());
+            }
+        }
+        if values.is_empty() {
+            return Ok(None);
+        }
+        let number = |key: &str| -> Result<Option<f64>, anyhow::Error> {
+            values.get(key).map(|v| parse_f64(key, v)).transpose()
+        };
+
+        let ramp = match values.get("ramp") {
+            Some(ramp) => Some(parse_ramp(
+                ramp,
+                number("min")?.unwrap_or(0.0),
+                number("max")?.unwrap_or(255.0),
+            )?),
+            None => None,
+        };
+        let hillshade = match values.get("hillshade").map(|v| v.as_str()) {
+            None | Some("false") => None,
+            Some(value) => {
+                let (azimuth, altitude) = match value {
+                    "true" => (315.0, 45.0),
+                    _ => {
+                        let (azimuth, altitude) = value.split_once(',').ok_or(anyhow!(
+                            "Hillshade must be 'true' or 'azimuth,altitude', got '{value}'"
+                        ))?;
+                        (
+                            parse_f64("hillshade", azimuth)?,
+                            parse_f64("hillshade", altitude)?,
+                        )
+                    }
+                };
+                Some(Hillshade {
+                    azimuth,
+                    altitude,
+                    exaggeration: number("exaggeration")?.unwrap_or(1.0),
+                })
+            }
+        };
+        let gamma = number("gamma")?;
+        if gamma.is_some_and(|g| g <= 0.0) {
+            return Err(anyhow!("Parameter 'gamma' must be positive"));
+        }
+        let brightness = number("brightness")?;
+        if brightness.is_some_and(|b| !(-1.0..=1.0).contains(&b)) {
+            return Err(anyhow!("Parameter 'brightness' must be in range -1..1"));
+        }
+
+        let params = RASTER_OPS_PARAMS
+            .iter()
+            .filter_map(|key| values.get(key).map(|v| format!("{key}={v}")))
+            .collect::<Vec<_>>()
+            .join("&");
+        Ok(Some(RasterOps {
+            ramp,
+            hillshade,
+            gamma,
+            brightness,
+            params,
+        }))
+    }
+
+    // Key of the tile with operations cached next to the tile without them
+    pub fn key(&self) -> String {
+        format!("{:016x}", xxh3_64(self.params.as_bytes()))
+    }
+
+    // Query of the tile without operations
+    pub fn base_query(query: Option<&str>) -> String {
+        let pairs: Vec<String> = query_pairs(query)
+            .filter(|(key, _)| !RASTER_OPS_PARAMS.contains(key))
+            .map(|(key, value)| format!("{key}={value}"))
+            .collect();
+        if pairs.is_empty() {
+            return String::new();
+        }
+        format!("?{}", pairs.join("&"))
+    }
+
+    /*
+        PNG tile with operations: values are decoded by the encoding of the DataSource,
+        'resolution' is the size of pixels in meters for the hillshade
+    */
+    pub fn apply(
+        &self,
+        tile: &[u8],
+        encoding: &str,
+        resolution: f64,
+    ) -> Result<Vec<u8>, anyhow::Error> {
+        let mut image = image::load_from_memory_with_format(tile, ImageFormat::Png)?.to_rgba8();
+        let (width, height) = (image.width() as usize, image.height() as usize);
+        let values: Vec<Option<f64>> = image
+            .pixels()
+            .map(|p| decode_value(encoding, p.0))
+            .collect();
+
+        let shades: Option<Vec<f64>> = self.hillshade.as_ref().map(|hillshade| {
+            let value = |col: usize, row: usize, v: f64| {
+                values[row.min(height - 1) * width + col.min(width - 1)].unwrap_or(v)
+            };
+            let zenith = (90.0 - hillshade.altitude).to_radians();
+            let azimuth = (360.0 - hillshade.azimuth + 90.0).to_radians();
+            let mut shades = vec![1.0; width * height];
+            for row in 0..height {
+                for col in 0..width {
+                    let Some(v) = values[row * width + col] else {
+                        continue;
+                    };
+                    let (left, right) = (col.saturating_sub(1), col + 1);
+                    let (up, down) = (row.saturating_sub(1), row + 1);
+                    // Horn's method over the 3x3 window, edges are extended
+                    let dzdx = ((value(right, up, v)
+                        + 2.0 * value(right, row, v)
+                        + value(right, down, v))
+                        - (value(left, up, v) + 2.0 * value(left, row, v) + value(left, down, v)))
+                        / (8.0 * resolution);
+                    let dzdy = ((value(left, down, v)
+                        + 2.0 * value(col, down, v)
+                        + value(right, down, v))
+                        - (value(left, up, v) + 2.0 * value(col, up, v) + value(right, up, v)))
+                        / (8.0 * resolution);
+                    let slope = (hillshade.exaggeration * dzdx.hypot(dzdy)).atan();
+                    let aspect = dzdy.atan2(-dzdx);
+                    let shade = zenith.cos() * slope.cos()
+                        + zenith.sin() * slope.sin() * (azimuth - aspect).cos();
+                    shades[row * width + col] = shade.clamp(0.0, 1.0);
+                }
+            }
+            shades
+        });
+
+        for (i, pixel) in image.pixels_mut().enumerate() {
+            let Some(value) = values[i] else {
+                pixel.0 = [0, 0, 0, 0];
+                continue;
+            };
+            let mut rgb = [pixel.0[0], pixel.0[1], pixel.0[2]].map(|c| c as f64);
+            if let Some(ramp) = self.ramp.as_ref() {
+                rgb = ramp_color(ramp, value).map(|c| c as f64);
+            } else if self.hillshade.is_some() {
+                rgb = [255.0; 3];
+            }
+            if let Some(shades) = shades.as_ref() {
+                rgb = rgb.map(|c| c * shades[i]);
+            }
+            if let Some(gamma) = self.gamma {
+                rgb = rgb.map(|c| 255.0 * (c / 255.0).powf(1.0 / gamma));
+            }
+            if let Some(brightness) = self.brightness {
+                rgb = rgb.map(|c| c + 255.0 * brightness);
+            }
+            let [r, g, b] = rgb.map(|c| c.round().clamp(0.0, 255.0) as u8);
+            pixel.0 = [r, g, b, pixel.0[3]];
+        }
+
+        let mut data = Cursor::new(Vec::new());
+        image.write_to(&mut data, ImageFormat::Png)?;
+        Ok(data.into_inner())
+    }
+}
+
+/*
+    Value of the pixel by the encoding of the DataSource: 'f32' (float in RGBA),
+    'mapbox' and 'terrarium' (elevations in RGB), 'none' (first band), None for transparent pixels
+*/
+fn decode_value(encoding: &str, [r, g, b, a]: [u8; 4]) -> Option<f64> {
+    if a == 0 {
+        return None;
+    }
+    let (r, g, b) = (r as f64, g as f64, b as f64);
+    match encoding {
+        "f32" => {
+            let mantissa = r * 65536.0 + g * 256.0 + b;
+            let scale = 2f64.powi(a as i32 - 128);
+            if mantissa > 8388608.0 {
+                Some((1.0 + (mantissa - 8388609.0) / 8388608.0) * scale)
+            } else if mantissa < 8388608.0 {
+                Some(-(1.0 + (8388607.0 - mantissa) / 8388608.0) * scale)
+            } else {
+                Some(0.0)
+            }
+        }
+        "mapbox" => Some(-10000.0 + (r * 65536.0 + g * 256.0 + b) * 0.1),
+        "terrarium" => Some(r * 256.0 + g + b / 256.0 - 32768.0),
+        _ => Some(r),
+    }
+}
+
+fn ramp_color(ramp: &[RampStop], value: f64) -> [u8; 3] {
+    let Some(upper) = ramp.iter().position(|(v, _)| *v >= value) else {
+        return ramp.last().map_or([0; 3], |(_, c)| *c);
+    };
+    if upper == 0 {
+        return ramp[0].1;
+    }
+    let ((v0, c0), (v1, c1)) = (ramp[upper - 1], ramp[upper]);
+    let t = if v1 > v0 {
+        (value - v0) / (v1 - v0)
+    } else {
+        0.0
+    };
+    [0, 1, 2].map(|i| (c0[i] as f64 + t * (c1[i] as f64 - c0[i] as f64)).round() as u8)
+}
+
+// Size of pixels of the tile in meters along the parallel of its center
+pub fn ground_resolution(grid: &TileGrid, z: u8, x: u64, y: u64) -> Option<f64> {
+    let [minx, miny, maxx, maxy] = grid.tile_extent(z, x, y);
+    let (west, lat) = grid.unproject(minx, (miny + maxy) / 2.0)?;
+    let (east, _) = grid.unproject(maxx, (miny + maxy) / 2.0)?;
+    Some((east - west).to_radians() * EARTH_RADIUS * lat.to_radians().cos() / grid.tile_size as f64)
+}
@@ -0,0 +1,168 @@
+use std::time::Duration;
+
+use anyhow::anyhow;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::StatusCode;
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+
+use crate::config::CacheOrigin;
+use crate::defaults::{REMOTE_MAX_WAIT_SECS, REMOTE_RATE_LIMIT, REMOTE_REQUEST_TIMEOUT_SECS};
+use crate::tasks::datasources::DataSource;
+
+pub const STORE_REMOTE: &str = "remote";
+
+pub enum RemoteTile {
+    Tile(Vec<u8>),
+    // upstream has no tile: 404 or 204
+    Empty,
+    // requests to the upstream are queued longer than REMOTE_MAX_WAIT_SECS
+    RateLimited,
+}
+
+/*
+    DataSource with the store 'remote': missing tiles are fetched by the proxy from the upstream
+    by the URL template 'dataStore.url' with '{z}', '{x}', '{y}' and saved to the cache of the DataSource.
+    Requests to the upstream are limited to 'dataStore.rate_limit' per second
+*/
+#[derive(Debug)]
+pub struct RemoteSource {
+    url: String,
+    client: reqwest::Client,
+    interval: Duration,
+    // time of the next request allowed by the rate limit
+    next_request: Mutex<Instant>,
+}
+
+impl RemoteSource {
+    pub fn from_datasource(ds: &DataSource) -> Result<Self, anyhow::Error> {
+        let data_store = ds.data.0.get("dataStore");
+        let url = data_store
+            .and_then(|s| s.get("url"))
+            .and_then(|u| u.as_str())
+            .ok_or(anyhow!("DataSource has no 'dataStore.url'"))?;
+        if !url.starts_with("http://") && !url.starts_with("https://") {
+            return Err(anyhow!("URL of remote tiles must be HTTP(S), got '{url}'"));
+        }
+        if !["{z}", "{x}", "{y}"].iter().all(|p| url.contains(p)) {
+            return Err(anyhow!(
+                "URL of remote tiles must have '{{z}}', '{{x}}' and '{{y}}', got '{url}'"
+            ));
+        }
+        let rate_limit = data_store
+            .and_then(|s| s.get("rate_limit"))
+            .and_then(|v| v.as_f64())
+            .filter(|v| *v > 0.0)
+            .unwrap_or(REMOTE_RATE_LIMIT);
+
+        // headers of upstream requests, e.g. 'User-Agent' or keys of the service
+        let mut headers = HeaderMap::new();
+        if let Some(map) = data_store
+            .and_then(|s| s.get("headers"))
+            .and_then(|h| h.as_object())
+        {
+            for (name, value) in map {
+                let Some(value) = value.as_str() else {
+                    continue;
+                };
+                headers.insert(
+                    HeaderName::from_bytes(name.as_bytes())?,
+                    HeaderValue::from_str(value)?,
+                );
+            }
+        }
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(REMOTE_REQUEST_TIMEOUT_SECS))
+            .user_agent(concat!("tiler-proxy-balancer/", env!("CARGO_PKG_VERSION")))
+            .default_headers(headers)
+            .build()?;
+
+        Ok(RemoteSource {
+            url: url.to_string(),
+            client,
+            interval: Duration::from_secs_f64(1.0 / rate_limit),
+            next_request: Mutex::new(Instant::now()),
+        })
+    }
+
+    // Slot of the request by the rate limit, false when the queue to the upstream is too long
+    async fn wait_slot(&self) -> bool {
+        let slot = {
+            let mut next_request = self.next_request.lock().await;
+            let now = Instant::now();
+            let slot = (*next_request).max(now);
+            if slot - now > Duration::from_secs(REMOTE_MAX_WAIT_SECS) {
+                return false;
+            }
+            *next_request = slot + self.interval;
+            slot
+        };
+        tokio::time::sleep_until(slot).await;
+        true
+    }
+
+    pub async fn fetch(&self, z: u8, x: u64, y: u64) -> Result<RemoteTile, anyhow::Error> {
+        if !self.wait_slot().await {
+            return Ok(RemoteTile::RateLimited);
+        }
+        let url = self
+            .url
+            .replace("{z}", &z.to_string())
+            .replace("{x}", &x.to_string())
+            .replace("{y}", &y.to_string());
+        let response = self.client.get(&url).send().await?;
+        match response.status() {
+            StatusCode::NOT_FOUND | StatusCode::NO_CONTENT => Ok(RemoteTile::Empty),
+            status if status.is_success() => {
+                let tile = response.bytes().await?;
+                if tile.is_empty() {
+                    return Ok(RemoteTile::Empty);
+                }
+                Ok(RemoteTile::Tile(tile.to_vec()))
+            }
+            status => Err(anyhow!("Upstream '{url}' responded {status}")),
+        }
+    }
+}
+
+/*
+    Origin tiler of the edge node 'serve-cache': tiles missing in the local roots are requested
+    by the same path from 'serve_cache.origin.url'
+*/
+#[derive(Debug)]
+pub struct OriginSource {
+    url: String,
+    client: reqwest::Client,
+    pub store: bool,
+}
+
+impl OriginSource {
+    pub fn new(origin: &CacheOrigin) -> Result<Self, anyhow::Error> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(origin.timeout_secs))
+            .user_agent(concat!("tiler-proxy-balancer/", env!("CARGO_PKG_VERSION")))
+            .build()?;
+        Ok(OriginSource {
+            url: origin.url.clone(),
+            client,
+            store: origin.store,
+        })
+    }
+
+    // Tile by the path with the query: '/tiles/{datasource_id}/{z}/{x}/{y}.png?grid=...'
+    pub async fn fetch(&self, path_and_query: &str) -> Result<RemoteTile, anyhow::Error> {
+        let url = format!("{}{path_and_query}", self.url);
+        let response = self.client.get(&url).send().await?;
+        match response.status() {
+            StatusCode::NOT_FOUND | StatusCode::NO_CONTENT => Ok(RemoteTile::Empty),
+            status if status.is_success() => {
+                let tile = response.bytes().await?;
+                if tile.is_empty() {
+                    return Ok(RemoteTile::Empty);
+                }
+                Ok(RemoteTile::Tile(tile.to_vec()))
+            }
+            status => Err(anyhow!("Origin '{url}' responded {status}")),
+        }
+    }
+}
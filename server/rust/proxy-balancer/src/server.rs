@@ -0,0 +1,611 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server};
+use tiler_core::sqlite_clients::{sqlite_clients_maintenance, MessageSQLiteClient};
+use tokio::net::TcpListener;
+use tokio::process::Child;
+use tokio::sync::{oneshot, watch};
+use tokio::task::JoinHandle;
+use tracing::{event, Level};
+
+use crate::config::{load_config, Config};
+use crate::db::{
+    check_migrations, db_pool, init_db,
+    job::queue::{JobDetail, SharedQueue},
+    registry::{open_registry, SharedRegistry},
+    DBPool,
+};
+use crate::defaults::{CACHE_DIR_ENV, DATA_DIR_ENV, REGISTRY_POSTGRES};
+use crate::environment::get_cwd;
+use crate::forwarded::{incoming_listener, ClientStream};
+use crate::handles::{handle, RequestContext};
+use crate::paths::Paths;
+use crate::tasks::audit::{audit_maintenance, MessageAudit};
+use crate::tasks::cluster::{cluster_heartbeat, cluster_maintenance, MessageCluster};
+use crate::tasks::datasources::{datasources_maintenance, MessageDatasource};
+use crate::tasks::events::{events_maintenance, MessageEvents};
+use crate::tasks::exports::{exports_maintenance, MessageExports};
+use crate::tasks::job::{init_job_queue, pool_queue, JobChannels};
+use crate::tasks::listener::datasources_listener;
+use crate::tasks::quotas::quotas_maintenance;
+use crate::tasks::reload_workers::{reload_workers_maintenance, MessageMaintenanceWorkers};
+use crate::tasks::semaphore::{semaphore_maintenance, MessageSemaphore};
+use crate::tasks::stats::{stats_maintenance, MessageStats};
+use crate::tasks::storage::{storage_maintenance, StorageInfo};
+use crate::tasks::trash::trash_maintenance;
+use crate::tasks::uploads::{remove_stale_parts, uploads_maintenance, MessageUploads};
+use crate::tasks::usage::{usage_maintenance, MessageUsage};
+use crate::tasks::workers::{cmd_run_worker, workers_maintenance};
+use crate::utils::{get_available_port, try_save_process_pid, worker_client};
+
+// Handler of the route added by the application which embeds the server
+pub type RouteHandler =
+    Arc<dyn Fn(Request<Body>) -> BoxFuture<'static, Response<Body>> + Send + Sync>;
+
+#[derive(Clone)]
+struct EmbeddedRoute {
+    path: String,
+    handler: RouteHandler,
+}
+
+/*
+    Tile proxy of the mode 'serve' embeddable into other applications of Rust.
+    Configuration, registry of DataSources and the config database are taken from the builder,
+    the rest is loaded the same way as by the command 'serve'
+*/
+pub struct TilerServer {
+    paths: Paths,
+    config: Config,
+    vars: HashMap<&'static str, String>,
+    bind: Option<SocketAddr>,
+    registry: Option<SharedRegistry>,
+    pool: Option<DBPool>,
+    queue: Option<SharedQueue>,
+    workers: Vec<u16>,
+    routes: Vec<EmbeddedRoute>,
+}
+
+#[derive(Default)]
+pub struct TilerServerBuilder {
+    cwd: Option<String>,
+    config: Option<Config>,
+    vars: HashMap<&'static str, String>,
+    bind: Option<SocketAddr>,
+    registry: Option<SharedRegistry>,
+    pool: Option<DBPool>,
+    queue: Option<SharedQueue>,
+    workers: Vec<u16>,
+    routes: Vec<EmbeddedRoute>,
+}
+
+impl TilerServerBuilder {
+    // Root directory of the application, relative 'server.paths' are resolved in it, by default the current one
+    pub fn cwd(mut self, cwd: impl Into<String>) -> Self {
+        self.cwd = Some(cwd.into());
+        self
+    }
+
+    // Configuration of the server, by default it is loaded from 'config_app.json'
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    // Environment variables of Python workers, see 'setup_envs'
+    pub fn vars(mut self, vars: HashMap<&'static str, String>) -> Self {
+        self.vars = vars;
+        self
+    }
+
+    // Address of the listener instead of 'host' and 'port' of the configuration, port 0 - any free port
+    pub fn bind(mut self, addr: SocketAddr) -> Self {
+        self.bind = Some(addr);
+        self
+    }
+
+    // Registry of DataSources instead of the one selected by 'registry.type'
+    pub fn registry(mut self, registry: SharedRegistry) -> Self {
+        self.registry = Some(registry);
+        self
+    }
+
+    // Pool of the config database instead of the one created by 'DBSCHEME'
+    pub fn pool(mut self, pool: DBPool) -> Self {
+        self.pool = Some(pool);
+        self
+    }
+
+    // Queue of jobs of the master instead of the table 'queue' of the config database
+    pub fn queue(mut self, queue: SharedQueue) -> Self {
+        self.queue = Some(queue);
+        self
+    }
+
+    // Workers run outside of the server on the ports, they are proxied but not started or restarted
+    pub fn workers(mut self, ports: Vec<u16>) -> Self {
+        self.workers = ports;
+        self
+    }
+
+    // Requests of the path and its subpaths are answered by the handler before routes of the tiler
+    pub fn route<F>(mut self, path: impl Into<String>, handler: F) -> Self
+    where
+        F: Fn(Request<Body>) -> BoxFuture<'static, Response<Body>> + Send + Sync + 'static,
+    {
+        let path = path.into().trim_end_matches('/').to_string();
+        self.routes.push(EmbeddedRoute {
+            path,
+            handler: Arc::new(handler),
+        });
+        self
+    }
+
+    pub async fn build(self) -> Result<TilerServer, anyhow::Error> {
+        let cwd = match self.cwd {
+            Some(cwd) => cwd,
+            None => get_cwd()?,
+        };
+        let config = match self.config {
+            Some(config) => config,
+            None => load_config().await?,
+        };
+        let paths = Paths::new(&cwd, &config.paths);
+
+        // workers write the cache and read source data in the same directories as the balancer
+        let mut vars = self.vars;
+        vars.insert(CACHE_DIR_ENV, paths.cache.to_string_lossy().to_string());
+        vars.insert(DATA_DIR_ENV, paths.data.to_string_lossy().to_string());
+
+        Ok(TilerServer {
+            paths,
+            config,
+            vars,
+            bind: self.bind,
+            registry: self.registry,
+            pool: self.pool,
+            queue: self.queue,
+            workers: self.workers,
+            routes: self.routes,
+        })
+    }
+}
+
+// Background tasks of the server, workers are terminated and MBTiles are closed on shutdown
+struct Tasks {
+    tx_sqlite_client: flume::Sender<MessageSQLiteClient>,
+    tx_mw: flume::Sender<MessageMaintenanceWorkers>,
+    handles: Vec<JoinHandle<()>>,
+}
+
+impl Tasks {
+    fn close(&self) {
+        let _ = self
+            .tx_sqlite_client
+            .send(MessageSQLiteClient::CloseSQLiteClients());
+        let _ = self
+            .tx_mw
+            .send(MessageMaintenanceWorkers::TerminateWorkers());
+        event!(Level::INFO, "Terminate Python workers");
+    }
+
+    fn abort(&mut self) {
+        for jh in self.handles.drain(..) {
+            jh.abort();
+        }
+    }
+
+    async fn stop(mut self) {
+        self.close();
+        tokio::time::sleep(std::time::Duration::from_secs(3)).await;
+        self.abort();
+    }
+}
+
+// Running server: address of the listener and its shutdown
+pub struct TilerServerHandle {
+    local_addr: SocketAddr,
+    tx_shutdown: Option<oneshot::Sender<()>>,
+    jh_server: JoinHandle<Result<(), hyper::Error>>,
+    tasks: Option<Tasks>,
+}
+
+impl TilerServer {
+    pub fn builder() -> TilerServerBuilder {
+        TilerServerBuilder::default()
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn paths(&self) -> &Paths {
+        &self.paths
+    }
+
+    // Starts workers, background tasks and the listener, requests are served in a task
+    pub async fn start(self) -> Result<TilerServerHandle, anyhow::Error> {
+        let TilerServer {
+            paths,
+            config,
+            vars,
+            bind,
+            registry,
+            pool,
+            queue,
+            workers,
+            routes,
+        } = self;
+
+        // check exist DB tiler.db
+        if let Err(err) = init_db(&paths).await {
+            return Err(anyhow!("Error initialize DataBase 'tiler.db': {err}"));
+        }
+
+        /*
+            Config database is required by the registry 'postgres' and by the job queue of the master,
+            MySQL ('DBSCHEME=mysql') keeps only the job queue
+        */
+        let requires_pool = (registry.is_none() && config.registry_type == REGISTRY_POSTGRES)
+            || (queue.is_none() && config.master);
+        let opt_pool = match pool {
+            Some(p) => Some(p),
+            None if requires_pool => Some(db_pool(&config.db_pool).await?),
+            None => None,
+        };
+        if let Some(pool) = &opt_pool {
+            check_migrations(pool).await?;
+        }
+
+        let pg_pool = opt_pool.as_ref().and_then(DBPool::postgres);
+        let registry = match registry {
+            Some(r) => r,
+            None => match open_registry(&paths, &config, pg_pool.clone()).await {
+                Err(err) => return Err(anyhow!("Error open registry of DataSources: {err}")),
+                Ok(r) => r,
+            },
+        };
+
+        // workers by ports with indexes of their slots
+        let mut childs: HashMap<u16, (usize, Child)> =
+            HashMap::with_capacity(config.processes_workers as usize);
+        let mut ports: Vec<u16> = Vec::with_capacity(config.processes_workers as usize);
+        // one pooled client is shared between all workers
+        let client = worker_client(&config);
+
+        // in native mode tiles are rendered by the balancer, Python workers are not started
+        if !config.is_native() && workers.is_empty() {
+            for _ in 0..config.processes_workers {
+                if let Some(p) = get_available_port(
+                    config.worker_port_from as u16,
+                    config.worker_port_to as u16,
+                    &ports,
+                )
+                .await
+                {
+                    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+                    ports.push(p);
+                }
+            }
+
+            if ports.len() == 0 {
+                return Err(anyhow!("Error request free ports"));
+            }
+
+            for (index, p) in ports.iter().enumerate() {
+                match cmd_run_worker(&config, &vars, *p, index) {
+                    Ok(worker) => {
+                        childs.insert(*p, (index, worker));
+                    }
+                    Err(err) => event!(Level::ERROR, "{}", err),
+                }
+            }
+
+            if childs.len() == 0 {
+                return Err(anyhow!("Worker processes did not start"));
+            }
+        }
+
+        let workers_pids: Vec<u32> = childs.values().map(|(_, w)| w.id().unwrap()).collect();
+        let mut handles: Vec<JoinHandle<()>> = Vec::new();
+
+        let (tx_events, rx_events) = flume::unbounded::<MessageEvents>();
+        handles.push(events_maintenance(rx_events));
+
+        let (tx_stats, rx_stats) = flume::unbounded::<MessageStats>();
+        handles.push(stats_maintenance(rx_stats));
+
+        let (tx_exports, rx_exports) = flume::unbounded::<MessageExports>();
+        handles.push(exports_maintenance(rx_exports));
+
+        remove_stale_parts(&paths).await;
+        let (tx_uploads, rx_uploads) = flume::unbounded::<MessageUploads>();
+        handles.push(uploads_maintenance(rx_uploads, config.upload_expire_secs));
+
+        let (tx_usage, rx_usage) = flume::unbounded::<MessageUsage>();
+        handles.push(usage_maintenance(
+            paths.clone(),
+            rx_usage,
+            config.usage_flush_secs,
+            config.usage_buffer_size,
+            config.usage_retention_days,
+            config.billing_quotas.clone(),
+        ));
+
+        let (tx_audit, rx_audit) = flume::unbounded::<MessageAudit>();
+        handles.push(audit_maintenance(paths.clone(), rx_audit));
+
+        // workers are not ready until a quorum of them passes the health check
+        let (tx_ready, rx_ready) = watch::channel(false);
+
+        let (tx_mw, rx_mw) = flume::unbounded::<MessageMaintenanceWorkers>();
+        handles.push(workers_maintenance(
+            paths.clone(),
+            config.clone(),
+            vars.clone(),
+            childs,
+            workers.clone(),
+            client,
+            rx_mw,
+            tx_events.clone(),
+            tx_ready,
+        ));
+
+        handles.push(reload_workers_maintenance(
+            paths.clone(),
+            tx_mw.clone(),
+            tx_audit.clone(),
+            config.clone(),
+        ));
+
+        let (tx_sqlite_client, rx_sqlite_client) = flume::unbounded::<MessageSQLiteClient>();
+        handles.push(sqlite_clients_maintenance(
+            rx_sqlite_client,
+            config.mbtiles_maintenance_secs,
+            config.mbtiles_thresholds,
+            config.sqlite_pool_max_clients,
+            config.sqlite_pool_idle_secs,
+            false,
+        ));
+
+        let (tx, rx) = flume::unbounded::<MessageDatasource>();
+        handles.push(datasources_maintenance(
+            paths.clone(),
+            registry.clone(),
+            rx,
+            config.clone(),
+            tx_sqlite_client.clone(),
+            tx_events.clone(),
+        ));
+
+        // soft-deleted DataSources are purged after the retention period
+        handles.push(trash_maintenance(
+            paths.clone(),
+            tx.clone(),
+            tx_mw.clone(),
+            tx_usage.clone(),
+            config.trash_interval_secs,
+        ));
+
+        // changes of DataSources made on any node are received from PostgreSQL
+        if let Some(pool) = pg_pool
+            .clone()
+            .filter(|_| config.registry_type == REGISTRY_POSTGRES)
+        {
+            handles.push(datasources_listener(pool, tx.clone(), tx_mw.clone()));
+        }
+
+        // pyramids are refused while the tiles volume is low on free space
+        let (tx_storage, rx_storage) = watch::channel(StorageInfo::default());
+        handles.push(storage_maintenance(
+            paths.clone(),
+            tx_storage,
+            config.storage_interval_secs,
+            config.storage_min_free_mb,
+        ));
+
+        // janitor of the cache evicts tiles of DataSources over their quotas
+        if config.quota_interval_secs > 0 {
+            handles.push(quotas_maintenance(
+                paths.clone(),
+                tx.clone(),
+                tx_sqlite_client.clone(),
+                tx_stats.clone(),
+                config.quota_interval_secs,
+            ));
+        }
+
+        let (tx_sem, rx_sem) = flume::unbounded::<MessageSemaphore>();
+        handles.push(semaphore_maintenance(
+            rx_sem,
+            tx_sem.clone(),
+            config.clone(),
+            [ports, workers].concat(),
+        ));
+
+        let (tx_cluster, rx_cluster) = flume::unbounded::<MessageCluster>();
+        handles.push(cluster_maintenance(rx_cluster, config.clone()));
+
+        // node of the cluster registers itself on the master server
+        if let Some(master) = config.cluster_master.clone() {
+            match config
+                .cluster_advertise_address
+                .clone()
+                .or(config.address.clone())
+            {
+                Some(advertise_address) => {
+                    handles.push(cluster_heartbeat(
+                        config.clone(),
+                        master,
+                        advertise_address,
+                        tx.clone(),
+                    ));
+                }
+                None => {
+                    event!(
+                        Level::ERROR,
+                        "Cluster 'advertise_address' is undefined, node will not register on master"
+                    );
+                }
+            }
+        }
+
+        let mut opt_tx_jd: Option<flume::Sender<JobDetail>> = None;
+        if let Some(queue) = queue.or(opt_pool.map(pool_queue)).filter(|_| config.master) {
+            let (tx_jd, rx_jd) = flume::unbounded::<JobDetail>();
+            let (jh_add_job, jh_job_worker) = init_job_queue(
+                paths.clone(),
+                queue,
+                registry.clone(),
+                rx_jd,
+                JobChannels {
+                    tx: tx.clone(),
+                    tx_mw: tx_mw.clone(),
+                    tx_sem: tx_sem.clone(),
+                    tx_sqlite_client: tx_sqlite_client.clone(),
+                    tx_events: tx_events.clone(),
+                    rx_storage: rx_storage.clone(),
+                },
+                config.clone(),
+            )?;
+            opt_tx_jd = Some(tx_jd);
+            handles.push(jh_add_job);
+            handles.push(jh_job_worker);
+        }
+
+        let tasks = Tasks {
+            tx_sqlite_client: tx_sqlite_client.clone(),
+            tx_mw: tx_mw.clone(),
+            handles,
+        };
+
+        if let Err(err) = try_save_process_pid(&paths, workers_pids).await {
+            tasks.stop().await;
+            return Err(anyhow!("Error save porcesses PIDs: {err}"));
+        }
+
+        let addr = match bind {
+            Some(addr) => addr,
+            None => format!("{}:{}", config.host, config.port).parse()?,
+        };
+        let listener = TcpListener::bind(addr).await?;
+        let local_addr = listener.local_addr()?;
+        let routes = Arc::new(routes);
+        let protocol = config.proxy.protocol;
+
+        let ctx = RequestContext {
+            paths,
+            registry,
+            pg_pool,
+            config,
+            tx,
+            tx_sqlite_client,
+            tx_mw,
+            tx_jd: opt_tx_jd,
+            tx_sem,
+            tx_cluster,
+            tx_events,
+            tx_stats,
+            tx_exports,
+            tx_uploads,
+            tx_usage,
+            tx_audit,
+            rx_storage,
+            rx_ready,
+        };
+
+        // Connection handler
+        let make_svc = make_service_fn(move |conn: &ClientStream| {
+            let remote_addr = conn.remote_addr().ip();
+            let ctx = ctx.clone();
+            let routes = routes.clone();
+
+            async move {
+                // Request handler
+                Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                    if let Some(route) = embedded_route(&routes, req.uri().path()) {
+                        let response = (route.handler)(req);
+                        return async move { Ok::<_, Infallible>(response.await) }.boxed();
+                    }
+
+                    handle(ctx.clone(), remote_addr, req).boxed()
+                }))
+            }
+        });
+
+        let (tx_shutdown, rx_shutdown) = oneshot::channel::<()>();
+        let server = Server::builder(incoming_listener(listener, protocol))
+            .serve(make_svc)
+            .with_graceful_shutdown(async {
+                let _ = rx_shutdown.await;
+            });
+        event!(
+            Level::INFO,
+            "Isone Tiler Server running on {:?}",
+            local_addr
+        );
+
+        Ok(TilerServerHandle {
+            local_addr,
+            tx_shutdown: Some(tx_shutdown),
+            jh_server: tokio::spawn(server),
+            tasks: Some(tasks),
+        })
+    }
+}
+
+impl TilerServerHandle {
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    // Waits until the server stops accepting requests
+    pub async fn wait(self) -> Result<(), anyhow::Error> {
+        self.jh_server.await??;
+        Ok(())
+    }
+
+    // Stops accepting requests, terminates workers and background tasks
+    pub async fn shutdown(mut self) -> Result<(), anyhow::Error> {
+        if let Some(tx_shutdown) = self.tx_shutdown.take() {
+            let _ = tx_shutdown.send(());
+        }
+        if let Some(tasks) = self.tasks.take() {
+            tasks.stop().await;
+        }
+        self.wait().await
+    }
+
+    /*
+        Process of the command 'serve': workers are terminated on Ctrl-C and the process exits,
+        background tasks can not be awaited in the handler of the signal
+    */
+    pub(crate) fn exit_on_ctrlc(&mut self, on_exit: fn()) -> Result<(), anyhow::Error> {
+        let mut tasks = self
+            .tasks
+            .take()
+            .ok_or(anyhow!("Server is already shut down"))?;
+        ctrlc::set_handler(move || {
+            tasks.close();
+            std::thread::sleep(std::time::Duration::from_secs(3));
+            tasks.abort();
+            on_exit();
+            std::process::exit(0);
+        })?;
+        Ok(())
+    }
+}
+
+// Embedded route of the path by whole segments
+fn embedded_route<'a>(routes: &'a [EmbeddedRoute], path: &str) -> Option<&'a EmbeddedRoute> {
+    routes.iter().find(|route| {
+        path.strip_prefix(route.path.as_str())
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    })
+}
@@ -0,0 +1,218 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use tracing::{event, Level};
+
+use crate::cog::raster_file_bounds;
+use crate::paths::Paths;
+use crate::tasks::datasources::DataSource;
+
+// State of source files of the DataSource in its cache, recorded after the pyramid
+pub const SOURCES_STATE: &str = "sources.json";
+
+const RASTER_EXTENSIONS: [&str; 2] = ["tif", "tiff"];
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SourceFile {
+    // seconds since the epoch
+    pub mtime: u64,
+    pub size: u64,
+    // [west, south, east, north] in degrees, None when the header of the file is unreadable
+    pub bounds: Option<[f64; 4]>,
+}
+
+// Source files by paths relative to the directory 'data'
+pub type SourceState = BTreeMap<String, SourceFile>;
+
+/*
+    Changes of source files since the recorded state: bounds cover new, modified and removed files,
+    None - the area is unknown (no recorded state or a file without bounds)
+*/
+#[derive(Debug, Clone, Default)]
+pub struct SourceChanges {
+    pub files: Vec<String>,
+    pub bounds: Option<[f64; 4]>,
+}
+
+/*
+    Source rasters of the DataSource: 'dataStore.file' of the single raster or GeoTIFFs
+    of 'dataStore.folder' of mosaics. Other DataSources have no tracked source files
+*/
+pub async fn source_files(paths: &Paths, ds: &DataSource) -> Result<Vec<PathBuf>, anyhow::Error> {
+    let data_store = ds.data.0.get("dataStore");
+    let field = |name: &str| {
+        data_store
+            .and_then(|s| s.get(name))
+            .and_then(|v| v.as_str())
+            .map(String::from)
+    };
+    let mosaics = ds
+        .data
+        .0
+        .get("mosaics")
+        .and_then(|m| m.as_bool())
+        .unwrap_or(false);
+
+    if ds.data_type.as_deref() != Some("raster") {
+        return Err(anyhow!(
+            "DataSource '{}' has no tracked source files",
+            ds.identifier
+        ));
+    }
+    if mosaics {
+        let folder = field("folder").ok_or(anyhow!("DataSource has no 'dataStore.folder'"))?;
+        let dir = paths.mosaics_dir().join(folder);
+        let mut files = Vec::new();
+        let mut entries = tokio::fs::read_dir(&dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            let is_raster = path
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| RASTER_EXTENSIONS.contains(&ext.to_lowercase().as_str()));
+            if is_raster {
+                files.push(path);
+            }
+        }
+        files.sort();
+        return Ok(files);
+    }
+    let file = field("file").ok_or(anyhow!("DataSource has no 'dataStore.file'"))?;
+    Ok(vec![paths.data.join(file)])
+}
+
+/*
+    Current state of source files: bounds of files unchanged since 'previous' are kept,
+    headers of new and modified files are read
+*/
+pub async fn scan_sources(
+    paths: &Paths,
+    ds: &DataSource,
+    previous: Option<&SourceState>,
+) -> Result<SourceState, anyhow::Error> {
+    let mut state = SourceState::new();
+    for path in source_files(paths, ds).await? {
+        let metadata = tokio::fs::metadata(&path).await?;
+        let mtime = metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        let key = relative_path(&paths.data, &path);
+        let bounds = match previous.and_then(|p| p.get(&key)) {
+            Some(file) if file.mtime == mtime && file.size == metadata.len() => file.bounds,
+            _ => match raster_file_bounds(&path).await {
+                Ok(bounds) => Some(bounds),
+                Err(err) => {
+                    event!(Level::WARN, "Bounds of source file {path:?} unknown: {err}");
+                    None
+                }
+            },
+        };
+        state.insert(
+            key,
+            SourceFile {
+                mtime,
+                size: metadata.len(),
+                bounds,
+            },
+        );
+    }
+    Ok(state)
+}
+
+fn relative_path(root: &Path, path: &Path) -> String {
+    path.strip_prefix(root)
+        .unwrap_or(path)
+        .to_string_lossy()
+        .to_string()
+}
+
+pub fn source_changes(previous: &SourceState, current: &SourceState) -> SourceChanges {
+    let mut changes = SourceChanges::default();
+    let mut unknown = false;
+    let mut bounds: Option<[f64; 4]> = None;
+    let mut extend = |file_bounds: Option<[f64; 4]>| match (file_bounds, bounds) {
+        (None, _) => unknown = true,
+        (Some(b), None) => bounds = Some(b),
+        (Some(b), Some(a)) => {
+            bounds = Some([
+                a[0].min(b[0]),
+                a[1].min(b[1]),
+                a[2].max(b[2]),
+                a[3].max(b[3]),
+            ])
+        }
+    };
+    for (key, file) in current.iter() {
+        match previous.get(key) {
+            Some(old) if old.mtime == file.mtime && old.size == file.size => {}
+            Some(old) => {
+                // tiles of the old and the new extent of the file are created again
+                extend(old.bounds);
+                extend(file.bounds);
+                changes.files.push(key.clone());
+            }
+            None => {
+                extend(file.bounds);
+                changes.files.push(key.clone());
+            }
+        }
+    }
+    for (key, old) in previous.iter() {
+        if !current.contains_key(key) {
+            extend(old.bounds);
+            changes.files.push(key.clone());
+        }
+    }
+    changes.bounds = if unknown { None } else { bounds };
+    changes
+}
+
+pub async fn load_sources_state(paths: &Paths, datasource_id: &str) -> Option<SourceState> {
+    let path = paths.tiles_dir(datasource_id).join(SOURCES_STATE);
+    let data = tokio::fs::read(&path).await.ok()?;
+    match serde_json::from_slice(&data) {
+        Ok(state) => Some(state),
+        Err(err) => {
+            event!(Level::ERROR, "Error parse state of sources {path:?}: {err}");
+            None
+        }
+    }
+}
+
+pub async fn save_sources_state(
+    paths: &Paths,
+    datasource_id: &str,
+    state: &SourceState,
+) -> Result<(), anyhow::Error> {
+    let path = paths.tiles_dir(datasource_id).join(SOURCES_STATE);
+    tokio::fs::write(&path, serde_json::to_vec_pretty(state)?).await?;
+    Ok(())
+}
+
+// State of sources is recorded after the pyramid, DataSources without tracked sources are skipped
+pub async fn record_sources_state(paths: &Paths, ds: &DataSource) {
+    let previous = load_sources_state(paths, &ds.identifier).await;
+    let state = match scan_sources(paths, ds, previous.as_ref()).await {
+        Ok(state) => state,
+        Err(err) => {
+            event!(
+                Level::DEBUG,
+                "Sources of DataSource '{}' are not recorded: {err}",
+                ds.identifier
+            );
+            return;
+        }
+    };
+    if let Err(err) = save_sources_state(paths, &ds.identifier, &state).await {
+        event!(
+            Level::ERROR,
+            "Error save state of sources of DataSource '{}': {err}",
+            ds.identifier
+        );
+    }
+}
@@ -2,11 +2,25 @@ use strum_macros::{AsRefStr, Display, EnumString, IntoStaticStr};
 
 #[derive(Debug, Clone, Copy, PartialEq, Display, EnumString, IntoStaticStr, AsRefStr)]
 pub enum EndPoints {
+    // Prefixes of the API: unversioned paths are legacy aliases of the current version
+    #[strum(serialize = "/api")]
+    Api,
+    #[strum(serialize = "/api/v1")]
+    ApiV1,
+
     // API
     #[strum(serialize = "/api/tile")]
     Tile,
     #[strum(serialize = "/api/pyramid")]
     Pyramid,
+    #[strum(serialize = "/api/pyramid/smart")]
+    PyramidSmart,
+    #[strum(serialize = "/api/pyramid/delta")]
+    PyramidDelta,
+    #[strum(serialize = "/api/pyramid/distributed")]
+    PyramidDistributed,
+    #[strum(serialize = "/api/pyramid/shard")]
+    PyramidShard,
     #[strum(serialize = "/api/datasources")]
     DataSources,
     #[strum(serialize = "/api/datasources/load_files")]
@@ -15,6 +29,36 @@ pub enum EndPoints {
     DataSourcesReloadFiles,
     #[strum(serialize = "/api/health")]
     Health,
+    #[strum(serialize = "/api/events")]
+    Events,
+    #[strum(serialize = "/api/tile_grids")]
+    TileGrids,
+    #[strum(serialize = "/api/export")]
+    Export,
+    #[strum(serialize = "/api/export/download")]
+    ExportDownload,
+    #[strum(serialize = "/api/audit")]
+    AuditLog,
+    #[strum(serialize = "/api/usage")]
+    Usage,
+    #[strum(serialize = "/api/log_level")]
+    WorkerLogLevel,
+    #[strum(serialize = "/metrics")]
+    Metrics,
+    #[strum(serialize = "/healthz")]
+    Healthz,
+    #[strum(serialize = "/readyz")]
+    Readyz,
+    #[strum(serialize = "/api/openapi.json")]
+    OpenApi,
+    #[strum(serialize = "/api/docs")]
+    ApiDocs,
+
+    // Cluster
+    #[strum(serialize = "/api/cluster/register")]
+    ClusterRegister,
+    #[strum(serialize = "/api/cluster/nodes")]
+    ClusterNodes,
 
     // Jobs
     #[strum(serialize = "/api/jobs")]
@@ -23,6 +67,8 @@ pub enum EndPoints {
     // Debug
     #[strum(serialize = "/debug")]
     Debug,
+    #[strum(serialize = "/debug/route")]
+    DebugRoute,
 
     // Maintenance
     #[strum(serialize = "/maintenance/add_workers")]
@@ -38,10 +84,32 @@ pub enum EndPoints {
     IncreaseLimitConcurrentRequests,
     #[strum(serialize = "/maintenance/decrease_limit_cr")]
     DecreaseLimitConcurrentRequests,
+    #[strum(serialize = "/maintenance/limit_cr")]
+    LimitConcurrentRequests,
+    #[strum(serialize = "/maintenance/log_level")]
+    LogLevel,
+    #[strum(serialize = "/maintenance/save_pids")]
+    SavePids,
+    #[strum(serialize = "/maintenance/audit")]
+    Audit,
 
     //Static assets
     #[strum(serialize = "/static")]
     Static,
+
+    // Styles of MapLibre/Mapbox GL
+    #[strum(serialize = "/styles")]
+    Styles,
+    #[strum(serialize = "/sprites")]
+    Sprites,
+    #[strum(serialize = "/fonts")]
+    Fonts,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Display, EnumString, IntoStaticStr, AsRefStr)]
+pub enum ApiVersion {
+    #[strum(serialize = "v1")]
+    V1,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Display, EnumString, IntoStaticStr, AsRefStr)]
@@ -52,16 +120,41 @@ pub enum Extension {
     Pbf,
     #[strum(serialize = "mvt")]
     Mvt,
+    #[strum(serialize = "webp")]
+    Webp,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Display, EnumString, IntoStaticStr, AsRefStr)]
 pub enum ContentType {
     #[strum(serialize = "image/png")]
     Png,
+    #[strum(serialize = "image/webp")]
+    Webp,
     #[strum(serialize = "application/vnd.mapbox-vector-tile")]
     MvtPbf,
     #[strum(serialize = "application/json")]
     ApplicationJson,
+    #[strum(serialize = "text/event-stream")]
+    EventStream,
+    #[strum(serialize = "application/x-protobuf")]
+    Protobuf,
+    #[strum(serialize = "application/vnd.sqlite3")]
+    MBTiles,
+    #[strum(serialize = "text/plain; version=0.0.4")]
+    Prometheus,
+    #[strum(serialize = "application/offset+octet-stream")]
+    OffsetOctetStream,
     #[strum(serialize = "")]
     Empty,
 }
+
+// Response to tiles without data: 204, 404 or the PNG of 'blank_tile' (204 for other formats)
+#[derive(Debug, Clone, Copy, PartialEq, Display, EnumString, IntoStaticStr, AsRefStr)]
+pub enum MissingTile {
+    #[strum(serialize = "no_content")]
+    NoContent,
+    #[strum(serialize = "not_found")]
+    NotFound,
+    #[strum(serialize = "blank")]
+    Blank,
+}
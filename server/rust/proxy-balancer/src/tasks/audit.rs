@@ -0,0 +1,369 @@
+use std::net::IpAddr;
+use std::path::PathBuf;
+
+use anyhow::anyhow;
+use async_sqlite::{Client, ClientBuilder, JournalMode};
+use hyper::http::header::{self, HeaderMap, HeaderValue};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tracing::{event, Level};
+
+use crate::paths::Paths;
+
+/*
+    Maintenance actions, mutations of DataSources and starts of pyramids,
+    'timestamp' in seconds of UNIX time, 'details' - JSON of parameters,
+    'body_digest' - SHA-256 of the body of the request
+*/
+const TABLE_AUDIT: &str = "CREATE TABLE IF NOT EXISTS audit (
+        id integer PRIMARY KEY AUTOINCREMENT,
+        timestamp integer NOT NULL,
+        action text NOT NULL,
+        client_ip text NOT NULL,
+        user_agent text NOT NULL,
+        request_id text NOT NULL,
+        details text NOT NULL,
+        status integer NOT NULL,
+        actor text NOT NULL DEFAULT '',
+        datasource_id text NOT NULL DEFAULT '',
+        body_digest text NOT NULL DEFAULT ''
+    );";
+const INDEX_AUDIT: &str = "CREATE INDEX IF NOT EXISTS audit_timestamp ON audit (timestamp);";
+const INDEX_AUDIT_DATASOURCE: &str =
+    "CREATE INDEX IF NOT EXISTS audit_datasource_id ON audit (datasource_id);";
+// columns added to databases of earlier versions
+const AUDIT_COLUMNS: [&str; 3] = ["actor", "datasource_id", "body_digest"];
+
+// Actions of the audit log of DataSources and pyramids, maintenance actions are paths of endpoints
+pub const ACTION_DATASOURCE_CREATE: &str = "datasource_create";
+pub const ACTION_DATASOURCE_UPDATE: &str = "datasource_update";
+pub const ACTION_DATASOURCE_DELETE: &str = "datasource_delete";
+pub const ACTION_DATASOURCE_RESTORE: &str = "datasource_restore";
+pub const ACTION_DATASOURCES_LOAD: &str = "datasources_load_files";
+pub const ACTION_PYRAMID: &str = "pyramid";
+pub const ACTION_PYRAMID_SMART: &str = "pyramid_smart";
+pub const ACTION_PYRAMID_DELTA: &str = "pyramid_delta";
+pub const ACTION_PYRAMID_DISTRIBUTED: &str = "pyramid_distributed";
+pub const ACTION_PYRAMID_SHARD: &str = "pyramid_shard";
+
+/*
+    Client of the request, scheduled actions of the server have the client 'scheduler'.
+    'principal' is who is authenticated: the scheme of 'Authorization' with 16 hex chars
+    of SHA-256 of credentials (credentials are never stored), 'cluster' for signed requests
+    of instances of the cluster, empty for anonymous requests
+*/
+#[derive(Debug, Clone, Default)]
+pub struct AuditActor {
+    pub client_ip: String,
+    pub user_agent: String,
+    pub request_id: String,
+    pub principal: String,
+}
+
+impl AuditActor {
+    pub fn scheduler() -> Self {
+        AuditActor {
+            client_ip: "scheduler".to_string(),
+            principal: "scheduler".to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn from_request(
+        client_ip: IpAddr,
+        headers: &HeaderMap<HeaderValue>,
+        request_id: String,
+        signed: bool,
+    ) -> Self {
+        let header = |name: header::HeaderName| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .unwrap_or_default()
+        };
+        let authorization = header(header::AUTHORIZATION).trim();
+        let principal = if signed {
+            "cluster".to_string()
+        } else if authorization.is_empty() {
+            String::new()
+        } else {
+            let (scheme, credentials) =
+                authorization.split_once(' ').unwrap_or(("", authorization));
+            let digest = hex::encode(Sha256::digest(credentials.trim().as_bytes()));
+            format!("{}:{}", scheme.to_ascii_lowercase(), &digest[..16])
+        };
+        AuditActor {
+            client_ip: client_ip.to_string(),
+            user_agent: header(header::USER_AGENT).to_string(),
+            request_id,
+            principal,
+        }
+    }
+}
+
+// Filter of the audit log, None - any value
+#[derive(Debug, Clone, Default)]
+pub struct AuditFilter {
+    pub since: i64,
+    pub until: Option<i64>,
+    pub action: Option<String>,
+    pub datasource_id: Option<String>,
+    pub actor: Option<String>,
+    pub limit: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditEntry {
+    pub id: i64,
+    pub timestamp: i64,
+    pub action: String,
+    pub client_ip: String,
+    pub user_agent: String,
+    pub request_id: String,
+    pub details: serde_json::Value,
+    pub status: u16,
+    pub actor: String,
+    pub datasource_id: String,
+    pub body_digest: String,
+}
+
+pub enum MessageAudit {
+    Record {
+        actor: AuditActor,
+        action: String,
+        datasource_id: String,
+        body_digest: String,
+        details: serde_json::Value,
+        status: u16,
+    },
+    // the latest actions go first
+    List {
+        filter: AuditFilter,
+        tx_entries: oneshot::Sender<Result<Vec<AuditEntry>, anyhow::Error>>,
+    },
+}
+
+pub fn db_audit(paths: &Paths) -> PathBuf {
+    paths.data.join("audit.db")
+}
+
+async fn open_audit_db(paths: &Paths) -> Result<Client, anyhow::Error> {
+    let client = ClientBuilder::new()
+        .path(db_audit(paths))
+        .journal_mode(JournalMode::Wal)
+        .open()
+        .await
+        .map_err(|err| anyhow!(err))?;
+    client
+        .conn(|connection| {
+            connection.execute(TABLE_AUDIT, ())?;
+            let mut stmt = connection.prepare("SELECT name FROM pragma_table_info('audit');")?;
+            let columns = stmt
+                .query_map((), |row| row.get::<_, String>(0))?
+                .collect::<Result<Vec<String>, _>>()?;
+            for column in AUDIT_COLUMNS {
+                if !columns.iter().any(|c| c == column) {
+                    connection.execute(
+                        &format!("ALTER TABLE audit ADD COLUMN {column} text NOT NULL DEFAULT '';"),
+                        (),
+                    )?;
+                }
+            }
+            connection.execute(INDEX_AUDIT, ())?;
+            connection.execute(INDEX_AUDIT_DATASOURCE, ())
+        })
+        .await
+        .map_err(|err| anyhow!(err))?;
+    Ok(client)
+}
+
+async fn insert_entry(
+    client: &Client,
+    actor: AuditActor,
+    action: String,
+    datasource_id: String,
+    body_digest: String,
+    details: serde_json::Value,
+    status: u16,
+) -> Result<(), anyhow::Error> {
+    let timestamp = chrono::Utc::now().timestamp();
+    client
+        .conn(move |connection| {
+            connection.execute(
+                "INSERT INTO audit
+                 (timestamp, action, client_ip, user_agent, request_id, details, status,
+                  actor, datasource_id, body_digest)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10);",
+                rusqlite::params![
+                    timestamp,
+                    action,
+                    actor.client_ip,
+                    actor.user_agent,
+                    actor.request_id,
+                    details.to_string(),
+                    status,
+                    actor.principal,
+                    datasource_id,
+                    body_digest
+                ],
+            )
+        })
+        .await
+        .map(|_| ())
+        .map_err(|err| anyhow!(err))
+}
+
+async fn select_entries(
+    client: &Client,
+    filter: AuditFilter,
+) -> Result<Vec<AuditEntry>, anyhow::Error> {
+    client
+        .conn(move |connection| {
+            let mut entries = Vec::new();
+            let mut stmt = connection.prepare(
+                "SELECT id, timestamp, action, client_ip, user_agent, request_id, details, status,
+                 actor, datasource_id, body_digest
+                 FROM audit WHERE timestamp >= ?1 AND (?2 IS NULL OR timestamp <= ?2)
+                 AND (?3 IS NULL OR action = ?3) AND (?4 IS NULL OR datasource_id = ?4)
+                 AND (?5 IS NULL OR actor = ?5)
+                 ORDER BY id DESC LIMIT ?6;",
+            )?;
+            let mut rows = stmt.query(rusqlite::params![
+                filter.since,
+                filter.until,
+                filter.action,
+                filter.datasource_id,
+                filter.actor,
+                filter.limit
+            ])?;
+            while let Some(row) = rows.next()? {
+                let details: String = row.get(6)?;
+                entries.push(AuditEntry {
+                    id: row.get(0)?,
+                    timestamp: row.get(1)?,
+                    action: row.get(2)?,
+                    client_ip: row.get(3)?,
+                    user_agent: row.get(4)?,
+                    request_id: row.get(5)?,
+                    details: serde_json::from_str(&details).unwrap_or_default(),
+                    status: row.get(7)?,
+                    actor: row.get(8)?,
+                    datasource_id: row.get(9)?,
+                    body_digest: row.get(10)?,
+                });
+            }
+            Ok(entries)
+        })
+        .await
+        .map_err(|err| anyhow!(err))
+}
+
+/*
+    Audit log in 'audit.db' of 'paths.data': who (principal, address, user agent and id
+    of the request), when and what was done with workers, limits, the log level, DataSources
+    and pyramids, and the status of the response
+*/
+pub fn audit_maintenance(paths: Paths, rx: flume::Receiver<MessageAudit>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let client = match open_audit_db(&paths).await {
+            Ok(c) => Some(c),
+            Err(err) => {
+                event!(Level::ERROR, "Error open database of audit: {err}");
+                None
+            }
+        };
+
+        while let Ok(message) = rx.recv_async().await {
+            match message {
+                MessageAudit::Record {
+                    actor,
+                    action,
+                    datasource_id,
+                    body_digest,
+                    details,
+                    status,
+                } => {
+                    let Some(client) = client.as_ref() else {
+                        continue;
+                    };
+                    let result = insert_entry(
+                        client,
+                        actor,
+                        action.clone(),
+                        datasource_id,
+                        body_digest,
+                        details,
+                        status,
+                    );
+                    if let Err(err) = result.await {
+                        event!(Level::ERROR, "Error record audit of '{action}': {err}");
+                    }
+                }
+                MessageAudit::List { filter, tx_entries } => {
+                    let result = match client.as_ref() {
+                        Some(client) => select_entries(client, filter).await,
+                        None => Err(anyhow!("Database of audit is not available")),
+                    };
+                    if tx_entries.send(result).is_err() {
+                        event!(Level::ERROR, "Error send entries of audit");
+                    }
+                }
+            }
+        }
+
+        if let Some(client) = client {
+            if let Err(err) = client.close().await {
+                event!(Level::ERROR, "Error close database of audit: {err}");
+            }
+        }
+    })
+}
+
+pub fn record_audit(
+    tx: &flume::Sender<MessageAudit>,
+    actor: &AuditActor,
+    action: &str,
+    details: serde_json::Value,
+    status: u16,
+) {
+    let message = MessageAudit::Record {
+        actor: actor.clone(),
+        action: action.to_string(),
+        datasource_id: String::new(),
+        body_digest: String::new(),
+        details,
+        status,
+    };
+    if let Err(err) = tx.send(message) {
+        event!(Level::ERROR, "Error send audit of '{action}': {err}");
+    }
+}
+
+// Mutation of the DataSource or the start of the pyramid with SHA-256 of the body of the request
+pub fn record_mutation(
+    tx: &flume::Sender<MessageAudit>,
+    actor: &AuditActor,
+    action: &str,
+    datasource_id: &str,
+    body: &[u8],
+    status: u16,
+) {
+    let body_digest = if body.is_empty() {
+        String::new()
+    } else {
+        hex::encode(Sha256::digest(body))
+    };
+    let message = MessageAudit::Record {
+        actor: actor.clone(),
+        action: action.to_string(),
+        datasource_id: datasource_id.to_string(),
+        body_digest,
+        details: serde_json::json!({ "size": body.len() }),
+        status,
+    };
+    if let Err(err) = tx.send(message) {
+        event!(Level::ERROR, "Error send audit of '{action}': {err}");
+    }
+}
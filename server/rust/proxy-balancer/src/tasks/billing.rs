@@ -0,0 +1,340 @@
+use std::collections::HashMap;
+
+use anyhow::anyhow;
+use async_sqlite::Client;
+use chrono::{Datelike, NaiveDate};
+use hyper::http::header::{HeaderMap, HeaderValue};
+use serde::Serialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+/*
+    Tiles and bytes served by API keys per DataSource and day (UTC, 'YYYY-MM-DD').
+    Keys are never stored: 'api_key' is 16 hex chars of SHA-256 of the key
+*/
+pub const TABLE_BILLING_USAGE: &str = "CREATE TABLE IF NOT EXISTS billing_usage (
+        api_key text NOT NULL,
+        datasource_id text NOT NULL,
+        day text NOT NULL,
+        tiles integer NOT NULL,
+        bytes integer NOT NULL,
+        PRIMARY KEY(api_key, datasource_id, day)
+    );";
+pub const INDEX_BILLING_USAGE: &str =
+    "CREATE INDEX IF NOT EXISTS billing_usage_day ON billing_usage (day);";
+
+// Requests without the key are counted as one client
+pub const ANONYMOUS_KEY: &str = "anonymous";
+const API_KEY_PARAM: &str = "api_key";
+
+// Id of the API key of the request: the header of the config, otherwise the query parameter 'api_key'
+pub fn api_key_id(
+    headers: &HeaderMap<HeaderValue>,
+    query: Option<&str>,
+    key_header: &str,
+) -> String {
+    let key = headers
+        .get(key_header)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.trim().to_string())
+        .or_else(|| {
+            query.and_then(|q| {
+                q.split('&')
+                    .filter_map(|pair| pair.split_once('='))
+                    .find(|(k, _)| *k == API_KEY_PARAM)
+                    .and_then(|(_, v)| urlencoding::decode(v).ok())
+                    .map(|v| v.trim().to_string())
+            })
+        })
+        .filter(|key| !key.is_empty());
+    match key {
+        Some(key) => hex::encode(Sha256::digest(key.as_bytes()))[..16].to_string(),
+        None => ANONYMOUS_KEY.to_string(),
+    }
+}
+
+/*
+    Hard quota of the API key, None - unlimited. Exceeded daily limits are refused with 429
+    until the next day (UTC), monthly limits with 402 until the next month
+*/
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BillingQuota {
+    pub daily_tiles: Option<u64>,
+    pub daily_bytes: Option<u64>,
+    pub monthly_tiles: Option<u64>,
+    pub monthly_bytes: Option<u64>,
+}
+
+impl BillingQuota {
+    // Object {"daily_tiles", "daily_mb", "monthly_tiles", "monthly_mb"}
+    pub fn from_json(value: &Value) -> Result<Self, anyhow::Error> {
+        let object = value
+            .as_object()
+            .ok_or(anyhow!("quota must be an object"))?;
+        let limit = |name: &str| match object.get(name) {
+            None | Some(Value::Null) => Ok(None),
+            Some(limit) => limit
+                .as_u64()
+                .map(Some)
+                .ok_or(anyhow!("'{name}' must be a non-negative integer")),
+        };
+        Ok(BillingQuota {
+            daily_tiles: limit("daily_tiles")?,
+            daily_bytes: limit("daily_mb")?.map(|mb| mb * 1024 * 1024),
+            monthly_tiles: limit("monthly_tiles")?,
+            monthly_bytes: limit("monthly_mb")?.map(|mb| mb * 1024 * 1024),
+        })
+    }
+
+    // Monthly limits go first: the key is refused until the next month anyway
+    pub fn exceeded(&self, totals: &BillingTotals) -> Option<QuotaExceeded> {
+        let limits = [
+            (
+                QuotaPeriod::Monthly,
+                "monthly_tiles",
+                self.monthly_tiles,
+                totals.monthly_tiles,
+            ),
+            (
+                QuotaPeriod::Monthly,
+                "monthly_mb",
+                self.monthly_bytes,
+                totals.monthly_bytes,
+            ),
+            (
+                QuotaPeriod::Daily,
+                "daily_tiles",
+                self.daily_tiles,
+                totals.daily_tiles,
+            ),
+            (
+                QuotaPeriod::Daily,
+                "daily_mb",
+                self.daily_bytes,
+                totals.daily_bytes,
+            ),
+        ];
+        limits
+            .into_iter()
+            .find(|(_, _, limit, served)| limit.is_some_and(|limit| *served >= limit))
+            .map(|(period, limit, _, _)| QuotaExceeded {
+                period,
+                limit,
+                day: totals.day,
+            })
+    }
+}
+
+/*
+    Quotas of the billing: listed API keys, the default quota of other keys and requests
+    without the key. Unknown keys can be refused, totals are kept in memory for 'max_keys' keys
+*/
+#[derive(Debug, Clone, Default)]
+pub struct BillingQuotas {
+    pub keys: HashMap<String, BillingQuota>,
+    pub default: Option<BillingQuota>,
+    pub reject_unknown_keys: bool,
+    pub max_keys: usize,
+}
+
+impl BillingQuotas {
+    pub fn quota(&self, api_key: &str) -> Option<&BillingQuota> {
+        self.keys.get(api_key).or(self.default.as_ref())
+    }
+
+    pub fn is_known(&self, api_key: &str) -> bool {
+        self.keys.contains_key(api_key)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum QuotaPeriod {
+    Daily,
+    Monthly,
+}
+
+#[derive(Debug, Clone)]
+pub struct QuotaExceeded {
+    pub period: QuotaPeriod,
+    // name of the exceeded limit of the quota
+    pub limit: &'static str,
+    pub day: NaiveDate,
+}
+
+impl QuotaExceeded {
+    // Seconds until the limit is reset: the next day or the first day of the next month (UTC)
+    pub fn retry_after_secs(&self, now: chrono::DateTime<chrono::Utc>) -> u64 {
+        let next_day = self.day.succ_opt().unwrap_or(self.day);
+        let reset = match self.period {
+            QuotaPeriod::Daily => next_day,
+            QuotaPeriod::Monthly => {
+                let (year, month) = match self.day.month() {
+                    12 => (self.day.year() + 1, 1),
+                    month => (self.day.year(), month + 1),
+                };
+                NaiveDate::from_ymd_opt(year, month, 1).unwrap_or(next_day)
+            }
+        };
+        let reset = reset.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc();
+        (reset - now).num_seconds().max(1) as u64
+    }
+}
+
+// Tile served to the API key, bytes of the body of the response
+#[derive(Debug, Clone)]
+pub struct TileServed {
+    pub api_key: String,
+    pub datasource_id: String,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BillingCounter {
+    pub api_key: String,
+    pub datasource_id: String,
+    pub day: String,
+    pub tiles: u64,
+    pub bytes: u64,
+}
+
+// Filter of counters, None - any value. Days 'YYYY-MM-DD' are inclusive
+#[derive(Debug, Clone, Default)]
+pub struct BillingFilter {
+    pub api_key: Option<String>,
+    pub datasource_id: Option<String>,
+    pub since: Option<String>,
+    pub until: Option<String>,
+    pub limit: u64,
+}
+
+// Served by the API key in the current day and month, counted for keys with quotas
+#[derive(Debug, Clone)]
+pub struct BillingTotals {
+    pub day: NaiveDate,
+    pub daily_tiles: u64,
+    pub daily_bytes: u64,
+    pub monthly_tiles: u64,
+    pub monthly_bytes: u64,
+}
+
+impl BillingTotals {
+    pub fn new(today: NaiveDate) -> Self {
+        BillingTotals {
+            day: today,
+            daily_tiles: 0,
+            daily_bytes: 0,
+            monthly_tiles: 0,
+            monthly_bytes: 0,
+        }
+    }
+
+    // Counters of a past day or month are reset
+    pub fn roll(&mut self, today: NaiveDate) {
+        if self.day == today {
+            return;
+        }
+        if (self.day.year(), self.day.month()) != (today.year(), today.month()) {
+            self.monthly_tiles = 0;
+            self.monthly_bytes = 0;
+        }
+        self.daily_tiles = 0;
+        self.daily_bytes = 0;
+        self.day = today;
+    }
+
+    pub fn add(&mut self, today: NaiveDate, bytes: u64) {
+        self.roll(today);
+        self.daily_tiles += 1;
+        self.daily_bytes += bytes;
+        self.monthly_tiles += 1;
+        self.monthly_bytes += bytes;
+    }
+}
+
+pub fn billing_day(date: NaiveDate) -> String {
+    date.format("%Y-%m-%d").to_string()
+}
+
+// Tiles and bytes by (API key, DataSource, day) are added to the counters of the table
+pub fn insert_counters(
+    tx: &rusqlite::Transaction,
+    served: &HashMap<(String, String, String), (u64, u64)>,
+) -> Result<(), rusqlite::Error> {
+    let mut stmt = tx.prepare(
+        "INSERT INTO billing_usage (api_key, datasource_id, day, tiles, bytes)
+         VALUES (?1, ?2, ?3, ?4, ?5)
+         ON CONFLICT (api_key, datasource_id, day)
+         DO UPDATE SET tiles = tiles + excluded.tiles, bytes = bytes + excluded.bytes;",
+    )?;
+    for ((api_key, datasource_id, day), (tiles, bytes)) in served {
+        stmt.execute(rusqlite::params![api_key, datasource_id, day, tiles, bytes])?;
+    }
+    Ok(())
+}
+
+pub async fn select_counters(
+    client: &Client,
+    filter: BillingFilter,
+) -> Result<Vec<BillingCounter>, anyhow::Error> {
+    client
+        .conn(move |connection| {
+            let mut counters = Vec::new();
+            let mut stmt = connection.prepare(
+                "SELECT api_key, datasource_id, day, tiles, bytes FROM billing_usage
+                 WHERE (?1 IS NULL OR api_key = ?1) AND (?2 IS NULL OR datasource_id = ?2)
+                 AND (?3 IS NULL OR day >= ?3) AND (?4 IS NULL OR day <= ?4)
+                 ORDER BY day DESC, api_key, datasource_id LIMIT ?5;",
+            )?;
+            let mut rows = stmt.query(rusqlite::params![
+                filter.api_key,
+                filter.datasource_id,
+                filter.since,
+                filter.until,
+                filter.limit
+            ])?;
+            while let Some(row) = rows.next()? {
+                counters.push(BillingCounter {
+                    api_key: row.get(0)?,
+                    datasource_id: row.get(1)?,
+                    day: row.get(2)?,
+                    tiles: row.get(3)?,
+                    bytes: row.get(4)?,
+                });
+            }
+            Ok(counters)
+        })
+        .await
+        .map_err(|err| anyhow!(err))
+}
+
+// Totals of the API key in the day and its month from the table
+pub async fn select_totals(
+    client: &Client,
+    api_key: String,
+    today: NaiveDate,
+) -> Result<BillingTotals, anyhow::Error> {
+    let day = billing_day(today);
+    let month_start = billing_day(today.with_day(1).unwrap_or(today));
+    client
+        .conn(move |connection| {
+            let sums = |since: &str| {
+                connection.query_row(
+                    "SELECT COALESCE(SUM(tiles), 0), COALESCE(SUM(bytes), 0) FROM billing_usage
+                     WHERE api_key = ?1 AND day >= ?2 AND day <= ?3;",
+                    rusqlite::params![api_key, since, day],
+                    |row| Ok((row.get::<_, u64>(0)?, row.get::<_, u64>(1)?)),
+                )
+            };
+            let (daily_tiles, daily_bytes) = sums(&day)?;
+            let (monthly_tiles, monthly_bytes) = sums(&month_start)?;
+            Ok(BillingTotals {
+                day: today,
+                daily_tiles,
+                daily_bytes,
+                monthly_tiles,
+                monthly_bytes,
+            })
+        })
+        .await
+        .map_err(|err| anyhow!(err))
+}
@@ -0,0 +1,162 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use tracing::{event, Level};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BreakerState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CircuitBreakerInfo {
+    pub state: BreakerState,
+    pub consecutive_failures: u64,
+    pub open_seconds_ago: Option<u64>,
+}
+
+#[derive(Debug)]
+struct CircuitBreaker {
+    state: BreakerState,
+    consecutive_failures: u64,
+    opened_at: Option<Instant>,
+    probe_started_at: Option<Instant>,
+}
+
+impl CircuitBreaker {
+    fn new() -> Self {
+        CircuitBreaker {
+            state: BreakerState::Closed,
+            consecutive_failures: 0,
+            opened_at: None,
+            probe_started_at: None,
+        }
+    }
+}
+
+/*
+    Circuit breakers of worker ports:
+    - Closed: port takes part in balancing
+    - Open: after 'failure_threshold' consecutive upstream errors the port is removed from balancing
+    - HalfOpen: after 'open_duration' one probe request is sent to the port,
+      success closes the breaker, failure opens it again
+*/
+#[derive(Debug)]
+pub struct CircuitBreakers {
+    failure_threshold: u64,
+    open_duration: Duration,
+    breakers: HashMap<u16, CircuitBreaker>,
+}
+
+impl CircuitBreakers {
+    pub fn new(failure_threshold: u64, open_secs: u64) -> Self {
+        CircuitBreakers {
+            failure_threshold,
+            open_duration: Duration::from_secs(open_secs),
+            breakers: HashMap::new(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.failure_threshold > 0
+    }
+
+    // Check if the port may receive a request, switches Open -> HalfOpen after cooldown
+    pub fn allow(&mut self, port: u16) -> bool {
+        if !self.is_enabled() {
+            return true;
+        }
+        let open_duration = self.open_duration;
+        let breaker = self
+            .breakers
+            .entry(port)
+            .or_insert_with(CircuitBreaker::new);
+        match breaker.state {
+            BreakerState::Closed => true,
+            BreakerState::Open => {
+                let elapsed = breaker.opened_at.map(|t| t.elapsed()).unwrap_or_default();
+                if elapsed >= open_duration {
+                    breaker.state = BreakerState::HalfOpen;
+                    breaker.probe_started_at = Some(Instant::now());
+                    event!(
+                        Level::INFO,
+                        "Circuit breaker half-open for worker port {port}"
+                    );
+                    true
+                } else {
+                    false
+                }
+            }
+            BreakerState::HalfOpen => {
+                // probe result was lost (request cancelled), allow next probe
+                let probe_expired = breaker
+                    .probe_started_at
+                    .map(|t| t.elapsed() >= open_duration)
+                    .unwrap_or(true);
+                if probe_expired {
+                    breaker.probe_started_at = Some(Instant::now());
+                }
+                probe_expired
+            }
+        }
+    }
+
+    pub fn record(&mut self, port: u16, success: bool) {
+        if !self.is_enabled() {
+            return;
+        }
+        let breaker = self
+            .breakers
+            .entry(port)
+            .or_insert_with(CircuitBreaker::new);
+        if success {
+            if breaker.state != BreakerState::Closed {
+                event!(Level::INFO, "Circuit breaker closed for worker port {port}");
+            }
+            *breaker = CircuitBreaker::new();
+            return;
+        }
+
+        breaker.consecutive_failures += 1;
+        let open = match breaker.state {
+            BreakerState::Closed => breaker.consecutive_failures >= self.failure_threshold,
+            BreakerState::HalfOpen => true,
+            BreakerState::Open => false,
+        };
+        if open {
+            breaker.state = BreakerState::Open;
+            breaker.opened_at = Some(Instant::now());
+            breaker.probe_started_at = None;
+            event!(
+                Level::ERROR,
+                "Circuit breaker open for worker port {port} after {} consecutive failures",
+                breaker.consecutive_failures
+            );
+        }
+    }
+
+    // Remove breakers of ports which are no longer in rotation
+    pub fn retain(&mut self, ports: &[u16]) {
+        self.breakers.retain(|port, _| ports.contains(port));
+    }
+
+    pub fn info(&self) -> HashMap<u16, CircuitBreakerInfo> {
+        self.breakers
+            .iter()
+            .map(|(port, b)| {
+                (
+                    *port,
+                    CircuitBreakerInfo {
+                        state: b.state,
+                        consecutive_failures: b.consecutive_failures,
+                        open_seconds_ago: b.opened_at.map(|t| t.elapsed().as_secs()),
+                    },
+                )
+            })
+            .collect()
+    }
+}
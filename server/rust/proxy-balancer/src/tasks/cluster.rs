@@ -0,0 +1,240 @@
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+use hyper::{header, Body, Client, Method, Request};
+use serde::Serialize;
+use serde_json::json;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tracing::{event, Level};
+
+use super::datasources::MessageDatasource;
+use crate::cluster::auth::sign_request;
+use crate::cluster::ring::HashRing;
+use crate::config::Config;
+use crate::structs::{ContentType, EndPoints};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct NodeInfo {
+    pub address: String,
+    pub alive: bool,
+    pub last_heartbeat_seconds_ago: u64,
+    pub datasources: Vec<String>,
+}
+
+pub enum MessageCluster {
+    Register {
+        address: String,
+        datasources: Vec<String>,
+    },
+    GetNodes {
+        tx_nodes: oneshot::Sender<Vec<NodeInfo>>,
+    },
+    SelectNodes {
+        datasource_id: String,
+        locations: Vec<String>,
+        // tile key '{datasource}/{z}/{x}/{y}' for routing on the hash ring
+        tile_key: Option<String>,
+        // selection for '/debug/route', the counter of round robin is not advanced
+        preview: bool,
+        tx_nodes: oneshot::Sender<Vec<String>>,
+    },
+}
+
+struct Node {
+    last_heartbeat: Instant,
+    datasources: HashSet<String>,
+}
+
+pub fn cluster_maintenance(rx: flume::Receiver<MessageCluster>, config: Config) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let node_timeout = Duration::from_secs(config.cluster_node_timeout_secs);
+        let mut nodes: HashMap<String, Node> = HashMap::new();
+        let mut counters: HashMap<String, usize> = HashMap::new();
+        let mut rings: HashMap<Vec<String>, HashRing> = HashMap::new();
+
+        while let Ok(message) = rx.recv_async().await {
+            match message {
+                MessageCluster::Register {
+                    address,
+                    datasources,
+                } => {
+                    if !nodes.contains_key(&address) {
+                        event!(Level::INFO, "Node '{address}' registered in cluster");
+                    }
+                    nodes.insert(
+                        address,
+                        Node {
+                            last_heartbeat: Instant::now(),
+                            datasources: datasources.into_iter().collect(),
+                        },
+                    );
+                }
+                MessageCluster::GetNodes { tx_nodes } => {
+                    let mut info: Vec<NodeInfo> = nodes
+                        .iter()
+                        .map(|(address, node)| {
+                            let mut datasources: Vec<String> =
+                                node.datasources.iter().cloned().collect();
+                            datasources.sort();
+                            NodeInfo {
+                                address: address.clone(),
+                                alive: node.last_heartbeat.elapsed() < node_timeout,
+                                last_heartbeat_seconds_ago: node.last_heartbeat.elapsed().as_secs(),
+                                datasources,
+                            }
+                        })
+                        .collect();
+                    info.sort_by(|a, b| a.address.cmp(&b.address));
+                    if tx_nodes.send(info).is_err() {
+                        event!(Level::ERROR, "Error send cluster nodes");
+                    }
+                }
+                MessageCluster::SelectNodes {
+                    datasource_id,
+                    locations,
+                    tile_key,
+                    preview,
+                    tx_nodes,
+                } => {
+                    let is_alive = |node: &Node| node.last_heartbeat.elapsed() < node_timeout;
+
+                    // nodes which never registered are trusted as is
+                    let mut selected: Vec<String> = locations
+                        .iter()
+                        .filter(|address| nodes.get(*address).map(is_alive).unwrap_or(true))
+                        .cloned()
+                        .collect();
+                    if let (Some(key), true) = (tile_key, selected.len() > 1) {
+                        // the same tile always goes to the same node cache
+                        selected.sort();
+                        let ring = rings
+                            .entry(selected.clone())
+                            .or_insert_with(|| HashRing::new(&selected));
+                        selected = ring.nodes_for(&key);
+                    } else if selected.len() > 1 {
+                        // balancing between replicas of the DataSource
+                        let counter = counters.entry(datasource_id.clone()).or_insert(0);
+                        let n = *counter % selected.len();
+                        selected.rotate_left(n);
+                        if !preview {
+                            *counter = counter.wrapping_add(1);
+                        }
+                    }
+
+                    // failover to any other alive node which serves the DataSource
+                    let mut others: Vec<String> = nodes
+                        .iter()
+                        .filter(|(address, node)| {
+                            is_alive(node)
+                                && node.datasources.contains(&datasource_id)
+                                && !locations.contains(address)
+                        })
+                        .map(|(address, _)| address.clone())
+                        .collect();
+                    others.sort();
+                    selected.extend(others);
+
+                    if tx_nodes.send(selected).is_err() {
+                        event!(Level::ERROR, "Error send cluster nodes selection");
+                    }
+                }
+            }
+        }
+    })
+}
+
+/*
+    Node registers itself periodically on the master server,
+    the list of local DataSources allows the master to route requests to it
+*/
+pub fn cluster_heartbeat(
+    config: Config,
+    master: String,
+    advertise_address: String,
+    tx: flume::Sender<MessageDatasource>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let client = Client::new();
+        let mut delay =
+            tokio::time::interval(Duration::from_secs(config.cluster_heartbeat_secs.max(1)));
+
+        loop {
+            delay.tick().await;
+
+            let (tx_ids, rx_ids) = oneshot::channel();
+            if let Err(err) = tx
+                .send_async(MessageDatasource::GetLocalDataSourceIds {
+                    address: advertise_address.clone(),
+                    tx_ids,
+                })
+                .await
+            {
+                event!(Level::ERROR, "Error request local DataSources: {err}");
+                continue;
+            }
+            let datasources = rx_ids.await.unwrap_or_default();
+
+            let body = json!({
+                "address": advertise_address,
+                "datasources": datasources,
+            })
+            .to_string();
+
+            let ct: &str = ContentType::ApplicationJson.into();
+            let mut req = match Request::builder()
+                .method(Method::POST)
+                .uri(format!(
+                    "http://{master}{}",
+                    EndPoints::ClusterRegister.as_ref()
+                ))
+                .header(header::CONTENT_TYPE, ct)
+                .body(Body::from(body.clone()))
+            {
+                Ok(r) => r,
+                Err(err) => {
+                    event!(Level::ERROR, "Error build cluster register request: {err}");
+                    continue;
+                }
+            };
+            if let Some(secret) = config.cluster_secret.as_deref() {
+                if let Err(err) = sign_request(
+                    req.headers_mut(),
+                    secret,
+                    &Method::POST,
+                    EndPoints::ClusterRegister.as_ref(),
+                    body.as_bytes(),
+                ) {
+                    event!(Level::ERROR, "Error sign cluster register request: {err}");
+                    continue;
+                }
+            }
+
+            match tokio::time::timeout(
+                Duration::from_secs(config.timeout_worker_response),
+                client.request(req),
+            )
+            .await
+            {
+                Err(_) => {
+                    event!(
+                        Level::ERROR,
+                        "Register on master '{master}' completed due to timeout"
+                    );
+                }
+                Ok(Err(err)) => {
+                    event!(Level::ERROR, "Error register on master '{master}': {err:?}");
+                }
+                Ok(Ok(response)) => {
+                    if !response.status().is_success() {
+                        event!(
+                            Level::ERROR,
+                            "Master '{master}' rejected registration: {}",
+                            response.status()
+                        );
+                    }
+                }
+            }
+        }
+    })
+}
@@ -1,18 +1,33 @@
 use std::collections::HashMap;
-use std::path::PathBuf;
 use std::process::exit;
+use std::sync::Arc;
 
 use anyhow::anyhow;
 use hyper::Client;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use sqlx::FromRow;
+use tiler_core::sqlite_clients::MessageSQLiteClient;
 use tokio::sync::oneshot;
 use tokio::task::JoinHandle;
+use tracing::{event, Level};
 
-use super::sqlite_clients::MessageSQLiteClient;
-use crate::config::Config;
-use crate::db::{init_mbtiles_db, DB};
-use crate::utils::worker_load_dss;
+use super::events::{publish_event, EventKind, MessageEvents};
+use super::trash::{load_trash, move_to_trash, restore_from_trash, TrashEntry};
+use crate::cog::{Cog, STORE_COG};
+use crate::composite::{CompositeSource, STORE_COMPOSITE};
+use crate::config::{parse_missing_tile, Config};
+use crate::db::{init_mbtiles_db, registry::SharedRegistry, storage_compressed};
+use crate::defaults::MAXZOOM;
+use crate::geofence::{parse_geofence, Geofence};
+use crate::grid::TileGrid;
+use crate::origins::AllowedOrigins;
+use crate::paths::Paths;
+use crate::postgis::{PostgisSource, STORE_POSTGIS};
+use crate::remote::{RemoteSource, STORE_REMOTE};
+use crate::structs::MissingTile;
+use crate::utils::{mbtiles_path_from_ds_id, worker_load_dss};
+use crate::vector_file::{load_vector_file, VectorFile, STORE_FILE};
 
 #[derive(Debug, Clone)]
 pub struct DataSourceInfo {
@@ -20,6 +35,71 @@ pub struct DataSourceInfo {
     pub port: Option<i32>,
     pub use_cache_only: Option<bool>,
     pub compress_tiles: Option<bool>,
+    pub replicas: Vec<String>,
+    pub tile_grid: Option<TileGrid>,
+    pub minzoom: Option<u8>,
+    pub maxzoom: Option<u8>,
+    // [west, south, east, north] in degrees
+    pub bounds: Option<[f64; 4]>,
+    // soft-deleted DataSource is in the trash until it is restored or purged
+    pub trash: Option<TrashEntry>,
+    // tiles of the store 'postgis' are generated by the proxy
+    pub postgis: Option<PostgisSource>,
+    // tiles of the store 'file' are cut by the proxy from the file loaded into memory
+    pub vector_file: Option<Arc<VectorFile>>,
+    // tiles of the store 'cog' are rendered by the proxy from range requests to the GeoTIFF
+    pub cog: Option<Arc<Cog>>,
+    // missing tiles of the store 'remote' are fetched by the proxy from the upstream
+    pub remote: Option<Arc<RemoteSource>>,
+    // tiles of the store 'composite' are merged by the proxy from tiles of child DataSources
+    pub composite: Option<CompositeSource>,
+    // encoding of values in raster tiles: 'f32', 'mapbox', 'terrarium' or 'none' for images
+    pub encoding: Option<String>,
+    // websites allowed to show tiles, None - tiles are not restricted
+    pub allowed_origins: Option<AllowedOrigins>,
+    // licensed area out of which tiles are refused, None - tiles are not restricted
+    pub geofence: Option<Arc<Geofence>>,
+    // waiters of a port after which tiles are refused, None - 'worker.max_queue_depth'
+    pub max_queue_depth: Option<usize>,
+    // response to tiles without data, None - 'missing_tile' of the server
+    pub missing_tile: Option<MissingTile>,
+    // empty tiles of workers are saved to the cache, None - 'negative_cache' of the server
+    pub negative_cache: Option<bool>,
+}
+
+impl DataSourceInfo {
+    /*
+        Network addresses 'host:port' of the DataSource: the main location first, then replicas.
+        Replicas are used only with the main location defined
+    */
+    pub fn locations(&self) -> Vec<String> {
+        let mut locations = Vec::new();
+        if let (Some(host), Some(port)) = (self.host.as_ref(), self.port) {
+            locations.push(format!("{host}:{port}"));
+            for replica in self.replicas.iter() {
+                if !locations.contains(replica) {
+                    locations.push(replica.clone());
+                }
+            }
+        }
+        locations
+    }
+
+    // Zoom levels out of 'minzoom'/'maxzoom' of the DataSource have no tiles
+    pub fn zoom_in_range(&self, z: u8) -> bool {
+        !matches!(self.minzoom, Some(minzoom) if z < minzoom)
+            && !matches!(self.maxzoom, Some(maxzoom) if z > maxzoom)
+    }
+
+    // Zoom of the parent tile when 'z' is deeper than 'maxzoom' but not deeper than 'overzoom_maxzoom'
+    pub fn overzoom_parent(&self, z: u8, overzoom_maxzoom: Option<u8>) -> Option<u8> {
+        match (self.maxzoom, overzoom_maxzoom) {
+            (Some(maxzoom), Some(overzoom_maxzoom)) if z > maxzoom && z <= overzoom_maxzoom => {
+                Some(maxzoom)
+            }
+            _ => None,
+        }
+    }
 }
 
 pub enum MessageDatasource {
@@ -30,11 +110,41 @@ pub enum MessageDatasource {
     UpdateDataSources {
         is_header_master: bool,
     },
+    // DataSources with the limit of the cache size 'max_cache_mb' in the DataSource JSON
+    GetQuotas {
+        tx_quotas: oneshot::Sender<Vec<(String, u64)>>,
+    },
+    // DataSources served by the node with given address (or without host and port)
+    GetLocalDataSourceIds {
+        address: String,
+        tx_ids: oneshot::Sender<Vec<String>>,
+    },
+    // cache of the DataSource is moved to the trash, tiles are not served until it is restored
+    SoftDelete {
+        datasource_id: String,
+        retention_days: u64,
+        tx_entry: oneshot::Sender<Result<TrashEntry, anyhow::Error>>,
+    },
+    Restore {
+        datasource_id: String,
+        tx_restored: oneshot::Sender<Result<bool, anyhow::Error>>,
+    },
+    // DataSources in the trash longer than the retention period
+    GetExpiredTrash {
+        tx_ids: oneshot::Sender<Vec<String>>,
+    },
+    PurgeTrash {
+        datasource_id: String,
+    },
 }
 
 #[derive(Debug, Clone)]
 pub struct MapDataSources {
     pub datasources: HashMap<String, DataSource>,
+    pub vector_files: HashMap<String, Arc<VectorFile>>,
+    pub cogs: HashMap<String, Arc<Cog>>,
+    pub remotes: HashMap<String, Arc<RemoteSource>>,
+    pub geofences: HashMap<String, Arc<Geofence>>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -73,38 +183,141 @@ pub struct DataSource {
     pub data: sqlx::types::Json<serde_json::Value>,
 }
 
-pub async fn load_datasource_from_db(pool: &DB, id: &str) -> Result<DataSource, anyhow::Error> {
-    let query =
-        sqlx::query_as::<_, DataSource>("SELECT * FROM datasource WHERE identifier = $1").bind(id);
-    let datasource: DataSource = query.fetch_one(pool).await?;
-    Ok(datasource)
-}
+impl DataSource {
+    /*
+        Rows of the table 'metadata' of MBTiles by the MBTiles 1.3 specification,
+        'json' with 'vector_layers' is defined only for vector DataSources
+    */
+    pub fn mbtiles_metadata(&self) -> Vec<(&'static str, String)> {
+        let is_vector = self.data_type.as_deref() == Some("vector");
+        let minzoom = self.minzoom.unwrap_or(0);
+        let maxzoom = self.maxzoom.unwrap_or(MAXZOOM as i16);
+        let [west, south, east, north] = self
+            .bounds
+            .as_ref()
+            .and_then(|b| parse_bounds(&b.0))
+            .unwrap_or([-180.0, -85.051129, 180.0, 85.051129]);
 
-pub async fn load_datasources_from_db(pool: &DB) -> Result<Vec<DataSource>, anyhow::Error> {
-    let query = sqlx::query_as::<_, DataSource>("SELECT * FROM datasource");
-    let datasources: Vec<DataSource> = query.fetch_all(pool).await?;
-    Ok(datasources)
+        let mut metadata = vec![
+            ("name", self.name.clone().unwrap_or(self.identifier.clone())),
+            ("format", if is_vector { "pbf" } else { "png" }.to_string()),
+            ("bounds", format!("{west},{south},{east},{north}")),
+            ("minzoom", minzoom.to_string()),
+            ("maxzoom", maxzoom.to_string()),
+            ("type", "overlay".to_string()),
+            ("scheme", "xyz".to_string()),
+        ];
+
+        let center = self
+            .center
+            .as_ref()
+            .and_then(|c| c.0.as_array().cloned())
+            .map(|c| c.iter().filter_map(|v| v.as_f64()).collect::<Vec<f64>>());
+        let center = match center.as_deref() {
+            Some([lon, lat, zoom]) => format!("{lon},{lat},{zoom}"),
+            Some([lon, lat]) => format!("{lon},{lat},{minzoom}"),
+            _ => format!(
+                "{},{},{minzoom}",
+                (west + east) / 2.0,
+                (south + north) / 2.0
+            ),
+        };
+        metadata.push(("center", center));
+
+        if let Some(description) = self.description.as_ref() {
+            metadata.push(("description", description.clone()));
+        }
+        if let Some(attribution) = self.attribution.as_ref() {
+            metadata.push(("attribution", attribution.clone()));
+        }
+        if let Some(version) = self.data.0.get("version").and_then(|v| v.as_str()) {
+            metadata.push(("version", version.to_string()));
+        }
+
+        if is_vector {
+            let vector_layers: Vec<serde_json::Value> = self
+                .data
+                .0
+                .get("layers")
+                .and_then(|l| l.as_array())
+                .map(|layers| {
+                    layers
+                        .iter()
+                        .filter_map(|layer| {
+                            let id = layer.get("id")?.as_str()?;
+                            let fields: serde_json::Map<String, serde_json::Value> = layer
+                                .get("fields")
+                                .and_then(|f| f.as_array())
+                                .map(|fields| {
+                                    fields
+                                        .iter()
+                                        .filter_map(|f| {
+                                            let name = f.get("name")?.as_str()?;
+                                            let description = f
+                                                .get("description")
+                                                .and_then(|d| d.as_str())
+                                                .unwrap_or("");
+                                            Some((name.to_string(), json!(description)))
+                                        })
+                                        .collect()
+                                })
+                                .unwrap_or_default();
+                            let zoom = |key: &str, default: i16| {
+                                layer
+                                    .get(key)
+                                    .and_then(|z| z.as_i64())
+                                    .unwrap_or(default as i64)
+                            };
+                            Some(json!({
+                                "id": id,
+                                "description": layer.get("description").and_then(|d| d.as_str()).unwrap_or(""),
+                                "minzoom": zoom("minzoom", minzoom),
+                                "maxzoom": zoom("maxzoom", maxzoom),
+                                "fields": fields,
+                            }))
+                        })
+                        .collect()
+                })
+                .unwrap_or_default();
+            metadata.push((
+                "json",
+                json!({ "vector_layers": vector_layers }).to_string(),
+            ));
+        }
+
+        metadata
+    }
 }
 
 pub async fn init_datasources_tile_dirs(
-    cwd: String,
-    pool: &DB,
+    paths: Paths,
+    registry: &SharedRegistry,
     tx_sqlite_client: flume::Sender<MessageSQLiteClient>,
+    trash: &HashMap<String, TrashEntry>,
+    config: &Config,
 ) -> Result<MapDataSources, anyhow::Error> {
-    let datasources = load_datasources_from_db(&pool)
+    let datasources = registry
+        .datasources()
         .await
-        .expect("Error load datasources from database");
+        .expect("Error load datasources from registry");
     let mut map_dss = HashMap::with_capacity(datasources.len());
     let mut handles = Vec::with_capacity(datasources.len());
 
     for ds in datasources.iter() {
-        let ds_tile_dir: PathBuf = [&cwd, "tiles", &ds.identifier].iter().collect();
+        // cache of the DataSource in the trash is not created again
+        if trash.contains_key(&ds.identifier) {
+            map_dss.insert(ds.identifier.clone(), ds.clone());
+            continue;
+        }
+        let ds_tile_dir = paths.tiles_dir(&ds.identifier);
 
         let jh = tokio::spawn({
             let tx_sqlite_client = tx_sqlite_client.clone();
             let mbtiles = ds.mbtiles.clone();
             let identifier = ds.identifier.clone();
-            let cwd = cwd.clone();
+            let maintenance = ds.data.0.get("mbtiles_maintenance").cloned();
+            let compressed = storage_compressed(&ds.data.0);
+            let paths = paths.clone();
 
             async move {
                 if !tokio::fs::try_exists(&ds_tile_dir).await? {
@@ -125,12 +338,27 @@ pub async fn init_datasources_tile_dirs(
                 // init mbtiles Database
                 if let Some(mbt) = mbtiles {
                     if mbt {
-                        init_mbtiles_db(&cwd, identifier.clone(), Some(tx_sqlite_client))
-                            .await
-                            .expect(&format!(
-                                "Error init mbtiles database for ID '{}'",
-                                identifier
-                            ));
+                        init_mbtiles_db(
+                            &paths,
+                            identifier.clone(),
+                            compressed,
+                            Some(tx_sqlite_client.clone()),
+                        )
+                        .await
+                        .expect(&format!(
+                            "Error init mbtiles database for ID '{}'",
+                            identifier
+                        ));
+                        let mbtiles_db = mbtiles_path_from_ds_id(&paths, &identifier)?
+                            .into_os_string()
+                            .into_string()
+                            .map_err(|err| anyhow!("{err:?}"))?;
+                        tx_sqlite_client
+                            .send_async(MessageSQLiteClient::SetMaintenanceThresholds {
+                                mbtiles_db,
+                                thresholds: maintenance,
+                            })
+                            .await?;
                     }
                 }
                 Ok::<(), anyhow::Error>(())
@@ -145,23 +373,188 @@ pub async fn init_datasources_tile_dirs(
         jh.await??;
     }
 
+    // files are loaded again with every update of DataSources
+    let mut vector_files = HashMap::new();
+    for ds in datasources
+        .iter()
+        .filter(|ds| ds.store_type.as_deref() == Some(STORE_FILE))
+    {
+        match load_vector_file(&paths, ds).await {
+            Ok(vector_file) => {
+                vector_files.insert(ds.identifier.clone(), Arc::new(vector_file));
+            }
+            Err(err) => {
+                event!(
+                    Level::ERROR,
+                    "Error load file of DataSource '{}': {err}",
+                    ds.identifier
+                );
+            }
+        }
+    }
+
+    // header of COG is read with the first tile
+    let mut cogs = HashMap::new();
+    for ds in datasources
+        .iter()
+        .filter(|ds| ds.store_type.as_deref() == Some(STORE_COG))
+    {
+        match Cog::from_datasource(ds) {
+            Ok(cog) => {
+                cogs.insert(ds.identifier.clone(), Arc::new(cog));
+            }
+            Err(err) => {
+                event!(
+                    Level::ERROR,
+                    "Error COG of DataSource '{}': {err}",
+                    ds.identifier
+                );
+            }
+        }
+    }
+
+    // rate limits of upstreams are reset with every update of DataSources
+    let mut remotes = HashMap::new();
+    for ds in datasources
+        .iter()
+        .filter(|ds| ds.store_type.as_deref() == Some(STORE_REMOTE))
+    {
+        match RemoteSource::from_datasource(ds) {
+            Ok(remote) => {
+                remotes.insert(ds.identifier.clone(), Arc::new(remote));
+            }
+            Err(err) => {
+                event!(
+                    Level::ERROR,
+                    "Error remote tiles of DataSource '{}': {err}",
+                    ds.identifier
+                );
+            }
+        }
+    }
+
+    // masks of geofences are computed in the grid of the DataSource
+    let mut geofences = HashMap::new();
+    for ds in datasources.iter() {
+        let Some(value) = ds.data.0.get("geofence").cloned() else {
+            continue;
+        };
+        let grid = datasource_tile_grid(ds, config)
+            .unwrap_or_else(|| config.tile_grids.default_grid().clone());
+        let geofence = tokio::task::spawn_blocking(move || {
+            parse_geofence(&value).map(|polygons| Geofence::new(polygons, &grid))
+        })
+        .await?;
+        match geofence {
+            Ok(geofence) => {
+                geofences.insert(ds.identifier.clone(), Arc::new(geofence));
+            }
+            Err(err) => {
+                event!(
+                    Level::ERROR,
+                    "Error geofence of DataSource '{}': {err}",
+                    ds.identifier
+                );
+            }
+        }
+    }
+
     Ok(MapDataSources {
         datasources: map_dss,
+        vector_files,
+        cogs,
+        remotes,
+        geofences,
     })
 }
 
+/*
+    Bounds of the DataSource: object {"lng_w", "lat_s", "lng_e", "lat_n"}
+    or array [west, south, east, north]
+*/
+fn parse_bounds(value: &serde_json::Value) -> Option<[f64; 4]> {
+    let bounds: Vec<f64> = if let Some(array) = value.as_array() {
+        array.iter().filter_map(|v| v.as_f64()).collect()
+    } else {
+        ["lng_w", "lat_s", "lng_e", "lat_n"]
+            .iter()
+            .filter_map(|key| value.get(key).and_then(|v| v.as_f64()))
+            .collect()
+    };
+    if bounds.len() != 4 || bounds[1] > bounds[3] {
+        return None;
+    }
+    Some([bounds[0], bounds[1], bounds[2], bounds[3]])
+}
+
+/*
+    Tile grid of the DataSource: field 'tile_grid' of the DataSource JSON
+    is the name of a registered grid or the definition of a custom grid
+*/
+fn datasource_tile_grid(ds: &DataSource, config: &Config) -> Option<TileGrid> {
+    let value = ds.data.0.get("tile_grid")?;
+    let grid = match value.as_str() {
+        Some(name) => config
+            .tile_grids
+            .get(name)
+            .cloned()
+            .ok_or(anyhow!("Tile grid '{name}' is not registered")),
+        None => TileGrid::from_json(value),
+    };
+    match grid {
+        Ok(g) => Some(g),
+        Err(err) => {
+            event!(
+                Level::ERROR,
+                "Error tile grid of DataSource '{}': {err}",
+                ds.identifier
+            );
+            None
+        }
+    }
+}
+
+// Tiles of workers are encoded to RGBA unless 'pyramidSettings.encode_to_rgba' is false
+fn raster_encoding(ds: &DataSource) -> String {
+    let encode_to_rgba = ds
+        .data
+        .0
+        .get("pyramidSettings")
+        .and_then(|s| s.get("encode_to_rgba"))
+        .and_then(|v| v.as_bool())
+        .unwrap_or(true);
+    let store = ds.store_type.as_deref();
+    if !encode_to_rgba || store == Some(STORE_COG) || store == Some(STORE_REMOTE) {
+        return "none".to_string();
+    }
+    ds.data
+        .0
+        .get("encoding")
+        .and_then(|v| v.as_str())
+        .unwrap_or("f32")
+        .to_string()
+}
+
 pub fn datasources_maintenance(
-    cwd: String,
-    pool: DB,
+    paths: Paths,
+    registry: SharedRegistry,
     rx: flume::Receiver<MessageDatasource>,
     config: Config,
     tx_sqlite_client: flume::Sender<MessageSQLiteClient>,
+    tx_events: flume::Sender<MessageEvents>,
 ) -> JoinHandle<()> {
     tokio::spawn(async move {
         let client = Client::new();
-        let mut map_dss = init_datasources_tile_dirs(cwd.clone(), &pool, tx_sqlite_client.clone())
-            .await
-            .expect("Error init datasources tiles directories");
+        let mut trash = load_trash(&paths).await;
+        let mut map_dss = init_datasources_tile_dirs(
+            paths.clone(),
+            &registry,
+            tx_sqlite_client.clone(),
+            &trash,
+            &config,
+        )
+        .await
+        .expect("Error init datasources tiles directories");
 
         while let Ok(message) = rx.recv_async().await {
             match message {
@@ -174,6 +567,58 @@ pub fn datasources_maintenance(
                             ds.data.0.get("use_cache_only").and_then(|v| v.as_bool());
                         let compress_tiles =
                             ds.data.0.get("compress_tiles").and_then(|v| v.as_bool());
+                        let replicas: Vec<String> = ds
+                            .data
+                            .0
+                            .get("replicas")
+                            .and_then(|v| v.as_array())
+                            .map(|replicas| {
+                                replicas
+                                    .iter()
+                                    .filter_map(|r| r.as_str().map(|s| s.to_string()))
+                                    .collect()
+                            })
+                            .unwrap_or_default();
+                        let tile_grid = datasource_tile_grid(ds, &config);
+                        let postgis = if ds.store_type.as_deref() == Some(STORE_POSTGIS) {
+                            match PostgisSource::from_data(&ds.data.0) {
+                                Ok(source) => Some(source),
+                                Err(err) => {
+                                    event!(
+                                        Level::ERROR,
+                                        "Error PostGIS DataSource '{datasource_id}': {err}"
+                                    );
+                                    None
+                                }
+                            }
+                        } else {
+                            None
+                        };
+                        // children are existing DataSources, not composite to avoid cycles
+                        let composite = if ds.store_type.as_deref() == Some(STORE_COMPOSITE) {
+                            match CompositeSource::from_data(&ds.data.0) {
+                                Ok(mut source) => {
+                                    source.children.retain(|child| {
+                                        map_dss.datasources.get(&child.id).is_some_and(|c| {
+                                            c.store_type.as_deref() != Some(STORE_COMPOSITE)
+                                        })
+                                    });
+                                    Some(source)
+                                }
+                                Err(err) => {
+                                    event!(
+                                        Level::ERROR,
+                                        "Error composite DataSource '{datasource_id}': {err}"
+                                    );
+                                    None
+                                }
+                            }
+                        } else {
+                            None
+                        };
+
+                        let encoding = (ds.data_type.as_deref() == Some("raster"))
+                            .then(|| raster_encoding(ds));
 
                         tx_ds
                             .send(Some(DataSourceInfo {
@@ -181,6 +626,36 @@ pub fn datasources_maintenance(
                                 port: ds.port,
                                 use_cache_only,
                                 compress_tiles,
+                                replicas,
+                                tile_grid,
+                                minzoom: ds.minzoom.and_then(|z| u8::try_from(z).ok()),
+                                maxzoom: ds.maxzoom.and_then(|z| u8::try_from(z).ok()),
+                                bounds: ds.bounds.as_ref().and_then(|b| parse_bounds(&b.0)),
+                                trash: trash.get(&datasource_id).cloned(),
+                                postgis,
+                                vector_file: map_dss.vector_files.get(&datasource_id).cloned(),
+                                cog: map_dss.cogs.get(&datasource_id).cloned(),
+                                remote: map_dss.remotes.get(&datasource_id).cloned(),
+                                composite,
+                                encoding,
+                                allowed_origins: AllowedOrigins::from_data(&ds.data.0),
+                                geofence: map_dss.geofences.get(&datasource_id).cloned(),
+                                max_queue_depth: ds
+                                    .data
+                                    .0
+                                    .get("max_queue_depth")
+                                    .and_then(|depth| depth.as_u64())
+                                    .map(|depth| depth as usize),
+                                missing_tile: ds
+                                    .data
+                                    .0
+                                    .get("missing_tile")
+                                    .and_then(parse_missing_tile),
+                                negative_cache: ds
+                                    .data
+                                    .0
+                                    .get("negative_cache")
+                                    .and_then(|enabled| enabled.as_bool()),
                             }))
                             .expect("Error send DataSourceInfo");
                     } else {
@@ -188,10 +663,20 @@ pub fn datasources_maintenance(
                     }
                 }
                 MessageDatasource::UpdateDataSources { is_header_master } => {
-                    map_dss =
-                        init_datasources_tile_dirs(cwd.clone(), &pool, tx_sqlite_client.clone())
-                            .await
-                            .expect("UpdateDataSources: error init datasources tiles directories");
+                    map_dss = init_datasources_tile_dirs(
+                        paths.clone(),
+                        &registry,
+                        tx_sqlite_client.clone(),
+                        &trash,
+                        &config,
+                    )
+                    .await
+                    .expect("UpdateDataSources: error init datasources tiles directories");
+                    publish_event(
+                        &tx_events,
+                        EventKind::DataSource,
+                        json!({"action": "update", "count": map_dss.datasources.len()}),
+                    );
                     if config.master && !is_header_master {
                         match worker_load_dss(&config, &map_dss, &client).await {
                             Err(_) => {}
@@ -199,6 +684,125 @@ pub fn datasources_maintenance(
                         }
                     }
                 }
+                MessageDatasource::GetQuotas { tx_quotas } => {
+                    let quotas: Vec<(String, u64)> = map_dss
+                        .datasources
+                        .values()
+                        .filter(|ds| !trash.contains_key(&ds.identifier))
+                        .filter_map(|ds| {
+                            let max_cache_mb = ds.data.0.get("max_cache_mb")?.as_u64()?;
+                            Some((ds.identifier.clone(), max_cache_mb))
+                        })
+                        .collect();
+                    if tx_quotas.send(quotas).is_err() {
+                        event!(Level::ERROR, "Error send quotas of DataSources");
+                    }
+                }
+                MessageDatasource::GetLocalDataSourceIds { address, tx_ids } => {
+                    let mut ids: Vec<String> = map_dss
+                        .datasources
+                        .values()
+                        .filter(|ds| match (ds.host.as_ref(), ds.port) {
+                            (Some(host), Some(port)) => format!("{host}:{port}") == address,
+                            _ => true,
+                        })
+                        .map(|ds| ds.identifier.clone())
+                        .collect();
+                    ids.sort();
+                    if tx_ids.send(ids).is_err() {
+                        event!(Level::ERROR, "Error send local DataSource ids");
+                    }
+                }
+                MessageDatasource::SoftDelete {
+                    datasource_id,
+                    retention_days,
+                    tx_entry,
+                } => {
+                    let result = if !map_dss.datasources.contains_key(&datasource_id) {
+                        Err(anyhow!("DataSource '{datasource_id}' not found"))
+                    } else if let Some(entry) = trash.get(&datasource_id) {
+                        Ok(entry.clone())
+                    } else {
+                        match move_to_trash(&paths, &datasource_id, retention_days).await {
+                            Ok(entry) => {
+                                trash.insert(datasource_id.clone(), entry.clone());
+                                Ok(entry)
+                            }
+                            Err(err) => Err(err),
+                        }
+                    };
+                    if result.is_ok() {
+                        publish_event(
+                            &tx_events,
+                            EventKind::DataSource,
+                            json!({"action": "soft_delete", "datasource_id": datasource_id}),
+                        );
+                    }
+                    if tx_entry.send(result).is_err() {
+                        event!(Level::ERROR, "Error send trash entry of '{datasource_id}'");
+                    }
+                }
+                MessageDatasource::Restore {
+                    datasource_id,
+                    tx_restored,
+                } => {
+                    let result = if trash.contains_key(&datasource_id) {
+                        match restore_from_trash(&paths, &datasource_id).await {
+                            Ok(()) => {
+                                trash.remove(&datasource_id);
+                                // MBTiles of the restored DataSource are opened again
+                                match init_datasources_tile_dirs(
+                                    paths.clone(),
+                                    &registry,
+                                    tx_sqlite_client.clone(),
+                                    &trash,
+                                    &config,
+                                )
+                                .await
+                                {
+                                    Ok(m) => map_dss = m,
+                                    Err(err) => {
+                                        event!(
+                                            Level::ERROR,
+                                            "Error init tiles directory of restored '{datasource_id}': {err}"
+                                        );
+                                    }
+                                }
+                                publish_event(
+                                    &tx_events,
+                                    EventKind::DataSource,
+                                    json!({"action": "restore", "datasource_id": datasource_id}),
+                                );
+                                Ok(true)
+                            }
+                            Err(err) => Err(err),
+                        }
+                    } else {
+                        Ok(false)
+                    };
+                    if tx_restored.send(result).is_err() {
+                        event!(Level::ERROR, "Error send restore of '{datasource_id}'");
+                    }
+                }
+                MessageDatasource::GetExpiredTrash { tx_ids } => {
+                    let ids: Vec<String> = trash
+                        .values()
+                        .filter(|entry| entry.is_expired())
+                        .map(|entry| entry.datasource_id.clone())
+                        .collect();
+                    if tx_ids.send(ids).is_err() {
+                        event!(Level::ERROR, "Error send expired DataSources of the trash");
+                    }
+                }
+                MessageDatasource::PurgeTrash { datasource_id } => {
+                    trash.remove(&datasource_id);
+                    map_dss.datasources.remove(&datasource_id);
+                    publish_event(
+                        &tx_events,
+                        EventKind::DataSource,
+                        json!({"action": "delete", "datasource_id": datasource_id}),
+                    );
+                }
             }
         }
     })
@@ -0,0 +1,569 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::anyhow;
+use hyper::body::{Bytes, HttpBody};
+use hyper::http::HeaderMap;
+use hyper::{Body, Method, Response, Uri};
+use serde::Serialize;
+use serde_json::json;
+use strum_macros::{AsRefStr, Display};
+use tokio::io::AsyncWriteExt;
+use tracing::{event, Level};
+
+use super::datasources::MessageDatasource;
+use super::events::{publish_event, EventKind};
+use super::exports::{
+    expires_in, export_event, new_export_job, package_export, ExportJob, ExportState,
+    MessageExports,
+};
+use super::seeding::{seed_tiles, SeedingContext, SeedingKind};
+use crate::config::Config;
+use crate::db::export::import_package;
+use crate::db::registry::SharedRegistry;
+use crate::defaults::{LOCAL_NODE, PYRAMID_POLL_SECS, SHARD_POLL_ERRORS};
+use crate::grid::TileGrid;
+use crate::handles::endpoints::pyramid::{plan_delta_pyramid, DeltaPlan};
+use crate::handles::helpers::{split_node_address, worker_response};
+use crate::structs::{ContentType, EndPoints};
+use crate::webhooks::{notify_pyramid, PyramidNotification, PyramidWebhooks};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Display, AsRefStr)]
+#[serde(rename_all = "lowercase")]
+pub enum ShardState {
+    #[strum(serialize = "queued")]
+    Queued,
+    #[strum(serialize = "running")]
+    Running,
+    #[strum(serialize = "completed")]
+    Completed,
+    #[strum(serialize = "failed")]
+    Failed,
+}
+
+// Area and zoom levels of the distributed pyramid created by one node
+#[derive(Debug, Clone, Serialize)]
+pub struct PyramidShard {
+    pub shard: usize,
+    // address 'host:port' of the node, 'local' - workers of this server
+    pub node: String,
+    pub bbox: [f64; 4],
+    pub minzoom: u8,
+    pub maxzoom: u8,
+    pub state: ShardState,
+    // tiles created by workers of this server or in the package of the node
+    pub tiles: Option<u64>,
+    // tiles of the package of the node merged into MBTiles of this server
+    pub merged: Option<u64>,
+    // job of the package of the shard on the node
+    pub node_job_id: Option<String>,
+    pub message: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DistributedPyramid {
+    pub job_id: String,
+    pub job_type: &'static str,
+    pub datasource_id: String,
+    pub shard_by: String,
+    pub state: ShardState,
+    pub shards: Vec<PyramidShard>,
+    pub message: Option<String>,
+    pub expires_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl DistributedPyramid {
+    pub fn is_expired(&self) -> bool {
+        matches!(self.expires_at, Some(expires_at) if expires_at < chrono::Utc::now())
+    }
+}
+
+// Channels of the coordinator of the distributed pyramid
+#[derive(Clone)]
+pub struct DistributedContext {
+    pub seeding: SeedingContext,
+    pub registry: SharedRegistry,
+    pub tx: flume::Sender<MessageDatasource>,
+    pub tx_exports: flume::Sender<MessageExports>,
+    pub config: Config,
+}
+
+fn pyramid_event(ctx: &DistributedContext, pyramid: &DistributedPyramid) {
+    publish_event(
+        &ctx.seeding.tx_events,
+        EventKind::Job,
+        json!({
+            "state": pyramid.state.as_ref(),
+            "job_id": pyramid.job_id,
+            "job_type": pyramid.job_type,
+            "name": format!("Distributed pyramid {}", pyramid.datasource_id),
+            "shards": pyramid.shards.len(),
+        }),
+    );
+}
+
+async fn save_pyramid(ctx: &DistributedContext, pyramid: &DistributedPyramid) {
+    let message = MessageExports::SavePyramid {
+        pyramid: pyramid.clone(),
+    };
+    if let Err(err) = ctx.tx_exports.send_async(message).await {
+        event!(Level::ERROR, "Error save distributed pyramid: {err}");
+    }
+}
+
+// Body of the delta pyramid of the area of the shard
+fn shard_body(datasource_id: &str, shard: &PyramidShard, max_tiles: u64) -> serde_json::Value {
+    json!({
+        "datasource_id": datasource_id,
+        "bbox": shard.bbox,
+        "minzoom": shard.minzoom,
+        "maxzoom": shard.maxzoom,
+        "max_tiles": max_tiles,
+    })
+}
+
+async fn response_json(response: Response<Body>) -> Result<serde_json::Value, anyhow::Error> {
+    let status = response.status();
+    let bytes = hyper::body::to_bytes(response.into_body()).await?;
+    if !status.is_success() {
+        return Err(anyhow!(
+            "{status}: {}",
+            String::from_utf8_lossy(&bytes).trim()
+        ));
+    }
+    Ok(serde_json::from_slice(&bytes)?)
+}
+
+async fn node_request(
+    ctx: &DistributedContext,
+    node: &str,
+    uri: Uri,
+    method: &Method,
+    body: Bytes,
+) -> Result<Response<Body>, anyhow::Error> {
+    let (host, port) = split_node_address(node)?;
+    worker_response(
+        host,
+        port,
+        uri,
+        method,
+        &ctx.seeding.client,
+        HeaderMap::new(),
+        body,
+        ContentType::ApplicationJson.as_ref(),
+        ctx.config.timeout_worker_response,
+        ctx.config.cluster_secret.as_deref(),
+    )
+    .await
+}
+
+// Tiles of the shard are created again by workers of this server, directly in its cache
+async fn local_shard(
+    ctx: &DistributedContext,
+    pyramid_id: &str,
+    datasource_id: &str,
+    shard: &mut PyramidShard,
+    max_tiles: u64,
+) -> Result<(), anyhow::Error> {
+    let body = shard_body(datasource_id, shard, max_tiles);
+    let plan = plan_delta_pyramid(
+        &ctx.seeding.paths,
+        &ctx.registry,
+        &ctx.tx,
+        &body,
+        &ctx.config,
+    )
+    .await?;
+    let report = seed_tiles(
+        ctx.seeding.clone(),
+        SeedingKind::Shard,
+        format!("{pyramid_id}-{}", shard.shard),
+        datasource_id.to_string(),
+        plan.tiles,
+    )
+    .await;
+    shard.tiles = Some(report.seeded);
+    if report.failed > 0 && report.seeded == 0 {
+        return Err(anyhow!("{} tiles of the shard failed", report.failed));
+    }
+    Ok(())
+}
+
+async fn download_package(
+    ctx: &DistributedContext,
+    node: &str,
+    download_url: &str,
+    package: &Path,
+) -> Result<(), anyhow::Error> {
+    let uri: Uri = download_url.parse()?;
+    let response = node_request(ctx, node, uri, &Method::GET, Bytes::new()).await?;
+    if !response.status().is_success() {
+        return Err(anyhow!(
+            "Package of node '{node}' is not downloaded: {}",
+            response.status()
+        ));
+    }
+    let mut file = tokio::fs::File::create(package).await?;
+    let mut body = response.into_body();
+    while let Some(chunk) = body.data().await {
+        file.write_all(&chunk?).await?;
+    }
+    file.flush().await?;
+    Ok(())
+}
+
+/*
+    Shard of the remote node: the node creates tiles of the area by its workers and assembles
+    the package like '/api/export', the job of the node is polled at '/api/jobs/{job_id}'.
+    The package is downloaded and its tiles are merged into MBTiles of this server
+*/
+async fn remote_shard(
+    ctx: &DistributedContext,
+    pyramid_id: &str,
+    datasource_id: &str,
+    shard: &mut PyramidShard,
+    max_tiles: u64,
+) -> Result<(), anyhow::Error> {
+    let node = shard.node.clone();
+    let body = shard_body(datasource_id, shard, max_tiles).to_string();
+    let uri: Uri = EndPoints::PyramidShard.as_ref().parse()?;
+    let response = node_request(ctx, &node, uri, &Method::POST, Bytes::from(body)).await?;
+    let started = response_json(response).await?;
+    let job_id = started
+        .get("job_id")
+        .and_then(|id| id.as_str())
+        .ok_or(anyhow!("Node '{node}' responded without 'job_id'"))?
+        .to_string();
+    shard.node_job_id = Some(job_id.clone());
+
+    let job_uri: Uri = format!("{}/{job_id}", EndPoints::Jobs.as_ref()).parse()?;
+    let mut errors = 0;
+    let job = loop {
+        tokio::time::sleep(Duration::from_secs(PYRAMID_POLL_SECS)).await;
+        let response = node_request(ctx, &node, job_uri.clone(), &Method::GET, Bytes::new()).await;
+        let job = match response {
+            Ok(response) => response_json(response).await,
+            Err(err) => Err(err),
+        };
+        match job {
+            Ok(job) => {
+                errors = 0;
+                match job.get("state").and_then(|s| s.as_str()) {
+                    Some(state) if state == ExportState::Running.as_ref() => continue,
+                    _ => break job,
+                }
+            }
+            Err(err) => {
+                errors += 1;
+                event!(
+                    Level::WARN,
+                    "Error poll job '{job_id}' of node '{node}' ({errors}/{SHARD_POLL_ERRORS}): {err}"
+                );
+                if errors >= SHARD_POLL_ERRORS {
+                    return Err(err);
+                }
+            }
+        }
+    };
+    shard.tiles = job.get("tiles").and_then(|t| t.as_u64());
+    if job.get("state").and_then(|s| s.as_str()) != Some(ExportState::Completed.as_ref()) {
+        let message = job
+            .get("message")
+            .and_then(|m| m.as_str())
+            .unwrap_or("unknown error");
+        return Err(anyhow!("Job of node '{node}' failed: {message}"));
+    }
+    // area of the shard outside of the DataSource has no package
+    let Some(download_url) = job.get("download_url").and_then(|u| u.as_str()) else {
+        shard.merged = Some(0);
+        return Ok(());
+    };
+
+    let package = shard_package(ctx, pyramid_id, shard);
+    tokio::fs::create_dir_all(ctx.seeding.paths.data.join("exports")).await?;
+    let result = match download_package(ctx, &node, download_url, &package).await {
+        Ok(()) => {
+            let grid = grid_of(ctx, datasource_id).await?;
+            import_package(
+                package.clone(),
+                &ctx.seeding.paths.mbtiles_db(datasource_id),
+                grid,
+                ctx.seeding.tx_sqlite_client.clone(),
+                ctx.seeding.integrity,
+            )
+            .await
+        }
+        Err(err) => Err(err),
+    };
+    remove_package(&package).await;
+    shard.merged = Some(result?);
+    Ok(())
+}
+
+// Package of the remote shard downloaded to the directory 'exports'
+fn shard_package(ctx: &DistributedContext, pyramid_id: &str, shard: &PyramidShard) -> PathBuf {
+    ctx.seeding
+        .paths
+        .data
+        .join("exports")
+        .join(format!("{pyramid_id}-{}.mbtiles", shard.shard))
+}
+
+async fn remove_package(package: &Path) {
+    if let Err(err) = tokio::fs::remove_file(package).await {
+        if err.kind() != std::io::ErrorKind::NotFound {
+            event!(Level::ERROR, "Error remove package {package:?}: {err}");
+        }
+    }
+}
+
+/*
+    Remote shard is failed after 'shard_timeout_seconds' of the cluster: the node which stopped
+    responding or creates the shard too long is asked to cancel its job, the unfinished package is removed
+*/
+async fn timed_remote_shard(
+    ctx: &DistributedContext,
+    pyramid_id: &str,
+    datasource_id: &str,
+    shard: &mut PyramidShard,
+    max_tiles: u64,
+) -> Result<(), anyhow::Error> {
+    let timeout_secs = ctx.config.cluster_shard_timeout_secs;
+    if timeout_secs == 0 {
+        return remote_shard(ctx, pyramid_id, datasource_id, shard, max_tiles).await;
+    }
+    let timeout = Duration::from_secs(timeout_secs);
+    let shard_future = remote_shard(ctx, pyramid_id, datasource_id, shard, max_tiles);
+    if let Ok(result) = tokio::time::timeout(timeout, shard_future).await {
+        return result;
+    }
+    if let Some(job_id) = shard.node_job_id.clone() {
+        cancel_node_job(ctx, &shard.node, &job_id).await;
+    }
+    remove_package(&shard_package(ctx, pyramid_id, shard)).await;
+    Err(anyhow!(
+        "Shard is not completed by node '{}' in {timeout_secs} seconds",
+        shard.node
+    ))
+}
+
+async fn cancel_node_job(ctx: &DistributedContext, node: &str, job_id: &str) {
+    let uri = format!("{}/{job_id}", EndPoints::PyramidShard.as_ref()).parse::<Uri>();
+    let result = match uri {
+        Ok(uri) => match node_request(ctx, node, uri, &Method::DELETE, Bytes::new()).await {
+            Ok(response) => response_json(response).await.map(|_| ()),
+            Err(err) => Err(err),
+        },
+        Err(err) => Err(err.into()),
+    };
+    if let Err(err) = result {
+        event!(
+            Level::ERROR,
+            "Error cancel job '{job_id}' of node '{node}': {err}"
+        );
+    }
+}
+
+async fn grid_of(ctx: &DistributedContext, datasource_id: &str) -> Result<TileGrid, anyhow::Error> {
+    let (tx_ds, rx_ds) = tokio::sync::oneshot::channel();
+    ctx.tx
+        .send_async(MessageDatasource::GetDataSource {
+            datasource_id: datasource_id.to_string(),
+            tx_ds,
+        })
+        .await?;
+    let ds_info = rx_ds
+        .await?
+        .ok_or(anyhow!("DataSource '{datasource_id}' not found"))?;
+    Ok(ds_info
+        .tile_grid
+        .unwrap_or_else(|| ctx.config.tile_grids.default_grid().clone()))
+}
+
+// Shards of one node are created one after another, the state of each shard is saved
+async fn node_shards(
+    ctx: DistributedContext,
+    pyramid: DistributedPyramid,
+    shards: Vec<PyramidShard>,
+    max_tiles: u64,
+    tx_shards: flume::Sender<PyramidShard>,
+) {
+    for mut shard in shards {
+        shard.state = ShardState::Running;
+        let _ = tx_shards.send_async(shard.clone()).await;
+        let result = if shard.node == LOCAL_NODE {
+            local_shard(
+                &ctx,
+                &pyramid.job_id,
+                &pyramid.datasource_id,
+                &mut shard,
+                max_tiles,
+            )
+            .await
+        } else {
+            timed_remote_shard(
+                &ctx,
+                &pyramid.job_id,
+                &pyramid.datasource_id,
+                &mut shard,
+                max_tiles,
+            )
+            .await
+        };
+        match result {
+            Ok(()) => shard.state = ShardState::Completed,
+            Err(err) => {
+                event!(
+                    Level::ERROR,
+                    "Error shard {} of distributed pyramid '{}' on node '{}': {err}",
+                    shard.shard,
+                    pyramid.job_id,
+                    shard.node
+                );
+                shard.state = ShardState::Failed;
+                shard.message = Some(err.to_string());
+            }
+        }
+        let _ = tx_shards.send_async(shard).await;
+    }
+}
+
+/*
+    Distributed pyramid: shards (zoom levels or regions of the DataSource) are created by nodes
+    of the cluster at the same time, every node creates its shards one after another.
+    Packages of remote nodes are merged into MBTiles of this server as they are completed,
+    the state of the pyramid is available at '/api/jobs/{job_id}'
+*/
+pub async fn start_distributed_pyramid(
+    ctx: DistributedContext,
+    mut pyramid: DistributedPyramid,
+    max_tiles: u64,
+) {
+    save_pyramid(&ctx, &pyramid).await;
+    pyramid_event(&ctx, &pyramid);
+
+    tokio::spawn(async move {
+        let mut by_node: BTreeMap<String, Vec<PyramidShard>> = BTreeMap::new();
+        for shard in pyramid.shards.iter() {
+            by_node
+                .entry(shard.node.clone())
+                .or_default()
+                .push(shard.clone());
+        }
+        let (tx_shards, rx_shards) = flume::unbounded();
+        for shards in by_node.into_values() {
+            tokio::spawn(node_shards(
+                ctx.clone(),
+                pyramid.clone(),
+                shards,
+                max_tiles,
+                tx_shards.clone(),
+            ));
+        }
+        drop(tx_shards);
+
+        while let Ok(shard) = rx_shards.recv_async().await {
+            let index = shard.shard;
+            if let Some(s) = pyramid.shards.get_mut(index) {
+                *s = shard;
+            }
+            save_pyramid(&ctx, &pyramid).await;
+        }
+
+        let failed = pyramid
+            .shards
+            .iter()
+            .filter(|shard| shard.state != ShardState::Completed)
+            .count();
+        if failed == 0 {
+            pyramid.state = ShardState::Completed;
+        } else {
+            pyramid.state = ShardState::Failed;
+            pyramid.message = Some(format!(
+                "{failed} of {} shards failed",
+                pyramid.shards.len()
+            ));
+        }
+        event!(
+            Level::INFO,
+            "Distributed pyramid '{}' of DataSource '{}' finished: {}",
+            pyramid.job_id,
+            pyramid.datasource_id,
+            pyramid.state
+        );
+        // finished pyramid is kept as long as packages of exports
+        pyramid.expires_at = expires_in(ctx.config.export_ttl_seconds);
+        save_pyramid(&ctx, &pyramid).await;
+        pyramid_event(&ctx, &pyramid);
+
+        let notification = match pyramid.message.clone() {
+            None => PyramidNotification::completed(&pyramid.datasource_id, &pyramid.job_id),
+            Some(message) => {
+                PyramidNotification::failed(&pyramid.datasource_id, Some(&pyramid.job_id), message)
+            }
+        };
+        let webhooks = PyramidWebhooks::from_config(&ctx.config);
+        notify_pyramid(&ctx.registry, &webhooks, notification).await;
+    });
+}
+
+/*
+    Shard on the node: tiles of the plan are created again by workers of the node,
+    then the package of the area is assembled. The job of the package is polled by the master
+*/
+pub async fn start_pyramid_shard(
+    ctx: SeedingContext,
+    datasource_id: &str,
+    plan: DeltaPlan,
+    ttl_seconds: u64,
+    tx_exports: flume::Sender<MessageExports>,
+) -> Result<ExportJob, anyhow::Error> {
+    let mut job = new_export_job(&ctx.paths, datasource_id).await?;
+    let Some(area) = plan.area else {
+        job.state = ExportState::Completed;
+        job.tiles = Some(0);
+        job.expires_at = expires_in(ttl_seconds);
+        tx_exports
+            .send_async(MessageExports::Save { job: job.clone() })
+            .await?;
+        return Ok(job);
+    };
+    tx_exports
+        .send_async(MessageExports::Save { job: job.clone() })
+        .await?;
+    export_event(&ctx.tx_events, &job);
+
+    let started = job.clone();
+    let tx_job = tx_exports.clone();
+    let task = tokio::spawn(async move {
+        let report = seed_tiles(
+            ctx.clone(),
+            SeedingKind::Shard,
+            job.job_id.clone(),
+            job.datasource_id.clone(),
+            plan.tiles,
+        )
+        .await;
+        let job = if report.failed > 0 && report.seeded == 0 {
+            job.state = ExportState::Failed;
+            job.message = Some(format!("{} tiles of the shard failed", report.failed));
+            job.expires_at = expires_in(ttl_seconds);
+            job
+        } else {
+            package_export(&ctx.paths, job, area, ttl_seconds).await
+        };
+        export_event(&ctx.tx_events, &job);
+        if let Err(err) = tx_exports.send_async(MessageExports::Save { job }).await {
+            event!(Level::ERROR, "Error save job of pyramid shard: {err}");
+        }
+    });
+    tx_job
+        .send_async(MessageExports::Track {
+            job_id: started.job_id.clone(),
+            task: task.abort_handle(),
+        })
+        .await?;
+
+    Ok(started)
+}
@@ -0,0 +1,71 @@
+use serde_json::json;
+use strum_macros::{AsRefStr, Display};
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tracing::{event, Level};
+
+use crate::defaults::EVENTS_SUBSCRIBER_BUFFER;
+
+#[derive(Debug, Clone, Copy, PartialEq, Display, AsRefStr)]
+pub enum EventKind {
+    #[strum(serialize = "job")]
+    Job,
+    #[strum(serialize = "worker")]
+    Worker,
+    #[strum(serialize = "datasource")]
+    DataSource,
+}
+
+pub enum MessageEvents {
+    Subscribe {
+        tx_sub: oneshot::Sender<flume::Receiver<String>>,
+    },
+    Publish {
+        kind: EventKind,
+        data: serde_json::Value,
+    },
+}
+
+/*
+    Broadcast of server events to subscribers of '/api/events'.
+    Events are formatted as Server-Sent Events, slow subscribers lose events
+    instead of blocking the publishers
+*/
+pub fn events_maintenance(rx: flume::Receiver<MessageEvents>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut subscribers: Vec<flume::Sender<String>> = Vec::new();
+
+        while let Ok(message) = rx.recv_async().await {
+            match message {
+                MessageEvents::Subscribe { tx_sub } => {
+                    let (tx_event, rx_event) = flume::bounded(EVENTS_SUBSCRIBER_BUFFER);
+                    if tx_sub.send(rx_event).is_ok() {
+                        subscribers.push(tx_event);
+                    }
+                }
+                MessageEvents::Publish { kind, data } => {
+                    let payload = json!({
+                        "type": kind.as_ref(),
+                        "timestamp": chrono::Utc::now().to_rfc3339(),
+                        "data": data,
+                    });
+                    let message = format!("event: {kind}\ndata: {payload}\n\n");
+                    subscribers.retain(|tx_event| match tx_event.try_send(message.clone()) {
+                        Ok(_) => true,
+                        Err(flume::TrySendError::Full(_)) => {
+                            event!(Level::WARN, "Events subscriber is too slow, event dropped");
+                            true
+                        }
+                        Err(flume::TrySendError::Disconnected(_)) => false,
+                    });
+                }
+            }
+        }
+    })
+}
+
+pub fn publish_event(tx: &flume::Sender<MessageEvents>, kind: EventKind, data: serde_json::Value) {
+    if let Err(err) = tx.send(MessageEvents::Publish { kind, data }) {
+        event!(Level::ERROR, "Error publish {kind} event: {err}");
+    }
+}
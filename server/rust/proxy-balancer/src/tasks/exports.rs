@@ -0,0 +1,290 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use serde::Serialize;
+use strum_macros::{AsRefStr, Display};
+use tiler_core::mbtiles::get_mbtiles_paths;
+use tokio::sync::oneshot;
+use tokio::task::{AbortHandle, JoinHandle};
+use tracing::{event, Level};
+use uuid::Uuid;
+
+use super::distributed::DistributedPyramid;
+use super::events::{publish_event, EventKind, MessageEvents};
+use crate::db::export::{export_mbtiles, ExportArea};
+use crate::paths::Paths;
+use crate::structs::EndPoints;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Display, AsRefStr)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportState {
+    #[strum(serialize = "running")]
+    Running,
+    #[strum(serialize = "completed")]
+    Completed,
+    #[strum(serialize = "failed")]
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ExportJob {
+    pub job_id: String,
+    pub datasource_id: String,
+    pub state: ExportState,
+    pub tiles: Option<u64>,
+    pub message: Option<String>,
+    pub download_url: Option<String>,
+    pub expires_at: Option<chrono::DateTime<chrono::Utc>>,
+    #[serde(skip)]
+    pub token: String,
+    #[serde(skip)]
+    pub package: PathBuf,
+}
+
+impl ExportJob {
+    pub fn is_expired(&self) -> bool {
+        matches!(self.expires_at, Some(expires_at) if expires_at < chrono::Utc::now())
+    }
+}
+
+pub enum MessageExports {
+    Save {
+        job: ExportJob,
+    },
+    GetJob {
+        job_id: String,
+        tx_job: oneshot::Sender<Option<ExportJob>>,
+    },
+    SavePyramid {
+        pyramid: DistributedPyramid,
+    },
+    GetPyramid {
+        job_id: String,
+        tx_pyramid: oneshot::Sender<Option<DistributedPyramid>>,
+    },
+    // task of the running job, it is aborted when the job is cancelled
+    Track {
+        job_id: String,
+        task: AbortHandle,
+    },
+    Cancel {
+        job_id: String,
+        ttl_seconds: u64,
+        tx_job: oneshot::Sender<Option<ExportJob>>,
+    },
+}
+
+/*
+    Registry of export jobs (offline packages) and distributed pyramids of '/api/jobs/{job_id}'.
+    Packages are available for download until 'expires_at', expired packages are removed
+    with their jobs, finished pyramids are kept as long as packages.
+    Cancelled running job is failed, its task is aborted and its unfinished package is removed
+*/
+pub fn exports_maintenance(rx: flume::Receiver<MessageExports>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut jobs: HashMap<String, ExportJob> = HashMap::new();
+        let mut pyramids: HashMap<String, DistributedPyramid> = HashMap::new();
+        let mut tasks: HashMap<String, AbortHandle> = HashMap::new();
+
+        while let Ok(message) = rx.recv_async().await {
+            pyramids.retain(|_, pyramid| !pyramid.is_expired());
+            let expired: Vec<String> = jobs
+                .values()
+                .filter(|job| job.is_expired())
+                .map(|job| job.job_id.clone())
+                .collect();
+            for job_id in expired {
+                if let Some(job) = jobs.remove(&job_id) {
+                    match tokio::fs::remove_file(&job.package).await {
+                        Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                            event!(
+                                Level::ERROR,
+                                "Error remove expired package {:?}: {err}",
+                                job.package
+                            );
+                        }
+                        _ => {}
+                    }
+                }
+            }
+
+            match message {
+                MessageExports::Save { job } => {
+                    // job saved by its task after the cancellation stays cancelled
+                    let finished = jobs
+                        .get(&job.job_id)
+                        .is_some_and(|saved| saved.state != ExportState::Running);
+                    if !finished {
+                        if job.state != ExportState::Running {
+                            tasks.remove(&job.job_id);
+                        }
+                        jobs.insert(job.job_id.clone(), job);
+                    }
+                }
+                MessageExports::GetJob { job_id, tx_job } => {
+                    if tx_job.send(jobs.get(&job_id).cloned()).is_err() {
+                        event!(Level::ERROR, "Error send export job '{job_id}'");
+                    }
+                }
+                MessageExports::SavePyramid { pyramid } => {
+                    pyramids.insert(pyramid.job_id.clone(), pyramid);
+                }
+                MessageExports::GetPyramid { job_id, tx_pyramid } => {
+                    if tx_pyramid.send(pyramids.get(&job_id).cloned()).is_err() {
+                        event!(Level::ERROR, "Error send distributed pyramid '{job_id}'");
+                    }
+                }
+                MessageExports::Track { job_id, task } => {
+                    // the task may finish before it is tracked
+                    if jobs
+                        .get(&job_id)
+                        .is_some_and(|job| job.state == ExportState::Running)
+                    {
+                        tasks.insert(job_id, task);
+                    }
+                }
+                MessageExports::Cancel {
+                    job_id,
+                    ttl_seconds,
+                    tx_job,
+                } => {
+                    let job = jobs.get_mut(&job_id);
+                    if let Some(job) = job.filter(|job| job.state == ExportState::Running) {
+                        if let Some(task) = tasks.remove(&job_id) {
+                            task.abort();
+                        }
+                        job.state = ExportState::Failed;
+                        job.message = Some("Job is cancelled".to_string());
+                        job.expires_at = expires_in(ttl_seconds);
+                        match tokio::fs::remove_file(&job.package).await {
+                            Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                                event!(
+                                    Level::ERROR,
+                                    "Error remove package of cancelled job {:?}: {err}",
+                                    job.package
+                                );
+                            }
+                            _ => {}
+                        }
+                    }
+                    if tx_job.send(jobs.get(&job_id).cloned()).is_err() {
+                        event!(Level::ERROR, "Error send cancelled job '{job_id}'");
+                    }
+                }
+            }
+        }
+    })
+}
+
+/*
+    Package is assembled in the background, the job is available at '/api/jobs/{job_id}',
+    completed job has the time-limited URL of the package
+*/
+pub async fn start_export(
+    paths: &Paths,
+    datasource_id: &str,
+    area: ExportArea,
+    ttl_seconds: u64,
+    tx_exports: flume::Sender<MessageExports>,
+    tx_events: flume::Sender<MessageEvents>,
+) -> Result<ExportJob, anyhow::Error> {
+    let job = new_export_job(paths, datasource_id).await?;
+    tx_exports
+        .send_async(MessageExports::Save { job: job.clone() })
+        .await?;
+    export_event(&tx_events, &job);
+
+    let started = job.clone();
+    let paths = paths.clone();
+    tokio::spawn(async move {
+        let job = package_export(&paths, job, area, ttl_seconds).await;
+        export_event(&tx_events, &job);
+        if let Err(err) = tx_exports.send_async(MessageExports::Save { job }).await {
+            event!(Level::ERROR, "Error save export job: {err}");
+        }
+    });
+
+    Ok(started)
+}
+
+// Running job of the package in the directory 'exports'
+pub async fn new_export_job(
+    paths: &Paths,
+    datasource_id: &str,
+) -> Result<ExportJob, anyhow::Error> {
+    let job_id = Uuid::new_v4().to_string();
+    let exports_dir = paths.data.join("exports");
+    tokio::fs::create_dir_all(&exports_dir).await?;
+
+    Ok(ExportJob {
+        job_id: job_id.clone(),
+        datasource_id: datasource_id.to_string(),
+        state: ExportState::Running,
+        tiles: None,
+        message: None,
+        download_url: None,
+        expires_at: None,
+        token: Uuid::new_v4().to_simple().to_string(),
+        package: exports_dir.join(format!("{job_id}.mbtiles")),
+    })
+}
+
+// Moment of the expiration after the TTL, the job is kept forever when it is out of the range of dates
+pub fn expires_in(ttl_seconds: u64) -> Option<chrono::DateTime<chrono::Utc>> {
+    i64::try_from(ttl_seconds)
+        .ok()
+        .and_then(chrono::TimeDelta::try_seconds)
+        .and_then(|ttl| chrono::Utc::now().checked_add_signed(ttl))
+}
+
+// Package of the area is assembled from the cache, the job is completed or failed
+pub async fn package_export(
+    paths: &Paths,
+    mut job: ExportJob,
+    area: ExportArea,
+    ttl_seconds: u64,
+) -> ExportJob {
+    let tiles_dir = paths.tiles_dir(&job.datasource_id);
+    let cache_mbtiles = get_mbtiles_paths(tiles_dir.clone())
+        .await
+        .unwrap_or_default();
+    let name = job.datasource_id.clone();
+    match export_mbtiles(cache_mbtiles, tiles_dir, job.package.clone(), name, area).await {
+        Ok(tiles) => {
+            job.state = ExportState::Completed;
+            job.tiles = Some(tiles);
+            job.download_url = Some(format!(
+                "{}/{}?token={}",
+                EndPoints::ExportDownload.as_ref(),
+                job.job_id,
+                job.token
+            ));
+            job.expires_at = expires_in(ttl_seconds);
+        }
+        Err(err) => {
+            event!(
+                Level::ERROR,
+                "Error export DataSource '{}': {err}",
+                job.datasource_id
+            );
+            job.state = ExportState::Failed;
+            job.message = Some(err.to_string());
+            // failed job is kept as long as the package would be
+            job.expires_at = expires_in(ttl_seconds);
+        }
+    }
+    job
+}
+
+pub fn export_event(tx_events: &flume::Sender<MessageEvents>, job: &ExportJob) {
+    publish_event(
+        tx_events,
+        EventKind::Job,
+        serde_json::json!({
+            "state": job.state.as_ref(),
+            "job_id": job.job_id,
+            "job_type": "export",
+            "name": format!("Export {}", job.datasource_id),
+        }),
+    );
+}
@@ -1,83 +1,124 @@
 use std::net::IpAddr;
 use std::str::FromStr;
+use std::sync::Arc;
 use std::time::Duration;
 
 use anyhow::anyhow;
-use hyper::Response;
+use hyper::{body::Bytes, Response};
 use hyper::{client::HttpConnector, http::HeaderMap, Body, Client, Method, Request, Uri};
 use serde_json::json;
-use tokio::sync::oneshot;
+use tiler_core::sqlite_clients::MessageSQLiteClient;
+use tokio::sync::{oneshot, watch};
 use tokio::task::JoinHandle;
 use tracing::{event, Level};
 
 use super::datasources::MessageDatasource;
+use super::events::{publish_event, EventKind, MessageEvents};
+use super::mbtiles_metadata::{pyramid_atomic_swap, watch_pyramid_response, PyramidWatch};
 use super::reload_workers::MessageMaintenanceWorkers;
-use super::sqlite_clients::MessageSQLiteClient;
+use super::seeding::SeedingContext;
+use super::semaphore::MessageSemaphore;
+use super::storage::StorageInfo;
 use crate::config::Config;
 use crate::db::{
     job::{
+        mysql::MySqlQueue,
         postgres::PostgresQueue,
-        queue::{Job, JobDetail, JobType, Queue},
+        queue::{Job, JobDetail, JobType, SharedQueue},
     },
-    DB,
+    registry::SharedRegistry,
+    DBPool,
 };
 use crate::defaults::{JOB_CONCURRENCY, LOCALHOST};
-use crate::handles::helpers::{get_worker_data, worker_response};
+use crate::handles::endpoints::pyramid::{plan_delta_pyramid, start_delta_pyramid};
+use crate::handles::helpers::{
+    get_worker_data, replicate_request, split_node_address, worker_response,
+};
 use crate::hyper_reverse_proxy;
+use crate::paths::Paths;
 use crate::structs::{ContentType, EndPoints};
 use crate::utils::{dataset_dir_from_ds_id, mbtiles_path_from_ds_id, try_init_mbtiles};
+use crate::webhooks::{notify_pyramid, PyramidNotification, PyramidWebhooks};
+
+// Channels of the maintenance tasks used by the job worker
+#[derive(Debug, Clone)]
+pub struct JobChannels {
+    pub tx: flume::Sender<MessageDatasource>,
+    pub tx_mw: flume::Sender<MessageMaintenanceWorkers>,
+    pub tx_sem: flume::Sender<MessageSemaphore>,
+    pub tx_sqlite_client: flume::Sender<MessageSQLiteClient>,
+    pub tx_events: flume::Sender<MessageEvents>,
+    pub rx_storage: watch::Receiver<StorageInfo>,
+}
+
+// Queue of jobs in the table 'queue' of the config database
+pub fn pool_queue(pool: DBPool) -> SharedQueue {
+    match pool {
+        DBPool::Postgres(db) => Arc::new(PostgresQueue::new(db)),
+        DBPool::MySql(db) => Arc::new(MySqlQueue::new(db)),
+    }
+}
 
 pub fn init_job_queue(
-    cwd: String,
-    pool: DB,
+    paths: Paths,
+    queue: SharedQueue,
+    registry: SharedRegistry,
     rx_jd: flume::Receiver<JobDetail>,
-    tx: flume::Sender<MessageDatasource>,
-    tx_mw: flume::Sender<MessageMaintenanceWorkers>,
-    tx_sqlite_client: flume::Sender<MessageSQLiteClient>,
+    channels: JobChannels,
     config: Config,
 ) -> Result<(JoinHandle<()>, JoinHandle<()>), anyhow::Error> {
-    let pg_queue = PostgresQueue::new(pool.clone());
-    let jh_add_job = job_add_to_queue(rx_jd, pg_queue.clone());
-    let jh_job_worker = job_worker(
-        cwd,
-        pg_queue,
-        pool,
-        tx,
-        tx_mw,
-        tx_sqlite_client,
-        config.clone(),
-    );
+    let jh_add_job = job_add_to_queue(rx_jd, queue.clone(), channels.tx_events.clone());
+    let jh_job_worker = job_worker(paths, queue, registry, channels, config.clone());
     Ok((jh_add_job, jh_job_worker))
 }
 
 pub fn job_add_to_queue(
     rx_jd: flume::Receiver<JobDetail>,
-    pg_queue: PostgresQueue,
+    queue: SharedQueue,
+    tx_events: flume::Sender<MessageEvents>,
 ) -> JoinHandle<()> {
     tokio::spawn(async move {
         while let Ok(jd) = rx_jd.recv_async().await {
-            if let Err(err) = pg_queue.push(jd.clone(), jd.scheduled_for).await {
+            if let Err(err) = queue.push(jd.clone(), jd.scheduled_for).await {
                 let _jd = jd.clone();
                 let jt = _jd.jt.as_ref();
                 event!(Level::ERROR, "Error push job {jt}: {:?}", err);
+            } else {
+                publish_event(
+                    &tx_events,
+                    EventKind::Job,
+                    json!({
+                        "state": "queued",
+                        "job_type": jd.jt.as_ref(),
+                        "name": jd.name,
+                        "scheduled_for": jd.scheduled_for,
+                    }),
+                );
             }
         }
     })
 }
 
 pub fn job_worker(
-    cwd: String,
-    pg_queue: PostgresQueue,
-    pool: DB,
-    tx: flume::Sender<MessageDatasource>,
-    tx_mw: flume::Sender<MessageMaintenanceWorkers>,
-    tx_sqlite_client: flume::Sender<MessageSQLiteClient>,
+    paths: Paths,
+    queue: SharedQueue,
+    registry: SharedRegistry,
+    channels: JobChannels,
     config: Config,
 ) -> JoinHandle<()> {
+    let JobChannels {
+        tx,
+        tx_mw,
+        tx_sem,
+        tx_sqlite_client,
+        tx_events,
+        rx_storage,
+    } = channels;
+
     tokio::spawn(async move {
         let client_ip: IpAddr = IpAddr::from_str(LOCALHOST).unwrap();
         loop {
-            let jobs = match pg_queue.pull(Some(JOB_CONCURRENCY as i32)).await {
+            let jobs = match queue.pull(Some(JOB_CONCURRENCY as i32)).await {
                 Ok(jobs) => jobs,
                 Err(err) => {
                     event!(Level::ERROR, "Error 'job_worker' pulling jobs: {}", err);
@@ -106,21 +147,48 @@ pub fn job_worker(
                     }
                 }
 
+                job_event(&tx_events, job, "running");
+
                 match &job.detail.jt {
                     // Pyramid JOBs
                     JobType::Pyramid { datasource_id } => {
-                        if let Err(err) = job_pyramid(
-                            cwd.clone(),
+                        // pyramid would fail halfway, the job is failed before it starts
+                        let storage = *rx_storage.borrow();
+                        if storage.is_low() {
+                            event!(
+                                Level::ERROR,
+                                "Pyramid job '{}' refused: free space of the tiles volume {} bytes",
+                                job.job_id.to_string(),
+                                storage.available_bytes
+                            );
+                            job_event(&tx_events, job, "failed");
+                            if let Err(err) = queue.fail_job(job.job_id).await {
+                                event!(
+                                    Level::ERROR,
+                                    "Error 'fail_job' for job '{}' {:?}",
+                                    job.job_id.to_string(),
+                                    err
+                                );
+                            }
+                            let message = format!(
+                                "Free space of the tiles volume {} bytes",
+                                storage.available_bytes
+                            );
+                            notify_job_failed(&registry, &config, job, datasource_id, message)
+                                .await;
+                        } else if let Err(err) = job_pyramid(
+                            paths.clone(),
                             tx.clone(),
                             datasource_id,
                             &client,
                             &config,
-                            pg_queue.clone(),
+                            queue.clone(),
                             job,
                             tx_sqlite_client.clone(),
-                            &pool,
+                            &registry,
                             client_ip,
                             &ports,
+                            &tx_events,
                         )
                         .await
                         {
@@ -132,6 +200,31 @@ pub fn job_worker(
                             );
                         }
                     }
+                    JobType::DeltaPyramid { datasource_id } => {
+                        let ctx = SeedingContext {
+                            paths: paths.clone(),
+                            client: client.clone(),
+                            client_ip,
+                            ports: ports.clone(),
+                            tx_sem: tx_sem.clone(),
+                            tx_mw: tx_mw.clone(),
+                            tx_sqlite_client: tx_sqlite_client.clone(),
+                            tx_events: tx_events.clone(),
+                            timeout_secs: config.timeout_tile_response,
+                            integrity: config.integrity,
+                        };
+                        job_delta_pyramid(
+                            &paths,
+                            &registry,
+                            &tx,
+                            datasource_id,
+                            ctx,
+                            &config,
+                            &queue,
+                            job,
+                        )
+                        .await;
+                    }
                     // Any Calculating JOBs
                     JobType::Calculation => {}
                 }
@@ -143,17 +236,18 @@ pub fn job_worker(
 }
 
 async fn job_pyramid(
-    cwd: String,
+    paths: Paths,
     tx: flume::Sender<MessageDatasource>,
     datasource_id: &str,
     client: &Client<HttpConnector>,
     config: &Config,
-    pg_queue: PostgresQueue,
+    queue: SharedQueue,
     job: &Job,
     tx_sqlite_client: flume::Sender<MessageSQLiteClient>,
-    pool: &DB,
+    registry: &SharedRegistry,
     client_ip: IpAddr,
     ports: &Vec<u16>,
+    tx_events: &flume::Sender<MessageEvents>,
 ) -> Result<(), anyhow::Error> {
     let (tx_ds, rx_ds) = oneshot::channel();
     tx.send_async(MessageDatasource::GetDataSource {
@@ -162,91 +256,128 @@ async fn job_pyramid(
     })
     .await?;
 
+    let atomic_swap = pyramid_atomic_swap(&job.detail.data.0);
     let body = json!({
-        "datasource_id": datasource_id
+        "datasource_id": datasource_id,
+        "atomic_swap": atomic_swap
     })
     .to_string();
 
     // in case the DataSource is located on another server
     if let Some(ds) = rx_ds.await? {
-        if let Some(host) = ds.host {
-            if let Some(port) = ds.port {
-                if let Some(current_addr) = config.address.as_ref() {
-                    let addr = format!("{host}:{port}");
-                    // if NOT current machine then send request
-                    if *current_addr != addr {
-                        let body = Body::from(body);
-                        let uri = Uri::from_str(EndPoints::Pyramid.as_ref())?;
-                        let method = Method::POST;
-                        let headers = HeaderMap::new();
-                        let ct = ContentType::ApplicationJson.as_ref();
+        let locations = ds.locations();
+        if let Some(current_addr) = config.address.as_ref() {
+            // every replica builds its own cache of the DataSource
+            let (current, mut others): (Vec<String>, Vec<String>) = locations
+                .into_iter()
+                .partition(|address| address == current_addr);
+            let uri = Uri::from_str(EndPoints::Pyramid.as_ref())?;
+            let method = Method::POST;
 
-                        match worker_response(
-                            host,
-                            port,
-                            uri,
-                            &method,
-                            &client,
-                            headers,
-                            body,
-                            ct,
-                            config.timeout_worker_response,
-                        )
-                        .await
+            // if NOT current machine then send request
+            if current.is_empty() && !others.is_empty() {
+                let main_location = others.remove(0);
+                replicate_request(
+                    others,
+                    uri.clone(),
+                    method.clone(),
+                    client.clone(),
+                    HeaderMap::new(),
+                    Bytes::from(body.clone()),
+                    config,
+                );
+
+                let (host, port) = split_node_address(&main_location)?;
+                let body = Bytes::from(body);
+                let headers = HeaderMap::new();
+                let ct = ContentType::ApplicationJson.as_ref();
+
+                match worker_response(
+                    host,
+                    port,
+                    uri,
+                    &method,
+                    &client,
+                    headers,
+                    body,
+                    ct,
+                    config.timeout_worker_response,
+                    config.cluster_secret.as_deref(),
+                )
+                .await
+                {
+                    Ok(response) => {
+                        // the node notifies webhooks when its pyramid is finished
+                        if let Some(message) =
+                            job_processing_result(response, &queue, job, tx_events).await?
                         {
-                            Ok(response) => {
-                                job_processing_result(response, &pg_queue, job).await?;
-                            }
+                            notify_job_failed(registry, config, job, datasource_id, message).await;
+                        }
+                    }
+                    Err(err) => {
+                        event!(
+                            Level::ERROR,
+                            "Error 'job_worker' request to worker {:?}",
+                            err
+                        );
+                        job_event(tx_events, job, "failed");
+                        match queue.fail_job(job.job_id).await {
                             Err(err) => {
                                 event!(
                                     Level::ERROR,
-                                    "Error 'job_worker' request to worker {:?}",
+                                    "Error 'fail_job' for job '{}' {:?}",
+                                    job.job_id.to_string(),
                                     err
                                 );
-                                match pg_queue.fail_job(job.job_id).await {
-                                    Err(err) => {
-                                        event!(
-                                            Level::ERROR,
-                                            "Error 'fail_job' for job '{}' {:?}",
-                                            job.job_id.to_string(),
-                                            err
-                                        );
-                                    }
-                                    Ok(_) => {}
-                                };
                             }
-                        }
-                        return Ok(());
+                            Ok(_) => {}
+                        };
+                        notify_job_failed(registry, config, job, datasource_id, err.to_string())
+                            .await;
                     }
                 }
+                return Ok(());
             }
+
+            replicate_request(
+                others,
+                uri,
+                method,
+                client.clone(),
+                HeaderMap::new(),
+                Bytes::from(body.clone()),
+                config,
+            );
         }
     }
 
     // DataSource is on the current server instance
-    let dataset_dir = dataset_dir_from_ds_id(&cwd, datasource_id)?;
-    let mbtiles_db = mbtiles_path_from_ds_id(&cwd, datasource_id)
+    let dataset_dir = dataset_dir_from_ds_id(&paths, datasource_id)?;
+    let mbtiles_db = mbtiles_path_from_ds_id(&paths, datasource_id)
         .expect("Error mbtiles_path_from_ds_id")
         .into_os_string()
         .into_string()
         .map_err(|err| anyhow!("{:?}", err))?;
 
-    if let Err(err) = tx_sqlite_client
-        .send_async(MessageSQLiteClient::RemoveSQLiteClient {
-            mbtiles_db,
-            remove_tiles_folder: None,
-            remove_tiles_db: Some(true),
-        })
-        .await
-    {
-        event!(
-            Level::ERROR,
-            "Error send message to Remove SQLite client {err:?}"
-        );
+    // with 'atomic_swap' the cache is served until the new MBTiles is swapped in
+    if !atomic_swap {
+        if let Err(err) = tx_sqlite_client
+            .send_async(MessageSQLiteClient::RemoveSQLiteClient {
+                mbtiles_db,
+                remove_tiles_folder: None,
+                remove_tiles_db: Some(true),
+            })
+            .await
+        {
+            event!(
+                Level::ERROR,
+                "Error send message to Remove SQLite client {err:?}"
+            );
+        }
     }
 
     if let Some(response) =
-        try_init_mbtiles(&cwd, dataset_dir, datasource_id, pool.clone(), None).await
+        try_init_mbtiles(&paths, dataset_dir, datasource_id, registry.clone(), None).await
     {
         // return Response with Error
         let b = response.into_body();
@@ -277,7 +408,22 @@ async fn job_pyramid(
     .await
     {
         Ok(response) => {
-            job_processing_result(response, &pg_queue, job).await?;
+            let response = watch_pyramid_response(
+                response,
+                &paths,
+                datasource_id,
+                registry,
+                tx_sqlite_client,
+                PyramidWatch {
+                    atomic_swap,
+                    job_id: Some(job.job_id.to_string()),
+                    webhooks: PyramidWebhooks::from_config(config),
+                },
+            )
+            .await?;
+            if let Some(message) = job_processing_result(response, &queue, job, tx_events).await? {
+                notify_job_failed(registry, config, job, datasource_id, message).await;
+            }
         }
         Err(err) => {
             event!(
@@ -285,7 +431,8 @@ async fn job_pyramid(
                 "Error 'job_worker' request to worker {:?}",
                 err
             );
-            match pg_queue.fail_job(job.job_id).await {
+            job_event(tx_events, job, "failed");
+            match queue.fail_job(job.job_id).await {
                 Err(err) => {
                     event!(
                         Level::ERROR,
@@ -296,17 +443,66 @@ async fn job_pyramid(
                 }
                 Ok(_) => {}
             };
+            notify_job_failed(registry, config, job, datasource_id, format!("{err:?}")).await;
         }
     }
 
     Ok(())
 }
 
+/*
+    Delta pyramid of the job is planned when the job is pulled: changes of source files
+    are found at the time of the run. The job is completed when tiles are requested from workers
+*/
+async fn job_delta_pyramid(
+    paths: &Paths,
+    registry: &SharedRegistry,
+    tx: &flume::Sender<MessageDatasource>,
+    datasource_id: &str,
+    ctx: SeedingContext,
+    config: &Config,
+    queue: &SharedQueue,
+    job: &Job,
+) {
+    let tx_events = ctx.tx_events.clone();
+    let result = match plan_delta_pyramid(paths, registry, tx, &job.detail.data.0, config).await {
+        Ok(plan) => {
+            let pyramid_id = start_delta_pyramid(ctx, datasource_id, plan).await;
+            event!(
+                Level::INFO,
+                "Delta pyramid job '{}' of DataSource '{datasource_id}' started: {pyramid_id:?}",
+                job.job_id.to_string()
+            );
+            job_event(&tx_events, job, "completed");
+            queue.delete_job(job.job_id).await
+        }
+        Err(err) => {
+            event!(
+                Level::ERROR,
+                "Error delta pyramid job '{}' of DataSource '{datasource_id}': {err}",
+                job.job_id.to_string()
+            );
+            job_event(&tx_events, job, "failed");
+            queue.fail_job(job.job_id).await
+        }
+    };
+    if let Err(err) = result {
+        event!(
+            Level::ERROR,
+            "Error finish job '{}' {:?}",
+            job.job_id.to_string(),
+            err
+        );
+    }
+}
+
+// Job is completed or failed by the response of the worker, the error of the failed job is returned
 pub async fn job_processing_result(
     response: Response<Body>,
-    pg_queue: &PostgresQueue,
+    queue: &SharedQueue,
     job: &Job,
-) -> Result<(), anyhow::Error> {
+    tx_events: &flume::Sender<MessageEvents>,
+) -> Result<Option<String>, anyhow::Error> {
     let status = response.status().as_u16();
     let code = ((status as f32 / 100.0) as f32).round() as u16;
     if code == 4 || code == 5 || code == 6 {
@@ -316,7 +512,8 @@ pub async fn job_processing_result(
             Level::ERROR,
             "Error 'job_worker' request to worker: {err:?}"
         );
-        match pg_queue.fail_job(job.job_id).await {
+        job_event(tx_events, job, "failed");
+        match queue.fail_job(job.job_id).await {
             Err(err) => {
                 event!(
                     Level::ERROR,
@@ -327,8 +524,10 @@ pub async fn job_processing_result(
             }
             Ok(_) => {}
         };
+        return Ok(Some(err));
     } else {
-        match pg_queue.delete_job(job.job_id).await {
+        job_event(tx_events, job, "completed");
+        match queue.delete_job(job.job_id).await {
             Err(err) => {
                 event!(
                     Level::ERROR,
@@ -341,5 +540,36 @@ pub async fn job_processing_result(
         };
     }
 
-    Ok(())
+    Ok(None)
+}
+
+// Pyramid of the job failed before the worker started it
+async fn notify_job_failed(
+    registry: &SharedRegistry,
+    config: &Config,
+    job: &Job,
+    datasource_id: &str,
+    message: String,
+) {
+    let notification = PyramidNotification::failed(datasource_id, None, message)
+        .with_job(Some(job.job_id.to_string()));
+    notify_pyramid(
+        registry,
+        &PyramidWebhooks::from_config(config),
+        notification,
+    )
+    .await;
+}
+
+fn job_event(tx_events: &flume::Sender<MessageEvents>, job: &Job, state: &str) {
+    publish_event(
+        tx_events,
+        EventKind::Job,
+        json!({
+            "state": state,
+            "job_id": job.job_id.to_string(),
+            "job_type": job.detail.jt.as_ref(),
+            "name": job.detail.name,
+        }),
+    );
 }
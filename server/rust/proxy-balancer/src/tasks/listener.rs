@@ -0,0 +1,111 @@
+use std::net::IpAddr;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use hyper::{client::HttpConnector, Body, Client, Method, Request};
+use sqlx::postgres::PgListener;
+use tokio::task::JoinHandle;
+use tracing::{event, Level};
+
+use super::datasources::MessageDatasource;
+use super::reload_workers::MessageMaintenanceWorkers;
+use crate::db::DB;
+use crate::defaults::{DATASOURCE_CHANNEL, DATASOURCE_NOTIFY_DEBOUNCE_MS, LOCALHOST};
+use crate::handles::helpers::get_worker_data;
+use crate::hyper_reverse_proxy;
+use crate::structs::EndPoints;
+
+// Workers of this node reload DataSources from the database
+async fn reload_on_workers(
+    client: &Client<HttpConnector>,
+    ports: &[u16],
+) -> Result<(), anyhow::Error> {
+    for port in ports {
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri(EndPoints::DataSources.as_ref())
+            .body(Body::empty())?;
+        let response = hyper_reverse_proxy::call(
+            IpAddr::from([127, 0, 0, 1]),
+            &format!("http://{}:{}", LOCALHOST, port),
+            request,
+            client,
+        )
+        .await
+        .map_err(|err| anyhow!("{err:?}"))?;
+        if !response.status().is_success() {
+            return Err(anyhow!(
+                "worker port {port} responded {}",
+                response.status()
+            ));
+        }
+    }
+    Ok(())
+}
+
+/*
+    Changes of the table 'datasource' made on any node are notified by the trigger
+    on the channel 'datasource_changed'. Notifications of bulk uploads are merged,
+    the state is updated only on this node without the header 'Master-Server'
+*/
+pub fn datasources_listener(
+    pool: DB,
+    tx: flume::Sender<MessageDatasource>,
+    tx_mw: flume::Sender<MessageMaintenanceWorkers>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut listener = loop {
+            match PgListener::connect_with(&pool).await {
+                Ok(mut listener) => match listener.listen(DATASOURCE_CHANNEL).await {
+                    Ok(_) => break listener,
+                    Err(err) => {
+                        event!(Level::ERROR, "Error listen '{DATASOURCE_CHANNEL}': {err}");
+                    }
+                },
+                Err(err) => {
+                    event!(Level::ERROR, "Error connect listener of DataSources: {err}");
+                }
+            }
+            tokio::time::sleep(Duration::from_secs(5)).await;
+        };
+
+        loop {
+            match listener.recv().await {
+                Ok(notification) => {
+                    event!(
+                        Level::DEBUG,
+                        "DataSource '{}' changed",
+                        notification.payload()
+                    );
+                }
+                Err(err) => {
+                    // notifications could be lost while the connection is restored
+                    event!(Level::ERROR, "Error receive changes of DataSources: {err}");
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                }
+            }
+            let debounce = Duration::from_millis(DATASOURCE_NOTIFY_DEBOUNCE_MS);
+            while let Ok(Ok(_)) = tokio::time::timeout(debounce, listener.recv()).await {}
+
+            match get_worker_data(tx_mw.clone()).await {
+                Ok(wd) => {
+                    if let Err(err) = reload_on_workers(&wd.client, &wd.ports).await {
+                        event!(Level::ERROR, "Error reload DataSources on workers: {err}");
+                    }
+                }
+                Err(err) => {
+                    event!(Level::ERROR, "Error reload DataSources on workers: {err}");
+                }
+            }
+            if tx
+                .send_async(MessageDatasource::UpdateDataSources {
+                    is_header_master: true,
+                })
+                .await
+                .is_err()
+            {
+                break;
+            }
+        }
+    })
+}
@@ -0,0 +1,209 @@
+use std::time::Duration;
+
+use anyhow::anyhow;
+use hyper::{Body, Response};
+use tiler_core::sqlite_clients::MessageSQLiteClient;
+use tokio::sync::oneshot;
+use tracing::{event, Level};
+
+use crate::db::{pyramid_state, registry::SharedRegistry, write_mbtiles_metadata};
+use crate::defaults::PYRAMID_POLL_SECS;
+use crate::paths::Paths;
+use crate::sources::record_sources_state;
+use crate::utils::mbtiles_path_from_ds_id;
+use crate::webhooks::{notify_pyramid, PyramidNotification, PyramidWebhooks};
+
+// Table 'metadata' of MBTiles is populated from the row of the DataSource
+pub async fn write_datasource_metadata(
+    paths: &Paths,
+    datasource_id: &str,
+    registry: &SharedRegistry,
+    tx_sqlite_client: flume::Sender<MessageSQLiteClient>,
+) -> Result<(), anyhow::Error> {
+    let ds = registry
+        .datasource(datasource_id)
+        .await?
+        .ok_or(anyhow::anyhow!("DataSource '{datasource_id}' not found"))?;
+    let mbtiles_db = mbtiles_path_from_ds_id(paths, datasource_id)?;
+    write_mbtiles_metadata(&mbtiles_db, ds.mbtiles_metadata(), tx_sqlite_client).await
+}
+
+// 'atomic_swap' of the body of the pyramid request
+pub fn pyramid_atomic_swap(body: &serde_json::Value) -> bool {
+    body.get("atomic_swap")
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false)
+}
+
+// Options of the watched pyramid
+#[derive(Debug, Clone)]
+pub struct PyramidWatch {
+    pub atomic_swap: bool,
+    // job of the queue which started the pyramid
+    pub job_id: Option<String>,
+    pub webhooks: PyramidWebhooks,
+}
+
+// New MBTiles of the complete pyramid replaces the served MBTiles of the DataSource
+async fn swap_pyramid_mbtiles(
+    paths: &Paths,
+    datasource_id: &str,
+    tx_sqlite_client: &flume::Sender<MessageSQLiteClient>,
+) -> Result<(), anyhow::Error> {
+    let (tx_result, rx_result) = oneshot::channel();
+    tx_sqlite_client
+        .send_async(MessageSQLiteClient::SwapMBTiles {
+            mbtiles_db: path_string(mbtiles_path_from_ds_id(paths, datasource_id)?)?,
+            new_db: path_string(paths.mbtiles_db_new(datasource_id))?,
+            tx_result,
+        })
+        .await?;
+    rx_result.await?
+}
+
+fn path_string(path: std::path::PathBuf) -> Result<String, anyhow::Error> {
+    path.into_os_string()
+        .into_string()
+        .map_err(|err| anyhow!("{err:?}"))
+}
+
+// MBTiles of the incomplete pyramid is removed, the served MBTiles is kept
+async fn remove_new_mbtiles(paths: &Paths, datasource_id: &str) {
+    let new_db = paths.mbtiles_db_new(datasource_id);
+    for suffix in ["", "-wal", "-shm"] {
+        let mut file = new_db.clone().into_os_string();
+        file.push(suffix);
+        if let Err(err) = tokio::fs::remove_file(&file).await {
+            if err.kind() != std::io::ErrorKind::NotFound {
+                event!(Level::ERROR, "Error remove MBTiles file {file:?}: {err}");
+            }
+        }
+    }
+}
+
+/*
+    Pyramid of tiles is created by the worker in the background, its completion
+    is recorded only in 'tiler.db', so the state of the pyramid is polled.
+    With 'atomic_swap' the worker builds '{datasource_id}.mbtiles.new' while the cache is served,
+    the file is swapped in only when the pyramid is complete. Webhooks are notified of the result
+*/
+fn watch_pyramid(
+    paths: Paths,
+    datasource_id: String,
+    pyramid_id: String,
+    registry: SharedRegistry,
+    tx_sqlite_client: flume::Sender<MessageSQLiteClient>,
+    watch: PyramidWatch,
+) {
+    tokio::spawn(async move {
+        let failed = |message: String| {
+            PyramidNotification::failed(&datasource_id, Some(&pyramid_id), message)
+                .with_job(watch.job_id.clone())
+        };
+        let mut interval = tokio::time::interval(Duration::from_secs(PYRAMID_POLL_SECS));
+        loop {
+            interval.tick().await;
+            match pyramid_state(&paths, &pyramid_id).await {
+                Ok(None) => {}
+                Ok(Some(true)) => break,
+                Ok(Some(false)) => {
+                    event!(
+                        Level::WARN,
+                        "Pyramid '{pyramid_id}' of DataSource '{datasource_id}' is not complete"
+                    );
+                    if watch.atomic_swap {
+                        remove_new_mbtiles(&paths, &datasource_id).await;
+                    }
+                    let notification = failed("Pyramid is not complete".to_string());
+                    notify_pyramid(&registry, &watch.webhooks, notification).await;
+                    return;
+                }
+                Err(err) => {
+                    event!(
+                        Level::ERROR,
+                        "Error check state of pyramid '{pyramid_id}': {err}"
+                    );
+                    let notification = failed(format!("State of the pyramid is unknown: {err}"));
+                    notify_pyramid(&registry, &watch.webhooks, notification).await;
+                    return;
+                }
+            }
+        }
+
+        if watch.atomic_swap {
+            match swap_pyramid_mbtiles(&paths, &datasource_id, &tx_sqlite_client).await {
+                Ok(_) => event!(
+                    Level::INFO,
+                    "MBTiles of pyramid '{pyramid_id}' of DataSource '{datasource_id}' swapped in"
+                ),
+                Err(err) => {
+                    event!(
+                        Level::ERROR,
+                        "Error swap MBTiles of pyramid '{pyramid_id}' of DataSource '{datasource_id}': {err}"
+                    );
+                    let notification = failed(format!("Error swap MBTiles: {err}"));
+                    notify_pyramid(&registry, &watch.webhooks, notification).await;
+                    return;
+                }
+            }
+        }
+
+        // source files of the complete pyramid are the base of the next delta pyramid
+        if let Ok(Some(ds)) = registry.datasource(&datasource_id).await {
+            record_sources_state(&paths, &ds).await;
+        }
+
+        match write_datasource_metadata(&paths, &datasource_id, &registry, tx_sqlite_client).await {
+            Ok(_) => event!(
+                Level::INFO,
+                "Metadata of MBTiles of DataSource '{datasource_id}' populated"
+            ),
+            Err(err) => event!(
+                Level::ERROR,
+                "Error populate metadata of MBTiles of DataSource '{datasource_id}': {err}"
+            ),
+        }
+
+        let notification =
+            PyramidNotification::completed(&datasource_id, &pyramid_id).with_job(watch.job_id);
+        notify_pyramid(&registry, &watch.webhooks, notification).await;
+    });
+}
+
+/*
+    Response of the worker to the request to create the pyramid contains 'pyramid_id',
+    the body is read to start watching the pyramid and the response is rebuilt
+*/
+pub async fn watch_pyramid_response(
+    response: Response<Body>,
+    paths: &Paths,
+    datasource_id: &str,
+    registry: &SharedRegistry,
+    tx_sqlite_client: flume::Sender<MessageSQLiteClient>,
+    watch: PyramidWatch,
+) -> Result<Response<Body>, anyhow::Error> {
+    if !response.status().is_success() {
+        return Ok(response);
+    }
+
+    let (parts, body) = response.into_parts();
+    let b_bytes = hyper::body::to_bytes(body).await?;
+    let pyramid_id = serde_json::from_slice::<serde_json::Value>(&b_bytes)
+        .ok()
+        .and_then(|b| {
+            b.get("pyramid_id")
+                .and_then(|id| id.as_str())
+                .map(String::from)
+        });
+    if let Some(pyramid_id) = pyramid_id {
+        watch_pyramid(
+            paths.clone(),
+            datasource_id.to_string(),
+            pyramid_id,
+            registry.clone(),
+            tx_sqlite_client,
+            watch,
+        );
+    }
+    Ok(Response::from_parts(parts, Body::from(b_bytes)))
+}
@@ -1,6 +1,22 @@
+pub mod audit;
+pub mod billing;
+pub mod circuit_breaker;
+pub mod cluster;
 pub mod datasources;
+pub mod distributed;
+pub mod events;
+pub mod exports;
 pub mod job;
+pub mod listener;
+pub mod mbtiles_metadata;
+pub mod quotas;
 pub mod reload_workers;
+pub mod seeding;
 pub mod semaphore;
-pub mod sqlite_clients;
+pub mod stats;
+pub mod storage;
+pub mod supervisor;
+pub mod trash;
+pub mod uploads;
+pub mod usage;
 pub mod workers;
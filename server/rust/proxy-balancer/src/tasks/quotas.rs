@@ -0,0 +1,230 @@
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use tiler_core::integrity::remove_disk_tile;
+use tiler_core::mbtiles::get_mbtiles_paths;
+use tiler_core::sqlite_clients::MessageSQLiteClient;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tracing::{event, Level};
+
+use super::datasources::MessageDatasource;
+use super::stats::{EvictionCounters, MessageStats};
+use crate::db::{evict_mbtiles_zoom, get_mbtiles_zoom_counts};
+use crate::defaults::{QUOTA_EVICT_BATCH, QUOTA_TARGET_RATIO};
+use crate::paths::Paths;
+use crate::structs::Extension;
+
+struct DiskTile {
+    path: PathBuf,
+    z: u8,
+    size: u64,
+    accessed: SystemTime,
+}
+
+async fn file_size(path: &Path) -> u64 {
+    tokio::fs::metadata(path)
+        .await
+        .map(|md| md.len())
+        .unwrap_or(0)
+}
+
+// MBTiles with their WAL files
+async fn mbtiles_usage(mbtiles_paths: &[PathBuf]) -> u64 {
+    let mut usage = 0;
+    for p in mbtiles_paths {
+        let mut wal = p.as_os_str().to_owned();
+        wal.push("-wal");
+        usage += file_size(p).await + file_size(Path::new(&wal)).await;
+    }
+    usage
+}
+
+// Tile files of the layout '{z}/{x}/{y}.{ext}' with the time of the last access
+async fn disk_tiles(tiles_dir: &Path) -> Vec<DiskTile> {
+    let extensions = [
+        Extension::Png.as_ref(),
+        Extension::Pbf.as_ref(),
+        Extension::Mvt.as_ref(),
+        Extension::Webp.as_ref(),
+    ];
+    let mut tiles = Vec::new();
+    let mut dirs = vec![(tiles_dir.to_path_buf(), 0, 0)];
+    while let Some((dir, depth, z)) = dirs.pop() {
+        let Ok(mut entries) = tokio::fs::read_dir(&dir).await else {
+            continue;
+        };
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let path = entry.path();
+            let Ok(md) = entry.metadata().await else {
+                continue;
+            };
+            if md.is_dir() && depth < 2 {
+                let z = match depth {
+                    0 => match entry.file_name().to_str().and_then(|n| n.parse().ok()) {
+                        Some(z) => z,
+                        None => continue,
+                    },
+                    _ => z,
+                };
+                dirs.push((path, depth + 1, z));
+            } else if md.is_file() && depth == 2 {
+                let is_tile = path
+                    .extension()
+                    .and_then(|ext| ext.to_str())
+                    .is_some_and(|ext| extensions.contains(&ext));
+                if is_tile {
+                    // filesystems mounted with 'noatime' give the time of the write
+                    let accessed = md
+                        .accessed()
+                        .or(md.modified())
+                        .unwrap_or(SystemTime::UNIX_EPOCH);
+                    tiles.push(DiskTile {
+                        path,
+                        z,
+                        size: md.len(),
+                        accessed,
+                    });
+                }
+            }
+        }
+    }
+    tiles
+}
+
+/*
+    Cache of the DataSource over the quota is reduced to 'QUOTA_TARGET_RATIO' of the quota:
+    tiles of the highest zoom are evicted first, on disk the least recently accessed,
+    in MBTiles the earliest inserted (MBTiles has no time of access)
+*/
+async fn enforce_quota(
+    paths: &Paths,
+    datasource_id: &str,
+    max_cache_mb: u64,
+    tx_sqlite_client: flume::Sender<MessageSQLiteClient>,
+) -> Result<EvictionCounters, anyhow::Error> {
+    let tiles_dir = paths.tiles_dir(datasource_id);
+    let mbtiles_paths = get_mbtiles_paths(tiles_dir.clone())
+        .await
+        .unwrap_or_default();
+    let mut tiles = disk_tiles(&tiles_dir).await;
+
+    let quota = max_cache_mb * 1024 * 1024;
+    let mut usage = mbtiles_usage(&mbtiles_paths).await + tiles.iter().map(|t| t.size).sum::<u64>();
+    let mut evicted = EvictionCounters::default();
+    if usage <= quota {
+        return Ok(evicted);
+    }
+    let target = (quota as f64 * QUOTA_TARGET_RATIO) as u64;
+
+    // the highest zoom first, then the least recently accessed
+    tiles.sort_by(|a, b| b.z.cmp(&a.z).then(a.accessed.cmp(&b.accessed)));
+    let mut tiles = tiles.into_iter().peekable();
+
+    loop {
+        let mut mbtiles_zooms: Vec<(PathBuf, u8)> = Vec::new();
+        for p in mbtiles_paths.iter() {
+            let counts = get_mbtiles_zoom_counts(p, tx_sqlite_client.clone()).await?;
+            if let Some((z, _)) = counts.iter().rev().find(|(_, count)| *count > 0) {
+                mbtiles_zooms.push((p.clone(), *z));
+            }
+        }
+        let max_mbtiles_zoom = mbtiles_zooms.iter().map(|(_, z)| *z).max();
+        let max_disk_zoom = tiles.peek().map(|t| t.z);
+        let Some(z) = max_mbtiles_zoom.max(max_disk_zoom) else {
+            break;
+        };
+
+        while usage > target && tiles.peek().is_some_and(|t| t.z == z) {
+            let Some(tile) = tiles.next() else {
+                break;
+            };
+            remove_disk_tile(&tile.path).await;
+            usage = usage.saturating_sub(tile.size);
+            evicted.tiles += 1;
+            evicted.bytes += tile.size;
+        }
+
+        for (p, _) in mbtiles_zooms.iter().filter(|(_, mz)| *mz == z) {
+            while usage > target {
+                let (n, bytes) =
+                    evict_mbtiles_zoom(p, z, QUOTA_EVICT_BATCH, tx_sqlite_client.clone()).await?;
+                if n == 0 {
+                    break;
+                }
+                usage = usage.saturating_sub(bytes);
+                evicted.tiles += n;
+                evicted.bytes += bytes;
+            }
+        }
+
+        if usage <= target {
+            break;
+        }
+    }
+
+    event!(
+        Level::INFO,
+        "Cache of DataSource '{datasource_id}' over the quota {max_cache_mb} MB: evicted {} tiles, {} bytes",
+        evicted.tiles,
+        evicted.bytes
+    );
+    Ok(evicted)
+}
+
+/*
+    Janitor of the cache: every 'interval_secs' the usage of MBTiles and tiles on disk
+    is measured for DataSources with the quota 'max_cache_mb' in the DataSource JSON
+*/
+pub fn quotas_maintenance(
+    paths: Paths,
+    tx: flume::Sender<MessageDatasource>,
+    tx_sqlite_client: flume::Sender<MessageSQLiteClient>,
+    tx_stats: flume::Sender<MessageStats>,
+    interval_secs: u64,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(interval_secs.max(1)));
+        loop {
+            interval.tick().await;
+            let (tx_quotas, rx_quotas) = oneshot::channel();
+            if tx
+                .send_async(MessageDatasource::GetQuotas { tx_quotas })
+                .await
+                .is_err()
+            {
+                break;
+            }
+            let Ok(quotas) = rx_quotas.await else {
+                continue;
+            };
+
+            for (datasource_id, max_cache_mb) in quotas {
+                match enforce_quota(
+                    &paths,
+                    &datasource_id,
+                    max_cache_mb,
+                    tx_sqlite_client.clone(),
+                )
+                .await
+                {
+                    Ok(evicted) if evicted.tiles > 0 => {
+                        if let Err(err) = tx_stats.send(MessageStats::Evicted {
+                            datasource_id,
+                            evicted,
+                        }) {
+                            event!(Level::ERROR, "Error send evictions: {err}");
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(err) => {
+                        event!(
+                            Level::ERROR,
+                            "Error quota of the cache of DataSource '{datasource_id}': {err}"
+                        );
+                    }
+                }
+            }
+        }
+    })
+}
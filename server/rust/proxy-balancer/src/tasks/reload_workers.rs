@@ -1,15 +1,19 @@
 use std::time::Duration;
 
 use hyper::client::HttpConnector;
-use hyper::Client;
+use hyper::{Client, StatusCode};
+use serde_json::json;
 use tokio::sync::oneshot;
 use tokio::task::JoinHandle;
 use tokio_schedule::Job;
 use tracing::{event, Level};
 
+use super::audit::{record_audit, AuditActor, MessageAudit};
 use super::workers::SystemInfoWorkers;
 use crate::config::Config;
 use crate::db::check_running_pyramids;
+use crate::paths::Paths;
+use crate::structs::EndPoints;
 
 trait DurationExt {
     fn from_hours(hours: u64) -> Duration;
@@ -47,16 +51,34 @@ pub enum MessageMaintenanceWorkers {
     AddWorkers {
         count: u64,
     },
+    ReportWorkerResult {
+        port: u16,
+        success: bool,
+    },
+    // retry of the request goes to the port only if its circuit breaker lets it through
+    AllowWorker {
+        port: u16,
+        tx_allowed: oneshot::Sender<bool>,
+    },
     ReloadWorkers(),
     TerminateWorkers(),
     GetWorkerState {
         tx_ws: oneshot::Sender<WorkerState>,
     },
+    // PIDs of workers and their processes are written again to 'scripts/PIDs'
+    SavePids {
+        tx_result: oneshot::Sender<Result<Vec<u32>, anyhow::Error>>,
+    },
+    // ports and PIDs of started workers
+    ListWorkers {
+        tx_lw: oneshot::Sender<Vec<(u16, u32)>>,
+    },
 }
 
 pub fn reload_workers_maintenance(
-    cwd: String,
+    paths: Paths,
     tx_mw: flume::Sender<MessageMaintenanceWorkers>,
+    tx_audit: flume::Sender<MessageAudit>,
     config: Config,
 ) -> JoinHandle<()> {
     tokio::spawn(async move {
@@ -95,7 +117,7 @@ pub fn reload_workers_maintenance(
                     // The first tick completes immediately.
                     delay.tick().await;
 
-                    match check_running_pyramids(&cwd).await {
+                    match check_running_pyramids(&paths).await {
                         Err(err) => {
                             event!(
                                 Level::ERROR,
@@ -110,6 +132,13 @@ pub fn reload_workers_maintenance(
                                     .await
                                     .unwrap();
                                 event!(Level::INFO, "Reload Python workers after attemp '{i}'");
+                                record_audit(
+                                    &tx_audit,
+                                    &AuditActor::scheduler(),
+                                    EndPoints::ReloadWorkers.as_ref(),
+                                    json!({ "attempt": i }),
+                                    StatusCode::OK.as_u16(),
+                                );
                                 drop(delay);
                                 break;
                             } else {
@@ -0,0 +1,389 @@
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use hyper::{client::HttpConnector, Body, Client, Method, Request};
+use serde::Serialize;
+use serde_json::json;
+use tiler_core::integrity::{remove_disk_tile, remove_mbtile};
+use tiler_core::mbtiles::mbtiles_client;
+use tiler_core::sqlite_clients::MessageSQLiteClient;
+use tokio::task::JoinHandle;
+use tracing::{event, Level};
+use uuid::Uuid;
+
+use super::events::{publish_event, EventKind, MessageEvents};
+use super::reload_workers::MessageMaintenanceWorkers;
+use super::semaphore::{Lane, MessageSemaphore};
+use super::usage::MissedTile;
+use crate::db::get_mbtile;
+use crate::defaults::{DEFAULT_TILE_GRID, LOCALHOST};
+use crate::handles::helpers::{report_worker_latency, report_worker_result};
+use crate::hyper_reverse_proxy;
+use crate::paths::Paths;
+use crate::sources::{save_sources_state, SourceState};
+use crate::structs::{ContentType, EndPoints, Extension};
+use crate::utils::get_tile_from_disk;
+
+// Channels and parameters of requests of tiles to workers
+#[derive(Clone)]
+pub struct SeedingContext {
+    pub paths: Paths,
+    pub client: Client<HttpConnector>,
+    pub client_ip: IpAddr,
+    pub ports: Vec<u16>,
+    pub tx_sem: flume::Sender<MessageSemaphore>,
+    pub tx_mw: flume::Sender<MessageMaintenanceWorkers>,
+    pub tx_sqlite_client: flume::Sender<MessageSQLiteClient>,
+    pub tx_events: flume::Sender<MessageEvents>,
+    pub timeout_secs: u64,
+    pub integrity: bool,
+}
+
+/*
+    Smart pyramid creates missing tiles, delta pyramid creates tiles again: every tile is removed
+    from the cache right before its request, the state of sources is recorded on success.
+    Shard of the distributed pyramid creates tiles of its area again like the delta pyramid
+*/
+#[derive(Debug, Clone)]
+pub enum SeedingKind {
+    Smart,
+    Delta { sources: Option<SourceState> },
+    Shard,
+}
+
+impl SeedingKind {
+    fn job_type(&self) -> &'static str {
+        match self {
+            SeedingKind::Smart => "smart_pyramid",
+            SeedingKind::Delta { .. } => "delta_pyramid",
+            SeedingKind::Shard => "pyramid_shard",
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            SeedingKind::Smart => "Smart pyramid",
+            SeedingKind::Delta { .. } => "Delta pyramid",
+            SeedingKind::Shard => "Pyramid shard",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct SeedingReport {
+    pub seeded: u64,
+    pub cached: u64,
+    pub failed: u64,
+}
+
+impl SeedingReport {
+    fn add(&mut self, other: SeedingReport) {
+        self.seeded += other.seeded;
+        self.cached += other.cached;
+        self.failed += other.failed;
+    }
+}
+
+fn tile_path(datasource_id: &str, tile: &MissedTile) -> String {
+    format!(
+        "{}/{datasource_id}/{}/{}/{}.{}",
+        EndPoints::Tile.as_ref(),
+        tile.z,
+        tile.x,
+        tile.y,
+        tile.ext
+    )
+}
+
+// Tile was created since the miss: by a request, a pyramid or a previous seeding
+async fn is_tile_cached(ctx: &SeedingContext, datasource_id: &str, tile: &MissedTile) -> bool {
+    let content_type = if tile.ext == Extension::Png.as_ref() {
+        ContentType::Png.as_ref()
+    } else {
+        ContentType::MvtPbf.as_ref()
+    };
+    let file_tile_path: PathBuf = ctx
+        .paths
+        .tiles_dir(datasource_id)
+        .join(tile.z.to_string())
+        .join(tile.x.to_string())
+        .join(format!("{}.{}", tile.y, tile.ext));
+    if let Ok(Some(_)) = get_tile_from_disk(&file_tile_path, content_type, ctx.integrity).await {
+        return true;
+    }
+
+    let mbtiles_db = ctx.paths.mbtiles_db(datasource_id);
+    if !tokio::fs::try_exists(&mbtiles_db).await.unwrap_or(false) {
+        return false;
+    }
+    matches!(
+        get_mbtile(
+            &mbtiles_db,
+            tile.z as u64,
+            tile.x,
+            tile.y,
+            content_type,
+            ctx.tx_sqlite_client.clone(),
+            ctx.integrity,
+        )
+        .await,
+        Ok(Some(_))
+    )
+}
+
+// Workers do not replace tiles of the cache, the tile is removed before it is created again
+async fn remove_cached_tile(
+    ctx: &SeedingContext,
+    datasource_id: &str,
+    tile: &MissedTile,
+) -> Result<(), anyhow::Error> {
+    let file_tile_path: PathBuf = ctx
+        .paths
+        .tiles_dir(datasource_id)
+        .join(tile.z.to_string())
+        .join(tile.x.to_string())
+        .join(format!("{}.{}", tile.y, tile.ext));
+    if tokio::fs::try_exists(&file_tile_path)
+        .await
+        .unwrap_or(false)
+    {
+        remove_disk_tile(&file_tile_path).await;
+    }
+
+    let mbtiles_db = ctx.paths.mbtiles_db(datasource_id);
+    if !tokio::fs::try_exists(&mbtiles_db).await.unwrap_or(false) {
+        return Ok(());
+    }
+    let (z, x, y) = (tile.z as u64, tile.x, tile.y);
+    mbtiles_client(&mbtiles_db, false, ctx.tx_sqlite_client.clone())
+        .await?
+        .conn(move |connection| remove_mbtile(connection, z, x, y))
+        .await?;
+    Ok(())
+}
+
+// Worker creates the tile and saves it in the cache of the DataSource
+async fn request_tile(
+    ctx: &SeedingContext,
+    port: u16,
+    datasource_id: &str,
+    tile: &MissedTile,
+) -> Result<(), anyhow::Error> {
+    let mut uri = tile_path(datasource_id, tile);
+    if tile.tile_grid != DEFAULT_TILE_GRID {
+        uri.push_str(&format!("?grid={}", tile.tile_grid));
+    }
+    let request = Request::builder()
+        .method(Method::GET)
+        .uri(uri)
+        .body(Body::empty())?;
+
+    let (tx_permit, rx_permit) = tokio::sync::oneshot::channel();
+    ctx.tx_sem
+        .send_async(MessageSemaphore::GetPermit {
+            port,
+            lane: Lane::Batch,
+            max_queued: 0,
+            tx_permit,
+        })
+        .await?;
+    let permit = rx_permit.await;
+
+    let forward_uri = format!("http://{}:{}", LOCALHOST, port);
+    let worker_call = hyper_reverse_proxy::call(ctx.client_ip, &forward_uri, request, &ctx.client);
+    let upstream_started = Instant::now();
+    let result = if ctx.timeout_secs > 0 {
+        match tokio::time::timeout(Duration::from_secs(ctx.timeout_secs), worker_call).await {
+            Ok(res) => res,
+            Err(_) => {
+                drop(permit);
+                report_worker_result(&ctx.tx_mw, port, false).await;
+                let latency = upstream_started.elapsed();
+                report_worker_latency(&ctx.tx_sem, port, latency, false).await;
+                return Err(anyhow::anyhow!(
+                    "no response in {} seconds",
+                    ctx.timeout_secs
+                ));
+            }
+        }
+    } else {
+        worker_call.await
+    };
+    drop(permit);
+    let latency = upstream_started.elapsed();
+
+    match result {
+        Ok(response) => {
+            let success = !response.status().is_server_error();
+            report_worker_result(&ctx.tx_mw, port, success).await;
+            report_worker_latency(&ctx.tx_sem, port, latency, success).await;
+            if success {
+                Ok(())
+            } else {
+                Err(anyhow::anyhow!("worker response {}", response.status()))
+            }
+        }
+        Err(err) => {
+            report_worker_result(&ctx.tx_mw, port, false).await;
+            report_worker_latency(&ctx.tx_sem, port, latency, false).await;
+            Err(anyhow::anyhow!("{err:?}"))
+        }
+    }
+}
+
+fn seeding_event(
+    tx_events: &flume::Sender<MessageEvents>,
+    kind: &SeedingKind,
+    seeding_id: &str,
+    datasource_id: &str,
+    state: &str,
+    report: Option<SeedingReport>,
+) {
+    publish_event(
+        tx_events,
+        EventKind::Job,
+        json!({
+            "state": state,
+            "job_id": seeding_id,
+            "job_type": kind.job_type(),
+            "name": format!("{} {datasource_id}", kind.name()),
+            "report": report,
+        }),
+    );
+}
+
+// Requests of worker ports stop with the seeding, e.g. the aborted job of the shard
+struct PortTasks(Vec<JoinHandle<SeedingReport>>);
+
+impl Drop for PortTasks {
+    fn drop(&mut self) {
+        for jh in &self.0 {
+            jh.abort();
+        }
+    }
+}
+
+/*
+    Smart pyramid: tiles missed by the cache are requested from workers, one queue of tiles
+    is shared by the requests to every worker port. The most missed tiles go first,
+    tiles cached since the miss are skipped. Tiles of the delta pyramid are always created again
+*/
+pub fn start_seeding(
+    ctx: SeedingContext,
+    kind: SeedingKind,
+    datasource_id: String,
+    tiles: Vec<MissedTile>,
+) -> String {
+    let seeding_id = Uuid::new_v4().to_string();
+    let id = seeding_id.clone();
+    tokio::spawn(async move {
+        seed_tiles(ctx, kind, id, datasource_id, tiles).await;
+    });
+    seeding_id
+}
+
+// Tiles are requested until the queue is empty, the report is published with the final event
+pub async fn seed_tiles(
+    ctx: SeedingContext,
+    kind: SeedingKind,
+    id: String,
+    datasource_id: String,
+    tiles: Vec<MissedTile>,
+) -> SeedingReport {
+    let name = kind.name();
+    seeding_event(&ctx.tx_events, &kind, &id, &datasource_id, "running", None);
+    event!(
+        Level::INFO,
+        "{name} '{id}' of DataSource '{datasource_id}': {} tiles",
+        tiles.len()
+    );
+
+    let (tx_tiles, rx_tiles) = flume::unbounded::<MissedTile>();
+    for tile in tiles {
+        let _ = tx_tiles.send(tile);
+    }
+    drop(tx_tiles);
+
+    let refresh = !matches!(kind, SeedingKind::Smart);
+    let mut handles = PortTasks(Vec::with_capacity(ctx.ports.len()));
+    for port in ctx.ports.clone() {
+        let ctx = ctx.clone();
+        let rx_tiles = rx_tiles.clone();
+        let datasource_id = datasource_id.clone();
+        handles.0.push(tokio::spawn(async move {
+            let mut report = SeedingReport::default();
+            while let Ok(tile) = rx_tiles.recv_async().await {
+                if refresh {
+                    if let Err(err) = remove_cached_tile(&ctx, &datasource_id, &tile).await {
+                        report.failed += 1;
+                        event!(
+                            Level::ERROR,
+                            "Error remove tile '{}' from the cache: {err}",
+                            tile_path(&datasource_id, &tile)
+                        );
+                        continue;
+                    }
+                } else if is_tile_cached(&ctx, &datasource_id, &tile).await {
+                    report.cached += 1;
+                    continue;
+                }
+                match request_tile(&ctx, port, &datasource_id, &tile).await {
+                    Ok(()) => report.seeded += 1,
+                    Err(err) => {
+                        report.failed += 1;
+                        event!(
+                            Level::ERROR,
+                            "Error seed tile '{}' on worker port {port}: {err}",
+                            tile_path(&datasource_id, &tile)
+                        );
+                    }
+                }
+            }
+            report
+        }));
+    }
+
+    let mut report = SeedingReport::default();
+    for jh in handles.0.iter_mut() {
+        match jh.await {
+            Ok(r) => report.add(r),
+            Err(err) => event!(Level::ERROR, "Error {name} task: {err}"),
+        }
+    }
+    event!(
+        Level::INFO,
+        "{name} '{id}' of DataSource '{datasource_id}' finished: seeded {}, already cached {}, failed {}",
+        report.seeded,
+        report.cached,
+        report.failed
+    );
+    let state = if report.failed > 0 && report.seeded == 0 {
+        "failed"
+    } else {
+        "completed"
+    };
+    // failed tiles are created again by the next delta pyramid of the same changes
+    if let SeedingKind::Delta {
+        sources: Some(sources),
+    } = &kind
+    {
+        if report.failed == 0 {
+            if let Err(err) = save_sources_state(&ctx.paths, &datasource_id, sources).await {
+                event!(
+                    Level::ERROR,
+                    "Error save state of sources of DataSource '{datasource_id}': {err}"
+                );
+            }
+        }
+    }
+    seeding_event(
+        &ctx.tx_events,
+        &kind,
+        &id,
+        &datasource_id,
+        state,
+        Some(report),
+    );
+    report
+}
@@ -1,20 +1,46 @@
 use std::collections::{HashMap, VecDeque};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use tokio::sync::{oneshot, OwnedSemaphorePermit, RwLock, Semaphore};
+use serde::Serialize;
+use strum_macros::{AsRefStr, Display, EnumString};
+use tokio::sync::{oneshot, OwnedSemaphorePermit, Semaphore};
 use tokio::task::JoinHandle;
 use tracing::{event, Level};
 
-use crate::config::Config;
+use crate::config::{AdaptiveConcurrency, Config};
+use crate::defaults::SEMAPHORE_REJECTIONS_WINDOW_SECS;
+
+/*
+    Lane of the tile request: batch requests (seeding of missed tiles, requests with the header
+    'X-Tile-Priority: batch') wait after interactive ones and hold only a share of permits
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Display, EnumString, AsRefStr)]
+pub enum Lane {
+    #[strum(serialize = "interactive")]
+    Interactive,
+    #[strum(serialize = "batch")]
+    Batch,
+}
+
+// The request is shed: the queue of the port already has 'depth' waiters
+#[derive(Debug, Clone, Copy)]
+pub struct QueueFull {
+    pub depth: usize,
+}
 
 #[derive(Debug)]
 pub enum MessageSemaphore {
+    // 'max_queued' - waiters of the port after which requests are shed, 0 - unlimited
     GetPermit {
         port: u16,
-        tx_permit: oneshot::Sender<tokio::sync::OwnedSemaphorePermit>,
+        lane: Lane,
+        max_queued: usize,
+        tx_permit: oneshot::Sender<Result<PermitGuard, QueueFull>>,
     },
     ReleasedPermit {
         port: u16,
+        lane: Lane,
     },
     AddPermits {
         n: usize,
@@ -22,12 +48,201 @@ pub enum MessageSemaphore {
     ForgetPermits {
         n: usize,
     },
+    Info {
+        tx_info: oneshot::Sender<HashMap<u16, PermitsInfo>>,
+    },
+    // response of the worker for adaptive concurrency
+    Observe {
+        port: u16,
+        latency: Duration,
+        success: bool,
+    },
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PermitsInfo {
+    pub configured: usize,
+    pub available: usize,
+    pub queued: usize,
+    pub batch_queued: usize,
+    pub batch_in_flight: usize,
+    // waiters cancelled by the client before they got the permit
+    pub rejections: u64,
+    pub recent_rejections: usize,
+    // requests shed because the queue was full
+    pub shed: u64,
+    pub batch_shed: u64,
 }
 
+// Configured permits of the port and cancelled waiters, the recent ones are kept for the window
+#[derive(Debug, Default)]
+struct PortPermits {
+    configured: usize,
+    rejections: u64,
+    recent_rejections: VecDeque<Instant>,
+    // permits to forget when requests release them (held permits are not forgotten)
+    shrink: usize,
+    // responses of the interval of adaptive concurrency
+    responses: u32,
+    errors: u32,
+    latency: Duration,
+    // a request waited for the permit during the interval
+    saturated: bool,
+    // permits held by batch requests
+    batch_held: usize,
+    shed: u64,
+    batch_shed: u64,
+}
+
+impl PortPermits {
+    fn new(configured: usize) -> Self {
+        PortPermits {
+            configured,
+            ..Default::default()
+        }
+    }
+
+    fn reject(&mut self) {
+        self.rejections += 1;
+        self.recent_rejections.push_back(Instant::now());
+        self.prune();
+    }
+
+    fn shed(&mut self, lane: Lane) {
+        match lane {
+            Lane::Interactive => self.shed += 1,
+            Lane::Batch => self.batch_shed += 1,
+        }
+    }
+
+    fn observe(&mut self, latency: Duration, success: bool) {
+        self.responses += 1;
+        self.latency += latency;
+        if !success {
+            self.errors += 1;
+        }
+    }
+
+    fn forget_shrink(&mut self, semaphore: &Arc<Semaphore>) {
+        if self.shrink == 0 {
+            return;
+        }
+        let forgotten = semaphore.forget_permits(self.shrink);
+        self.shrink -= forgotten;
+        self.configured -= forgotten;
+    }
+
+    /*
+        AIMD: the limit is halved down to 'min_permits' when the average latency of the interval
+        exceeds the target or errors exceed the rate, otherwise it grows by one permit up to
+        'max_permits' if requests waited for permits. Returns true when permits are added
+    */
+    fn adjust(
+        &mut self,
+        port: u16,
+        semaphore: &Arc<Semaphore>,
+        adaptive: &AdaptiveConcurrency,
+    ) -> bool {
+        let responses = std::mem::take(&mut self.responses);
+        let errors = std::mem::take(&mut self.errors);
+        let latency = std::mem::take(&mut self.latency);
+        let saturated = std::mem::take(&mut self.saturated);
+        if responses == 0 {
+            return false;
+        }
+
+        let average = latency / responses;
+        let error_rate = errors as f64 / responses as f64;
+        let limit = self.configured.saturating_sub(self.shrink);
+        if average > Duration::from_millis(adaptive.target_latency_ms)
+            || error_rate > adaptive.max_error_rate
+        {
+            let target = (limit / 2).max(adaptive.min_permits);
+            if target < limit {
+                self.shrink += limit - target;
+                self.forget_shrink(semaphore);
+                let reason = format!("latency {} ms, errors {error_rate:.2}", average.as_millis());
+                event!(
+                    Level::DEBUG,
+                    "Limit of worker port {port} decreased to {target}: {reason}"
+                );
+            }
+            return false;
+        }
+        if !saturated || limit >= adaptive.max_permits {
+            return false;
+        }
+        if self.shrink > 0 {
+            self.shrink -= 1;
+        } else {
+            semaphore.add_permits(1);
+            self.configured += 1;
+        }
+        event!(
+            Level::DEBUG,
+            "Limit of worker port {port} increased to {}",
+            limit + 1
+        );
+        true
+    }
+
+    // Permits of the batch lane, at least one so batch requests are not blocked forever
+    fn batch_quota(&self, batch_share: f64) -> usize {
+        let limit = self.configured.saturating_sub(self.shrink);
+        ((limit as f64 * batch_share) as usize).max(1)
+    }
+
+    fn prune(&mut self) {
+        let window = Duration::from_secs(SEMAPHORE_REJECTIONS_WINDOW_SECS);
+        while let Some(rejected) = self.recent_rejections.front() {
+            if rejected.elapsed() <= window {
+                break;
+            }
+            self.recent_rejections.pop_front();
+        }
+    }
+}
+
+/*
+    Wrapper over the semaphore permit: when the permit is dropped (request completed
+    or cancelled by the client) the waiter queue of the port is notified immediately
+*/
 #[derive(Debug)]
-pub enum MessageChangeLimitCR {
-    Increase { n: usize },
-    Decrease { n: usize },
+pub struct PermitGuard {
+    port: u16,
+    lane: Lane,
+    permit: Option<OwnedSemaphorePermit>,
+    tx: flume::Sender<MessageSemaphore>,
+}
+
+impl PermitGuard {
+    fn new(
+        port: u16,
+        lane: Lane,
+        permit: OwnedSemaphorePermit,
+        tx: flume::Sender<MessageSemaphore>,
+    ) -> Self {
+        PermitGuard {
+            port,
+            lane,
+            permit: Some(permit),
+            tx,
+        }
+    }
+}
+
+impl Drop for PermitGuard {
+    fn drop(&mut self) {
+        // return permit to the semaphore before notify waiters
+        drop(self.permit.take());
+        let message = MessageSemaphore::ReleasedPermit {
+            port: self.port,
+            lane: self.lane,
+        };
+        if let Err(err) = self.tx.send(message) {
+            event!(Level::ERROR, "Error send message 'released permit': {err}");
+        }
+    }
 }
 
 pub fn semaphore_maintenance(
@@ -35,161 +250,245 @@ pub fn semaphore_maintenance(
     tx: flume::Sender<MessageSemaphore>,
     config: Config,
     ports: Vec<u16>,
-) -> (JoinHandle<()>, JoinHandle<()>) {
+) -> JoinHandle<()> {
     let max_concurrent_tile_requests = config.max_concurrent_tile_requests;
+    let adaptive = config.adaptive_concurrency.clone();
+    let batch_share = config.batch_permits_share;
 
-    // Shared semaphores between tasks
-    let semaphores_map: Arc<RwLock<HashMap<u16, Arc<Semaphore>>>> =
-        Arc::new(RwLock::new(HashMap::new()));
+    let mut semaphores_map: HashMap<u16, Arc<Semaphore>> = HashMap::new();
+    let mut permits_map: HashMap<u16, PortPermits> = HashMap::new();
     for port in ports.iter() {
-        let semaphore = Arc::new(tokio::sync::Semaphore::new(max_concurrent_tile_requests));
-        if let Ok(mut semaphores_map_guard) = semaphores_map.try_write() {
-            if !semaphores_map_guard.contains_key(port) {
-                semaphores_map_guard.insert(*port, semaphore);
-            }
-            drop(semaphores_map_guard);
-        }
+        semaphores_map
+            .entry(*port)
+            .or_insert_with(|| Arc::new(Semaphore::new(max_concurrent_tile_requests)));
+        permits_map
+            .entry(*port)
+            .or_insert_with(|| PortPermits::new(max_concurrent_tile_requests));
     }
 
-    let (tx_change_permits, rx_change_permits) = flume::unbounded::<MessageChangeLimitCR>();
-
-    let jh_wait_permits = tokio::spawn({
-        let rx_change_permits = rx_change_permits.clone();
-        let semaphores_map = semaphores_map.clone();
-        let mut number_concurrent_requests = max_concurrent_tile_requests;
-
-        let mut delay = tokio::time::interval(std::time::Duration::from_millis(5));
-
-        async move {
-            loop {
-                delay.tick().await;
+    tokio::spawn(async move {
+        let mut senders_map: HashMap<u16, Waiters> = HashMap::new();
+        let interval_secs = adaptive.as_ref().map(|a| a.interval_secs).unwrap_or(1);
+        let mut interval = tokio::time::interval(Duration::from_secs(interval_secs));
 
-                let mut delta_permits = 0;
-                let mut actual_n = 0;
-
-                if let Ok(message) = rx_change_permits.try_recv() {
-                    match message {
-                        MessageChangeLimitCR::Increase { n } => {
-                            delta_permits = n as isize;
-                        }
-                        MessageChangeLimitCR::Decrease { n } => {
-                            delta_permits = -1 * n as isize;
+        loop {
+            let message = tokio::select! {
+                message = rx.recv_async() => match message {
+                    Ok(m) => m,
+                    Err(_) => break,
+                },
+                _ = interval.tick(), if adaptive.is_some() => {
+                    let Some(adaptive) = adaptive.as_ref() else {
+                        continue;
+                    };
+                    for (port, sm) in semaphores_map.iter() {
+                        let permits = permits_map.entry(*port).or_default();
+                        if permits.adjust(*port, sm, adaptive) {
+                            if let Some(waiters) = senders_map.get_mut(port) {
+                                dispatch_waiters(*port, sm, waiters, permits, batch_share, &tx);
+                            }
                         }
                     }
+                    continue;
                 }
+            };
 
-                let tx = tx.clone();
-                let sems_map_guard = semaphores_map.read().await;
-                for (port, semaphore) in sems_map_guard.iter() {
-                    if semaphore.available_permits() == number_concurrent_requests {
-                        if let Err(err) = tx
-                            .send_async(MessageSemaphore::ReleasedPermit { port: *port })
-                            .await
-                        {
-                            event!(Level::ERROR, "Error send message 'released permit': {err}");
-                        }
+            match message {
+                MessageSemaphore::ReleasedPermit { port, lane } => {
+                    let permits = permits_map.entry(port).or_default();
+                    if lane == Lane::Batch {
+                        permits.batch_held = permits.batch_held.saturating_sub(1);
                     }
-
-                    if delta_permits > 0 {
-                        semaphore.add_permits(delta_permits as usize);
-                    } else if delta_permits < 0 {
-                        actual_n = semaphore.forget_permits(delta_permits.abs() as usize);
+                    if let (Some(sm), Some(waiters)) =
+                        (semaphores_map.get(&port), senders_map.get_mut(&port))
+                    {
+                        permits.forget_shrink(sm);
+                        dispatch_waiters(port, sm, waiters, permits, batch_share, &tx);
                     }
                 }
-
-                if sems_map_guard.len() > 0 {
-                    if delta_permits > 0 {
-                        number_concurrent_requests += delta_permits as usize;
-                    } else if delta_permits < 0 {
-                        number_concurrent_requests -= actual_n;
+                MessageSemaphore::Observe {
+                    port,
+                    latency,
+                    success,
+                } => {
+                    if adaptive.is_some() {
+                        permits_map
+                            .entry(port)
+                            .or_default()
+                            .observe(latency, success);
                     }
                 }
-            }
-        }
-    });
-
-    let jh_permits_maintenance = tokio::spawn({
-        let semaphores_map = semaphores_map.clone();
-        let mut senders_map: HashMap<u16, VecDeque<oneshot::Sender<OwnedSemaphorePermit>>> =
-            HashMap::new();
-
-        async move {
-            while let Ok(message) = rx.recv_async().await {
-                match message {
-                    MessageSemaphore::ReleasedPermit { port } => {
-                        if senders_map.contains_key(&port) {
-                            if let Some(deque) = senders_map.get_mut(&port) {
-                                if let Some(sender) = deque.pop_front() {
-                                    let mut semaphores_map_guard = semaphores_map.write().await;
-                                    if let Some(sm) = semaphores_map_guard.get_mut(&port) {
-                                        if sm.available_permits() > 0 {
-                                            let permit = sm.clone().acquire_owned().await.unwrap();
-                                            if let Err(err) = sender.send(permit) {
-                                                // Send error occurs when the receiver is removed,
-                                                // for example when the request is cancelled by the client (MapLibre)
-                                                drop(err);
-                                            }
-                                        }
-                                    }
-                                }
-                            }
+                MessageSemaphore::GetPermit {
+                    port,
+                    lane,
+                    max_queued,
+                    tx_permit,
+                } => {
+                    let sm = semaphores_map
+                        .entry(port)
+                        .or_insert_with(|| Arc::new(Semaphore::new(max_concurrent_tile_requests)));
+                    let permits = permits_map
+                        .entry(port)
+                        .or_insert_with(|| PortPermits::new(max_concurrent_tile_requests));
+                    let waiters = senders_map.entry(port).or_default();
+                    // backpressure: the request is refused instead of waiting behind a long queue
+                    if max_queued > 0 {
+                        let depth = waiters.queued(Lane::Interactive) + waiters.queued(Lane::Batch);
+                        if depth >= max_queued {
+                            permits.shed(lane);
+                            let _ = tx_permit.send(Err(QueueFull { depth }));
+                            continue;
                         }
                     }
-                    MessageSemaphore::GetPermit { port, tx_permit } => {
-                        let mut semaphores_map_guard = semaphores_map.write().await;
-                        if semaphores_map_guard.contains_key(&port) {
-                            if let Some(sm) = semaphores_map_guard.get_mut(&port) {
-                                if sm.available_permits() > 0 {
-                                    let permit = sm.clone().acquire_owned().await.unwrap();
-                                    if let Err(err) = tx_permit.send(permit) {
-                                        // Send error occurs when the receiver is removed,
-                                        // for example when the request is cancelled by the client (MapLibre)
-                                        drop(err);
-                                    }
-                                } else {
-                                    if !senders_map.contains_key(&port) {
-                                        let mut deque = VecDeque::new();
-                                        deque.push_back(tx_permit);
-                                        senders_map.insert(port, deque);
-                                    } else {
-                                        if let Some(deque) = senders_map.get_mut(&port) {
-                                            deque.push_back(tx_permit);
-                                        }
-                                    }
-                                }
-                            }
-                        } else {
-                            let semaphore =
-                                Arc::new(tokio::sync::Semaphore::new(max_concurrent_tile_requests));
-                            let permit = semaphore.clone().acquire_owned().await.unwrap();
-                            if let Err(err) = tx_permit.send(permit) {
-                                // Send error occurs when the receiver is removed,
-                                // for example when the request is cancelled by the client (MapLibre)
-                                drop(err);
-                            }
-                            semaphores_map_guard.insert(port, semaphore);
-                        }
+                    match lane {
+                        Lane::Interactive => waiters.interactive.push_back(tx_permit),
+                        Lane::Batch => waiters.batch.push_back(tx_permit),
                     }
-                    MessageSemaphore::AddPermits { n } => {
-                        if let Err(err) = tx_change_permits
-                            .send_async(MessageChangeLimitCR::Increase { n })
-                            .await
-                        {
-                            event!(Level::ERROR, "Error send increase message: {}", err);
+                    dispatch_waiters(port, sm, waiters, permits, batch_share, &tx);
+                }
+                MessageSemaphore::AddPermits { n } => {
+                    for (port, sm) in semaphores_map.iter() {
+                        sm.add_permits(n);
+                        let permits = permits_map.entry(*port).or_default();
+                        permits.configured += n;
+                        if let Some(waiters) = senders_map.get_mut(port) {
+                            dispatch_waiters(*port, sm, waiters, permits, batch_share, &tx);
                         }
                     }
-                    MessageSemaphore::ForgetPermits { n } => {
-                        if let Err(err) = tx_change_permits
-                            .send_async(MessageChangeLimitCR::Decrease { n })
-                            .await
-                        {
-                            event!(Level::ERROR, "Error send decrease message: {}", err);
-                        }
+                }
+                MessageSemaphore::ForgetPermits { n } => {
+                    for (port, sm) in semaphores_map.iter() {
+                        // permits held by requests are not forgotten
+                        let forgotten = sm.forget_permits(n);
+                        let permits = permits_map.entry(*port).or_default();
+                        permits.configured = permits.configured.saturating_sub(forgotten);
+                    }
+                }
+                MessageSemaphore::Info { tx_info } => {
+                    let mut info = HashMap::new();
+                    for (port, sm) in semaphores_map.iter() {
+                        let permits = permits_map.entry(*port).or_default();
+                        permits.prune();
+                        let (queued, batch_queued) = senders_map
+                            .get(port)
+                            .map(|waiters| {
+                                (
+                                    waiters.queued(Lane::Interactive),
+                                    waiters.queued(Lane::Batch),
+                                )
+                            })
+                            .unwrap_or((0, 0));
+                        let permits_info = PermitsInfo {
+                            configured: permits.configured.saturating_sub(permits.shrink),
+                            available: sm.available_permits(),
+                            queued: queued + batch_queued,
+                            batch_queued,
+                            batch_in_flight: permits.batch_held,
+                            rejections: permits.rejections,
+                            recent_rejections: permits.recent_rejections.len(),
+                            shed: permits.shed,
+                            batch_shed: permits.batch_shed,
+                        };
+                        info.insert(*port, permits_info);
+                    }
+                    if tx_info.send(info).is_err() {
+                        event!(Level::ERROR, "Error send info of permits");
                     }
                 }
             }
         }
-    });
+    })
+}
+
+// Waiters of the port, interactive requests get permits before batch ones
+#[derive(Debug, Default)]
+struct Waiters {
+    interactive: VecDeque<oneshot::Sender<Result<PermitGuard, QueueFull>>>,
+    batch: VecDeque<oneshot::Sender<Result<PermitGuard, QueueFull>>>,
+}
+
+impl Waiters {
+    fn queued(&self, lane: Lane) -> usize {
+        let deque = match lane {
+            Lane::Interactive => &self.interactive,
+            Lane::Batch => &self.batch,
+        };
+        deque.iter().filter(|sender| !sender.is_closed()).count()
+    }
+}
+
+/*
+    Hand out available permits to the waiters of the port in FIFO order of each lane:
+    interactive waiters first, then batch waiters within the quota of the batch lane
+*/
+fn dispatch_waiters(
+    port: u16,
+    semaphore: &Arc<Semaphore>,
+    waiters: &mut Waiters,
+    permits: &mut PortPermits,
+    batch_share: f64,
+    tx: &flume::Sender<MessageSemaphore>,
+) {
+    let interactive = &mut waiters.interactive;
+    dispatch_lane(
+        port,
+        Lane::Interactive,
+        semaphore,
+        interactive,
+        permits,
+        batch_share,
+        tx,
+    );
+    if interactive.is_empty() {
+        let batch = &mut waiters.batch;
+        dispatch_lane(
+            port,
+            Lane::Batch,
+            semaphore,
+            batch,
+            permits,
+            batch_share,
+            tx,
+        );
+    }
+}
 
-    (jh_wait_permits, jh_permits_maintenance)
+fn dispatch_lane(
+    port: u16,
+    lane: Lane,
+    semaphore: &Arc<Semaphore>,
+    deque: &mut VecDeque<oneshot::Sender<Result<PermitGuard, QueueFull>>>,
+    permits: &mut PortPermits,
+    batch_share: f64,
+    tx: &flume::Sender<MessageSemaphore>,
+) {
+    while let Some(sender) = deque.pop_front() {
+        // request is cancelled by the client (MapLibre) while waiting
+        if sender.is_closed() {
+            permits.reject();
+            continue;
+        }
+        if lane == Lane::Batch && permits.batch_held >= permits.batch_quota(batch_share) {
+            deque.push_front(sender);
+            break;
+        }
+        let permit = match semaphore.clone().try_acquire_owned() {
+            Ok(p) => p,
+            Err(_) => {
+                permits.saturated = true;
+                deque.push_front(sender);
+                break;
+            }
+        };
+        if lane == Lane::Batch {
+            permits.batch_held += 1;
+        }
+        /*
+            Send error occurs when the receiver is removed after the check above.
+            The returned guard is dropped and the permit goes to the next waiter
+        */
+        if let Err(guard) = sender.send(Ok(PermitGuard::new(port, lane, permit, tx.clone()))) {
+            drop(guard);
+        }
+    }
 }
@@ -0,0 +1,203 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use serde::Serialize;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tracing::{event, Level};
+
+use crate::defaults::LATENCY_BUCKETS_SECS;
+
+// Tile requests of the DataSource: served from the cache (hits) or by workers (misses)
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct TileCounters {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+// Tiles of the DataSource evicted from the cache over the quota
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct EvictionCounters {
+    pub tiles: u64,
+    pub bytes: u64,
+}
+
+/*
+    Latency of requests of the endpoint: counts per bucket of 'LATENCY_BUCKETS_SECS'
+    (last one is '+Inf'), percentiles are estimated from buckets
+*/
+#[derive(Debug, Clone)]
+pub struct LatencyHistogram {
+    pub buckets: Vec<u64>,
+    pub count: u64,
+    pub sum_secs: f64,
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        LatencyHistogram {
+            buckets: vec![0; LATENCY_BUCKETS_SECS.len() + 1],
+            count: 0,
+            sum_secs: 0.0,
+        }
+    }
+}
+
+impl LatencyHistogram {
+    pub fn observe(&mut self, elapsed: Duration) {
+        let secs = elapsed.as_secs_f64();
+        let index = LATENCY_BUCKETS_SECS
+            .iter()
+            .position(|bound| secs <= *bound)
+            .unwrap_or(LATENCY_BUCKETS_SECS.len());
+        self.buckets[index] += 1;
+        self.count += 1;
+        self.sum_secs += secs;
+    }
+
+    // Linear interpolation inside the bucket of the quantile, '+Inf' gives the last bound
+    pub fn percentile(&self, q: f64) -> f64 {
+        if self.count == 0 {
+            return 0.0;
+        }
+        let rank = q * self.count as f64;
+        let mut cumulative = 0;
+        for (i, n) in self.buckets.iter().enumerate() {
+            if *n > 0 && (cumulative + n) as f64 >= rank {
+                let Some(upper) = LATENCY_BUCKETS_SECS.get(i) else {
+                    break;
+                };
+                let lower = if i == 0 {
+                    0.0
+                } else {
+                    LATENCY_BUCKETS_SECS[i - 1]
+                };
+                let position = (rank - cumulative as f64) / *n as f64;
+                return lower + (upper - lower) * position;
+            }
+            cumulative += n;
+        }
+        LATENCY_BUCKETS_SECS[LATENCY_BUCKETS_SECS.len() - 1]
+    }
+}
+
+// Timings of the tile request served by the worker, attached to the response as extension
+#[derive(Debug, Clone)]
+pub struct WorkerTimings {
+    pub datasource_id: String,
+    pub port: u16,
+    pub permit_wait: Duration,
+    pub upstream: Duration,
+}
+
+pub enum MessageStats {
+    Hit {
+        datasource_id: String,
+    },
+    Miss {
+        datasource_id: String,
+    },
+    GetCounters {
+        datasource_id: String,
+        tx_counters: oneshot::Sender<TileCounters>,
+    },
+    Evicted {
+        datasource_id: String,
+        evicted: EvictionCounters,
+    },
+    GetEvictions {
+        datasource_id: String,
+        tx_evictions: oneshot::Sender<EvictionCounters>,
+    },
+    Latency {
+        endpoint: &'static str,
+        elapsed: Duration,
+    },
+    GetLatencies {
+        tx_latencies: oneshot::Sender<Vec<(String, LatencyHistogram)>>,
+    },
+}
+
+/*
+    Counters of tile requests and evicted tiles per DataSource since the start of the server,
+    reported by 'GET /api/datasources/{id}/stats', and latency of requests
+    per endpoint reported by 'GET /metrics'
+*/
+pub fn stats_maintenance(rx: flume::Receiver<MessageStats>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut counters: HashMap<String, TileCounters> = HashMap::new();
+        let mut latencies: HashMap<&'static str, LatencyHistogram> = HashMap::new();
+        let mut evictions: HashMap<String, EvictionCounters> = HashMap::new();
+
+        while let Ok(message) = rx.recv_async().await {
+            match message {
+                MessageStats::Hit { datasource_id } => {
+                    counters.entry(datasource_id).or_default().hits += 1;
+                }
+                MessageStats::Miss { datasource_id } => {
+                    counters.entry(datasource_id).or_default().misses += 1;
+                }
+                MessageStats::GetCounters {
+                    datasource_id,
+                    tx_counters,
+                } => {
+                    let c = counters.get(&datasource_id).copied().unwrap_or_default();
+                    if tx_counters.send(c).is_err() {
+                        event!(
+                            Level::ERROR,
+                            "Error send tile counters of '{datasource_id}'"
+                        );
+                    }
+                }
+                MessageStats::Evicted {
+                    datasource_id,
+                    evicted,
+                } => {
+                    let e = evictions.entry(datasource_id).or_default();
+                    e.tiles += evicted.tiles;
+                    e.bytes += evicted.bytes;
+                }
+                MessageStats::GetEvictions {
+                    datasource_id,
+                    tx_evictions,
+                } => {
+                    let e = evictions.get(&datasource_id).copied().unwrap_or_default();
+                    if tx_evictions.send(e).is_err() {
+                        event!(Level::ERROR, "Error send evictions of '{datasource_id}'");
+                    }
+                }
+                MessageStats::Latency { endpoint, elapsed } => {
+                    latencies.entry(endpoint).or_default().observe(elapsed);
+                }
+                MessageStats::GetLatencies { tx_latencies } => {
+                    let mut l: Vec<(String, LatencyHistogram)> = latencies
+                        .iter()
+                        .map(|(endpoint, h)| (endpoint.to_string(), h.clone()))
+                        .collect();
+                    l.sort_by(|a, b| a.0.cmp(&b.0));
+                    if tx_latencies.send(l).is_err() {
+                        event!(Level::ERROR, "Error send latencies of endpoints");
+                    }
+                }
+            }
+        }
+    })
+}
+
+pub fn record_tile(tx: &flume::Sender<MessageStats>, datasource_id: &str, hit: bool) {
+    let datasource_id = datasource_id.to_string();
+    let message = if hit {
+        MessageStats::Hit { datasource_id }
+    } else {
+        MessageStats::Miss { datasource_id }
+    };
+    if let Err(err) = tx.send(message) {
+        event!(Level::ERROR, "Error send tile stats: {err}");
+    }
+}
+
+pub fn record_latency(tx: &flume::Sender<MessageStats>, endpoint: &'static str, elapsed: Duration) {
+    if let Err(err) = tx.send(MessageStats::Latency { endpoint, elapsed }) {
+        event!(Level::ERROR, "Error send latency of request: {err}");
+    }
+}
@@ -0,0 +1,92 @@
+use std::path::Path;
+use std::time::Duration;
+
+use serde::Serialize;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tracing::{event, Level};
+
+use crate::paths::Paths;
+
+// Space of the volume of the folder 'tiles'
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct StorageInfo {
+    pub total_bytes: u64,
+    pub available_bytes: u64,
+    pub min_free_bytes: u64,
+}
+
+impl StorageInfo {
+    // Pyramids are refused below the threshold, 0 disables the threshold
+    pub fn is_low(&self) -> bool {
+        self.min_free_bytes > 0
+            && self.total_bytes > 0
+            && self.available_bytes < self.min_free_bytes
+    }
+}
+
+// Disk with the longest mount point containing the path
+fn volume_space(path: &Path) -> Option<(u64, u64)> {
+    let path = path.canonicalize().ok()?;
+    let disks = sysinfo::Disks::new_with_refreshed_list();
+    disks
+        .list()
+        .iter()
+        .filter(|disk| path.starts_with(disk.mount_point()))
+        .max_by_key(|disk| disk.mount_point().as_os_str().len())
+        .map(|disk| (disk.total_space(), disk.available_space()))
+}
+
+/*
+    Monitor of the free space of the tiles volume: SQLite writes of pyramids fail halfway
+    when the disk is full, so new pyramids are refused while the space is below 'min_free_mb'
+*/
+pub fn storage_maintenance(
+    paths: Paths,
+    tx_storage: watch::Sender<StorageInfo>,
+    interval_secs: u64,
+    min_free_mb: u64,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let tiles_dir = paths.cache.clone();
+        let mut interval = tokio::time::interval(Duration::from_secs(interval_secs.max(1)));
+        loop {
+            interval.tick().await;
+            let dir = tiles_dir.clone();
+            let space = match tokio::task::spawn_blocking(move || volume_space(&dir)).await {
+                Ok(Some(space)) => space,
+                Ok(None) => {
+                    event!(Level::ERROR, "Volume of the folder {tiles_dir:?} not found");
+                    continue;
+                }
+                Err(err) => {
+                    event!(Level::ERROR, "Error get space of the volume: {err}");
+                    continue;
+                }
+            };
+
+            let info = StorageInfo {
+                total_bytes: space.0,
+                available_bytes: space.1,
+                min_free_bytes: min_free_mb * 1024 * 1024,
+            };
+            let was_low = tx_storage.borrow().is_low();
+            if info.is_low() && !was_low {
+                event!(
+                    Level::WARN,
+                    "Free space of the tiles volume {} bytes is below {min_free_mb} MB, pyramids are refused",
+                    info.available_bytes
+                );
+            } else if !info.is_low() && was_low {
+                event!(
+                    Level::INFO,
+                    "Free space of the tiles volume {} bytes, pyramids are accepted",
+                    info.available_bytes
+                );
+            }
+            if tx_storage.send(info).is_err() {
+                break;
+            }
+        }
+    })
+}
@@ -0,0 +1,182 @@
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use tokio::process::Child;
+use tokio::task::JoinHandle;
+use tracing::{event, Level};
+
+use crate::defaults::WORKER_STABLE_SECS;
+
+pub enum MessageSupervisor {
+    Exited { port: u16, pid: u32, status: String },
+    Started { port: u16, pid: u32, ready: bool },
+    Restart { port: u16 },
+}
+
+// Worker process waited for exit in its own task
+pub struct WorkerProcess {
+    pub pid: u32,
+    // index of the slot of settings ('worker.slots')
+    pub index: usize,
+    // worker passed the startup probe and was added to rotation
+    ready_at: Option<Instant>,
+    jh: JoinHandle<()>,
+    startup: Option<JoinHandle<()>>,
+}
+
+impl WorkerProcess {
+    // Probe of the readiness of the new worker, stopped with the worker
+    pub fn probe_startup(&mut self, jh: JoinHandle<()>) {
+        self.startup = Some(jh);
+    }
+
+    pub fn set_ready(&mut self) {
+        self.ready_at = Some(Instant::now());
+    }
+}
+
+// Aborted task drops the child, 'kill_on_drop' kills the worker like before supervision
+impl Drop for WorkerProcess {
+    fn drop(&mut self) {
+        self.jh.abort();
+        if let Some(startup) = self.startup.as_ref() {
+            startup.abort();
+        }
+    }
+}
+
+pub fn supervise(
+    mut child: Child,
+    port: u16,
+    index: usize,
+    tx: flume::Sender<MessageSupervisor>,
+) -> WorkerProcess {
+    let pid = child.id().unwrap_or_default();
+    let jh = tokio::spawn(async move {
+        let status = match child.wait().await {
+            Ok(status) => status.to_string(),
+            Err(err) => format!("unknown status ({err})"),
+        };
+        let _ = tx
+            .send_async(MessageSupervisor::Exited { port, pid, status })
+            .await;
+    });
+    WorkerProcess {
+        pid,
+        index,
+        ready_at: None,
+        jh,
+        startup: None,
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WorkerRestartInfo {
+    pub crashes: u64,
+    pub consecutive_crashes: u32,
+    pub last_status: String,
+    pub last_crash_seconds_ago: u64,
+    pub restart_pending: bool,
+}
+
+#[derive(Debug)]
+struct WorkerCrashes {
+    crashes: u64,
+    consecutive: u32,
+    last_status: String,
+    last_crash: Instant,
+}
+
+/*
+    Crashes of worker ports and delays of restarts. The delay doubles with every consecutive crash
+    from 'backoff' up to 'max_backoff', a worker serving 'WORKER_STABLE_SECS' resets the sequence
+*/
+#[derive(Debug)]
+pub struct WorkerRestarts {
+    enabled: bool,
+    backoff: Duration,
+    max_backoff: Duration,
+    crashes: HashMap<u16, WorkerCrashes>,
+    pending: HashSet<u16>,
+}
+
+impl WorkerRestarts {
+    pub fn new(enabled: bool, backoff_secs: u64, max_backoff_secs: u64) -> Self {
+        WorkerRestarts {
+            enabled,
+            backoff: Duration::from_secs(backoff_secs),
+            max_backoff: Duration::from_secs(max_backoff_secs),
+            crashes: HashMap::new(),
+            pending: HashSet::new(),
+        }
+    }
+
+    // Delay of the restart of the crashed worker, None - workers are not restarted
+    pub fn record_crash(
+        &mut self,
+        port: u16,
+        status: &str,
+        worker: &WorkerProcess,
+    ) -> Option<Duration> {
+        let crashes = self.crashes.entry(port).or_insert_with(|| WorkerCrashes {
+            crashes: 0,
+            consecutive: 0,
+            last_status: String::new(),
+            last_crash: Instant::now(),
+        });
+        let stable = Duration::from_secs(WORKER_STABLE_SECS);
+        if worker
+            .ready_at
+            .is_some_and(|ready_at| ready_at.elapsed() >= stable)
+        {
+            crashes.consecutive = 0;
+        }
+        crashes.crashes += 1;
+        crashes.consecutive += 1;
+        crashes.last_status = status.to_string();
+        crashes.last_crash = Instant::now();
+
+        if !self.enabled {
+            return None;
+        }
+        self.pending.insert(port);
+        let factor = 2u32.saturating_pow(crashes.consecutive - 1);
+        Some(self.backoff.saturating_mul(factor).min(self.max_backoff))
+    }
+
+    // Restart is still expected: the port was not cancelled by reload or termination
+    pub fn take_pending(&mut self, port: u16) -> bool {
+        self.pending.remove(&port)
+    }
+
+    // Workers are reloaded or terminated, scheduled restarts are dropped
+    pub fn cancel_pending(&mut self) {
+        if !self.pending.is_empty() {
+            event!(
+                Level::INFO,
+                "Cancelled restarts of workers on ports {:?}",
+                self.pending
+            );
+            self.pending.clear();
+        }
+    }
+
+    pub fn info(&self) -> HashMap<u16, WorkerRestartInfo> {
+        self.crashes
+            .iter()
+            .map(|(port, c)| {
+                (
+                    *port,
+                    WorkerRestartInfo {
+                        crashes: c.crashes,
+                        consecutive_crashes: c.consecutive,
+                        last_status: c.last_status.clone(),
+                        last_crash_seconds_ago: c.last_crash.elapsed().as_secs(),
+                        restart_pending: self.pending.contains(port),
+                    },
+                )
+            })
+            .collect()
+    }
+}
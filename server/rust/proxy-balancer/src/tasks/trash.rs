@@ -0,0 +1,239 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use hyper::{client::HttpConnector, Body, Client, Method, Request};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tracing::{event, Level};
+
+use super::datasources::MessageDatasource;
+use super::reload_workers::MessageMaintenanceWorkers;
+use super::usage::MessageUsage;
+use crate::defaults::LOCALHOST;
+use crate::handles::helpers::get_worker_data;
+use crate::hyper_reverse_proxy;
+use crate::paths::Paths;
+use crate::structs::EndPoints;
+
+/*
+    Soft-deleted DataSource: the cache '{cache}/{id}' is moved to '{trash}/{id}',
+    the entry is saved next to it in '{trash}/{id}.json'
+*/
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrashEntry {
+    pub datasource_id: String,
+    pub deleted_at: chrono::DateTime<chrono::Utc>,
+    // None keeps the DataSource in the trash until it is restored
+    pub expires_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl TrashEntry {
+    pub fn is_expired(&self) -> bool {
+        matches!(self.expires_at, Some(expires_at) if expires_at < chrono::Utc::now())
+    }
+}
+
+fn trash_entry_path(paths: &Paths, datasource_id: &str) -> PathBuf {
+    paths.trash.join(format!("{datasource_id}.json"))
+}
+
+// Entries of the trash survive restarts of the server
+pub async fn load_trash(paths: &Paths) -> HashMap<String, TrashEntry> {
+    let mut trash = HashMap::new();
+    let Ok(mut entries) = tokio::fs::read_dir(&paths.trash).await else {
+        return trash;
+    };
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let path = entry.path();
+        if path.extension().is_none_or(|ext| ext != "json") {
+            continue;
+        }
+        match tokio::fs::read(&path)
+            .await
+            .map_err(|err| anyhow!(err))
+            .and_then(|data| {
+                serde_json::from_slice::<TrashEntry>(&data).map_err(|err| anyhow!(err))
+            }) {
+            Ok(t) => {
+                trash.insert(t.datasource_id.clone(), t);
+            }
+            Err(err) => {
+                event!(
+                    Level::ERROR,
+                    "Error read entry of the trash {path:?}: {err}"
+                );
+            }
+        }
+    }
+    trash
+}
+
+pub async fn move_to_trash(
+    paths: &Paths,
+    datasource_id: &str,
+    retention_days: u64,
+) -> Result<TrashEntry, anyhow::Error> {
+    tokio::fs::create_dir_all(&paths.trash).await?;
+    let tiles_dir = paths.tiles_dir(datasource_id);
+    if tokio::fs::try_exists(&tiles_dir).await.unwrap_or(false) {
+        tokio::fs::rename(&tiles_dir, paths.trash.join(datasource_id)).await?;
+    }
+
+    let deleted_at = chrono::Utc::now();
+    let entry = TrashEntry {
+        datasource_id: datasource_id.to_string(),
+        deleted_at,
+        // DataSource is kept forever when the retention is out of the range of dates
+        expires_at: (retention_days > 0)
+            .then_some(retention_days)
+            .and_then(|days| i64::try_from(days).ok())
+            .and_then(chrono::TimeDelta::try_days)
+            .and_then(|retention| deleted_at.checked_add_signed(retention)),
+    };
+    tokio::fs::write(
+        trash_entry_path(paths, datasource_id),
+        serde_json::to_vec(&entry)?,
+    )
+    .await?;
+    Ok(entry)
+}
+
+pub async fn restore_from_trash(paths: &Paths, datasource_id: &str) -> Result<(), anyhow::Error> {
+    let trashed = paths.trash.join(datasource_id);
+    if tokio::fs::try_exists(&trashed).await.unwrap_or(false) {
+        let tiles_dir = paths.tiles_dir(datasource_id);
+        // the empty folder could be created by requests during the deletion
+        if tokio::fs::try_exists(&tiles_dir).await.unwrap_or(false) {
+            tokio::fs::remove_dir(&tiles_dir).await?;
+        }
+        tokio::fs::rename(&trashed, &tiles_dir).await?;
+    }
+    tokio::fs::remove_file(trash_entry_path(paths, datasource_id)).await?;
+    Ok(())
+}
+
+pub async fn purge_from_trash(paths: &Paths, datasource_id: &str) {
+    let trashed = paths.trash.join(datasource_id);
+    match tokio::fs::remove_dir_all(&trashed).await {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+            event!(
+                Level::ERROR,
+                "Error remove cache {trashed:?} from the trash: {err}"
+            );
+        }
+        _ => {}
+    }
+    if let Err(err) = tokio::fs::remove_file(trash_entry_path(paths, datasource_id)).await {
+        event!(
+            Level::ERROR,
+            "Error remove entry of DataSource '{datasource_id}' from the trash: {err}"
+        );
+    }
+}
+
+// DataSource is removed from the database and from the state of every worker
+async fn delete_on_workers(
+    client: &Client<HttpConnector>,
+    ports: &[u16],
+    datasource_id: &str,
+) -> Result<(), anyhow::Error> {
+    let body = json!({ "datasource_id": datasource_id }).to_string();
+    for port in ports {
+        let request = Request::builder()
+            .method(Method::DELETE)
+            .uri(EndPoints::DataSources.as_ref())
+            .header(hyper::header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.clone()))?;
+        let response = hyper_reverse_proxy::call(
+            IpAddr::from([127, 0, 0, 1]),
+            &format!("http://{}:{}", LOCALHOST, port),
+            request,
+            client,
+        )
+        .await
+        .map_err(|err| anyhow!("{err:?}"))?;
+        if !response.status().is_success() {
+            return Err(anyhow!(
+                "worker port {port} responded {}",
+                response.status()
+            ));
+        }
+    }
+    Ok(())
+}
+
+/*
+    DataSources in the trash longer than the retention period are deleted
+    as by the request DELETE '/api/datasources'
+*/
+pub fn trash_maintenance(
+    paths: Paths,
+    tx: flume::Sender<MessageDatasource>,
+    tx_mw: flume::Sender<MessageMaintenanceWorkers>,
+    tx_usage: flume::Sender<MessageUsage>,
+    interval_secs: u64,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(interval_secs.max(1)));
+        loop {
+            interval.tick().await;
+
+            let (tx_ids, rx_ids) = oneshot::channel();
+            if tx
+                .send_async(MessageDatasource::GetExpiredTrash { tx_ids })
+                .await
+                .is_err()
+            {
+                break;
+            }
+            let ids = rx_ids.await.unwrap_or_default();
+            if ids.is_empty() {
+                continue;
+            }
+
+            let wd = match get_worker_data(tx_mw.clone()).await {
+                Ok(wd) => wd,
+                Err(err) => {
+                    event!(Level::ERROR, "Error purge the trash: {err}");
+                    continue;
+                }
+            };
+            for datasource_id in ids {
+                if let Err(err) = delete_on_workers(&wd.client, &wd.ports, &datasource_id).await {
+                    event!(
+                        Level::ERROR,
+                        "Error delete DataSource '{datasource_id}' from the trash: {err}"
+                    );
+                    continue;
+                }
+                purge_from_trash(&paths, &datasource_id).await;
+                event!(
+                    Level::INFO,
+                    "DataSource '{datasource_id}' deleted from the trash after the retention period"
+                );
+                if let Err(err) = tx_usage
+                    .send_async(MessageUsage::Remove {
+                        datasource_id: datasource_id.clone(),
+                    })
+                    .await
+                {
+                    event!(Level::ERROR, "Error send message to remove usage {err:?}");
+                }
+                if let Err(err) = tx
+                    .send_async(MessageDatasource::PurgeTrash { datasource_id })
+                    .await
+                {
+                    event!(
+                        Level::ERROR,
+                        "Error send message to purge the trash {err:?}"
+                    );
+                }
+            }
+        }
+    })
+}
@@ -0,0 +1,233 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tracing::{event, Level};
+
+use crate::defaults::UPLOAD_CLEANUP_SECS;
+use crate::paths::Paths;
+
+#[derive(Debug, Clone)]
+pub struct Upload {
+    pub upload_id: String,
+    pub datasource_id: String,
+    pub ds_type: String,
+    pub name: String,
+    pub length: u64,
+    pub offset: u64,
+    pub sha256: Option<String>,
+    // pyramid of tiles is scheduled after the upload is completed
+    pub pyramid: bool,
+    pub part: PathBuf,
+    pub path: PathBuf,
+    pub expires_at: chrono::DateTime<chrono::Utc>,
+}
+
+impl Upload {
+    pub fn is_expired(&self) -> bool {
+        self.expires_at < chrono::Utc::now()
+    }
+}
+
+pub enum MessageUploads {
+    Save {
+        upload: Upload,
+    },
+    GetUpload {
+        upload_id: String,
+        tx_upload: oneshot::Sender<Option<Upload>>,
+    },
+    // Upload is locked by one PATCH request at a time, the lock is released by Save, Remove or Release
+    Acquire {
+        upload_id: String,
+        tx_upload: oneshot::Sender<Result<Upload, UploadBusy>>,
+    },
+    Release {
+        upload_id: String,
+    },
+    Remove {
+        upload_id: String,
+    },
+}
+
+/*
+    Lock of the upload held by the request. The upload is saved or removed through the lock,
+    the lock of the request dropped before (broken connection, timeout) is released without changes
+*/
+pub struct UploadLock {
+    upload_id: String,
+    tx_uploads: flume::Sender<MessageUploads>,
+    released: bool,
+}
+
+impl UploadLock {
+    pub fn new(upload_id: &str, tx_uploads: &flume::Sender<MessageUploads>) -> Self {
+        UploadLock {
+            upload_id: upload_id.to_string(),
+            tx_uploads: tx_uploads.clone(),
+            released: false,
+        }
+    }
+
+    pub async fn save(mut self, upload: Upload) -> Result<(), anyhow::Error> {
+        self.released = true;
+        self.tx_uploads
+            .send_async(MessageUploads::Save { upload })
+            .await?;
+        Ok(())
+    }
+
+    pub async fn remove(mut self) -> Result<(), anyhow::Error> {
+        self.released = true;
+        self.tx_uploads
+            .send_async(MessageUploads::Remove {
+                upload_id: self.upload_id.clone(),
+            })
+            .await?;
+        Ok(())
+    }
+}
+
+impl Drop for UploadLock {
+    fn drop(&mut self) {
+        if self.released {
+            return;
+        }
+        let release = MessageUploads::Release {
+            upload_id: self.upload_id.clone(),
+        };
+        if self.tx_uploads.send(release).is_err() {
+            event!(
+                Level::ERROR,
+                "Error release lock of upload '{}'",
+                self.upload_id
+            );
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum UploadBusy {
+    NotFound,
+    Locked,
+}
+
+async fn remove_part(upload: &Upload) {
+    match tokio::fs::remove_file(&upload.part).await {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+            event!(
+                Level::ERROR,
+                "Error remove part of upload {:?}: {err}",
+                upload.part
+            );
+        }
+        _ => {}
+    }
+}
+
+/*
+    Registry of resumable uploads of DataSource files. Upload is extended by every PATCH
+    request, uploads without requests for 'upload.expire_seconds' are removed with their '.part' files
+*/
+pub fn uploads_maintenance(
+    rx: flume::Receiver<MessageUploads>,
+    expire_seconds: u64,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut uploads: HashMap<String, Upload> = HashMap::new();
+        let mut locked: Vec<String> = Vec::new();
+        let mut cleanup = tokio::time::interval(Duration::from_secs(UPLOAD_CLEANUP_SECS));
+
+        loop {
+            let message = tokio::select! {
+                message = rx.recv_async() => match message {
+                    Ok(m) => m,
+                    Err(_) => break,
+                },
+                _ = cleanup.tick() => {
+                    let expired: Vec<String> = uploads
+                        .values()
+                        .filter(|u| u.is_expired() && !locked.contains(&u.upload_id))
+                        .map(|u| u.upload_id.clone())
+                        .collect();
+                    for upload_id in expired {
+                        if let Some(upload) = uploads.remove(&upload_id) {
+                            event!(
+                                Level::INFO,
+                                "Upload '{upload_id}' of DataSource '{}' expired",
+                                upload.datasource_id
+                            );
+                            remove_part(&upload).await;
+                        }
+                    }
+                    continue;
+                }
+            };
+
+            match message {
+                MessageUploads::Save { mut upload } => {
+                    locked.retain(|id| id != &upload.upload_id);
+                    upload.expires_at = i64::try_from(expire_seconds)
+                        .ok()
+                        .and_then(chrono::TimeDelta::try_seconds)
+                        .and_then(|expire| chrono::Utc::now().checked_add_signed(expire))
+                        .unwrap_or(chrono::DateTime::<chrono::Utc>::MAX_UTC);
+                    uploads.insert(upload.upload_id.clone(), upload);
+                }
+                MessageUploads::GetUpload {
+                    upload_id,
+                    tx_upload,
+                } => {
+                    if tx_upload.send(uploads.get(&upload_id).cloned()).is_err() {
+                        event!(Level::ERROR, "Error send upload '{upload_id}'");
+                    }
+                }
+                MessageUploads::Acquire {
+                    upload_id,
+                    tx_upload,
+                } => {
+                    let result = match uploads.get(&upload_id) {
+                        None => Err(UploadBusy::NotFound),
+                        Some(_) if locked.contains(&upload_id) => Err(UploadBusy::Locked),
+                        Some(upload) => {
+                            locked.push(upload_id.clone());
+                            Ok(upload.clone())
+                        }
+                    };
+                    if tx_upload.send(result).is_err() {
+                        // request is gone, the upload must not stay locked
+                        locked.retain(|id| id != &upload_id);
+                        event!(Level::ERROR, "Error send upload '{upload_id}'");
+                    }
+                }
+                MessageUploads::Release { upload_id } => {
+                    locked.retain(|id| id != &upload_id);
+                }
+                MessageUploads::Remove { upload_id } => {
+                    locked.retain(|id| id != &upload_id);
+                    uploads.remove(&upload_id);
+                }
+            }
+        }
+    })
+}
+
+// Uploads are kept in memory, '.part' files left by the previous run of the server are removed
+pub async fn remove_stale_parts(paths: &Paths) {
+    for ds_type in ["raster", "vector"] {
+        let ds_dir = paths.datasources_dir(ds_type);
+        let Ok(mut entries) = tokio::fs::read_dir(&ds_dir).await else {
+            continue;
+        };
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let path = entry.path();
+            if path.extension().is_some_and(|ext| ext == "part") {
+                if let Err(err) = tokio::fs::remove_file(&path).await {
+                    event!(Level::ERROR, "Error remove part of upload {path:?}: {err}");
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,657 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use anyhow::anyhow;
+use async_sqlite::{Client, ClientBuilder, JournalMode};
+use serde::Serialize;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tracing::{event, Level};
+
+use super::billing::{
+    billing_day, insert_counters, select_counters, select_totals, BillingCounter, BillingFilter,
+    BillingQuotas, BillingTotals, QuotaExceeded, TileServed, INDEX_BILLING_USAGE,
+    TABLE_BILLING_USAGE,
+};
+use crate::defaults::{USAGE_BUCKET_ZOOM, USAGE_RETENTION_CHECK_SECS};
+use crate::paths::Paths;
+
+const TABLE_TILE_USAGE: &str = "CREATE TABLE IF NOT EXISTS tile_usage (
+        datasource_id text NOT NULL,
+        tile_grid text NOT NULL,
+        zoom_level integer NOT NULL,
+        bucket_zoom integer NOT NULL,
+        bucket_x integer NOT NULL,
+        bucket_y integer NOT NULL,
+        hits integer NOT NULL,
+        PRIMARY KEY(datasource_id, tile_grid, zoom_level, bucket_zoom, bucket_x, bucket_y)
+    );";
+
+// Misses of the cache by tiles, 'last_miss' in seconds of UNIX time
+const TABLE_TILE_MISSES: &str = "CREATE TABLE IF NOT EXISTS tile_misses (
+        datasource_id text NOT NULL,
+        tile_grid text NOT NULL,
+        zoom_level integer NOT NULL,
+        tile_column integer NOT NULL,
+        tile_row integer NOT NULL,
+        ext text NOT NULL,
+        misses integer NOT NULL,
+        last_miss integer NOT NULL,
+        PRIMARY KEY(datasource_id, tile_grid, zoom_level, tile_column, tile_row, ext)
+    );";
+const INDEX_TILE_MISSES: &str =
+    "CREATE INDEX IF NOT EXISTS tile_misses_last_miss ON tile_misses (datasource_id, last_miss);";
+
+#[derive(Debug, Clone)]
+pub struct TileAccess {
+    pub datasource_id: String,
+    pub tile_grid: String,
+    pub z: u8,
+    pub x: u64,
+    pub y: u64,
+}
+
+impl TileAccess {
+    // Tiles are aggregated into their parent tile at zoom 'USAGE_BUCKET_ZOOM'
+    fn bucket(&self) -> (u8, u64, u64) {
+        let bucket_zoom = self.z.min(USAGE_BUCKET_ZOOM);
+        let shift = self.z - bucket_zoom;
+        (bucket_zoom, self.x >> shift, self.y >> shift)
+    }
+}
+
+// Parent tile with requests of its tiles at all zooms
+#[derive(Debug, Clone, Serialize)]
+pub struct UsageBucket {
+    pub tile_grid: String,
+    pub z: u8,
+    pub x: u64,
+    pub y: u64,
+    pub hits: u64,
+}
+
+// Tile missed by the cache: extension of the request and time of the miss
+#[derive(Debug, Clone)]
+pub struct TileMiss {
+    pub access: TileAccess,
+    pub ext: String,
+    pub timestamp: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MissedTile {
+    pub tile_grid: String,
+    pub z: u8,
+    pub x: u64,
+    pub y: u64,
+    pub ext: String,
+    pub misses: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DataSourceUsage {
+    pub zooms: BTreeMap<u8, u64>,
+    pub buckets: Vec<UsageBucket>,
+}
+
+pub enum MessageUsage {
+    Access {
+        access: TileAccess,
+    },
+    Miss {
+        access: TileAccess,
+        ext: String,
+    },
+    // the most requested buckets go first
+    GetUsage {
+        datasource_id: String,
+        limit: u64,
+        tx_usage: oneshot::Sender<Result<DataSourceUsage, anyhow::Error>>,
+    },
+    // tiles missed since 'since' (UNIX time), the most missed go first
+    GetMisses {
+        datasource_id: String,
+        since: i64,
+        limit: u64,
+        tx_misses: oneshot::Sender<Result<Vec<MissedTile>, anyhow::Error>>,
+    },
+    Remove {
+        datasource_id: String,
+    },
+    // tile served to the API key, counted only with billing enabled
+    Served {
+        served: TileServed,
+    },
+    // None - the API key has no quota or it is not exceeded
+    CheckQuota {
+        api_key: String,
+        tx_exceeded: oneshot::Sender<Option<QuotaExceeded>>,
+    },
+    // the latest days go first
+    GetBilling {
+        filter: BillingFilter,
+        tx_counters: oneshot::Sender<Result<Vec<BillingCounter>, anyhow::Error>>,
+    },
+}
+
+pub fn db_usage(paths: &Paths) -> PathBuf {
+    paths.data.join("usage.db")
+}
+
+async fn open_usage_db(paths: &Paths) -> Result<Client, anyhow::Error> {
+    let client = ClientBuilder::new()
+        .path(db_usage(paths))
+        .journal_mode(JournalMode::Wal)
+        .open()
+        .await
+        .map_err(|err| anyhow!(err))?;
+    client
+        .conn(|connection| {
+            connection.execute(TABLE_TILE_USAGE, ())?;
+            connection.execute(TABLE_TILE_MISSES, ())?;
+            connection.execute(INDEX_TILE_MISSES, ())?;
+            connection.execute(TABLE_BILLING_USAGE, ())?;
+            connection.execute(INDEX_BILLING_USAGE, ())
+        })
+        .await
+        .map_err(|err| anyhow!(err))?;
+    Ok(client)
+}
+
+/*
+    Ring buffers of accesses and misses, the oldest entries are dropped when a buffer is full.
+    Served tiles are summed up by (API key, DataSource, day) until the flush
+*/
+struct UsageBuffer {
+    accesses: VecDeque<TileAccess>,
+    misses: VecDeque<TileMiss>,
+    served: HashMap<(String, String, String), (u64, u64)>,
+    capacity: usize,
+}
+
+impl UsageBuffer {
+    fn new(capacity: usize) -> Self {
+        UsageBuffer {
+            accesses: VecDeque::with_capacity(capacity),
+            misses: VecDeque::new(),
+            served: HashMap::new(),
+            capacity,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.accesses.is_empty() && self.misses.is_empty() && self.served.is_empty()
+    }
+
+    fn is_full(&self) -> bool {
+        self.accesses.len() >= self.capacity || self.misses.len() >= self.capacity
+    }
+
+    fn push_access(&mut self, access: TileAccess) {
+        if self.accesses.len() >= self.capacity {
+            self.accesses.pop_front();
+        }
+        self.accesses.push_back(access);
+    }
+
+    fn push_miss(&mut self, miss: TileMiss) {
+        if self.misses.len() >= self.capacity {
+            self.misses.pop_front();
+        }
+        self.misses.push_back(miss);
+    }
+
+    fn push_served(&mut self, served: TileServed, day: String) {
+        let counter = self
+            .served
+            .entry((served.api_key, served.datasource_id, day))
+            .or_default();
+        counter.0 += 1;
+        counter.1 += served.bytes;
+    }
+
+    fn retain_datasource(&mut self, datasource_id: &str) {
+        self.accesses.retain(|a| a.datasource_id != datasource_id);
+        self.misses
+            .retain(|m| m.access.datasource_id != datasource_id);
+    }
+
+    // buffers are cleared only after a successful flush
+    async fn flush(&mut self, client: &Client) {
+        if self.is_empty() {
+            return;
+        }
+        match flush_usage(
+            client,
+            self.accesses.make_contiguous(),
+            self.misses.make_contiguous(),
+            &self.served,
+        )
+        .await
+        {
+            Ok(()) => {
+                self.accesses.clear();
+                self.misses.clear();
+                self.served.clear();
+            }
+            Err(err) => event!(Level::ERROR, "Error flush tile usage: {err}"),
+        }
+    }
+}
+
+// DataSource, tile grid, zoom level, column, row and extension of the missed tile
+type MissKey = (String, String, u8, u64, u64, String);
+
+/*
+    Accesses of the buffer are summed up by buckets and added to the counters of the table,
+    misses are summed up by tiles with the time of the last miss
+*/
+async fn flush_usage(
+    client: &Client,
+    accesses: &[TileAccess],
+    misses: &[TileMiss],
+    served: &HashMap<(String, String, String), (u64, u64)>,
+) -> Result<(), anyhow::Error> {
+    let mut counters: HashMap<(String, String, u8, u8, u64, u64), u64> = HashMap::new();
+    for access in accesses {
+        let (bucket_zoom, bucket_x, bucket_y) = access.bucket();
+        *counters
+            .entry((
+                access.datasource_id.clone(),
+                access.tile_grid.clone(),
+                access.z,
+                bucket_zoom,
+                bucket_x,
+                bucket_y,
+            ))
+            .or_default() += 1;
+    }
+    let mut missed: HashMap<MissKey, (u64, i64)> = HashMap::new();
+    for miss in misses {
+        let entry = missed
+            .entry((
+                miss.access.datasource_id.clone(),
+                miss.access.tile_grid.clone(),
+                miss.access.z,
+                miss.access.x,
+                miss.access.y,
+                miss.ext.clone(),
+            ))
+            .or_default();
+        entry.0 += 1;
+        entry.1 = entry.1.max(miss.timestamp);
+    }
+    let served = served.clone();
+
+    client
+        .conn(move |connection| {
+            let tx = connection.unchecked_transaction()?;
+            {
+                let mut stmt = tx.prepare(
+                    "INSERT INTO tile_usage (datasource_id, tile_grid, zoom_level, bucket_zoom, bucket_x, bucket_y, hits)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+                     ON CONFLICT (datasource_id, tile_grid, zoom_level, bucket_zoom, bucket_x, bucket_y)
+                     DO UPDATE SET hits = hits + excluded.hits;",
+                )?;
+                for ((datasource_id, tile_grid, z, bz, bx, by), hits) in counters {
+                    stmt.execute(rusqlite::params![datasource_id, tile_grid, z, bz, bx, by, hits])?;
+                }
+
+                let mut stmt = tx.prepare(
+                    "INSERT INTO tile_misses (datasource_id, tile_grid, zoom_level, tile_column, tile_row, ext, misses, last_miss)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+                     ON CONFLICT (datasource_id, tile_grid, zoom_level, tile_column, tile_row, ext)
+                     DO UPDATE SET misses = misses + excluded.misses, last_miss = MAX(last_miss, excluded.last_miss);",
+                )?;
+                for ((datasource_id, tile_grid, z, x, y, ext), (count, last_miss)) in missed {
+                    stmt.execute(rusqlite::params![
+                        datasource_id, tile_grid, z, x, y, ext, count, last_miss
+                    ])?;
+                }
+
+                insert_counters(&tx, &served)?;
+            }
+            tx.commit()
+        })
+        .await
+        .map_err(|err| anyhow!(err))
+}
+
+async fn select_usage(
+    client: &Client,
+    datasource_id: String,
+    limit: u64,
+) -> Result<DataSourceUsage, anyhow::Error> {
+    client
+        .conn(move |connection| {
+            let mut usage = DataSourceUsage::default();
+            let mut stmt = connection.prepare(
+                "SELECT zoom_level, SUM(hits) FROM tile_usage
+                 WHERE datasource_id = ?1 GROUP BY zoom_level;",
+            )?;
+            let mut rows = stmt.query([&datasource_id])?;
+            while let Some(row) = rows.next()? {
+                usage.zooms.insert(row.get(0)?, row.get(1)?);
+            }
+            drop(rows);
+            drop(stmt);
+
+            let mut stmt = connection.prepare(
+                "SELECT tile_grid, bucket_zoom, bucket_x, bucket_y, SUM(hits) AS total FROM tile_usage
+                 WHERE datasource_id = ?1
+                 GROUP BY tile_grid, bucket_zoom, bucket_x, bucket_y
+                 ORDER BY total DESC LIMIT ?2;",
+            )?;
+            let mut rows = stmt.query(rusqlite::params![datasource_id, limit])?;
+            while let Some(row) = rows.next()? {
+                usage.buckets.push(UsageBucket {
+                    tile_grid: row.get(0)?,
+                    z: row.get(1)?,
+                    x: row.get(2)?,
+                    y: row.get(3)?,
+                    hits: row.get(4)?,
+                });
+            }
+            Ok(usage)
+        })
+        .await
+        .map_err(|err| anyhow!(err))
+}
+
+async fn select_misses(
+    client: &Client,
+    datasource_id: String,
+    since: i64,
+    limit: u64,
+) -> Result<Vec<MissedTile>, anyhow::Error> {
+    client
+        .conn(move |connection| {
+            let mut tiles = Vec::new();
+            let mut stmt = connection.prepare(
+                "SELECT tile_grid, zoom_level, tile_column, tile_row, ext, misses FROM tile_misses
+                 WHERE datasource_id = ?1 AND last_miss >= ?2
+                 ORDER BY misses DESC, zoom_level LIMIT ?3;",
+            )?;
+            let mut rows = stmt.query(rusqlite::params![datasource_id, since, limit])?;
+            while let Some(row) = rows.next()? {
+                tiles.push(MissedTile {
+                    tile_grid: row.get(0)?,
+                    z: row.get(1)?,
+                    x: row.get(2)?,
+                    y: row.get(3)?,
+                    ext: row.get(4)?,
+                    misses: row.get(5)?,
+                });
+            }
+            Ok(tiles)
+        })
+        .await
+        .map_err(|err| anyhow!(err))
+}
+
+// Misses older than the retention period are not used by the smart pyramid
+async fn remove_old_misses(client: &Client, retention_days: u64) {
+    let before = chrono::Utc::now().timestamp() - (retention_days * 86400) as i64;
+    if let Err(err) = client
+        .conn(move |connection| {
+            connection.execute("DELETE FROM tile_misses WHERE last_miss < ?1;", [before])
+        })
+        .await
+    {
+        event!(Level::ERROR, "Error remove old misses of tiles: {err}");
+    }
+}
+
+/*
+    Statistics of tile requests: accesses and misses of the cache are collected in ring buffers
+    of 'buffer_size' and flushed to 'data/usage.db' every 'flush_secs' or when a buffer is full.
+    If the flush fails the oldest entries are dropped, requests of tiles are never blocked.
+    Totals of API keys with quotas are kept in memory, loaded from the table with the first check.
+    Totals of the least recently checked keys are dropped over 'billing.max_keys' keys
+*/
+pub fn usage_maintenance(
+    paths: Paths,
+    rx: flume::Receiver<MessageUsage>,
+    flush_secs: u64,
+    buffer_size: usize,
+    retention_days: u64,
+    quotas: BillingQuotas,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let client = match open_usage_db(&paths).await {
+            Ok(c) => Some(c),
+            Err(err) => {
+                event!(Level::ERROR, "Error open database of tile usage: {err}");
+                None
+            }
+        };
+        let mut buffer = UsageBuffer::new(buffer_size.max(1));
+        let mut totals: HashMap<String, (BillingTotals, Instant)> = HashMap::new();
+        let mut interval = tokio::time::interval(Duration::from_secs(flush_secs.max(1)));
+        let mut retention = tokio::time::interval(Duration::from_secs(USAGE_RETENTION_CHECK_SECS));
+
+        loop {
+            let message = tokio::select! {
+                message = rx.recv_async() => match message {
+                    Ok(m) => m,
+                    Err(_) => break,
+                },
+                _ = interval.tick(), if !buffer.is_empty() => {
+                    if let Some(client) = client.as_ref() {
+                        buffer.flush(client).await;
+                    }
+                    continue;
+                }
+                _ = retention.tick(), if retention_days > 0 => {
+                    if let Some(client) = client.as_ref() {
+                        remove_old_misses(client, retention_days).await;
+                    }
+                    continue;
+                }
+            };
+
+            match message {
+                MessageUsage::Access { access } => {
+                    if buffer.is_full() {
+                        if let Some(client) = client.as_ref() {
+                            buffer.flush(client).await;
+                        }
+                    }
+                    buffer.push_access(access);
+                }
+                MessageUsage::Miss { access, ext } => {
+                    if buffer.is_full() {
+                        if let Some(client) = client.as_ref() {
+                            buffer.flush(client).await;
+                        }
+                    }
+                    buffer.push_miss(TileMiss {
+                        access,
+                        ext,
+                        timestamp: chrono::Utc::now().timestamp(),
+                    });
+                }
+                MessageUsage::GetUsage {
+                    datasource_id,
+                    limit,
+                    tx_usage,
+                } => {
+                    let result = match client.as_ref() {
+                        Some(client) => {
+                            // statistics include accesses not flushed yet
+                            buffer.flush(client).await;
+                            select_usage(client, datasource_id.clone(), limit).await
+                        }
+                        None => Err(anyhow!("Database of tile usage is not available")),
+                    };
+                    if tx_usage.send(result).is_err() {
+                        event!(
+                            Level::ERROR,
+                            "Error send tile usage of DataSource '{datasource_id}'"
+                        );
+                    }
+                }
+                MessageUsage::GetMisses {
+                    datasource_id,
+                    since,
+                    limit,
+                    tx_misses,
+                } => {
+                    let result = match client.as_ref() {
+                        Some(client) => {
+                            buffer.flush(client).await;
+                            select_misses(client, datasource_id.clone(), since, limit).await
+                        }
+                        None => Err(anyhow!("Database of tile usage is not available")),
+                    };
+                    if tx_misses.send(result).is_err() {
+                        event!(
+                            Level::ERROR,
+                            "Error send missed tiles of DataSource '{datasource_id}'"
+                        );
+                    }
+                }
+                MessageUsage::Served { served } => {
+                    let today = chrono::Utc::now().date_naive();
+                    if let Some((key_totals, _)) = totals.get_mut(&served.api_key) {
+                        key_totals.add(today, served.bytes);
+                    }
+                    buffer.push_served(served, billing_day(today));
+                }
+                MessageUsage::CheckQuota {
+                    api_key,
+                    tx_exceeded,
+                } => {
+                    let today = chrono::Utc::now().date_naive();
+                    let exceeded = match quotas.quota(&api_key) {
+                        Some(quota) => {
+                            if !totals.contains_key(&api_key) {
+                                evict_totals(&mut totals, quotas.max_keys);
+                                // without the database tiles are counted since the start
+                                let key_totals = match client.as_ref() {
+                                    Some(client) => {
+                                        buffer.flush(client).await;
+                                        select_totals(client, api_key.clone(), today).await
+                                    }
+                                    None => Err(anyhow!("Database of tile usage is not available")),
+                                };
+                                let key_totals = key_totals.unwrap_or_else(|err| {
+                                    event!(
+                                        Level::ERROR,
+                                        "Error load totals of API key '{api_key}': {err}"
+                                    );
+                                    BillingTotals::new(today)
+                                });
+                                totals.insert(api_key.clone(), (key_totals, Instant::now()));
+                            }
+                            totals.get_mut(&api_key).and_then(|(key_totals, checked)| {
+                                *checked = Instant::now();
+                                key_totals.roll(today);
+                                quota.exceeded(key_totals)
+                            })
+                        }
+                        None => None,
+                    };
+                    if tx_exceeded.send(exceeded).is_err() {
+                        event!(Level::ERROR, "Error send quota of API key '{api_key}'");
+                    }
+                }
+                MessageUsage::GetBilling {
+                    filter,
+                    tx_counters,
+                } => {
+                    let result = match client.as_ref() {
+                        Some(client) => {
+                            buffer.flush(client).await;
+                            select_counters(client, filter).await
+                        }
+                        None => Err(anyhow!("Database of tile usage is not available")),
+                    };
+                    if tx_counters.send(result).is_err() {
+                        event!(Level::ERROR, "Error send billing counters");
+                    }
+                }
+                MessageUsage::Remove { datasource_id } => {
+                    buffer.retain_datasource(&datasource_id);
+                    if let Some(client) = client.as_ref() {
+                        let id = datasource_id.clone();
+                        if let Err(err) = client
+                            .conn(move |connection| {
+                                connection.execute(
+                                    "DELETE FROM tile_usage WHERE datasource_id = ?1;",
+                                    [&id],
+                                )?;
+                                connection.execute(
+                                    "DELETE FROM tile_misses WHERE datasource_id = ?1;",
+                                    [&id],
+                                )
+                            })
+                            .await
+                        {
+                            event!(
+                                Level::ERROR,
+                                "Error remove tile usage of DataSource '{datasource_id}': {err}"
+                            );
+                        }
+                    }
+                }
+            }
+        }
+
+        if let Some(client) = client {
+            buffer.flush(&client).await;
+            if let Err(err) = client.close().await {
+                event!(Level::ERROR, "Error close database of tile usage: {err}");
+            }
+        }
+    })
+}
+
+// Totals of the least recently checked keys are dropped, they are loaded from the table again
+fn evict_totals(totals: &mut HashMap<String, (BillingTotals, Instant)>, max_keys: usize) {
+    while totals.len() >= max_keys {
+        let Some(api_key) = totals
+            .iter()
+            .min_by_key(|(_, (_, checked))| *checked)
+            .map(|(api_key, _)| api_key.clone())
+        else {
+            break;
+        };
+        totals.remove(&api_key);
+    }
+}
+
+pub fn record_access(tx: &flume::Sender<MessageUsage>, access: TileAccess) {
+    if let Err(err) = tx.send(MessageUsage::Access { access }) {
+        event!(Level::ERROR, "Error send tile access: {err}");
+    }
+}
+
+pub fn record_miss(tx: &flume::Sender<MessageUsage>, access: TileAccess, ext: &str) {
+    let ext = ext.to_string();
+    if let Err(err) = tx.send(MessageUsage::Miss { access, ext }) {
+        event!(Level::ERROR, "Error send tile miss: {err}");
+    }
+}
+
+pub fn record_served(tx: &flume::Sender<MessageUsage>, served: TileServed) {
+    if let Err(err) = tx.send(MessageUsage::Served { served }) {
+        event!(Level::ERROR, "Error send served tile: {err}");
+    }
+}
+
+// None - the quota is not exceeded or it is unknown, tiles are not refused without the statistics
+pub async fn quota_exceeded(
+    tx: &flume::Sender<MessageUsage>,
+    api_key: &str,
+) -> Option<QuotaExceeded> {
+    let (tx_exceeded, rx_exceeded) = oneshot::channel();
+    tx.send_async(MessageUsage::CheckQuota {
+        api_key: api_key.to_string(),
+        tx_exceeded,
+    })
+    .await
+    .ok()?;
+    rx_exceeded.await.ok().flatten()
+}
@@ -1,38 +1,105 @@
 use std::collections::HashMap;
-use std::path::PathBuf;
 use std::process::Stdio;
+use std::time::{Duration, Instant};
 
-use hyper::{client::HttpConnector, Client};
+use anyhow::anyhow;
+use hyper::{client::HttpConnector, Body, Client, Method, Request, StatusCode};
 use serde::Serialize;
+use serde_json::json;
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
 use tokio::process::Command;
+use tokio::sync::watch;
 use tokio::task::JoinHandle;
 use tracing::{event, Level};
 
+use super::circuit_breaker::{CircuitBreakerInfo, CircuitBreakers};
+use super::events::{publish_event, EventKind, MessageEvents};
 use super::reload_workers::{MessageMaintenanceWorkers, WorkerData, WorkerState};
-use crate::config::Config;
+use super::supervisor::{
+    supervise, MessageSupervisor, WorkerProcess, WorkerRestartInfo, WorkerRestarts,
+};
+use crate::config::{Config, WorkerSlot};
 use crate::db::init_db;
-use crate::defaults::GRANIAN;
+use crate::defaults::{
+    GRANIAN, INTEGRITY_ENV, LOCALHOST, NATIVE_RENDERER_PORT, PROBE_TIMEOUT_SECS,
+    WORKER_READY_POLL_MS,
+};
+use crate::log::WORKER_LOG_TARGET;
+use crate::paths::Paths;
 use crate::utils::{get_available_port, try_save_process_pid};
 
+// Workers record checksums of written tiles in integrity mode
+fn integrity_env(config: &Config) -> &'static str {
+    if config.integrity {
+        "1"
+    } else {
+        "0"
+    }
+}
+
+// Worker pinned to CPUs of its slot is started by 'taskset', processes of Granian inherit it
+fn worker_command(program: &str, slot: Option<&WorkerSlot>) -> Command {
+    match slot.and_then(|slot| slot.cpu_list()) {
+        Some(cpus) => {
+            let mut command = Command::new("taskset");
+            command.arg("-c").arg(cpus).arg(program);
+            command
+        }
+        None => Command::new(program),
+    }
+}
+
+// Worker with the index gets the environment and the CPU affinity of its slot
 pub fn cmd_run_worker(
     config: &Config,
     vars: &HashMap<&'static str, String>,
     port: u16,
-) -> tokio::process::Child {
-    if config.type_server == "robyn" {
-        Command::new("python")
+    index: usize,
+) -> Result<tokio::process::Child, anyhow::Error> {
+    let slot = config.worker_slot(index);
+    let slot_env = slot.map(|slot| slot.env.clone()).unwrap_or_default();
+    let mut child = if let Some(launch) = config.worker_launch.as_ref() {
+        let mut command = worker_command(&launch.command, slot);
+        command
+            .envs(vars)
+            .env(INTEGRITY_ENV, integrity_env(config))
+            .envs(launch.env.iter().map(|(key, value)| (key, value)))
+            .envs(slot_env.iter().map(|(key, value)| (key, value)))
+            .args(launch.args(port, &config.log_level_worker, config.thread_workers))
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+        if let Some(workdir) = launch.workdir.as_ref() {
+            command.current_dir(workdir);
+        }
+        command.spawn().map_err(|err| {
+            anyhow!(
+                "Worker '{}' failed to start on port {}: {}",
+                launch.command,
+                port,
+                err
+            )
+        })?
+    } else if config.type_server == "robyn" {
+        worker_command("python", slot)
             .envs(vars)
+            .env(INTEGRITY_ENV, integrity_env(config))
+            .envs(slot_env.iter().map(|(key, value)| (key, value)))
             .arg("app_robyn.py")
             .arg(format!("--log-level={}", config.log_level_worker))
             .arg(format!("--workers={}", config.thread_workers))
             .arg(format!("--processes={}", 1))
             .arg(format!("--port={}", port))
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
             .kill_on_drop(true)
             .spawn()
-            .expect(&format!("Robyn worker failed to start on port {}", port))
+            .map_err(|err| anyhow!("Robyn worker failed to start on port {}: {}", port, err))?
     } else {
-        Command::new("granian")
+        worker_command("granian", slot)
             .envs(vars)
+            .env(INTEGRITY_ENV, integrity_env(config))
+            .envs(slot_env.iter().map(|(key, value)| (key, value)))
             .arg("app_granian:app")
             .arg(format!("--interface={}", config.interface))
             .arg(format!("--workers={}", 1))
@@ -42,17 +109,80 @@ pub fn cmd_run_worker(
             .arg(format!("--backlog={}", config.backlog))
             .arg(format!("--backpressure={}", config.backpressure))
             .arg(format!("--log-config={}", "log_config.json"))
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
             .kill_on_drop(true)
             .spawn()
-            .expect(&format!("Granian worker failed to start on port {}", port))
+            .map_err(|err| anyhow!("Granian worker failed to start on port {}: {}", port, err))?
+    };
+    capture_worker_output(&mut child, port);
+    Ok(child)
+}
+
+/*
+    Output of the worker goes to the log of the server line by line with the port and the PID.
+    Lines without a level of Python logging get INFO for stdout and ERROR for stderr (tracebacks)
+*/
+fn capture_worker_output(child: &mut tokio::process::Child, port: u16) {
+    let pid = child.id().unwrap_or_default();
+    if let Some(stdout) = child.stdout.take() {
+        tokio::spawn(log_worker_output(stdout, port, pid, Level::INFO));
     }
+    if let Some(stderr) = child.stderr.take() {
+        tokio::spawn(log_worker_output(stderr, port, pid, Level::ERROR));
+    }
+}
+
+// Pipe is read until the worker exits, otherwise the worker blocks on the full pipe
+async fn log_worker_output<R: AsyncRead + Unpin>(output: R, port: u16, pid: u32, level: Level) {
+    let mut reader = BufReader::new(output);
+    let mut buf = Vec::new();
+    loop {
+        buf.clear();
+        match reader.read_until(b'\n', &mut buf).await {
+            Ok(0) => break,
+            Ok(_) => {
+                let line = String::from_utf8_lossy(&buf);
+                let line = line.trim_end();
+                if line.is_empty() {
+                    continue;
+                }
+                let message = format!("Worker {port} (PID {pid}): {line}");
+                match output_level(line).unwrap_or(level) {
+                    Level::ERROR => event!(target: WORKER_LOG_TARGET, Level::ERROR, "{message}"),
+                    Level::WARN => event!(target: WORKER_LOG_TARGET, Level::WARN, "{message}"),
+                    Level::INFO => event!(target: WORKER_LOG_TARGET, Level::INFO, "{message}"),
+                    _ => event!(target: WORKER_LOG_TARGET, Level::DEBUG, "{message}"),
+                }
+            }
+            Err(err) => {
+                event!(
+                    Level::ERROR,
+                    "Error read output of worker {port} (PID {pid}): {err}"
+                );
+                break;
+            }
+        }
+    }
+}
+
+// Level of the line of Python logging: 'ERROR', 'WARNING', 'INFO' as a separate word
+fn output_level(line: &str) -> Option<Level> {
+    line.split(|c: char| !c.is_ascii_alphabetic())
+        .find_map(|word| match word {
+            "CRITICAL" | "ERROR" => Some(Level::ERROR),
+            "WARNING" | "WARN" => Some(Level::WARN),
+            "INFO" => Some(Level::INFO),
+            "DEBUG" => Some(Level::DEBUG),
+            _ => None,
+        })
 }
 
 pub async fn run_python_terminate_childs(
-    cwd: &str,
+    paths: &Paths,
     vars: HashMap<&'static str, String>,
 ) -> Result<(), anyhow::Error> {
-    let script: PathBuf = [cwd, "scripts", "terminate_childs.py"].iter().collect();
+    let script = paths.scripts.join("terminate_childs.py");
     let out = Command::new("python")
         .envs(vars)
         .arg(script)
@@ -69,10 +199,21 @@ pub async fn run_python_terminate_childs(
 pub struct SystemInfoWorkers {
     pub worker_childs: HashMap<u32, Vec<(u32, u32)>>,
     pub worker_memory: HashMap<u32, Vec<u64>>,
+    pub circuit_breakers: HashMap<u16, CircuitBreakerInfo>,
+    pub worker_restarts: HashMap<u16, WorkerRestartInfo>,
 }
 
-pub fn is_process_run(name: &'static str) -> JoinHandle<bool> {
+// Name of processes of workers waited for exit at reload
+fn worker_process_name(config: &Config) -> String {
+    match config.worker_launch.as_ref() {
+        Some(launch) => launch.process_name(),
+        None => GRANIAN.to_string(),
+    }
+}
+
+pub fn is_process_run(name: String) -> JoinHandle<bool> {
     tokio::task::spawn_blocking(move || {
+        let name: &str = &name;
         let sys = sysinfo::System::new_all();
         let mut is_run = false;
         for _ in sys.processes_by_exact_name(name.as_ref()) {
@@ -119,27 +260,339 @@ pub fn info_workers(workers_pids: Vec<u32>) -> JoinHandle<SystemInfoWorkers> {
         SystemInfoWorkers {
             worker_childs,
             worker_memory,
+            circuit_breakers: HashMap::new(),
+            worker_restarts: HashMap::new(),
         }
     })
 }
 
+// Worker answers the readiness path ('/api/health') with 200 in 'PROBE_TIMEOUT_SECS'
+pub async fn worker_healthy(client: &Client<HttpConnector>, port: u16, path: &str) -> bool {
+    let request = match Request::builder()
+        .method(Method::GET)
+        .uri(format!("http://{LOCALHOST}:{port}{path}"))
+        .body(Body::empty())
+    {
+        Ok(r) => r,
+        Err(_) => return false,
+    };
+    let timeout = Duration::from_secs(PROBE_TIMEOUT_SECS);
+    matches!(
+        tokio::time::timeout(timeout, client.request(request)).await,
+        Ok(Ok(response)) if response.status() == StatusCode::OK
+    )
+}
+
+/*
+    Workers are ready when a quorum of them passes the health check,
+    until then the balancer answers 503 to traffic and '/readyz' fails
+*/
+fn wait_workers_ready(
+    ports: Vec<u16>,
+    quorum: f64,
+    path: String,
+    client: Client<HttpConnector>,
+    tx_ready: watch::Sender<bool>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let required = ((ports.len() as f64 * quorum).ceil() as usize).max(1);
+        loop {
+            let mut healthy = 0;
+            for port in ports.iter() {
+                if worker_healthy(&client, *port, &path).await {
+                    healthy += 1;
+                }
+            }
+            if healthy >= required {
+                event!(
+                    Level::INFO,
+                    "Workers are ready: {healthy} of {} passed health check",
+                    ports.len()
+                );
+                tx_ready.send_replace(true);
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(WORKER_READY_POLL_MS)).await;
+        }
+    })
+}
+
+/*
+    New worker gets traffic after it answers the readiness path, GDAL is imported by workers
+    for seconds after the start. The result is sent when the worker is ready or the timeout expired
+*/
+fn probe_worker_startup(
+    port: u16,
+    pid: u32,
+    config: &Config,
+    client: Client<HttpConnector>,
+    tx: flume::Sender<MessageSupervisor>,
+) -> JoinHandle<()> {
+    let timeout = Duration::from_secs(config.worker_startup_timeout_secs);
+    let path = config.worker_readiness_path.clone();
+    tokio::spawn(async move {
+        let started = Instant::now();
+        let ready = loop {
+            if worker_healthy(&client, port, &path).await {
+                break true;
+            }
+            if started.elapsed() >= timeout {
+                break false;
+            }
+            tokio::time::sleep(Duration::from_millis(WORKER_READY_POLL_MS)).await;
+        };
+        let _ = tx
+            .send_async(MessageSupervisor::Started { port, pid, ready })
+            .await;
+    })
+}
+
+/*
+    Worker is supervised for crashes and added to rotation after the startup probe.
+    A worker that failed to start is logged and its port stays free
+*/
+fn start_worker(
+    config: &Config,
+    vars: &HashMap<&'static str, String>,
+    port: u16,
+    index: usize,
+    client: &Client<HttpConnector>,
+    tx_supervisor: &flume::Sender<MessageSupervisor>,
+) -> Option<WorkerProcess> {
+    let child = match cmd_run_worker(config, vars, port, index) {
+        Ok(child) => child,
+        Err(err) => {
+            event!(Level::ERROR, "{}", err);
+            return None;
+        }
+    };
+    let mut worker = supervise(child, port, index, tx_supervisor.clone());
+    let jh = probe_worker_startup(
+        port,
+        worker.pid,
+        config,
+        client.clone(),
+        tx_supervisor.clone(),
+    );
+    worker.probe_startup(jh);
+    Some(worker)
+}
+
+// The lowest index not taken by started workers, indexes of crashed and stopped workers are reused
+fn free_worker_index(childs: &HashMap<u16, WorkerProcess>) -> usize {
+    (0..)
+        .find(|index| childs.values().all(|w| w.index != *index))
+        .unwrap_or_default()
+}
+
+// Ports of started workers, in rotation or passing the startup probe
+fn worker_ports(childs: &HashMap<u16, WorkerProcess>) -> Vec<u16> {
+    let mut ports: Vec<u16> = childs.keys().copied().collect();
+    ports.sort();
+    ports
+}
+
 pub fn workers_maintenance(
-    cwd: String,
+    paths: Paths,
     config: Config,
     vars: HashMap<&'static str, String>,
-    mut childs: HashMap<u16, tokio::process::Child>,
-    mut ports: Vec<u16>,
-    mut clients: Vec<Client<HttpConnector>>,
+    childs: HashMap<u16, (usize, tokio::process::Child)>,
+    external: Vec<u16>,
+    client: Client<HttpConnector>,
     rx: flume::Receiver<MessageMaintenanceWorkers>,
+    tx_events: flume::Sender<MessageEvents>,
+    tx_ready: watch::Sender<bool>,
 ) -> JoinHandle<()> {
     tokio::spawn(async move {
+        // exits of workers, results of startup probes and delayed restarts of crashed workers
+        let (tx_supervisor, rx_supervisor) = flume::unbounded::<MessageSupervisor>();
+        let mut childs: HashMap<u16, WorkerProcess> = childs
+            .into_iter()
+            .map(|(port, (index, child))| {
+                let mut worker = supervise(child, port, index, tx_supervisor.clone());
+                let jh = probe_worker_startup(
+                    port,
+                    worker.pid,
+                    &config,
+                    client.clone(),
+                    tx_supervisor.clone(),
+                );
+                worker.probe_startup(jh);
+                (port, worker)
+            })
+            .collect();
+        /*
+            Ports in rotation, workers are added after the startup probe. External workers
+            are run outside of the balancer, they are in rotation at once and are not restarted
+        */
+        let mut ports: Vec<u16> = external.clone();
+        // native renderers of the balancer are ready at once
+        let mut jh_ready = if config.is_native() {
+            tx_ready.send_replace(true);
+            tokio::spawn(async {})
+        } else {
+            wait_workers_ready(
+                [worker_ports(&childs), external.clone()].concat(),
+                config.worker_ready_quorum,
+                config.worker_readiness_path.clone(),
+                client.clone(),
+                tx_ready.clone(),
+            )
+        };
         let mut index_port = 0;
         let mut count_ports = ports.len();
-        let mut workers_pids: Vec<u32> = childs.values().map(|w| w.id().unwrap()).collect();
+        let mut workers_pids: Vec<u32> = childs.values().map(|w| w.pid).collect();
         let mut last_reload_time: Option<chrono::DateTime<chrono::Utc>> = None;
+        let mut breakers = CircuitBreakers::new(
+            config.circuit_breaker_failure_threshold,
+            config.circuit_breaker_open_secs,
+        );
+        let mut restarts = WorkerRestarts::new(
+            config.worker_restart,
+            config.worker_restart_backoff_secs,
+            config.worker_restart_max_backoff_secs,
+        );
+
+        loop {
+            let message = tokio::select! {
+                message = rx.recv_async() => match message {
+                    Ok(m) => m,
+                    Err(_) => break,
+                },
+                Ok(supervision) = rx_supervisor.recv_async() => {
+                    match supervision {
+                        MessageSupervisor::Exited { port, pid, status } => {
+                            // workers killed at reload and termination are removed before exit
+                            if childs.get(&port).map(|w| w.pid) != Some(pid) {
+                                continue;
+                            }
+                            let Some(worker) = childs.remove(&port) else {
+                                continue;
+                            };
+                            ports.retain(|p| *p != port);
+                            count_ports = ports.len();
+                            breakers.retain(&ports);
+                            workers_pids = childs.values().map(|w| w.pid).collect();
+                            let saved = try_save_process_pid(&paths, workers_pids.clone()).await;
+                            if let Err(err) = saved {
+                                event!(Level::ERROR, "Error save porcesses PIDs: {}", err);
+                            }
+
+                            let delay = restarts.record_crash(port, &status, &worker);
+                            let exited = format!("Worker {port} (PID {pid}) exited with {status}");
+                            match delay {
+                                Some(delay) => {
+                                    event!(Level::ERROR, "{exited}, restart in {delay:?}");
+                                    let tx_supervisor = tx_supervisor.clone();
+                                    tokio::spawn(async move {
+                                        tokio::time::sleep(delay).await;
+                                        let _ = tx_supervisor
+                                            .send_async(MessageSupervisor::Restart { port })
+                                            .await;
+                                    });
+                                }
+                                None => {
+                                    event!(Level::ERROR, "{exited}, restart is disabled");
+                                }
+                            }
+                            publish_event(
+                                &tx_events,
+                                EventKind::Worker,
+                                json!({
+                                    "action": "crash",
+                                    "port": port,
+                                    "status": status,
+                                    "restart_in_ms": delay.map(|d| d.as_millis() as u64),
+                                }),
+                            );
+                        }
+                        MessageSupervisor::Started { port, pid, ready } => {
+                            // worker is in rotation, stopped or restarted during the probe
+                            if ports.contains(&port) {
+                                continue;
+                            }
+                            let Some(worker) = childs.get_mut(&port).filter(|w| w.pid == pid)
+                            else {
+                                continue;
+                            };
+                            let timeout = config.worker_startup_timeout_secs;
+                            if !ready && config.worker_startup_kill {
+                                // exit of the worker is handled as a crash
+                                event!(
+                                    Level::ERROR,
+                                    "Worker {port} (PID {pid}) is not ready in {timeout}s, killed"
+                                );
+                                if let Err(err) = kill_tree::tokio::kill_tree(pid).await {
+                                    event!(Level::ERROR, "Error kill worker {port}: {err:?}");
+                                }
+                                continue;
+                            }
+                            if ready {
+                                event!(Level::INFO, "Worker {port} (PID {pid}) is ready");
+                            } else {
+                                event!(
+                                    Level::WARN,
+                                    "Worker {port} (PID {pid}) is not ready in {timeout}s, added"
+                                );
+                            }
+                            worker.set_ready();
+                            ports.push(port);
+                            count_ports = ports.len();
+                            publish_event(
+                                &tx_events,
+                                EventKind::Worker,
+                                json!({"action": "ready", "port": port, "ready": ready}),
+                            );
+                        }
+                        MessageSupervisor::Restart { port } => {
+                            // restart was cancelled or the port was taken by added workers
+                            if !restarts.take_pending(port) || childs.contains_key(&port) {
+                                continue;
+                            }
+                            let index = free_worker_index(&childs);
+                            let Some(worker) =
+                                start_worker(&config, &vars, port, index, &client, &tx_supervisor)
+                            else {
+                                continue;
+                            };
+                            childs.insert(port, worker);
+                            workers_pids = childs.values().map(|w| w.pid).collect();
+                            let saved = try_save_process_pid(&paths, workers_pids.clone()).await;
+                            if let Err(err) = saved {
+                                event!(Level::ERROR, "Error save porcesses PIDs: {}", err);
+                            }
+                            event!(Level::INFO, "Worker {port} restarted after crash");
+                            publish_event(
+                                &tx_events,
+                                EventKind::Worker,
+                                json!({"action": "restart", "ports": worker_ports(&childs)}),
+                            );
+                        }
+                    }
+                    continue;
+                }
+            };
 
-        while let Ok(message) = rx.recv_async().await {
             match message {
+                // requests of tiles get the virtual port of the pool of native renderers
+                MessageMaintenanceWorkers::GetWorkerData { tx_wd } if config.is_native() => {
+                    let wd = WorkerData {
+                        port: NATIVE_RENDERER_PORT,
+                        index: 0,
+                        ports: Vec::new(),
+                        client: client.clone(),
+                    };
+                    if tx_wd.send(Some(wd)).is_err() {
+                        event!(Level::ERROR, "Error send port of native renderers");
+                    }
+                }
+                MessageMaintenanceWorkers::AddWorkers { .. }
+                | MessageMaintenanceWorkers::ReloadWorkers()
+                | MessageMaintenanceWorkers::TerminateWorkers()
+                    if config.is_native() =>
+                {
+                    event!(Level::WARN, "Python workers are not run in native mode");
+                }
                 MessageMaintenanceWorkers::GetWorkerData { tx_wd } => {
                     if count_ports == 0 {
                         if let Err(_) = tx_wd.send(None) {
@@ -147,7 +600,7 @@ pub fn workers_maintenance(
                         }
                     } else {
                         // simple balancing based on the order of workers
-                        let index = {
+                        let mut next_index = || {
                             if index_port > (count_ports - 1) as u16 {
                                 index_port = 1;
                                 0
@@ -156,12 +609,28 @@ pub fn workers_maintenance(
                                 (index_port - 1) as usize
                             }
                         };
+                        let mut index = next_index();
+                        // skip ports with open circuit breaker, if all are open keep the first choice
+                        if breakers.is_enabled() {
+                            let first = index;
+                            let mut allowed = breakers.allow(ports[index]);
+                            for _ in 1..count_ports {
+                                if allowed {
+                                    break;
+                                }
+                                index = next_index();
+                                allowed = breakers.allow(ports[index]);
+                            }
+                            if !allowed {
+                                index = first;
+                            }
+                        }
                         let port = ports[index];
                         if let Err(_) = tx_wd.send(Some(WorkerData {
                             port,
                             index,
                             ports: ports.clone(),
-                            client: clients[index].clone(),
+                            client: client.clone(),
                         })) {
                             event!(Level::ERROR, "Error send port {port}");
                         }
@@ -173,56 +642,85 @@ pub fn workers_maintenance(
                         Err(err) => {
                             event!(Level::ERROR, "Error get system info workers {err}");
                         }
-                        Ok(iw) => {
+                        Ok(mut iw) => {
+                            iw.circuit_breakers = breakers.info();
+                            iw.worker_restarts = restarts.info();
                             if let Err(_) = tx_iw.send(Some(iw)) {
                                 event!(Level::ERROR, "Error send system info workers");
                             }
                         }
                     }
                 }
+                MessageMaintenanceWorkers::ReportWorkerResult { port, success } => {
+                    breakers.record(port, success);
+                }
+                MessageMaintenanceWorkers::AllowWorker { port, tx_allowed } => {
+                    if tx_allowed.send(breakers.allow(port)).is_err() {
+                        event!(Level::ERROR, "Error send circuit breaker of port {port}");
+                    }
+                }
                 MessageMaintenanceWorkers::AddWorkers { count } => {
                     for _ in 0..count {
                         if let Some(p) = get_available_port(
                             config.worker_port_from as u16,
                             config.worker_port_to as u16,
-                            &ports,
+                            &worker_ports(&childs),
                         )
                         .await
                         {
                             tokio::time::sleep(std::time::Duration::from_millis(1000)).await;
-                            let worker = cmd_run_worker(&config, &vars, p);
-                            childs.insert(p, worker);
-                            ports.push(p);
-                            clients.push(Client::new());
+                            let index = free_worker_index(&childs);
+                            if let Some(worker) =
+                                start_worker(&config, &vars, p, index, &client, &tx_supervisor)
+                            {
+                                childs.insert(p, worker);
+                            }
                         }
                     }
                     count_ports = ports.len();
+                    breakers.retain(&ports);
 
-                    workers_pids = childs.values().map(|w| w.id().unwrap()).collect();
-                    if let Err(err) = try_save_process_pid(&cwd, workers_pids.clone()).await {
+                    workers_pids = childs.values().map(|w| w.pid).collect();
+                    if let Err(err) = try_save_process_pid(&paths, workers_pids.clone()).await {
                         event!(Level::ERROR, "Error save porcesses PIDs: {}", err);
                     }
+                    // workers added after termination make the balancer ready again
+                    if !*tx_ready.borrow() {
+                        jh_ready.abort();
+                        jh_ready = wait_workers_ready(
+                            [worker_ports(&childs), external.clone()].concat(),
+                            config.worker_ready_quorum,
+                            config.worker_readiness_path.clone(),
+                            client.clone(),
+                            tx_ready.clone(),
+                        );
+                    }
+                    publish_event(
+                        &tx_events,
+                        EventKind::Worker,
+                        json!({"action": "add", "ports": worker_ports(&childs)}),
+                    );
                 }
                 MessageMaintenanceWorkers::ReloadWorkers() => {
+                    jh_ready.abort();
+                    tx_ready.send_replace(false);
+                    restarts.cancel_pending();
                     for (_port, child) in childs.iter() {
-                        kill_tree::tokio::kill_tree(child.id().unwrap())
-                            .await
-                            .unwrap();
+                        kill_tree::tokio::kill_tree(child.pid).await.unwrap();
                     }
 
                     if config.terminate_childs_with_python {
-                        if let Err(err) = run_python_terminate_childs(&cwd, vars.clone()).await {
+                        if let Err(err) = run_python_terminate_childs(&paths, vars.clone()).await {
                             event!(Level::ERROR, "Error run_python_terminate_childs: {}", err);
                         }
                     }
 
                     childs.clear();
-                    ports.clear();
-                    clients.clear();
+                    ports.retain(|p| external.contains(p));
 
                     for _ in 0..60 {
                         tokio::time::sleep(std::time::Duration::from_secs(1)).await;
-                        if let Ok(is_run) = is_process_run(GRANIAN).await {
+                        if let Ok(is_run) = is_process_run(worker_process_name(&config)).await {
                             if !is_run {
                                 break;
                             }
@@ -233,20 +731,23 @@ pub fn workers_maintenance(
                         if let Some(p) = get_available_port(
                             config.worker_port_from as u16,
                             config.worker_port_to as u16,
-                            &ports,
+                            &worker_ports(&childs),
                         )
                         .await
                         {
                             tokio::time::sleep(std::time::Duration::from_millis(100)).await;
-                            let worker = cmd_run_worker(&config, &vars, p);
-                            childs.insert(p, worker);
-                            ports.push(p);
-                            clients.push(Client::new());
+                            let index = free_worker_index(&childs);
+                            if let Some(worker) =
+                                start_worker(&config, &vars, p, index, &client, &tx_supervisor)
+                            {
+                                childs.insert(p, worker);
+                            }
                         }
                     }
                     count_ports = ports.len();
+                    breakers.retain(&ports);
 
-                    if let Err(err) = init_db(&cwd).await {
+                    if let Err(err) = init_db(&paths).await {
                         event!(
                             Level::ERROR,
                             "Error initialize DataBase 'tiler.db' at reload workers: {}",
@@ -254,28 +755,63 @@ pub fn workers_maintenance(
                         );
                     }
 
-                    workers_pids = childs.values().map(|w| w.id().unwrap()).collect();
-                    if let Err(err) = try_save_process_pid(&cwd, workers_pids.clone()).await {
+                    workers_pids = childs.values().map(|w| w.pid).collect();
+                    if let Err(err) = try_save_process_pid(&paths, workers_pids.clone()).await {
                         event!(Level::ERROR, "Error save porcesses PIDs: {}", err);
                     }
+                    jh_ready = wait_workers_ready(
+                        [worker_ports(&childs), external.clone()].concat(),
+                        config.worker_ready_quorum,
+                        config.worker_readiness_path.clone(),
+                        client.clone(),
+                        tx_ready.clone(),
+                    );
+                    publish_event(
+                        &tx_events,
+                        EventKind::Worker,
+                        json!({"action": "reload", "ports": worker_ports(&childs)}),
+                    );
                 }
                 MessageMaintenanceWorkers::TerminateWorkers() => {
+                    jh_ready.abort();
+                    tx_ready.send_replace(false);
+                    restarts.cancel_pending();
                     for (_port, child) in childs.iter() {
-                        kill_tree::tokio::kill_tree(child.id().unwrap())
-                            .await
-                            .unwrap();
+                        kill_tree::tokio::kill_tree(child.pid).await.unwrap();
                     }
 
                     if config.terminate_childs_with_python {
-                        if let Err(err) = run_python_terminate_childs(&cwd, vars.clone()).await {
+                        if let Err(err) = run_python_terminate_childs(&paths, vars.clone()).await {
                             event!(Level::ERROR, "Error run_python_terminate_childs: {}", err);
                         }
                     }
 
                     childs.clear();
                     ports.clear();
-                    clients.clear();
                     count_ports = ports.len();
+                    breakers.retain(&ports);
+                    publish_event(
+                        &tx_events,
+                        EventKind::Worker,
+                        json!({"action": "terminate", "ports": ports}),
+                    );
+                }
+                MessageMaintenanceWorkers::ListWorkers { tx_lw } => {
+                    let workers = worker_ports(&childs)
+                        .into_iter()
+                        .filter_map(|port| childs.get(&port).map(|w| (port, w.pid)))
+                        .collect();
+                    if tx_lw.send(workers).is_err() {
+                        event!(Level::ERROR, "Error send list of workers");
+                    }
+                }
+                MessageMaintenanceWorkers::SavePids { tx_result } => {
+                    let result = try_save_process_pid(&paths, workers_pids.clone())
+                        .await
+                        .map(|_| workers_pids.clone());
+                    if tx_result.send(result).is_err() {
+                        event!(Level::ERROR, "Error send result of saving PIDs");
+                    }
                 }
                 MessageMaintenanceWorkers::GetWorkerState { tx_ws } => {
                     if let Some(lrt) = last_reload_time {
@@ -0,0 +1,405 @@
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::anyhow;
+use hyper::body::Bytes;
+use hyper::client::HttpConnector;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Client, Request, Response, Server, StatusCode};
+use serde_json::json;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use ulid::Ulid;
+
+use crate::config::{config_from_json, Config};
+use crate::db::job::memory::MemoryQueue;
+use crate::db::registry::memory::MemoryRegistry;
+use crate::defaults::LOCALHOST;
+use crate::environment::init_dirs;
+use crate::paths::Paths;
+use crate::server::{TilerServer, TilerServerHandle};
+use crate::structs::EndPoints;
+use crate::tasks::datasources::DataSource;
+
+/*
+    Support of end-to-end tests of the balancer without GDAL, Python and PostgreSQL:
+    workers are replaced by servers of hyper in the process of the test,
+    DataSources and jobs are kept in memory
+*/
+
+const READY_TIMEOUT_SECS: u64 = 10;
+const READY_POLL_MS: u64 = 50;
+
+// Answer of the mock worker to requests other than its readiness path
+#[derive(Debug, Clone)]
+pub struct MockResponse {
+    pub status: StatusCode,
+    pub content_type: String,
+    pub body: Bytes,
+    pub delay: Duration,
+}
+
+impl Default for MockResponse {
+    fn default() -> Self {
+        MockResponse {
+            status: StatusCode::OK,
+            content_type: "application/json".to_string(),
+            body: Bytes::from_static(b"{}"),
+            delay: Duration::ZERO,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct MockState {
+    response: MockResponse,
+    healthy: bool,
+    // the next requests are failed by closing the connection without a response
+    failures: u64,
+    paths: Vec<String>,
+}
+
+// Fake worker on a free port of the localhost, its behavior is changed while the test runs
+pub struct MockWorker {
+    port: u16,
+    readiness_path: String,
+    state: Arc<Mutex<MockState>>,
+    requests: Arc<AtomicU64>,
+    tx_shutdown: Option<oneshot::Sender<()>>,
+    jh: JoinHandle<()>,
+}
+
+impl MockWorker {
+    pub async fn start(readiness_path: &str) -> Result<MockWorker, anyhow::Error> {
+        let state = Arc::new(Mutex::new(MockState {
+            response: MockResponse::default(),
+            healthy: true,
+            failures: 0,
+            paths: Vec::new(),
+        }));
+        let requests = Arc::new(AtomicU64::new(0));
+        let readiness_path = readiness_path.to_string();
+
+        let addr: SocketAddr = format!("{LOCALHOST}:0").parse()?;
+        let listener = std::net::TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        let port = listener.local_addr()?.port();
+
+        let make_svc = make_service_fn({
+            let state = state.clone();
+            let requests = requests.clone();
+            let readiness_path = readiness_path.clone();
+            move |_| {
+                let state = state.clone();
+                let requests = requests.clone();
+                let readiness_path = readiness_path.clone();
+                async move {
+                    Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                        mock_handle(req, state.clone(), requests.clone(), readiness_path.clone())
+                    }))
+                }
+            }
+        });
+
+        let (tx_shutdown, rx_shutdown) = oneshot::channel::<()>();
+        let server = Server::from_tcp(listener)?
+            .serve(make_svc)
+            .with_graceful_shutdown(async {
+                let _ = rx_shutdown.await;
+            });
+        let jh = tokio::spawn(async move {
+            let _ = server.await;
+        });
+
+        Ok(MockWorker {
+            port,
+            readiness_path,
+            state,
+            requests,
+            tx_shutdown: Some(tx_shutdown),
+            jh,
+        })
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    // Requests to the worker except probes of its readiness
+    pub fn requests(&self) -> u64 {
+        self.requests.load(Ordering::Relaxed)
+    }
+
+    pub fn paths(&self) -> Vec<String> {
+        self.state.lock().unwrap().paths.clone()
+    }
+
+    pub fn respond(&self, response: MockResponse) {
+        self.state.lock().unwrap().response = response;
+    }
+
+    pub fn set_healthy(&self, healthy: bool) {
+        self.state.lock().unwrap().healthy = healthy;
+    }
+
+    pub fn fail_next(&self, count: u64) {
+        self.state.lock().unwrap().failures = count;
+    }
+
+    pub fn readiness_path(&self) -> &str {
+        &self.readiness_path
+    }
+
+    pub async fn stop(mut self) {
+        if let Some(tx_shutdown) = self.tx_shutdown.take() {
+            let _ = tx_shutdown.send(());
+        }
+        let _ = (&mut self.jh).await;
+    }
+}
+
+async fn mock_handle(
+    req: Request<Body>,
+    state: Arc<Mutex<MockState>>,
+    requests: Arc<AtomicU64>,
+    readiness_path: String,
+) -> Result<Response<Body>, anyhow::Error> {
+    let path = req.uri().path().to_string();
+    if path == readiness_path {
+        let healthy = state.lock().unwrap().healthy;
+        let status = if healthy {
+            StatusCode::OK
+        } else {
+            StatusCode::SERVICE_UNAVAILABLE
+        };
+        return Ok(Response::builder().status(status).body(Body::empty())?);
+    }
+
+    requests.fetch_add(1, Ordering::Relaxed);
+    let (response, failed) = {
+        let mut state = state.lock().unwrap();
+        state.paths.push(path.clone());
+        let failed = state.failures > 0;
+        if failed {
+            state.failures -= 1;
+        }
+        (state.response.clone(), failed)
+    };
+    if !response.delay.is_zero() {
+        tokio::time::sleep(response.delay).await;
+    }
+    // error of the service closes the connection, the balancer gets an error of the proxy
+    if failed {
+        return Err(anyhow!("Mock worker failed request '{path}'"));
+    }
+    Ok(Response::builder()
+        .status(response.status)
+        .header(hyper::header::CONTENT_TYPE, response.content_type)
+        .body(Body::from(response.body))?)
+}
+
+// Configuration of the balancer with mock workers, the values are overridden by the test
+pub fn test_config_json() -> serde_json::Value {
+    json!({
+        "server": {
+            "type": "granian",
+            "timeout_worker_response": 5,
+            "host": LOCALHOST,
+            "port": 0,
+            "log_level": "ERROR",
+            "thread_workers": 1,
+            "processes_workers": 0,
+            "interface": "asgi",
+            "worker": {
+                "log_level": "ERROR",
+                "ports": {
+                    "from": 9000,
+                    "to": 9999
+                },
+                "reload_time": "02:30:00",
+                "reload_periodicity_days": 1,
+                "reload_repeat_minutes": 20,
+                "reload_repeat_attempts": 5,
+                "max_concurrent_tile_requests": 5,
+                "restart": {
+                    "enabled": false
+                }
+            },
+            "registry": {
+                "type": "sqlite"
+            }
+        }
+    })
+}
+
+// Values of the patch replace values of the document, objects are merged by keys
+fn merge_json(document: &mut serde_json::Value, patch: serde_json::Value) {
+    match (document, patch) {
+        (serde_json::Value::Object(document), serde_json::Value::Object(patch)) => {
+            for (key, value) in patch {
+                merge_json(
+                    document.entry(key).or_insert(serde_json::Value::Null),
+                    value,
+                );
+            }
+        }
+        (document, patch) => *document = patch,
+    }
+}
+
+pub struct TestServerBuilder {
+    workers: usize,
+    config_json: serde_json::Value,
+    datasources: Vec<DataSource>,
+    address: Option<String>,
+}
+
+impl TestServerBuilder {
+    // Count of mock workers, 0 - requests to workers are answered by the balancer with errors
+    pub fn workers(mut self, count: usize) -> Self {
+        self.workers = count;
+        self
+    }
+
+    // Part of 'config_app.json' merged into the configuration of tests
+    pub fn config_json(mut self, patch: serde_json::Value) -> Self {
+        merge_json(&mut self.config_json, patch);
+        self
+    }
+
+    pub fn datasource(mut self, datasource: DataSource) -> Self {
+        self.datasources.push(datasource);
+        self
+    }
+
+    // The balancer runs as master with the job queue in memory
+    pub fn master(mut self, address: impl Into<String>) -> Self {
+        self.address = Some(address.into());
+        self
+    }
+
+    pub async fn start(self) -> Result<TestServer, anyhow::Error> {
+        let mut config: Config = config_from_json(self.config_json)?;
+        config.master = self.address.is_some();
+        config.address = self.address;
+
+        // every server of tests gets its own cache, database 'tiler.db' and PIDs file
+        let cwd = std::env::temp_dir().join(format!("tiler-test-{}", Ulid::new()));
+        let cwd_str = cwd.to_string_lossy().to_string();
+        let paths = Paths::new(&cwd_str, &config.paths);
+        tokio::fs::create_dir_all(&paths.scripts).await?;
+        init_dirs(&paths).await?;
+
+        let mut workers = Vec::with_capacity(self.workers);
+        for _ in 0..self.workers {
+            workers.push(MockWorker::start(&config.worker_readiness_path).await?);
+        }
+
+        let registry = Arc::new(MemoryRegistry::new(self.datasources));
+        let queue = Arc::new(MemoryQueue::new());
+        let addr: SocketAddr = format!("{LOCALHOST}:0").parse()?;
+        let handle = TilerServer::builder()
+            .cwd(cwd_str)
+            .config(config)
+            .bind(addr)
+            .registry(registry.clone())
+            .queue(queue.clone())
+            .workers(workers.iter().map(MockWorker::port).collect())
+            .build()
+            .await?
+            .start()
+            .await?;
+
+        let server = TestServer {
+            handle,
+            workers,
+            registry,
+            queue,
+            cwd,
+            client: Client::new(),
+        };
+        if !server.workers.is_empty() {
+            server.wait_ready().await?;
+        }
+        Ok(server)
+    }
+}
+
+// Balancer started in the process of the test with mock workers
+pub struct TestServer {
+    handle: TilerServerHandle,
+    pub workers: Vec<MockWorker>,
+    pub registry: Arc<MemoryRegistry>,
+    pub queue: Arc<MemoryQueue>,
+    cwd: PathBuf,
+    client: Client<HttpConnector>,
+}
+
+impl TestServer {
+    pub fn builder() -> TestServerBuilder {
+        TestServerBuilder {
+            workers: 1,
+            config_json: test_config_json(),
+            datasources: Vec::new(),
+            address: None,
+        }
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.handle.local_addr()
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}{}", self.addr(), path)
+    }
+
+    pub fn cwd(&self) -> &PathBuf {
+        &self.cwd
+    }
+
+    pub async fn request(&self, req: Request<Body>) -> Result<Response<Body>, anyhow::Error> {
+        Ok(self.client.request(req).await?)
+    }
+
+    pub async fn get(&self, path: &str) -> Result<Response<Body>, anyhow::Error> {
+        Ok(self.client.get(self.url(path).parse()?).await?)
+    }
+
+    // Status and body of the GET request
+    pub async fn get_bytes(&self, path: &str) -> Result<(StatusCode, Bytes), anyhow::Error> {
+        let response = self.get(path).await?;
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await?;
+        Ok((status, body))
+    }
+
+    // Waits until a quorum of workers passes the health check and '/readyz' answers 200
+    pub async fn wait_ready(&self) -> Result<(), anyhow::Error> {
+        let started = Instant::now();
+        loop {
+            if let Ok(response) = self.get(EndPoints::Readyz.as_ref()).await {
+                if response.status() == StatusCode::OK {
+                    return Ok(());
+                }
+            }
+            if started.elapsed() >= Duration::from_secs(READY_TIMEOUT_SECS) {
+                return Err(anyhow!("Server is not ready in {READY_TIMEOUT_SECS}s"));
+            }
+            tokio::time::sleep(Duration::from_millis(READY_POLL_MS)).await;
+        }
+    }
+
+    // Stops the balancer and mock workers, the working directory is removed
+    pub async fn shutdown(self) -> Result<(), anyhow::Error> {
+        let result = self.handle.shutdown().await;
+        for worker in self.workers {
+            worker.stop().await;
+        }
+        let _ = tokio::fs::remove_dir_all(&self.cwd).await;
+        result
+    }
+}
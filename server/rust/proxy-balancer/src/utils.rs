@@ -1,4 +1,4 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 use std::{fs, io::Write};
 
@@ -6,16 +6,24 @@ use anyhow::{anyhow, Error};
 use hyper::client::HttpConnector;
 use hyper::header::HeaderValue;
 use hyper::{Body, Client, Method, Request, Response, StatusCode};
-use serde_json::json;
+use tiler_core::integrity::write_disk_tile_checksum;
+use tiler_core::lookup::{read_disk_tile, CachedTile};
+use tiler_core::manifest::mbtiles_path_for_tile;
+use tiler_core::sqlite_clients::MessageSQLiteClient;
 use tracing::{event, Level};
 
+use crate::cluster::auth::sign_request;
 use crate::config::Config;
-use crate::db::{init_mbtiles_db, DB};
-use crate::defaults::{LOCALHOST, MASTER_HEADER};
-use crate::handles::helpers::response_with_body_and_code;
-use crate::structs::Extension;
-use crate::tasks::datasources::{load_datasource_from_db, MapDataSources};
-use crate::tasks::sqlite_clients::MessageSQLiteClient;
+use crate::db::{get_mbtile, init_mbtiles_db, registry::SharedRegistry, storage_compressed};
+use crate::defaults::{LOCALHOST, MASTER_HEADER, TILE_SCALES};
+use crate::grid::TileGrid;
+use crate::handles::error::ApiError;
+use crate::handles::headers::{CacheControl, ResponseBuilderExt};
+use crate::handles::helpers::no_content_response;
+use crate::paths::Paths;
+use crate::raster::resize_png;
+use crate::structs::{ContentType, EndPoints, Extension};
+use crate::tasks::datasources::MapDataSources;
 use crate::tasks::workers::info_workers;
 
 pub async fn worker_load_dss(
@@ -44,11 +52,20 @@ pub async fn worker_load_dss(
                 uries.push(uri.clone());
 
                 // header "Master-Server" to avoid endless sending of requests between some masters
-                let req = Request::builder()
+                let mut req = Request::builder()
                     .method(Method::GET)
                     .uri(uri.clone())
                     .header(MASTER_HEADER, v.clone())
                     .body(Body::empty())?;
+                if let Some(secret) = config.cluster_secret.as_deref() {
+                    sign_request(
+                        req.headers_mut(),
+                        secret,
+                        &Method::GET,
+                        EndPoints::DataSources.as_ref(),
+                        &[],
+                    )?;
+                }
 
                 tokio::spawn({
                     let client = client.clone();
@@ -87,7 +104,29 @@ pub async fn worker_load_dss(
     Ok(())
 }
 
-pub async fn try_save_process_pid(cwd: &str, workers_pids: Vec<u32>) -> Result<(), anyhow::Error> {
+// Pooled HTTP client for requests from the proxy to the workers
+pub fn worker_client(config: &Config) -> Client<HttpConnector> {
+    let mut connector = HttpConnector::new();
+    connector.set_nodelay(true);
+    if config.worker_client_keepalive_secs > 0 {
+        connector.set_keepalive(Some(Duration::from_secs(
+            config.worker_client_keepalive_secs,
+        )));
+    }
+
+    Client::builder()
+        .pool_max_idle_per_host(config.worker_client_pool_max_idle_per_host)
+        .pool_idle_timeout(Duration::from_secs(
+            config.worker_client_pool_idle_timeout_secs,
+        ))
+        .http2_only(config.worker_client_http2)
+        .build(connector)
+}
+
+pub async fn try_save_process_pid(
+    paths: &Paths,
+    workers_pids: Vec<u32>,
+) -> Result<(), anyhow::Error> {
     let mut delay = tokio::time::interval(std::time::Duration::from_secs(1));
     // The first tick completes immediately.
     delay.tick().await;
@@ -95,7 +134,7 @@ pub async fn try_save_process_pid(cwd: &str, workers_pids: Vec<u32>) -> Result<(
     let count_attempts = 10;
     for i in 0..count_attempts {
         delay.tick().await;
-        let is_workers_run = match save_process_pid(cwd, workers_pids.clone()).await {
+        let is_workers_run = match save_process_pid(paths, workers_pids.clone()).await {
             Err(err) => {
                 return Err(err);
             }
@@ -114,7 +153,10 @@ pub async fn try_save_process_pid(cwd: &str, workers_pids: Vec<u32>) -> Result<(
     Ok(())
 }
 
-pub async fn save_process_pid(cwd: &str, workers_pids: Vec<u32>) -> Result<bool, anyhow::Error> {
+pub async fn save_process_pid(
+    paths: &Paths,
+    workers_pids: Vec<u32>,
+) -> Result<bool, anyhow::Error> {
     let jh = info_workers(workers_pids);
     match jh.await {
         Err(err) => {
@@ -127,12 +169,11 @@ pub async fn save_process_pid(cwd: &str, workers_pids: Vec<u32>) -> Result<bool,
             ))
         }
         Ok(iw) => {
-            let pids: PathBuf = [cwd, "scripts", "PIDs"].iter().collect();
             let mut file = fs::OpenOptions::new()
                 .create(true)
                 .write(true)
                 .truncate(true)
-                .open(pids)?;
+                .open(paths.pids())?;
             for (wid, childs_id) in &iw.worker_childs {
                 if childs_id.len() == 0 {
                     return Ok(false);
@@ -183,46 +224,43 @@ pub fn datasource_id_from_uri(uri: &str) -> Result<&str, Error> {
     Ok(datasource_id)
 }
 
-pub fn dataset_dir_from_uri(cwd: &str, uri: &str) -> Result<PathBuf, Error> {
+pub fn dataset_dir_from_uri(paths: &Paths, uri: &str) -> Result<PathBuf, Error> {
     let path_elements: Vec<&str> = uri.split("/").collect();
     if path_elements.len() < 7 {
         return Err(anyhow!("Path of URI is invalid: {:?}", path_elements));
     }
     let datasource_id = path_elements[3];
-    let dataset_dir: PathBuf = [cwd, "tiles", datasource_id].iter().collect();
-    Ok(dataset_dir)
+    Ok(paths.tiles_dir(datasource_id))
+}
+
+// Root directory of static files: absolute path as is, relative to the application folder
+pub fn static_root(paths: &Paths, config: &Config) -> PathBuf {
+    match config.static_root.as_ref() {
+        Some(root) => paths.resolve(root),
+        None => paths.root.join("static"),
+    }
 }
 
-pub fn dataset_dir_from_ds_id(cwd: &str, datasource_id: &str) -> Result<PathBuf, Error> {
-    let dataset_dir: PathBuf = [cwd, "tiles", datasource_id].iter().collect();
-    Ok(dataset_dir)
+pub fn dataset_dir_from_ds_id(paths: &Paths, datasource_id: &str) -> Result<PathBuf, Error> {
+    Ok(paths.tiles_dir(datasource_id))
 }
 
-pub fn mbtiles_path_from_ds_id(cwd: &str, datasource_id: &str) -> Result<PathBuf, Error> {
-    let mbtiles_db: PathBuf = [
-        cwd,
-        "tiles",
-        datasource_id,
-        &format!("{datasource_id}.mbtiles"),
-    ]
-    .iter()
-    .collect();
-    Ok(mbtiles_db)
+pub fn mbtiles_path_from_ds_id(paths: &Paths, datasource_id: &str) -> Result<PathBuf, Error> {
+    Ok(paths.mbtiles_db(datasource_id))
 }
 
 pub async fn try_init_mbtiles(
-    cwd: &str,
+    paths: &Paths,
     dataset_dir: PathBuf,
     datasource_id: &str,
-    pool: DB,
+    registry: SharedRegistry,
     tx_sqlite_client: Option<flume::Sender<MessageSQLiteClient>>,
 ) -> Option<Response<Body>> {
     // Check dataset exists
     if tokio::fs::metadata(&dataset_dir).await.is_err() {
-        // check 'tiles' directory exists
-        let tiles_dir: PathBuf = [cwd, "tiles"].iter().collect();
-        if tokio::fs::metadata(&tiles_dir).await.is_err() {
-            if let Err(err) = tokio::fs::create_dir(&tiles_dir)
+        // check directory of the cache exists
+        if tokio::fs::metadata(&paths.cache).await.is_err() {
+            if let Err(err) = tokio::fs::create_dir_all(&paths.cache)
                 .await
                 .map_err(|err| anyhow!(err))
             {
@@ -230,7 +268,7 @@ pub async fn try_init_mbtiles(
             }
         }
 
-        if let Ok(ds) = load_datasource_from_db(&pool, datasource_id).await {
+        if let Ok(Some(ds)) = registry.datasource(datasource_id).await {
             if let Ok(_) = tokio::fs::create_dir(&dataset_dir)
                 .await
                 .map_err(|err| anyhow!(err))
@@ -238,30 +276,29 @@ pub async fn try_init_mbtiles(
                 // init MBTiles Database
                 if let Some(mbtiles) = ds.mbtiles {
                     if mbtiles {
-                        init_mbtiles_db(cwd, ds.identifier.clone(), tx_sqlite_client)
-                            .await
-                            .expect(&format!(
-                                "Error init mbtiles database for ID '{}'",
-                                ds.identifier.clone()
-                            ));
+                        init_mbtiles_db(
+                            paths,
+                            ds.identifier.clone(),
+                            storage_compressed(&ds.data.0),
+                            tx_sqlite_client,
+                        )
+                        .await
+                        .expect(&format!(
+                            "Error init mbtiles database for ID '{}'",
+                            ds.identifier.clone()
+                        ));
                     }
                 }
             }
             return None;
         } else {
-            let body = json!({
-                "status": 404,
-                "message": format!("DataSource with id '{}' does not exists", datasource_id)
-            })
-            .to_string();
-            let response = response_with_body_and_code(body, StatusCode::NOT_FOUND);
-            return Some(response);
+            return Some(ApiError::datasource_not_found(datasource_id).into_response());
         }
     }
     return None;
 }
 
-pub fn file_path_from_uri(cwd: &str, uri: &str) -> Result<PathBuf, Error> {
+pub fn file_path_from_uri(paths: &Paths, uri: &str) -> Result<PathBuf, Error> {
     let path_elements: Vec<&str> = uri.split("/").collect();
     if path_elements.len() < 7 {
         return Err(anyhow!("Path of URI is invalid: {:?}", path_elements));
@@ -276,48 +313,29 @@ pub fn file_path_from_uri(cwd: &str, uri: &str) -> Result<PathBuf, Error> {
         Err(err) => return Err(anyhow!("Error parse X: {}", err)),
     };
 
-    let file_tile_name: PathBuf = [
-        cwd,
-        "tiles",
-        datasource_id,
-        &z.to_string(),
-        &x.to_string(),
-        path_elements[6],
-    ]
-    .iter()
-    .collect();
+    let file_tile_name: PathBuf = paths
+        .tiles_dir(datasource_id)
+        .join(z.to_string())
+        .join(x.to_string())
+        .join(path_elements[6]);
 
     Ok(file_tile_name)
 }
 
-pub async fn mbtiles_path_from_uri(cwd: &str, uri: &str) -> Result<PathBuf, Error> {
+// MBTiles of the tile '(x, y, z)' of the DataSource, chosen by the manifest of the DataSource
+pub async fn mbtiles_path_from_uri(
+    paths: &Paths,
+    uri: &str,
+    (x, y, z): (u64, u64, u8),
+) -> Result<PathBuf, Error> {
     let path_elements: Vec<&str> = uri.split("/").collect();
     if path_elements.len() < 7 {
         return Err(anyhow!("Path of URI is invalid: {:?}", path_elements));
     }
     let datasource_id = path_elements[3];
-    let datasource_tiles_dir: PathBuf = [cwd, "tiles", datasource_id].iter().collect();
-    let mbtile_files = get_mbtiles_paths(datasource_tiles_dir).await?;
-    if mbtile_files.len() > 0 {
-        return Ok(mbtile_files[0].clone());
-    }
-
-    Err(anyhow!(
-        "DataBase of MBTiles for DataSource ID '{}' not found",
-        datasource_id
-    ))
-}
-
-async fn get_mbtiles_paths(dir: PathBuf) -> Result<Vec<PathBuf>, anyhow::Error> {
-    let mut paths = Vec::new();
-    let mut dir = tokio::fs::read_dir(dir).await?;
-    while let Some(entry) = dir.next_entry().await? {
-        let path = entry.path();
-        if path.extension().map_or(false, |ext| ext == "mbtiles") {
-            paths.push(path);
-        }
-    }
-    Ok(paths)
+    mbtiles_path_for_tile(paths.tiles_dir(datasource_id), z, x, y)
+        .await
+        .map_err(|err| anyhow!("DataBase of MBTiles for DataSource ID '{datasource_id}': {err}"))
 }
 
 pub fn zxy_from_uri(uri: &str) -> Result<(u64, u64, u8), Error> {
@@ -333,8 +351,8 @@ pub fn zxy_from_uri(uri: &str) -> Result<(u64, u64, u8), Error> {
         Ok(v) => v,
         Err(err) => return Err(anyhow!("Error parse X: {}", err)),
     };
-    let y_ext = path_elements[6].split(".").collect::<Vec<_>>();
-    let y = match y_ext[0].parse::<u64>() {
+    let (y, _, ext) = split_tile_name(path_elements[6])?;
+    let y = match y.parse::<u64>() {
         Ok(v) => v,
         Err(err) => return Err(anyhow!("Error parse Y: {}", err)),
     };
@@ -342,63 +360,168 @@ pub fn zxy_from_uri(uri: &str) -> Result<(u64, u64, u8), Error> {
     let png = Extension::Png.as_ref();
     let mvt = Extension::Mvt.as_ref();
     let pbf = Extension::Pbf.as_ref();
+    let webp = Extension::Webp.as_ref();
+
+    let ext = ext.to_lowercase();
+    if ext != png && ext != mvt && ext != pbf && ext != webp {
+        return Err(anyhow!(
+            "Extension of tile must be '{}', '{}', '{}' or '{}'. Got {}",
+            png,
+            mvt,
+            pbf,
+            webp,
+            ext
+        ));
+    }
+
+    Ok((x, y, z))
+}
 
-    if y_ext[1] != png && y_ext[1] != mvt && y_ext[1] != pbf {
-        if y_ext[1].to_lowercase() != png
-            && y_ext[1].to_lowercase() != mvt
-            && y_ext[1].to_lowercase() != pbf
-        {
-            return Err(anyhow!(
-                "Extension of tile must be '{}', '{}' or '{}'. Got {}",
-                png,
-                mvt,
-                pbf,
-                y_ext[1]
-            ));
+/*
+    Name of the tile file '{y}.{ext}', retina tiles have the scale suffix:
+    '{y}@2x.png', '{y}@0.5x.png'
+*/
+fn split_tile_name(name: &str) -> Result<(&str, Option<f32>, &str), Error> {
+    let (stem, ext) = name
+        .split_once('.')
+        .ok_or(anyhow!("Extension of tile '{name}' is missing"))?;
+    match stem.split_once('@') {
+        None => Ok((stem, None, ext)),
+        Some((y, scale)) => {
+            let scale = scale
+                .strip_suffix('x')
+                .and_then(|s| s.parse::<f32>().ok())
+                .filter(|s| TILE_SCALES.contains(s))
+                .ok_or(anyhow!(
+                    "Scale of tile '{name}' must be one of {}",
+                    TILE_SCALES.map(|s| format!("@{s}x")).join(", ")
+                ))?;
+            Ok((y, Some(scale), ext))
         }
     }
+}
 
+pub fn tile_scale_from_uri(uri: &str) -> Result<Option<f32>, Error> {
+    let path_elements: Vec<&str> = uri.split("/").collect();
+    if path_elements.len() < 7 {
+        return Err(anyhow!("Path of URI is invalid: {:?}", path_elements));
+    }
+    let (_, scale, _) = split_tile_name(path_elements[6])?;
+    Ok(scale)
+}
+
+// Tile coordinates of URI validated against zoom levels and matrix of the tile grid
+pub fn zxy_from_uri_in_grid(uri: &str, grid: &TileGrid) -> Result<(u64, u64, u8), Error> {
+    let (x, y, z) = zxy_from_uri(uri)?;
+    grid.validate_tile(z, x, y)?;
     Ok((x, y, z))
 }
 
+// Response of the tile of the cache, None - the tile is missing
+pub fn cached_tile_response(
+    tile: CachedTile,
+    content_type: &str,
+) -> Result<Option<Response<Body>>, anyhow::Error> {
+    match tile {
+        CachedTile::Tile(tile) => Ok(Some(tile_response(tile, content_type)?)),
+        CachedTile::Empty => Ok(Some(no_content_response()?)),
+        CachedTile::Missing => Ok(None),
+    }
+}
+
 pub async fn get_tile_from_disk(
-    file_tile_path: &PathBuf,
+    file_tile_path: &Path,
     content_type: &str,
+    integrity: bool,
 ) -> Result<Option<Response<Body>>, anyhow::Error> {
-    if let Ok(md) = tokio::fs::metadata(&file_tile_path).await {
-        if md.len() > 0 {
-            let tile = tokio::fs::read(file_tile_path).await?;
-            let response = tile_response(tile, content_type)?;
-            return Ok(Some(response));
-        } else {
-            let response = Response::builder()
-                .status(StatusCode::BAD_REQUEST)
-                .header("Content-Length", 0)
-                .header("Access-Control-Allow-Origin", "*")
-                .header("Cache-Control", "max-age=0")
-                .body(Body::empty())?;
-            return Ok(Some(response));
+    let tile = read_disk_tile(file_tile_path, integrity).await?;
+    cached_tile_response(tile, content_type)
+}
+
+/*
+    Retina tile '{y}@2x.png' is resized from the cached tile '{y}.png' (file or MBTiles)
+    and saved to 'file_tile_path', next requests get it from disk
+*/
+pub async fn get_scaled_tile(
+    file_tile_path: &PathBuf,
+    mbtiles_path: Option<PathBuf>,
+    (x, y, z): (u64, u64, u8),
+    scale: f32,
+    tx_sqlite_client: flume::Sender<MessageSQLiteClient>,
+    integrity: bool,
+) -> Result<Option<Response<Body>>, anyhow::Error> {
+    let content_type = ContentType::Png.as_ref();
+    if let Some(response) = get_tile_from_disk(file_tile_path, content_type, integrity).await? {
+        return Ok(Some(response));
+    }
+
+    let base_path = file_tile_path.with_file_name(format!("{y}.{}", Extension::Png.as_ref()));
+    let mut base = get_tile_from_disk(&base_path, content_type, integrity).await?;
+    if base.is_none() {
+        if let Some(p) = mbtiles_path {
+            base = get_mbtile(
+                &p,
+                z as u64,
+                x,
+                y,
+                content_type,
+                tx_sqlite_client,
+                integrity,
+            )
+            .await?;
         }
     }
-    Ok(None)
+    let base = match base {
+        Some(response) if response.status() == StatusCode::OK => {
+            hyper::body::to_bytes(response.into_body()).await?
+        }
+        _ => return Ok(None),
+    };
+
+    let tile = save_scaled_tile(file_tile_path, &base, scale, integrity).await?;
+    tile_response(tile, content_type).map(Some)
+}
+
+// Tile resized from the base tile is saved to 'file_tile_path', next requests get it from disk
+pub async fn save_scaled_tile(
+    file_tile_path: &PathBuf,
+    base: &[u8],
+    scale: f32,
+    integrity: bool,
+) -> Result<Vec<u8>, anyhow::Error> {
+    let tile = resize_png(base, scale)?;
+    if let Some(dir) = file_tile_path.parent() {
+        tokio::fs::create_dir_all(dir).await?;
+    }
+    match tokio::fs::write(file_tile_path, &tile).await {
+        Ok(_) if integrity => write_disk_tile_checksum(file_tile_path, &tile).await,
+        Ok(_) => {}
+        Err(err) => event!(
+            Level::ERROR,
+            "Error save scaled tile {file_tile_path:?}: {err}"
+        ),
+    }
+    Ok(tile)
+}
+
+// URI of the tile without the scale suffix: '{y}@2x.png' -> '{y}.png'
+pub fn unscaled_tile_uri(uri: &str) -> String {
+    match uri.rsplit_once('/') {
+        Some((dir, name)) => match split_tile_name(name) {
+            Ok((y, Some(_), ext)) => format!("{dir}/{y}.{ext}"),
+            _ => uri.to_string(),
+        },
+        None => uri.to_string(),
+    }
 }
 
 pub fn tile_response(tile: Vec<u8>, content_type: &str) -> Result<Response<Body>, anyhow::Error> {
-    let response = if tile.starts_with(b"\x1f\x8b\x08") {
-        Response::builder()
-            .status(StatusCode::OK)
-            .header("Content-type", content_type)
-            .header("Access-Control-Allow-Origin", "*")
-            .header("Cache-Control", "max-age=0")
-            .header("Content-Encoding", "gzip")
-            .body(Body::from(tile))?
-    } else {
-        Response::builder()
-            .status(StatusCode::OK)
-            .header("Content-type", content_type)
-            .header("Access-Control-Allow-Origin", "*")
-            .header("Cache-Control", "max-age=0")
-            .body(Body::from(tile))?
-    };
-    Ok(response)
+    let mut builder = Response::builder()
+        .status(StatusCode::OK)
+        .content_type(content_type)
+        .cache_control(CacheControl::Revalidate);
+    if tile.starts_with(b"\x1f\x8b\x08") {
+        builder = builder.gzip_encoded();
+    }
+    Ok(builder.body(Body::from(tile))?)
 }
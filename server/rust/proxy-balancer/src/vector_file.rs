@@ -0,0 +1,415 @@
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::anyhow;
+use flatgeobuf::FgbReader;
+use geozero::geojson::GeoJsonWriter;
+use serde_json::Value;
+
+use crate::defaults::{MVT_BUFFER, MVT_EXTENT};
+use crate::overzoom::mvt::{
+    clip_lines, clip_points, clip_polygons, encode_geometry, write_bytes, write_varint,
+    zigzag_encode, Point, FEATURE_GEOMETRY, FEATURE_TYPE, GEOM_LINESTRING, GEOM_POINT,
+    GEOM_POLYGON, LAYER_EXTENT, LAYER_FEATURES, LAYER_NAME, TILE_LAYERS,
+};
+use crate::paths::Paths;
+use crate::tasks::datasources::DataSource;
+
+pub const STORE_FILE: &str = "file";
+
+const LAYER_VERSION: u32 = 15;
+const LAYER_KEYS: u32 = 3;
+const LAYER_VALUES: u32 = 4;
+const FEATURE_ID: u32 = 1;
+const FEATURE_TAGS: u32 = 2;
+const VALUE_STRING: u32 = 1;
+const VALUE_DOUBLE: u32 = 3;
+const VALUE_UINT: u32 = 5;
+const VALUE_SINT: u32 = 6;
+const VALUE_BOOL: u32 = 7;
+
+// Half of the equator of EPSG:3857 in meters
+const MERCATOR_HALF: f64 = 20037508.342789244;
+const MERCATOR_MAX_LAT: f64 = 85.0511287798066;
+
+type Coord = (f64, f64);
+
+#[derive(Debug)]
+struct Feature {
+    id: Option<u64>,
+    geom_type: u64,
+    // coordinates in EPSG:3857, exterior rings of polygons are followed by their holes
+    parts: Vec<Vec<Coord>>,
+    // [min_x, min_y, max_x, max_y]
+    bbox: [f64; 4],
+    // name of the property and the encoded message 'Value' of MVT
+    properties: Vec<(String, Vec<u8>)>,
+}
+
+/*
+    Vector DataSource with the store 'file': small GeoJSON or FlatGeobuf file in EPSG:4326
+    under 'datasources/vector' is loaded into memory, MVT tiles are cut by the proxy
+*/
+#[derive(Debug)]
+pub struct VectorFile {
+    layer: String,
+    extent: u32,
+    buffer: u32,
+    features: Vec<Feature>,
+}
+
+// File of the DataSource from the field 'dataStore.file', only inside of 'datasources/vector'
+pub fn vector_file_path(paths: &Paths, ds: &DataSource) -> Result<PathBuf, anyhow::Error> {
+    let file = ds
+        .data
+        .0
+        .get("dataStore")
+        .and_then(|s| s.get("file"))
+        .and_then(|f| f.as_str())
+        .ok_or(anyhow!("DataSource has no 'dataStore.file'"))?;
+    if !Path::new(file)
+        .components()
+        .all(|c| matches!(c, Component::Normal(_)))
+    {
+        return Err(anyhow!("File '{file}' is outside of 'datasources/vector'"));
+    }
+    Ok(paths.datasources_dir("vector").join(file))
+}
+
+pub async fn load_vector_file(paths: &Paths, ds: &DataSource) -> Result<VectorFile, anyhow::Error> {
+    let path = vector_file_path(paths, ds)?;
+    let data_store = ds.data.0.get("dataStore");
+    let layer = data_store
+        .and_then(|s| s.get("layer"))
+        .and_then(|l| l.as_str())
+        .map(|l| l.to_string())
+        .or(path.file_stem().map(|s| s.to_string_lossy().to_string()))
+        .unwrap_or(ds.identifier.clone());
+    let extent = ds
+        .data
+        .0
+        .get("extent")
+        .and_then(|v| v.as_u64())
+        .filter(|v| *v > 0)
+        .map_or(MVT_EXTENT, |v| v as u32);
+    let buffer = ds
+        .data
+        .0
+        .get("buffer")
+        .and_then(|v| v.as_u64())
+        .map_or(MVT_BUFFER, |v| v as u32);
+
+    tokio::task::spawn_blocking(move || {
+        let features = read_features(&path)?;
+        Ok(VectorFile {
+            layer,
+            extent,
+            buffer,
+            features,
+        })
+    })
+    .await?
+}
+
+fn read_features(path: &Path) -> Result<Vec<Feature>, anyhow::Error> {
+    let ext = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    let geojson = match ext.as_str() {
+        "fgb" => {
+            let mut reader = BufReader::new(File::open(path)?);
+            let mut fgb = FgbReader::open(&mut reader)?.select_all()?;
+            let mut geojson = Vec::new();
+            fgb.process_features(&mut GeoJsonWriter::new(&mut geojson))?;
+            geojson
+        }
+        "geojson" | "json" => std::fs::read(path)?,
+        _ => return Err(anyhow!("Unsupported format of the file {path:?}")),
+    };
+    let value: Value = serde_json::from_slice(&geojson)?;
+
+    let mut features = Vec::new();
+    match value.get("type").and_then(|t| t.as_str()) {
+        Some("FeatureCollection") => {
+            for feature in value
+                .get("features")
+                .and_then(|f| f.as_array())
+                .into_iter()
+                .flatten()
+            {
+                features.extend(parse_feature(feature));
+            }
+        }
+        Some("Feature") => features.extend(parse_feature(&value)),
+        _ => features.extend(parse_geometry(&value, None, Vec::new())),
+    }
+    Ok(features)
+}
+
+fn parse_feature(feature: &Value) -> Vec<Feature> {
+    let id = feature.get("id").and_then(|id| id.as_u64());
+    let properties: Vec<(String, Vec<u8>)> = feature
+        .get("properties")
+        .and_then(|p| p.as_object())
+        .map(|p| {
+            p.iter()
+                .filter_map(|(key, value)| encode_value(value).map(|v| (key.clone(), v)))
+                .collect()
+        })
+        .unwrap_or_default();
+    match feature.get("geometry") {
+        Some(geometry) => parse_geometry(geometry, id, properties),
+        None => Vec::new(),
+    }
+}
+
+// Geometries of the collection are features with the same properties
+fn parse_geometry(
+    geometry: &Value,
+    id: Option<u64>,
+    properties: Vec<(String, Vec<u8>)>,
+) -> Vec<Feature> {
+    let coordinates = geometry.get("coordinates");
+    let (geom_type, parts) = match geometry.get("type").and_then(|t| t.as_str()) {
+        Some("Point") => (GEOM_POINT, coordinates.map(|c| vec![coords(c, 0)])),
+        Some("MultiPoint") => (GEOM_POINT, coordinates.map(|c| vec![coords(c, 1)])),
+        Some("LineString") => (GEOM_LINESTRING, coordinates.map(|c| vec![coords(c, 1)])),
+        Some("MultiLineString") => (GEOM_LINESTRING, coordinates.map(lines)),
+        Some("Polygon") => (GEOM_POLYGON, coordinates.map(polygon)),
+        Some("MultiPolygon") => (
+            GEOM_POLYGON,
+            coordinates
+                .and_then(|c| c.as_array())
+                .map(|polygons| polygons.iter().flat_map(polygon).collect()),
+        ),
+        Some("GeometryCollection") => {
+            return geometry
+                .get("geometries")
+                .and_then(|g| g.as_array())
+                .into_iter()
+                .flatten()
+                .flat_map(|g| parse_geometry(g, id, properties.clone()))
+                .collect();
+        }
+        _ => (0, None),
+    };
+
+    let parts: Vec<Vec<Coord>> = parts
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|p| !p.is_empty())
+        .collect();
+    if parts.is_empty() {
+        return Vec::new();
+    }
+    let mut bbox = [f64::MAX, f64::MAX, f64::MIN, f64::MIN];
+    for (x, y) in parts.iter().flatten() {
+        bbox = [
+            bbox[0].min(*x),
+            bbox[1].min(*y),
+            bbox[2].max(*x),
+            bbox[3].max(*y),
+        ];
+    }
+    vec![Feature {
+        id,
+        geom_type,
+        parts,
+        bbox,
+        properties,
+    }]
+}
+
+// Positions of the GeoJSON array with the nesting 'depth' projected to EPSG:3857
+fn coords(value: &Value, depth: u8) -> Vec<Coord> {
+    let position = |v: &Value| -> Option<Coord> {
+        let p = v.as_array()?;
+        Some(mercator(p.first()?.as_f64()?, p.get(1)?.as_f64()?))
+    };
+    match depth {
+        0 => position(value).into_iter().collect(),
+        _ => value
+            .as_array()
+            .map(|a| a.iter().filter_map(position).collect())
+            .unwrap_or_default(),
+    }
+}
+
+fn lines(value: &Value) -> Vec<Vec<Coord>> {
+    value
+        .as_array()
+        .map(|a| a.iter().map(|l| coords(l, 1)).collect())
+        .unwrap_or_default()
+}
+
+/*
+    Rings of the polygon without the closing point, oriented for MVT:
+    after the flip of the Y axis exterior rings have positive area, holes negative
+*/
+fn polygon(value: &Value) -> Vec<Vec<Coord>> {
+    let mut rings = lines(value);
+    for (i, ring) in rings.iter_mut().enumerate() {
+        if ring.len() > 1 && ring.first() == ring.last() {
+            ring.pop();
+        }
+        let area: f64 = (0..ring.len())
+            .map(|j| {
+                let (a, b) = (ring[j], ring[(j + 1) % ring.len()]);
+                a.0 * b.1 - b.0 * a.1
+            })
+            .sum();
+        if (i == 0 && area > 0.0) || (i > 0 && area < 0.0) {
+            ring.reverse();
+        }
+    }
+    rings.retain(|r| r.len() >= 3);
+    rings
+}
+
+fn mercator(lon: f64, lat: f64) -> Coord {
+    let lat = lat.clamp(-MERCATOR_MAX_LAT, MERCATOR_MAX_LAT);
+    (
+        lon.to_radians() * MERCATOR_HALF / PI,
+        (PI / 4.0 + lat.to_radians() / 2.0).tan().ln() * MERCATOR_HALF / PI,
+    )
+}
+
+// Message 'Value' of MVT, nested arrays and objects are encoded as JSON strings
+fn encode_value(value: &Value) -> Option<Vec<u8>> {
+    let mut buf = Vec::new();
+    match value {
+        Value::Null => return None,
+        Value::Bool(b) => {
+            write_varint(&mut buf, (VALUE_BOOL as u64) << 3);
+            write_varint(&mut buf, *b as u64);
+        }
+        Value::Number(n) => {
+            if let Some(u) = n.as_u64() {
+                write_varint(&mut buf, (VALUE_UINT as u64) << 3);
+                write_varint(&mut buf, u);
+            } else if let Some(i) = n.as_i64() {
+                write_varint(&mut buf, (VALUE_SINT as u64) << 3);
+                write_varint(&mut buf, zigzag_encode(i));
+            } else {
+                write_varint(&mut buf, ((VALUE_DOUBLE as u64) << 3) | 1);
+                buf.extend_from_slice(&n.as_f64()?.to_le_bytes());
+            }
+        }
+        Value::String(s) => write_bytes(&mut buf, VALUE_STRING, s.as_bytes()),
+        value => write_bytes(&mut buf, VALUE_STRING, value.to_string().as_bytes()),
+    }
+    Some(buf)
+}
+
+impl VectorFile {
+    /*
+        MVT of the tile (z, x, y) of 'WebMercatorQuad' with one layer,
+        empty Vec when no feature intersects the tile with its buffer
+    */
+    pub fn tile(&self, z: u8, x: u64, y: u64) -> Vec<u8> {
+        let extent = self.extent as f64;
+        let size = 2.0 * MERCATOR_HALF / (1u64 << z) as f64;
+        let (min_x, max_y) = (
+            -MERCATOR_HALF + x as f64 * size,
+            MERCATOR_HALF - y as f64 * size,
+        );
+        let margin = size * self.buffer as f64 / extent;
+        let bbox = [
+            min_x - margin,
+            max_y - size - margin,
+            min_x + size + margin,
+            max_y + margin,
+        ];
+        let clip = [-(self.buffer as i64), (self.extent + self.buffer) as i64];
+        let transform = |(mx, my): &Coord| -> Point {
+            (
+                ((mx - min_x) / size * extent).round() as i64,
+                ((max_y - my) / size * extent).round() as i64,
+            )
+        };
+
+        let mut keys: HashMap<&str, u32> = HashMap::new();
+        let mut values: HashMap<&[u8], u32> = HashMap::new();
+        let mut layer = Vec::new();
+        for feature in self.features.iter().filter(|f| {
+            f.bbox[0] <= bbox[2]
+                && f.bbox[2] >= bbox[0]
+                && f.bbox[1] <= bbox[3]
+                && f.bbox[3] >= bbox[1]
+        }) {
+            let parts: Vec<Vec<Point>> = feature
+                .parts
+                .iter()
+                .map(|part| {
+                    let mut points: Vec<Point> = part.iter().map(transform).collect();
+                    points.dedup();
+                    points
+                })
+                .collect();
+            let parts = match feature.geom_type {
+                GEOM_POINT => clip_points(parts, &clip),
+                GEOM_LINESTRING => clip_lines(parts, &clip),
+                _ => clip_polygons(parts, &clip),
+            };
+            if parts.is_empty() {
+                continue;
+            }
+
+            let mut tags = Vec::new();
+            for (key, value) in feature.properties.iter() {
+                let next_key = keys.len() as u32;
+                write_varint(
+                    &mut tags,
+                    *keys.entry(key.as_str()).or_insert(next_key) as u64,
+                );
+                let next_value = values.len() as u32;
+                write_varint(
+                    &mut tags,
+                    *values.entry(value.as_slice()).or_insert(next_value) as u64,
+                );
+            }
+            let mut encoded = Vec::new();
+            if let Some(id) = feature.id {
+                write_varint(&mut encoded, (FEATURE_ID as u64) << 3);
+                write_varint(&mut encoded, id);
+            }
+            write_bytes(&mut encoded, FEATURE_TAGS, &tags);
+            write_varint(&mut encoded, (FEATURE_TYPE as u64) << 3);
+            write_varint(&mut encoded, feature.geom_type);
+            write_bytes(
+                &mut encoded,
+                FEATURE_GEOMETRY,
+                &encode_geometry(&parts, feature.geom_type),
+            );
+            write_bytes(&mut layer, LAYER_FEATURES, &encoded);
+        }
+        if layer.is_empty() {
+            return Vec::new();
+        }
+
+        // keys and values are written in the order of their indexes
+        let mut keys: Vec<(&str, u32)> = keys.into_iter().collect();
+        keys.sort_by_key(|(_, i)| *i);
+        for (key, _) in keys {
+            write_bytes(&mut layer, LAYER_KEYS, key.as_bytes());
+        }
+        let mut values: Vec<(&[u8], u32)> = values.into_iter().collect();
+        values.sort_by_key(|(_, i)| *i);
+        for (value, _) in values {
+            write_bytes(&mut layer, LAYER_VALUES, value);
+        }
+        write_bytes(&mut layer, LAYER_NAME, self.layer.as_bytes());
+        write_varint(&mut layer, (LAYER_EXTENT as u64) << 3);
+        write_varint(&mut layer, self.extent as u64);
+        write_varint(&mut layer, (LAYER_VERSION as u64) << 3);
+        write_varint(&mut layer, 2);
+
+        let mut tile = Vec::new();
+        write_bytes(&mut tile, TILE_LAYERS, &layer);
+        tile
+    }
+}
@@ -0,0 +1,175 @@
+use std::time::Duration;
+
+use anyhow::anyhow;
+use serde::Serialize;
+use serde_json::Value;
+use tracing::{event, Level};
+
+use crate::config::Config;
+use crate::db::registry::SharedRegistry;
+use crate::defaults::{WEBHOOK_RETRIES, WEBHOOK_RETRY_SECS};
+
+pub const PYRAMID_COMPLETED: &str = "pyramid_completed";
+pub const PYRAMID_FAILED: &str = "pyramid_failed";
+
+/*
+    Notification of the finished pyramid: JSON is POSTed to URLs of 'webhooks.pyramid' of the config
+    and of the field 'webhooks' of the DataSource. Deliveries failed with errors or codes other than 2xx
+    are retried, they never affect the pyramid
+*/
+#[derive(Debug, Clone, Serialize)]
+pub struct PyramidNotification {
+    pub event: &'static str,
+    pub datasource_id: String,
+    // job of the queue, None - the pyramid of the request
+    pub job_id: Option<String>,
+    // pyramid of the worker, None - the pyramid failed before the worker started it
+    pub pyramid_id: Option<String>,
+    pub message: Option<String>,
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+}
+
+impl PyramidNotification {
+    pub fn completed(datasource_id: &str, pyramid_id: &str) -> Self {
+        PyramidNotification {
+            event: PYRAMID_COMPLETED,
+            datasource_id: datasource_id.to_string(),
+            job_id: None,
+            pyramid_id: Some(pyramid_id.to_string()),
+            message: None,
+            timestamp: chrono::Utc::now(),
+        }
+    }
+
+    pub fn failed(datasource_id: &str, pyramid_id: Option<&str>, message: String) -> Self {
+        PyramidNotification {
+            event: PYRAMID_FAILED,
+            datasource_id: datasource_id.to_string(),
+            job_id: None,
+            pyramid_id: pyramid_id.map(String::from),
+            message: Some(message),
+            timestamp: chrono::Utc::now(),
+        }
+    }
+
+    pub fn with_job(mut self, job_id: Option<String>) -> Self {
+        self.job_id = job_id;
+        self
+    }
+}
+
+// Webhooks of the pyramid: URLs of the config and the timeout of a delivery
+#[derive(Debug, Clone)]
+pub struct PyramidWebhooks {
+    pub urls: Vec<String>,
+    pub timeout_secs: u64,
+}
+
+impl PyramidWebhooks {
+    pub fn from_config(config: &Config) -> Self {
+        PyramidWebhooks {
+            urls: config.pyramid_webhooks.clone(),
+            timeout_secs: config.webhook_timeout_secs,
+        }
+    }
+}
+
+pub fn is_webhook_url(url: &str) -> bool {
+    url.starts_with("http://") || url.starts_with("https://")
+}
+
+// URLs of the field 'webhooks' of the DataSource JSON
+pub fn datasource_webhooks(data: &Value) -> Vec<String> {
+    data.get("webhooks")
+        .and_then(|urls| urls.as_array())
+        .map(|urls| {
+            urls.iter()
+                .filter_map(|url| url.as_str())
+                .map(|url| url.trim().to_string())
+                .filter(|url| is_webhook_url(url))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+async fn deliver(
+    client: &reqwest::Client,
+    url: &str,
+    notification: &PyramidNotification,
+) -> Result<(), anyhow::Error> {
+    let response = client
+        .post(url)
+        .header("X-Tiler-Event", notification.event)
+        .json(notification)
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        return Err(anyhow!("responded {}", response.status()));
+    }
+    Ok(())
+}
+
+// Every URL of the DataSource and of the config is notified once in the background
+pub async fn notify_pyramid(
+    registry: &SharedRegistry,
+    webhooks: &PyramidWebhooks,
+    notification: PyramidNotification,
+) {
+    let mut urls = webhooks.urls.clone();
+    match registry.datasource(&notification.datasource_id).await {
+        Ok(Some(ds)) => {
+            for url in datasource_webhooks(&ds.data.0) {
+                if !urls.contains(&url) {
+                    urls.push(url);
+                }
+            }
+        }
+        Ok(None) => {}
+        Err(err) => event!(
+            Level::ERROR,
+            "Error load webhooks of DataSource '{}': {err}",
+            notification.datasource_id
+        ),
+    }
+    if urls.is_empty() {
+        return;
+    }
+
+    let client = match reqwest::Client::builder()
+        .timeout(Duration::from_secs(webhooks.timeout_secs))
+        .user_agent(concat!("tiler-proxy-balancer/", env!("CARGO_PKG_VERSION")))
+        .build()
+    {
+        Ok(client) => client,
+        Err(err) => {
+            event!(Level::ERROR, "Error create client of webhooks: {err}");
+            return;
+        }
+    };
+    for url in urls {
+        let client = client.clone();
+        let notification = notification.clone();
+        tokio::spawn(async move {
+            for attempt in 1..=WEBHOOK_RETRIES {
+                match deliver(&client, &url, &notification).await {
+                    Ok(()) => return,
+                    Err(err) => event!(
+                        Level::WARN,
+                        "Error notify webhook '{url}' of {} ({attempt}/{WEBHOOK_RETRIES}): {err}",
+                        notification.event
+                    ),
+                }
+                if attempt < WEBHOOK_RETRIES {
+                    tokio::time::sleep(Duration::from_secs(WEBHOOK_RETRY_SECS * attempt as u64))
+                        .await;
+                }
+            }
+            event!(
+                Level::ERROR,
+                "Webhook '{url}' is not notified of {} of DataSource '{}'",
+                notification.event,
+                notification.datasource_id
+            );
+        });
+    }
+}
@@ -0,0 +1,75 @@
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::PathBuf;
+
+use hyper::{header, Body, Request, Response, StatusCode};
+use serde_json::json;
+use ulid::Ulid;
+
+use tiler_server::config::{config_from_json, Config};
+use tiler_server::handles::cache::handle_cache;
+use tiler_server::paths::Paths;
+use tiler_server::png::solid_png;
+use tiler_server::testing::test_config_json;
+
+// Cache of 'serve-cache' with the tile '3/2/1.png' of the DataSource 'test' in the root with 'allowed_origins'
+async fn cache_with_tile() -> (Paths, Config, PathBuf) {
+    let cwd = std::env::temp_dir().join(format!("tiler-test-{}", Ulid::new()));
+    let root = cwd.join("tiles");
+    let tile_dir = root.join("test").join("3").join("2");
+    tokio::fs::create_dir_all(&tile_dir).await.unwrap();
+    tokio::fs::write(tile_dir.join("1.png"), solid_png(256, [0, 255, 0, 255]))
+        .await
+        .unwrap();
+
+    let mut config_json = test_config_json();
+    config_json["server"]["serve_cache"] = json!({
+        "roots": [{
+            "path": root.to_string_lossy(),
+            "prefixes": [],
+            "allowed_origins": ["https://*.example.com"]
+        }]
+    });
+    let config = config_from_json(config_json).unwrap();
+    let paths = Paths::new(&cwd.to_string_lossy(), &config.paths);
+    (paths, config, cwd)
+}
+
+async fn cache_tile(paths: &Paths, config: &Config, origin: Option<&str>) -> Response<Body> {
+    let mut req = Request::get("/api/tile/test/3/2/1.png");
+    if let Some(origin) = origin {
+        req = req.header(header::ORIGIN, origin);
+    }
+    let (tx_sqlite_client, _rx_sqlite_client) = flume::unbounded();
+    handle_cache(
+        paths.clone(),
+        IpAddr::V4(Ipv4Addr::LOCALHOST),
+        req.body(Body::empty()).unwrap(),
+        tx_sqlite_client,
+        PathBuf::from("static"),
+        config.clone(),
+        None,
+    )
+    .await
+    .unwrap()
+}
+
+// Tiles of the root with 'allowed_origins' are served by the cache only to these websites
+#[tokio::test]
+async fn cache_tile_is_restricted_to_allowed_origins() {
+    let (paths, config, cwd) = cache_with_tile().await;
+
+    let response = cache_tile(&paths, &config, Some("https://maps.example.com")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+        "https://maps.example.com"
+    );
+
+    let response = cache_tile(&paths, &config, Some("https://evil.com")).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    let response = cache_tile(&paths, &config, None).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    tokio::fs::remove_dir_all(cwd).await.unwrap();
+}
@@ -0,0 +1,138 @@
+use serde_json::json;
+use sqlx::types::Json;
+
+use tiler_server::db::job::memory::MemoryQueue;
+use tiler_server::db::job::queue::{JobDetail, JobType, Queue};
+use tiler_server::tasks::exports::{exports_maintenance, ExportJob, ExportState, MessageExports};
+
+fn pyramid_job(datasource_id: &str) -> JobDetail {
+    JobDetail {
+        jt: JobType::Pyramid {
+            datasource_id: datasource_id.to_string(),
+        },
+        name: "Pyramid".to_string(),
+        scheduled_for: None,
+        data: Json(json!({"datasource_id": datasource_id})),
+    }
+}
+
+// Job is pulled once while it runs, it is deleted after it is done
+#[tokio::test]
+async fn job_is_pulled_once_and_deleted() {
+    let queue = MemoryQueue::new();
+    queue.push(pyramid_job("test"), None).await.unwrap();
+
+    let jobs = queue.pull(Some(1)).await.unwrap();
+    assert_eq!(jobs.len(), 1);
+    assert_eq!(jobs[0].detail.name, "Pyramid");
+    assert!(queue.pull(Some(1)).await.unwrap().is_empty());
+
+    queue.delete_job(jobs[0].job_id).await.unwrap();
+    assert!(queue.pull(Some(1)).await.unwrap().is_empty());
+}
+
+// Failed job is pulled again until it fails 3 times
+#[tokio::test]
+async fn failed_job_is_retried_until_attempts_are_exhausted() {
+    let queue = MemoryQueue::new();
+    queue.push(pyramid_job("test"), None).await.unwrap();
+
+    for _ in 0..3 {
+        let jobs = queue.pull(Some(1)).await.unwrap();
+        assert_eq!(jobs.len(), 1);
+        queue.fail_job(jobs[0].job_id).await.unwrap();
+    }
+    assert!(queue.pull(Some(1)).await.unwrap().is_empty());
+}
+
+// Job scheduled for the future is not pulled, due jobs are pulled in the order of their dates
+#[tokio::test]
+async fn scheduled_jobs_are_pulled_when_due() {
+    let queue = MemoryQueue::new();
+    let now = chrono::Utc::now();
+    let tomorrow = now + chrono::TimeDelta::try_days(1).unwrap();
+    let hour_ago = now - chrono::TimeDelta::try_hours(1).unwrap();
+    queue
+        .push(pyramid_job("later"), Some(tomorrow))
+        .await
+        .unwrap();
+    queue.push(pyramid_job("recent"), Some(now)).await.unwrap();
+    queue
+        .push(pyramid_job("first"), Some(hour_ago))
+        .await
+        .unwrap();
+
+    let jobs = queue.pull(Some(10)).await.unwrap();
+    let datasources: Vec<&str> = jobs
+        .iter()
+        .map(|job| job.detail.data.0["datasource_id"].as_str().unwrap())
+        .collect();
+    assert_eq!(datasources, vec!["first", "recent"]);
+}
+
+fn running_export(job_id: &str) -> ExportJob {
+    ExportJob {
+        job_id: job_id.to_string(),
+        datasource_id: "test".to_string(),
+        state: ExportState::Running,
+        tiles: None,
+        message: None,
+        download_url: None,
+        expires_at: None,
+        token: "token".to_string(),
+        package: std::env::temp_dir().join(format!("{job_id}.mbtiles")),
+    }
+}
+
+async fn cancel_export(tx: &flume::Sender<MessageExports>, job_id: &str) -> Option<ExportJob> {
+    let (tx_job, rx_job) = tokio::sync::oneshot::channel();
+    tx.send_async(MessageExports::Cancel {
+        job_id: job_id.to_string(),
+        ttl_seconds: 60,
+        tx_job,
+    })
+    .await
+    .unwrap();
+    rx_job.await.unwrap()
+}
+
+// Cancelled job is failed, its task is aborted and its later result is ignored
+#[tokio::test]
+async fn cancelled_job_is_failed_and_aborted() {
+    let (tx, rx) = flume::unbounded();
+    let jh = exports_maintenance(rx);
+    let job = running_export("cancelled-job");
+    tx.send_async(MessageExports::Save { job: job.clone() })
+        .await
+        .unwrap();
+    let task = tokio::spawn(std::future::pending::<()>());
+    tx.send_async(MessageExports::Track {
+        job_id: job.job_id.clone(),
+        task: task.abort_handle(),
+    })
+    .await
+    .unwrap();
+
+    let cancelled = cancel_export(&tx, &job.job_id).await.unwrap();
+    assert_eq!(cancelled.state, ExportState::Failed);
+    assert!(cancelled.expires_at.is_some());
+    assert!(task.await.unwrap_err().is_cancelled());
+
+    let mut completed = job.clone();
+    completed.state = ExportState::Completed;
+    tx.send_async(MessageExports::Save { job: completed })
+        .await
+        .unwrap();
+    let (tx_job, rx_job) = tokio::sync::oneshot::channel();
+    tx.send_async(MessageExports::GetJob {
+        job_id: job.job_id.clone(),
+        tx_job,
+    })
+    .await
+    .unwrap();
+    assert_eq!(rx_job.await.unwrap().unwrap().state, ExportState::Failed);
+
+    assert!(cancel_export(&tx, "unknown-job").await.is_none());
+    drop(tx);
+    jh.await.unwrap();
+}
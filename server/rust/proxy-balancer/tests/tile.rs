@@ -0,0 +1,142 @@
+use hyper::body::Bytes;
+use hyper::{header, StatusCode};
+use serde_json::json;
+use sqlx::types::Json;
+
+use tiler_server::png::solid_png;
+use tiler_server::tasks::datasources::DataSource;
+use tiler_server::testing::{MockResponse, TestServer};
+
+fn raster_datasource(identifier: &str) -> DataSource {
+    DataSource {
+        identifier: identifier.to_string(),
+        data_type: Some("raster".to_string()),
+        store_type: Some("internal".to_string()),
+        host: None,
+        port: None,
+        mbtiles: Some(false),
+        name: None,
+        description: None,
+        attribution: None,
+        minzoom: Some(0),
+        maxzoom: Some(18),
+        bounds: None,
+        center: None,
+        data: Json(json!({})),
+    }
+}
+
+// Tile of the DataSource is rendered by the worker and returned to the client unchanged
+#[tokio::test]
+async fn tile_is_rendered_by_worker() {
+    let server = TestServer::builder()
+        .datasource(raster_datasource("test"))
+        .start()
+        .await
+        .unwrap();
+    let tile = Bytes::from(solid_png(256, [255, 0, 0, 255]));
+    server.workers[0].respond(MockResponse {
+        content_type: "image/png".to_string(),
+        body: tile.clone(),
+        ..MockResponse::default()
+    });
+
+    let response = server.get("/api/tile/test/3/2/1.png").await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers().get(header::CONTENT_TYPE).unwrap(),
+        "image/png"
+    );
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    assert_eq!(body, tile);
+    assert_eq!(server.workers[0].paths(), vec!["/api/tile/test/3/2/1.png"]);
+
+    server.shutdown().await.unwrap();
+}
+
+// Connection closed by the worker is answered by the balancer with an error, not a tile
+#[tokio::test]
+async fn failed_worker_is_not_a_tile() {
+    let server = TestServer::builder()
+        .datasource(raster_datasource("test"))
+        .start()
+        .await
+        .unwrap();
+    server.workers[0].fail_next(1);
+
+    let (status, _) = server.get_bytes("/api/tile/test/3/2/1.png").await.unwrap();
+    assert!(status.is_server_error(), "status {status}");
+    assert_eq!(server.workers[0].requests(), 1);
+
+    server.shutdown().await.unwrap();
+}
+
+// Retina tile without the cached base tile is resized from the tile rendered by the worker
+#[tokio::test]
+async fn scaled_tile_is_resized_from_worker_tile() {
+    let server = TestServer::builder()
+        .datasource(raster_datasource("test"))
+        .start()
+        .await
+        .unwrap();
+    server.workers[0].respond(MockResponse {
+        content_type: "image/png".to_string(),
+        body: Bytes::from(solid_png(256, [0, 0, 255, 255])),
+        ..MockResponse::default()
+    });
+
+    let (status, body) = server
+        .get_bytes("/api/tile/test/3/2/1@2x.png")
+        .await
+        .unwrap();
+    assert_eq!(status, StatusCode::OK);
+    // width of the image in the header IHDR of PNG
+    assert_eq!(u32::from_be_bytes(body[16..20].try_into().unwrap()), 512);
+    assert_eq!(server.workers[0].paths(), vec!["/api/tile/test/3/2/1.png"]);
+
+    let (status, _) = server
+        .get_bytes("/api/tile/test/3/2/1@1.5x.png")
+        .await
+        .unwrap();
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    server.shutdown().await.unwrap();
+}
+
+// Failed tile is not retried on the port with the open circuit breaker
+#[tokio::test]
+async fn open_circuit_breaker_port_is_not_retried() {
+    let server = TestServer::builder()
+        .workers(2)
+        .config_json(json!({
+            "server": {
+                "worker": {
+                    "tile_retries": 1,
+                    "circuit_breaker": {"failure_threshold": 1, "open_seconds": 60}
+                }
+            }
+        }))
+        .datasource(raster_datasource("test"))
+        .start()
+        .await
+        .unwrap();
+    // the first attempt and its retry fail, breakers of both ports are open
+    server.workers[0].fail_next(1);
+    server.workers[1].fail_next(1);
+    let (status, _) = server.get_bytes("/api/tile/test/3/2/1.png").await.unwrap();
+    assert!(status.is_server_error(), "status {status}");
+    assert_eq!(server.workers[0].requests(), 1);
+    assert_eq!(server.workers[1].requests(), 1);
+
+    // all ports are open, the balancer keeps its choice but the other port is not retried
+    server.workers[0].fail_next(1);
+    server.workers[1].fail_next(1);
+    let (status, _) = server.get_bytes("/api/tile/test/3/2/1.png").await.unwrap();
+    assert!(status.is_server_error(), "status {status}");
+    assert_eq!(
+        server.workers[0].requests() + server.workers[1].requests(),
+        3
+    );
+
+    server.shutdown().await.unwrap();
+}
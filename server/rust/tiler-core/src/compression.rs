@@ -0,0 +1,68 @@
+use std::io::Read;
+
+use flate2::read::GzDecoder;
+
+/*
+    Compression of tiles at rest: MBTiles with the row 'tile_compression' = 'zstd' in 'metadata'
+    keeps blobs of tiles compressed with zstd. Tiles gzipped by workers are stored ungzipped
+    before compression, on serve the encoding of the response is negotiated by 'Accept-Encoding'.
+    Blobs are recognized by the magic number, so MBTiles with tiles of both kinds are readable
+*/
+pub const METADATA_TILE_COMPRESSION: &str = "tile_compression";
+pub const TILE_COMPRESSION_ZSTD: &str = "zstd";
+
+const ZSTD_LEVEL: i32 = 3;
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+pub fn is_zstd(data: &[u8]) -> bool {
+    data.starts_with(&ZSTD_MAGIC)
+}
+
+pub fn is_gzip(data: &[u8]) -> bool {
+    data.starts_with(&GZIP_MAGIC)
+}
+
+pub fn gunzip(data: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(data.len() * 2);
+    GzDecoder::new(data).read_to_end(&mut decoded)?;
+    Ok(decoded)
+}
+
+// Blob of the tile for MBTiles with compression 'zstd'
+pub fn encode_stored_tile(tile: &[u8]) -> std::io::Result<Vec<u8>> {
+    if is_zstd(tile) {
+        return Ok(tile.to_vec());
+    }
+    if is_gzip(tile) {
+        return zstd::encode_all(gunzip(tile)?.as_slice(), ZSTD_LEVEL);
+    }
+    zstd::encode_all(tile, ZSTD_LEVEL)
+}
+
+// Tile of the blob read from MBTiles, blobs without zstd are returned as is
+pub fn decode_stored_tile(blob: Vec<u8>) -> std::io::Result<Vec<u8>> {
+    if is_zstd(&blob) {
+        zstd::decode_all(blob.as_slice())
+    } else {
+        Ok(blob)
+    }
+}
+
+// MBTiles created before compression or without it has no row in 'metadata'
+pub fn mbtiles_compressed(connection: &rusqlite::Connection) -> Result<bool, rusqlite::Error> {
+    match connection.query_row(
+        "SELECT value FROM metadata WHERE name = (?) LIMIT 1;",
+        [METADATA_TILE_COMPRESSION],
+        |row| row.get::<_, String>(0),
+    ) {
+        Ok(value) => Ok(value.eq_ignore_ascii_case(TILE_COMPRESSION_ZSTD)),
+        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(false),
+        Err(rusqlite::Error::SqliteFailure(_, Some(message)))
+            if message.contains("no such table") =>
+        {
+            Ok(false)
+        }
+        Err(err) => Err(err),
+    }
+}
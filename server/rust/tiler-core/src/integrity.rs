@@ -0,0 +1,105 @@
+use std::path::{Path, PathBuf};
+
+use tracing::{event, Level};
+use xxhash_rust::xxh3::xxh3_64;
+
+/*
+    Integrity mode: checksums XXH3-64 of tiles are recorded by workers on write,
+    in the table 'tiles_checksum' of MBTiles and in the sidecar file '{y}.{ext}.xxh' of the tile on disk
+*/
+pub const TABLE_TILES_CHECKSUM: &str = "CREATE TABLE IF NOT EXISTS tiles_checksum (
+        zoom_level integer NOT NULL,
+        tile_column integer NOT NULL,
+        tile_row integer NOT NULL,
+        xxhash text NOT NULL,
+        PRIMARY KEY(zoom_level, tile_column, tile_row)
+    );";
+
+pub fn tile_checksum(data: &[u8]) -> String {
+    format!("{:016x}", xxh3_64(data))
+}
+
+pub fn checksum_path(file_tile_path: &Path) -> PathBuf {
+    let mut path = file_tile_path.as_os_str().to_owned();
+    path.push(".xxh");
+    PathBuf::from(path)
+}
+
+// Tile without the recorded checksum is considered intact
+pub fn is_tile_intact(data: &[u8], checksum: Option<&str>) -> bool {
+    match checksum {
+        Some(checksum) => checksum.trim().eq_ignore_ascii_case(&tile_checksum(data)),
+        None => true,
+    }
+}
+
+pub async fn disk_tile_checksum(file_tile_path: &Path) -> Option<String> {
+    tokio::fs::read_to_string(checksum_path(file_tile_path))
+        .await
+        .ok()
+}
+
+pub async fn write_disk_tile_checksum(file_tile_path: &Path, data: &[u8]) {
+    let path = checksum_path(file_tile_path);
+    if let Err(err) = tokio::fs::write(&path, tile_checksum(data)).await {
+        event!(Level::ERROR, "Error save checksum of tile {path:?}: {err}");
+    }
+}
+
+// Corrupt tile is removed with its checksum, the worker creates it again on the next request
+pub async fn remove_disk_tile(file_tile_path: &Path) {
+    for path in [file_tile_path.to_path_buf(), checksum_path(file_tile_path)] {
+        match tokio::fs::remove_file(&path).await {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                event!(Level::ERROR, "Error remove tile {path:?}: {err}");
+            }
+            _ => {}
+        }
+    }
+}
+
+pub fn mbtile_checksum(
+    connection: &rusqlite::Connection,
+    z: u64,
+    x: u64,
+    y: u64,
+) -> Result<Option<String>, rusqlite::Error> {
+    match connection.query_row(
+        "SELECT xxhash FROM tiles_checksum WHERE zoom_level = (?) AND tile_column = (?) AND tile_row = (?) LIMIT 1;",
+        [z, x, y],
+        |row| row.get(0),
+    ) {
+        Ok(checksum) => Ok(Some(checksum)),
+        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+        // MBTiles created before integrity mode has no table of checksums
+        Err(rusqlite::Error::SqliteFailure(_, Some(message)))
+            if message.contains("no such table") =>
+        {
+            Ok(None)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+pub fn remove_mbtile(
+    connection: &rusqlite::Connection,
+    z: u64,
+    x: u64,
+    y: u64,
+) -> Result<(), rusqlite::Error> {
+    connection.execute(
+        "DELETE FROM tiles WHERE zoom_level = (?) AND tile_column = (?) AND tile_row = (?);",
+        [z, x, y],
+    )?;
+    match connection.execute(
+        "DELETE FROM tiles_checksum WHERE zoom_level = (?) AND tile_column = (?) AND tile_row = (?);",
+        [z, x, y],
+    ) {
+        Err(rusqlite::Error::SqliteFailure(_, Some(message)))
+            if message.contains("no such table") =>
+        {
+            Ok(())
+        }
+        result => result.map(|_| ()),
+    }
+}
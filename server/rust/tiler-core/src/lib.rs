@@ -0,0 +1,11 @@
+/*
+    Cache of tiles shared by the modes 'serve' and 'serve-cache' of the tiler server
+    and by external tools: the task of SQLite clients of MBTiles, reading and writing
+    of tiles in MBTiles and on disk with checksums of the integrity mode and compression at rest
+*/
+pub mod compression;
+pub mod integrity;
+pub mod lookup;
+pub mod manifest;
+pub mod mbtiles;
+pub mod sqlite_clients;
@@ -0,0 +1,82 @@
+use std::path::{Path, PathBuf};
+
+use tracing::{event, info_span, Instrument, Level};
+
+use crate::integrity::{disk_tile_checksum, is_tile_intact, remove_disk_tile};
+use crate::manifest::mbtiles_path_for_tile;
+use crate::mbtiles::read_mbtile;
+use crate::sqlite_clients::MessageSQLiteClient;
+
+pub enum CachedTile {
+    Tile(Vec<u8>),
+    // empty file or blob of the tile: the area has no data, workers are not requested
+    Empty,
+    Missing,
+}
+
+pub struct TileLookup {
+    pub tile: CachedTile,
+    // MBTiles of the tile, None - the directory of the DataSource has no MBTiles
+    pub mbtiles_path: Option<PathBuf>,
+}
+
+// Tile saved to the file on disk, in integrity mode the corrupt tile is removed
+pub async fn read_disk_tile(
+    file_tile_path: &Path,
+    integrity: bool,
+) -> Result<CachedTile, anyhow::Error> {
+    let Ok(md) = tokio::fs::metadata(file_tile_path).await else {
+        return Ok(CachedTile::Missing);
+    };
+    if md.len() == 0 {
+        return Ok(CachedTile::Empty);
+    }
+    let tile = tokio::fs::read(file_tile_path).await?;
+    if integrity {
+        let checksum = disk_tile_checksum(file_tile_path).await;
+        if !is_tile_intact(&tile, checksum.as_deref()) {
+            event!(
+                Level::ERROR,
+                "Corrupt tile {file_tile_path:?}, tile is removed"
+            );
+            remove_disk_tile(file_tile_path).await;
+            return Ok(CachedTile::Missing);
+        }
+    }
+    Ok(CachedTile::Tile(tile))
+}
+
+/*
+    Tile of the cache of the DataSource: the file '{z}/{x}/{y}.{ext}' on disk,
+    then MBTiles of the directory of the DataSource (by its manifest)
+*/
+pub async fn lookup_tile(
+    dataset_dir: PathBuf,
+    file_tile_path: &Path,
+    (x, y, z): (u64, u64, u8),
+    tx_sqlite_client: flume::Sender<MessageSQLiteClient>,
+    integrity: bool,
+) -> Result<TileLookup, anyhow::Error> {
+    let on_disk = read_disk_tile(file_tile_path, integrity)
+        .instrument(info_span!("tile_lookup", source = "disk", z, x, y))
+        .await;
+    let mbtiles_path = mbtiles_path_for_tile(dataset_dir, z, x, y).await.ok();
+    match on_disk {
+        Ok(CachedTile::Missing) => {}
+        Ok(tile) => return Ok(TileLookup { tile, mbtiles_path }),
+        Err(err) => event!(Level::ERROR, "Error read tile {file_tile_path:?}: {err}"),
+    }
+
+    let mut tile = CachedTile::Missing;
+    if let Some(p) = mbtiles_path.as_ref() {
+        let mbtile = read_mbtile(p, z as u64, x, y, tx_sqlite_client, integrity)
+            .instrument(info_span!("tile_lookup", source = "mbtiles", z, x, y))
+            .await?;
+        tile = match mbtile {
+            Some(data) if data.is_empty() => CachedTile::Empty,
+            Some(data) => CachedTile::Tile(data),
+            None => CachedTile::Missing,
+        };
+    }
+    Ok(TileLookup { tile, mbtiles_path })
+}
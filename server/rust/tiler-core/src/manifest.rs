@@ -0,0 +1,158 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use anyhow::anyhow;
+use lazy_static::lazy_static;
+use serde::Deserialize;
+
+use crate::mbtiles::get_mbtiles_paths;
+
+pub const MBTILES_MANIFEST: &str = "mbtiles.json";
+pub const MBTILES_MANIFEST_MAXZOOM: u8 = 30;
+
+lazy_static! {
+    // manifests by directory of the DataSource, reread when the file is modified
+    static ref MANIFESTS: Mutex<HashMap<PathBuf, (SystemTime, Arc<MBTilesManifest>)>> =
+        Mutex::new(HashMap::new());
+}
+
+/*
+    Tiles of the archive on 'zoom'. Deeper tiles are covered when their ancestor on 'zoom'
+    is in the range, upper tiles when any of their descendants on 'zoom' is in the range
+*/
+#[derive(Debug, Clone, Deserialize)]
+pub struct TileRange {
+    pub zoom: u8,
+    pub min_x: u64,
+    pub max_x: u64,
+    pub min_y: u64,
+    pub max_y: u64,
+}
+
+impl TileRange {
+    fn covers(&self, z: u8, x: u64, y: u64) -> bool {
+        if z >= self.zoom {
+            let shift = z - self.zoom;
+            let (x, y) = (x >> shift, y >> shift);
+            return (self.min_x..=self.max_x).contains(&x)
+                && (self.min_y..=self.max_y).contains(&y);
+        }
+        let shift = self.zoom - z;
+        let (min_x, max_x) = (x << shift, ((x + 1) << shift) - 1);
+        let (min_y, max_y) = (y << shift, ((y + 1) << shift) - 1);
+        min_x <= self.max_x && max_x >= self.min_x && min_y <= self.max_y && max_y >= self.min_y
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ManifestArchive {
+    // file of MBTiles in the directory of the DataSource
+    pub file: String,
+    #[serde(default)]
+    pub minzoom: u8,
+    #[serde(default)]
+    pub maxzoom: Option<u8>,
+    // no range - all tiles of the zooms
+    #[serde(default)]
+    pub range: Option<TileRange>,
+}
+
+impl ManifestArchive {
+    fn covers(&self, z: u8, x: u64, y: u64) -> bool {
+        let maxzoom = self.maxzoom.unwrap_or(MBTILES_MANIFEST_MAXZOOM);
+        (self.minzoom..=maxzoom).contains(&z)
+            && self
+                .range
+                .as_ref()
+                .map_or(true, |range| range.covers(z, x, y))
+    }
+}
+
+/*
+    Cache of the DataSource split to several MBTiles (by zooms or regions), file 'mbtiles.json'
+    in the directory of the DataSource: {"archives": [{"file": "low.mbtiles", "minzoom": 0,
+    "maxzoom": 10}, {"file": "city.mbtiles", "minzoom": 11, "maxzoom": 18, "range": {"zoom": 11,
+    "min_x": 1234, "max_x": 1240, "min_y": 640, "max_y": 645}}]}. The first archive covering
+    the tile is used, coordinates are 'z/x/y' of the URI of the tile
+*/
+#[derive(Debug, Clone, Deserialize)]
+pub struct MBTilesManifest {
+    pub archives: Vec<ManifestArchive>,
+}
+
+impl MBTilesManifest {
+    pub fn archive(&self, dir: &Path, z: u8, x: u64, y: u64) -> Option<PathBuf> {
+        self.archives
+            .iter()
+            .find(|archive| archive.covers(z, x, y))
+            .map(|archive| dir.join(&archive.file))
+    }
+}
+
+// Manifest of the directory, None - the directory has no manifest
+pub async fn read_manifest(dir: &Path) -> Result<Option<Arc<MBTilesManifest>>, anyhow::Error> {
+    let path = dir.join(MBTILES_MANIFEST);
+    let modified = match tokio::fs::metadata(&path).await {
+        Ok(metadata) => metadata.modified()?,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(anyhow!(err)),
+    };
+    if let Some((cached_modified, manifest)) = MANIFESTS.lock().unwrap().get(dir) {
+        if *cached_modified == modified {
+            return Ok(Some(manifest.clone()));
+        }
+    }
+
+    let content = tokio::fs::read(&path).await?;
+    let manifest: MBTilesManifest = serde_json::from_slice(&content)
+        .map_err(|err| anyhow!("Invalid manifest {path:?}: {err}"))?;
+    // archives are only in the directory of the DataSource
+    if let Some(archive) = manifest.archives.iter().find(|archive| {
+        archive.file.is_empty() || archive.file.contains(['/', '\\']) || archive.file == ".."
+    }) {
+        return Err(anyhow!(
+            "Invalid file '{}' of the manifest {path:?}",
+            archive.file
+        ));
+    }
+    if manifest.archives.iter().any(|archive| {
+        archive
+            .range
+            .as_ref()
+            .is_some_and(|range| range.zoom > MBTILES_MANIFEST_MAXZOOM)
+    }) {
+        return Err(anyhow!(
+            "Zoom of ranges of the manifest {path:?} must be up to {MBTILES_MANIFEST_MAXZOOM}"
+        ));
+    }
+    let manifest = Arc::new(manifest);
+    MANIFESTS
+        .lock()
+        .unwrap()
+        .insert(dir.to_path_buf(), (modified, manifest.clone()));
+    Ok(Some(manifest))
+}
+
+/*
+    MBTiles of the tile in the directory of the DataSource: the archive of the manifest
+    covering the tile, without the manifest the first MBTiles of the directory
+*/
+pub async fn mbtiles_path_for_tile(
+    dir: PathBuf,
+    z: u8,
+    x: u64,
+    y: u64,
+) -> Result<PathBuf, anyhow::Error> {
+    if let Some(manifest) = read_manifest(&dir).await? {
+        return manifest.archive(&dir, z, x, y).ok_or(anyhow!(
+            "No MBTiles of the manifest cover the tile {z}/{x}/{y}"
+        ));
+    }
+    let mbtiles_paths = get_mbtiles_paths(dir.clone()).await?;
+    mbtiles_paths
+        .into_iter()
+        .next()
+        .ok_or(anyhow!("DataBase of MBTiles in {dir:?} not found"))
+}
@@ -0,0 +1,171 @@
+use std::path::PathBuf;
+
+use anyhow::anyhow;
+use tokio::sync::oneshot;
+use tracing::{event, info_span, Instrument, Level};
+
+use crate::compression::{decode_stored_tile, encode_stored_tile, mbtiles_compressed};
+use crate::integrity::{
+    is_tile_intact, mbtile_checksum, remove_mbtile, tile_checksum, TABLE_TILES_CHECKSUM,
+};
+use crate::sqlite_clients::MessageSQLiteClient;
+
+pub async fn get_mbtiles_paths(dir: PathBuf) -> Result<Vec<PathBuf>, anyhow::Error> {
+    let mut paths = Vec::new();
+    let mut dir = tokio::fs::read_dir(dir).await?;
+    while let Some(entry) = dir.next_entry().await? {
+        let path = entry.path();
+        if path.extension().map_or(false, |ext| ext == "mbtiles") {
+            paths.push(path);
+        }
+    }
+    Ok(paths)
+}
+
+// Client of the task of SQLite clients, 'read_only' - the client of readers of MBTiles
+pub async fn mbtiles_client(
+    mbtiles_db: &PathBuf,
+    read_only: bool,
+    tx_sqlite_client: flume::Sender<MessageSQLiteClient>,
+) -> Result<async_sqlite::Client, anyhow::Error> {
+    let (tx_client, rx_client) = oneshot::channel::<Option<async_sqlite::Client>>();
+    tx_sqlite_client
+        .send_async(MessageSQLiteClient::GetSQLiteClient {
+            mbtiles_db: mbtiles_db
+                .clone()
+                .into_os_string()
+                .into_string()
+                .map_err(|err| anyhow!("{err:?}"))?,
+            read_only,
+            tx_client,
+        })
+        .await?;
+
+    rx_client.await?.ok_or(anyhow!(
+        "Error get SQLite client for MBTiles {mbtiles_db:?}"
+    ))
+}
+
+/*
+    Tile of MBTiles, None - the tile is missing. In integrity mode the corrupt tile
+    is removed by the writer of MBTiles and is considered missing
+*/
+pub async fn read_mbtile(
+    mbtiles_db: &PathBuf,
+    z: u64,
+    x: u64,
+    y: u64,
+    tx_sqlite_client: flume::Sender<MessageSQLiteClient>,
+    integrity: bool,
+) -> Result<Option<Vec<u8>>, anyhow::Error> {
+    let sqlite_client = match mbtiles_client(mbtiles_db, true, tx_sqlite_client.clone()).await {
+        Ok(client) => client,
+        Err(err) => {
+            event!(Level::ERROR, "{err}");
+            return Ok(None);
+        }
+    };
+
+    let tile_data: Result<(Vec<u8>, Option<String>), _> = sqlite_client
+            .conn(move |connection| {
+                let tile = connection.query_row("SELECT tile_data FROM tiles WHERE zoom_level = (?) AND tile_column = (?) AND tile_row = (?) LIMIT 1;", [z, x, y], |row| row.get(0))?;
+                let checksum = if integrity {
+                    mbtile_checksum(connection, z, x, y)?
+                } else {
+                    None
+                };
+                Ok((tile, checksum))
+            })
+            .instrument(info_span!("mbtiles_query", z, x, y))
+            .await;
+
+    match tile_data {
+        Ok((tile, checksum)) => {
+            if !is_tile_intact(&tile, checksum.as_deref()) {
+                event!(
+                    Level::ERROR,
+                    "Corrupt tile {z}/{x}/{y} in {mbtiles_db:?}, tile is removed"
+                );
+                // tiles are read by the read-only client, the corrupt tile is removed by the writer
+                let removed = match mbtiles_client(mbtiles_db, false, tx_sqlite_client).await {
+                    Ok(writer) => writer
+                        .conn(move |connection| remove_mbtile(connection, z, x, y))
+                        .await
+                        .map_err(|err| anyhow!(err)),
+                    Err(err) => Err(err),
+                };
+                if let Err(err) = removed {
+                    event!(
+                        Level::ERROR,
+                        "Error remove corrupt tile from {mbtiles_db:?}: {err:?}"
+                    );
+                }
+                Ok(None)
+            } else {
+                match decode_stored_tile(tile) {
+                    Ok(tile) => Ok(Some(tile)),
+                    Err(err) => {
+                        event!(
+                            Level::ERROR,
+                            "Error decompress tile {z}/{x}/{y} of {mbtiles_db:?}: {err}"
+                        );
+                        Ok(None)
+                    }
+                }
+            }
+        }
+        Err(async_sqlite::Error::Rusqlite(rusqlite::Error::QueryReturnedNoRows)) => {
+            // Tile missing
+            Ok(None)
+        }
+        Err(err) => {
+            event!(
+                Level::ERROR,
+                "Error select tile from {mbtiles_db:?}: {err:?}"
+            );
+            Ok(None)
+        }
+    }
+}
+
+/*
+    Tile is saved to MBTiles with its checksum in integrity mode, in MBTiles with compression
+    the tile is stored compressed and the checksum is taken of the stored blob.
+    Empty tile is stored as the empty blob: the marker of the tile without data
+*/
+pub async fn save_mbtile(
+    mbtiles_db: &PathBuf,
+    z: u64,
+    x: u64,
+    y: u64,
+    tile: Vec<u8>,
+    tx_sqlite_client: flume::Sender<MessageSQLiteClient>,
+    integrity: bool,
+) -> Result<(), anyhow::Error> {
+    let sqlite_client = mbtiles_client(mbtiles_db, false, tx_sqlite_client).await?;
+    sqlite_client
+        .conn(move |connection| {
+            let tx = connection.unchecked_transaction()?;
+            let tile = if !tile.is_empty() && mbtiles_compressed(&tx)? {
+                encode_stored_tile(&tile).map_err(|err| {
+                    rusqlite::Error::ToSqlConversionFailure(Box::new(err))
+                })?
+            } else {
+                tile
+            };
+            tx.execute(
+                "INSERT OR REPLACE INTO tiles (zoom_level, tile_column, tile_row, tile_data) VALUES (?1, ?2, ?3, ?4);",
+                rusqlite::params![z, x, y, tile],
+            )?;
+            if integrity {
+                tx.execute(TABLE_TILES_CHECKSUM, ())?;
+                tx.execute(
+                    "INSERT OR REPLACE INTO tiles_checksum (zoom_level, tile_column, tile_row, xxhash) VALUES (?1, ?2, ?3, ?4);",
+                    rusqlite::params![z, x, y, tile_checksum(&tile)],
+                )?;
+            }
+            tx.commit()
+        })
+        .await?;
+    Ok(())
+}
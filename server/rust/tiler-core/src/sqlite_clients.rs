@@ -0,0 +1,517 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use async_sqlite::{Client as SQLiteClient, ClientBuilder, JournalMode};
+use rusqlite::OpenFlags;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tracing::{event, Level};
+
+pub enum MessageSQLiteClient {
+    GetSQLiteClient {
+        mbtiles_db: String,
+        read_only: bool,
+        tx_client: oneshot::Sender<Option<SQLiteClient>>,
+    },
+    AddSQLiteClient {
+        mbtiles_db: String,
+        client: SQLiteClient,
+    },
+    RemoveSQLiteClient {
+        mbtiles_db: String,
+        remove_tiles_folder: Option<bool>,
+        remove_tiles_db: Option<bool>,
+    },
+    CloseSQLiteClients(),
+    GetPoolStats {
+        tx_stats: oneshot::Sender<SQLitePoolStats>,
+    },
+    // field 'mbtiles_maintenance' of the DataSource JSON
+    SetMaintenanceThresholds {
+        mbtiles_db: String,
+        thresholds: Option<serde_json::Value>,
+    },
+    // MBTiles 'new_db' of the pyramid replaces 'mbtiles_db', clients of both are closed
+    SwapMBTiles {
+        mbtiles_db: String,
+        new_db: String,
+        tx_result: oneshot::Sender<Result<(), anyhow::Error>>,
+    },
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SQLitePoolStats {
+    pub open: usize,
+    pub max_clients: usize,
+    pub evicted_lru: u64,
+    pub evicted_idle: u64,
+}
+
+/*
+    Open clients of MBTiles with the time of the last request. Evicted clients are not closed
+    explicitly: the connection is closed when requests holding clones of the client are finished
+*/
+struct SQLitePool {
+    clients: HashMap<String, (SQLiteClient, Instant)>,
+    // 0 - number of clients is not limited
+    max_clients: usize,
+    evicted_lru: u64,
+    evicted_idle: u64,
+}
+
+impl SQLitePool {
+    fn new(max_clients: usize) -> Self {
+        SQLitePool {
+            clients: HashMap::new(),
+            max_clients,
+            evicted_lru: 0,
+            evicted_idle: 0,
+        }
+    }
+
+    fn get(&mut self, mbtiles_db: &str) -> Option<SQLiteClient> {
+        self.clients.get_mut(mbtiles_db).map(|(client, last_used)| {
+            *last_used = Instant::now();
+            client.clone()
+        })
+    }
+
+    fn insert(&mut self, mbtiles_db: String, client: SQLiteClient) {
+        self.clients.insert(mbtiles_db, (client, Instant::now()));
+        while self.max_clients > 0 && self.clients.len() > self.max_clients {
+            let lru = self
+                .clients
+                .iter()
+                .min_by_key(|(_, (_, last_used))| *last_used)
+                .map(|(mbtiles_db, _)| mbtiles_db.clone());
+            if let Some(mbtiles_db) = lru {
+                self.clients.remove(&mbtiles_db);
+                self.evicted_lru += 1;
+                event!(Level::DEBUG, "SQLite client '{mbtiles_db}' evicted (LRU)");
+            }
+        }
+    }
+
+    fn remove(&mut self, mbtiles_db: &str) -> Option<SQLiteClient> {
+        self.clients.remove(mbtiles_db).map(|(client, _)| client)
+    }
+
+    fn evict_idle(&mut self, idle: Duration) {
+        let before = self.clients.len();
+        self.clients
+            .retain(|_, (_, last_used)| last_used.elapsed() < idle);
+        let evicted = before - self.clients.len();
+        if evicted > 0 {
+            self.evicted_idle += evicted as u64;
+            event!(Level::DEBUG, "{evicted} idle SQLite clients evicted");
+        }
+    }
+
+    fn stats(&self) -> SQLitePoolStats {
+        SQLitePoolStats {
+            open: self.clients.len(),
+            max_clients: self.max_clients,
+            evicted_lru: self.evicted_lru,
+            evicted_idle: self.evicted_idle,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct MaintenanceThresholds {
+    // WAL file larger is checkpointed, 0 disables checkpoints
+    pub wal_threshold_mb: u64,
+    // free pages more are released by incremental vacuum, 0 disables vacuum
+    pub vacuum_pages: u64,
+}
+
+impl MaintenanceThresholds {
+    // Object {"wal_threshold_mb", "vacuum_pages"}, missing values are taken from 'default'
+    pub fn from_json(value: Option<&serde_json::Value>, default: MaintenanceThresholds) -> Self {
+        let get = |key: &str| value.and_then(|v| v.get(key)).and_then(|v| v.as_u64());
+        MaintenanceThresholds {
+            wal_threshold_mb: get("wal_threshold_mb").unwrap_or(default.wal_threshold_mb),
+            vacuum_pages: get("vacuum_pages").unwrap_or(default.vacuum_pages),
+        }
+    }
+}
+
+async fn file_size(path: &str) -> u64 {
+    tokio::fs::metadata(path)
+        .await
+        .map(|md| md.len())
+        .unwrap_or(0)
+}
+
+/*
+    Maintenance of MBTiles: free pages are released by incremental vacuum,
+    then the WAL file is checkpointed and truncated. Returns reclaimed space in bytes,
+    None if the thresholds are not reached
+*/
+async fn maintain_mbtiles(
+    mbtiles_db: &str,
+    client: &SQLiteClient,
+    thresholds: MaintenanceThresholds,
+) -> Result<Option<u64>, anyhow::Error> {
+    let wal_file = format!("{mbtiles_db}-wal");
+    let wal_size = file_size(&wal_file).await;
+    let size_before = file_size(mbtiles_db).await + wal_size;
+    let checkpoint =
+        thresholds.wal_threshold_mb > 0 && wal_size >= thresholds.wal_threshold_mb * 1024 * 1024;
+    let vacuum_pages = thresholds.vacuum_pages;
+
+    let (vacuumed, busy) = client
+        .conn(move |connection| {
+            let mut vacuumed = 0;
+            if vacuum_pages > 0 {
+                // vacuum is possible only for MBTiles created with 'auto_vacuum = INCREMENTAL'
+                let auto_vacuum: i64 =
+                    connection.query_row("PRAGMA auto_vacuum;", [], |row| row.get(0))?;
+                let free_pages: u64 =
+                    connection.query_row("PRAGMA freelist_count;", [], |row| row.get(0))?;
+                if auto_vacuum == 2 && free_pages >= vacuum_pages {
+                    connection.execute_batch("PRAGMA incremental_vacuum;")?;
+                    vacuumed = free_pages;
+                }
+            }
+            let mut busy = false;
+            if checkpoint || vacuumed > 0 {
+                busy = connection.query_row("PRAGMA wal_checkpoint(TRUNCATE);", [], |row| {
+                    row.get::<_, i64>(0)
+                })? == 1;
+            }
+            Ok((vacuumed, busy))
+        })
+        .await?;
+
+    if !checkpoint && vacuumed == 0 {
+        return Ok(None);
+    }
+    if busy {
+        // pyramid writes tiles, the checkpoint is repeated on the next run
+        event!(
+            Level::WARN,
+            "Checkpoint of MBTiles '{mbtiles_db}' is not complete, DataBase is busy"
+        );
+    }
+    let size_after = file_size(mbtiles_db).await + file_size(&wal_file).await;
+    let reclaimed = size_before.saturating_sub(size_after);
+    event!(
+        Level::INFO,
+        "Maintenance of MBTiles '{mbtiles_db}': WAL {wal_size} bytes, vacuumed {vacuumed} pages, reclaimed {reclaimed} bytes"
+    );
+    Ok(Some(reclaimed))
+}
+
+async fn maintain_all_mbtiles(clients: Vec<(String, SQLiteClient, MaintenanceThresholds)>) {
+    let mut reclaimed = 0;
+    let mut maintained = 0;
+    for (mbtiles_db, client, thresholds) in clients {
+        match maintain_mbtiles(&mbtiles_db, &client, thresholds).await {
+            Ok(Some(bytes)) => {
+                maintained += 1;
+                reclaimed += bytes;
+            }
+            Ok(None) => {}
+            Err(err) => {
+                event!(
+                    Level::ERROR,
+                    "Error maintenance of MBTiles '{mbtiles_db}': {err}"
+                );
+            }
+        }
+    }
+    if maintained > 0 {
+        event!(
+            Level::INFO,
+            "Maintenance of {maintained} MBTiles reclaimed {reclaimed} bytes"
+        );
+    }
+}
+
+/*
+    WAL of the new MBTiles is merged into the file, WAL fragments of the replaced MBTiles are removed
+    before the rename: SQLite would apply them to the new file. Requests holding clones
+    of closed clients are finished with the replaced file
+*/
+async fn swap_mbtiles(mbtiles_db: &str, new_db: &str) -> Result<(), anyhow::Error> {
+    if !tokio::fs::try_exists(new_db).await? {
+        return Err(anyhow::anyhow!("MBTiles '{new_db}' not found"));
+    }
+    let client = ClientBuilder::new()
+        .path(new_db)
+        .flags(OpenFlags::SQLITE_OPEN_READ_WRITE)
+        .open()
+        .await?;
+    let merged = client
+        .conn(|connection| {
+            connection.query_row("PRAGMA journal_mode = DELETE;", [], |row| {
+                row.get::<_, String>(0)
+            })
+        })
+        .await;
+    if let Err(err) = client.close().await {
+        event!(
+            Level::ERROR,
+            "Error close client for MBTiles '{new_db}': {err:?}"
+        );
+    }
+    merged?;
+
+    for suffix in ["-wal", "-shm"] {
+        let fragment = format!("{mbtiles_db}{suffix}");
+        if tokio::fs::try_exists(&fragment).await? {
+            tokio::fs::remove_file(&fragment).await?;
+        }
+    }
+    tokio::fs::rename(new_db, mbtiles_db).await?;
+    Ok(())
+}
+
+/*
+    URI of the immutable database: SQLite does not lock the file and does not look for WAL,
+    for MBTiles on NFS mounts which are never changed while served
+*/
+fn immutable_uri(mbtiles_db: &str) -> String {
+    let path = mbtiles_db
+        .replace('\\', "/")
+        .replace('%', "%25")
+        .replace('?', "%3f")
+        .replace('#', "%23");
+    let slash = if path.starts_with('/') { "" } else { "/" };
+    format!("file://{slash}{path}?immutable=1")
+}
+
+/*
+    Pool of SQLite clients of MBTiles. Every 'interval_secs' the opened MBTiles are maintained
+    in the background: long pyramids leave large WAL files which are truncated only on restart.
+    Number of open clients is limited by 'max_clients' (least recently used are evicted)
+    separately for readers and writers, clients without requests for 'idle_secs' are evicted.
+    With 'immutable' MBTiles are opened by readers as immutable files and writers are refused
+*/
+pub fn sqlite_clients_maintenance(
+    rx: flume::Receiver<MessageSQLiteClient>,
+    interval_secs: u64,
+    default_thresholds: MaintenanceThresholds,
+    max_clients: usize,
+    idle_secs: u64,
+    immutable: bool,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        // tiles are served by read-only clients, writers are used by maintenance and repair of tiles
+        let mut readers = SQLitePool::new(max_clients);
+        let mut sqlite_clients = SQLitePool::new(max_clients);
+        let mut idle_check = tokio::time::interval(Duration::from_secs(idle_secs.clamp(1, 60)));
+        let mut thresholds: HashMap<String, MaintenanceThresholds> = HashMap::new();
+        let mut interval = tokio::time::interval(Duration::from_secs(interval_secs.max(1)));
+        // the first tick completes immediately, MBTiles are not maintained at startup
+        interval.tick().await;
+        let mut jh_maintenance: Option<JoinHandle<()>> = None;
+
+        loop {
+            let message = tokio::select! {
+                message = rx.recv_async() => match message {
+                    Ok(m) => m,
+                    Err(_) => break,
+                },
+                _ = interval.tick(), if interval_secs > 0 => {
+                    // the previous maintenance is still running
+                    if jh_maintenance.as_ref().is_some_and(|jh| !jh.is_finished()) {
+                        continue;
+                    }
+                    let clients = sqlite_clients
+                        .clients
+                        .iter()
+                        .map(|(mbtiles_db, (client, _))| {
+                            let t = thresholds
+                                .get(mbtiles_db)
+                                .copied()
+                                .unwrap_or(default_thresholds);
+                            (mbtiles_db.clone(), client.clone(), t)
+                        })
+                        .collect();
+                    jh_maintenance = Some(tokio::spawn(maintain_all_mbtiles(clients)));
+                    continue;
+                }
+                _ = idle_check.tick(), if idle_secs > 0 => {
+                    readers.evict_idle(Duration::from_secs(idle_secs));
+                    sqlite_clients.evict_idle(Duration::from_secs(idle_secs));
+                    continue;
+                }
+            };
+
+            match message {
+                MessageSQLiteClient::GetPoolStats { tx_stats } => {
+                    let (r, w) = (readers.stats(), sqlite_clients.stats());
+                    let stats = SQLitePoolStats {
+                        open: r.open + w.open,
+                        max_clients: r.max_clients + w.max_clients,
+                        evicted_lru: r.evicted_lru + w.evicted_lru,
+                        evicted_idle: r.evicted_idle + w.evicted_idle,
+                    };
+                    if let Err(_) = tx_stats.send(stats) {}
+                }
+                MessageSQLiteClient::SetMaintenanceThresholds {
+                    mbtiles_db,
+                    thresholds: value,
+                } => {
+                    let t = MaintenanceThresholds::from_json(value.as_ref(), default_thresholds);
+                    thresholds.insert(mbtiles_db, t);
+                }
+                MessageSQLiteClient::GetSQLiteClient {
+                    mbtiles_db,
+                    read_only,
+                    tx_client,
+                } => {
+                    let pool = if read_only {
+                        &mut readers
+                    } else {
+                        &mut sqlite_clients
+                    };
+                    if let Some(client) = pool.get(&mbtiles_db) {
+                        if let Err(_) = tx_client.send(Some(client)) {}
+                    } else if immutable && !read_only {
+                        if let Err(_) = tx_client.send(None) {}
+                        event!(
+                            Level::WARN,
+                            "MBTiles '{mbtiles_db}' are immutable, not written"
+                        );
+                    } else {
+                        // reader does not change the journal mode, MBTiles may be on a read-only filesystem
+                        let result = if immutable {
+                            ClientBuilder::new()
+                                .path(immutable_uri(&mbtiles_db))
+                                .flags(
+                                    OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_URI,
+                                )
+                                .open()
+                                .await
+                        } else if read_only {
+                            ClientBuilder::new()
+                                .path(&mbtiles_db)
+                                .flags(OpenFlags::SQLITE_OPEN_READ_ONLY)
+                                .open()
+                                .await
+                        } else {
+                            ClientBuilder::new()
+                                .path(&mbtiles_db)
+                                .journal_mode(JournalMode::Wal)
+                                .flags(OpenFlags::SQLITE_OPEN_READ_WRITE)
+                                .open()
+                                .await
+                        };
+                        match result {
+                            Ok(cl) => {
+                                pool.insert(mbtiles_db.clone(), cl.clone());
+                                if let Err(_) = tx_client.send(Some(cl)) {}
+                            }
+                            Err(err) => {
+                                if let Err(_) = tx_client.send(None) {}
+                                event!(Level::ERROR, "SQLite client error: {}", err.to_string());
+                            }
+                        };
+                    }
+                }
+                MessageSQLiteClient::SwapMBTiles {
+                    mbtiles_db,
+                    new_db,
+                    tx_result,
+                } => {
+                    // no clients are opened until the swap is finished, requests wait for the pool
+                    for db in [&mbtiles_db, &new_db] {
+                        for client in [readers.remove(db), sqlite_clients.remove(db)]
+                            .into_iter()
+                            .flatten()
+                        {
+                            if let Err(err) = client.close().await {
+                                event!(
+                                    Level::ERROR,
+                                    "Error close client for MBTiles '{db}': {err:?}"
+                                );
+                            }
+                        }
+                    }
+                    let result = swap_mbtiles(&mbtiles_db, &new_db).await;
+                    if let Err(_) = tx_result.send(result) {}
+                }
+                MessageSQLiteClient::AddSQLiteClient { mbtiles_db, client } => {
+                    sqlite_clients.insert(mbtiles_db, client);
+                }
+                MessageSQLiteClient::CloseSQLiteClients() => {
+                    for (client, _) in readers
+                        .clients
+                        .values()
+                        .chain(sqlite_clients.clients.values())
+                    {
+                        if let Err(err) = client.close().await {
+                            event!(Level::ERROR, "Error close client MBTiles {err:?}");
+                        }
+                    }
+                }
+                MessageSQLiteClient::RemoveSQLiteClient {
+                    mbtiles_db,
+                    remove_tiles_folder,
+                    remove_tiles_db,
+                } => {
+                    let clients: Vec<SQLiteClient> = [
+                        readers.remove(&mbtiles_db),
+                        sqlite_clients.remove(&mbtiles_db),
+                    ]
+                    .into_iter()
+                    .flatten()
+                    .collect();
+                    if !clients.is_empty() {
+                        for client in clients {
+                            if let Err(err) = client.close().await {
+                                event!(
+                                    Level::ERROR,
+                                    "Error close client for MBTiles '{mbtiles_db}': {err:?}"
+                                );
+                            }
+                        }
+                        let jh = tokio::spawn({
+                            let mbtiles_db = mbtiles_db.clone();
+                            async move {
+                                if let Some(is_remove_folder) = remove_tiles_folder {
+                                    if is_remove_folder {
+                                        if let Some(parent) = PathBuf::from(&mbtiles_db).parent() {
+                                            if let Err(_err) =
+                                                tokio::fs::remove_dir_all(parent).await
+                                            {
+                                                /*
+                                                    event!(
+                                                        Level::ERROR,
+                                                        "Impossible to delete directory of MBTiles '{mbtiles_db}': {err:?}"
+                                                    );
+                                                */
+                                            }
+                                        }
+                                        return;
+                                    }
+                                }
+
+                                if let Some(is_remove_db) = remove_tiles_db {
+                                    if is_remove_db {
+                                        if let Err(_err) = tokio::fs::remove_file(&mbtiles_db).await
+                                        {
+                                            /*
+                                                event!(
+                                                    Level::INFO,
+                                                    "Impossible to delete MBTiles DataBase '{mbtiles_db}': {err:?}"
+                                                );
+                                            */
+                                        }
+                                    }
+                                }
+                            }
+                        });
+                        if let Err(err) = jh.await {
+                            event!(Level::ERROR, "Error remove MBTiles '{mbtiles_db}': {err:?}");
+                        }
+                    }
+                }
+            }
+        }
+    })
+}
@@ -5,6 +5,8 @@ import aiosqlite
 import logging
 import time
 
+from server.paths import data_dir
+
 logger = logging.getLogger(__name__)
 
 
@@ -107,7 +109,7 @@ async def optimize_connection_async(cursor: aiosqlite.Cursor) -> None:
 
 def init_db():
     cwd: str = os.getcwd()
-    tiler_db: str = os.path.join(cwd, "data", "tiler.db")
+    tiler_db: str = os.path.join(data_dir(cwd), "tiler.db")
     if not os.path.isfile(tiler_db):
         connection: sqlite3.Connection = sqlite_db_connect(tiler_db)
         cursor: sqlite3.Cursor = connection.cursor()
@@ -25,6 +25,13 @@ from starlette.exceptions import HTTPException
 from raster_tiles.mosaic.reader import mosaic_reader
 from raster_tiles.utils import encode_raster_to_rgba
 from server.sqlite_db import sqlite_db_connect_async
+from server.mbtiles import (
+    INTEGRITY,
+    async_insert_tiles,
+    decode_stored_tile,
+    tile_checksum,
+)
+from server.paths import tiles_dir, data_dir
 from server.datasources import EXTENSIONS
 from raster_tiles.defaults import (
     PIXEL_SELECTION_METHOD,
@@ -61,8 +68,7 @@ class NeighborTile:
         parent_dir: Path = Path(__file__).parents[1]
 
         tile_file_name: str = os.path.join(
-            f"{parent_dir}",
-            "tiles",
+            tiles_dir(str(parent_dir)),
             datasource_id,
             f"{t.z}",
             f"{t.x}",
@@ -82,8 +88,7 @@ class NeighborMosaicTile:
         parent_dir: Path = Path(__file__).parents[1]
 
         tile_file_name: str = os.path.join(
-            f"{parent_dir}",
-            "tiles",
+            tiles_dir(str(parent_dir)),
             datasource_id,
             f"{t.z}",
             f"{t.x}",
@@ -267,8 +272,7 @@ def get_mosaic_tile(
         buffer_requested: Optional[bytes] = None
 
         parent_dir: Path = Path(__file__).parents[1]
-        data_dir: str = os.path.join(parent_dir, "data")
-        assets_dir: str = os.path.join(data_dir, datasource_id)
+        assets_dir: str = os.path.join(data_dir(str(parent_dir)), datasource_id)
         psm = PIXEL_SELECTION_METHOD[pixel_selection_method]
 
         mosaic_assets: List[str] = []
@@ -543,10 +547,7 @@ async def save_mbtile(mbtiles_db: str, x: int, y: int, z: int, buffer: bytes) ->
     try:
         connection: aiosqlite.Connection = await sqlite_db_connect_async(mbtiles_db)
         cursor: aiosqlite.Cursor = await connection.cursor()
-        await cursor.execute(
-            """INSERT OR IGNORE INTO tiles (zoom_level, tile_column, tile_row, tile_data) values (?, ?, ?, ?);""",
-            (z, x, y, sqlite3.Binary(buffer)),
-        )
+        await async_insert_tiles(cursor, [(z, x, y, sqlite3.Binary(buffer))])
         await connection.commit()
         await cursor.close()
         await connection.close()
@@ -565,6 +566,9 @@ async def save_tile_on_disk(
             async with aiofiles.open(tile_file_name, "wb") as tile_file:
                 await tile_file.write(buffer)
                 await tile_file.flush()
+            if INTEGRITY:
+                async with aiofiles.open(f"{tile_file_name}.xxh", "w") as checksum_file:
+                    await checksum_file.write(tile_checksum(buffer))
     except Exception as e:
         logger.error(f"Error save tile '{tile_file_name}' on disk: {e}")
 
@@ -578,7 +582,8 @@ async def save_tile(
         await save_tile_on_disk(x, y, z, tile_file_name, buffer)
 
 
-async def get_mbtile(mbtiles_db: str, z: int, x: int, y: int) -> Optional[sqlite3.Row]:
+# Tile of MBTiles as it was rendered: tiles stored with zstd compression are decompressed
+async def get_mbtile(mbtiles_db: str, z: int, x: int, y: int) -> Optional[bytes]:
     connection = await sqlite_db_connect_async(mbtiles_db)
     cursor = await connection.cursor()
     sql_tile = "SELECT tile_data from tiles where zoom_level = (?) AND tile_column = (?) AND tile_row = (?);"
@@ -587,8 +592,8 @@ async def get_mbtile(mbtiles_db: str, z: int, x: int, y: int) -> Optional[sqlite
     await cursor.close()
     await connection.close()
 
-    if mbtile is not None:
-        return mbtile
+    if mbtile is not None and mbtile[0] is not None:
+        return decode_stored_tile(mbtile[0])
 
     return None
 
@@ -637,10 +642,7 @@ async def save_neighbors_tiles(
             ]
 
             try:
-                await cursor.executemany(
-                    """INSERT OR IGNORE INTO tiles (zoom_level, tile_column, tile_row, tile_data) values (?, ?, ?, ?);""",
-                    ngrs,
-                )
+                await async_insert_tiles(cursor, ngrs)
                 await connection.commit()
             except aiosqlite.OperationalError as e:
                 # Exception `database is locked`
@@ -648,10 +650,7 @@ async def save_neighbors_tiles(
                 for i in range(0, attempts):
                     await asyncio.sleep(0.5)
                     try:
-                        await cursor.executemany(
-                            """INSERT OR IGNORE INTO tiles (zoom_level, tile_column, tile_row, tile_data) values (?, ?, ?, ?);""",
-                            ngrs,
-                        )
+                        await async_insert_tiles(cursor, ngrs)
                         await connection.commit()
                     except Exception as exc:
                         if i == (attempts - 1):